@@ -2,21 +2,86 @@
 /// Format and fields names are loosely based on the IDTA AAS specification available at
 /// https://www.plattform-i40.de
 use serde::{Deserialize, Serialize};
+#[allow(clippy::single_component_path_imports)]
 use serde_yaml;
 
-use super::AssetID;
+use std::collections::{BTreeMap, HashMap};
+
+use super::{AssetID, GeoLocation, SlotValue, TransitionLimits};
+
+/// id_short of the synthetic submodel holding the live data of a twin
+pub const OPERATIONAL_DATA: &str = "OperationalData";
+
+/// Maximum nesting of the collections of a shell. Shells come from files and
+/// possibly from the network, and the element trees are walked recursively.
+pub const MAX_COLLECTION_DEPTH: usize = 32;
 
 /// A top-level Asset Administration Shell (AAS).
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AssetAdministrationShell {
-    /// Unique identifier of the asset (URI, URN, or any unique string).
+    /// Unique identifier of the asset, a URN (e.g., "urn:aas:smart-home:light:light-bulb:id-000001").
     pub id: AssetID,
     /// Human-readable name or short description.
     pub id_short: String,
     /// Optional: additional metadata about the asset or its owner.
     pub description: Option<String>,
+    /// Optional: version and revision of this shell.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub administration: Option<AdministrativeInformation>,
+    /// Whether the shell describes an asset type or a concrete instance.
+    #[serde(default)]
+    pub asset_kind: AssetKind,
+    /// Optional: ID of the Type shell this instance is derived from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub derived_from: Option<AssetID>,
     /// A set of Submodels describing various aspects of the asset.
+    #[serde(default)]
     pub submodels: Vec<Submodel>,
+    /// Optional: data dictionary of the concepts referenced by the elements' semantic IDs.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub concept_descriptions: Vec<ConceptDescription>,
+}
+
+/// Version and revision of an AAS (e.g., version "1", revision "2").
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AdministrativeInformation {
+    pub version: String,
+    #[serde(default)]
+    pub revision: String,
+}
+
+/// A Type shell describes a product (e.g., a charger model) and is never run
+/// directly; Instance shells describe the concrete assets.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum AssetKind {
+    Type,
+    #[default]
+    Instance,
+}
+
+/// A ConceptDescription defines the semantics of the elements referencing it
+/// through their semantic ID, usually an IRDI from a dictionary like ECLASS
+/// (e.g., "0173-1#02-AAV232#002" for the active power).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConceptDescription {
+    /// Identifier of the concept (IRDI, IRI, or any unique string).
+    pub id: String,
+    pub id_short: String,
+    /// Optional: human-readable name (e.g., "Active power").
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preferred_name: Option<String>,
+    /// Optional: definition of the concept.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub definition: Option<String>,
+    /// Optional: unit of measure of the values (e.g., "W").
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
+    /// Optional: expected value type of the elements using this concept.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value_type: Option<ValueType>,
+    /// External definitions this concept is a case of (e.g., ECLASS IRDIs).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub is_case_of: Vec<String>,
 }
 
 /// A Submodel groups related data and operations about a particular aspect
@@ -54,6 +119,9 @@ pub struct Property {
     pub id_short: String,
     pub value_type: ValueType,
     pub value: Value,
+    /// Optional: reference to the ConceptDescription defining this property.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub semantic_id: Option<String>,
 }
 
 /// Represents an operation with (potential) inputs and outputs.
@@ -66,13 +134,74 @@ pub struct Operation {
     /// Output variables: for example, a resulting status or a confirmation message.
     #[serde(default)]
     pub output_variables: Vec<OperationVariable>,
+    /// Optional: minimum time between two invocations, in milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cooldown_ms: Option<u64>,
+    /// Optional: the only principals allowed to invoke the operation (anyone if empty).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_principals: Vec<String>,
 }
 
 /// Represents an event, such as "ChargingStarted" or "LowBatteryAlert".
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Event {
     pub id_short: String,
-    // Additional fields for event triggers, conditions, payload, etc.
+    /// Fields of the payload published with the event, with their types.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub payload: Vec<EventField>,
+    /// Optional: reference to the element whose changes trigger the event,
+    /// in the same format as the reference elements (e.g., "urn:...:power#InputCurrent").
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub observed: Option<String>,
+    /// Optional: topic the event is published on, instead of the default one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_topic: Option<String>,
+    /// Optional: backend delivering the event to the device it actuates, besides publishing it
+    /// (e.g., a REST call to the cloud API of a charger).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub actuation: Option<Actuation>,
+}
+
+/// A backend delivering the events to the devices, for the devices not controlled over MQTT.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "backend", rename_all = "snake_case")]
+pub enum Actuation {
+    /// A call to an HTTP API
+    Http(HttpActuation),
+}
+
+/// An HTTP call actuating a device. The payload of the event is sent as a JSON body.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HttpActuation {
+    /// URL template, where "{asset_id}", "{event}" and "{<payload field>}" are replaced with
+    /// their values (e.g., "http://chargers.local/api/{asset_id}/current?amps={current}").
+    pub url: String,
+    /// HTTP method (POST by default).
+    #[serde(default = "HttpActuation::default_method")]
+    pub method: String,
+    /// Optional: name of the secret holding the credentials, sent in the Authorization header.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_secret: Option<String>,
+    /// Scheme of the Authorization header (Bearer by default).
+    #[serde(default = "HttpActuation::default_auth_scheme")]
+    pub auth_scheme: String,
+}
+
+impl HttpActuation {
+    fn default_method() -> String {
+        "POST".to_string()
+    }
+
+    fn default_auth_scheme() -> String {
+        "Bearer".to_string()
+    }
+}
+
+/// A field of an event payload (e.g., the measured current of an "OvercurrentFault").
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EventField {
+    pub name: String,
+    pub value_type: ValueType,
 }
 
 /// A grouping of submodel elements. Useful to nest logical groups
@@ -81,6 +210,9 @@ pub struct Event {
 pub struct SubmodelCollection {
     pub id_short: String,
     pub value: Vec<SubmodelElement>,
+    /// Optional: reference to the ConceptDescription defining this collection.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub semantic_id: Option<String>,
 }
 
 /// A reference element that points to an external entity, like an IoT sensor.
@@ -101,7 +233,7 @@ pub struct OperationVariable {
 }
 
 /// Simple enumeration for value types (string, integer, float, boolean, etc.).
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum ValueType {
     String,
@@ -124,11 +256,451 @@ pub enum Value {
     Null,
 }
 
+impl Event {
+    /// Shape the payload emitted by an actor to the declared fields: fields not
+    /// declared are dropped, and missing or mistyped fields are set to null.
+    /// Returns the payload and a description of each problem found.
+    pub fn shape_payload(&self, payload: &serde_json::Value) -> (serde_json::Value, Vec<String>) {
+        let mut problems = Vec::new();
+        let mut shaped = serde_json::Map::new();
+        for field in &self.payload {
+            let value = payload.get(&field.name).cloned().unwrap_or_default();
+            let valid = match (&field.value_type, &value) {
+                (_, serde_json::Value::Null) => {
+                    problems.push(format!("{}: missing field {}", self.id_short, field.name));
+                    true
+                }
+                (ValueType::String, v) => v.is_string(),
+                (ValueType::Int, v) => v.is_i64() || v.is_u64(),
+                (ValueType::Float, v) => v.is_number(),
+                (ValueType::Bool, v) => v.is_boolean(),
+                (ValueType::Json, _) => true,
+            };
+            if valid {
+                shaped.insert(field.name.clone(), value);
+            } else {
+                problems.push(format!(
+                    "{}: field {} is not {:?}: {value}",
+                    self.id_short, field.name, field.value_type
+                ));
+                shaped.insert(field.name.clone(), serde_json::Value::Null);
+            }
+        }
+        if let Some(extra) = payload.as_object() {
+            for name in extra.keys().filter(|name| !shaped.contains_key(*name)) {
+                problems.push(format!("{}: undeclared field {name}", self.id_short));
+            }
+        }
+        (serde_json::Value::Object(shaped), problems)
+    }
+}
+
+impl SubmodelElement {
+    pub fn id_short(&self) -> &str {
+        match self {
+            SubmodelElement::Property(p) => &p.id_short,
+            SubmodelElement::Operation(o) => &o.id_short,
+            SubmodelElement::Event(e) => &e.id_short,
+            SubmodelElement::Collection(c) => &c.id_short,
+            SubmodelElement::ReferenceElement(r) => &r.id_short,
+        }
+    }
+}
+
+/// The collection with this id_short, the given one or nested in it
+fn find_collection_mut<'a>(
+    collection: &'a mut SubmodelCollection,
+    target: &str,
+) -> Option<&'a mut SubmodelCollection> {
+    if collection.id_short == target {
+        return Some(collection);
+    }
+    collection.value.iter_mut().find_map(|elem| match elem {
+        SubmodelElement::Collection(c) => find_collection_mut(c, target),
+        _ => None,
+    })
+}
+
+/// Merge the overlay elements into the base ones, matching them by id_short
+fn merge_elements(base: &mut Vec<SubmodelElement>, overlay: &[SubmodelElement]) {
+    for elem in overlay {
+        let Some(pos) = base.iter().position(|b| b.id_short() == elem.id_short()) else {
+            base.push(elem.clone());
+            continue;
+        };
+        match (&mut base[pos], elem) {
+            (
+                SubmodelElement::Collection(base_collection),
+                SubmodelElement::Collection(overlay_collection),
+            ) => merge_elements(&mut base_collection.value, &overlay_collection.value),
+            (base_elem, _) => *base_elem = elem.clone(),
+        }
+    }
+}
+
 impl AssetAdministrationShell {
     /// Load an AssetAdministrationShell from a YAML string.
     pub fn from_reader<R: std::io::Read>(reader: R) -> Result<Self, String> {
         // TODO: validate id_short with [a-zA-Z][a-zA-Z0-9_\-\.]{0,127}
-        serde_yaml::from_reader(reader).map_err(|e| format!("Failed to parse YAML: {}", e))
+        let aas: Self =
+            serde_yaml::from_reader(reader).map_err(|e| format!("Failed to parse YAML: {}", e))?;
+        aas.check()?;
+        Ok(aas)
+    }
+
+    /// Load an AssetAdministrationShell from a JSON string, with the same layout as the YAML one.
+    pub fn from_json_reader<R: std::io::Read>(reader: R) -> Result<Self, String> {
+        let aas: Self =
+            serde_json::from_reader(reader).map_err(|e| format!("Failed to parse JSON: {}", e))?;
+        aas.check()?;
+        Ok(aas)
+    }
+
+    /// Reject the shells with an invalid ID, or whose collections are nested too deep
+    pub(crate) fn check(&self) -> Result<(), String> {
+        self.id.validate()?;
+        if let Some(type_id) = &self.derived_from {
+            type_id.validate()?;
+        }
+        self.check_depth()
+    }
+
+    /// Reject the shells whose collections are nested deeper than MAX_COLLECTION_DEPTH
+    fn check_depth(&self) -> Result<(), String> {
+        fn too_deep(elements: &[SubmodelElement], depth: usize) -> bool {
+            elements.iter().any(|elem| match elem {
+                SubmodelElement::Collection(c) => {
+                    depth >= MAX_COLLECTION_DEPTH || too_deep(&c.value, depth + 1)
+                }
+                _ => false,
+            })
+        }
+        match self.submodels.iter().find(|s| too_deep(&s.elements, 0)) {
+            Some(submodel) => Err(format!(
+                "submodel {} has collections nested deeper than {MAX_COLLECTION_DEPTH} levels",
+                submodel.id_short
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Create a concrete shell from this Type shell and an instance overlay, i.e. an
+    /// Instance shell derived from this one. The overlay provides the identity of the
+    /// instance, and its elements replace the ones of the type with the same id_short
+    /// (collections are merged recursively); elements and submodels the type does not
+    /// have are added.
+    pub fn instantiate(
+        &self,
+        overlay: &AssetAdministrationShell,
+    ) -> Result<AssetAdministrationShell, String> {
+        if self.asset_kind != AssetKind::Type {
+            return Err(format!("{} is not a Type shell", self.id));
+        }
+        if overlay.derived_from.as_ref() != Some(&self.id) {
+            return Err(format!("{} is not derived from {}", overlay.id, self.id));
+        }
+        let mut submodels = self.submodels.clone();
+        for overlay_submodel in &overlay.submodels {
+            match submodels
+                .iter_mut()
+                .find(|s| s.id_short == overlay_submodel.id_short)
+            {
+                Some(submodel) => merge_elements(&mut submodel.elements, &overlay_submodel.elements),
+                None => submodels.push(overlay_submodel.clone()),
+            }
+        }
+        let mut concept_descriptions = self.concept_descriptions.clone();
+        for concept in &overlay.concept_descriptions {
+            concept_descriptions.retain(|c| c.id != concept.id);
+            concept_descriptions.push(concept.clone());
+        }
+        Ok(AssetAdministrationShell {
+            id: overlay.id.clone(),
+            id_short: overlay.id_short.clone(),
+            description: overlay.description.clone().or_else(|| self.description.clone()),
+            administration: overlay.administration.clone(),
+            asset_kind: AssetKind::Instance,
+            derived_from: Some(self.id.clone()),
+            submodels,
+            concept_descriptions,
+        })
+    }
+
+    /// Returns the twin type (the name of the registered actor factory) declared
+    /// in the "TwinType" property of the "TwinConfiguration" submodel, if any.
+    pub fn twin_type(&self) -> Option<String> {
+        self.get_property_str("TwinConfiguration", "TwinType").ok()
+    }
+
+    /// Returns the command groups the twin belongs to, declared as a comma-separated
+    /// list in the "Groups" property of the "TwinConfiguration" submodel.
+    pub fn twin_groups(&self) -> Vec<String> {
+        self.get_property_str("TwinConfiguration", "Groups")
+            .map(|groups| {
+                groups
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|g| !g.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Returns the twins that must be running before this one starts, declared as a
+    /// comma-separated list of asset IDs in the "DependsOn" property of the "TwinConfiguration"
+    /// submodel.
+    pub fn twin_dependencies(&self) -> Vec<AssetID> {
+        self.get_property_str("TwinConfiguration", "DependsOn")
+            .map(|ids| {
+                ids.split(',')
+                    .map(str::trim)
+                    .filter(|id| !id.is_empty())
+                    .map(AssetID::from)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Returns the labels of the twin, declared as the properties of the "Labels" collection
+    /// of the "TwinConfiguration" submodel (property id_short -> value as a string).
+    pub fn twin_labels(&self) -> BTreeMap<String, String> {
+        let serde_json::Value::Object(labels) = self.configuration_json("Labels") else {
+            return BTreeMap::new();
+        };
+        labels
+            .into_iter()
+            .filter_map(|(key, value)| match value {
+                serde_json::Value::String(s) => Some((key, s)),
+                serde_json::Value::Number(_) | serde_json::Value::Bool(_) => Some((key, value.to_string())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns the maximum execution time of the handlers of the twin, in milliseconds,
+    /// declared in the "LatencyBudgetMs" property of the "TwinConfiguration" submodel
+    /// (0 disables the budget). Negative values are ignored.
+    pub fn twin_latency_budget_ms(&self) -> Option<u64> {
+        self.get_property_i64("TwinConfiguration", "LatencyBudgetMs")
+            .ok()
+            .and_then(|ms| u64::try_from(ms).ok())
+    }
+
+    /// Returns the limits on the transitions of the twin, declared in the "MaxTransitionsPerMinute",
+    /// "MinDwellMs" and "FlappingSafeState" properties of the "TwinConfiguration" submodel.
+    /// Negative values are ignored.
+    pub fn twin_transition_limits(&self) -> TransitionLimits {
+        let positive = |name| {
+            self.get_property_i64("TwinConfiguration", name)
+                .ok()
+                .and_then(|value| u64::try_from(value).ok())
+        };
+        TransitionLimits {
+            max_per_minute: positive("MaxTransitionsPerMinute").and_then(|max| u32::try_from(max).ok()),
+            min_dwell_ms: positive("MinDwellMs"),
+            safe_state: self
+                .get_property_str("TwinConfiguration", "FlappingSafeState")
+                .ok(),
+        }
+    }
+
+    /// Returns the location of the asset, declared in the "Latitude" and "Longitude" properties
+    /// (decimal degrees) of the "Location" collection of the "Nameplate" submodel. Coordinates
+    /// out of range are ignored.
+    pub fn twin_location(&self) -> Option<GeoLocation> {
+        GeoLocation::new(
+            self.get_property_f64("Nameplate", "Location.Latitude").ok()?,
+            self.get_property_f64("Nameplate", "Location.Longitude").ok()?,
+        )
+    }
+
+    /// Returns the actor parameters declared in the "Parameters" collection of the
+    /// "TwinConfiguration" submodel as a JSON object (property id_short -> value,
+    /// nested collections become nested objects), or Null if there are none.
+    pub fn twin_parameters(&self) -> serde_json::Value {
+        self.configuration_json("Parameters")
+    }
+
+    /// Returns the scheduled commands declared in the "Schedules" collection of the
+    /// "TwinConfiguration" submodel as a JSON object (schedule name -> object with
+    /// the "Cron", "Command" and optional "Args" of the schedule), or Null if there are none.
+    pub fn twin_schedules(&self) -> serde_json::Value {
+        self.configuration_json("Schedules")
+    }
+
+    /// Returns the conditions declared in the "Conditions" collection of the "TwinConfiguration"
+    /// submodel as a JSON object (condition name -> object with the "When" expression over
+    /// the slots and/or the "InState" state and "ForSeconds" time spent in it, the "Command"
+    /// or "Event" and optional "Args" of the condition), or Null if there are none.
+    pub fn twin_conditions(&self) -> serde_json::Value {
+        self.configuration_json("Conditions")
+    }
+
+    /// Convert a collection of the "TwinConfiguration" submodel into a JSON object
+    fn configuration_json(&self, id_short: &str) -> serde_json::Value {
+        self.submodels
+            .iter()
+            .find(|s| s.id_short == "TwinConfiguration")
+            .and_then(|s| {
+                s.elements.iter().find_map(|elem| match elem {
+                    SubmodelElement::Collection(c) if c.id_short == id_short => Some(c),
+                    _ => None,
+                })
+            })
+            .map_or(
+                serde_json::Value::Null,
+                AssetAdministrationShell::collection_to_json,
+            )
+    }
+
+    /// Convert the properties and sub-collections of a collection into a JSON object
+    fn collection_to_json(collection: &SubmodelCollection) -> serde_json::Value {
+        let object = collection
+            .value
+            .iter()
+            .filter_map(|elem| match elem {
+                SubmodelElement::Property(p) => {
+                    Some((p.id_short.clone(), serde_json::to_value(&p.value).ok()?))
+                }
+                SubmodelElement::Collection(c) => Some((
+                    c.id_short.clone(),
+                    AssetAdministrationShell::collection_to_json(c),
+                )),
+                _ => None,
+            })
+            .collect();
+        serde_json::Value::Object(object)
+    }
+
+    /// Look up a concept by its ID, or by one of the external IDs it is a case of.
+    pub fn concept_description(&self, id: &str) -> Option<&ConceptDescription> {
+        self.concept_descriptions.iter().find(|c| c.id == id).or_else(|| {
+            self.concept_descriptions
+                .iter()
+                .find(|c| c.is_case_of.iter().any(|i| i == id))
+        })
+    }
+
+    /// Returns the concept referenced by the semantic ID of a top-level property
+    /// or collection of a submodel (e.g., the "ActivePower" slot of "PowerAndElectrical").
+    pub fn element_concept(
+        &self,
+        submodel_id_short: &str,
+        element_id_short: &str,
+    ) -> Option<&ConceptDescription> {
+        self.concept_description(self.element_semantic_id(submodel_id_short, element_id_short)?)
+    }
+
+    /// Returns the semantic ID of a top-level property or collection of a submodel
+    fn element_semantic_id(&self, submodel_id_short: &str, element_id_short: &str) -> Option<&String> {
+        self.submodels
+            .iter()
+            .find(|s| s.id_short == submodel_id_short)?
+            .elements
+            .iter()
+            .find_map(|elem| match elem {
+                SubmodelElement::Property(p) if p.id_short == element_id_short => p.semantic_id.as_ref(),
+                SubmodelElement::Collection(c) if c.id_short == element_id_short => c.semantic_id.as_ref(),
+                _ => None,
+            })
+    }
+
+    /// Build the synthetic "OperationalData" submodel holding the live data of the
+    /// twin: its current state and the latest value of each slot. The slot properties
+    /// inherit the semantic ID of the matching "PowerAndElectrical" element.
+    pub fn operational_data(&self, state: &str, slot_values: &HashMap<String, SlotValue>) -> Submodel {
+        let mut slots: Vec<_> = slot_values.iter().collect();
+        slots.sort_by(|a, b| a.0.cmp(b.0));
+
+        let mut elements = vec![SubmodelElement::Property(Property {
+            id_short: "State".to_string(),
+            value_type: ValueType::String,
+            value: Value::Str(state.to_string()),
+            semantic_id: None,
+        })];
+        elements.extend(slots.into_iter().map(|(slot, value)| {
+            let (value_type, value) = match value {
+                SlotValue::Bool(b) => (ValueType::Bool, Value::Bool(*b)),
+                SlotValue::Number(n) => (ValueType::Float, Value::Flt(*n)),
+                SlotValue::Text(t) => (ValueType::String, Value::Str(t.clone())),
+            };
+            SubmodelElement::Property(Property {
+                id_short: slot.clone(),
+                value_type,
+                value,
+                semantic_id: self.element_semantic_id("PowerAndElectrical", slot).cloned(),
+            })
+        }));
+
+        Submodel {
+            id: format!("{}:operational-data", self.id),
+            id_short: OPERATIONAL_DATA.to_string(),
+            elements,
+        }
+    }
+
+    /// Check the semantic IDs of all the properties against the concept descriptions.
+    /// Returns a description of each problem found: unknown concepts, and values whose
+    /// type differs from the one required by their concept.
+    pub fn validate_semantics(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        for submodel in &self.submodels {
+            self.validate_elements_semantics(&submodel.id_short, &submodel.elements, &mut problems);
+        }
+        problems
+    }
+
+    fn validate_elements_semantics(
+        &self,
+        path: &str,
+        elements: &[SubmodelElement],
+        problems: &mut Vec<String>,
+    ) {
+        for elem in elements {
+            match elem {
+                SubmodelElement::Property(p) => {
+                    let Some(semantic_id) = &p.semantic_id else {
+                        continue;
+                    };
+                    match self.concept_description(semantic_id) {
+                        None => {
+                            problems.push(format!("{path}.{}: unknown concept {semantic_id}", p.id_short))
+                        }
+                        Some(ConceptDescription {
+                            value_type: Some(expected),
+                            ..
+                        }) if *expected != p.value_type => problems.push(format!(
+                            "{path}.{}: value type {:?} differs from {:?} required by {semantic_id}",
+                            p.id_short, p.value_type, expected
+                        )),
+                        _ => {}
+                    }
+                }
+                SubmodelElement::Collection(c) => {
+                    if let Some(semantic_id) = &c.semantic_id {
+                        if self.concept_description(semantic_id).is_none() {
+                            problems.push(format!("{path}.{}: unknown concept {semantic_id}", c.id_short));
+                        }
+                    }
+                    self.validate_elements_semantics(&format!("{path}.{}", c.id_short), &c.value, problems);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Returns all the operations declared in the submodels.
+    pub fn operations(&self) -> impl Iterator<Item = &Operation> {
+        self.submodels.iter().flat_map(|s| {
+            s.elements.iter().filter_map(|elem| {
+                if let SubmodelElement::Operation(op) = elem {
+                    Some(op)
+                } else {
+                    None
+                }
+            })
+        })
     }
 
     /// Given a submodel ID, collection ID, and reference element ID,
@@ -191,33 +763,93 @@ impl AssetAdministrationShell {
             }
         })?;
 
-        let sensor_id_prop = sensor_collection.value.iter().find_map(|elem| {
-            if let SubmodelElement::Property(p) = elem {
-                if p.id_short == "SensorID" {
-                    Some(p.value.clone())
-                } else {
-                    None
+        // We expect the SensorID to be a string like "urn:iot-sensor:powerAbs123"
+        sensor_collection.value.iter().find_map(|elem| match elem {
+            SubmodelElement::Property(p) if p.id_short == "SensorID" => match &p.value {
+                Value::Str(sensor_id) => Some(sensor_id.clone()),
+                _ => None,
+            },
+            _ => None,
+        })
+    }
+
+    /// Point the data source of a slot (a collection of the "PowerAndElectrical" submodel)
+    /// to another sensor, replacing the "SensorID" property of the referenced collection.
+    /// Returns the previous sensor ID.
+    pub fn rebind_slot(&mut self, slot: &str, sensor_id: &str) -> Result<String, String> {
+        let reference = self
+            .find_reference_value_in_collection("PowerAndElectrical", slot, "DataSource")
+            .ok_or_else(|| format!("no DataSource reference in PowerAndElectrical.{slot}"))?;
+        let (submodel_id, element_id_short) = reference
+            .split_once('#')
+            .ok_or_else(|| format!("invalid data source reference {reference}"))?;
+        let sensor_collection = self
+            .submodels
+            .iter_mut()
+            .find(|s| s.id == submodel_id)
+            .and_then(|submodel| {
+                submodel.elements.iter_mut().find_map(|elem| match elem {
+                    SubmodelElement::Collection(c) => find_collection_mut(c, element_id_short),
+                    _ => None,
+                })
+            })
+            .ok_or_else(|| format!("data source {reference} not found"))?;
+        sensor_collection
+            .value
+            .iter_mut()
+            .find_map(|elem| match elem {
+                SubmodelElement::Property(p) if p.id_short == "SensorID" => match &mut p.value {
+                    Value::Str(previous) => Some(std::mem::replace(previous, sensor_id.to_string())),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .ok_or_else(|| format!("no SensorID in the data source {reference}"))
+    }
+
+    /// Returns all the events declared in the shell, at any depth
+    pub fn events(&self) -> Vec<&Event> {
+        fn collect<'a>(elements: &'a [SubmodelElement], events: &mut Vec<&'a Event>) {
+            for elem in elements {
+                match elem {
+                    SubmodelElement::Event(e) => events.push(e),
+                    SubmodelElement::Collection(c) => collect(&c.value, events),
+                    _ => {}
                 }
-            } else {
-                None
             }
-        })?;
-
-        // We expect sensor_id_prop to be a Value::Str("urn:iot-sensor:powerAbs123"), etc.
-        if let Value::Str(sensor_id_str) = sensor_id_prop {
-            Some(sensor_id_str)
-        } else {
-            None
         }
+        let mut events = Vec::new();
+        for submodel in &self.submodels {
+            collect(&submodel.elements, &mut events);
+        }
+        events
+    }
+
+    /// Check the declared events against the ones an actor can emit.
+    /// Returns a description of each mismatch found.
+    pub fn validate_events(&self, emittable: &[String]) -> Vec<String> {
+        let declared = self.events();
+        let mut problems: Vec<_> = declared
+            .iter()
+            .filter(|e| !emittable.contains(&e.id_short))
+            .map(|e| format!("event {} is never emitted by the actor", e.id_short))
+            .collect();
+        problems.extend(
+            emittable
+                .iter()
+                .filter(|name| !declared.iter().any(|e| &e.id_short == *name))
+                .map(|name| format!("event {name} emitted by the actor is not declared")),
+        );
+        problems
     }
 
     /// Recursively search for a sub-collection with the given id_short.
-    pub fn find_collection_by_id_short(
-        collection: &SubmodelCollection,
+    pub fn find_collection_by_id_short<'a>(
+        collection: &'a SubmodelCollection,
         target: &str,
-    ) -> Option<SubmodelCollection> {
+    ) -> Option<&'a SubmodelCollection> {
         if collection.id_short == target {
-            return Some(collection.clone());
+            return Some(collection);
         }
         for elem in &collection.value {
             if let SubmodelElement::Collection(c) = elem {
@@ -274,6 +906,7 @@ impl AssetAdministrationShell {
                 SubmodelElement::Collection(sub_coll) => {
                     AssetAdministrationShell::gather_sensor_ids_in_collection(sub_coll, target, result);
                 }
+                #[allow(clippy::collapsible_match)]
                 SubmodelElement::Property(prop) => {
                     if prop.id_short == target {
                         // String expected
@@ -292,6 +925,7 @@ impl AssetAdministrationShell {
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[allow(clippy::single_component_path_imports)]
     use serde_yaml;
 
     fn load_aas_from_yaml(yaml_str: &str) -> AssetAdministrationShell {
@@ -373,6 +1007,50 @@ submodels:
         assert_eq!(sensor_id, Some("urn:iot-sensor:powerAbs123".to_string()));
     }
 
+    #[test]
+    fn test_rebind_slot() {
+        let yaml = r#"
+id: "urn:aas:example"
+id_short: "ExampleAAS"
+submodels:
+  - id: "urn:aas:example:power"
+    id_short: "PowerAndElectrical"
+    elements:
+      - element_type: "collection"
+        id_short: "InputCurrent"
+        value:
+          - element_type: "referenceelement"
+            id_short: "DataSource"
+            value: "urn:aas:example:datasources#SensorInputCurrent"
+  - id: "urn:aas:example:datasources"
+    id_short: "IoTDataSources"
+    elements:
+      - element_type: "collection"
+        id_short: "Sensors"
+        value:
+          - element_type: "collection"
+            id_short: "SensorInputCurrent"
+            value:
+              - element_type: "property"
+                id_short: "SensorID"
+                value_type: "string"
+                value: "urn:iot-sensor:current123"
+"#;
+        let mut aas = load_aas_from_yaml(yaml);
+
+        let previous = aas.rebind_slot("InputCurrent", "urn:iot-sensor:current456");
+        assert_eq!(previous.unwrap(), "urn:iot-sensor:current123");
+        assert_eq!(
+            aas.resolve_sensor_reference("urn:aas:example:datasources#SensorInputCurrent"),
+            Some("urn:iot-sensor:current456".to_string())
+        );
+        assert_eq!(
+            aas.find_elements_in_collection("IoTDataSources", "Sensors", "SensorID"),
+            ["urn:iot-sensor:current456"]
+        );
+        assert!(aas.rebind_slot("PowerDraw", "urn:iot-sensor:power1").is_err());
+    }
+
     #[test]
     fn test_find_collection_by_id_short() {
         let yaml = r#"
@@ -409,4 +1087,517 @@ submodels:
         assert!(target_collection.is_some());
         assert_eq!(target_collection.unwrap().id_short, "TargetCollection");
     }
+
+    #[test]
+    fn test_operations() {
+        let yaml = r#"
+id: "urn:aas:example"
+id_short: "ExampleAAS"
+submodels:
+  - id: "urn:aas:example:submodel1"
+    id_short: "Submodel1"
+    elements:
+      - element_type: "operation"
+        id_short: "Reset"
+        cooldown_ms: 5000
+      - element_type: "property"
+        id_short: "Prop"
+        value_type: "int"
+        value: 1
+  - id: "urn:aas:example:submodel2"
+    id_short: "Submodel2"
+    elements:
+      - element_type: "operation"
+        id_short: "SwitchOn"
+"#;
+        let aas = load_aas_from_yaml(yaml);
+
+        let ops: Vec<_> = aas
+            .operations()
+            .map(|op| (op.id_short.as_str(), op.cooldown_ms))
+            .collect();
+        assert_eq!(ops, vec![("Reset", Some(5000)), ("SwitchOn", None)]);
+    }
+
+    #[test]
+    fn test_twin_type() {
+        let yaml = r#"
+id: "urn:aas:example"
+id_short: "ExampleAAS"
+submodels:
+  - id: "urn:aas:example:config"
+    id_short: "TwinConfiguration"
+    elements:
+      - element_type: "property"
+        id_short: "TwinType"
+        value_type: "string"
+        value: "LightBulb"
+"#;
+        let aas = load_aas_from_yaml(yaml);
+        assert_eq!(aas.twin_type(), Some("LightBulb".to_string()));
+        assert!(aas.twin_groups().is_empty());
+        assert!(aas.twin_dependencies().is_empty());
+        assert!(aas.twin_labels().is_empty());
+        assert_eq!(aas.twin_latency_budget_ms(), None);
+        assert!(aas.twin_transition_limits().is_empty());
+        assert!(aas.twin_conditions().is_null());
+
+        let yaml = r#"
+id: "urn:aas:example"
+id_short: "ExampleAAS"
+submodels: []
+"#;
+        let aas = load_aas_from_yaml(yaml);
+        assert_eq!(aas.twin_type(), None);
+    }
+
+    #[test]
+    fn test_twin_groups() {
+        let yaml = r#"
+id: "urn:aas:example"
+id_short: "ExampleAAS"
+submodels:
+  - id: "urn:aas:example:config"
+    id_short: "TwinConfiguration"
+    elements:
+      - element_type: "property"
+        id_short: "Groups"
+        value_type: "string"
+        value: "car-park-b, chargers,"
+      - element_type: "property"
+        id_short: "DependsOn"
+        value_type: "string"
+        value: "urn:aas:example:charger-1, urn:aas:example:charger-2"
+      - element_type: "property"
+        id_short: "LatencyBudgetMs"
+        value_type: "int"
+        value: 50
+      - element_type: "property"
+        id_short: "MaxTransitionsPerMinute"
+        value_type: "int"
+        value: 6
+      - element_type: "property"
+        id_short: "FlappingSafeState"
+        value_type: "string"
+        value: "Off"
+      - element_type: "collection"
+        id_short: "Labels"
+        value:
+          - element_type: "property"
+            id_short: "site"
+            value_type: "string"
+            value: "hq"
+          - element_type: "property"
+            id_short: "floor"
+            value_type: "int"
+            value: 2
+"#;
+        let aas = load_aas_from_yaml(yaml);
+        assert_eq!(aas.twin_groups(), vec!["car-park-b", "chargers"]);
+        assert_eq!(
+            aas.twin_dependencies(),
+            vec!["urn:aas:example:charger-1", "urn:aas:example:charger-2"]
+        );
+        assert_eq!(aas.twin_latency_budget_ms(), Some(50));
+        assert_eq!(
+            aas.twin_transition_limits(),
+            TransitionLimits {
+                max_per_minute: Some(6),
+                min_dwell_ms: None,
+                safe_state: Some("Off".to_string()),
+            }
+        );
+        assert_eq!(
+            aas.twin_labels(),
+            BTreeMap::from([
+                ("floor".to_string(), "2".to_string()),
+                ("site".to_string(), "hq".to_string())
+            ])
+        );
+    }
+
+    #[test]
+    fn test_twin_location() {
+        let yaml = r#"
+id: "urn:aas:example"
+id_short: "ExampleAAS"
+submodels:
+  - id: "urn:aas:example:nameplate"
+    id_short: "Nameplate"
+    elements:
+      - element_type: "collection"
+        id_short: "Location"
+        value:
+          - element_type: "property"
+            id_short: "Latitude"
+            value_type: "float"
+            value: 45.4642
+          - element_type: "property"
+            id_short: "Longitude"
+            value_type: "float"
+            value: 9.19
+"#;
+        let aas = load_aas_from_yaml(yaml);
+        let milan = aas.twin_location().unwrap();
+        assert_eq!(milan, GeoLocation::new(45.4642, 9.19).unwrap());
+        let rome = GeoLocation::new(41.9028, 12.4964).unwrap();
+        assert!((milan.distance_km(&rome) - 477.0).abs() < 1.0);
+        assert_eq!(milan.distance_km(&milan), 0.0);
+
+        assert!(load_aas_from_yaml(&yaml.replace("45.4642", "95.0"))
+            .twin_location()
+            .is_none());
+        assert!(load_aas_from_yaml(&yaml.replace("Longitude", "Altitude"))
+            .twin_location()
+            .is_none());
+    }
+
+    #[test]
+    fn test_twin_schedules() {
+        let yaml = r#"
+id: "urn:aas:example"
+id_short: "ExampleAAS"
+submodels:
+  - id: "urn:aas:example:config"
+    id_short: "TwinConfiguration"
+    elements:
+      - element_type: "collection"
+        id_short: "Schedules"
+        value:
+          - element_type: "collection"
+            id_short: "NightCharging"
+            value:
+              - element_type: "property"
+                id_short: "Cron"
+                value_type: "string"
+                value: "0 23 * * *"
+              - element_type: "property"
+                id_short: "Command"
+                value_type: "string"
+                value: "SetChargingCurrent"
+              - element_type: "collection"
+                id_short: "Args"
+                value:
+                  - element_type: "property"
+                    id_short: "desired_current"
+                    value_type: "float"
+                    value: 6
+"#;
+        let aas = load_aas_from_yaml(yaml);
+        assert_eq!(
+            aas.twin_schedules(),
+            serde_json::json!({"NightCharging": {
+                "Cron": "0 23 * * *",
+                "Command": "SetChargingCurrent",
+                "Args": {"desired_current": 6}
+            }})
+        );
+        assert_eq!(aas.twin_parameters(), serde_json::Value::Null);
+    }
+
+    #[test]
+    fn test_twin_parameters() {
+        let yaml = r#"
+id: "urn:aas:example"
+id_short: "ExampleAAS"
+submodels:
+  - id: "urn:aas:example:config"
+    id_short: "TwinConfiguration"
+    elements:
+      - element_type: "property"
+        id_short: "TwinType"
+        value_type: "string"
+        value: "ThresholdDevice"
+      - element_type: "collection"
+        id_short: "Parameters"
+        value:
+          - element_type: "property"
+            id_short: "slot"
+            value_type: "string"
+            value: "Temperature"
+          - element_type: "property"
+            id_short: "on_above"
+            value_type: "float"
+            value: 30.5
+          - element_type: "collection"
+            id_short: "states"
+            value:
+              - element_type: "property"
+                id_short: "on"
+                value_type: "string"
+                value: "Heating"
+"#;
+        let aas = load_aas_from_yaml(yaml);
+        assert_eq!(
+            aas.twin_parameters(),
+            serde_json::json!({"slot": "Temperature", "on_above": 30.5, "states": {"on": "Heating"}})
+        );
+
+        let yaml = r#"
+id: "urn:aas:example"
+id_short: "ExampleAAS"
+submodels: []
+"#;
+        let aas = load_aas_from_yaml(yaml);
+        assert_eq!(aas.twin_parameters(), serde_json::Value::Null);
+    }
+
+    #[test]
+    fn test_instantiate() {
+        let type_yaml = r#"
+id: "urn:aas:example:type"
+id_short: "ExampleType"
+description: "Example product"
+asset_kind: Type
+administration:
+  version: "1"
+  revision: "0"
+submodels:
+  - id: "urn:aas:example:type:config"
+    id_short: "TwinConfiguration"
+    elements:
+      - element_type: "property"
+        id_short: "TwinType"
+        value_type: "string"
+        value: "LightBulb"
+  - id: "urn:aas:example:type:datasources"
+    id_short: "IoTDataSources"
+    elements:
+      - element_type: "collection"
+        id_short: "Sensors"
+        value:
+          - element_type: "collection"
+            id_short: "SensorPower"
+            value:
+              - element_type: "property"
+                id_short: "SensorID"
+                value_type: "string"
+                value: "urn:iot-sensor:placeholder"
+"#;
+        let instance_yaml = r#"
+id: "urn:aas:example:instance-1"
+id_short: "Instance1"
+derived_from: "urn:aas:example:type"
+submodels:
+  - id: "urn:aas:example:instance-1:datasources"
+    id_short: "IoTDataSources"
+    elements:
+      - element_type: "collection"
+        id_short: "Sensors"
+        value:
+          - element_type: "collection"
+            id_short: "SensorPower"
+            value:
+              - element_type: "property"
+                id_short: "SensorID"
+                value_type: "string"
+                value: "urn:iot-sensor:power001"
+"#;
+        let type_aas = load_aas_from_yaml(type_yaml);
+        let overlay = load_aas_from_yaml(instance_yaml);
+        assert_eq!(overlay.asset_kind, AssetKind::Instance);
+
+        let aas = type_aas.instantiate(&overlay).unwrap();
+        assert_eq!(aas.id, "urn:aas:example:instance-1");
+        assert_eq!(aas.asset_kind, AssetKind::Instance);
+        assert_eq!(aas.description.as_deref(), Some("Example product"));
+        assert_eq!(aas.twin_type(), Some("LightBulb".to_string()));
+        assert_eq!(
+            aas.find_elements_in_collection("IoTDataSources", "Sensors", "SensorID"),
+            vec!["urn:iot-sensor:power001"]
+        );
+
+        // Only Type shells can be instantiated, and only by their own instances
+        assert!(overlay.instantiate(&overlay).is_err());
+        let mut other = overlay.clone();
+        other.derived_from = Some("urn:aas:example:other-type".into());
+        assert!(type_aas.instantiate(&other).is_err());
+    }
+
+    #[test]
+    fn test_concept_descriptions() {
+        let yaml = r#"
+id: "urn:aas:example"
+id_short: "ExampleAAS"
+submodels:
+  - id: "urn:aas:example:power"
+    id_short: "PowerAndElectrical"
+    elements:
+      - element_type: "collection"
+        id_short: "ActivePower"
+        semantic_id: "urn:concept:active-power"
+        value: []
+      - element_type: "property"
+        id_short: "RatedPower"
+        value_type: "string"
+        value: "3000"
+        semantic_id: "0173-1#02-AAV232#002"
+      - element_type: "property"
+        id_short: "Voltage"
+        value_type: "float"
+        value: 230.0
+        semantic_id: "urn:concept:voltage"
+concept_descriptions:
+  - id: "urn:concept:active-power"
+    id_short: "ActivePower"
+    preferred_name: "Active power"
+    unit: "W"
+    value_type: "float"
+    is_case_of: ["0173-1#02-AAV232#002"]
+"#;
+        let aas = load_aas_from_yaml(yaml);
+        let concept = aas.element_concept("PowerAndElectrical", "ActivePower").unwrap();
+        assert_eq!(concept.unit.as_deref(), Some("W"));
+        // Lookup through the ECLASS IRDI
+        let concept = aas.element_concept("PowerAndElectrical", "RatedPower").unwrap();
+        assert_eq!(concept.id, "urn:concept:active-power");
+        assert!(aas.element_concept("PowerAndElectrical", "Voltage").is_none());
+
+        let problems = aas.validate_semantics();
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].starts_with("PowerAndElectrical.RatedPower: value type"));
+        assert!(problems[1].contains("unknown concept urn:concept:voltage"));
+    }
+
+    #[test]
+    fn test_operational_data() {
+        let yaml = r#"
+id: "urn:aas:example"
+id_short: "ExampleAAS"
+submodels:
+  - id: "urn:aas:example:power"
+    id_short: "PowerAndElectrical"
+    elements:
+      - element_type: "collection"
+        id_short: "ActivePower"
+        semantic_id: "urn:concept:active-power"
+        value: []
+"#;
+        let aas = load_aas_from_yaml(yaml);
+        let values = HashMap::from([
+            ("ActivePower".to_string(), SlotValue::Number(1500.0)),
+            ("DoorClosed".to_string(), SlotValue::Bool(true)),
+        ]);
+        let submodel = aas.operational_data("Charging", &values);
+        assert_eq!(submodel.id, "urn:aas:example:operational-data");
+        assert_eq!(submodel.id_short, OPERATIONAL_DATA);
+        let json = serde_json::to_value(&submodel.elements).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([
+                {"element_type": "property", "id_short": "State", "value_type": "string", "value": "Charging"},
+                {"element_type": "property", "id_short": "ActivePower", "value_type": "float", "value": 1500.0,
+                 "semantic_id": "urn:concept:active-power"},
+                {"element_type": "property", "id_short": "DoorClosed", "value_type": "bool", "value": true},
+            ])
+        );
+    }
+
+    #[test]
+    fn test_events() {
+        let yaml = r#"
+id: "urn:aas:example"
+id_short: "ExampleAAS"
+submodels:
+  - id: "urn:aas:example:power"
+    id_short: "PowerAndElectrical"
+    elements:
+      - element_type: "event"
+        id_short: "OvercurrentFault"
+        observed: "urn:aas:example:power#InputCurrent"
+        payload:
+          - name: "current"
+            value_type: "float"
+          - name: "limit"
+            value_type: "float"
+        actuation:
+          backend: "http"
+          url: "http://chargers.local/{asset_id}/limit"
+          auth_secret: "CHARGER_TOKEN"
+      - element_type: "collection"
+        id_short: "Battery"
+        value:
+          - element_type: "event"
+            id_short: "LowBatteryAlert"
+"#;
+        let aas = load_aas_from_yaml(yaml);
+        let events: Vec<_> = aas.events().iter().map(|e| e.id_short.as_str()).collect();
+        assert_eq!(events, vec!["OvercurrentFault", "LowBatteryAlert"]);
+
+        let problems = aas.validate_events(&["OvercurrentFault".to_string(), "ChargingComplete".to_string()]);
+        assert_eq!(
+            problems,
+            vec![
+                "event LowBatteryAlert is never emitted by the actor",
+                "event ChargingComplete emitted by the actor is not declared"
+            ]
+        );
+
+        let (payload, problems) =
+            aas.events()[0].shape_payload(&serde_json::json!({"current": 20.5, "limit": "16", "phase": 1}));
+        assert_eq!(payload, serde_json::json!({"current": 20.5, "limit": null}));
+        assert_eq!(problems.len(), 2);
+
+        assert_eq!(
+            aas.events()[0].actuation,
+            Some(Actuation::Http(HttpActuation {
+                url: "http://chargers.local/{asset_id}/limit".to_string(),
+                method: "POST".to_string(),
+                auth_secret: Some("CHARGER_TOKEN".to_string()),
+                auth_scheme: "Bearer".to_string(),
+            }))
+        );
+        assert!(aas.events()[1].actuation.is_none());
+    }
+
+    fn nested_shell(depth: usize) -> String {
+        let collection = r#"{"element_type": "collection", "id_short": "Nested", "value": ["#;
+        let leaf = r#"{"element_type": "property", "id_short": "Leaf", "value_type": "int", "value": 1}"#;
+        format!(
+            r#"{{"id": "urn:aas:nested", "id_short": "Nested", "submodels": [{{"id": "urn:aas:nested:sm", "id_short": "Deep", "elements": [{}{leaf}{}]}}]}}"#,
+            collection.repeat(depth),
+            "]}".repeat(depth)
+        )
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let ok = nested_shell(MAX_COLLECTION_DEPTH);
+        assert!(AssetAdministrationShell::from_json_reader(ok.as_bytes()).is_ok());
+        // JSON is valid YAML
+        assert!(AssetAdministrationShell::from_reader(ok.as_bytes()).is_ok());
+
+        let deep = nested_shell(MAX_COLLECTION_DEPTH + 1);
+        assert!(AssetAdministrationShell::from_json_reader(deep.as_bytes()).is_err());
+        assert!(AssetAdministrationShell::from_reader(deep.as_bytes()).is_err());
+        let too_deep = nested_shell(1000);
+        assert!(AssetAdministrationShell::from_json_reader(too_deep.as_bytes()).is_err());
+        assert!(AssetAdministrationShell::from_reader(too_deep.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_asset_id() {
+        assert!(
+            AssetID::from("urn:aas:smart-home:charging-station:ac-level2:id-000001")
+                .validate()
+                .is_ok()
+        );
+        assert!(AssetID::from("urn:aas:example").validate().is_ok());
+
+        for invalid in [
+            "light-1",
+            "urn:aas",
+            "urn::light",
+            "urn:aas:light:",
+            "urn:aas:light/1",
+            "urn:aas:light 1",
+        ] {
+            assert!(AssetID::from(invalid).validate().is_err(), "{invalid}");
+        }
+        // Invalid IDs are rejected when the shell is loaded
+        let shell = |id: &str| format!(r#"{{"id": "{id}", "id_short": "Light", "submodels": []}}"#);
+        assert!(AssetAdministrationShell::from_json_reader(shell("urn:aas:light:1").as_bytes()).is_ok());
+        let error = AssetAdministrationShell::from_reader(shell("light+1").as_bytes()).unwrap_err();
+        assert_eq!(error, r#"invalid asset ID "light+1": not a URN, "urn:" expected"#);
+    }
 }
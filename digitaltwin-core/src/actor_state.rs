@@ -1,13 +1,113 @@
 use std::collections::HashMap;
 
+use serde::{Deserialize, Serialize};
+
+use crate::{ActorContext, SlotValue};
+
 pub type ActorStateType = dyn ActorState + Send + Sync + 'static;
 
+/// An event emitted by an actor during a transition (e.g., "ChargingComplete"),
+/// published if declared by an Event element of the AAS
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ActorEvent {
+    pub name: String,
+    pub payload: serde_json::Value,
+}
+
+/// Limits on the transitions of an actor, to detect a model oscillating between states
+/// (flapping) before it drives the automations downstream
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TransitionLimits {
+    /// Maximum number of transitions within a minute
+    pub max_per_minute: Option<u32>,
+    /// Minimum time spent in a state before leaving it, in milliseconds
+    pub min_dwell_ms: Option<u64>,
+    /// State the actor is latched into when flapping
+    pub safe_state: Option<String>,
+}
+
+impl TransitionLimits {
+    pub fn is_empty(&self) -> bool {
+        self.max_per_minute.is_none() && self.min_dwell_ms.is_none()
+    }
+
+    /// These limits, each one replacing the other one when set
+    pub fn or(self, other: TransitionLimits) -> TransitionLimits {
+        TransitionLimits {
+            max_per_minute: self.max_per_minute.or(other.max_per_minute),
+            min_dwell_ms: self.min_dwell_ms.or(other.min_dwell_ms),
+            safe_state: self.safe_state.or(other.safe_state),
+        }
+    }
+}
+
+/// A transition made by a handler of an actor
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeclaredTransition {
+    pub from: String,
+    pub to: String,
+    /// Input slot or command handled by the handler
+    pub trigger: String,
+}
+
+/// The states of an actor, or of one of its regions, and the transitions between them,
+/// to draw the state machine
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateMachine {
+    /// Region of an actor composed of several regions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    pub states: Vec<String>,
+    pub transitions: Vec<DeclaredTransition>,
+    pub current: String,
+}
+
 pub trait ActorState {
     /// Handle the change of an input slot
-    fn input_change(&self, slot: &str, value: f32) -> Box<ActorStateType>;
+    fn input_value(&self, slot: &str, value: SlotValue) -> Box<ActorStateType>;
+    /// Handle the change of a numeric input slot
+    fn input_change(&self, slot: &str, value: f32) -> Box<ActorStateType> {
+        self.input_value(slot, value.into())
+    }
     /// Execute a command
     fn execute(&self, command: &str, input: serde_json::Value) -> Box<ActorStateType>;
 
+    /// Names of the events the actor can emit
+    fn events(&self) -> Vec<String> {
+        Vec::new()
+    }
+    /// Commands handled in the current state, sorted
+    fn commands(&self) -> Vec<String> {
+        Vec::new()
+    }
+    /// Input slots handled in the current state, sorted
+    fn inputs(&self) -> Vec<String> {
+        Vec::new()
+    }
+    /// Take the events emitted by the transitions since the last call
+    fn take_events(&mut self) -> Vec<ActorEvent> {
+        Vec::new()
+    }
+    /// Limits on the transitions of the actor, declared with the actor
+    fn transition_limits(&self) -> TransitionLimits {
+        TransitionLimits::default()
+    }
+    /// Give the actor the context of its twin, kept across its transitions
+    fn set_context(&mut self, _context: ActorContext) {}
+    /// The state machines of the actor, one for each region. Without a transition table,
+    /// only the current state is known.
+    fn state_machines(&self) -> Vec<StateMachine> {
+        vec![StateMachine {
+            region: None,
+            states: vec![self.state()],
+            transitions: Vec::new(),
+            current: self.state(),
+        }]
+    }
+
+    /// Serialize the actor (type, state and properties) into a snapshot
+    fn to_snapshot(&self) -> Result<serde_json::Value, String>;
+
     // Helper functions
     fn as_any(&self) -> &dyn std::any::Any;
     fn type_name(&self) -> String;
@@ -23,8 +123,10 @@ impl std::fmt::Debug for Box<ActorStateType> {
 /// Factory trait for creating actors. Each Actor type must implement this trait
 /// to provide a default instance and a way to create instances with parameters.
 pub trait ActorFactory {
-    fn create_default() -> (Box<ActorStateType>, Vec<&'static str>);
-    fn create_with_params(params: serde_json::Value) -> (Box<ActorStateType>, Vec<&'static str>);
+    fn create_default() -> (Box<ActorStateType>, Vec<String>);
+    fn create_with_params(params: serde_json::Value) -> (Box<ActorStateType>, Vec<String>);
+    /// Restore an actor from a snapshot produced by `ActorState::to_snapshot()`
+    fn from_snapshot(snapshot: serde_json::Value) -> Result<(Box<ActorStateType>, Vec<String>), String>;
 }
 
 /// State behavior trait for providing the input and command handler dispatch maps.
@@ -38,9 +140,15 @@ pub trait StateBehavior {
     fn create_command_map() -> CommandMap<Self::Actor>;
 
     fn state_name() -> String;
+
+    /// Transitions made by the handlers of the state to other states, as (input slot or
+    /// command, target state) pairs
+    fn transitions() -> Vec<(String, String)> {
+        Vec::new()
+    }
 }
 
 /// The dispatch map associates input slots (strings) with their handlers
-pub type DispatchMap<A> = HashMap<&'static str, fn(&A, f32) -> Box<ActorStateType>>;
+pub type DispatchMap<A> = HashMap<&'static str, fn(&A, SlotValue) -> Box<ActorStateType>>;
 /// The command map associates commands (strings) with their handlers
 pub type CommandMap<A> = HashMap<&'static str, fn(&A, serde_json::Value) -> Box<ActorStateType>>;
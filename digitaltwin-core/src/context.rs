@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{ActorStateType, FromSlotValue, SlotValue};
+
+/// The latest value received on a slot, with the time it was received
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CachedValue {
+    pub value: SlotValue,
+    pub timestamp: DateTime<Utc>,
+}
+
+type Values = Arc<RwLock<HashMap<String, CachedValue>>>;
+
+/// Cache of the latest value of every slot of a twin, written by its runner before each
+/// value reaches the actor
+#[derive(Debug, Default)]
+pub struct SlotCache {
+    values: Values,
+}
+
+impl SlotCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the latest value of a slot
+    pub fn record(&self, slot: &str, value: SlotValue, timestamp: DateTime<Utc>) {
+        let mut values = self.values.write().unwrap_or_else(|e| e.into_inner());
+        values.insert(slot.to_string(), CachedValue { value, timestamp });
+    }
+
+    /// Record the latest value of a slot and feed it to the actor, whose handler finds it
+    /// in the cache along with the values of the other slots
+    pub fn feed(
+        &self,
+        actor: &ActorStateType,
+        slot: &str,
+        value: SlotValue,
+        timestamp: DateTime<Utc>,
+    ) -> Box<ActorStateType> {
+        self.record(slot, value.clone(), timestamp);
+        actor.input_value(slot, value)
+    }
+
+    /// Read-only view of the cache, to be given to the actor
+    pub fn context(&self) -> ActorContext {
+        ActorContext {
+            values: self.values.clone(),
+        }
+    }
+}
+
+/// Context of an actor, giving its handlers a read-only view of the latest value of every
+/// slot of the twin, so that a handler can combine the value it receives with the other
+/// ones without keeping copies in the fields of the actor. Empty for an actor not run by
+/// a twin.
+#[derive(Debug, Clone, Default)]
+pub struct ActorContext {
+    values: Values,
+}
+
+impl ActorContext {
+    /// The latest value of a slot, None if not received yet or of the wrong type
+    pub fn last<T: FromSlotValue>(&self, slot: &str) -> Option<T> {
+        self.get(slot).and_then(|cached| T::from_slot_value(cached.value))
+    }
+
+    /// Time the latest value of a slot was received
+    pub fn last_update(&self, slot: &str) -> Option<DateTime<Utc>> {
+        self.get(slot).map(|cached| cached.timestamp)
+    }
+
+    /// The latest value of a slot, with the time it was received
+    pub fn get(&self, slot: &str) -> Option<CachedValue> {
+        let values = self.values.read().unwrap_or_else(|e| e.into_inner());
+        values.get(slot).cloned()
+    }
+
+    /// The latest values of all the slots
+    pub fn values(&self) -> HashMap<String, CachedValue> {
+        self.values.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slot_cache() {
+        let cache = SlotCache::new();
+        let context = cache.context();
+        assert_eq!(context.last::<f32>("Current"), None);
+
+        let now = Utc::now();
+        cache.record("Current", SlotValue::Number(4.5), now);
+        cache.record("Door", SlotValue::Bool(true), now);
+        // The context sees the values recorded after it was created
+        assert_eq!(context.last::<f32>("Current"), Some(4.5));
+        assert_eq!(context.last::<bool>("Door"), Some(true));
+        assert_eq!(context.last::<f64>("Door"), None);
+        assert_eq!(context.last_update("Current"), Some(now));
+        assert_eq!(context.values().len(), 2);
+        assert!(ActorContext::default().values().is_empty());
+    }
+}
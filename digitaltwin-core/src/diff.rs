@@ -0,0 +1,216 @@
+/// Structural comparison of two versions of an Asset Administration Shell
+use serde::Serialize;
+
+use crate::aas::{AssetAdministrationShell, SubmodelElement, Value};
+
+/// A single difference between two versions of an AAS. Element paths are made of
+/// id_shorts separated by dots, starting with the submodel (e.g., "PowerAndElectrical.ActivePower").
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum AasChange {
+    /// A shell-level field changed (description, administration, concept descriptions...)
+    ShellChanged {
+        field: String,
+    },
+    SubmodelAdded {
+        submodel: String,
+    },
+    SubmodelRemoved {
+        submodel: String,
+    },
+    ElementAdded {
+        path: String,
+    },
+    ElementRemoved {
+        path: String,
+    },
+    /// The element definition changed (type, kind, semantic ID, operation variables...)
+    ElementChanged {
+        path: String,
+    },
+    /// Only the value of a property or reference changed
+    ValueChanged {
+        path: String,
+        old: Value,
+        new: Value,
+    },
+}
+
+impl AssetAdministrationShell {
+    /// Compare this shell with a newer version of it
+    pub fn diff(&self, new: &AssetAdministrationShell) -> Vec<AasChange> {
+        let mut changes = Vec::new();
+
+        let shell_fields = [
+            ("id_short", self.id_short != new.id_short),
+            ("description", self.description != new.description),
+            ("administration", self.administration != new.administration),
+            ("asset_kind", self.asset_kind != new.asset_kind),
+            ("derived_from", self.derived_from != new.derived_from),
+            (
+                "concept_descriptions",
+                !same_json(&self.concept_descriptions, &new.concept_descriptions),
+            ),
+        ];
+        for (field, changed) in shell_fields {
+            if changed {
+                changes.push(AasChange::ShellChanged {
+                    field: field.to_string(),
+                });
+            }
+        }
+
+        for old_submodel in &self.submodels {
+            match new.submodels.iter().find(|s| s.id_short == old_submodel.id_short) {
+                Some(new_submodel) => {
+                    if old_submodel.id != new_submodel.id {
+                        changes.push(AasChange::ElementChanged {
+                            path: old_submodel.id_short.clone(),
+                        });
+                    }
+                    diff_elements(
+                        &old_submodel.id_short,
+                        &old_submodel.elements,
+                        &new_submodel.elements,
+                        &mut changes,
+                    );
+                }
+                None => changes.push(AasChange::SubmodelRemoved {
+                    submodel: old_submodel.id_short.clone(),
+                }),
+            }
+        }
+        for new_submodel in &new.submodels {
+            if !self.submodels.iter().any(|s| s.id_short == new_submodel.id_short) {
+                changes.push(AasChange::SubmodelAdded {
+                    submodel: new_submodel.id_short.clone(),
+                });
+            }
+        }
+        changes
+    }
+}
+
+fn same_json<T: Serialize>(a: &T, b: &T) -> bool {
+    serde_json::to_value(a).ok() == serde_json::to_value(b).ok()
+}
+
+/// Compare two lists of elements, matching them by id_short
+fn diff_elements(path: &str, old: &[SubmodelElement], new: &[SubmodelElement], changes: &mut Vec<AasChange>) {
+    for old_elem in old {
+        let elem_path = format!("{path}.{}", old_elem.id_short());
+        let Some(new_elem) = new.iter().find(|e| e.id_short() == old_elem.id_short()) else {
+            changes.push(AasChange::ElementRemoved { path: elem_path });
+            continue;
+        };
+        match (old_elem, new_elem) {
+            (SubmodelElement::Collection(old_c), SubmodelElement::Collection(new_c)) => {
+                if old_c.semantic_id != new_c.semantic_id {
+                    changes.push(AasChange::ElementChanged {
+                        path: elem_path.clone(),
+                    });
+                }
+                diff_elements(&elem_path, &old_c.value, &new_c.value, changes);
+            }
+            (SubmodelElement::Property(old_p), SubmodelElement::Property(new_p))
+                if old_p.value_type == new_p.value_type && old_p.semantic_id == new_p.semantic_id =>
+            {
+                if !same_json(&old_p.value, &new_p.value) {
+                    changes.push(AasChange::ValueChanged {
+                        path: elem_path,
+                        old: old_p.value.clone(),
+                        new: new_p.value.clone(),
+                    });
+                }
+            }
+            (SubmodelElement::ReferenceElement(old_r), SubmodelElement::ReferenceElement(new_r)) => {
+                if old_r.value != new_r.value {
+                    changes.push(AasChange::ValueChanged {
+                        path: elem_path,
+                        old: Value::Str(old_r.value.clone()),
+                        new: Value::Str(new_r.value.clone()),
+                    });
+                }
+            }
+            _ => {
+                if !same_json(old_elem, new_elem) {
+                    changes.push(AasChange::ElementChanged { path: elem_path });
+                }
+            }
+        }
+    }
+    for new_elem in new {
+        if !old.iter().any(|e| e.id_short() == new_elem.id_short()) {
+            changes.push(AasChange::ElementAdded {
+                path: format!("{path}.{}", new_elem.id_short()),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_aas_from_yaml(yaml_str: &str) -> AssetAdministrationShell {
+        serde_yaml::from_str(yaml_str).expect("Failed to parse YAML")
+    }
+
+    const BASE: &str = r#"
+id: "urn:aas:example"
+id_short: "ExampleAAS"
+submodels:
+  - id: "urn:aas:example:power"
+    id_short: "PowerAndElectrical"
+    elements:
+      - element_type: "collection"
+        id_short: "CurrentPowerDraw"
+        value:
+          - element_type: "referenceelement"
+            id_short: "DataSource"
+            value: "urn:aas:example:datasources#SensorPower"
+      - element_type: "property"
+        id_short: "MaxPower"
+        value_type: "float"
+        value: 60.0
+      - element_type: "operation"
+        id_short: "SwitchOn"
+  - id: "urn:aas:example:maintenance"
+    id_short: "MaintenanceDiagnostics"
+    elements: []
+"#;
+
+    #[test]
+    fn test_no_changes() {
+        let aas = load_aas_from_yaml(BASE);
+        assert!(aas.diff(&aas.clone()).is_empty());
+    }
+
+    #[test]
+    fn test_changes() {
+        let old = load_aas_from_yaml(BASE);
+        let new = load_aas_from_yaml(
+            &BASE
+                .replace("#SensorPower", "#SensorPower2")
+                .replace("value: 60.0", "value: 75.0")
+                .replace(
+                    r#"id_short: "SwitchOn""#,
+                    r#"id_short: "SwitchOn"
+        cooldown_ms: 1000"#,
+                )
+                .replace("MaintenanceDiagnostics", "Documentation"),
+        );
+        let changes = serde_json::to_value(old.diff(&new)).unwrap();
+        assert_eq!(
+            changes,
+            serde_json::json!([
+                {"change": "value_changed", "path": "PowerAndElectrical.CurrentPowerDraw.DataSource",
+                 "old": "urn:aas:example:datasources#SensorPower", "new": "urn:aas:example:datasources#SensorPower2"},
+                {"change": "value_changed", "path": "PowerAndElectrical.MaxPower", "old": 60.0, "new": 75.0},
+                {"change": "element_changed", "path": "PowerAndElectrical.SwitchOn"},
+                {"change": "submodel_removed", "submodel": "MaintenanceDiagnostics"},
+                {"change": "submodel_added", "submodel": "Documentation"},
+            ])
+        );
+    }
+}
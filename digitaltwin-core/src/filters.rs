@@ -0,0 +1,282 @@
+/// Smoothing filters applied to the numeric values received on the input slots
+use std::collections::VecDeque;
+
+use crate::index::IndexedShell;
+use crate::types::SlotValue;
+
+/// A filter, as declared in the "Filter" collection of a data source
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterKind {
+    /// Mean of the last `window` values
+    MovingAverage { window: usize },
+    /// Median of the last `window` values, discarding isolated spikes
+    Median { window: usize },
+    /// Exponential smoothing: each value weighs `alpha`, the previous output `1 - alpha`
+    Exponential { alpha: f64 },
+    /// One-dimensional Kalman filter of a constant value with process and measurement noise
+    Kalman {
+        process_noise: f64,
+        measurement_noise: f64,
+    },
+}
+
+impl std::fmt::Display for FilterKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FilterKind::MovingAverage { window } => write!(f, "moving average of {window} values"),
+            FilterKind::Median { window } => write!(f, "median of {window} values"),
+            FilterKind::Exponential { alpha } => write!(f, "exponential smoothing (alpha {alpha})"),
+            FilterKind::Kalman {
+                process_noise,
+                measurement_noise,
+            } => write!(
+                f,
+                "Kalman filter (process noise {process_noise}, measurement noise {measurement_noise})"
+            ),
+        }
+    }
+}
+
+/// A filter with its state. Only numbers are filtered, other values pass through.
+#[derive(Debug, Clone)]
+pub struct SlotFilter {
+    kind: FilterKind,
+    /// Latest values, for the window filters
+    values: VecDeque<f64>,
+    /// Latest output, for the exponential and Kalman filters
+    estimate: Option<f64>,
+    /// Variance of the estimate, for the Kalman filter
+    variance: f64,
+}
+
+impl SlotFilter {
+    pub fn new(kind: FilterKind) -> Self {
+        SlotFilter {
+            kind,
+            values: VecDeque::new(),
+            estimate: None,
+            variance: 0.0,
+        }
+    }
+
+    pub fn kind(&self) -> &FilterKind {
+        &self.kind
+    }
+
+    /// Add a value, returning the filtered one
+    pub fn apply(&mut self, value: f64) -> f64 {
+        match self.kind {
+            FilterKind::MovingAverage { window } | FilterKind::Median { window } => {
+                if self.values.len() == window {
+                    self.values.pop_front();
+                }
+                self.values.push_back(value);
+                if let FilterKind::Median { .. } = self.kind {
+                    let mut sorted: Vec<_> = self.values.iter().copied().collect();
+                    sorted.sort_by(f64::total_cmp);
+                    let mid = sorted.len() / 2;
+                    if sorted.len() % 2 == 0 {
+                        (sorted[mid - 1] + sorted[mid]) / 2.0
+                    } else {
+                        sorted[mid]
+                    }
+                } else {
+                    self.values.iter().sum::<f64>() / self.values.len() as f64
+                }
+            }
+            FilterKind::Exponential { alpha } => {
+                let estimate = self
+                    .estimate
+                    .map_or(value, |prev| alpha * value + (1.0 - alpha) * prev);
+                self.estimate = Some(estimate);
+                estimate
+            }
+            FilterKind::Kalman {
+                process_noise,
+                measurement_noise,
+            } => {
+                let estimate = match self.estimate {
+                    None => {
+                        self.variance = measurement_noise;
+                        value
+                    }
+                    Some(prev) => {
+                        let predicted_variance = self.variance + process_noise;
+                        let gain = predicted_variance / (predicted_variance + measurement_noise);
+                        self.variance = (1.0 - gain) * predicted_variance;
+                        prev + gain * (value - prev)
+                    }
+                };
+                self.estimate = Some(estimate);
+                estimate
+            }
+        }
+    }
+
+    pub fn filter(&mut self, value: SlotValue) -> SlotValue {
+        match value {
+            SlotValue::Number(n) if n.is_finite() => SlotValue::Number(self.apply(n)),
+            other => other,
+        }
+    }
+}
+
+impl IndexedShell {
+    /// The filter declared in the "Filter" collection of the data source referenced
+    /// as "<submodel ID>#<collection id_short>", with a "Type" property ("MovingAverage",
+    /// "Median", "Exponential" or "Kalman") and its parameters: "Window" for the window
+    /// filters, "Alpha" for the exponential one, "ProcessNoise" and "MeasurementNoise"
+    /// for the Kalman one. Returns None if the data source declares no filter.
+    pub fn sensor_filter(&self, full_ref: &str) -> Result<Option<FilterKind>, String> {
+        let Some(path) = self.collection_path(full_ref) else {
+            return Ok(None);
+        };
+        if self.element(&format!("{path}.Filter")).is_none() {
+            return Ok(None);
+        }
+        // Property paths are relative to the submodel
+        let (submodel, path) = path.split_once('.').unwrap_or((path, ""));
+        let property = |name: &str| {
+            self.get_property_f64(submodel, &format!("{path}.Filter.{name}"))
+                .map_err(|e| format!("invalid filter of {full_ref}: {e}"))
+        };
+        let window = || -> Result<usize, String> {
+            let window = property("Window")?;
+            if window < 1.0 || window.fract() != 0.0 {
+                return Err(format!(
+                    "invalid filter of {full_ref}: window must be a positive integer"
+                ));
+            }
+            Ok(window as usize)
+        };
+        let filter_type = self
+            .get_property_str(submodel, &format!("{path}.Filter.Type"))
+            .map_err(|e| format!("invalid filter of {full_ref}: {e}"))?;
+        let kind = match filter_type.as_str() {
+            "MovingAverage" => FilterKind::MovingAverage { window: window()? },
+            "Median" => FilterKind::Median { window: window()? },
+            "Exponential" => {
+                let alpha = property("Alpha")?;
+                if !(alpha > 0.0 && alpha <= 1.0) {
+                    return Err(format!("invalid filter of {full_ref}: alpha must be in (0, 1]"));
+                }
+                FilterKind::Exponential { alpha }
+            }
+            "Kalman" => {
+                let (process_noise, measurement_noise) =
+                    (property("ProcessNoise")?, property("MeasurementNoise")?);
+                if process_noise < 0.0 || measurement_noise <= 0.0 {
+                    return Err(format!("invalid filter of {full_ref}: noise must be positive"));
+                }
+                FilterKind::Kalman {
+                    process_noise,
+                    measurement_noise,
+                }
+            }
+            other => return Err(format!("invalid filter of {full_ref}: unknown type {other}")),
+        };
+        Ok(Some(kind))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aas::AssetAdministrationShell;
+
+    fn run(kind: FilterKind, values: &[f64]) -> Vec<f64> {
+        let mut filter = SlotFilter::new(kind);
+        values.iter().map(|&v| filter.apply(v)).collect()
+    }
+
+    #[test]
+    fn test_filters() {
+        assert_eq!(
+            run(FilterKind::MovingAverage { window: 2 }, &[1.0, 3.0, 5.0]),
+            [1.0, 2.0, 4.0]
+        );
+        // A single spike does not pass the median
+        assert_eq!(
+            run(FilterKind::Median { window: 3 }, &[10.0, 10.0, 99.0, 10.0]),
+            [10.0, 10.0, 10.0, 10.0]
+        );
+        assert_eq!(
+            run(FilterKind::Exponential { alpha: 0.5 }, &[0.0, 8.0, 8.0]),
+            [0.0, 4.0, 6.0]
+        );
+        let kalman = run(
+            FilterKind::Kalman {
+                process_noise: 0.01,
+                measurement_noise: 1.0,
+            },
+            &[10.0, 10.0, 30.0, 10.0],
+        );
+        assert!(kalman[2] > 10.0 && kalman[2] < 20.0, "{kalman:?}");
+
+        let mut filter = SlotFilter::new(FilterKind::Exponential { alpha: 0.5 });
+        assert_eq!(filter.filter(SlotValue::Bool(true)), SlotValue::Bool(true));
+        assert_eq!(filter.filter(SlotValue::Number(2.0)), SlotValue::Number(2.0));
+    }
+
+    #[test]
+    fn test_sensor_filter() {
+        let yaml = r#"
+id: "urn:aas:example"
+id_short: "ExampleAAS"
+submodels:
+  - id: "urn:aas:example:datasources"
+    id_short: "IoTDataSources"
+    elements:
+      - element_type: "collection"
+        id_short: "Sensors"
+        value:
+          - element_type: "collection"
+            id_short: "SensorCurrent"
+            value:
+              - element_type: "property"
+                id_short: "SensorID"
+                value_type: "string"
+                value: "urn:sensor:current"
+              - element_type: "collection"
+                id_short: "Filter"
+                value:
+                  - element_type: "property"
+                    id_short: "Type"
+                    value_type: "string"
+                    value: "Median"
+                  - element_type: "property"
+                    id_short: "Window"
+                    value_type: "int"
+                    value: 5
+          - element_type: "collection"
+            id_short: "SensorPower"
+            value:
+              - element_type: "property"
+                id_short: "SensorID"
+                value_type: "string"
+                value: "urn:sensor:power"
+              - element_type: "collection"
+                id_short: "Filter"
+                value:
+                  - element_type: "property"
+                    id_short: "Type"
+                    value_type: "string"
+                    value: "Exponential"
+                  - element_type: "property"
+                    id_short: "Alpha"
+                    value_type: "float"
+                    value: 1.5
+"#;
+        let aas = IndexedShell::new(AssetAdministrationShell::from_reader(yaml.as_bytes()).unwrap());
+        assert_eq!(
+            aas.sensor_filter("urn:aas:example:datasources#SensorCurrent"),
+            Ok(Some(FilterKind::Median { window: 5 }))
+        );
+        assert!(aas
+            .sensor_filter("urn:aas:example:datasources#SensorPower")
+            .unwrap_err()
+            .contains("alpha"));
+        assert_eq!(aas.sensor_filter("urn:aas:example:datasources#Missing"), Ok(None));
+        assert_eq!(aas.sensor_filter("urn:aas:other#SensorCurrent"), Ok(None));
+    }
+}
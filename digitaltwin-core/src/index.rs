@@ -0,0 +1,338 @@
+/// Indexed, read-only view of an Asset Administration Shell
+use std::collections::HashMap;
+use std::ops::Deref;
+
+use crate::aas::{AssetAdministrationShell, Submodel, SubmodelCollection, SubmodelElement, Value};
+
+/// Position of an element in the shell: the index of its submodel, followed by
+/// the indexes of the enclosing collections and of the element itself.
+#[derive(Debug, Clone)]
+struct Location {
+    submodel: usize,
+    positions: Vec<usize>,
+}
+
+/// An AAS together with an index of its elements, built once when the shell is
+/// loaded. Lookups borrow from the shell instead of walking (and cloning) the
+/// element tree on every call. The shell can't be modified through the index:
+/// use `into_inner` and index it again to change it.
+#[derive(Debug, Clone)]
+pub struct IndexedShell {
+    aas: AssetAdministrationShell,
+    /// Element locations by path (e.g., "PowerAndElectrical.CurrentPowerDraw.DataSource")
+    by_path: HashMap<String, Location>,
+    /// Element paths by id_short, in document order
+    by_id_short: HashMap<String, Vec<String>>,
+    /// Submodel positions by submodel ID
+    submodels: HashMap<String, usize>,
+    /// Sensor IDs by sensor reference (e.g., "urn:aas:example:datasources#SensorPower")
+    sensors: HashMap<String, Option<String>>,
+}
+
+impl IndexedShell {
+    pub fn new(aas: AssetAdministrationShell) -> Self {
+        let mut index = IndexedShell {
+            aas,
+            by_path: HashMap::new(),
+            by_id_short: HashMap::new(),
+            submodels: HashMap::new(),
+            sensors: HashMap::new(),
+        };
+        let aas = &index.aas;
+        let mut entries = Vec::new();
+        for (i, submodel) in aas.submodels.iter().enumerate() {
+            index.submodels.entry(submodel.id.clone()).or_insert(i);
+            collect_sensors(&submodel.id, &submodel.elements, &mut index.sensors);
+            collect_locations(
+                i,
+                &submodel.id_short,
+                &submodel.elements,
+                &mut Vec::new(),
+                &mut entries,
+            );
+        }
+        for (path, id_short, location) in entries {
+            if index.by_path.contains_key(&path) {
+                // Duplicate id_shorts: the first element wins, as in the tree walks
+                continue;
+            }
+            index.by_id_short.entry(id_short).or_default().push(path.clone());
+            index.by_path.insert(path, location);
+        }
+        index
+    }
+
+    /// Give back the shell, dropping the index
+    pub fn into_inner(self) -> AssetAdministrationShell {
+        self.aas
+    }
+
+    /// Number of indexed elements
+    pub fn len(&self) -> usize {
+        self.by_path.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_path.is_empty()
+    }
+
+    /// Find a submodel given its ID
+    pub fn submodel_by_id(&self, id: &str) -> Option<&Submodel> {
+        self.submodels.get(id).map(|&i| &self.aas.submodels[i])
+    }
+
+    /// Find an element given its full path, starting with the submodel id_short
+    /// (e.g., "PowerAndElectrical.CurrentPowerDraw.DataSource")
+    pub fn element(&self, path: &str) -> Option<&SubmodelElement> {
+        self.by_path.get(path).and_then(|location| self.locate(location))
+    }
+
+    /// All the elements with the given id_short, with their paths, in document order
+    pub fn find_by_id_short<'a>(
+        &'a self,
+        id_short: &str,
+    ) -> impl Iterator<Item = (&'a str, &'a SubmodelElement)> + 'a {
+        self.by_id_short
+            .get(id_short)
+            .into_iter()
+            .flatten()
+            .filter_map(|path| Some((path.as_str(), self.element(path)?)))
+    }
+
+    /// The first collection with the given id_short, at any depth
+    pub fn collection(&self, id_short: &str) -> Option<&SubmodelCollection> {
+        self.find_by_id_short(id_short).find_map(|(_, elem)| match elem {
+            SubmodelElement::Collection(c) => Some(c),
+            _ => None,
+        })
+    }
+
+    /// The value of the reference element at the given path
+    pub fn reference_value(&self, path: &str) -> Option<&str> {
+        match self.element(path)? {
+            SubmodelElement::ReferenceElement(r) => Some(&r.value),
+            _ => None,
+        }
+    }
+
+    /// Resolve a sensor reference of the form "<submodel ID>#<collection id_short>"
+    /// to the "SensorID" property of the referenced collection. Same as
+    /// `AssetAdministrationShell::resolve_sensor_reference`, without walking the tree.
+    pub fn sensor_id(&self, full_ref: &str) -> Option<&str> {
+        self.sensors.get(full_ref)?.as_deref()
+    }
+
+    /// Path of the collection referenced as "<submodel ID>#<collection id_short>"
+    /// (e.g., "IoTDataSources.Sensors.SensorPower")
+    pub fn collection_path(&self, full_ref: &str) -> Option<&str> {
+        let (submodel_id, collection) = full_ref.split_once('#')?;
+        let submodel = self.submodel_by_id(submodel_id)?;
+        self.find_by_id_short(collection)
+            .find(|(path, elem)| {
+                path.strip_prefix(submodel.id_short.as_str())
+                    .is_some_and(|rest| rest.starts_with('.'))
+                    && matches!(elem, SubmodelElement::Collection(_))
+            })
+            .map(|(path, _)| path)
+    }
+
+    /// The "MeasurementType" property of the data source referenced as
+    /// "<submodel ID>#<collection id_short>", used to bind discovered sensors
+    pub fn measurement_type(&self, full_ref: &str) -> Option<&str> {
+        match self.element(&format!("{}.MeasurementType", self.collection_path(full_ref)?))? {
+            SubmodelElement::Property(p) => match &p.value {
+                Value::Str(s) => Some(s),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn locate(&self, location: &Location) -> Option<&SubmodelElement> {
+        let (first, rest) = location.positions.split_first()?;
+        let mut elem = self.aas.submodels.get(location.submodel)?.elements.get(*first)?;
+        for &i in rest {
+            elem = match elem {
+                SubmodelElement::Collection(c) => c.value.get(i)?,
+                _ => return None,
+            };
+        }
+        Some(elem)
+    }
+}
+
+impl Deref for IndexedShell {
+    type Target = AssetAdministrationShell;
+
+    fn deref(&self) -> &Self::Target {
+        &self.aas
+    }
+}
+
+impl From<AssetAdministrationShell> for IndexedShell {
+    fn from(aas: AssetAdministrationShell) -> Self {
+        IndexedShell::new(aas)
+    }
+}
+
+/// Walk the element tree once, collecting (path, id_short, location) entries
+fn collect_locations(
+    submodel: usize,
+    path: &str,
+    elements: &[SubmodelElement],
+    positions: &mut Vec<usize>,
+    entries: &mut Vec<(String, String, Location)>,
+) {
+    for (i, elem) in elements.iter().enumerate() {
+        positions.push(i);
+        let elem_path = format!("{path}.{}", elem.id_short());
+        entries.push((
+            elem_path.clone(),
+            elem.id_short().to_string(),
+            Location {
+                submodel,
+                positions: positions.clone(),
+            },
+        ));
+        if let SubmodelElement::Collection(c) = elem {
+            collect_locations(submodel, &elem_path, &c.value, positions, entries);
+        }
+        positions.pop();
+    }
+}
+
+/// Map the references to every collection to the "SensorID" property the
+/// collection contains, if any. Only the first collection with a given id_short
+/// in each submodel can be referenced.
+fn collect_sensors(
+    submodel_id: &str,
+    elements: &[SubmodelElement],
+    sensors: &mut HashMap<String, Option<String>>,
+) {
+    for elem in elements {
+        if let SubmodelElement::Collection(c) = elem {
+            let sensor_id = c.value.iter().find_map(|e| match e {
+                SubmodelElement::Property(p) if p.id_short == "SensorID" => match &p.value {
+                    Value::Str(id) => Some(id.clone()),
+                    _ => None,
+                },
+                _ => None,
+            });
+            sensors
+                .entry(format!("{submodel_id}#{}", c.id_short))
+                .or_insert(sensor_id);
+            collect_sensors(submodel_id, &c.value, sensors);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_aas() -> AssetAdministrationShell {
+        serde_yaml::from_str(
+            r#"
+id: "urn:aas:example"
+id_short: "ExampleAAS"
+submodels:
+  - id: "urn:aas:example:power"
+    id_short: "PowerAndElectrical"
+    elements:
+      - element_type: "collection"
+        id_short: "CurrentPowerDraw"
+        value:
+          - element_type: "referenceelement"
+            id_short: "DataSource"
+            value: "urn:aas:example:datasources#SensorPower"
+      - element_type: "operation"
+        id_short: "SwitchOn"
+  - id: "urn:aas:example:datasources"
+    id_short: "IoTDataSources"
+    elements:
+      - element_type: "collection"
+        id_short: "Sensors"
+        value:
+          - element_type: "collection"
+            id_short: "SensorPower"
+            value:
+              - element_type: "property"
+                id_short: "SensorID"
+                value_type: "string"
+                value: "urn:iot-sensor:power123"
+              - element_type: "property"
+                id_short: "MeasurementType"
+                value_type: "string"
+                value: "PowerConsumption"
+          - element_type: "referenceelement"
+            id_short: "DataSource"
+            value: "urn:aas:example:other#Sensor"
+"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_index_lookups() {
+        let index = IndexedShell::new(load_aas());
+        assert_eq!(index.len(), 8);
+        assert_eq!(index.id_short, "ExampleAAS");
+        assert_eq!(
+            index
+                .submodel_by_id("urn:aas:example:datasources")
+                .map(|s| s.id_short.as_str()),
+            Some("IoTDataSources")
+        );
+        assert!(matches!(
+            index.element("IoTDataSources.Sensors.SensorPower.SensorID"),
+            Some(SubmodelElement::Property(p)) if p.id_short == "SensorID"
+        ));
+        assert!(index.element("IoTDataSources.SensorPower").is_none());
+        assert_eq!(index.collection("SensorPower").map(|c| c.value.len()), Some(2));
+        assert!(index.collection("SwitchOn").is_none());
+    }
+
+    #[test]
+    fn test_find_by_id_short() {
+        let index = IndexedShell::new(load_aas());
+        let paths: Vec<_> = index
+            .find_by_id_short("DataSource")
+            .map(|(path, _)| path)
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                "PowerAndElectrical.CurrentPowerDraw.DataSource",
+                "IoTDataSources.Sensors.DataSource"
+            ]
+        );
+        assert_eq!(index.find_by_id_short("Missing").count(), 0);
+    }
+
+    #[test]
+    fn test_sensor_references() {
+        let index = IndexedShell::new(load_aas());
+        let reference = index.reference_value("PowerAndElectrical.CurrentPowerDraw.DataSource");
+        assert_eq!(reference, Some("urn:aas:example:datasources#SensorPower"));
+        assert_eq!(
+            index.sensor_id(reference.unwrap()),
+            index.resolve_sensor_reference(reference.unwrap()).as_deref()
+        );
+        assert_eq!(
+            index.sensor_id(reference.unwrap()),
+            Some("urn:iot-sensor:power123")
+        );
+        assert_eq!(index.sensor_id("urn:aas:example:datasources#Sensors"), None);
+        assert_eq!(index.sensor_id("urn:aas:example:power#SensorPower"), None);
+        assert_eq!(index.reference_value("PowerAndElectrical.SwitchOn"), None);
+        assert_eq!(
+            index.collection_path(reference.unwrap()),
+            Some("IoTDataSources.Sensors.SensorPower")
+        );
+        assert_eq!(
+            index.measurement_type(reference.unwrap()),
+            Some("PowerConsumption")
+        );
+        assert_eq!(index.measurement_type("urn:aas:example:power#SensorPower"), None);
+    }
+}
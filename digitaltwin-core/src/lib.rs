@@ -1,7 +1,32 @@
 mod aas;
 mod actor_state;
+mod context;
+mod diff;
+mod filters;
+mod index;
+mod messages;
+mod operations;
+mod properties;
+mod regions;
 mod types;
+mod xml;
 
-pub use aas::AssetAdministrationShell;
+pub use aas::{
+    Actuation, AdministrativeInformation, AssetAdministrationShell, AssetKind, ConceptDescription, Event,
+    EventField, HttpActuation, Operation, Submodel, SubmodelElement, ValueType, MAX_COLLECTION_DEPTH,
+    OPERATIONAL_DATA,
+};
 pub use actor_state::*;
-pub use types::{AssetID, DeviceID};
+pub use context::{ActorContext, CachedValue, SlotCache};
+pub use diff::AasChange;
+pub use filters::{FilterKind, SlotFilter};
+pub use index::IndexedShell;
+pub use messages::{
+    ContentType, DecodeError, MqttCommand, MqttMessage, MqttUpdate, SensorAnnouncement, CURRENT_VERSION,
+};
+pub use operations::{
+    ArgumentValue, ExecutionState, OperationArgument, OperationMessage, OperationRequest, OperationResult,
+};
+pub use properties::PropertyError;
+pub use regions::RegionSet;
+pub use types::{AssetID, CorrelationID, DeviceID, FromSlotValue, GeoLocation, SlotValue};
@@ -0,0 +1,359 @@
+/// Messages received from the MQTT broker: sensor updates and commands
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::types::{AssetID, DeviceID, SlotValue};
+
+/// Version of the envelope written by the current clients
+pub const CURRENT_VERSION: u64 = 2;
+
+/// A message received on the updates topic, holding any number of updates and
+/// commands. Two envelope versions are understood:
+/// - v1 (no "v" field): `{"update": {..}, "command": {..}}`, both optional; gateways
+///   batching sensor readings can send `{"updates": [..]}` instead of "update"
+/// - v2: `{"v": 2, "timestamp": <ms since epoch>, "updates": [..], "commands": [..]}`
+///
+/// Both accept an optional "id", identifying the redeliveries of the same message, and an
+/// optional "correlation_id", relating the message to what it causes in the twins.
+#[derive(Debug, Clone)]
+pub struct MqttMessage {
+    /// envelope version
+    pub version: u64,
+    /// optional ID of the message, repeated in its redeliveries
+    pub id: Option<String>,
+    /// optional ID relating the message to the transitions and events it causes
+    pub correlation_id: Option<String>,
+    /// time the message was produced, in milliseconds since the Unix epoch (v2 only)
+    pub timestamp: Option<i64>,
+    /// data value updates
+    pub updates: Vec<MqttUpdate>,
+    /// commands to be executed
+    pub commands: Vec<MqttCommand>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MqttUpdate {
+    /// ID of the sensor/actuator
+    pub object: DeviceID,
+    /// update value (number, boolean or string)
+    pub value: SlotValue,
+    /// optional sequence number of the readings of the sensor/actuator, repeated in
+    /// the redeliveries of the same reading
+    #[serde(default)]
+    pub seq: Option<u64>,
+}
+
+/// A command; if command authentication is enabled, its "signature" field holds
+/// an HMAC or a JWT, checked against the command as received
+#[derive(Debug, Clone, Deserialize)]
+pub struct MqttCommand {
+    /// Asset ID of the target
+    pub target: AssetID,
+    /// command to be executed
+    pub command: String,
+    /// input value (any JSON object)
+    pub args: Value,
+    /// optional key identifying repeated deliveries of the same command
+    #[serde(default)]
+    pub idempotency_key: Option<String>,
+    /// optional identity of the issuer
+    #[serde(default)]
+    pub principal: Option<String>,
+    /// optional ID relating the command to the request that caused it
+    #[serde(default)]
+    pub correlation_id: Option<String>,
+    /// evaluate the command without executing it: the twin reports the transition it
+    /// would make on the acks topic
+    #[serde(default)]
+    pub dry_run: bool,
+    /// the command object as received, including its signature
+    #[serde(skip)]
+    pub raw: Value,
+}
+
+/// A sensor announcing itself after startup, on the discovery topic. Twins bind it to
+/// a slot whose data source has the same measurement type and no sensor yet.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SensorAnnouncement {
+    /// ID of the sensor
+    pub sensor_id: DeviceID,
+    /// what the sensor measures, as the "MeasurementType" of the AAS data sources
+    pub measurement_type: String,
+    /// optional Asset ID of the twin the sensor belongs to; any twin otherwise
+    #[serde(default)]
+    pub twin: Option<AssetID>,
+}
+
+impl SensorAnnouncement {
+    /// Decode a JSON announcement from an MQTT payload
+    pub fn decode(payload: &[u8]) -> Result<Self, DecodeError> {
+        serde_json::from_slice(payload).map_err(|e| DecodeError::Malformed(ContentType::Json, e.to_string()))
+    }
+}
+
+/// Encoding of the messages published on a topic
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ContentType {
+    #[default]
+    Json,
+    Yaml,
+}
+
+impl ContentType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentType::Json => "application/json",
+            ContentType::Yaml => "application/yaml",
+        }
+    }
+}
+
+impl std::str::FromStr for ContentType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "application/json" | "json" => Ok(ContentType::Json),
+            "application/yaml" | "yaml" => Ok(ContentType::Yaml),
+            _ => Err(format!("Unsupported content type: {s}")),
+        }
+    }
+}
+
+/// Error returned when a message can't be decoded
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    /// The payload is not valid in the content type of the topic
+    Malformed(ContentType, String),
+    /// The envelope declares a version this runtime does not know
+    UnsupportedVersion(Value),
+    /// The envelope does not match the declared version
+    InvalidEnvelope(u64, String),
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::Malformed(content_type, e) => {
+                write!(f, "payload is not valid {}: {e}", content_type.as_str())
+            }
+            DecodeError::UnsupportedVersion(v) => {
+                write!(f, "unsupported message version {v} (latest is {CURRENT_VERSION})")
+            }
+            DecodeError::InvalidEnvelope(v, e) => write!(f, "invalid v{v} message: {e}"),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct EnvelopeV1 {
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    correlation_id: Option<String>,
+    update: Option<MqttUpdate>,
+    #[serde(default)]
+    updates: Vec<MqttUpdate>,
+    command: Option<Value>,
+}
+
+#[derive(Deserialize)]
+struct EnvelopeV2 {
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    correlation_id: Option<String>,
+    #[serde(default)]
+    timestamp: Option<i64>,
+    #[serde(default)]
+    updates: Vec<MqttUpdate>,
+    #[serde(default)]
+    commands: Vec<Value>,
+}
+
+impl MqttMessage {
+    /// Decode a JSON message from an MQTT payload. The payload comes from the network:
+    /// this must never panic, whatever the input (see the fuzz targets).
+    pub fn decode(payload: &[u8]) -> Result<Self, DecodeError> {
+        Self::decode_as(payload, ContentType::Json)
+    }
+
+    /// Decode a message encoded with the given content type, of any known version
+    pub fn decode_as(payload: &[u8], content_type: ContentType) -> Result<Self, DecodeError> {
+        let malformed = |e: String| DecodeError::Malformed(content_type, e);
+        let mut envelope: Value = match content_type {
+            ContentType::Json => serde_json::from_slice(payload).map_err(|e| malformed(e.to_string()))?,
+            ContentType::Yaml => serde_yaml::from_slice(payload).map_err(|e| malformed(e.to_string()))?,
+        };
+        let version = match envelope.as_object_mut().map(|obj| obj.remove("v")) {
+            None => return Err(malformed("not an object".to_string())),
+            Some(None) if envelope.get("commands").is_some() => {
+                return Err(DecodeError::InvalidEnvelope(
+                    1,
+                    "command batches require \"v\": 2".to_string(),
+                ));
+            }
+            Some(None) => 1,
+            Some(Some(v)) => match v.as_u64() {
+                Some(version @ 2..=CURRENT_VERSION) => version,
+                _ => return Err(DecodeError::UnsupportedVersion(v)),
+            },
+        };
+        let invalid = |e: serde_json::Error| DecodeError::InvalidEnvelope(version, e.to_string());
+        let (id, correlation_id, timestamp, updates, commands) = if version == 1 {
+            let v1: EnvelopeV1 = serde_json::from_value(envelope).map_err(invalid)?;
+            (
+                v1.id,
+                v1.correlation_id,
+                None,
+                v1.update.into_iter().chain(v1.updates).collect(),
+                v1.command.into_iter().collect(),
+            )
+        } else {
+            let v2: EnvelopeV2 = serde_json::from_value(envelope).map_err(invalid)?;
+            (v2.id, v2.correlation_id, v2.timestamp, v2.updates, v2.commands)
+        };
+        let commands = commands
+            .into_iter()
+            .map(|raw| {
+                let mut command: MqttCommand = serde_json::from_value(raw.clone())?;
+                command.raw = raw;
+                Ok(command)
+            })
+            .collect::<Result<_, _>>()
+            .map_err(invalid)?;
+        Ok(MqttMessage {
+            version,
+            id,
+            correlation_id,
+            timestamp,
+            updates,
+            commands,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let message =
+            MqttMessage::decode(br#"{"update": {"object": "urn:sensor:1", "value": 2.5}}"#).unwrap();
+        assert_eq!(message.version, 1);
+        assert_eq!(message.updates[0].value, SlotValue::Number(2.5));
+        assert_eq!(message.updates[0].seq, None);
+        assert!(message.commands.is_empty());
+
+        let message = MqttMessage::decode(
+            br#"{"id": "gw-1/815", "update": {"object": "urn:sensor:1", "value": 2.5, "seq": 42}}"#,
+        )
+        .unwrap();
+        assert_eq!(message.id.as_deref(), Some("gw-1/815"));
+        assert_eq!(message.updates[0].seq, Some(42));
+
+        let message =
+            MqttMessage::decode(br#"{"command": {"target": "urn:twin:1", "command": "Reset", "args": {}}}"#)
+                .unwrap();
+        assert_eq!(message.commands[0].command, "Reset");
+        assert_eq!(message.commands[0].raw["target"], "urn:twin:1");
+
+        assert!(MqttMessage::decode(b"\xff").is_err());
+        assert!(MqttMessage::decode(br#"{"update": {"object": "urn:sensor:1", "value": [1]}}"#).is_err());
+        // Deeply nested args hit the recursion limit of the decoder instead of the stack
+        let nested = format!(
+            r#"{{"command": {{"target": "t", "command": "c", "args": {}1{}}}}}"#,
+            "[".repeat(100_000),
+            "]".repeat(100_000)
+        );
+        assert!(MqttMessage::decode(nested.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_decode_value_types() {
+        let message = MqttMessage::decode(
+            br#"{"updates": [{"object": "urn:door:1", "value": "open"}, {"object": "urn:relay:1", "value": false},
+                             {"object": "urn:meter:1", "value": 230}]}"#,
+        )
+        .unwrap();
+        let values: Vec<_> = message.updates.into_iter().map(|u| u.value).collect();
+        assert_eq!(
+            values,
+            [
+                SlotValue::from("open"),
+                SlotValue::Bool(false),
+                SlotValue::Number(230.0)
+            ]
+        );
+        assert!(MqttMessage::decode(br#"{"update": {"object": "urn:door:1", "value": null}}"#).is_err());
+    }
+
+    #[test]
+    fn test_decode_bulk_updates() {
+        let message = MqttMessage::decode(
+            br#"{"updates": [{"object": "urn:sensor:1", "value": 1}, {"object": "urn:sensor:2", "value": "on"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(message.version, 1);
+        let objects: Vec<_> = message.updates.iter().map(|u| u.object.as_str()).collect();
+        assert_eq!(objects, ["urn:sensor:1", "urn:sensor:2"]);
+        // A single invalid reading rejects the whole batch
+        assert!(MqttMessage::decode(
+            br#"{"updates": [{"object": "urn:sensor:1", "value": 1}, {"value": 2}]}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_decode_announcement() {
+        let announcement = SensorAnnouncement::decode(
+            br#"{"sensor_id": "urn:iot-sensor:current456", "measurement_type": "InputCurrent"}"#,
+        )
+        .unwrap();
+        assert_eq!(announcement.sensor_id, "urn:iot-sensor:current456");
+        assert_eq!(announcement.twin, None);
+        assert!(SensorAnnouncement::decode(br#"{"sensor_id": "urn:iot-sensor:current456"}"#).is_err());
+    }
+
+    #[test]
+    fn test_decode_versions() {
+        let message = MqttMessage::decode(
+            br#"{"v": 2, "timestamp": 1700000000000, "correlation_id": "reading-7",
+                 "updates": [{"object": "urn:sensor:1", "value": 1}, {"object": "urn:sensor:2", "value": true}],
+                 "commands": [{"target": "urn:twin:1", "command": "Reset", "args": {}, "dry_run": true}]}"#,
+        )
+        .unwrap();
+        assert_eq!(message.version, 2);
+        assert_eq!(message.id, None);
+        assert_eq!(message.correlation_id.as_deref(), Some("reading-7"));
+        assert_eq!(message.timestamp, Some(1700000000000));
+        assert_eq!(message.updates.len(), 2);
+        assert_eq!(message.commands.len(), 1);
+        assert!(message.commands[0].dry_run);
+
+        let yaml = b"v: 2\nupdates:\n  - object: urn:sensor:1\n    value: 3.5\n";
+        let message = MqttMessage::decode_as(yaml, ContentType::Yaml).unwrap();
+        assert_eq!(message.updates[0].value, SlotValue::Number(3.5));
+        assert!(matches!(
+            MqttMessage::decode(yaml),
+            Err(DecodeError::Malformed(ContentType::Json, _))
+        ));
+
+        assert_eq!(
+            MqttMessage::decode(br#"{"v": 3, "updates": []}"#)
+                .unwrap_err()
+                .to_string(),
+            "unsupported message version 3 (latest is 2)"
+        );
+        assert!(matches!(
+            MqttMessage::decode(br#"{"v": "2"}"#),
+            Err(DecodeError::UnsupportedVersion(_))
+        ));
+        // v2 fields in a v1 message are a mistake, not an empty message
+        assert!(matches!(
+            MqttMessage::decode(br#"{"commands": []}"#),
+            Err(DecodeError::InvalidEnvelope(1, _))
+        ));
+    }
+}
@@ -0,0 +1,270 @@
+/// Invocation of AAS Operations, following the request and result formats of the
+/// AAS HTTP/REST API ("inputArguments", "outputArguments", "executionState"...)
+use serde::{Deserialize, Serialize};
+
+use crate::aas::{Operation, OperationVariable, ValueType};
+use crate::xml::write_value_type;
+
+/// Body of an invoke request
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OperationRequest {
+    #[serde(default)]
+    pub input_arguments: Vec<OperationArgument>,
+}
+
+/// An input or output argument, wrapping a property
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OperationArgument {
+    pub value: ArgumentValue,
+}
+
+/// The property holding the value of an argument. Values are sent as strings by
+/// standard clients, but plain JSON values are accepted too.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArgumentValue {
+    #[serde(default = "property_model_type")]
+    pub model_type: String,
+    pub id_short: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value_type: Option<String>,
+    #[serde(default)]
+    pub value: serde_json::Value,
+}
+
+fn property_model_type() -> String {
+    "Property".to_string()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ExecutionState {
+    Completed,
+    Failed,
+}
+
+/// A message attached to an operation result
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OperationMessage {
+    /// "Info", "Warning" or "Error"
+    pub message_type: String,
+    pub text: String,
+}
+
+/// Result of an invoke request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OperationResult {
+    pub execution_state: ExecutionState,
+    pub success: bool,
+    pub output_arguments: Vec<OperationArgument>,
+    pub messages: Vec<OperationMessage>,
+}
+
+impl OperationResult {
+    pub fn completed(output_arguments: Vec<OperationArgument>) -> Self {
+        OperationResult {
+            execution_state: ExecutionState::Completed,
+            success: true,
+            output_arguments,
+            messages: Vec::new(),
+        }
+    }
+
+    pub fn failed(message: impl Into<String>) -> Self {
+        OperationResult {
+            execution_state: ExecutionState::Failed,
+            success: false,
+            output_arguments: Vec::new(),
+            messages: vec![OperationMessage {
+                message_type: "Error".to_string(),
+                text: message.into(),
+            }],
+        }
+    }
+
+    /// Attach an informational message
+    pub fn with_info(mut self, text: impl Into<String>) -> Self {
+        self.messages.push(OperationMessage {
+            message_type: "Info".to_string(),
+            text: text.into(),
+        });
+        self
+    }
+}
+
+impl Operation {
+    /// Translate the input arguments of a request into the actor's command args: an
+    /// object with a field for each input variable, converted to its declared type.
+    /// Missing arguments take the value declared in the AAS, if any.
+    pub fn command_args(&self, request: &OperationRequest) -> Result<serde_json::Value, String> {
+        let mut args = serde_json::Map::new();
+        for variable in &self.input_variables {
+            let argument = request
+                .input_arguments
+                .iter()
+                .find(|a| a.value.id_short == variable.name);
+            let value = match argument {
+                Some(argument) => {
+                    convert_argument(&argument.value.value, &variable.value_type).ok_or_else(|| {
+                        format!(
+                            "argument {} is not {:?}: {}",
+                            variable.name, variable.value_type, argument.value.value
+                        )
+                    })?
+                }
+                None => match serde_json::to_value(&variable.value).unwrap_or_default() {
+                    serde_json::Value::Null => return Err(format!("missing argument {}", variable.name)),
+                    default => default,
+                },
+            };
+            args.insert(variable.name.clone(), value);
+        }
+        for argument in &request.input_arguments {
+            if !args.contains_key(&argument.value.id_short) {
+                return Err(format!("unknown argument {}", argument.value.id_short));
+            }
+        }
+        Ok(serde_json::Value::Object(args))
+    }
+
+    /// Build the output arguments from the result of a command: each output variable
+    /// takes the field of the result with the same name, or its declared value.
+    pub fn output_arguments(&self, result: &serde_json::Value) -> Vec<OperationArgument> {
+        self.output_variables
+            .iter()
+            .map(|variable| {
+                let value = match result.get(&variable.name) {
+                    Some(value) => value.clone(),
+                    None => serde_json::to_value(&variable.value).unwrap_or_default(),
+                };
+                output_argument(variable, value)
+            })
+            .collect()
+    }
+}
+
+/// Convert an argument to the declared type, parsing strings
+fn convert_argument(value: &serde_json::Value, value_type: &ValueType) -> Option<serde_json::Value> {
+    use serde_json::Value as Json;
+    match (value_type, value) {
+        (ValueType::Json, Json::String(s)) => serde_json::from_str(s).ok().or(Some(value.clone())),
+        (ValueType::Json, _) => Some(value.clone()),
+        (ValueType::String, Json::String(_)) => Some(value.clone()),
+        (ValueType::String, Json::Number(_) | Json::Bool(_)) => Some(Json::String(value.to_string())),
+        (ValueType::Int, Json::Number(n)) if n.is_i64() => Some(value.clone()),
+        (ValueType::Int, Json::String(s)) => s.trim().parse::<i64>().ok().map(Json::from),
+        (ValueType::Float, Json::Number(_)) => Some(value.clone()),
+        (ValueType::Float, Json::String(s)) => s.trim().parse::<f64>().ok().map(Json::from),
+        (ValueType::Bool, Json::Bool(_)) => Some(value.clone()),
+        (ValueType::Bool, Json::String(s)) => match s.trim() {
+            "true" | "1" => Some(Json::Bool(true)),
+            "false" | "0" => Some(Json::Bool(false)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Wrap a value in an output argument, formatted as a string as in the AAS API
+fn output_argument(variable: &OperationVariable, value: serde_json::Value) -> OperationArgument {
+    let value = match value {
+        serde_json::Value::Null => serde_json::Value::Null,
+        serde_json::Value::String(s) => serde_json::Value::String(s),
+        other => serde_json::Value::String(other.to_string()),
+    };
+    OperationArgument {
+        value: ArgumentValue {
+            model_type: property_model_type(),
+            id_short: variable.name.clone(),
+            value_type: Some(write_value_type(&variable.value_type).to_string()),
+            value,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn operation() -> Operation {
+        serde_yaml::from_str(
+            r#"
+id_short: "SetChargingCurrent"
+input_variables:
+  - name: "desired_current"
+    value_type: "float"
+    value: null
+  - name: "phases"
+    value_type: "int"
+    value: 3
+output_variables:
+  - name: "state"
+    value_type: "string"
+    value: null
+  - name: "max_current"
+    value_type: "float"
+    value: 16.0
+"#,
+        )
+        .unwrap()
+    }
+
+    fn request(json: serde_json::Value) -> OperationRequest {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn test_command_args() {
+        let op = operation();
+        let args = op.command_args(&request(serde_json::json!({
+            "inputArguments": [
+                {"value": {"modelType": "Property", "idShort": "desired_current", "valueType": "xs:double", "value": "6.5"}}
+            ]
+        })));
+        assert_eq!(args, Ok(serde_json::json!({"desired_current": 6.5, "phases": 3})));
+
+        let args = op.command_args(&request(serde_json::json!({
+            "inputArguments": [
+                {"value": {"idShort": "desired_current", "value": 10}},
+                {"value": {"idShort": "phases", "value": "1"}}
+            ]
+        })));
+        assert_eq!(args, Ok(serde_json::json!({"desired_current": 10, "phases": 1})));
+
+        assert!(op.command_args(&OperationRequest::default()).is_err());
+        assert!(op
+            .command_args(&request(serde_json::json!({
+                "inputArguments": [{"value": {"idShort": "desired_current", "value": "fast"}}]
+            })))
+            .is_err());
+        assert!(op
+            .command_args(&request(serde_json::json!({
+                "inputArguments": [
+                    {"value": {"idShort": "desired_current", "value": 6}},
+                    {"value": {"idShort": "voltage", "value": 230}}
+                ]
+            })))
+            .is_err());
+    }
+
+    #[test]
+    fn test_output_arguments() {
+        let op = operation();
+        let outputs = op.output_arguments(&serde_json::json!({"state": "Charging"}));
+        let result = serde_json::to_value(OperationResult::completed(outputs)).unwrap();
+        assert_eq!(
+            result,
+            serde_json::json!({
+                "executionState": "Completed",
+                "success": true,
+                "outputArguments": [
+                    {"value": {"modelType": "Property", "idShort": "state", "valueType": "xs:string", "value": "Charging"}},
+                    {"value": {"modelType": "Property", "idShort": "max_current", "valueType": "xs:double", "value": "16.0"}}
+                ],
+                "messages": []
+            })
+        );
+    }
+}
@@ -0,0 +1,266 @@
+/// Typed access to the property values of an Asset Administration Shell
+use crate::aas::{AssetAdministrationShell, Property, SubmodelElement, Value, ValueType};
+
+/// Error returned by the typed property accessors
+#[derive(Debug, Clone, PartialEq)]
+pub enum PropertyError {
+    /// No element at the given path
+    NotFound(String),
+    /// The element at the given path is not a property
+    NotAProperty(String),
+    /// The property has no value
+    NoValue(String),
+    /// The declared type of the property can't be converted to the requested one
+    TypeMismatch {
+        path: String,
+        expected: &'static str,
+        found: ValueType,
+    },
+    /// The value does not match the declared type of the property
+    InvalidValue { path: String, value: String },
+}
+
+impl std::fmt::Display for PropertyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PropertyError::NotFound(path) => write!(f, "property {path} not found"),
+            PropertyError::NotAProperty(path) => write!(f, "element {path} is not a property"),
+            PropertyError::NoValue(path) => write!(f, "property {path} has no value"),
+            PropertyError::TypeMismatch {
+                path,
+                expected,
+                found,
+            } => {
+                write!(f, "property {path} is {found:?}, not {expected}")
+            }
+            PropertyError::InvalidValue { path, value } => {
+                write!(f, "property {path} has an invalid value: {value}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PropertyError {}
+
+impl AssetAdministrationShell {
+    /// Find an element given the id_short of its submodel and its path, made of
+    /// the id_shorts of the enclosing collections and of the element separated
+    /// by dots (e.g., "Sensors.SensorPowerAbsorption.SensorID").
+    pub fn find_element(&self, submodel_id_short: &str, path: &str) -> Option<&SubmodelElement> {
+        let mut elements = &self
+            .submodels
+            .iter()
+            .find(|s| s.id_short == submodel_id_short)?
+            .elements;
+        let mut ids = path.split('.').peekable();
+        while let Some(id) = ids.next() {
+            let element = elements.iter().find(|e| e.id_short() == id)?;
+            if ids.peek().is_none() {
+                return Some(element);
+            }
+            match element {
+                SubmodelElement::Collection(c) => elements = &c.value,
+                _ => return None,
+            }
+        }
+        None
+    }
+
+    fn find_property(&self, submodel_id_short: &str, path: &str) -> Result<&Property, PropertyError> {
+        let full_path = || format!("{submodel_id_short}.{path}");
+        match self.find_element(submodel_id_short, path) {
+            Some(SubmodelElement::Property(p)) => match p.value {
+                Value::Null | Value::Obj(serde_json::Value::Null) => Err(PropertyError::NoValue(full_path())),
+                _ => Ok(p),
+            },
+            Some(_) => Err(PropertyError::NotAProperty(full_path())),
+            None => Err(PropertyError::NotFound(full_path())),
+        }
+    }
+
+    /// Get the value of a numeric property. Integers are converted, and strings
+    /// are parsed if the property is declared as numeric.
+    pub fn get_property_f64(&self, submodel_id_short: &str, path: &str) -> Result<f64, PropertyError> {
+        let p = self.find_property(submodel_id_short, path)?;
+        let invalid = |value: String| PropertyError::InvalidValue {
+            path: format!("{submodel_id_short}.{path}"),
+            value,
+        };
+        match (&p.value_type, &p.value) {
+            (ValueType::Int | ValueType::Float, Value::Flt(f)) => Ok(*f),
+            (ValueType::Int | ValueType::Float, Value::Int(i)) => Ok(*i as f64),
+            (ValueType::Int | ValueType::Float, Value::Str(s)) => {
+                s.trim().parse().map_err(|_| invalid(s.clone()))
+            }
+            (ValueType::Int | ValueType::Float, value) => Err(invalid(format!("{value:?}"))),
+            (found, _) => Err(PropertyError::TypeMismatch {
+                path: format!("{submodel_id_short}.{path}"),
+                expected: "a number",
+                found: found.clone(),
+            }),
+        }
+    }
+
+    /// Get the value of an integer property. Strings are parsed if the property
+    /// is declared as integer; floats are never truncated.
+    pub fn get_property_i64(&self, submodel_id_short: &str, path: &str) -> Result<i64, PropertyError> {
+        let p = self.find_property(submodel_id_short, path)?;
+        let invalid = |value: String| PropertyError::InvalidValue {
+            path: format!("{submodel_id_short}.{path}"),
+            value,
+        };
+        match (&p.value_type, &p.value) {
+            (ValueType::Int, Value::Int(i)) => Ok(*i),
+            (ValueType::Int, Value::Str(s)) => s.trim().parse().map_err(|_| invalid(s.clone())),
+            (ValueType::Int, value) => Err(invalid(format!("{value:?}"))),
+            (found, _) => Err(PropertyError::TypeMismatch {
+                path: format!("{submodel_id_short}.{path}"),
+                expected: "an integer",
+                found: found.clone(),
+            }),
+        }
+    }
+
+    /// Get the value of a string property. Scalar values of properties declared
+    /// as strings (e.g., unquoted numbers in YAML) are converted to text.
+    pub fn get_property_str(&self, submodel_id_short: &str, path: &str) -> Result<String, PropertyError> {
+        let p = self.find_property(submodel_id_short, path)?;
+        match (&p.value_type, &p.value) {
+            (ValueType::String, Value::Str(s)) => Ok(s.clone()),
+            (ValueType::String, Value::Int(i)) => Ok(i.to_string()),
+            (ValueType::String, Value::Flt(f)) => Ok(f.to_string()),
+            (ValueType::String, Value::Bool(b)) => Ok(b.to_string()),
+            (ValueType::String, value) => Err(PropertyError::InvalidValue {
+                path: format!("{submodel_id_short}.{path}"),
+                value: format!("{value:?}"),
+            }),
+            (found, _) => Err(PropertyError::TypeMismatch {
+                path: format!("{submodel_id_short}.{path}"),
+                expected: "a string",
+                found: found.clone(),
+            }),
+        }
+    }
+
+    /// Get the value of a boolean property. "true"/"false" strings and 0/1
+    /// integers are accepted if the property is declared as boolean.
+    pub fn get_property_bool(&self, submodel_id_short: &str, path: &str) -> Result<bool, PropertyError> {
+        let p = self.find_property(submodel_id_short, path)?;
+        let invalid = |value: String| PropertyError::InvalidValue {
+            path: format!("{submodel_id_short}.{path}"),
+            value,
+        };
+        match (&p.value_type, &p.value) {
+            (ValueType::Bool, Value::Bool(b)) => Ok(*b),
+            (ValueType::Bool, Value::Int(0)) => Ok(false),
+            (ValueType::Bool, Value::Int(1)) => Ok(true),
+            (ValueType::Bool, Value::Str(s)) => match s.trim() {
+                "true" => Ok(true),
+                "false" => Ok(false),
+                _ => Err(invalid(s.clone())),
+            },
+            (ValueType::Bool, value) => Err(invalid(format!("{value:?}"))),
+            (found, _) => Err(PropertyError::TypeMismatch {
+                path: format!("{submodel_id_short}.{path}"),
+                expected: "a boolean",
+                found: found.clone(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_aas() -> AssetAdministrationShell {
+        serde_yaml::from_str(
+            r#"
+id: "urn:aas:example"
+id_short: "ExampleAAS"
+submodels:
+  - id: "urn:aas:example:config"
+    id_short: "Config"
+    elements:
+      - element_type: "property"
+        id_short: "MaxPower"
+        value_type: "float"
+        value: 3000
+      - element_type: "property"
+        id_short: "Phases"
+        value_type: "int"
+        value: "3"
+      - element_type: "property"
+        id_short: "Model"
+        value_type: "string"
+        value: 42
+      - element_type: "property"
+        id_short: "Enabled"
+        value_type: "bool"
+        value: "true"
+      - element_type: "property"
+        id_short: "Broken"
+        value_type: "float"
+        value: "fast"
+      - element_type: "property"
+        id_short: "Empty"
+        value_type: "string"
+        value: null
+      - element_type: "collection"
+        id_short: "Limits"
+        value:
+          - element_type: "property"
+            id_short: "MaxCurrent"
+            value_type: "float"
+            value: 16.5
+"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_typed_accessors() {
+        let aas = load_aas();
+        assert_eq!(aas.get_property_f64("Config", "MaxPower"), Ok(3000.0));
+        assert_eq!(aas.get_property_f64("Config", "Phases"), Ok(3.0));
+        assert_eq!(aas.get_property_f64("Config", "Limits.MaxCurrent"), Ok(16.5));
+        assert_eq!(aas.get_property_i64("Config", "Phases"), Ok(3));
+        assert_eq!(aas.get_property_str("Config", "Model"), Ok("42".to_string()));
+        assert_eq!(aas.get_property_bool("Config", "Enabled"), Ok(true));
+    }
+
+    #[test]
+    fn test_typed_accessor_errors() {
+        let aas = load_aas();
+        assert_eq!(
+            aas.get_property_f64("Config", "Missing"),
+            Err(PropertyError::NotFound("Config.Missing".to_string()))
+        );
+        assert_eq!(
+            aas.get_property_f64("Other", "MaxPower"),
+            Err(PropertyError::NotFound("Other.MaxPower".to_string()))
+        );
+        assert_eq!(
+            aas.get_property_f64("Config", "Limits"),
+            Err(PropertyError::NotAProperty("Config.Limits".to_string()))
+        );
+        assert_eq!(
+            aas.get_property_str("Config", "Empty"),
+            Err(PropertyError::NoValue("Config.Empty".to_string()))
+        );
+        assert_eq!(
+            aas.get_property_f64("Config", "Model"),
+            Err(PropertyError::TypeMismatch {
+                path: "Config.Model".to_string(),
+                expected: "a number",
+                found: ValueType::String
+            })
+        );
+        assert!(matches!(
+            aas.get_property_f64("Config", "Broken"),
+            Err(PropertyError::InvalidValue { .. })
+        ));
+        // Floats are not truncated to integers
+        assert!(aas.get_property_i64("Config", "MaxPower").is_err());
+    }
+}
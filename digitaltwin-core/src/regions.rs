@@ -0,0 +1,154 @@
+use std::collections::BTreeSet;
+
+use crate::{
+    ActorContext, ActorEvent, ActorState, ActorStateType, SlotValue, StateMachine, TransitionLimits,
+};
+
+/// An actor composed of several orthogonal regions. Each region is an independent
+/// state machine with its own states and dispatch maps; all regions receive every
+/// input change and command, and ignore the ones they don't handle.
+pub struct RegionSet {
+    /// Name of the composite actor type
+    type_name: &'static str,
+    /// Named regions, in declaration order
+    regions: Vec<(&'static str, Box<ActorStateType>)>,
+}
+
+impl RegionSet {
+    pub fn new(type_name: &'static str, regions: Vec<(&'static str, Box<ActorStateType>)>) -> Self {
+        RegionSet { type_name, regions }
+    }
+
+    /// Get the current state machine of a region
+    pub fn region(&self, name: &str) -> Option<&ActorStateType> {
+        self.regions
+            .iter()
+            .find(|(region, _)| *region == name)
+            .map(|(_, actor)| actor.as_ref())
+    }
+
+    /// Apply a transition function to every region
+    fn map_regions(&self, f: impl Fn(&ActorStateType) -> Box<ActorStateType>) -> Box<ActorStateType> {
+        Box::new(RegionSet {
+            type_name: self.type_name,
+            regions: self
+                .regions
+                .iter()
+                .map(|(name, actor)| (*name, f(actor.as_ref())))
+                .collect(),
+        })
+    }
+}
+
+impl ActorState for RegionSet {
+    fn input_value(&self, slot: &str, value: SlotValue) -> Box<ActorStateType> {
+        self.map_regions(|actor| actor.input_value(slot, value.clone()))
+    }
+
+    fn execute(&self, command: &str, input: serde_json::Value) -> Box<ActorStateType> {
+        self.map_regions(|actor| actor.execute(command, input.clone()))
+    }
+
+    /// The events of all regions, without duplicates
+    fn events(&self) -> Vec<String> {
+        let mut events = Vec::new();
+        for event in self.regions.iter().flat_map(|(_, actor)| actor.events()) {
+            if !events.contains(&event) {
+                events.push(event);
+            }
+        }
+        events
+    }
+
+    /// The commands handled by any region in its current state
+    fn commands(&self) -> Vec<String> {
+        let commands: BTreeSet<String> = self
+            .regions
+            .iter()
+            .flat_map(|(_, actor)| actor.commands())
+            .collect();
+        commands.into_iter().collect()
+    }
+
+    /// The input slots handled by any region in its current state
+    fn inputs(&self) -> Vec<String> {
+        let inputs: BTreeSet<String> = self
+            .regions
+            .iter()
+            .flat_map(|(_, actor)| actor.inputs())
+            .collect();
+        inputs.into_iter().collect()
+    }
+
+    fn take_events(&mut self) -> Vec<ActorEvent> {
+        self.regions
+            .iter_mut()
+            .flat_map(|(_, actor)| actor.take_events())
+            .collect()
+    }
+
+    /// The strictest limits of the regions, as a transition of any region changes the
+    /// combined state. The combined state has no safe state.
+    fn transition_limits(&self) -> TransitionLimits {
+        let limits: Vec<TransitionLimits> = self
+            .regions
+            .iter()
+            .map(|(_, actor)| actor.transition_limits())
+            .collect();
+        TransitionLimits {
+            max_per_minute: limits.iter().filter_map(|l| l.max_per_minute).min(),
+            min_dwell_ms: limits.iter().filter_map(|l| l.min_dwell_ms).max(),
+            safe_state: None,
+        }
+    }
+
+    /// All the regions share the context of the twin
+    fn set_context(&mut self, context: ActorContext) {
+        for (_, actor) in &mut self.regions {
+            actor.set_context(context.clone());
+        }
+    }
+
+    /// The state machines of the regions, in declaration order
+    fn state_machines(&self) -> Vec<StateMachine> {
+        self.regions
+            .iter()
+            .flat_map(|(name, actor)| {
+                actor.state_machines().into_iter().map(|machine| StateMachine {
+                    region: Some(name.to_string()),
+                    ..machine
+                })
+            })
+            .collect()
+    }
+
+    fn to_snapshot(&self) -> Result<serde_json::Value, String> {
+        let regions = self
+            .regions
+            .iter()
+            .map(|(name, actor)| Ok((name.to_string(), actor.to_snapshot()?)))
+            .collect::<Result<serde_json::Map<_, _>, String>>()?;
+        Ok(serde_json::json!({
+            "actor": self.type_name,
+            "state": self.state(),
+            "regions": regions,
+        }))
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn type_name(&self) -> String {
+        self.type_name.to_string()
+    }
+
+    /// The combined state, e.g. "Charging|Online"
+    fn state(&self) -> String {
+        self.regions
+            .iter()
+            .map(|(_, actor)| actor.state())
+            .collect::<Vec<_>>()
+            .join("|")
+    }
+}
@@ -1,6 +1,349 @@
-/// The unique identifier type for an IoT Sensor or Actuator
-/// (_not_ related to the Digital Twin ID in the AAS)
-pub type DeviceID = String;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::borrow::Borrow;
+use std::sync::Arc;
 
-/// The unique identifier type for an Asset in the AAS
-pub type AssetID = String;
+/// Identifiers backed by a shared string: cloning one (as a map key, or in each message
+/// fanned out to the subscribers) does not allocate. They compare, hash and serialize as
+/// their string, and maps keyed by them can be queried with a `&str`.
+macro_rules! shared_id {
+    ($(#[$doc:meta])* $name:ident) => {
+        $(#[$doc])*
+        #[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+        pub struct $name(Arc<str>);
+
+        impl $name {
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl std::ops::Deref for $name {
+            type Target = str;
+
+            fn deref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl AsRef<str> for $name {
+            fn as_ref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl Borrow<str> for $name {
+            fn borrow(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl std::fmt::Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                std::fmt::Display::fmt(&*self.0, f)
+            }
+        }
+
+        impl std::fmt::Debug for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                std::fmt::Debug::fmt(&*self.0, f)
+            }
+        }
+
+        impl From<&str> for $name {
+            fn from(id: &str) -> Self {
+                $name(id.into())
+            }
+        }
+
+        impl From<String> for $name {
+            fn from(id: String) -> Self {
+                $name(id.into())
+            }
+        }
+
+        impl From<&String> for $name {
+            fn from(id: &String) -> Self {
+                $name(id.as_str().into())
+            }
+        }
+
+        impl From<$name> for String {
+            fn from(id: $name) -> Self {
+                id.0.to_string()
+            }
+        }
+
+        impl From<&$name> for String {
+            fn from(id: &$name) -> Self {
+                id.0.to_string()
+            }
+        }
+
+        impl std::str::FromStr for $name {
+            type Err = std::convert::Infallible;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                Ok(s.into())
+            }
+        }
+
+        impl PartialEq<str> for $name {
+            fn eq(&self, other: &str) -> bool {
+                &*self.0 == other
+            }
+        }
+
+        impl PartialEq<&str> for $name {
+            fn eq(&self, other: &&str) -> bool {
+                &*self.0 == *other
+            }
+        }
+
+        impl PartialEq<String> for $name {
+            fn eq(&self, other: &String) -> bool {
+                *self.0 == **other
+            }
+        }
+
+        impl PartialEq<$name> for str {
+            fn eq(&self, other: &$name) -> bool {
+                self == &*other.0
+            }
+        }
+
+        impl PartialEq<$name> for &str {
+            fn eq(&self, other: &$name) -> bool {
+                *self == &*other.0
+            }
+        }
+
+        impl PartialEq<$name> for String {
+            fn eq(&self, other: &$name) -> bool {
+                **self == *other.0
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(&self.0)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                String::deserialize(deserializer).map(Into::into)
+            }
+        }
+    };
+}
+
+shared_id!(
+    /// The unique identifier type for an IoT Sensor or Actuator
+    /// (_not_ related to the Digital Twin ID in the AAS)
+    DeviceID
+);
+
+shared_id!(
+    /// The unique identifier type for an Asset in the AAS
+    AssetID
+);
+
+impl AssetID {
+    /// Check that the ID is a URN ("urn:<namespace ID>:<segment>[:<segment>...]") with no
+    /// empty segment, and that it can be used as an MQTT topic level: no whitespace, '/',
+    /// '+' or '#'
+    pub fn validate(&self) -> Result<(), String> {
+        let invalid = |reason: &str| Err(format!("invalid asset ID {:?}: {reason}", self.as_str()));
+        let mut segments = self.segments();
+        if !segments
+            .next()
+            .is_some_and(|scheme| scheme.eq_ignore_ascii_case("urn"))
+        {
+            return invalid("not a URN, \"urn:\" expected");
+        }
+        if self.segments().count() < 3 {
+            return invalid("a namespace ID and at least one segment expected after \"urn:\"");
+        }
+        if self.segments().any(str::is_empty) {
+            return invalid("empty segment");
+        }
+        if let Some(c) = self
+            .chars()
+            .find(|c| c.is_whitespace() || c.is_control() || matches!(c, '/' | '+' | '#'))
+        {
+            return invalid(&format!("{c:?} not allowed"));
+        }
+        Ok(())
+    }
+
+    /// The segments of the ID separated by ':', starting from the "urn" scheme
+    fn segments(&self) -> std::str::Split<'_, char> {
+        self.as_str().split(':')
+    }
+
+    /// Domain of the asset, the first segment after the namespace ID (e.g. "smart-home" in
+    /// "urn:aas:smart-home:charging-station:ac-level2:id-000001")
+    pub fn domain(&self) -> Option<&str> {
+        self.segments().nth(2)
+    }
+
+    /// Object type of the asset (e.g. "charging-station")
+    pub fn category(&self) -> Option<&str> {
+        self.segments().nth(3)
+    }
+
+    /// Instance identifier of the asset, the last segment after the object type
+    /// (e.g. "id-000001")
+    pub fn instance_id(&self) -> Option<&str> {
+        self.segments().skip(4).last()
+    }
+}
+
+shared_id!(
+    /// Identifier relating an input (a message or a command) to the state transitions,
+    /// events and acks it causes
+    CorrelationID
+);
+
+/// Mean radius of the Earth, in kilometers
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// Position of an asset, in decimal degrees (WGS 84)
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GeoLocation {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl GeoLocation {
+    /// The location, if the coordinates are within their ranges
+    pub fn new(latitude: f64, longitude: f64) -> Option<Self> {
+        ((-90.0..=90.0).contains(&latitude) && (-180.0..=180.0).contains(&longitude))
+            .then_some(GeoLocation { latitude, longitude })
+    }
+
+    /// Great-circle distance to another location, in kilometers (haversine formula)
+    pub fn distance_km(&self, other: &GeoLocation) -> f64 {
+        let (lat1, lat2) = (self.latitude.to_radians(), other.latitude.to_radians());
+        let d_lat = lat2 - lat1;
+        let d_lon = (other.longitude - self.longitude).to_radians();
+        let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_KM * a.sqrt().min(1.0).asin()
+    }
+}
+
+/// The value received on an input slot
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SlotValue {
+    Bool(bool),
+    Number(f64),
+    Text(String),
+}
+
+impl std::fmt::Display for SlotValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SlotValue::Bool(b) => write!(f, "{}", b),
+            SlotValue::Number(n) => write!(f, "{}", n),
+            SlotValue::Text(s) => write!(f, "{:?}", s),
+        }
+    }
+}
+
+impl From<f32> for SlotValue {
+    fn from(value: f32) -> Self {
+        SlotValue::Number(value as f64)
+    }
+}
+
+impl From<bool> for SlotValue {
+    fn from(value: bool) -> Self {
+        SlotValue::Bool(value)
+    }
+}
+
+impl From<&str> for SlotValue {
+    fn from(value: &str) -> Self {
+        SlotValue::Text(value.to_string())
+    }
+}
+
+/// Conversion of a slot value into the argument type of an input handler.
+/// Returns None if the value has the wrong type for the handler.
+pub trait FromSlotValue: Sized {
+    fn from_slot_value(value: SlotValue) -> Option<Self>;
+}
+
+impl FromSlotValue for SlotValue {
+    fn from_slot_value(value: SlotValue) -> Option<Self> {
+        Some(value)
+    }
+}
+
+impl FromSlotValue for f32 {
+    fn from_slot_value(value: SlotValue) -> Option<Self> {
+        f64::from_slot_value(value).map(|v| v as f32)
+    }
+}
+
+impl FromSlotValue for f64 {
+    fn from_slot_value(value: SlotValue) -> Option<Self> {
+        match value {
+            SlotValue::Number(n) => Some(n),
+            _ => None,
+        }
+    }
+}
+
+impl FromSlotValue for bool {
+    fn from_slot_value(value: SlotValue) -> Option<Self> {
+        match value {
+            SlotValue::Bool(b) => Some(b),
+            _ => None,
+        }
+    }
+}
+
+impl FromSlotValue for String {
+    fn from_slot_value(value: SlotValue) -> Option<Self> {
+        match value {
+            SlotValue::Text(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_asset_id_parts() {
+        let parts = |id: &str| {
+            let id = AssetID::from(id);
+            (
+                id.domain().map(str::to_string),
+                id.category().map(str::to_string),
+                id.instance_id().map(str::to_string),
+            )
+        };
+        let some = |s: &str| Some(s.to_string());
+        assert_eq!(
+            parts("urn:aas:smart-home:charging-station:ac-level2:id-000001"),
+            (some("smart-home"), some("charging-station"), some("id-000001"))
+        );
+        assert_eq!(
+            parts("urn:aas:smart-home:light:1"),
+            (some("smart-home"), some("light"), some("1"))
+        );
+        assert_eq!(
+            parts("urn:aas:smart-home:light"),
+            (some("smart-home"), some("light"), None)
+        );
+        assert_eq!(parts("urn:aas:example"), (some("example"), None, None));
+        // The namespace ID is not a part
+        assert_eq!(parts("urn:aas"), (None, None, None));
+        assert_eq!(parts("light-1"), (None, None, None));
+    }
+}
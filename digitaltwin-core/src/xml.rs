@@ -0,0 +1,899 @@
+/// XML (de)serialization of the Asset Administration Shell, following the IDTA AAS
+/// metamodel v3.0 XSD (https://admin-shell.io/aas/3/0). The XML environment keeps the
+/// submodels and concept descriptions next to the shells, linked by references.
+use std::io::BufRead;
+
+use quick_xml::escape::escape;
+use quick_xml::events::Event as XmlEvent;
+use quick_xml::Reader;
+
+use crate::aas::{
+    AdministrativeInformation, AssetAdministrationShell, AssetKind, ConceptDescription, Event, EventField,
+    Operation, OperationVariable, Property, ReferenceElement, Submodel, SubmodelCollection, SubmodelElement,
+    Value, ValueType, MAX_COLLECTION_DEPTH,
+};
+
+const AAS_NAMESPACE: &str = "https://admin-shell.io/aas/3/0";
+const IEC61360_TEMPLATE: &str =
+    "https://admin-shell.io/DataSpecificationTemplates/DataSpecificationIec61360/3/0";
+/// Qualifier type used to store `Operation::cooldown_ms`
+const COOLDOWN_QUALIFIER: &str = "CooldownMs";
+/// Qualifier type used to store each of `Operation::allowed_principals`
+const PRINCIPAL_QUALIFIER: &str = "AllowedPrincipal";
+/// Qualifier type prefix used to store the fields of `Event::payload` ("EventPayload:<name>")
+const PAYLOAD_QUALIFIER: &str = "EventPayload:";
+/// Language of the descriptions written
+const LANGUAGE: &str = "en";
+/// Maximum nesting of the XML elements, enough for MAX_COLLECTION_DEPTH collections
+const MAX_XML_DEPTH: usize = 4 * MAX_COLLECTION_DEPTH + 16;
+
+/// Minimal XML element tree (attributes are not used by the AAS schema)
+#[derive(Debug, Default)]
+struct Node {
+    name: String,
+    text: String,
+    children: Vec<Node>,
+}
+
+impl Node {
+    fn new(name: &str) -> Self {
+        Node {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn text(name: &str, text: impl Into<String>) -> Self {
+        Node {
+            name: name.to_string(),
+            text: text.into(),
+            children: Vec::new(),
+        }
+    }
+
+    fn with(mut self, child: Node) -> Self {
+        self.children.push(child);
+        self
+    }
+
+    fn with_opt(self, child: Option<Node>) -> Self {
+        match child {
+            Some(child) => self.with(child),
+            None => self,
+        }
+    }
+
+    fn child(&self, name: &str) -> Option<&Node> {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Node> {
+        self.children.iter().filter(move |c| c.name == name)
+    }
+
+    fn child_text(&self, name: &str) -> Option<&str> {
+        self.child(name).map(|c| c.text.as_str())
+    }
+
+    fn required_text(&self, name: &str) -> Result<String, String> {
+        self.child_text(name)
+            .map(str::to_string)
+            .ok_or_else(|| format!("missing {name} in {}", self.name))
+    }
+
+    /// Parse a whole document, returning the root element
+    fn parse<R: BufRead>(reader: R) -> Result<Node, String> {
+        let mut reader = Reader::from_reader(reader);
+        reader.config_mut().trim_text(true);
+        let mut buf = Vec::new();
+        let mut stack: Vec<Node> = Vec::new();
+        loop {
+            let event = reader
+                .read_event_into(&mut buf)
+                .map_err(|e| format!("Failed to parse XML: {e}"))?;
+            match event {
+                XmlEvent::Start(e) => {
+                    if stack.len() >= MAX_XML_DEPTH {
+                        return Err(format!(
+                            "Failed to parse XML: elements nested deeper than {MAX_XML_DEPTH} levels"
+                        ));
+                    }
+                    stack.push(Node::new(&String::from_utf8_lossy(e.local_name().as_ref())));
+                }
+                XmlEvent::Empty(e) => {
+                    let node = Node::new(&String::from_utf8_lossy(e.local_name().as_ref()));
+                    match stack.last_mut() {
+                        Some(parent) => parent.children.push(node),
+                        None => return Ok(node),
+                    }
+                }
+                XmlEvent::Text(e) => {
+                    if let Some(node) = stack.last_mut() {
+                        node.text
+                            .push_str(&e.unescape().map_err(|e| format!("Failed to parse XML: {e}"))?);
+                    }
+                }
+                XmlEvent::CData(e) => {
+                    if let Some(node) = stack.last_mut() {
+                        node.text.push_str(&String::from_utf8_lossy(&e));
+                    }
+                }
+                XmlEvent::End(_) => {
+                    let node = stack.pop().ok_or("Failed to parse XML: unbalanced end tag")?;
+                    match stack.last_mut() {
+                        Some(parent) => parent.children.push(node),
+                        None => return Ok(node),
+                    }
+                }
+                XmlEvent::Eof => return Err("Failed to parse XML: no root element".to_string()),
+                _ => {}
+            }
+            buf.clear();
+        }
+    }
+
+    fn write(&self, out: &mut String, depth: usize) {
+        let indent = "  ".repeat(depth);
+        if self.children.is_empty() {
+            out.push_str(&format!(
+                "{indent}<{0}>{1}</{0}>\n",
+                self.name,
+                escape(&self.text)
+            ));
+        } else {
+            out.push_str(&format!("{indent}<{}>\n", self.name));
+            for child in &self.children {
+                child.write(out, depth + 1);
+            }
+            out.push_str(&format!("{indent}</{}>\n", self.name));
+        }
+    }
+}
+
+impl AssetAdministrationShell {
+    /// Load an AssetAdministrationShell from an AAS XML environment. The first shell of
+    /// the environment is loaded, with the submodels it references (or all of them if
+    /// it references none) and all the concept descriptions.
+    pub fn from_xml_reader<R: BufRead>(reader: R) -> Result<Self, String> {
+        let root = Node::parse(reader)?;
+        if root.name != "environment" {
+            return Err(format!("unexpected root element {}", root.name));
+        }
+        let shell = root
+            .child("assetAdministrationShells")
+            .and_then(|shells| shells.child("assetAdministrationShell"))
+            .ok_or("no assetAdministrationShell found")?;
+
+        let mut submodels = root
+            .child("submodels")
+            .map(|s| s.children_named("submodel").map(read_submodel).collect())
+            .transpose()?
+            .unwrap_or_else(Vec::new);
+        let references: Vec<String> = shell
+            .child("submodels")
+            .map(|refs| {
+                refs.children_named("reference")
+                    .filter_map(read_reference)
+                    .collect()
+            })
+            .unwrap_or_default();
+        if !references.is_empty() {
+            submodels = references
+                .iter()
+                .filter_map(|id| submodels.iter().find(|s| &s.id == id).cloned())
+                .collect();
+        }
+
+        let concept_descriptions = root
+            .child("conceptDescriptions")
+            .map(|c| {
+                c.children_named("conceptDescription")
+                    .map(read_concept_description)
+                    .collect()
+            })
+            .transpose()?
+            .unwrap_or_else(Vec::new);
+
+        Ok(AssetAdministrationShell {
+            id: shell.required_text("id")?.into(),
+            id_short: shell.child_text("idShort").unwrap_or_default().to_string(),
+            description: shell.child("description").and_then(read_lang_string),
+            administration: shell.child("administration").map(|a| AdministrativeInformation {
+                version: a.child_text("version").unwrap_or_default().to_string(),
+                revision: a.child_text("revision").unwrap_or_default().to_string(),
+            }),
+            asset_kind: match shell
+                .child("assetInformation")
+                .and_then(|a| a.child_text("assetKind"))
+            {
+                Some("Type") => AssetKind::Type,
+                _ => AssetKind::Instance,
+            },
+            derived_from: shell
+                .child("derivedFrom")
+                .and_then(read_reference)
+                .map(Into::into),
+            submodels,
+            concept_descriptions,
+        })
+        .and_then(|aas| aas.check().map(|_| aas))
+    }
+
+    /// Serialize the AssetAdministrationShell into an AAS XML environment
+    pub fn to_xml(&self) -> String {
+        let mut shell = Node::new("assetAdministrationShell")
+            .with(Node::text("idShort", &self.id_short))
+            .with_opt(
+                self.description
+                    .as_ref()
+                    .map(|d| lang_string("description", "langStringTextType", d)),
+            )
+            .with_opt(self.administration.as_ref().map(|a| {
+                Node::new("administration")
+                    .with(Node::text("version", &a.version))
+                    .with(Node::text("revision", &a.revision))
+            }))
+            .with(Node::text("id", &self.id))
+            .with_opt(
+                self.derived_from
+                    .as_ref()
+                    .map(|id| model_reference("derivedFrom", &[("AssetAdministrationShell", id)])),
+            )
+            .with(
+                Node::new("assetInformation")
+                    .with(Node::text(
+                        "assetKind",
+                        match self.asset_kind {
+                            AssetKind::Type => "Type",
+                            AssetKind::Instance => "Instance",
+                        },
+                    ))
+                    .with(Node::text("globalAssetId", &self.id)),
+            );
+        if !self.submodels.is_empty() {
+            shell = shell.with(Node {
+                name: "submodels".to_string(),
+                text: String::new(),
+                children: self
+                    .submodels
+                    .iter()
+                    .map(|s| model_reference("reference", &[("Submodel", &s.id)]))
+                    .collect(),
+            });
+        }
+
+        let mut environment =
+            Node::new("environment").with(Node::new("assetAdministrationShells").with(shell));
+        if !self.submodels.is_empty() {
+            environment = environment.with(Node {
+                name: "submodels".to_string(),
+                text: String::new(),
+                children: self.submodels.iter().map(write_submodel).collect(),
+            });
+        }
+        if !self.concept_descriptions.is_empty() {
+            environment = environment.with(Node {
+                name: "conceptDescriptions".to_string(),
+                text: String::new(),
+                children: self
+                    .concept_descriptions
+                    .iter()
+                    .map(write_concept_description)
+                    .collect(),
+            });
+        }
+
+        // The root element carries the namespace declaration
+        let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        let mut body = String::new();
+        environment.write(&mut body, 0);
+        out.push_str(&body.replacen(
+            "<environment>",
+            &format!("<environment xmlns=\"{AAS_NAMESPACE}\">"),
+            1,
+        ));
+        out
+    }
+}
+
+// ========== READING ==========
+
+/// Read the text of the first language string of a multi-language element
+fn read_lang_string(node: &Node) -> Option<String> {
+    node.children
+        .first()
+        .and_then(|s| s.child_text("text"))
+        .map(str::to_string)
+}
+
+/// Read a reference: model references to an element of a submodel become
+/// "<submodel id>#<element id_short>", all others the value of their last key
+fn read_reference(node: &Node) -> Option<String> {
+    let keys: Vec<(&str, &str)> = node
+        .child("keys")?
+        .children_named("key")
+        .map(|k| {
+            (
+                k.child_text("type").unwrap_or_default(),
+                k.child_text("value").unwrap_or_default(),
+            )
+        })
+        .collect();
+    match keys.as_slice() {
+        [] => None,
+        [("Submodel", submodel), .., (_, element)] => Some(format!("{submodel}#{element}")),
+        [.., (_, value)] => Some(value.to_string()),
+    }
+}
+
+fn read_value_type(value_type: Option<&str>) -> ValueType {
+    match value_type.unwrap_or_default() {
+        "xs:int"
+        | "xs:integer"
+        | "xs:long"
+        | "xs:short"
+        | "xs:byte"
+        | "xs:unsignedInt"
+        | "xs:unsignedLong"
+        | "xs:unsignedShort"
+        | "xs:unsignedByte"
+        | "xs:nonNegativeInteger"
+        | "xs:positiveInteger"
+        | "xs:negativeInteger"
+        | "xs:nonPositiveInteger" => ValueType::Int,
+        "xs:double" | "xs:float" | "xs:decimal" => ValueType::Float,
+        "xs:boolean" => ValueType::Bool,
+        _ => ValueType::String,
+    }
+}
+
+fn read_value(text: Option<&str>, value_type: &ValueType) -> Value {
+    let Some(text) = text else {
+        return Value::Null;
+    };
+    match value_type {
+        ValueType::Int => text
+            .parse()
+            .map(Value::Int)
+            .unwrap_or(Value::Str(text.to_string())),
+        ValueType::Float => text
+            .parse()
+            .map(Value::Flt)
+            .unwrap_or(Value::Str(text.to_string())),
+        ValueType::Bool => match text {
+            "true" | "1" => Value::Bool(true),
+            "false" | "0" => Value::Bool(false),
+            _ => Value::Str(text.to_string()),
+        },
+        ValueType::Json => serde_json::from_str(text)
+            .map(Value::Obj)
+            .unwrap_or(Value::Str(text.to_string())),
+        ValueType::String => Value::Str(text.to_string()),
+    }
+}
+
+fn read_submodel(node: &Node) -> Result<Submodel, String> {
+    Ok(Submodel {
+        id: node.required_text("id")?,
+        id_short: node.child_text("idShort").unwrap_or_default().to_string(),
+        elements: read_elements(node.child("submodelElements"))?,
+    })
+}
+
+/// Read the elements in a container, skipping the kinds of element not supported
+fn read_elements(node: Option<&Node>) -> Result<Vec<SubmodelElement>, String> {
+    let Some(node) = node else {
+        return Ok(Vec::new());
+    };
+    let mut elements = Vec::new();
+    for child in &node.children {
+        if let Some(element) = read_element(child)? {
+            elements.push(element);
+        }
+    }
+    Ok(elements)
+}
+
+fn read_element(node: &Node) -> Result<Option<SubmodelElement>, String> {
+    let id_short = node.required_text("idShort")?;
+    let semantic_id = node.child("semanticId").and_then(read_reference);
+    let element = match node.name.as_str() {
+        "property" => {
+            let value_type = read_value_type(node.child_text("valueType"));
+            SubmodelElement::Property(Property {
+                id_short,
+                value: read_value(node.child_text("value"), &value_type),
+                value_type,
+                semantic_id,
+            })
+        }
+        "submodelElementCollection" => SubmodelElement::Collection(SubmodelCollection {
+            id_short,
+            value: read_elements(node.child("value"))?,
+            semantic_id,
+        }),
+        "operation" => SubmodelElement::Operation(Operation {
+            id_short,
+            input_variables: read_operation_variables(node.child("inputVariables")),
+            output_variables: read_operation_variables(node.child("outputVariables")),
+            cooldown_ms: node
+                .child("qualifiers")
+                .into_iter()
+                .flat_map(|q| q.children_named("qualifier"))
+                .find(|q| q.child_text("type") == Some(COOLDOWN_QUALIFIER))
+                .and_then(|q| q.child_text("value"))
+                .and_then(|v| v.parse().ok()),
+            allowed_principals: node
+                .child("qualifiers")
+                .into_iter()
+                .flat_map(|q| q.children_named("qualifier"))
+                .filter(|q| q.child_text("type") == Some(PRINCIPAL_QUALIFIER))
+                .filter_map(|q| q.child_text("value"))
+                .map(str::to_string)
+                .collect(),
+        }),
+        "referenceElement" => SubmodelElement::ReferenceElement(ReferenceElement {
+            id_short,
+            value: node.child("value").and_then(read_reference).unwrap_or_default(),
+        }),
+        "basicEventElement" => SubmodelElement::Event(Event {
+            id_short,
+            payload: node
+                .child("qualifiers")
+                .into_iter()
+                .flat_map(|q| q.children_named("qualifier"))
+                .filter_map(|q| {
+                    let name = q.child_text("type")?.strip_prefix(PAYLOAD_QUALIFIER)?;
+                    Some(EventField {
+                        name: name.to_string(),
+                        value_type: read_value_type(q.child_text("valueType")),
+                    })
+                })
+                .collect(),
+            // A reference to the whole submodel is just the required placeholder
+            observed: node
+                .child("observed")
+                .and_then(read_reference)
+                .filter(|r| r.contains('#')),
+            message_topic: node.child_text("messageTopic").map(str::to_string),
+            // Not part of the AAS metamodel
+            actuation: None,
+        }),
+        _ => return Ok(None),
+    };
+    Ok(Some(element))
+}
+
+/// Read operation variables, holding a property each
+fn read_operation_variables(node: Option<&Node>) -> Vec<OperationVariable> {
+    node.into_iter()
+        .flat_map(|n| n.children_named("operationVariable"))
+        .filter_map(|v| v.child("value")?.child("property"))
+        .map(|p| {
+            let value_type = read_value_type(p.child_text("valueType"));
+            OperationVariable {
+                name: p.child_text("idShort").unwrap_or_default().to_string(),
+                value: read_value(p.child_text("value"), &value_type),
+                value_type,
+            }
+        })
+        .collect()
+}
+
+fn read_concept_description(node: &Node) -> Result<ConceptDescription, String> {
+    let iec61360 = node
+        .child("embeddedDataSpecifications")
+        .into_iter()
+        .flat_map(|e| e.children_named("embeddedDataSpecification"))
+        .find_map(|e| {
+            e.child("dataSpecificationContent")?
+                .child("dataSpecificationIec61360")
+        });
+    Ok(ConceptDescription {
+        id: node.required_text("id")?,
+        id_short: node.child_text("idShort").unwrap_or_default().to_string(),
+        preferred_name: iec61360
+            .and_then(|c| c.child("preferredName"))
+            .and_then(read_lang_string),
+        definition: iec61360
+            .and_then(|c| c.child("definition"))
+            .and_then(read_lang_string)
+            .or_else(|| node.child("description").and_then(read_lang_string)),
+        unit: iec61360.and_then(|c| c.child_text("unit")).map(str::to_string),
+        value_type: iec61360
+            .and_then(|c| c.child_text("dataType"))
+            .and_then(|t| match t {
+                "STRING" | "STRING_TRANSLATABLE" => Some(ValueType::String),
+                "INTEGER_COUNT" | "INTEGER_MEASURE" | "INTEGER_CURRENCY" => Some(ValueType::Int),
+                "REAL_COUNT" | "REAL_MEASURE" | "REAL_CURRENCY" => Some(ValueType::Float),
+                "BOOLEAN" => Some(ValueType::Bool),
+                _ => None,
+            }),
+        is_case_of: node
+            .child("isCaseOf")
+            .map(|refs| {
+                refs.children_named("reference")
+                    .filter_map(read_reference)
+                    .collect()
+            })
+            .unwrap_or_default(),
+    })
+}
+
+// ========== WRITING ==========
+
+fn lang_string(name: &str, kind: &str, text: &str) -> Node {
+    Node::new(name).with(
+        Node::new(kind)
+            .with(Node::text("language", LANGUAGE))
+            .with(Node::text("text", text)),
+    )
+}
+
+fn reference(name: &str, kind: &str, keys: &[(&str, &str)]) -> Node {
+    let keys = keys.iter().fold(Node::new("keys"), |node, (key_type, value)| {
+        node.with(
+            Node::new("key")
+                .with(Node::text("type", *key_type))
+                .with(Node::text("value", *value)),
+        )
+    });
+    Node::new(name).with(Node::text("type", kind)).with(keys)
+}
+
+fn model_reference(name: &str, keys: &[(&str, &str)]) -> Node {
+    reference(name, "ModelReference", keys)
+}
+
+fn global_reference(name: &str, value: &str) -> Node {
+    reference(name, "ExternalReference", &[("GlobalReference", value)])
+}
+
+/// Write a reference string: "<submodel id>#<element id_short>" becomes a model reference
+fn element_reference(name: &str, value: &str) -> Node {
+    match value.split_once('#') {
+        Some((submodel, element)) => model_reference(
+            name,
+            &[("Submodel", submodel), ("SubmodelElementCollection", element)],
+        ),
+        None => global_reference(name, value),
+    }
+}
+
+pub(crate) fn write_value_type(value_type: &ValueType) -> &'static str {
+    match value_type {
+        ValueType::String | ValueType::Json => "xs:string",
+        ValueType::Int => "xs:long",
+        ValueType::Float => "xs:double",
+        ValueType::Bool => "xs:boolean",
+    }
+}
+
+fn write_value(value: &Value) -> Option<String> {
+    match value {
+        Value::Str(s) => Some(s.clone()),
+        Value::Int(i) => Some(i.to_string()),
+        Value::Flt(f) => Some(f.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        Value::Obj(o) => Some(o.to_string()),
+        Value::Null => None,
+    }
+}
+
+fn write_property(
+    id_short: &str,
+    semantic_id: Option<&String>,
+    value_type: &ValueType,
+    value: &Value,
+) -> Node {
+    Node::new("property")
+        .with(Node::text("idShort", id_short))
+        .with_opt(semantic_id.map(|id| global_reference("semanticId", id)))
+        .with(Node::text("valueType", write_value_type(value_type)))
+        .with_opt(write_value(value).map(|v| Node::text("value", v)))
+}
+
+fn write_submodel(submodel: &Submodel) -> Node {
+    Node::new("submodel")
+        .with(Node::text("idShort", &submodel.id_short))
+        .with(Node::text("id", &submodel.id))
+        .with(Node {
+            name: "submodelElements".to_string(),
+            text: String::new(),
+            children: submodel
+                .elements
+                .iter()
+                .map(|e| write_element(&submodel.id, e))
+                .collect(),
+        })
+}
+
+fn write_element(submodel_id: &str, element: &SubmodelElement) -> Node {
+    match element {
+        SubmodelElement::Property(p) => {
+            write_property(&p.id_short, p.semantic_id.as_ref(), &p.value_type, &p.value)
+        }
+        SubmodelElement::Collection(c) => Node::new("submodelElementCollection")
+            .with(Node::text("idShort", &c.id_short))
+            .with_opt(
+                c.semantic_id
+                    .as_ref()
+                    .map(|id| global_reference("semanticId", id)),
+            )
+            .with(Node {
+                name: "value".to_string(),
+                text: String::new(),
+                children: c.value.iter().map(|e| write_element(submodel_id, e)).collect(),
+            }),
+        SubmodelElement::Operation(o) => {
+            let variables = |name: &str, variables: &[OperationVariable]| {
+                (!variables.is_empty()).then(|| Node {
+                    name: name.to_string(),
+                    text: String::new(),
+                    children: variables
+                        .iter()
+                        .map(|v| {
+                            Node::new("operationVariable").with(Node::new("value").with(write_property(
+                                &v.name,
+                                None,
+                                &v.value_type,
+                                &v.value,
+                            )))
+                        })
+                        .collect(),
+                })
+            };
+            let qualifiers: Vec<_> = o
+                .cooldown_ms
+                .map(|cooldown| (COOLDOWN_QUALIFIER, "xs:long", cooldown.to_string()))
+                .into_iter()
+                .chain(
+                    o.allowed_principals
+                        .iter()
+                        .map(|principal| (PRINCIPAL_QUALIFIER, "xs:string", principal.clone())),
+                )
+                .map(|(qualifier_type, value_type, value)| {
+                    Node::new("qualifier")
+                        .with(Node::text("type", qualifier_type))
+                        .with(Node::text("valueType", value_type))
+                        .with(Node::text("value", value))
+                })
+                .collect();
+            Node::new("operation")
+                .with(Node::text("idShort", &o.id_short))
+                .with_opt((!qualifiers.is_empty()).then(|| Node {
+                    name: "qualifiers".to_string(),
+                    text: String::new(),
+                    children: qualifiers,
+                }))
+                .with_opt(variables("inputVariables", &o.input_variables))
+                .with_opt(variables("outputVariables", &o.output_variables))
+        }
+        SubmodelElement::ReferenceElement(r) => Node::new("referenceElement")
+            .with(Node::text("idShort", &r.id_short))
+            .with(element_reference("value", &r.value)),
+        SubmodelElement::Event(e) => Node::new("basicEventElement")
+            .with(Node::text("idShort", &e.id_short))
+            .with_opt((!e.payload.is_empty()).then(|| {
+                Node {
+                    name: "qualifiers".to_string(),
+                    text: String::new(),
+                    children: e
+                        .payload
+                        .iter()
+                        .map(|field| {
+                            Node::new("qualifier")
+                                .with(Node::text("type", format!("{PAYLOAD_QUALIFIER}{}", field.name)))
+                                .with(Node::text("valueType", write_value_type(&field.value_type)))
+                        })
+                        .collect(),
+                }
+            }))
+            .with(match &e.observed {
+                Some(observed) => element_reference("observed", observed),
+                None => model_reference("observed", &[("Submodel", submodel_id)]),
+            })
+            .with(Node::text("direction", "output"))
+            .with(Node::text("state", "on"))
+            .with_opt(e.message_topic.as_ref().map(|t| Node::text("messageTopic", t))),
+    }
+}
+
+fn write_concept_description(concept: &ConceptDescription) -> Node {
+    let data_type = concept.value_type.as_ref().map(|t| match t {
+        ValueType::String | ValueType::Json => "STRING",
+        ValueType::Int => "INTEGER_MEASURE",
+        ValueType::Float => "REAL_MEASURE",
+        ValueType::Bool => "BOOLEAN",
+    });
+    let preferred_name = concept.preferred_name.as_deref().unwrap_or(&concept.id_short);
+    let content = Node::new("dataSpecificationIec61360")
+        .with(lang_string(
+            "preferredName",
+            "langStringPreferredNameTypeIec61360",
+            preferred_name,
+        ))
+        .with_opt(concept.unit.as_ref().map(|u| Node::text("unit", u)))
+        .with_opt(data_type.map(|t| Node::text("dataType", t)))
+        .with_opt(
+            concept
+                .definition
+                .as_ref()
+                .map(|d| lang_string("definition", "langStringDefinitionTypeIec61360", d)),
+        );
+    let mut node = Node::new("conceptDescription")
+        .with(Node::text("idShort", &concept.id_short))
+        .with(Node::text("id", &concept.id))
+        .with(
+            Node::new("embeddedDataSpecifications").with(
+                Node::new("embeddedDataSpecification")
+                    .with(global_reference("dataSpecification", IEC61360_TEMPLATE))
+                    .with(Node::new("dataSpecificationContent").with(content)),
+            ),
+        );
+    if !concept.is_case_of.is_empty() {
+        node = node.with(Node {
+            name: "isCaseOf".to_string(),
+            text: String::new(),
+            children: concept
+                .is_case_of
+                .iter()
+                .map(|id| global_reference("reference", id))
+                .collect(),
+        });
+    }
+    node
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<aas:environment xmlns:aas="https://admin-shell.io/aas/3/0">
+  <aas:assetAdministrationShells>
+    <aas:assetAdministrationShell>
+      <aas:idShort>LightBulb1</aas:idShort>
+      <aas:description>
+        <aas:langStringTextType><aas:language>en</aas:language><aas:text>A simple light bulb</aas:text></aas:langStringTextType>
+      </aas:description>
+      <aas:id>urn:aas:example:light</aas:id>
+      <aas:assetInformation><aas:assetKind>Instance</aas:assetKind></aas:assetInformation>
+      <aas:submodels>
+        <aas:reference>
+          <aas:type>ModelReference</aas:type>
+          <aas:keys><aas:key><aas:type>Submodel</aas:type><aas:value>urn:aas:example:power</aas:value></aas:key></aas:keys>
+        </aas:reference>
+      </aas:submodels>
+    </aas:assetAdministrationShell>
+  </aas:assetAdministrationShells>
+  <aas:submodels>
+    <aas:submodel>
+      <aas:idShort>PowerAndElectrical</aas:idShort>
+      <aas:id>urn:aas:example:power</aas:id>
+      <aas:submodelElements>
+        <aas:submodelElementCollection>
+          <aas:idShort>CurrentPowerDraw</aas:idShort>
+          <aas:value>
+            <aas:referenceElement>
+              <aas:idShort>DataSource</aas:idShort>
+              <aas:value>
+                <aas:type>ModelReference</aas:type>
+                <aas:keys>
+                  <aas:key><aas:type>Submodel</aas:type><aas:value>urn:aas:example:datasources</aas:value></aas:key>
+                  <aas:key><aas:type>SubmodelElementCollection</aas:type><aas:value>Sensors</aas:value></aas:key>
+                  <aas:key><aas:type>SubmodelElementCollection</aas:type><aas:value>SensorPower</aas:value></aas:key>
+                </aas:keys>
+              </aas:value>
+            </aas:referenceElement>
+          </aas:value>
+        </aas:submodelElementCollection>
+        <aas:property>
+          <aas:idShort>MaxPower</aas:idShort>
+          <aas:valueType>xs:double</aas:valueType>
+          <aas:value>60</aas:value>
+        </aas:property>
+        <aas:multiLanguageProperty>
+          <aas:idShort>Unsupported</aas:idShort>
+        </aas:multiLanguageProperty>
+      </aas:submodelElements>
+    </aas:submodel>
+    <aas:submodel>
+      <aas:idShort>Unreferenced</aas:idShort>
+      <aas:id>urn:aas:example:other</aas:id>
+    </aas:submodel>
+  </aas:submodels>
+</aas:environment>
+"#;
+
+    #[test]
+    fn test_from_xml() {
+        let aas = AssetAdministrationShell::from_xml_reader(XML.as_bytes()).unwrap();
+        assert_eq!(aas.id, "urn:aas:example:light");
+        assert_eq!(aas.id_short, "LightBulb1");
+        assert_eq!(aas.description.as_deref(), Some("A simple light bulb"));
+        assert_eq!(aas.submodels.len(), 1);
+        assert_eq!(aas.submodels[0].elements.len(), 2);
+        assert_eq!(
+            aas.find_reference_value_in_collection("PowerAndElectrical", "CurrentPowerDraw", "DataSource"),
+            Some("urn:aas:example:datasources#SensorPower".to_string())
+        );
+        assert!(
+            matches!(&aas.submodels[0].elements[1], SubmodelElement::Property(p) if matches!(p.value, Value::Flt(v) if v == 60.0))
+        );
+    }
+
+    #[test]
+    fn test_xml_roundtrip() {
+        let yaml = r#"
+id: "urn:aas:example:charger"
+id_short: "Charger"
+description: "Example charger"
+asset_kind: Type
+administration:
+  version: "1"
+  revision: "2"
+submodels:
+  - id: "urn:aas:example:power"
+    id_short: "PowerAndElectrical"
+    elements:
+      - element_type: "collection"
+        id_short: "CurrentPowerDraw"
+        semantic_id: "urn:concept:power"
+        value:
+          - element_type: "referenceelement"
+            id_short: "DataSource"
+            value: "urn:aas:example:datasources#SensorPower"
+      - element_type: "property"
+        id_short: "Phases"
+        value_type: "int"
+        value: 3
+      - element_type: "property"
+        id_short: "Enabled"
+        value_type: "bool"
+        value: true
+      - element_type: "operation"
+        id_short: "SetCurrent"
+        input_variables:
+          - name: "desired_current"
+            value_type: "float"
+            value: 6.5
+        cooldown_ms: 5000
+        allowed_principals: ["operator", "installer"]
+      - element_type: "event"
+        id_short: "ChargingStarted"
+      - element_type: "event"
+        id_short: "OvercurrentFault"
+        observed: "urn:aas:example:power#CurrentPowerDraw"
+        message_topic: "alerts/charger"
+        payload:
+          - name: "current"
+            value_type: "float"
+concept_descriptions:
+  - id: "urn:concept:power"
+    id_short: "Power"
+    preferred_name: "Active power"
+    definition: "Power drawn <from> the grid & more"
+    unit: "W"
+    value_type: "float"
+    is_case_of: ["0173-1#02-AAV232#002"]
+"#;
+        let aas: AssetAdministrationShell = serde_yaml::from_str(yaml).unwrap();
+        let xml = aas.to_xml();
+        assert!(xml.contains(r#"<environment xmlns="https://admin-shell.io/aas/3/0">"#));
+        let restored = AssetAdministrationShell::from_xml_reader(xml.as_bytes()).unwrap();
+        assert!(aas.diff(&restored).is_empty(), "{:?}", aas.diff(&restored));
+    }
+
+    #[test]
+    fn test_invalid_xml() {
+        assert!(AssetAdministrationShell::from_xml_reader("<environment><submodels>".as_bytes()).is_err());
+        assert!(AssetAdministrationShell::from_xml_reader("<shell></shell>".as_bytes()).is_err());
+        let deep = format!(
+            "<environment>{}{}</environment>",
+            "<a>".repeat(100_000),
+            "</a>".repeat(100_000)
+        );
+        assert!(AssetAdministrationShell::from_xml_reader(deep.as_bytes()).is_err());
+    }
+}
@@ -2,7 +2,7 @@ use std::str::FromStr;
 
 use proc_macro::TokenStream;
 use proc_macro2::Span;
-use quote::{format_ident, quote};
+use quote::{format_ident, quote, ToTokens};
 use syn::{
     parse::{Parse, ParseStream},
     parse_macro_input, AttributeArgs, Data, DeriveInput, Fields, ItemImpl, Lit, Meta, NestedMeta,
@@ -12,9 +12,17 @@ use syn::{
 
 /// Main actor attribute macro. This transforms a regular struct into a state machine actor.
 ///
+/// The optional `states(...)` list names every state the actor can be in, and is used
+/// to rebuild the actor from a snapshot. If omitted, only the default state is restorable.
+/// The optional `events(...)` list names the events the handlers can `emit`.
+/// The optional `max_transitions_per_minute`, `min_dwell_ms` and `safe_state` arguments limit
+/// the transitions of the actor: a twin exceeding them is flapping, and is latched into the
+/// safe state (one of the restorable states) if any.
+/// Handlers read the latest values of all the slots of the twin from `self.context()`.
+///
 /// Example:
 /// ```ignore
-/// #[actor(default_state = "Off", slots("CurrentPowerDraw"))]
+/// #[actor(default_state = "Off", states("Off", "On"), slots("CurrentPowerDraw"), events("BulbBroken"))]
 /// struct LightBulb {
 ///     #[actor_attr(default = "0.5")]
 ///     threshold: f32,
@@ -32,13 +40,56 @@ pub fn actor(attr: TokenStream, item: TokenStream) -> TokenStream {
     let name = &input.ident;
     let vis = &input.vis; // Preserve visibility
     let factory_name = format_ident!("{}Factory", name);
+    let fields_name = format_ident!("{}Fields", name);
+    let snapshot_name = format_ident!("{}Snapshot", name);
 
     // Extract default state from attributes
     let default_state = extract_default_state_from_attr_args(&attr_args)
         .unwrap_or_else(|| panic!("No default_state attribute found for Actor"));
 
-    // Extract slots from attributes
-    let slots = extract_slots_from_attr_args(&attr_args);
+    // Extract slots and events from attributes
+    let slots = extract_list_from_attr_args(&attr_args, "slots");
+    let events = extract_list_from_attr_args(&attr_args, "events");
+
+    // Extract the restorable states from attributes, always including the default one
+    let mut states: Vec<syn::Ident> = extract_list_from_attr_args(&attr_args, "states")
+        .iter()
+        .map(|s| syn::Ident::new(s, Span::call_site()))
+        .collect();
+    if !states.contains(&default_state) {
+        states.push(default_state.clone());
+    }
+
+    // Extract the limits on the transitions from attributes
+    let max_per_minute = match extract_value_from_attr_args(&attr_args, "max_transitions_per_minute") {
+        Some(Lit::Int(max)) => {
+            let max: u32 = max.base10_parse().expect("Invalid max_transitions_per_minute");
+            quote! { Some(#max) }
+        }
+        Some(_) => panic!("max_transitions_per_minute must be an integer"),
+        None => quote! { None },
+    };
+    let min_dwell_ms = match extract_value_from_attr_args(&attr_args, "min_dwell_ms") {
+        Some(Lit::Int(ms)) => {
+            let ms: u64 = ms.base10_parse().expect("Invalid min_dwell_ms");
+            quote! { Some(#ms) }
+        }
+        Some(_) => panic!("min_dwell_ms must be an integer"),
+        None => quote! { None },
+    };
+    let safe_state = match extract_value_from_attr_args(&attr_args, "safe_state") {
+        Some(Lit::Str(state)) => {
+            if !states.iter().any(|s| *s == state.value()) {
+                panic!(
+                    "safe_state {} is not one of the states of the actor",
+                    state.value()
+                );
+            }
+            quote! { Some(#state.to_string()) }
+        }
+        Some(_) => panic!("safe_state must be a string"),
+        None => quote! { None },
+    };
 
     // Extract fields and their default values
     let fields = match &input.data {
@@ -81,10 +132,18 @@ pub fn actor(attr: TokenStream, item: TokenStream) -> TokenStream {
         })
         .collect();
 
+    // The fields of the actor, copied into another state or into its snapshot
     let field_copies: Vec<_> = fields
         .iter()
         .map(|(name, _, _)| {
-            quote! { #name: self.#name, }
+            quote! { #name: self.#name.clone(), }
+        })
+        .collect();
+
+    let field_restores: Vec<_> = fields
+        .iter()
+        .map(|(name, _, _)| {
+            quote! { #name: snapshot.fields.#name, }
         })
         .collect();
 
@@ -101,8 +160,7 @@ pub fn actor(attr: TokenStream, item: TokenStream) -> TokenStream {
             quote! {
                 let #name = params
                     .get(stringify!(#name))
-                    .and_then(|v| v.as_f64())
-                    .map(|v| v as #ty)
+                    .and_then(|v| serde_json::from_value::<#ty>(v.clone()).ok())
                     .unwrap_or(#default);
             }
         })
@@ -114,6 +172,11 @@ pub fn actor(attr: TokenStream, item: TokenStream) -> TokenStream {
         quote! { #slot_str }
     });
 
+    let event_literals = events.iter().map(|event| {
+        let event_str = event.as_str();
+        quote! { #event_str }
+    });
+
     // Generate the implementation
     let output = quote! {
         use ::digitaltwin_core::StateBehavior;
@@ -125,6 +188,10 @@ pub fn actor(attr: TokenStream, item: TokenStream) -> TokenStream {
             // Generic actor properties
             dispatch_map: ::digitaltwin_core::DispatchMap<#name<State>>,
             command_map: ::digitaltwin_core::CommandMap<#name<State>>,
+            /// Events emitted and not yet taken
+            pending_events: Vec<::digitaltwin_core::ActorEvent>,
+            /// Latest values of the slots of the twin
+            context: ::digitaltwin_core::ActorContext,
             _state: std::marker::PhantomData<State>,
         }
 
@@ -134,18 +201,78 @@ pub fn actor(attr: TokenStream, item: TokenStream) -> TokenStream {
             #name<State>: ::digitaltwin_core::ActorState,
         {
             /// Create a new actor instance
+            #[allow(clippy::too_many_arguments)]
             pub fn create(#(#fn_params),*) -> Box<::digitaltwin_core::ActorStateType> {
                 Box::new(#name {
                     #(#field_inits)*
                     dispatch_map: <#default_state>::create_dispatch_map(),
                     command_map: <#default_state>::create_command_map(),
+                    pending_events: Vec::new(),
+                    context: ::digitaltwin_core::ActorContext::default(),
                     _state: std::marker::PhantomData::<_>,
                 })
             }
 
             /// Define the actor's input slots
-            pub fn slots() -> Vec<&'static str> {
-                vec![#(#slot_literals),*]
+            pub fn slots() -> Vec<String> {
+                vec![#(#slot_literals.to_string()),*]
+            }
+
+            /// Define the events the actor can emit
+            pub fn event_names() -> Vec<String> {
+                vec![#(#event_literals.to_string()),*]
+            }
+
+            /// Limits on the transitions of the actor
+            pub fn declared_transition_limits() -> ::digitaltwin_core::TransitionLimits {
+                ::digitaltwin_core::TransitionLimits {
+                    max_per_minute: #max_per_minute,
+                    min_dwell_ms: #min_dwell_ms,
+                    safe_state: #safe_state,
+                }
+            }
+
+            /// Define the states of the actor
+            pub fn state_names() -> Vec<String> {
+                vec![#(<#states as ::digitaltwin_core::StateBehavior>::state_name()),*]
+            }
+
+            /// Transitions made by the handlers of all the states
+            pub fn declared_transitions() -> Vec<::digitaltwin_core::DeclaredTransition> {
+                let mut transitions = Vec::new();
+                #(
+                    transitions.extend(
+                        <#states as ::digitaltwin_core::StateBehavior>::transitions()
+                            .into_iter()
+                            .map(|(trigger, to)| ::digitaltwin_core::DeclaredTransition {
+                                from: <#states as ::digitaltwin_core::StateBehavior>::state_name(),
+                                to,
+                                trigger,
+                            }),
+                    );
+                )*
+                transitions
+            }
+
+            /// Emit an event, delivered after the next transition
+            #[allow(dead_code)]
+            fn emit(&mut self, event: &str, payload: serde_json::Value) {
+                debug_assert!(
+                    Self::event_names().iter().any(|e| e == event),
+                    "event {} not declared for {}",
+                    event,
+                    stringify!(#name)
+                );
+                self.pending_events.push(::digitaltwin_core::ActorEvent {
+                    name: event.to_string(),
+                    payload,
+                });
+            }
+
+            /// Context of the twin, with the latest values of its slots
+            #[allow(dead_code)]
+            fn context(&self) -> &::digitaltwin_core::ActorContext {
+                &self.context
             }
 
             /// Transition to another state
@@ -158,6 +285,69 @@ pub fn actor(attr: TokenStream, item: TokenStream) -> TokenStream {
                     #(#field_copies)*
                     dispatch_map: T::create_dispatch_map(),
                     command_map: T::create_command_map(),
+                    pending_events: self.pending_events.clone(),
+                    context: self.context.clone(),
+                    _state: std::marker::PhantomData::<_>,
+                })
+            }
+        }
+
+        /// Serializable copy of the actor-specific properties
+        #[derive(::serde::Serialize, ::serde::Deserialize)]
+        struct #fields_name {
+            #(#field_decls)*
+        }
+
+        /// Serializable snapshot of the actor (dispatch maps are rebuilt from the state name)
+        #[derive(::serde::Serialize, ::serde::Deserialize)]
+        struct #snapshot_name {
+            actor: String,
+            state: String,
+            fields: #fields_name,
+        }
+
+        impl<State> ::serde::Serialize for #name<State>
+        where
+            State: ::digitaltwin_core::StateBehavior,
+        {
+            fn serialize<S: ::serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                let snapshot = #snapshot_name {
+                    actor: stringify!(#name).to_string(),
+                    state: State::state_name(),
+                    fields: #fields_name {
+                        #(#field_copies)*
+                    },
+                };
+                ::serde::Serialize::serialize(&snapshot, serializer)
+            }
+        }
+
+        impl<'de, State> ::serde::Deserialize<'de> for #name<State>
+        where
+            State: ::digitaltwin_core::StateBehavior<Actor = #name<State>>,
+        {
+            fn deserialize<D: ::serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let snapshot = <#snapshot_name as ::serde::Deserialize>::deserialize(deserializer)?;
+                if snapshot.actor != stringify!(#name) {
+                    return Err(::serde::de::Error::custom(format!(
+                        "snapshot is for actor {}, not {}",
+                        snapshot.actor,
+                        stringify!(#name)
+                    )));
+                }
+                if snapshot.state != State::state_name() {
+                    return Err(::serde::de::Error::custom(format!(
+                        "snapshot is in state {}, not {}",
+                        snapshot.state,
+                        State::state_name()
+                    )));
+                }
+                Ok(#name {
+                    #(#field_restores)*
+                    dispatch_map: State::create_dispatch_map(),
+                    command_map: State::create_command_map(),
+                    pending_events: Vec::new(),
+                    context: ::digitaltwin_core::ActorContext::default(),
                     _state: std::marker::PhantomData::<_>,
                 })
             }
@@ -170,14 +360,14 @@ pub fn actor(attr: TokenStream, item: TokenStream) -> TokenStream {
         #vis struct #factory_name;
 
         impl ::digitaltwin_core::ActorFactory for #factory_name {
-            fn create_default() -> (Box<::digitaltwin_core::ActorStateType>, Vec<&'static str>) {
+            fn create_default() -> (Box<::digitaltwin_core::ActorStateType>, Vec<String>) {
                 (
                     #name::<#default_state>::create(#(#default_values),*),
                     #name::<#default_state>::slots(),
                 )
             }
 
-            fn create_with_params(params: serde_json::Value) -> (Box<::digitaltwin_core::ActorStateType>, Vec<&'static str>) {
+            fn create_with_params(params: serde_json::Value) -> (Box<::digitaltwin_core::ActorStateType>, Vec<String>) {
                 #(#param_extractions)*
 
                 (
@@ -185,6 +375,23 @@ pub fn actor(attr: TokenStream, item: TokenStream) -> TokenStream {
                     #name::<#default_state>::slots(),
                 )
             }
+
+            fn from_snapshot(snapshot: serde_json::Value) -> Result<(Box<::digitaltwin_core::ActorStateType>, Vec<String>), String> {
+                let state = snapshot
+                    .get("state")
+                    .and_then(|s| s.as_str())
+                    .ok_or_else(|| "missing state in snapshot".to_string())?
+                    .to_string();
+
+                let actor: Box<::digitaltwin_core::ActorStateType> =
+                    #(if state == <#states as ::digitaltwin_core::StateBehavior>::state_name() {
+                        Box::new(serde_json::from_value::<#name<#states>>(snapshot).map_err(|e| e.to_string())?)
+                    } else)* {
+                        return Err(format!("unknown state {} for actor {}", state, stringify!(#name)));
+                    };
+
+                Ok((actor, #name::<#default_state>::slots()))
+            }
         }
     };
 
@@ -235,16 +442,36 @@ pub fn actor_state(attr: TokenStream, item: TokenStream) -> TokenStream {
     // Extract handler maps from attributes
     let (dispatch_entries, command_entries) = extract_handler_maps(&input);
 
+    // Transitions of the handlers to other states
+    let transitions = dispatch_entries
+        .iter()
+        .chain(&command_entries)
+        .flat_map(|(trigger, handler)| {
+            transition_targets(&input, handler)
+                .into_iter()
+                .filter(|target| *target != state_ident)
+                .map(move |target| quote! { (#trigger.to_string(), stringify!(#target).to_string()) })
+        })
+        .collect::<Vec<_>>();
+
     // Clean up attribute macros from the input
     input
         .attrs
         .retain(|attr| !attr.path.is_ident("dispatch_map") && !attr.path.is_ident("command_map"));
 
-    // Generate dispatch map entries
+    // Generate dispatch map entries, converting the slot value to the handler's argument type
     let dispatch_entries = dispatch_entries.iter().map(|(slot, handler)| {
         let slot_str = slot.as_str();
         quote! {
-            map.insert(#slot_str, #actor_ident::<#state_ident>::#handler as fn(&Self::Actor, f32) -> Box<::digitaltwin_core::ActorStateType>);
+            map.insert(#slot_str, (|actor: &Self::Actor, value: ::digitaltwin_core::SlotValue| {
+                match ::digitaltwin_core::FromSlotValue::from_slot_value(value.clone()) {
+                    Some(value) => #actor_ident::<#state_ident>::#handler(actor, value),
+                    None => {
+                        ::log::warn!("Ignored value {:?} of slot {}: wrong type for {}", value, #slot_str, stringify!(#handler));
+                        Box::new(actor.clone())
+                    }
+                }
+            }) as fn(&Self::Actor, ::digitaltwin_core::SlotValue) -> Box<::digitaltwin_core::ActorStateType>);
         }
     });
 
@@ -278,6 +505,113 @@ pub fn actor_state(attr: TokenStream, item: TokenStream) -> TokenStream {
             fn state_name() -> String {
                 stringify!(#state_ident).to_string()
             }
+
+            fn transitions() -> Vec<(String, String)> {
+                vec![#(#transitions),*]
+            }
+        }
+    };
+
+    TokenStream::from(output)
+}
+
+// ========== ACTOR REGIONS ATTRIBUTE MACRO ==========
+
+/// The actor_regions attribute macro. Combines several actors into a single twin made of
+/// orthogonal regions, each one keeping its own state set and dispatch maps.
+/// A `<Name>Factory` is generated, creating a `RegionSet` from the regions' factories.
+///
+/// Example:
+/// ```ignore
+/// #[actor_regions(charging = "ChargingStationFactory", connectivity = "ConnectivityFactory")]
+/// pub struct ChargingPoint;
+/// ```
+#[proc_macro_attribute]
+pub fn actor_regions(attr: TokenStream, item: TokenStream) -> TokenStream {
+    // Parse the attribute arguments
+    let attr_args = parse_macro_input!(attr as AttributeArgs);
+
+    // Parse the input struct
+    let input = parse_macro_input!(item as DeriveInput);
+    let name = &input.ident;
+    let vis = &input.vis;
+    let factory_name = format_ident!("{}Factory", name);
+
+    // Extract (region name, factory path) pairs from attributes
+    let regions = extract_regions_from_attr_args(&attr_args);
+    if regions.is_empty() {
+        panic!("No regions found for actor_regions");
+    }
+    let region_names: Vec<_> = regions.iter().map(|(region, _)| region.as_str()).collect();
+    let region_factories: Vec<_> = regions.iter().map(|(_, factory)| factory).collect();
+
+    let output = quote! {
+        // The struct only names the composite actor, it is never constructed
+        #[allow(dead_code)]
+        #input
+
+        #vis struct #factory_name;
+
+        impl #factory_name {
+            /// Merge the slots of all regions, without duplicates
+            fn merge_slots(slots: Vec<Vec<String>>) -> Vec<String> {
+                let mut merged = Vec::new();
+                for slot in slots.into_iter().flatten() {
+                    if !merged.contains(&slot) {
+                        merged.push(slot);
+                    }
+                }
+                merged
+            }
+        }
+
+        impl ::digitaltwin_core::ActorFactory for #factory_name {
+            fn create_default() -> (Box<::digitaltwin_core::ActorStateType>, Vec<String>) {
+                let mut regions = Vec::new();
+                let mut slots = Vec::new();
+                #(
+                    let (actor, region_slots) = <#region_factories as ::digitaltwin_core::ActorFactory>::create_default();
+                    regions.push((#region_names, actor));
+                    slots.push(region_slots);
+                )*
+                (
+                    Box::new(::digitaltwin_core::RegionSet::new(stringify!(#name), regions)),
+                    Self::merge_slots(slots),
+                )
+            }
+
+            fn create_with_params(params: serde_json::Value) -> (Box<::digitaltwin_core::ActorStateType>, Vec<String>) {
+                let mut regions = Vec::new();
+                let mut slots = Vec::new();
+                #(
+                    let (actor, region_slots) = <#region_factories as ::digitaltwin_core::ActorFactory>::create_with_params(params.clone());
+                    regions.push((#region_names, actor));
+                    slots.push(region_slots);
+                )*
+                (
+                    Box::new(::digitaltwin_core::RegionSet::new(stringify!(#name), regions)),
+                    Self::merge_slots(slots),
+                )
+            }
+
+            fn from_snapshot(snapshot: serde_json::Value) -> Result<(Box<::digitaltwin_core::ActorStateType>, Vec<String>), String> {
+                let mut regions = Vec::new();
+                let mut slots = Vec::new();
+                #(
+                    let region_snapshot = snapshot
+                        .get("regions")
+                        .and_then(|r| r.get(#region_names))
+                        .cloned()
+                        .ok_or_else(|| format!("missing region {} in snapshot", #region_names))?;
+                    let (actor, region_slots) = <#region_factories as ::digitaltwin_core::ActorFactory>::from_snapshot(region_snapshot)?;
+                    regions.push((#region_names, actor));
+                    slots.push(region_slots);
+                )*
+                Ok((
+                    Box::new(::digitaltwin_core::RegionSet::new(stringify!(#name), regions)),
+                    Self::merge_slots(slots),
+                ))
+            }
         }
     };
 
@@ -294,7 +628,7 @@ pub fn impl_actor_state(input: TokenStream) -> TokenStream {
         where
             S: ::digitaltwin_core::StateBehavior + Clone + Send + Sync + 'static,
         {
-            fn input_change(&self, slot: &str, value: f32) -> Box<::digitaltwin_core::ActorStateType> {
+            fn input_value(&self, slot: &str, value: ::digitaltwin_core::SlotValue) -> Box<::digitaltwin_core::ActorStateType> {
                 match self.dispatch_map.get(slot) {
                     Some(func) => func(self, value),
                     // TODO: notify error
@@ -314,6 +648,47 @@ pub fn impl_actor_state(input: TokenStream) -> TokenStream {
                 S::state_name()
             }
 
+            fn events(&self) -> Vec<String> {
+                Self::event_names()
+            }
+
+            fn commands(&self) -> Vec<String> {
+                let mut commands: Vec<String> = self.command_map.keys().map(|c| c.to_string()).collect();
+                commands.sort();
+                commands
+            }
+
+            fn inputs(&self) -> Vec<String> {
+                let mut inputs: Vec<String> = self.dispatch_map.keys().map(|s| s.to_string()).collect();
+                inputs.sort();
+                inputs
+            }
+
+            fn take_events(&mut self) -> Vec<::digitaltwin_core::ActorEvent> {
+                ::std::mem::take(&mut self.pending_events)
+            }
+
+            fn transition_limits(&self) -> ::digitaltwin_core::TransitionLimits {
+                Self::declared_transition_limits()
+            }
+
+            fn set_context(&mut self, context: ::digitaltwin_core::ActorContext) {
+                self.context = context;
+            }
+
+            fn state_machines(&self) -> Vec<::digitaltwin_core::StateMachine> {
+                vec![::digitaltwin_core::StateMachine {
+                    region: None,
+                    states: Self::state_names(),
+                    transitions: Self::declared_transitions(),
+                    current: S::state_name(),
+                }]
+            }
+
+            fn to_snapshot(&self) -> Result<::serde_json::Value, String> {
+                ::serde_json::to_value(self).map_err(|e| e.to_string())
+            }
+
             fn type_name(&self) -> String {
                 stringify!(#input).to_string()
             }
@@ -327,6 +702,175 @@ pub fn impl_actor_state(input: TokenStream) -> TokenStream {
     TokenStream::from(output)
 }
 
+// ========== AAS CONSTANTS MACRO ==========
+
+/// Generate constants from AAS files (YAML, JSON or XML, by extension), so that tests and
+/// simulators naming slots, sensors, operations and events fail to compile when the twin
+/// definitions change. Paths are relative to the manifest of the crate using the macro.
+///
+/// Each AAS gives a module named after its id_short, in snake case:
+/// ```ignore
+/// aas_constants!("../twins/charger.yaml");
+///
+/// home_charging_station::ASSET_ID; // "urn:aas:smart-home:charging-station:ac-level2:id-000001"
+/// home_charging_station::TWIN_TYPE; // "ChargingPoint"
+/// home_charging_station::slots::INPUT_CURRENT; // "InputCurrent"
+/// home_charging_station::sensors::INPUT_CURRENT; // sensor ID bound to the slot
+/// home_charging_station::operations::VEHICLE_DETECTED; // "VehicleDetected"
+/// home_charging_station::events::OVERCURRENT_FAULT; // "OvercurrentFault"
+/// ```
+#[proc_macro]
+pub fn aas_constants(input: TokenStream) -> TokenStream {
+    let paths = parse_macro_input!(
+        input with syn::punctuated::Punctuated::<syn::LitStr, syn::Token![,]>::parse_terminated
+    );
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap_or_default();
+    let modules = paths.iter().map(|path| {
+        let full_path = std::path::Path::new(&manifest_dir).join(path.value());
+        match load_shell(&full_path) {
+            Ok(aas) => shell_constants(&aas, &full_path.to_string_lossy()),
+            Err(e) => {
+                syn::Error::new(path.span(), format!("{}: {e}", full_path.display())).to_compile_error()
+            }
+        }
+    });
+    TokenStream::from(quote! { #(#modules)* })
+}
+
+fn load_shell(path: &std::path::Path) -> Result<digitaltwin_core::AssetAdministrationShell, String> {
+    use digitaltwin_core::AssetAdministrationShell;
+    let file = std::fs::File::open(path).map_err(|e| e.to_string())?;
+    let reader = std::io::BufReader::new(file);
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("yaml") | Some("yml") => AssetAdministrationShell::from_reader(reader),
+        Some("json") => AssetAdministrationShell::from_json_reader(reader),
+        Some("xml") => AssetAdministrationShell::from_xml_reader(reader),
+        _ => Err("unknown AAS format, expected .yaml, .json or .xml".to_string()),
+    }
+}
+
+/// The module of constants of an AAS
+fn shell_constants(aas: &digitaltwin_core::AssetAdministrationShell, path: &str) -> proc_macro2::TokenStream {
+    use digitaltwin_core::SubmodelElement;
+
+    let module = format_ident!("{}", identifier(&aas.id_short, false));
+    let asset_id = aas.id.as_str();
+    let twin_type = aas
+        .twin_type()
+        .map(|twin_type| quote! { pub const TWIN_TYPE: &str = #twin_type; });
+
+    // Slots are the collections of PowerAndElectrical with a DataSource, as bound by the twins
+    let mut slots = Vec::new();
+    let mut sensors = Vec::new();
+    for submodel in aas
+        .submodels
+        .iter()
+        .filter(|s| s.id_short == "PowerAndElectrical")
+    {
+        for elem in &submodel.elements {
+            let SubmodelElement::Collection(collection) = elem else {
+                continue;
+            };
+            let data_source = collection.value.iter().find_map(|elem| match elem {
+                SubmodelElement::ReferenceElement(r) if r.id_short == "DataSource" => Some(&r.value),
+                _ => None,
+            });
+            let Some(data_source) = data_source else {
+                continue;
+            };
+            slots.push(collection.id_short.clone());
+            if let Some(sensor) = aas.resolve_sensor_reference(data_source) {
+                sensors.push((collection.id_short.clone(), sensor));
+            }
+        }
+    }
+    let operations: Vec<String> = aas.operations().map(|op| op.id_short.clone()).collect();
+    let events: Vec<String> = aas.events().iter().map(|e| e.id_short.clone()).collect();
+
+    let names = |names: &[String]| -> proc_macro2::TokenStream {
+        let consts = names.iter().map(|name| {
+            let ident = format_ident!("{}", identifier(name, true));
+            quote! { pub const #ident: &str = #name; }
+        });
+        quote! {
+            #(#consts)*
+            /// All of them, in the order of the AAS
+            pub const ALL: &[&str] = &[#(#names),*];
+        }
+    };
+    let (slots, operations, events) = (names(&slots), names(&operations), names(&events));
+    let sensors = sensors.iter().map(|(slot, sensor)| {
+        let ident = format_ident!("{}", identifier(slot, true));
+        quote! { pub const #ident: &str = #sensor; }
+    });
+
+    quote! {
+        #[allow(dead_code)]
+        pub mod #module {
+            // Rebuild when the AAS changes
+            const _: &[u8] = include_bytes!(#path);
+
+            pub const ASSET_ID: &str = #asset_id;
+            #twin_type
+
+            /// Names of the input slots
+            pub mod slots {
+                #slots
+            }
+
+            /// IDs of the sensors bound to the slots, by slot
+            pub mod sensors {
+                #(#sensors)*
+            }
+
+            /// Names of the operations
+            pub mod operations {
+                #operations
+            }
+
+            /// Names of the events
+            pub mod events {
+                #events
+            }
+        }
+    }
+}
+
+/// A Rust identifier from an id_short: "HomeChargingStation" becomes
+/// "home_charging_station", or "HOME_CHARGING_STATION" for constants
+fn identifier(id_short: &str, upper: bool) -> String {
+    let chars: Vec<char> = id_short.chars().collect();
+    let mut ident = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        if !c.is_ascii_alphanumeric() {
+            if !ident.is_empty() && !ident.ends_with('_') {
+                ident.push('_');
+            }
+            continue;
+        }
+        // A word starts at an uppercase letter after a lowercase one or a digit, or
+        // before a lowercase one at the end of an acronym ("HVACPower")
+        let starts_word = c.is_ascii_uppercase()
+            && i > 0
+            && (chars[i - 1].is_ascii_lowercase()
+                || chars[i - 1].is_ascii_digit()
+                || (chars[i - 1].is_ascii_uppercase()
+                    && chars.get(i + 1).is_some_and(|n| n.is_ascii_lowercase())));
+        if starts_word && !ident.ends_with('_') {
+            ident.push('_');
+        }
+        ident.push(if upper {
+            c.to_ascii_uppercase()
+        } else {
+            c.to_ascii_lowercase()
+        });
+    }
+    if ident.is_empty() || ident.starts_with(|c: char| c.is_ascii_digit()) {
+        ident.insert(0, '_');
+    }
+    ident
+}
+
 // ========== HELPER FUNCTIONS ==========
 
 /// Extract the default state from attribute arguments
@@ -343,26 +887,51 @@ fn extract_default_state_from_attr_args(args: &[NestedMeta]) -> Option<syn::Iden
     None
 }
 
-/// Extract slots from attribute arguments
-fn extract_slots_from_attr_args(args: &[NestedMeta]) -> Vec<String> {
+/// Extract the literal of a named argument (e.g. `min_dwell_ms = 500`) from attribute arguments
+fn extract_value_from_attr_args(args: &[NestedMeta], name: &str) -> Option<Lit> {
+    args.iter().find_map(|arg| match arg {
+        NestedMeta::Meta(Meta::NameValue(name_value)) if name_value.path.is_ident(name) => {
+            Some(name_value.lit.clone())
+        }
+        _ => None,
+    })
+}
+
+/// Extract a list of string literals (e.g. `slots("A", "B")`) from attribute arguments
+fn extract_list_from_attr_args(args: &[NestedMeta], list_name: &str) -> Vec<String> {
     for arg in args {
         if let NestedMeta::Meta(Meta::List(list)) = arg {
-            if list.path.is_ident("slots") {
+            if list.path.is_ident(list_name) {
                 // Extract elements from the list
-                let mut slots = Vec::new();
+                let mut items = Vec::new();
                 for nested in &list.nested {
                     if let NestedMeta::Lit(Lit::Str(lit_str)) = nested {
-                        slots.push(lit_str.value());
+                        items.push(lit_str.value());
                     }
                 }
-                return slots;
+                return items;
+            }
+        }
+    }
+    Vec::new() // Empty list if none provided
+}
+
+/// Extract (region name, factory path) pairs from attribute arguments
+fn extract_regions_from_attr_args(args: &[NestedMeta]) -> Vec<(String, syn::Path)> {
+    let mut regions = Vec::new();
+    for arg in args {
+        if let NestedMeta::Meta(Meta::NameValue(name_value)) = arg {
+            if let (Some(region), Lit::Str(lit_str)) = (name_value.path.get_ident(), &name_value.lit) {
+                let factory = syn::parse_str::<syn::Path>(&lit_str.value()).expect("Invalid factory path");
+                regions.push((region.to_string(), factory));
             }
         }
     }
-    Vec::new() // Empty slots if none provided
+    regions
 }
 
 /// Extract default value from field attributes
+#[allow(clippy::collapsible_match)]
 fn extract_default_value(attrs: &[syn::Attribute]) -> proc_macro2::TokenStream {
     for attr in attrs {
         if attr.path.is_ident("actor_attr") {
@@ -391,7 +960,79 @@ fn extract_default_value(attrs: &[syn::Attribute]) -> proc_macro2::TokenStream {
     }
 }
 
+/// States a handler transitions to, from the `transition::<State>()` calls in its body and
+/// in the methods of the same impl block it calls on `self`. The transitions made by the
+/// helpers of other impl blocks (e.g., shared by all the states) are not found.
+fn transition_targets(item_impl: &ItemImpl, handler: &syn::Ident) -> Vec<syn::Ident> {
+    let mut targets = Vec::new();
+    let mut visited = vec![handler.clone()];
+    let mut pending = vec![handler.clone()];
+    while let Some(method) = pending.pop() {
+        let body = item_impl.items.iter().find_map(|item| match item {
+            syn::ImplItem::Method(m) if m.sig.ident == method => Some(m.block.to_token_stream()),
+            _ => None,
+        });
+        let Some(body) = body else { continue };
+        collect_transitions(body.clone(), &mut targets);
+        let mut calls = Vec::new();
+        collect_self_calls(body, &mut calls);
+        for call in calls {
+            if !visited.contains(&call) {
+                visited.push(call.clone());
+                pending.push(call);
+            }
+        }
+    }
+    targets
+}
+
+/// Methods called on `self` (`self.method(...)`)
+fn collect_self_calls(tokens: proc_macro2::TokenStream, calls: &mut Vec<syn::Ident>) {
+    use proc_macro2::{Delimiter, TokenTree};
+
+    let tokens: Vec<TokenTree> = tokens.into_iter().collect();
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            TokenTree::Group(group) => collect_self_calls(group.stream(), calls),
+            TokenTree::Ident(ident) if ident == "self" => {
+                if let [TokenTree::Punct(dot), TokenTree::Ident(method), TokenTree::Group(args), ..] =
+                    &tokens[i + 1..]
+                {
+                    if dot.as_char() == '.' && args.delimiter() == Delimiter::Parenthesis {
+                        calls.push(method.clone());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn collect_transitions(tokens: proc_macro2::TokenStream, targets: &mut Vec<syn::Ident>) {
+    use proc_macro2::TokenTree;
+
+    let tokens: Vec<TokenTree> = tokens.into_iter().collect();
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            TokenTree::Group(group) => collect_transitions(group.stream(), targets),
+            TokenTree::Ident(ident) if ident == "transition" => {
+                // transition::<State>
+                if let [TokenTree::Punct(colon1), TokenTree::Punct(colon2), TokenTree::Punct(lt), TokenTree::Ident(state), TokenTree::Punct(gt), ..] =
+                    &tokens[i + 1..]
+                {
+                    let punct: String = [colon1, colon2, lt, gt].iter().map(|p| p.as_char()).collect();
+                    if punct == "::<>" && !targets.contains(state) {
+                        targets.push(state.clone());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
 /// Extract handler maps from attributed impl blocks
+#[allow(clippy::type_complexity)]
 fn extract_handler_maps(item_impl: &ItemImpl) -> (Vec<(String, syn::Ident)>, Vec<(String, syn::Ident)>) {
     let mut dispatch_entries = Vec::new();
     let mut command_entries = Vec::new();
@@ -0,0 +1,413 @@
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+use crate::failover::{self, SharedFailover};
+use crate::http_client;
+use crate::network_receiver::TwinEvent;
+use crate::secrets::SecretsProvider;
+use crate::twin_runner::ActorMessage;
+use digitaltwin_core::{Actuation, AssetID, HttpActuation};
+
+/// Event published, and command executed by the actors handling it, when the actuation
+/// breaker of a twin opens
+pub const ACTUATION_UNAVAILABLE: &str = "ActuationUnavailable";
+
+#[derive(Parser, Clone)]
+pub struct ActuationOptions {
+    /// attempts after the first one, when the actuation of a device fails
+    #[clap(long, default_value_t = 2, env = "ACTUATION_RETRIES")]
+    actuation_retries: u32,
+
+    /// delay before the first retry of an actuation, doubled at each attempt (milliseconds)
+    #[clap(long, default_value_t = 500, env = "ACTUATION_BACKOFF_MS")]
+    actuation_backoff_ms: u64,
+
+    /// failed calls in a row after which the actuation of a twin is suspended (0 = never)
+    #[clap(long, default_value_t = 5, env = "ACTUATION_BREAKER_FAILURES")]
+    actuation_breaker_failures: u32,
+
+    /// seconds the actuation of a twin stays suspended, before a single call probes whether
+    /// the device API is back
+    #[clap(long, default_value_t = 60, env = "ACTUATION_BREAKER_COOLDOWN")]
+    actuation_breaker_cooldown: u64,
+}
+
+/// State of the circuit breaker of the actuation of a twin
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    /// The calls go through
+    #[default]
+    Closed,
+    /// The calls are dropped until the end of the cooldown
+    Open,
+    /// A single call probes the device API, the others are dropped
+    HalfOpen,
+}
+
+/// Circuit breaker of the actuation of a twin, shown in its report and by the REST API
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BreakerStatus {
+    pub state: BreakerState,
+    /// Failed calls since the last successful one
+    pub consecutive_failures: u32,
+    /// Times the breaker opened
+    pub trips: u64,
+    /// Actuations dropped while the breaker was open
+    pub rejected: u64,
+    /// End of the cooldown, while the breaker is open
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_at: Option<DateTime<Utc>>,
+}
+
+/// Sent to a twin when its actuation breaker opens
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BreakerTrip {
+    /// Event whose actuation failed last
+    pub event: String,
+    /// Failed calls in a row
+    pub failures: u32,
+    /// End of the cooldown
+    pub retry_at: DateTime<Utc>,
+}
+
+/// Circuit breaker of the actuation of a twin
+#[derive(Debug, Default)]
+struct CircuitBreaker {
+    status: BreakerStatus,
+    /// End of the cooldown, while open
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    /// Whether a call can be made; after the cooldown, the first call is let through as a probe
+    fn allow(&mut self, now: Instant) -> bool {
+        match self.status.state {
+            BreakerState::Closed => true,
+            BreakerState::Open if self.open_until.is_some_and(|until| now >= until) => {
+                self.status.state = BreakerState::HalfOpen;
+                self.status.retry_at = None;
+                true
+            }
+            BreakerState::Open | BreakerState::HalfOpen => {
+                self.status.rejected += 1;
+                false
+            }
+        }
+    }
+
+    /// Record the outcome of a call, returning whether the breaker opened from closed
+    fn record(&mut self, success: bool, threshold: u32, cooldown: Duration, now: Instant) -> bool {
+        if success {
+            self.status.state = BreakerState::Closed;
+            self.status.consecutive_failures = 0;
+            self.status.retry_at = None;
+            self.open_until = None;
+            return false;
+        }
+        self.status.consecutive_failures += 1;
+        let opens = match self.status.state {
+            BreakerState::Closed => threshold > 0 && self.status.consecutive_failures >= threshold,
+            // A failed probe opens the breaker again
+            BreakerState::HalfOpen => true,
+            BreakerState::Open => false,
+        };
+        if opens {
+            self.open_until = Some(now + cooldown);
+            self.status.retry_at = Some(Utc::now() + cooldown);
+            let tripped = self.status.state == BreakerState::Closed;
+            if tripped {
+                self.status.trips += 1;
+            }
+            self.status.state = BreakerState::Open;
+            return tripped;
+        }
+        false
+    }
+}
+
+/// Replace the placeholders of a URL template with the asset ID, the event name and the
+/// fields of the payload. Fails on a placeholder with no value.
+pub fn render_url(template: &str, asset_id: &AssetID, event: &TwinEvent) -> Result<String, String> {
+    let mut url = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("unclosed placeholder in {template}"))?;
+        let name = &rest[start + 1..start + end];
+        url.push_str(&rest[..start]);
+        match (name, event.payload.get(name)) {
+            ("asset_id", _) => url.push_str(asset_id),
+            ("event", _) => url.push_str(&event.event),
+            (_, Some(serde_json::Value::String(value))) => url.push_str(value),
+            (_, Some(value)) if !value.is_null() => url.push_str(&value.to_string()),
+            _ => return Err(format!("no value for {{{name}}} in {template}")),
+        }
+        rest = &rest[start + end + 1..];
+    }
+    url.push_str(rest);
+    Ok(url)
+}
+
+/// Delivers the events of the twins to the devices through the backends declared in the
+/// AAS, for the devices not controlled over MQTT
+pub struct Actuators {
+    /// Credentials of the device APIs, named in the AAS
+    secrets: Arc<SecretsProvider>,
+    retries: u32,
+    backoff: Duration,
+    /// Failed calls in a row opening a breaker (0 = never)
+    breaker_failures: u32,
+    breaker_cooldown: Duration,
+    /// Circuit breaker of each twin
+    breakers: Mutex<HashMap<AssetID, CircuitBreaker>>,
+    /// A standby instance does not actuate
+    failover: SharedFailover,
+}
+
+impl Actuators {
+    pub fn new(
+        options: &ActuationOptions,
+        secrets: Arc<SecretsProvider>,
+        failover: SharedFailover,
+    ) -> Arc<Self> {
+        Arc::new(Actuators {
+            secrets,
+            retries: options.actuation_retries,
+            backoff: Duration::from_millis(options.actuation_backoff_ms),
+            breaker_failures: options.actuation_breaker_failures,
+            breaker_cooldown: Duration::from_secs(options.actuation_breaker_cooldown.max(1)),
+            breakers: Mutex::new(HashMap::new()),
+            failover,
+        })
+    }
+
+    /// Delay before the given retry (1 for the first one)
+    fn backoff(&self, retry: u32) -> Duration {
+        self.backoff
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+    }
+
+    /// Status of the breaker of a twin, once it actuated a device
+    pub fn breaker(&self, asset_id: &AssetID) -> Option<BreakerStatus> {
+        let breakers = self.breakers.lock().unwrap_or_else(|e| e.into_inner());
+        breakers.get(asset_id).map(|breaker| breaker.status.clone())
+    }
+
+    /// Status of the breakers of the twins
+    pub fn breakers(&self) -> BTreeMap<AssetID, BreakerStatus> {
+        let breakers = self.breakers.lock().unwrap_or_else(|e| e.into_inner());
+        breakers
+            .iter()
+            .map(|(asset_id, breaker)| (asset_id.clone(), breaker.status.clone()))
+            .collect()
+    }
+
+    fn allow(&self, asset_id: &AssetID) -> bool {
+        let mut breakers = self.breakers.lock().unwrap_or_else(|e| e.into_inner());
+        breakers
+            .entry(asset_id.clone())
+            .or_default()
+            .allow(Instant::now())
+    }
+
+    /// Record the outcome of a call, returning the trip if the breaker opened
+    fn record(&self, asset_id: &AssetID, success: bool, event: &str) -> Option<BreakerTrip> {
+        let mut breakers = self.breakers.lock().unwrap_or_else(|e| e.into_inner());
+        let breaker = breakers.entry(asset_id.clone()).or_default();
+        let opened = breaker.record(
+            success,
+            self.breaker_failures,
+            self.breaker_cooldown,
+            Instant::now(),
+        );
+        opened.then(|| BreakerTrip {
+            event: event.to_string(),
+            failures: breaker.status.consecutive_failures,
+            retry_at: breaker.status.retry_at.unwrap_or_else(Utc::now),
+        })
+    }
+
+    /// Deliver an event through its backend, in the background. The twin is notified on
+    /// its channel if its breaker opens.
+    pub fn actuate(
+        self: &Arc<Self>,
+        asset_id: &AssetID,
+        actuation: &Actuation,
+        event: &TwinEvent,
+        twin_ch: mpsc::Sender<ActorMessage>,
+    ) {
+        if !failover::is_active(&self.failover) {
+            return;
+        }
+        match actuation {
+            Actuation::Http(http) => {
+                let url = match render_url(&http.url, asset_id, event) {
+                    Ok(url) => url,
+                    Err(e) => {
+                        warn!("{asset_id} Event {} not actuated: {e}", event.event);
+                        return;
+                    }
+                };
+                let authorization = match &http.auth_secret {
+                    Some(name) => match self.secrets.get(name) {
+                        Some(secret) => Some(format!("{} {secret}", http.auth_scheme)),
+                        None => {
+                            warn!("{asset_id} Event {} not actuated: no {name} secret", event.event);
+                            return;
+                        }
+                    },
+                    None => None,
+                };
+                let body = event.payload.to_string();
+                let (actuators, http) = (self.clone(), http.clone());
+                let (correlation_id, event) = (event.correlation_id.to_string(), event.event.clone());
+                let asset_id = asset_id.clone();
+                tokio::spawn(async move {
+                    let mut headers = vec![("X-Correlation-ID", correlation_id.as_str())];
+                    if let Some(authorization) = &authorization {
+                        headers.push(("Authorization", authorization));
+                    }
+                    let delivery = actuators.call(&asset_id, &event, &http, &url, &headers, &body);
+                    if let Some(trip) = delivery.await {
+                        warn!(
+                            "{asset_id} Actuation suspended after {} failed calls, until {}",
+                            trip.failures, trip.retry_at
+                        );
+                        let _ = twin_ch.send(ActorMessage::ActuationUnavailable(trip)).await;
+                    }
+                });
+            }
+        }
+    }
+
+    /// Call a device API, retrying with exponential backoff while the breaker of the twin
+    /// lets the calls through. Returns the trip if the breaker opened.
+    async fn call(
+        &self,
+        asset_id: &AssetID,
+        event: &str,
+        http: &HttpActuation,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: &str,
+    ) -> Option<BreakerTrip> {
+        let mut retry = 0;
+        loop {
+            if !self.allow(asset_id) {
+                debug!("{asset_id} Event {event} not actuated, the breaker is open");
+                return None;
+            }
+            let result = http_client::send_json(&http.method, url, headers, body).await;
+            let probe = self.breaker(asset_id).map(|status| status.state) == Some(BreakerState::HalfOpen);
+            if let Some(trip) = self.record(asset_id, result.is_ok(), event) {
+                return Some(trip);
+            }
+            match result {
+                Ok(_) => {
+                    if probe {
+                        info!("{asset_id} Actuation resumed, {} {url} answered", http.method);
+                    }
+                    debug!("Event {event} actuated with {} {url}", http.method);
+                    return None;
+                }
+                Err(e) if retry < self.retries => {
+                    retry += 1;
+                    debug!(
+                        "Actuation of {event} with {} {url} failed ({e}), retry {retry} of {}",
+                        http.method, self.retries
+                    );
+                    tokio::time::sleep(self.backoff(retry)).await;
+                }
+                Err(e) => {
+                    warn!(
+                        "Actuation of {event} with {} {url} failed after {} attempts: {e}",
+                        http.method,
+                        retry + 1
+                    );
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use digitaltwin_core::CorrelationID;
+
+    #[test]
+    fn test_render_url() {
+        let asset_id = AssetID::from("urn:aas:charger:id-1");
+        let event = TwinEvent {
+            event: "ChargingCurrentRequested".to_string(),
+            timestamp: Utc::now(),
+            payload: serde_json::json!({ "current": 10.0, "mode": "eco", "phase": null }),
+            correlation_id: CorrelationID::from("c-1".to_string()),
+        };
+        assert_eq!(
+            render_url(
+                "http://chargers.local/{asset_id}/current?amps={current}&mode={mode}",
+                &asset_id,
+                &event
+            )
+            .unwrap(),
+            "http://chargers.local/urn:aas:charger:id-1/current?amps=10.0&mode=eco"
+        );
+        assert_eq!(
+            render_url("http://chargers.local/events/{event}", &asset_id, &event).unwrap(),
+            "http://chargers.local/events/ChargingCurrentRequested"
+        );
+        assert!(render_url("http://chargers.local/{phase}", &asset_id, &event).is_err());
+        assert!(render_url("http://chargers.local/{voltage}", &asset_id, &event).is_err());
+        assert!(render_url("http://chargers.local/{asset_id", &asset_id, &event).is_err());
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let (cooldown, now) = (Duration::from_secs(60), Instant::now());
+        let mut breaker = CircuitBreaker::default();
+        assert!(breaker.allow(now));
+        assert!(!breaker.record(false, 2, cooldown, now));
+        // A success resets the failures
+        assert!(!breaker.record(true, 2, cooldown, now));
+        assert!(!breaker.record(false, 2, cooldown, now));
+        assert!(breaker.record(false, 2, cooldown, now));
+        assert_eq!(breaker.status.state, BreakerState::Open);
+        assert_eq!(breaker.status.trips, 1);
+        assert!(breaker.status.retry_at.is_some());
+        assert!(!breaker.allow(now + Duration::from_secs(30)));
+        assert_eq!(breaker.status.rejected, 1);
+
+        // After the cooldown a single probe goes through; its failure opens the breaker again
+        let later = now + cooldown;
+        assert!(breaker.allow(later));
+        assert_eq!(breaker.status.state, BreakerState::HalfOpen);
+        assert!(!breaker.allow(later));
+        assert!(!breaker.record(false, 2, cooldown, later));
+        assert_eq!(breaker.status.state, BreakerState::Open);
+        assert_eq!(breaker.status.trips, 1);
+
+        let later = later + cooldown;
+        assert!(breaker.allow(later));
+        assert!(!breaker.record(true, 2, cooldown, later));
+        assert_eq!(breaker.status.state, BreakerState::Closed);
+        assert_eq!(breaker.status.consecutive_failures, 0);
+
+        // A threshold of 0 never opens the breaker
+        let mut breaker = CircuitBreaker::default();
+        for _ in 0..10 {
+            assert!(!breaker.record(false, 0, cooldown, now));
+        }
+        assert!(breaker.allow(now));
+    }
+}
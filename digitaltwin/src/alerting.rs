@@ -0,0 +1,702 @@
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use thiserror::Error as ThisError;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::event_bus::{self, BusEvent, EventBus};
+use crate::failover::{self, SharedFailover};
+use crate::http_client::{self, HttpError};
+use crate::manager::{ManagerMessage, Query};
+use crate::tls;
+use crate::twin_runner::TwinReport;
+use digitaltwin_core::AssetID;
+
+/// Maximum time for an SMTP exchange
+const SMTP_TIMEOUT: Duration = Duration::from_secs(10);
+/// Delay of the evaluation following a transition, gathering the ones close together
+const TRANSITION_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Parser, Clone)]
+pub struct AlertingOptions {
+    /// alert rule, as "<name>=state:<state>" (a twin, or one of its regions, is in the state,
+    /// e.g. "fault=state:Fault") or "<name>=stale:<seconds>" (no input received for this time,
+    /// e.g. "stale=stale:3600"); separate rules with ';' in ALERT_RULES
+    #[clap(long = "alert-rule", value_delimiter = ';', env = "ALERT_RULES")]
+    alert_rules: Vec<AlertRule>,
+
+    /// webhook receiving the alerts and their resolution as JSON (http:// or https://), e.g.
+    /// an adapter to Slack or PagerDuty
+    #[clap(long, env = "ALERT_WEBHOOK")]
+    alert_webhook: Option<String>,
+
+    /// SMTP relay sending the alerts by email, as "<host>:<port>" (plain text, port 25 by
+    /// default), "smtps://<host>:<port>" (TLS, port 465 by default) or
+    /// "smtp+starttls://<host>:<port>" (upgraded to TLS with STARTTLS, port 587 by default).
+    /// The certificate of the relay is verified against the ones of the system. No
+    /// authentication: relays requiring it need a local relay forwarding the emails.
+    #[clap(long, env = "ALERT_SMTP")]
+    alert_smtp: Option<SmtpRelay>,
+
+    /// sender of the alert emails
+    #[clap(long, default_value = "digitaltwin@localhost", env = "ALERT_EMAIL_FROM")]
+    alert_email_from: String,
+
+    /// recipients of the alert emails (separate recipients with ',')
+    #[clap(long, value_delimiter = ',', env = "ALERT_EMAIL_TO")]
+    alert_email_to: Vec<String>,
+
+    /// seconds between two evaluations of the rules
+    #[clap(long, default_value_t = 30, env = "ALERT_INTERVAL")]
+    alert_interval: u64,
+}
+
+/// Condition of an alert rule, checked on the report of each twin
+#[derive(Debug, Clone, PartialEq)]
+pub enum AlertCondition {
+    /// The twin, or one of its regions, is in the state
+    State(String),
+    /// No input received for the given time
+    Stale(Duration),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlertRule {
+    pub name: String,
+    pub condition: AlertCondition,
+}
+
+impl FromStr for AlertRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("expected <name>=state:<state> or <name>=stale:<seconds>: {s}");
+        let (name, condition) = s.split_once('=').ok_or_else(invalid)?;
+        let condition = match condition.trim().split_once(':').ok_or_else(invalid)? {
+            ("state", state) if !state.trim().is_empty() => AlertCondition::State(state.trim().to_string()),
+            ("stale", seconds) => AlertCondition::Stale(Duration::from_secs(
+                seconds.trim().parse().map_err(|_| invalid())?,
+            )),
+            _ => return Err(invalid()),
+        };
+        Ok(AlertRule {
+            name: name.trim().to_string(),
+            condition,
+        })
+    }
+}
+
+/// Security of the connection to the SMTP relay
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SmtpSecurity {
+    Plain,
+    /// TLS from the start of the connection
+    Tls,
+    /// Plain text upgraded to TLS before sending the email
+    StartTls,
+}
+
+/// SMTP relay sending the alert emails
+#[derive(Debug, Clone, PartialEq)]
+pub struct SmtpRelay {
+    pub host: String,
+    pub port: u16,
+    pub security: SmtpSecurity,
+}
+
+impl FromStr for SmtpRelay {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid =
+            || format!("expected <host>:<port>, smtps://<host>:<port> or smtp+starttls://<host>:<port>: {s}");
+        let s = s.trim();
+        let (security, address) = if let Some(address) = s.strip_prefix("smtps://") {
+            (SmtpSecurity::Tls, address)
+        } else if let Some(address) = s.strip_prefix("smtp+starttls://") {
+            (SmtpSecurity::StartTls, address)
+        } else {
+            (SmtpSecurity::Plain, s.strip_prefix("smtp://").unwrap_or(s))
+        };
+        let (host, port) = match address.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| invalid())?),
+            None => match security {
+                SmtpSecurity::Plain => (address, 25),
+                SmtpSecurity::Tls => (address, 465),
+                SmtpSecurity::StartTls => (address, 587),
+            },
+        };
+        if host.is_empty() || host.contains('/') {
+            return Err(invalid());
+        }
+        Ok(SmtpRelay {
+            host: host.to_string(),
+            port,
+            security,
+        })
+    }
+}
+
+impl std::fmt::Display for SmtpRelay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.host, self.port)
+    }
+}
+
+impl AlertRule {
+    /// Why the rule fires for a twin, if it does
+    fn check(&self, report: &TwinReport, first_seen: DateTime<Utc>, now: DateTime<Utc>) -> Option<String> {
+        match &self.condition {
+            // Twins with regions have a composite state, as "Fault|Online"
+            AlertCondition::State(state) => report
+                .state
+                .split('|')
+                .any(|s| s == state)
+                .then(|| format!("state is {}", report.state)),
+            AlertCondition::Stale(after) => {
+                let since = report.last_input.unwrap_or(first_seen);
+                let elapsed = (now - since).to_std().unwrap_or_default();
+                (elapsed >= *after).then(|| match report.last_input {
+                    Some(last) => format!("no input since {}", last.to_rfc3339()),
+                    None => format!("no input received in {}s", elapsed.as_secs()),
+                })
+            }
+        }
+    }
+}
+
+/// An alert firing for a twin
+#[derive(Debug, Clone, Serialize)]
+pub struct Alert {
+    pub rule: String,
+    pub asset_id: AssetID,
+    pub since: DateTime<Utc>,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertStatus {
+    Firing,
+    Resolved,
+}
+
+/// A change of an alert, sent to the notifiers
+#[derive(Debug, Clone, Serialize)]
+pub struct AlertNotification {
+    pub status: AlertStatus,
+    #[serde(flatten)]
+    pub alert: Alert,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// The alerts firing, deduplicated by rule and twin
+#[derive(Debug, Default)]
+pub struct AlertTable {
+    active: BTreeMap<(String, AssetID), Alert>,
+    /// First time each twin was seen, the reference of the stale rules before any input
+    first_seen: HashMap<AssetID, DateTime<Utc>>,
+}
+
+/// Alerts shared by the alerting task and the REST server
+pub type SharedAlerts = Arc<Mutex<AlertTable>>;
+
+impl AlertTable {
+    /// Check the rules on the reports of the twins, returning the alerts that started
+    /// firing and the ones resolved. An alert firing is notified once, until resolved.
+    pub fn evaluate(
+        &mut self,
+        rules: &[AlertRule],
+        reports: &[TwinReport],
+        now: DateTime<Utc>,
+    ) -> Vec<AlertNotification> {
+        self.first_seen
+            .retain(|id, _| reports.iter().any(|report| &report.asset_id == id));
+        let mut firing = BTreeMap::new();
+        for report in reports {
+            let first_seen = *self.first_seen.entry(report.asset_id.clone()).or_insert(now);
+            for rule in rules {
+                if let Some(detail) = rule.check(report, first_seen, now) {
+                    firing.insert((rule.name.clone(), report.asset_id.clone()), detail);
+                }
+            }
+        }
+        let mut notifications = Vec::new();
+        // Twins removed meanwhile resolve their alerts
+        let resolved: Vec<_> = self
+            .active
+            .keys()
+            .filter(|key| !firing.contains_key(*key))
+            .cloned()
+            .collect();
+        for key in resolved {
+            if let Some(alert) = self.active.remove(&key) {
+                notifications.push(AlertNotification {
+                    status: AlertStatus::Resolved,
+                    alert,
+                    timestamp: now,
+                });
+            }
+        }
+        for ((rule, asset_id), detail) in firing {
+            let key = (rule, asset_id);
+            if self.active.contains_key(&key) {
+                continue;
+            }
+            let alert = Alert {
+                rule: key.0.clone(),
+                asset_id: key.1.clone(),
+                since: now,
+                detail,
+            };
+            self.active.insert(key, alert.clone());
+            notifications.push(AlertNotification {
+                status: AlertStatus::Firing,
+                alert,
+                timestamp: now,
+            });
+        }
+        notifications
+    }
+
+    /// The alerts firing, sorted by rule and asset ID
+    pub fn active(&self) -> Vec<Alert> {
+        self.active.values().cloned().collect()
+    }
+}
+
+#[derive(ThisError, Debug)]
+pub enum NotifyError {
+    #[error(transparent)]
+    Http(#[from] HttpError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("SMTP relay answered: {0}")]
+    Smtp(String),
+    #[error("SMTP exchange timed out")]
+    Timeout,
+}
+
+/// Destination of the alert notifications
+#[derive(Debug, Clone)]
+enum Notifier {
+    Webhook(String),
+    Email {
+        relay: SmtpRelay,
+        from: String,
+        to: Vec<String>,
+    },
+}
+
+impl Notifier {
+    async fn send(&self, notification: &AlertNotification) -> Result<(), NotifyError> {
+        match self {
+            Notifier::Webhook(url) => {
+                let body = serde_json::to_string(notification).unwrap_or_default();
+                http_client::post_json(url, &[], &body).await?;
+                Ok(())
+            }
+            Notifier::Email { relay, from, to } => {
+                let alert = &notification.alert;
+                let subject = format!(
+                    "[{}] {} on {}",
+                    if notification.status == AlertStatus::Firing {
+                        "FIRING"
+                    } else {
+                        "RESOLVED"
+                    },
+                    alert.rule,
+                    alert.asset_id
+                );
+                let body = format!(
+                    "Rule: {}\r\nTwin: {}\r\nSince: {}\r\nDetail: {}\r\n",
+                    alert.rule,
+                    alert.asset_id,
+                    alert.since.to_rfc3339(),
+                    alert.detail
+                );
+                tokio::time::timeout(SMTP_TIMEOUT, send_email(relay, from, to, &subject, &body))
+                    .await
+                    .map_err(|_| NotifyError::Timeout)?
+            }
+        }
+    }
+}
+
+/// Read an SMTP reply (possibly multiline), failing unless its code has the expected first digit
+async fn smtp_reply<R: AsyncBufReadExt + Unpin>(reader: &mut R, expected: char) -> Result<(), NotifyError> {
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Err(NotifyError::Smtp("connection closed".to_string()));
+        }
+        // "250-..." continues the reply, "250 ..." ends it
+        if line.as_bytes().get(3) == Some(&b'-') {
+            continue;
+        }
+        return if line.starts_with(expected) {
+            Ok(())
+        } else {
+            Err(NotifyError::Smtp(line.trim_end().to_string()))
+        };
+    }
+}
+
+/// Send an SMTP command, flushed for the TLS sessions, and read its reply
+async fn smtp_command<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut BufReader<S>,
+    command: &str,
+    expected: char,
+) -> Result<(), NotifyError> {
+    stream.write_all(command.as_bytes()).await?;
+    stream.flush().await?;
+    smtp_reply(stream, expected).await
+}
+
+/// Send a plain text email through an SMTP relay
+async fn send_email(
+    relay: &SmtpRelay,
+    from: &str,
+    to: &[String],
+    subject: &str,
+    body: &str,
+) -> Result<(), NotifyError> {
+    let stream = tokio::net::TcpStream::connect((relay.host.as_str(), relay.port)).await?;
+    match relay.security {
+        SmtpSecurity::Plain => {
+            let mut stream = BufReader::new(stream);
+            smtp_reply(&mut stream, '2').await?;
+            smtp_session(stream, from, to, subject, body).await
+        }
+        SmtpSecurity::Tls => {
+            let mut stream = BufReader::new(tls::connect(&relay.host, stream).await?);
+            smtp_reply(&mut stream, '2').await?;
+            smtp_session(stream, from, to, subject, body).await
+        }
+        SmtpSecurity::StartTls => {
+            let mut stream = BufReader::new(stream);
+            smtp_reply(&mut stream, '2').await?;
+            smtp_command(&mut stream, "EHLO digitaltwin\r\n", '2').await?;
+            smtp_command(&mut stream, "STARTTLS\r\n", '2').await?;
+            // The relay waits for the TLS handshake: nothing is left in the buffer
+            let stream = BufReader::new(tls::connect(&relay.host, stream.into_inner()).await?);
+            smtp_session(stream, from, to, subject, body).await
+        }
+    }
+}
+
+/// Send the email once the relay greeted the client
+async fn smtp_session<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: BufReader<S>,
+    from: &str,
+    to: &[String],
+    subject: &str,
+    body: &str,
+) -> Result<(), NotifyError> {
+    let mut commands = vec![
+        ("HELO digitaltwin\r\n".to_string(), '2'),
+        (format!("MAIL FROM:<{from}>\r\n"), '2'),
+    ];
+    commands.extend(to.iter().map(|to| (format!("RCPT TO:<{to}>\r\n"), '2')));
+    commands.push(("DATA\r\n".to_string(), '3'));
+    // Lines starting with a dot are escaped by doubling it
+    let body = body.replace("\r\n.", "\r\n..");
+    commands.push((
+        format!(
+            "From: {from}\r\nTo: {}\r\nSubject: {subject}\r\nDate: {}\r\n\r\n{body}\r\n.\r\n",
+            to.join(", "),
+            Utc::now().to_rfc2822()
+        ),
+        '2',
+    ));
+    for (command, expected) in commands {
+        smtp_command(&mut stream, &command, expected).await?;
+    }
+    stream.write_all(b"QUIT\r\n").await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+/// Periodically checks the alert rules on the twins and notifies the changes
+pub struct Alerting {
+    rules: Vec<AlertRule>,
+    notifiers: Vec<Notifier>,
+    interval: Duration,
+    alerts: SharedAlerts,
+    manager_ch: mpsc::Sender<ManagerMessage>,
+    /// Transitions of the twins, evaluating the state rules without waiting for the interval
+    bus: EventBus,
+    /// A standby instance leaves the notifications to the active one
+    failover: SharedFailover,
+}
+
+impl Alerting {
+    pub fn new(
+        options: AlertingOptions,
+        manager_ch: mpsc::Sender<ManagerMessage>,
+        bus: EventBus,
+        failover: SharedFailover,
+    ) -> Self {
+        let mut notifiers = Vec::new();
+        if let Some(url) = options.alert_webhook {
+            notifiers.push(Notifier::Webhook(url));
+        }
+        if let Some(relay) = options.alert_smtp {
+            if options.alert_email_to.is_empty() {
+                warn!("No recipient for the alert emails, SMTP relay {relay} ignored");
+            } else {
+                notifiers.push(Notifier::Email {
+                    relay,
+                    from: options.alert_email_from,
+                    to: options.alert_email_to,
+                });
+            }
+        }
+        Alerting {
+            rules: options.alert_rules,
+            notifiers,
+            interval: Duration::from_secs(options.alert_interval.max(1)),
+            alerts: Arc::new(Mutex::new(AlertTable::default())),
+            manager_ch,
+            bus,
+            failover,
+        }
+    }
+
+    pub fn alerts(&self) -> SharedAlerts {
+        self.alerts.clone()
+    }
+
+    /// Send a notification to all the notifiers, without blocking the evaluation
+    fn notify(&self, notification: AlertNotification) {
+        for notifier in self.notifiers.clone() {
+            let notification = notification.clone();
+            tokio::spawn(async move {
+                if let Err(e) = notifier.send(&notification).await {
+                    warn!(
+                        "Cannot notify alert {} on {}: {e}",
+                        notification.alert.rule, notification.alert.asset_id
+                    );
+                }
+            });
+        }
+    }
+
+    pub async fn body(&mut self) {
+        if self.rules.is_empty() {
+            return;
+        }
+        info!("Alerting body starting with {} rules", self.rules.len());
+        let state_rules = self
+            .rules
+            .iter()
+            .any(|rule| matches!(rule.condition, AlertCondition::State(_)));
+        let mut bus = self.bus.subscribe();
+        let mut tick = tokio::time::interval(self.interval);
+        // Whether an evaluation is brought forward by a transition
+        let mut due = false;
+        loop {
+            tokio::select! {
+                _ = tick.tick() => due = false,
+                Some(event) = event_bus::next_event(&mut bus, "Alerting") => {
+                    // The transitions close together are evaluated at once
+                    if state_rules && matches!(event, BusEvent::Transition(_)) && !due {
+                        due = true;
+                        tick.reset_after(TRANSITION_DELAY);
+                    }
+                    continue;
+                }
+            }
+            let (reply, response) = oneshot::channel();
+            if self
+                .manager_ch
+                .send(ManagerMessage::Query(Query::ListTwins(reply)))
+                .await
+                .is_err()
+            {
+                return;
+            }
+            let Ok(reports) = response.await else {
+                continue;
+            };
+            let notifications = self.alerts.lock().unwrap_or_else(|e| e.into_inner()).evaluate(
+                &self.rules,
+                &reports,
+                Utc::now(),
+            );
+            let active = failover::is_active(&self.failover);
+            for notification in notifications {
+                info!(
+                    "Alert {} on {} {}: {}",
+                    notification.alert.rule,
+                    notification.alert.asset_id,
+                    if notification.status == AlertStatus::Firing {
+                        "firing"
+                    } else {
+                        "resolved"
+                    },
+                    notification.alert.detail
+                );
+                if active {
+                    self.notify(notification);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(id: &str, state: &str, last_input: Option<DateTime<Utc>>) -> TwinReport {
+        TwinReport {
+            asset_id: id.into(),
+            actor_type: "ChargingPoint".to_string(),
+            state: state.to_string(),
+            last_input,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_rules() {
+        assert_eq!(
+            "fault=state:Fault".parse::<AlertRule>().unwrap().condition,
+            AlertCondition::State("Fault".to_string())
+        );
+        assert_eq!(
+            "stale = stale:3600".parse::<AlertRule>().unwrap(),
+            AlertRule {
+                name: "stale".to_string(),
+                condition: AlertCondition::Stale(Duration::from_secs(3600)),
+            }
+        );
+        assert!("fault=state:".parse::<AlertRule>().is_err());
+        assert!("stale=stale:1h".parse::<AlertRule>().is_err());
+        assert!("fault".parse::<AlertRule>().is_err());
+    }
+
+    #[test]
+    fn test_smtp_relay() {
+        let relay = |host: &str, port, security| SmtpRelay {
+            host: host.to_string(),
+            port,
+            security,
+        };
+        assert_eq!(
+            "mail.local:2525".parse::<SmtpRelay>().unwrap(),
+            relay("mail.local", 2525, SmtpSecurity::Plain)
+        );
+        assert_eq!(
+            "smtp://mail.local".parse::<SmtpRelay>().unwrap(),
+            relay("mail.local", 25, SmtpSecurity::Plain)
+        );
+        assert_eq!(
+            "smtps://smtp.example.com".parse::<SmtpRelay>().unwrap(),
+            relay("smtp.example.com", 465, SmtpSecurity::Tls)
+        );
+        assert_eq!(
+            "smtp+starttls://smtp.example.com:2587"
+                .parse::<SmtpRelay>()
+                .unwrap(),
+            relay("smtp.example.com", 2587, SmtpSecurity::StartTls)
+        );
+        assert!("smtps://".parse::<SmtpRelay>().is_err());
+        assert!("mail.local:smtp".parse::<SmtpRelay>().is_err());
+        assert!("https://mail.local".parse::<SmtpRelay>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_send_email() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let relay = format!("127.0.0.1:{}", listener.local_addr().unwrap().port());
+        // A relay accepting everything, recording what it received
+        let received = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = BufReader::new(stream);
+            stream.write_all(b"220 relay ready\r\n").await.unwrap();
+            let (mut received, mut data) = (String::new(), false);
+            loop {
+                let mut line = String::new();
+                if stream.read_line(&mut line).await.unwrap() == 0 {
+                    return received;
+                }
+                received.push_str(&line);
+                let reply: &[u8] = if data {
+                    if line != ".\r\n" {
+                        continue;
+                    }
+                    data = false;
+                    b"250 queued\r\n"
+                } else if line == "DATA\r\n" {
+                    data = true;
+                    b"354 go ahead\r\n"
+                } else if line == "QUIT\r\n" {
+                    return received;
+                } else {
+                    b"250 ok\r\n"
+                };
+                stream.write_all(reply).await.unwrap();
+            }
+        });
+        let relay = relay.parse().unwrap();
+        let to = ["ops@example.com".to_string()];
+        send_email(
+            &relay,
+            "dt@example.com",
+            &to,
+            "fault",
+            "Rule: fault\r\n.hidden\r\n",
+        )
+        .await
+        .unwrap();
+        let received = received.await.unwrap();
+        assert!(received.starts_with(
+            "HELO digitaltwin\r\nMAIL FROM:<dt@example.com>\r\nRCPT TO:<ops@example.com>\r\nDATA\r\n"
+        ));
+        assert!(received.contains("Subject: fault\r\n"));
+        assert!(received.contains("\r\n..hidden\r\n"));
+        assert!(received.ends_with(".\r\nQUIT\r\n"));
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let rules = ["fault=state:Fault", "stale=stale:3600"].map(|rule| rule.parse::<AlertRule>().unwrap());
+        let start = Utc::now();
+        let mut table = AlertTable::default();
+
+        let fired = table.evaluate(&rules, &[report("a", "Fault|Online", Some(start))], start);
+        assert_eq!(fired.len(), 1);
+        assert_eq!(fired[0].status, AlertStatus::Firing);
+        assert_eq!(fired[0].alert.rule, "fault");
+        // Deduplicated while firing
+        let later = start + chrono::Duration::minutes(30);
+        assert!(table
+            .evaluate(&rules, &[report("a", "Fault|Online", Some(start))], later)
+            .is_empty());
+
+        let later = start + chrono::Duration::minutes(90);
+        let changes = table.evaluate(&rules, &[report("a", "Idle|Online", Some(start))], later);
+        let summary: Vec<_> = changes
+            .iter()
+            .map(|n| (n.status, n.alert.rule.as_str()))
+            .collect();
+        assert_eq!(
+            summary,
+            [(AlertStatus::Resolved, "fault"), (AlertStatus::Firing, "stale")]
+        );
+        assert_eq!(table.active().len(), 1);
+
+        // A removed twin resolves its alerts
+        let changes = table.evaluate(&rules, &[], later);
+        assert_eq!(changes[0].status, AlertStatus::Resolved);
+        assert!(table.active().is_empty());
+    }
+}
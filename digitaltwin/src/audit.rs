@@ -0,0 +1,120 @@
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::command::{CommandEnvelope, CommandSource};
+use crate::twin_runner::TwinTransition;
+use digitaltwin_core::{AssetID, CorrelationID, DeviceID};
+
+/// An entry of the audit log. The command args are not recorded, as they may hold secrets.
+#[derive(Debug, Serialize)]
+pub struct AuditRecord<'a> {
+    pub asset_id: &'a AssetID,
+    pub command: &'a str,
+    pub source: CommandSource,
+    pub principal: Option<&'a str>,
+    pub correlation_id: &'a str,
+    pub received: DateTime<Utc>,
+    /// What happened to the command ("executed", "duplicate", "cooling_down", "unauthorized")
+    pub outcome: &'static str,
+    /// State of the twin after the command
+    pub state: String,
+}
+
+impl<'a> AuditRecord<'a> {
+    pub fn new(
+        asset_id: &'a AssetID,
+        envelope: &'a CommandEnvelope,
+        outcome: &'static str,
+        state: String,
+    ) -> Self {
+        AuditRecord {
+            asset_id,
+            command: &envelope.command,
+            source: envelope.source,
+            principal: envelope.principal.as_deref(),
+            correlation_id: &envelope.correlation_id,
+            received: envelope.timestamp,
+            outcome,
+            state,
+        }
+    }
+}
+
+/// An entry of the audit log for a slot bound to another sensor at runtime
+#[derive(Debug, Serialize)]
+pub struct RebindRecord<'a> {
+    pub asset_id: &'a AssetID,
+    /// Kind of change ("rebind_slot")
+    pub change: &'static str,
+    pub slot: &'a str,
+    pub previous: &'a DeviceID,
+    pub device_id: &'a DeviceID,
+    pub principal: Option<&'a str>,
+    pub received: DateTime<Utc>,
+}
+
+/// An entry of the audit log for a state transition, with the ID of the message or
+/// command that caused it
+#[derive(Debug, Serialize)]
+pub struct TransitionRecord<'a> {
+    pub asset_id: &'a AssetID,
+    /// Kind of change ("transition")
+    pub change: &'static str,
+    pub from: &'a str,
+    pub to: &'a str,
+    pub correlation_id: &'a CorrelationID,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl<'a> TransitionRecord<'a> {
+    pub fn new(transition: &'a TwinTransition) -> Self {
+        TransitionRecord {
+            asset_id: &transition.asset_id,
+            change: "transition",
+            from: &transition.from,
+            to: &transition.to,
+            correlation_id: &transition.correlation_id,
+            timestamp: transition.timestamp,
+        }
+    }
+}
+
+/// Audit log of the commands received by the twins, of their state transitions and of the runtime changes to their bindings, shared by all the twin runners.
+/// Records are appended as JSON lines to a file, or logged with the "audit" target.
+#[derive(Debug, Clone, Default)]
+pub struct AuditLog {
+    file: Option<Arc<Mutex<File>>>,
+}
+
+impl AuditLog {
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(AuditLog {
+            file: Some(Arc::new(Mutex::new(file))),
+        })
+    }
+
+    pub fn record(&self, record: &impl Serialize) {
+        let line = match serde_json::to_string(record) {
+            Ok(line) => line,
+            Err(e) => {
+                error!("Failed to serialize audit record: {e:?}");
+                return;
+            }
+        };
+        match &self.file {
+            Some(file) => {
+                let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+                if let Err(e) = writeln!(file, "{line}") {
+                    error!("Failed to write audit record: {e:?}");
+                }
+            }
+            None => info!(target: "audit", "{line}"),
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use chrono::{DateTime, Utc};
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use thiserror::Error as ThisError;
+
+use crate::history::HistoryMetadata;
+use crate::http_client::{self, HttpError};
+use crate::manager;
+use digitaltwin_core::{AssetID, DeviceID};
+
+/// Version of the backup format, checked on restore
+pub const BACKUP_VERSION: u32 = 1;
+
+#[derive(ThisError, Debug)]
+pub enum BackupError {
+    #[error("unsupported backup version {0} (expected {BACKUP_VERSION})")]
+    Version(u32),
+    #[error("invalid definition file name {0:?}")]
+    FileName(String),
+    #[error("cannot access {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("invalid backup: {0}")]
+    Invalid(String),
+    #[error(transparent)]
+    Http(#[from] HttpError),
+}
+
+/// Runtime state of a deployment, to migrate it to another host or roll it back after a
+/// bad change
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Backup {
+    pub version: u32,
+    pub created: DateTime<Utc>,
+    /// Content of the twin definition files (AAS documents, templates and their instances),
+    /// by file name
+    pub definitions: BTreeMap<String, String>,
+    /// Snapshot of the actor of each running twin
+    pub snapshots: BTreeMap<AssetID, serde_json::Value>,
+    /// Sensor bound to each slot of each running twin, including the rebindings made at runtime
+    pub subscriptions: BTreeMap<AssetID, BTreeMap<String, DeviceID>>,
+    /// Extent of the history of each slot. The history itself is kept in memory and is not
+    /// restored.
+    pub history: Vec<HistoryMetadata>,
+}
+
+/// Outcome of the restore of a backup
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RestoreReport {
+    /// Definition files written
+    pub definitions: Vec<String>,
+    /// Twins restored from their snapshot
+    pub restored: Vec<AssetID>,
+    /// Twins restarting with a changed definition, restored from their snapshot once started
+    pub pending: Vec<AssetID>,
+    /// Twins whose snapshot could not be restored, with the reason
+    pub failed: BTreeMap<AssetID, String>,
+}
+
+/// Whether a file name can be written in the twins directory by a restore: a plain name with
+/// the extension of a definition format
+fn valid_file_name(name: &str) -> bool {
+    !name.starts_with('.') && !name.contains(['/', '\\']) && manager::parser_for(Path::new(name)).is_some()
+}
+
+impl Backup {
+    /// A backup of the definitions in a directory, without twins
+    pub fn new(dir: &Path) -> Result<Self, BackupError> {
+        let mut definitions = BTreeMap::new();
+        let io_error = |e| BackupError::Io(dir.to_path_buf(), e);
+        for entry in std::fs::read_dir(dir).map_err(io_error)? {
+            let path = entry.map_err(io_error)?.path();
+            let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            if path.is_file() && valid_file_name(name) {
+                let content = std::fs::read_to_string(&path).map_err(|e| BackupError::Io(path.clone(), e))?;
+                definitions.insert(name.to_string(), content);
+            }
+        }
+        Ok(Backup {
+            version: BACKUP_VERSION,
+            created: Utc::now(),
+            definitions,
+            snapshots: BTreeMap::new(),
+            subscriptions: BTreeMap::new(),
+            history: Vec::new(),
+        })
+    }
+
+    /// Check that the backup can be restored, before changing anything
+    pub fn validate(&self) -> Result<(), BackupError> {
+        if self.version != BACKUP_VERSION {
+            return Err(BackupError::Version(self.version));
+        }
+        if let Some(name) = self.definitions.keys().find(|name| !valid_file_name(name)) {
+            return Err(BackupError::FileName(name.clone()));
+        }
+        for (name, content) in &self.definitions {
+            let path = Path::new(name);
+            // Templates and instances files are only valid together, checked at load time
+            if crate::templates::is_template(path) || crate::templates::is_instances(path) {
+                continue;
+            }
+            let parse = manager::parser_for(path).ok_or_else(|| BackupError::FileName(name.clone()))?;
+            parse(content.as_bytes()).map_err(|e| BackupError::Invalid(format!("{name}: {e}")))?;
+        }
+        Ok(())
+    }
+
+    /// Write the definitions to a directory, replacing the files with the same name, and
+    /// return their names. The other files are left untouched.
+    pub fn write_definitions(&self, dir: &Path) -> Result<Vec<String>, BackupError> {
+        self.validate()?;
+        std::fs::create_dir_all(dir).map_err(|e| BackupError::Io(dir.to_path_buf(), e))?;
+        for (name, content) in &self.definitions {
+            let path = dir.join(name);
+            std::fs::write(&path, content).map_err(|e| BackupError::Io(path, e))?;
+        }
+        Ok(self.definitions.keys().cloned().collect())
+    }
+}
+
+/// Arguments of the backup subcommand
+#[derive(Args)]
+pub struct BackupArgs {
+    /// file the backup is written to (standard output by default)
+    #[clap(long, short)]
+    pub output: Option<PathBuf>,
+    /// REST API of the runtime (http:// or https://)
+    #[clap(long, default_value = "http://127.0.0.1:8080", env = "DT_URL")]
+    pub url: String,
+    /// API key of the REST API, if required
+    #[clap(long, env = "DT_API_KEY")]
+    pub api_key: Option<String>,
+}
+
+/// Arguments of the restore subcommand
+#[derive(Args)]
+pub struct RestoreArgs {
+    /// backup file written by the backup subcommand
+    pub file: PathBuf,
+    /// REST API of the runtime (http:// or https://)
+    #[clap(long, default_value = "http://127.0.0.1:8080", env = "DT_URL")]
+    pub url: String,
+    /// API key of the REST API, if required
+    #[clap(long, env = "DT_API_KEY")]
+    pub api_key: Option<String>,
+}
+
+fn authorization(api_key: &Option<String>) -> Option<String> {
+    api_key.as_ref().map(|key| format!("Bearer {key}"))
+}
+
+impl BackupArgs {
+    /// Download a backup from the REST API and write it to the output, returning the number
+    /// of twins saved
+    pub async fn run(&self) -> Result<usize, BackupError> {
+        let url = format!("{}/backup", self.url.trim_end_matches('/'));
+        let authorization = authorization(&self.api_key);
+        let mut headers = vec![("Accept", "application/json")];
+        if let Some(authorization) = &authorization {
+            headers.push(("Authorization", authorization));
+        }
+        let body = http_client::get(&url, &headers).await?;
+        let backup: Backup = serde_json::from_str(&body).map_err(|e| BackupError::Invalid(e.to_string()))?;
+        match &self.output {
+            Some(path) => std::fs::write(path, &body).map_err(|e| BackupError::Io(path.clone(), e))?,
+            None => println!("{body}"),
+        }
+        Ok(backup.snapshots.len())
+    }
+}
+
+impl RestoreArgs {
+    /// Send a backup file to the REST API, returning the JSON restore report
+    pub async fn run(&self) -> Result<String, BackupError> {
+        let body = std::fs::read_to_string(&self.file).map_err(|e| BackupError::Io(self.file.clone(), e))?;
+        // Checked locally first, for a clearer error than the one of the runtime
+        let backup: Backup = serde_json::from_str(&body).map_err(|e| BackupError::Invalid(e.to_string()))?;
+        backup.validate()?;
+        let url = format!("{}/backup/restore", self.url.trim_end_matches('/'));
+        let authorization = authorization(&self.api_key);
+        let mut headers = vec![("Accept", "application/json")];
+        if let Some(authorization) = &authorization {
+            headers.push(("Authorization", authorization));
+        }
+        Ok(http_client::post_json(&url, &headers, &body).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backup_definitions() {
+        let dir = std::env::temp_dir().join(format!("dt-backup-{}", std::process::id()));
+        let restored = dir.join("restored");
+        std::fs::create_dir_all(&dir).unwrap();
+        let shell = "id: \"urn:test:lamp\"\nid_short: \"Lamp\"\nsubmodels: []\n";
+        std::fs::write(dir.join("lamp.yaml"), shell).unwrap();
+        std::fs::write(dir.join("README.md"), "not a definition").unwrap();
+
+        let backup = Backup::new(&dir).unwrap();
+        assert_eq!(backup.definitions.keys().collect::<Vec<_>>(), ["lamp.yaml"]);
+        assert_eq!(backup.write_definitions(&restored).unwrap(), ["lamp.yaml"]);
+        assert_eq!(
+            std::fs::read_to_string(restored.join("lamp.yaml")).unwrap(),
+            shell
+        );
+
+        let mut invalid = backup.clone();
+        invalid.version = BACKUP_VERSION + 1;
+        assert!(matches!(invalid.validate(), Err(BackupError::Version(_))));
+        for name in ["../lamp.yaml", ".hidden.yaml", "lamp.txt"] {
+            let mut invalid = backup.clone();
+            invalid.definitions.insert(name.to_string(), shell.to_string());
+            assert!(
+                matches!(invalid.validate(), Err(BackupError::FileName(_))),
+                "{name}"
+            );
+        }
+        let mut invalid = backup.clone();
+        invalid
+            .definitions
+            .insert("broken.yaml".to_string(), "id: [".to_string());
+        assert!(matches!(
+            invalid.write_definitions(&dir),
+            Err(BackupError::Invalid(_))
+        ));
+        assert!(!dir.join("broken.yaml").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,95 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Counter used to generate the correlation IDs
+static NEXT_CORRELATION_ID: AtomicU64 = AtomicU64::new(1);
+
+/// A new correlation ID, for the inputs received without one
+pub fn new_correlation_id() -> String {
+    format!(
+        "{:x}-{}",
+        Utc::now().timestamp_millis(),
+        NEXT_CORRELATION_ID.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// The interface a command was received from
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CommandSource {
+    Mqtt,
+    Rest,
+    Scheduler,
+    Optimizer,
+    /// The runtime, reporting the failures of the actuation backends
+    Actuation,
+    /// The runtime, when a condition over the slots of the twin is met
+    Condition,
+}
+
+/// A command, with the information about who issued it and when
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandEnvelope {
+    pub command: String,
+    pub args: serde_json::Value,
+    /// Optional key identifying repeated deliveries of the same command
+    pub idempotency_key: Option<String>,
+    pub source: CommandSource,
+    /// Identity of the issuer, as declared by the client
+    pub principal: Option<String>,
+    /// ID relating the command to the request that caused it (generated if not provided)
+    pub correlation_id: String,
+    /// Time the command was received
+    pub timestamp: DateTime<Utc>,
+}
+
+impl CommandEnvelope {
+    pub fn new(source: CommandSource, command: impl Into<String>, args: serde_json::Value) -> Self {
+        let timestamp = Utc::now();
+        CommandEnvelope {
+            command: command.into(),
+            args,
+            idempotency_key: None,
+            source,
+            principal: None,
+            correlation_id: new_correlation_id(),
+            timestamp,
+        }
+    }
+
+    pub fn with_idempotency_key(mut self, key: Option<String>) -> Self {
+        self.idempotency_key = key;
+        self
+    }
+
+    pub fn with_principal(mut self, principal: Option<String>) -> Self {
+        self.principal = principal;
+        self
+    }
+
+    /// Use the correlation ID provided by the client, if any
+    pub fn with_correlation_id(mut self, correlation_id: Option<String>) -> Self {
+        if let Some(correlation_id) = correlation_id {
+            self.correlation_id = correlation_id;
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_correlation_ids() {
+        let a = CommandEnvelope::new(CommandSource::Mqtt, "Reset", serde_json::Value::Null);
+        let b = CommandEnvelope::new(CommandSource::Rest, "Reset", serde_json::Value::Null);
+        assert_ne!(a.correlation_id, b.correlation_id);
+
+        let c = b.with_correlation_id(Some("req-42".to_string()));
+        assert_eq!(c.correlation_id, "req-42");
+        let d = c.with_correlation_id(None);
+        assert_eq!(d.correlation_id, "req-42");
+    }
+}
@@ -0,0 +1,368 @@
+use ring::hmac;
+use serde_json::Value;
+use std::collections::HashMap;
+use thiserror::Error as ThisError;
+
+/// How far the timestamp of an HMAC-signed command can be from the clock of the runtime,
+/// in seconds; the nonces are remembered as long
+const MAX_COMMAND_SKEW: i64 = 300;
+
+/// How the commands received over MQTT are authenticated
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum CommandAuth {
+    /// Commands are accepted as they are
+    None,
+    /// The "signature" field holds the hex HMAC-SHA256 of the command, computed over
+    /// its compact JSON serialization (keys sorted, without the signature field). The
+    /// signed command carries "ts", in seconds since the Unix epoch and within 5 minutes
+    /// of the clock of the runtime, and a "nonce" string never used before
+    Hmac,
+    /// The "signature" field holds a JWT (HS256, HS384 or HS512) whose claims repeat
+    /// the target, command and args; the "sub" claim is the principal
+    Jwt,
+}
+
+#[derive(ThisError, Debug, PartialEq)]
+pub enum VerifyError {
+    #[error("command is not signed")]
+    Unsigned,
+    #[error("no verification key configured")]
+    NoKeys,
+    #[error("invalid signature")]
+    BadSignature,
+    #[error("malformed token: {0}")]
+    MalformedToken(&'static str),
+    #[error("unsupported token algorithm: {0}")]
+    UnsupportedAlgorithm(String),
+    #[error("token expired")]
+    Expired,
+    #[error("token not valid yet")]
+    NotYetValid,
+    #[error("token does not match the command field {0}")]
+    Mismatch(&'static str),
+    #[error("command has no {0}")]
+    Missing(&'static str),
+    #[error("command timestamp too far from the current time")]
+    Stale,
+    #[error("command nonce already used")]
+    ReusedNonce,
+}
+
+/// Verifies the signature of incoming commands with a set of shared secrets.
+/// Every key is tried in turn, so that keys can be rotated without downtime.
+#[derive(Debug, Clone)]
+pub struct CommandVerifier {
+    mode: CommandAuth,
+    keys: Vec<Vec<u8>>,
+    /// Nonces of the recent HMAC-signed commands, with their timestamp
+    nonces: HashMap<String, i64>,
+}
+
+impl CommandVerifier {
+    pub fn new(mode: CommandAuth, keys: &[String]) -> Self {
+        CommandVerifier {
+            mode,
+            keys: keys.iter().map(|k| k.as_bytes().to_vec()).collect(),
+            nonces: HashMap::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.mode != CommandAuth::None
+    }
+
+    /// Verify a command object as received. Returns the verified principal, if the
+    /// signature carries one (JWT subject), or the declared one otherwise.
+    pub fn verify(&mut self, command: &Value, now: i64) -> Result<Option<String>, VerifyError> {
+        let declared = command
+            .get("principal")
+            .and_then(Value::as_str)
+            .map(str::to_string);
+        if self.mode == CommandAuth::None {
+            return Ok(declared);
+        }
+        if self.keys.is_empty() {
+            return Err(VerifyError::NoKeys);
+        }
+        let signature = command
+            .get("signature")
+            .and_then(Value::as_str)
+            .ok_or(VerifyError::Unsigned)?;
+        match self.mode {
+            CommandAuth::None => unreachable!(),
+            CommandAuth::Hmac => {
+                let ts = command
+                    .get("ts")
+                    .and_then(Value::as_i64)
+                    .ok_or(VerifyError::Missing("ts"))?;
+                let nonce = command
+                    .get("nonce")
+                    .and_then(Value::as_str)
+                    .ok_or(VerifyError::Missing("nonce"))?;
+                if (now - ts).abs() > MAX_COMMAND_SKEW {
+                    return Err(VerifyError::Stale);
+                }
+                let tag = decode_hex(signature).ok_or(VerifyError::BadSignature)?;
+                let mut unsigned = command.clone();
+                if let Some(obj) = unsigned.as_object_mut() {
+                    obj.remove("signature");
+                }
+                let message = canonical_json(&unsigned);
+                self.verify_hmac(hmac::HMAC_SHA256, message.as_bytes(), &tag)?;
+                // The nonces older than the window can't come back, their commands being stale
+                self.nonces.retain(|_, ts| (now - *ts).abs() <= MAX_COMMAND_SKEW);
+                if self.nonces.insert(nonce.to_string(), ts).is_some() {
+                    return Err(VerifyError::ReusedNonce);
+                }
+                Ok(declared)
+            }
+            CommandAuth::Jwt => {
+                let claims = self.verify_token(signature, now)?;
+                for field in ["target", "command", "args"] {
+                    if claims.get(field) != command.get(field) {
+                        return Err(VerifyError::Mismatch(field));
+                    }
+                }
+                Ok(claims.get("sub").and_then(Value::as_str).map(str::to_string))
+            }
+        }
+    }
+
+    fn verify_hmac(&self, algorithm: hmac::Algorithm, message: &[u8], tag: &[u8]) -> Result<(), VerifyError> {
+        self.keys
+            .iter()
+            .any(|key| hmac::verify(&hmac::Key::new(algorithm, key), message, tag).is_ok())
+            .then_some(())
+            .ok_or(VerifyError::BadSignature)
+    }
+
+    /// Check the signature and the validity period of a JWT, returning its claims
+    fn verify_token(&self, token: &str, now: i64) -> Result<Value, VerifyError> {
+        let mut parts = token.split('.');
+        let (Some(header), Some(payload), Some(signature), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(VerifyError::MalformedToken("expected three parts"));
+        };
+        let decode_json = |part: &str| -> Result<Value, VerifyError> {
+            let bytes = decode_base64url(part).ok_or(VerifyError::MalformedToken("invalid base64"))?;
+            serde_json::from_slice(&bytes).map_err(|_| VerifyError::MalformedToken("invalid JSON"))
+        };
+        let algorithm = match decode_json(header)?.get("alg").and_then(Value::as_str) {
+            Some("HS256") => hmac::HMAC_SHA256,
+            Some("HS384") => hmac::HMAC_SHA384,
+            Some("HS512") => hmac::HMAC_SHA512,
+            Some(alg) => return Err(VerifyError::UnsupportedAlgorithm(alg.to_string())),
+            None => return Err(VerifyError::MalformedToken("missing algorithm")),
+        };
+        let tag = decode_base64url(signature).ok_or(VerifyError::MalformedToken("invalid base64"))?;
+        let signed = &token[..header.len() + 1 + payload.len()];
+        self.verify_hmac(algorithm, signed.as_bytes(), &tag)?;
+
+        let claims = decode_json(payload)?;
+        if claims
+            .get("exp")
+            .and_then(Value::as_i64)
+            .is_some_and(|exp| now >= exp)
+        {
+            return Err(VerifyError::Expired);
+        }
+        if claims
+            .get("nbf")
+            .and_then(Value::as_i64)
+            .is_some_and(|nbf| now < nbf)
+        {
+            return Err(VerifyError::NotYetValid);
+        }
+        Ok(claims)
+    }
+}
+
+/// Compact JSON serialization with the object keys sorted, as signed by the clients
+pub fn canonical_json(value: &Value) -> String {
+    match value {
+        Value::Object(obj) => {
+            let mut entries: Vec<_> = obj.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            let fields: Vec<_> = entries
+                .into_iter()
+                .map(|(k, v)| format!("{}:{}", Value::String(k.clone()), canonical_json(v)))
+                .collect();
+            format!("{{{}}}", fields.join(","))
+        }
+        Value::Array(items) => {
+            let items: Vec<_> = items.iter().map(canonical_json).collect();
+            format!("[{}]", items.join(","))
+        }
+        other => other.to_string(),
+    }
+}
+
+/// Decode a hex string, such as a signature or a key
+pub fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Decode unpadded base64url, as used in JWTs
+fn decode_base64url(s: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(s.len() * 3 / 4);
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+    for c in s.trim_end_matches('=').bytes() {
+        let v = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'-' => 62,
+            b'_' => 63,
+            _ => return None,
+        };
+        acc = (acc << 6) | v as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((acc >> bits) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const SECRET: &str = "s3cret";
+
+    fn encode_base64url(bytes: &[u8]) -> String {
+        const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+        let mut out = String::new();
+        for chunk in bytes.chunks(3) {
+            let n = chunk.iter().fold(0u32, |acc, &b| (acc << 8) | b as u32) << (8 * (3 - chunk.len()));
+            for i in 0..=chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            }
+        }
+        out
+    }
+
+    fn token(alg: &str, claims: Value, secret: &str) -> String {
+        let signed = format!(
+            "{}.{}",
+            encode_base64url(json!({"alg": alg, "typ": "JWT"}).to_string().as_bytes()),
+            encode_base64url(claims.to_string().as_bytes())
+        );
+        let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+        let tag = hmac::sign(&key, signed.as_bytes());
+        format!("{signed}.{}", encode_base64url(tag.as_ref()))
+    }
+
+    fn command() -> Value {
+        json!({"target": "urn:aas:charger", "command": "SetChargingCurrent", "args": {"desired_current": 6}, "principal": "ops"})
+    }
+
+    fn sign_hmac(mut cmd: Value, ts: i64, nonce: &str) -> Value {
+        cmd["ts"] = json!(ts);
+        cmd["nonce"] = json!(nonce);
+        let key = hmac::Key::new(hmac::HMAC_SHA256, SECRET.as_bytes());
+        let tag = hmac::sign(&key, canonical_json(&cmd).as_bytes());
+        let hex: String = tag.as_ref().iter().map(|b| format!("{b:02x}")).collect();
+        cmd["signature"] = json!(hex);
+        cmd
+    }
+
+    #[test]
+    fn test_hmac_signature() {
+        let mut verifier = CommandVerifier::new(CommandAuth::Hmac, &["old".to_string(), SECRET.to_string()]);
+        assert_eq!(verifier.verify(&command(), 0), Err(VerifyError::Unsigned));
+
+        let mut cmd = sign_hmac(command(), 1000, "n-1");
+        assert_eq!(verifier.verify(&cmd, 1000), Ok(Some("ops".to_string())));
+
+        // Any change to the signed fields invalidates the signature
+        cmd["args"]["desired_current"] = json!(32);
+        assert_eq!(verifier.verify(&cmd, 1000), Err(VerifyError::BadSignature));
+        cmd["signature"] = json!("not hex");
+        assert_eq!(verifier.verify(&cmd, 1000), Err(VerifyError::BadSignature));
+    }
+
+    #[test]
+    fn test_hmac_replay() {
+        let mut verifier = CommandVerifier::new(CommandAuth::Hmac, &[SECRET.to_string()]);
+        // The timestamp and the nonce are required, and signed
+        let mut unstamped = sign_hmac(command(), 1000, "n-1");
+        unstamped.as_object_mut().unwrap().remove("nonce");
+        assert_eq!(
+            verifier.verify(&unstamped, 1000),
+            Err(VerifyError::Missing("nonce"))
+        );
+        unstamped.as_object_mut().unwrap().remove("ts");
+        assert_eq!(verifier.verify(&unstamped, 1000), Err(VerifyError::Missing("ts")));
+        let mut backdated = sign_hmac(command(), 1000, "n-1");
+        backdated["ts"] = json!(1100);
+        assert_eq!(verifier.verify(&backdated, 1100), Err(VerifyError::BadSignature));
+
+        // A command is accepted once, within 5 minutes of its timestamp
+        let cmd = sign_hmac(command(), 1000, "n-1");
+        assert_eq!(verifier.verify(&cmd, 1301), Err(VerifyError::Stale));
+        assert_eq!(verifier.verify(&cmd, 699), Err(VerifyError::Stale));
+        assert!(verifier.verify(&cmd, 1300).is_ok());
+        assert_eq!(verifier.verify(&cmd, 1300), Err(VerifyError::ReusedNonce));
+        assert!(verifier.verify(&sign_hmac(command(), 1000, "n-2"), 1300).is_ok());
+
+        // The nonces are forgotten once their commands are stale
+        assert!(verifier.verify(&sign_hmac(command(), 1400, "n-3"), 1400).is_ok());
+        assert_eq!(verifier.nonces.len(), 1);
+    }
+
+    #[test]
+    fn test_jwt_signature() {
+        let mut verifier = CommandVerifier::new(CommandAuth::Jwt, &[SECRET.to_string()]);
+        let mut claims = command();
+        claims["sub"] = json!("scheduler");
+        claims["exp"] = json!(1000);
+        let mut cmd = command();
+
+        cmd["signature"] = json!(token("HS256", claims.clone(), SECRET));
+        assert_eq!(verifier.verify(&cmd, 999), Ok(Some("scheduler".to_string())));
+        assert_eq!(verifier.verify(&cmd, 1000), Err(VerifyError::Expired));
+
+        cmd["signature"] = json!(token("HS256", claims.clone(), "wrong"));
+        assert_eq!(verifier.verify(&cmd, 0), Err(VerifyError::BadSignature));
+        cmd["signature"] = json!(token("none", claims.clone(), SECRET));
+        assert_eq!(
+            verifier.verify(&cmd, 0),
+            Err(VerifyError::UnsupportedAlgorithm("none".to_string()))
+        );
+        cmd["signature"] = json!("abc.def");
+        assert!(matches!(
+            verifier.verify(&cmd, 0),
+            Err(VerifyError::MalformedToken(_))
+        ));
+
+        // The token is bound to the command it was issued for
+        cmd["signature"] = json!(token("HS256", claims, SECRET));
+        cmd["command"] = json!("Reset");
+        assert_eq!(verifier.verify(&cmd, 0), Err(VerifyError::Mismatch("command")));
+    }
+
+    #[test]
+    fn test_no_keys() {
+        let mut cmd = command();
+        cmd["signature"] = json!("00");
+        assert_eq!(
+            CommandVerifier::new(CommandAuth::Hmac, &[]).verify(&cmd, 0),
+            Err(VerifyError::NoKeys)
+        );
+        assert_eq!(
+            CommandVerifier::new(CommandAuth::None, &[]).verify(&command(), 0),
+            Ok(Some("ops".to_string()))
+        );
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use digitaltwin_core::AssetAdministrationShell;
+
+/// Number of idempotency keys remembered by each twin
+const MAX_RECENT_KEYS: usize = 64;
+
+/// Outcome of the command guard check
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    /// The command can be executed
+    Execute,
+    /// A command with the same idempotency key was already executed
+    Duplicate,
+    /// The command was executed too recently, retry after the given time
+    CoolingDown(Duration),
+}
+
+/// Filters out duplicate commands (by idempotency key) and enforces
+/// the per-command cooldowns and allowed principals declared in the AAS operations.
+#[derive(Debug, Default)]
+pub struct CommandGuard {
+    /// Minimum time between two executions of a command
+    cooldowns: HashMap<String, Duration>,
+    /// Principals allowed to issue a command, for the restricted commands
+    allowed_principals: HashMap<String, Vec<String>>,
+    /// Last execution time of each command
+    last_executed: HashMap<String, Instant>,
+    /// Most recent idempotency keys, oldest first
+    recent_keys: VecDeque<String>,
+}
+
+impl CommandGuard {
+    /// Create a guard with the cooldowns declared in the AAS operations
+    pub fn from_aas(aas: &AssetAdministrationShell) -> Self {
+        CommandGuard {
+            cooldowns: aas
+                .operations()
+                .filter_map(|op| {
+                    op.cooldown_ms
+                        .map(|ms| (op.id_short.clone(), Duration::from_millis(ms)))
+                })
+                .collect(),
+            allowed_principals: aas
+                .operations()
+                .filter(|op| !op.allowed_principals.is_empty())
+                .map(|op| (op.id_short.clone(), op.allowed_principals.clone()))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Check whether the principal issuing a command is allowed to. Commands
+    /// without a list of allowed principals can be issued by anyone.
+    pub fn authorize(&self, command: &str, principal: Option<&str>) -> bool {
+        match self.allowed_principals.get(command) {
+            Some(allowed) => principal.is_some_and(|p| allowed.iter().any(|a| a == p)),
+            None => true,
+        }
+    }
+
+    /// Check whether a command could be executed now, without recording it (dry run)
+    pub fn peek(&self, command: &str, key: Option<&str>, now: Instant) -> Verdict {
+        if let Some(key) = key {
+            if self.recent_keys.iter().any(|k| k == key) {
+                return Verdict::Duplicate;
+            }
+        }
+        if let (Some(cooldown), Some(last)) = (self.cooldowns.get(command), self.last_executed.get(command)) {
+            let elapsed = now.duration_since(*last);
+            if elapsed < *cooldown {
+                return Verdict::CoolingDown(*cooldown - elapsed);
+            }
+        }
+        Verdict::Execute
+    }
+
+    /// Check whether a command can be executed now, recording it if so
+    pub fn check(&mut self, command: &str, key: Option<&str>, now: Instant) -> Verdict {
+        let verdict = self.peek(command, key, now);
+        if verdict != Verdict::Execute {
+            return verdict;
+        }
+        if let Some(key) = key {
+            if self.recent_keys.len() == MAX_RECENT_KEYS {
+                self.recent_keys.pop_front();
+            }
+            self.recent_keys.push_back(key.to_string());
+        }
+        self.last_executed.insert(command.to_string(), now);
+        Verdict::Execute
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_duplicate_keys() {
+        let mut guard = CommandGuard::default();
+        let now = Instant::now();
+        assert_eq!(guard.check("Reset", Some("abc"), now), Verdict::Execute);
+        assert_eq!(guard.check("Reset", Some("abc"), now), Verdict::Duplicate);
+        assert_eq!(guard.check("Reset", Some("def"), now), Verdict::Execute);
+        // Commands without a key are never duplicates
+        assert_eq!(guard.check("Reset", None, now), Verdict::Execute);
+        assert_eq!(guard.check("Reset", None, now), Verdict::Execute);
+    }
+
+    #[test]
+    fn test_cooldown() {
+        let mut guard = CommandGuard::default();
+        guard
+            .cooldowns
+            .insert("Reset".to_string(), Duration::from_secs(10));
+        let now = Instant::now();
+        assert_eq!(guard.check("Reset", None, now), Verdict::Execute);
+        assert_eq!(
+            guard.check("Reset", None, now + Duration::from_secs(4)),
+            Verdict::CoolingDown(Duration::from_secs(6))
+        );
+        assert_eq!(
+            guard.check("Reset", None, now + Duration::from_secs(10)),
+            Verdict::Execute
+        );
+        // Other commands are not affected
+        assert_eq!(guard.check("SwitchOn", None, now), Verdict::Execute);
+
+        // A dry run does not start the cooldown
+        let later = now + Duration::from_secs(30);
+        assert_eq!(guard.peek("Reset", Some("k"), later), Verdict::Execute);
+        assert_eq!(guard.check("Reset", Some("k"), later), Verdict::Execute);
+        assert_eq!(guard.peek("Reset", Some("k"), later), Verdict::Duplicate);
+    }
+
+    #[test]
+    fn test_authorize() {
+        let mut guard = CommandGuard::default();
+        guard
+            .allowed_principals
+            .insert("Unlock".to_string(), vec!["owner".to_string()]);
+        assert!(guard.authorize("Unlock", Some("owner")));
+        assert!(!guard.authorize("Unlock", Some("guest")));
+        assert!(!guard.authorize("Unlock", None));
+        // Unrestricted commands
+        assert!(guard.authorize("Lock", None));
+    }
+}
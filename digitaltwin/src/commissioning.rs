@@ -0,0 +1,462 @@
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use crate::models;
+use digitaltwin_core::{AssetAdministrationShell, AssetID, DeviceID, SlotValue};
+
+/// Devices recorded, the ones seen after dropped
+const DEVICES_KEPT: usize = 10_000;
+/// Other candidates listed for each slot of a proposal
+const ALTERNATIVES: usize = 3;
+
+#[derive(Parser, Clone)]
+pub struct CommissioningOptions {
+    /// listen to all the topics of the broker and record the devices seen with their values,
+    /// to propose the data sources of new twins
+    #[clap(long, env = "COMMISSIONING")]
+    commissioning: bool,
+
+    /// topic filter listened to in commissioning mode; the brokers delivering a message once per
+    /// matching subscription deliver the inputs twice, so commission on a separate instance
+    #[clap(long, default_value = "#", env = "COMMISSIONING_TOPIC")]
+    commissioning_topic: String,
+}
+
+/// Kind of the values of a device
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SampleKind {
+    Number,
+    Bool,
+    Text,
+}
+
+/// A device seen on the broker, with the range of its values
+#[derive(Debug, Clone, Serialize)]
+pub struct ObservedDevice {
+    pub device_id: DeviceID,
+    /// Topic of the latest value
+    pub topic: String,
+    pub samples: u64,
+    /// Kind of the latest value
+    pub kind: SampleKind,
+    /// Range of the numeric values
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub last_value: SlotValue,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// What a slot usually measures: the words naming its devices and the range of its values
+struct SlotProfile {
+    /// Found in the name of the slot
+    slot: &'static str,
+    /// Found in the IDs or topics of the devices
+    keywords: &'static [&'static str],
+    range: Option<(f64, f64)>,
+    boolean: bool,
+}
+
+/// Profiles of the slots of the twin types, the first one found in the slot name applies
+/// ("CurrentPowerDraw" is a power)
+const PROFILES: &[SlotProfile] = &[
+    SlotProfile {
+        slot: "power",
+        keywords: &["power", "pwr", "watt"],
+        range: Some((-50_000.0, 50_000.0)),
+        boolean: false,
+    },
+    SlotProfile {
+        slot: "current",
+        keywords: &["current", "curr", "amp"],
+        range: Some((0.0, 100.0)),
+        boolean: false,
+    },
+    SlotProfile {
+        slot: "voltage",
+        keywords: &["volt"],
+        range: Some((0.0, 1_000.0)),
+        boolean: false,
+    },
+    SlotProfile {
+        slot: "frequency",
+        keywords: &["freq", "hz"],
+        range: Some((45.0, 65.0)),
+        boolean: false,
+    },
+    SlotProfile {
+        slot: "temp",
+        keywords: &["temp"],
+        range: Some((-40.0, 150.0)),
+        boolean: false,
+    },
+    SlotProfile {
+        slot: "signal",
+        keywords: &["signal", "rssi", "dbm"],
+        range: Some((-120.0, 0.0)),
+        boolean: false,
+    },
+    SlotProfile {
+        slot: "flow",
+        keywords: &["flow"],
+        range: Some((0.0, 10_000.0)),
+        boolean: false,
+    },
+    SlotProfile {
+        slot: "pressure",
+        keywords: &["press", "bar"],
+        range: Some((0.0, 100.0)),
+        boolean: false,
+    },
+    SlotProfile {
+        slot: "position",
+        keywords: &["position", "bolt"],
+        range: Some((0.0, 100.0)),
+        boolean: false,
+    },
+    SlotProfile {
+        slot: "closed",
+        keywords: &["door", "closed", "contact"],
+        range: None,
+        boolean: true,
+    },
+    SlotProfile {
+        slot: "alarm",
+        keywords: &["alarm", "tamper"],
+        range: None,
+        boolean: true,
+    },
+];
+
+fn slot_profile(slot: &str) -> Option<&'static SlotProfile> {
+    let slot = slot.to_lowercase();
+    PROFILES.iter().find(|profile| slot.contains(profile.slot))
+}
+
+/// How well a device fits a slot, with the reasons, or None if its values can't feed it
+fn score(slot: &str, device: &ObservedDevice) -> Option<(i32, Vec<String>)> {
+    let profile = slot_profile(slot);
+    let boolean = profile.is_some_and(|profile| profile.boolean);
+    match device.kind {
+        SampleKind::Bool if boolean => {}
+        SampleKind::Number if !boolean => {}
+        _ => return None,
+    }
+    let mut score = 0;
+    let mut reasons = Vec::new();
+    let names = format!("{} {}", device.device_id, device.topic).to_lowercase();
+    let slot_name = slot.to_lowercase();
+    let keyword = profile
+        .into_iter()
+        .flat_map(|profile| profile.keywords.iter().copied())
+        .chain([slot_name.as_str()])
+        .find(|keyword| names.contains(keyword));
+    if let Some(keyword) = keyword {
+        score += 2;
+        reasons.push(format!("name matches \"{keyword}\""));
+    }
+    if let (Some((low, high)), Some(min), Some(max)) =
+        (profile.and_then(|profile| profile.range), device.min, device.max)
+    {
+        if low <= min && max <= high {
+            score += 1;
+            reasons.push(format!("values {min}..{max} within {low}..{high}"));
+        } else {
+            score -= 2;
+            reasons.push(format!("values {min}..{max} outside {low}..{high}"));
+        }
+    }
+    Some((score, reasons))
+}
+
+/// Proposed data source of a slot
+#[derive(Debug, Clone, Serialize)]
+pub struct SlotProposal {
+    pub slot: String,
+    /// Best device for the slot, if any fits
+    pub sensor: Option<DeviceID>,
+    pub score: i32,
+    pub reasons: Vec<String>,
+    /// Next best devices, not proposed for another slot
+    pub alternatives: Vec<DeviceID>,
+}
+
+/// Data sources proposed for a new twin, with its definition
+#[derive(Debug, Clone, Serialize)]
+pub struct BindingProposal {
+    pub asset_id: AssetID,
+    pub twin_type: String,
+    pub slots: Vec<SlotProposal>,
+    /// Definition of the twin binding the proposed devices, as YAML
+    pub yaml: String,
+}
+
+/// Devices seen on the broker in commissioning mode
+#[derive(Debug)]
+pub struct Commissioning {
+    filter: String,
+    devices: HashMap<DeviceID, ObservedDevice>,
+}
+
+pub type SharedCommissioning = Arc<Mutex<Commissioning>>;
+
+impl Commissioning {
+    /// The recorder of the devices, in commissioning mode
+    pub fn shared(options: &CommissioningOptions) -> Option<SharedCommissioning> {
+        options.commissioning.then(|| {
+            Arc::new(Mutex::new(Commissioning {
+                filter: options.commissioning_topic.clone(),
+                devices: HashMap::new(),
+            }))
+        })
+    }
+
+    /// Topic filter listened to
+    pub fn filter(&self) -> &str {
+        &self.filter
+    }
+
+    /// Record a value of a device
+    pub fn observe(&mut self, device: &DeviceID, topic: &str, value: &SlotValue, now: DateTime<Utc>) {
+        if !self.devices.contains_key(device) && self.devices.len() >= DEVICES_KEPT {
+            return;
+        }
+        let observed = self
+            .devices
+            .entry(device.clone())
+            .or_insert_with(|| ObservedDevice {
+                device_id: device.clone(),
+                topic: String::new(),
+                samples: 0,
+                kind: SampleKind::Text,
+                min: None,
+                max: None,
+                last_value: value.clone(),
+                first_seen: now,
+                last_seen: now,
+            });
+        observed.topic = topic.to_string();
+        observed.samples += 1;
+        observed.kind = match value {
+            SlotValue::Number(n) => {
+                observed.min = Some(observed.min.map_or(*n, |min| min.min(*n)));
+                observed.max = Some(observed.max.map_or(*n, |max| max.max(*n)));
+                SampleKind::Number
+            }
+            SlotValue::Bool(_) => SampleKind::Bool,
+            SlotValue::Text(_) => SampleKind::Text,
+        };
+        observed.last_value = value.clone();
+        observed.last_seen = now;
+    }
+
+    /// Record a payload published on a topic of its own: a number, a boolean or a numeric
+    /// string is the value of a device named as the topic. False for other payloads.
+    pub fn observe_raw(&mut self, topic: &str, payload: &[u8], now: DateTime<Utc>) -> bool {
+        let value = match serde_json::from_slice(payload) {
+            Ok(serde_json::Value::Number(n)) => n.as_f64().map(SlotValue::Number),
+            Ok(serde_json::Value::Bool(b)) => Some(SlotValue::Bool(b)),
+            Ok(serde_json::Value::String(s)) => s.trim().parse().ok().map(SlotValue::Number),
+            _ => std::str::from_utf8(payload)
+                .ok()
+                .and_then(|s| s.trim().parse().ok())
+                .map(SlotValue::Number),
+        };
+        match value {
+            Some(value) => {
+                self.observe(&DeviceID::from(topic), topic, &value, now);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The devices seen, sorted by ID
+    pub fn devices(&self) -> Vec<ObservedDevice> {
+        let mut devices: Vec<ObservedDevice> = self.devices.values().cloned().collect();
+        devices.sort_by(|a, b| a.device_id.cmp(&b.device_id));
+        devices
+    }
+
+    /// Propose a device for each slot of a twin type, each device bound at most once, the
+    /// best fits first
+    pub fn propose(&self, twin_type: &str, asset_id: AssetID) -> Result<BindingProposal, String> {
+        let (_, slots) = models::create_actor(twin_type, serde_json::Value::Null)
+            .ok_or_else(|| format!("unknown twin type {twin_type}"))?;
+        let devices = self.devices();
+        let mut candidates: Vec<(i32, &str, &ObservedDevice, Vec<String>)> = slots
+            .iter()
+            .flat_map(|slot| {
+                devices.iter().filter_map(move |device| {
+                    score(slot, device).map(|(score, reasons)| (score, slot.as_str(), device, reasons))
+                })
+            })
+            .filter(|(score, ..)| *score > 0)
+            .collect();
+        candidates.sort_by(|a, b| {
+            b.0.cmp(&a.0)
+                .then_with(|| a.1.cmp(b.1))
+                .then_with(|| a.2.device_id.cmp(&b.2.device_id))
+        });
+        let mut proposed: HashMap<&str, (i32, &ObservedDevice, Vec<String>)> = HashMap::new();
+        let mut bound = HashSet::new();
+        for (score, slot, device, reasons) in &candidates {
+            if !proposed.contains_key(slot) && !bound.contains(&device.device_id) {
+                bound.insert(device.device_id.clone());
+                proposed.insert(slot, (*score, device, reasons.clone()));
+            }
+        }
+        let slots: Vec<SlotProposal> = slots
+            .iter()
+            .map(|slot| {
+                let best = proposed.get(slot.as_str());
+                SlotProposal {
+                    slot: slot.clone(),
+                    sensor: best.map(|(_, device, _)| device.device_id.clone()),
+                    score: best.map_or(0, |(score, ..)| *score),
+                    reasons: best.map(|(_, _, reasons)| reasons.clone()).unwrap_or_default(),
+                    alternatives: candidates
+                        .iter()
+                        .filter(|(_, candidate, device, _)| {
+                            candidate == slot && !bound.contains(&device.device_id)
+                        })
+                        .map(|(_, _, device, _)| device.device_id.clone())
+                        .take(ALTERNATIVES)
+                        .collect(),
+                }
+            })
+            .collect();
+        let yaml = definition(twin_type, &asset_id, &slots)?;
+        Ok(BindingProposal {
+            asset_id,
+            twin_type: twin_type.to_string(),
+            slots,
+            yaml,
+        })
+    }
+}
+
+/// Definition of a twin with a data source for each proposed slot, as YAML
+fn definition(twin_type: &str, asset_id: &AssetID, slots: &[SlotProposal]) -> Result<String, String> {
+    let proposed = slots
+        .iter()
+        .filter_map(|slot| slot.sensor.as_ref().map(|sensor| (slot, sensor)));
+    let (mut values, mut sensors) = (Vec::new(), Vec::new());
+    for (slot, sensor) in proposed {
+        let boolean = slot_profile(&slot.slot).is_some_and(|profile| profile.boolean);
+        values.push(serde_json::json!({
+            "element_type": "collection",
+            "id_short": slot.slot,
+            "value": [
+                {
+                    "element_type": "property",
+                    "id_short": format!("{}Value", slot.slot),
+                    "value_type": if boolean { "bool" } else { "float" },
+                    "value": if boolean { serde_json::json!(false) } else { serde_json::json!(0.0) },
+                },
+                {
+                    "element_type": "referenceelement",
+                    "id_short": "DataSource",
+                    "value": format!("{asset_id}:datasources#Sensor{}", slot.slot),
+                },
+            ],
+        }));
+        sensors.push(serde_json::json!({
+            "element_type": "collection",
+            "id_short": format!("Sensor{}", slot.slot),
+            "value": [
+                { "element_type": "property", "id_short": "SensorID", "value_type": "string", "value": sensor },
+                { "element_type": "property", "id_short": "MeasurementType", "value_type": "string", "value": slot.slot },
+            ],
+        }));
+    }
+    let aas: AssetAdministrationShell = serde_json::from_value(serde_json::json!({
+        "id": asset_id,
+        "id_short": twin_type,
+        "description": "Proposed in commissioning mode",
+        "submodels": [
+            {
+                "id": format!("{asset_id}:configuration"),
+                "id_short": "TwinConfiguration",
+                "elements": [
+                    { "element_type": "property", "id_short": "TwinType", "value_type": "string", "value": twin_type },
+                ],
+            },
+            {
+                "id": format!("{asset_id}:power"),
+                "id_short": "PowerAndElectrical",
+                "elements": values,
+            },
+            {
+                "id": format!("{asset_id}:datasources"),
+                "id_short": "IoTDataSources",
+                "elements": [
+                    { "element_type": "collection", "id_short": "Sensors", "value": sensors },
+                ],
+            },
+        ],
+    }))
+    .map_err(|e| e.to_string())?;
+    serde_yaml::to_string(&aas).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use digitaltwin_core::IndexedShell;
+
+    #[test]
+    fn test_propose() {
+        let mut commissioning = Commissioning {
+            filter: "#".to_string(),
+            devices: HashMap::new(),
+        };
+        let now = Utc::now();
+        for value in [0.0, 3.2, 7.1] {
+            let value = SlotValue::Number(value * 1000.0);
+            commissioning.observe(&"urn:iot-sensor:pwr-7".into(), "twins/updates", &value, now);
+        }
+        for value in [6.0, 16.0] {
+            let value = SlotValue::Number(value);
+            commissioning.observe(&"urn:iot-sensor:amp-7".into(), "twins/updates", &value, now);
+        }
+        // Named as a current, with the values of a power
+        let value = SlotValue::Number(2300.0);
+        commissioning.observe(&"urn:iot-sensor:curr-9".into(), "twins/updates", &value, now);
+        assert!(commissioning.observe_raw("site/charger/rssi", b"-67", now));
+        assert!(!commissioning.observe_raw("site/charger/status", b"{\"ok\": true}", now));
+        assert_eq!(commissioning.devices().len(), 4);
+
+        let proposal = commissioning
+            .propose("ChargingPoint", "urn:aas:site:charging-station:cp-7".into())
+            .unwrap();
+        let sensors: Vec<Option<&str>> = proposal
+            .slots
+            .iter()
+            .map(|slot| slot.sensor.as_ref().map(|sensor| sensor.as_ref()))
+            .collect();
+        assert_eq!(
+            sensors,
+            [
+                Some("urn:iot-sensor:pwr-7"),
+                Some("urn:iot-sensor:amp-7"),
+                Some("site/charger/rssi")
+            ]
+        );
+        assert_eq!(proposal.slots[0].score, 3);
+        assert!(proposal.slots[1].alternatives.is_empty());
+
+        let aas = AssetAdministrationShell::from_reader(proposal.yaml.as_bytes()).unwrap();
+        assert_eq!(aas.twin_type().as_deref(), Some("ChargingPoint"));
+        let aas = IndexedShell::new(aas);
+        let reference = aas
+            .reference_value("PowerAndElectrical.InputCurrent.DataSource")
+            .unwrap();
+        assert_eq!(aas.sensor_id(reference), Some("urn:iot-sensor:amp-7"));
+        assert!(commissioning.propose("Toaster", "urn:aas:x".into()).is_err());
+    }
+}
@@ -0,0 +1,400 @@
+use std::collections::{BTreeSet, HashMap};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::virtual_sensors::Expression;
+use digitaltwin_core::SlotValue;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
+    Equal,
+    NotEqual,
+}
+
+/// Comparison operators, the two-character ones first
+const COMPARISONS: [(&str, Comparison); 6] = [
+    (">=", Comparison::GreaterOrEqual),
+    ("<=", Comparison::LessOrEqual),
+    ("==", Comparison::Equal),
+    ("!=", Comparison::NotEqual),
+    (">", Comparison::Greater),
+    ("<", Comparison::Less),
+];
+
+/// Guard over the latest values of several slots of a twin, as comparisons of arithmetic
+/// expressions joined by "&&" and "||", e.g. "InputCurrent > 16 && CurrentPowerDraw < 100".
+/// Boolean slots are worth 1 or 0; a comparison reading a slot without a value is false.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Guard {
+    Compare(Comparison, Expression, Expression),
+    Not(Box<Guard>),
+    All(Vec<Guard>),
+    Any(Vec<Guard>),
+}
+
+/// Split at the separators outside of parentheses and braces
+fn split_top_level<'a>(s: &'a str, separator: &str) -> Vec<&'a str> {
+    let mut parts = Vec::new();
+    let (mut depth, mut start) = (0i32, 0);
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' | '{' => depth += 1,
+            ')' | '}' => depth -= 1,
+            _ if depth == 0 && i >= start && s[i..].starts_with(separator) => {
+                parts.push(&s[start..i]);
+                start = i + separator.len();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+/// Position of the parenthesis closing the one the string starts with
+fn closing_parenthesis(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+impl FromStr for Guard {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        for (separator, join) in [("||", Guard::Any as fn(Vec<Guard>) -> Guard), ("&&", Guard::All)] {
+            let parts = split_top_level(s, separator);
+            if parts.len() > 1 {
+                return parts
+                    .into_iter()
+                    .map(str::parse)
+                    .collect::<Result<_, _>>()
+                    .map(join);
+            }
+        }
+        if let Some(negated) = s.strip_prefix('!').filter(|rest| !rest.starts_with('=')) {
+            return Ok(Guard::Not(Box::new(negated.parse()?)));
+        }
+        // A guard between parentheses, not an expression as in "(a + b) > 1"
+        if s.starts_with('(') && closing_parenthesis(s) == Some(s.len() - 1) {
+            return s[1..s.len() - 1].parse();
+        }
+        let mut depth = 0;
+        for (i, c) in s.char_indices() {
+            match c {
+                '(' | '{' => depth += 1,
+                ')' | '}' => depth -= 1,
+                _ if depth == 0 => {
+                    if let Some((operator, comparison)) = COMPARISONS
+                        .iter()
+                        .find(|(operator, _)| s[i..].starts_with(operator))
+                    {
+                        let left = s[..i].parse().map_err(|e| format!("invalid guard {s}: {e}"))?;
+                        let right = s[i + operator.len()..]
+                            .parse()
+                            .map_err(|e| format!("invalid guard {s}: {e}"))?;
+                        return Ok(Guard::Compare(*comparison, left, right));
+                    }
+                }
+                _ => {}
+            }
+        }
+        Err(format!("invalid guard {s}: expected a comparison"))
+    }
+}
+
+impl Guard {
+    /// The slots the guard reads
+    pub fn slots(&self) -> BTreeSet<String> {
+        match self {
+            Guard::Compare(_, left, right) => left
+                .devices()
+                .into_iter()
+                .chain(right.devices())
+                .map(|slot| slot.to_string())
+                .collect(),
+            Guard::Not(guard) => guard.slots(),
+            Guard::All(guards) | Guard::Any(guards) => guards.iter().flat_map(Guard::slots).collect(),
+        }
+    }
+
+    pub fn evaluate(&self, value_of: &impl Fn(&str) -> Option<f64>) -> bool {
+        match self {
+            Guard::Compare(comparison, left, right) => {
+                let (Some(left), Some(right)) = (left.evaluate(value_of), right.evaluate(value_of)) else {
+                    return false;
+                };
+                match comparison {
+                    Comparison::Greater => left > right,
+                    Comparison::GreaterOrEqual => left >= right,
+                    Comparison::Less => left < right,
+                    Comparison::LessOrEqual => left <= right,
+                    Comparison::Equal => left == right,
+                    Comparison::NotEqual => left != right,
+                }
+            }
+            Guard::Not(guard) => !guard.evaluate(value_of),
+            Guard::All(guards) => guards.iter().all(|guard| guard.evaluate(value_of)),
+            Guard::Any(guards) => guards.iter().any(|guard| guard.evaluate(value_of)),
+        }
+    }
+}
+
+/// A state the twin must have been in for a while, a timer on the condition
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dwell {
+    /// State of the twin, or of one of its regions
+    pub state: String,
+    pub at_least: Duration,
+}
+
+/// What a twin does when one of its conditions becomes true
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    /// Execute a command, with the arguments of the condition
+    Command(String),
+    /// Emit an event, with the arguments of the condition as payload
+    Event(String),
+}
+
+/// A command executed, or an event emitted, by a twin when a guard over its slots becomes
+/// true, optionally after being in a state for a while
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    pub name: String,
+    pub guard: Guard,
+    pub dwell: Option<Dwell>,
+    pub action: Action,
+    pub args: serde_json::Value,
+}
+
+impl Condition {
+    /// Read the conditions declared in the "Conditions" collection of the AAS of a twin
+    pub fn from_aas(conditions: &serde_json::Value) -> Vec<Result<Condition, String>> {
+        let Some(conditions) = conditions.as_object() else {
+            return Vec::new();
+        };
+        conditions
+            .iter()
+            .map(|(name, condition)| {
+                let field = |field: &str| {
+                    condition
+                        .get(field)
+                        .and_then(|v| v.as_str())
+                        .filter(|v| !v.trim().is_empty())
+                };
+                let dwell = match (field("InState"), condition.get("ForSeconds")) {
+                    (Some(state), seconds) => Some(Dwell {
+                        state: state.to_string(),
+                        at_least: Duration::from_secs(seconds.map_or(Some(0), |s| s.as_u64()).ok_or_else(
+                            || format!("invalid ForSeconds in condition {name}: expected seconds"),
+                        )?),
+                    }),
+                    (None, Some(_)) => return Err(format!("ForSeconds without InState in condition {name}")),
+                    (None, None) => None,
+                };
+                // Without a guard, a timer on a state
+                let guard = match (field("When"), &dwell) {
+                    (Some(when), _) => when.parse()?,
+                    (None, Some(_)) => Guard::All(Vec::new()),
+                    (None, None) => return Err(format!("missing When in condition {name}")),
+                };
+                let action = match (field("Command"), field("Event")) {
+                    (Some(command), None) => Action::Command(command.to_string()),
+                    (None, Some(event)) => Action::Event(event.to_string()),
+                    (Some(_), Some(_)) => return Err(format!("both Command and Event in condition {name}")),
+                    (None, None) => return Err(format!("missing Command or Event in condition {name}")),
+                };
+                Ok(Condition {
+                    name: name.clone(),
+                    guard,
+                    dwell,
+                    action,
+                    args: condition
+                        .get("Args")
+                        .cloned()
+                        .unwrap_or_else(|| serde_json::json!({})),
+                })
+            })
+            .collect()
+    }
+
+    /// Arguments of the command, or payload of the event: the declared ones, with the name
+    /// of the condition and the values of the slots it read
+    pub fn command_args(&self, values: &HashMap<String, SlotValue>) -> serde_json::Value {
+        let mut args = self.args.clone();
+        if let Some(args) = args.as_object_mut() {
+            let slots: serde_json::Map<_, _> = self
+                .guard
+                .slots()
+                .into_iter()
+                .filter_map(|slot| {
+                    let value = serde_json::to_value(values.get(&slot)?).ok()?;
+                    Some((slot, value))
+                })
+                .collect();
+            args.insert("condition".to_string(), self.name.clone().into());
+            args.insert("slots".to_string(), slots.into());
+        }
+        args
+    }
+}
+
+/// Numeric value of a slot in a guard
+fn guard_value(value: &SlotValue) -> Option<f64> {
+    match value {
+        SlotValue::Number(n) => Some(*n),
+        SlotValue::Bool(b) => Some(if *b { 1.0 } else { 0.0 }),
+        SlotValue::Text(s) => s.trim().parse().ok(),
+    }
+}
+
+/// Conditions of a twin, with whether each one is met
+#[derive(Debug, Default)]
+pub struct Conditions {
+    conditions: Vec<(Condition, bool)>,
+}
+
+impl Conditions {
+    pub fn new(conditions: Vec<Condition>) -> Self {
+        Conditions {
+            conditions: conditions
+                .into_iter()
+                .map(|condition| (condition, false))
+                .collect(),
+        }
+    }
+
+    /// The conditions met with the latest values of the slots and the time spent in the
+    /// current states, and not met before: a condition fires once, then again only after
+    /// being unmet
+    pub fn newly_met(
+        &mut self,
+        values: &HashMap<String, SlotValue>,
+        dwell_in: &impl Fn(&str) -> Option<Duration>,
+    ) -> Vec<Condition> {
+        let value_of = |slot: &str| values.get(slot).and_then(guard_value);
+        let mut met = Vec::new();
+        for (condition, was_met) in &mut self.conditions {
+            let is_met = condition.guard.evaluate(&value_of)
+                && condition.dwell.as_ref().is_none_or(|dwell| {
+                    dwell_in(&dwell.state).is_some_and(|dwell_time| dwell_time >= dwell.at_least)
+                });
+            if is_met && !*was_met {
+                met.push(condition.clone());
+            }
+            *was_met = is_met;
+        }
+        met
+    }
+
+    /// Whether a timer is running: the twin is in the state of a condition not met yet,
+    /// which may be met by just waiting
+    pub fn timer_running(&self, dwell_in: &impl Fn(&str) -> Option<Duration>) -> bool {
+        self.conditions.iter().any(|(condition, was_met)| {
+            !*was_met
+                && condition
+                    .dwell
+                    .as_ref()
+                    .is_some_and(|dwell| dwell_in(&dwell.state).is_some())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conditions() {
+        let declared = serde_json::json!({
+            "CurrentWithoutPower": {
+                "When": "InputCurrent > 16 && (CurrentPowerDraw < 100 || !(Online == 1))",
+                "Command": "SensorMismatch",
+                "Args": { "severity": "high" },
+            },
+            "Broken": { "When": "InputCurrent >" , "Command": "Reset" },
+        });
+        let (valid, invalid): (Vec<_>, Vec<_>) = Condition::from_aas(&declared)
+            .into_iter()
+            .partition(Result::is_ok);
+        assert_eq!(invalid.len(), 1);
+        let condition = valid.into_iter().next().unwrap().unwrap();
+        assert_eq!(
+            condition.guard.slots().into_iter().collect::<Vec<_>>(),
+            ["CurrentPowerDraw", "InputCurrent", "Online"]
+        );
+        assert_eq!(
+            "(InputCurrent + 1) * 2 >= max(CurrentPowerDraw, 3)"
+                .parse::<Guard>()
+                .map(|g| g.slots().len()),
+            Ok(2)
+        );
+        assert!("InputCurrent".parse::<Guard>().is_err());
+
+        assert_eq!(condition.action, Action::Command("SensorMismatch".to_string()));
+        let no_state = |_: &str| None;
+        let mut conditions = Conditions::new(vec![condition]);
+        let mut values = HashMap::from([
+            ("InputCurrent".to_string(), SlotValue::Number(20.0)),
+            ("Online".to_string(), SlotValue::Bool(true)),
+        ]);
+        // The power has no value yet
+        assert!(conditions.newly_met(&values, &no_state).is_empty());
+        values.insert("CurrentPowerDraw".to_string(), SlotValue::Number(50.0));
+        let met = conditions.newly_met(&values, &no_state);
+        assert_eq!(met.len(), 1);
+        let args = met[0].command_args(&values);
+        assert_eq!(args["severity"], "high");
+        assert_eq!(args["condition"], "CurrentWithoutPower");
+        assert_eq!(args["slots"]["CurrentPowerDraw"], 50.0);
+        // Still met: fired once
+        assert!(conditions.newly_met(&values, &no_state).is_empty());
+        values.insert("CurrentPowerDraw".to_string(), SlotValue::Number(3000.0));
+        assert!(conditions.newly_met(&values, &no_state).is_empty());
+        values.insert("Online".to_string(), SlotValue::Bool(false));
+        assert_eq!(conditions.newly_met(&values, &no_state).len(), 1);
+    }
+
+    #[test]
+    fn test_timer_conditions() {
+        let declared = serde_json::json!({
+            "FaultTooLong": { "InState": "Fault", "ForSeconds": 3600, "Event": "FaultEscalated" },
+            "NoAction": { "InState": "Fault" },
+            "NoState": { "ForSeconds": 60, "Command": "Reset" },
+        });
+        let mut conditions: Vec<_> = Condition::from_aas(&declared).into_iter().collect();
+        conditions.sort_by_key(Result::is_ok);
+        assert!(conditions[..2].iter().all(Result::is_err));
+        let condition = conditions.pop().unwrap().unwrap();
+        assert_eq!(condition.action, Action::Event("FaultEscalated".to_string()));
+        assert!(condition.guard.slots().is_empty());
+
+        let mut conditions = Conditions::new(vec![condition]);
+        let values = HashMap::new();
+        let in_fault = |seconds| move |state: &str| (state == "Fault").then(|| Duration::from_secs(seconds));
+        assert!(!conditions.timer_running(&|_: &str| None));
+        assert!(conditions.timer_running(&in_fault(10)));
+        assert!(conditions.newly_met(&values, &in_fault(10)).is_empty());
+        assert_eq!(conditions.newly_met(&values, &in_fault(3600)).len(), 1);
+        // Fired once while in the state
+        assert!(!conditions.timer_running(&in_fault(4000)));
+        assert!(conditions.newly_met(&values, &in_fault(4000)).is_empty());
+    }
+}
@@ -0,0 +1,279 @@
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use thiserror::Error as ThisError;
+
+use crate::{
+    actuation, alerting, backup, commissioning, failover, federation, historian, history, importer, ipc, kpi,
+    latency_budget, manager, network_receiver, outbox, predictor, rate_limit, rest_server, scheduler,
+    secrets, sessions, sinks, smart_charging, sparkplug, webhooks,
+};
+
+/// Environment variable naming the configuration file, as the --config option
+const CONFIG_ENV: &str = "DT_CONFIG";
+
+/// Options of the whole runtime. Each option is taken from the command line, then from
+/// its environment variable, then from the configuration file, then from its default.
+#[derive(Parser)]
+#[command(subcommand_negates_reqs = true)]
+pub struct RuntimeConfig {
+    /// YAML (or JSON) configuration file, with an entry for each option, e.g.
+    /// "broker: mqtt.local", optionally grouped in sections ("network: {broker: mqtt.local}")
+    #[clap(long, env = CONFIG_ENV)]
+    pub config: Option<PathBuf>,
+
+    #[clap(flatten)]
+    pub manager: manager::ManagerOptions,
+
+    #[clap(flatten)]
+    pub network: network_receiver::NetworkOptions,
+
+    #[clap(flatten)]
+    pub rest: rest_server::RestOptions,
+
+    #[clap(flatten)]
+    pub secrets: secrets::SecretsOptions,
+
+    #[clap(flatten)]
+    pub rate_limit: rate_limit::RateLimitOptions,
+
+    #[clap(flatten)]
+    pub scheduler: scheduler::SchedulerOptions,
+
+    #[clap(flatten)]
+    pub smart_charging: smart_charging::SmartChargingOptions,
+
+    #[clap(flatten)]
+    pub failover: failover::FailoverOptions,
+
+    #[clap(flatten)]
+    pub historian: historian::HistorianOptions,
+
+    #[clap(flatten)]
+    pub history: history::HistoryOptions,
+
+    #[clap(flatten)]
+    pub sessions: sessions::SessionOptions,
+
+    #[clap(flatten)]
+    pub predictor: predictor::PredictorOptions,
+
+    #[clap(flatten)]
+    pub webhooks: webhooks::WebhookOptions,
+
+    #[clap(flatten)]
+    pub actuation: actuation::ActuationOptions,
+
+    #[clap(flatten)]
+    pub sinks: sinks::SinkOptions,
+
+    #[clap(flatten)]
+    pub alerting: alerting::AlertingOptions,
+
+    #[clap(flatten)]
+    pub kpi: kpi::KpiOptions,
+
+    #[clap(flatten)]
+    pub ipc: ipc::IpcOptions,
+
+    #[clap(flatten)]
+    pub import: importer::ImportOptions,
+
+    #[clap(flatten)]
+    pub sparkplug: sparkplug::SparkplugOptions,
+
+    #[clap(flatten)]
+    pub outbox: outbox::OutboxOptions,
+
+    #[clap(flatten)]
+    pub latency_budget: latency_budget::LatencyBudgetOptions,
+
+    #[clap(flatten)]
+    pub federation: federation::FederationOptions,
+
+    #[clap(flatten)]
+    pub commissioning: commissioning::CommissioningOptions,
+
+    /// One-off tasks; without a command, the runtime runs the twins
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Write a twin definition for each asset of a fleet inventory, from a template, then exit.
+    /// The running runtime starts the new twins at its next reload.
+    Import(importer::ImportArgs),
+    /// Query the history of a slot of a twin from a running runtime, e.g. the average power
+    /// per state in the last week, and print it as JSON
+    History(history::HistoryArgs),
+    /// Save the definitions, snapshots, subscriptions and history metadata of the twins of a
+    /// running runtime to a backup file
+    Backup(backup::BackupArgs),
+    /// Restore a backup file on a running runtime: write the definitions, reload them and
+    /// restore the twins from their snapshots
+    Restore(backup::RestoreArgs),
+    /// List the twins of a running runtime, or report on one of them, with their state,
+    /// bound sensors, unbound slots and latest input, and print them as JSON
+    Twins(manager::TwinsArgs),
+}
+
+#[derive(ThisError, Debug)]
+pub enum ConfigError {
+    #[error("cannot read {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("invalid configuration file {0}: {1}")]
+    Yaml(PathBuf, serde_yaml::Error),
+    #[error("unknown option in the configuration file: {0}")]
+    UnknownOption(String),
+    #[error("invalid value for {0} in the configuration file: only scalars and lists are allowed")]
+    InvalidValue(String),
+    /// Invalid command line, or help/version requested
+    #[error(transparent)]
+    Cli(#[from] clap::Error),
+}
+
+impl RuntimeConfig {
+    /// Load the configuration from the command line, the environment and the configuration file
+    pub fn load() -> Result<Self, ConfigError> {
+        let matches = matches_from(std::env::args_os())?;
+        Ok(Self::from_arg_matches(&matches)?)
+    }
+}
+
+/// The configuration file named on the command line (--config <path> or --config=<path>),
+/// or in the environment
+fn config_path(args: &[OsString]) -> Option<PathBuf> {
+    let mut args = args.iter().skip(1).map(|arg| arg.to_string_lossy());
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next().map(|path| PathBuf::from(path.as_ref()));
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(PathBuf::from(path));
+        }
+    }
+    std::env::var_os(CONFIG_ENV).map(PathBuf::from)
+}
+
+/// The option values set in a configuration file, as (option, values) pairs. Sections
+/// (mappings) only group the options: their names are ignored.
+fn file_values(path: &Path) -> Result<Vec<(String, Vec<String>)>, ConfigError> {
+    let file = std::fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_path_buf(), e))?;
+    let document: serde_yaml::Value =
+        serde_yaml::from_str(&file).map_err(|e| ConfigError::Yaml(path.to_path_buf(), e))?;
+    let mut values = Vec::new();
+    collect_values(document, 0, &mut values)?;
+    Ok(values)
+}
+
+fn collect_values(
+    value: serde_yaml::Value,
+    depth: usize,
+    values: &mut Vec<(String, Vec<String>)>,
+) -> Result<(), ConfigError> {
+    let serde_yaml::Value::Mapping(mapping) = value else {
+        return Ok(());
+    };
+    for (key, value) in mapping {
+        let key = scalar(&key).ok_or_else(|| ConfigError::InvalidValue(format!("{key:?}")))?;
+        match value {
+            serde_yaml::Value::Null => {}
+            serde_yaml::Value::Mapping(_) if depth == 0 => collect_values(value, depth + 1, values)?,
+            serde_yaml::Value::Sequence(items) => {
+                let items = items.iter().map(scalar).collect::<Option<Vec<_>>>();
+                values.push((key.clone(), items.ok_or(ConfigError::InvalidValue(key))?));
+            }
+            value => {
+                let value = scalar(&value).ok_or_else(|| ConfigError::InvalidValue(key.clone()))?;
+                values.push((key, vec![value]));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn scalar(value: &serde_yaml::Value) -> Option<String> {
+    match value {
+        serde_yaml::Value::Bool(b) => Some(b.to_string()),
+        serde_yaml::Value::Number(n) => Some(n.to_string()),
+        serde_yaml::Value::String(s) => Some(s.clone()),
+        _ => None,
+    }
+}
+
+/// Parse the command line, with the values of the configuration file as defaults
+fn matches_from(args: impl IntoIterator<Item = OsString>) -> Result<ArgMatches, ConfigError> {
+    let args: Vec<_> = args.into_iter().collect();
+    let mut command = RuntimeConfig::command();
+    if let Some(path) = config_path(&args) {
+        for (key, values) in file_values(&path)? {
+            // Options are named as on the command line or as their fields ("reload-interval" or "reload_interval")
+            let id = command
+                .get_arguments()
+                .find(|arg| {
+                    arg.get_long() == Some(key.as_str()) || arg.get_id() == key.replace('-', "_").as_str()
+                })
+                .map(|arg| arg.get_id().clone())
+                .ok_or_else(|| ConfigError::UnknownOption(key.clone()))?;
+            // Defaults are static strings; the few configuration values live as long as the runtime
+            let values: Vec<&'static str> = values
+                .into_iter()
+                .map(|value| &*Box::leak(value.into_boxed_str()))
+                .collect();
+            // A value in the file satisfies the required options, such as the broker
+            command = command.mut_arg(id, |arg| arg.default_values(values).required(false));
+        }
+    }
+    Ok(command.try_get_matches_from(args)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_config(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("dt-config-{}-{name}.yaml", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_layering() {
+        let path = write_config(
+            "layering",
+            "network:\n  broker: mqtt.local\n  topic: site/updates\nmanager:\n  reload-interval: 60\n  restart_unhealthy: true\n",
+        );
+        let args = [
+            "digitaltwin",
+            "--config",
+            path.to_str().unwrap(),
+            "--topic",
+            "cli/updates",
+        ];
+        let matches = matches_from(args.map(OsString::from)).unwrap();
+        assert_eq!(matches.get_one::<String>("broker").unwrap(), "mqtt.local");
+        assert_eq!(matches.get_one::<String>("topic").unwrap(), "cli/updates");
+        assert_eq!(matches.get_one::<u64>("reload_interval"), Some(&60));
+        assert_eq!(matches.get_one::<bool>("restart_unhealthy"), Some(&true));
+        // Defaults apply to the options missing from the file
+        assert_eq!(matches.get_one::<String>("status_topic").unwrap(), "twins/status");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let path = write_config("invalid", "broker: mqtt.local\nbrokers: typo\n");
+        let args = ["digitaltwin", &format!("--config={}", path.display())];
+        assert!(matches!(
+            matches_from(args.map(OsString::from)),
+            Err(ConfigError::UnknownOption(key)) if key == "brokers"
+        ));
+        std::fs::write(&path, "network:\n  broker: {host: mqtt.local}\n").unwrap();
+        assert!(matches!(
+            matches_from(args.map(OsString::from)),
+            Err(ConfigError::InvalidValue(key)) if key == "broker"
+        ));
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,186 @@
+use std::collections::BTreeMap;
+
+use digitaltwin_core::{AssetID, DeviceID};
+
+/// Whether a device ID is a pattern: `*` matches any sequence of characters, `?` a
+/// single character (e.g., "urn:iot-sensor:carpark-7:*")
+pub fn is_pattern(device: &str) -> bool {
+    device.contains(['*', '?'])
+}
+
+/// Match a device ID against a glob pattern
+pub fn pattern_matches(pattern: &str, device: &str) -> bool {
+    let (pattern, device): (Vec<char>, Vec<char>) = (pattern.chars().collect(), device.chars().collect());
+    // Iterative matching, backtracking to the latest star only
+    let (mut p, mut d) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while d < device.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, d));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == device[d] => {
+                p += 1;
+                d += 1;
+            }
+            _ => match star {
+                Some((star_p, star_d)) => {
+                    p = star_p + 1;
+                    d = star_d + 1;
+                    star = Some((star_p, star_d + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[derive(Debug, Default)]
+struct Node {
+    children: BTreeMap<char, Node>,
+    /// Subscriptions whose literal prefix ends here: the rest of the pattern and the subscriber
+    entries: Vec<(String, AssetID)>,
+}
+
+impl Node {
+    fn is_empty(&self) -> bool {
+        self.children.is_empty() && self.entries.is_empty()
+    }
+
+    /// Drop the entries rejected by `keep` and the branches left empty
+    fn retain(&mut self, keep: &mut impl FnMut(&AssetID) -> bool) {
+        self.entries.retain(|(_, asset)| keep(asset));
+        self.children.retain(|_, child| {
+            child.retain(keep);
+            !child.is_empty()
+        });
+    }
+}
+
+/// Subscriptions to device ID patterns, indexed by their literal prefix: a lookup
+/// only checks the patterns whose prefix is a prefix of the device ID
+#[derive(Debug, Default)]
+pub struct DeviceTrie {
+    root: Node,
+    len: usize,
+}
+
+impl DeviceTrie {
+    /// Subscribe an asset to a pattern, unless already subscribed
+    pub fn insert(&mut self, pattern: &str, asset: &AssetID) {
+        let split = pattern.find(['*', '?']).unwrap_or(pattern.len());
+        let (prefix, rest) = pattern.split_at(split);
+        let node = prefix
+            .chars()
+            .fold(&mut self.root, |node, c| node.children.entry(c).or_default());
+        if !node.entries.iter().any(|(r, a)| r == rest && a == asset) {
+            node.entries.push((rest.to_string(), asset.clone()));
+            self.len += 1;
+        }
+    }
+
+    /// Unsubscribe an asset from a pattern
+    pub fn remove(&mut self, pattern: &str, asset: &AssetID) {
+        let split = pattern.find(['*', '?']).unwrap_or(pattern.len());
+        let (prefix, rest) = pattern.split_at(split);
+        let Some(node) = prefix
+            .chars()
+            .try_fold(&mut self.root, |node, c| node.children.get_mut(&c))
+        else {
+            return;
+        };
+        let before = node.entries.len();
+        node.entries.retain(|(r, a)| r != rest || a != asset);
+        self.len -= before - node.entries.len();
+    }
+
+    /// Subscribers of the patterns matching a device ID, without duplicates
+    pub fn matches(&self, device: &DeviceID) -> Vec<AssetID> {
+        let mut found: Vec<AssetID> = Vec::new();
+        let mut node = Some(&self.root);
+        let mut chars = device.char_indices();
+        while let Some(current) = node {
+            let rest = chars.as_str();
+            for (pattern, asset) in &current.entries {
+                if pattern_matches(pattern, rest) && !found.contains(asset) {
+                    found.push(asset.clone());
+                }
+            }
+            node = chars.next().and_then(|(_, c)| current.children.get(&c));
+        }
+        found
+    }
+
+    /// Keep only the subscriptions of the assets accepted by `keep`
+    pub fn retain(&mut self, mut keep: impl FnMut(&AssetID) -> bool) {
+        let mut removed = 0;
+        self.root.retain(&mut |asset| {
+            let kept = keep(asset);
+            removed += usize::from(!kept);
+            kept
+        });
+        self.len -= removed;
+    }
+
+    /// Number of pattern subscriptions
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pattern_matches() {
+        assert!(pattern_matches("urn:sensor:*", "urn:sensor:1"));
+        assert!(pattern_matches("urn:sensor:*", "urn:sensor:"));
+        assert!(pattern_matches("urn:*:bay-?", "urn:carpark-7:level-2:bay-4"));
+        assert!(!pattern_matches("urn:*:bay-?", "urn:carpark-7:bay-42"));
+        assert!(pattern_matches("*-current", "urn:meter-current"));
+        assert!(!pattern_matches("urn:sensor:1", "urn:sensor:12"));
+        assert!(!is_pattern("urn:sensor:1"));
+        assert!(is_pattern("urn:sensor:?"));
+    }
+
+    #[test]
+    fn test_trie() {
+        let (area, level, other) = (
+            AssetID::from("urn:twin:area"),
+            AssetID::from("urn:twin:level"),
+            AssetID::from("urn:twin:x"),
+        );
+        let mut trie = DeviceTrie::default();
+        trie.insert("urn:iot-sensor:carpark-7:*", &area);
+        trie.insert("urn:iot-sensor:carpark-7:*", &area);
+        trie.insert("urn:iot-sensor:carpark-7:level-2:*", &level);
+        trie.insert("urn:iot-sensor:*:level-2:*", &area);
+        trie.insert("urn:iot-meter:*", &other);
+        assert_eq!(trie.len(), 4);
+
+        let device = DeviceID::from("urn:iot-sensor:carpark-7:level-2:bay-1");
+        assert_eq!(trie.matches(&device), [area.clone(), level.clone()]);
+        assert_eq!(
+            trie.matches(&"urn:iot-sensor:carpark-8:level-1".into()),
+            Vec::<AssetID>::new()
+        );
+
+        trie.remove("urn:iot-sensor:carpark-7:*", &level);
+        trie.remove("urn:iot-sensor:carpark-9:*", &area);
+        assert_eq!(trie.len(), 4);
+        trie.remove("urn:iot-sensor:*:level-2:*", &area);
+        assert_eq!(trie.len(), 3);
+        assert_eq!(trie.matches(&device), [area.clone(), level.clone()]);
+
+        trie.retain(|asset| asset != &area);
+        assert_eq!(trie.len(), 2);
+        assert_eq!(trie.matches(&device), [level]);
+    }
+}
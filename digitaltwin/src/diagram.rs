@@ -0,0 +1,239 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+use crate::twin_runner::TwinTransition;
+use digitaltwin_core::{AssetID, StateMachine};
+
+/// Number of recent transitions kept by a twin for its diagram
+pub const RECENT_TRANSITIONS: usize = 20;
+
+/// A transition seen recently that no handler declares, e.g. made by a helper method
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObservedTransition {
+    pub from: String,
+    pub to: String,
+}
+
+/// The state machine of a twin, or of one of its regions, with the states it went through
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MachineDiagram {
+    #[serde(flatten)]
+    pub machine: StateMachine,
+    /// States of the recent transitions, oldest first, ending with the current one
+    pub path: Vec<String>,
+    pub observed_transitions: Vec<ObservedTransition>,
+}
+
+/// Live diagram of the state machines of a twin, for debugging its model
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateDiagram {
+    pub asset_id: AssetID,
+    pub state: String,
+    pub machines: Vec<MachineDiagram>,
+    /// Recent transitions of the twin, oldest first
+    pub recent_transitions: Vec<TwinTransition>,
+}
+
+/// Quote an identifier of the DOT language
+fn quoted(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+impl StateDiagram {
+    pub fn new(
+        asset_id: AssetID,
+        state: String,
+        machines: Vec<StateMachine>,
+        recent_transitions: Vec<TwinTransition>,
+    ) -> Self {
+        // A twin with regions has a composite state, as "Fault|Online": each region
+        // follows its own part of the transitions
+        let regions = machines.len();
+        let part = |state: &str, region: usize| -> Option<String> {
+            let parts: Vec<&str> = state.split('|').collect();
+            (parts.len() == regions).then(|| parts[region].to_string())
+        };
+        let machines = machines
+            .into_iter()
+            .enumerate()
+            .map(|(region, machine)| {
+                let mut path: Vec<String> = recent_transitions
+                    .first()
+                    .and_then(|first| part(&first.from, region))
+                    .into_iter()
+                    .chain(recent_transitions.iter().filter_map(|t| part(&t.to, region)))
+                    .collect();
+                path.dedup();
+                if path.last() != Some(&machine.current) {
+                    path.push(machine.current.clone());
+                }
+                let mut observed_transitions = Vec::new();
+                for step in path.windows(2) {
+                    let observed = ObservedTransition {
+                        from: step[0].clone(),
+                        to: step[1].clone(),
+                    };
+                    let declared = machine
+                        .transitions
+                        .iter()
+                        .any(|t| t.from == observed.from && t.to == observed.to);
+                    if !declared && !observed_transitions.contains(&observed) {
+                        observed_transitions.push(observed);
+                    }
+                }
+                MachineDiagram {
+                    machine,
+                    path,
+                    observed_transitions,
+                }
+            })
+            .collect();
+        StateDiagram {
+            asset_id,
+            state,
+            machines,
+            recent_transitions,
+        }
+    }
+
+    /// The diagram in the DOT language of Graphviz: the current states are filled, the
+    /// transitions of the recent path are bold, the undeclared ones are dashed
+    pub fn to_dot(&self) -> String {
+        let mut dot = format!("digraph {} {{\n  rankdir=LR;\n", quoted(self.asset_id.as_str()));
+        for (i, diagram) in self.machines.iter().enumerate() {
+            let machine = &diagram.machine;
+            let prefix = machine.region.clone().unwrap_or_default();
+            let node = |state: &str| quoted(&format!("{prefix}/{state}"));
+            let on_path = |from: &str, to: &str| {
+                diagram
+                    .path
+                    .windows(2)
+                    .any(|step| step[0] == from && step[1] == to)
+            };
+            let _ = writeln!(dot, "  subgraph cluster_{i} {{");
+            if let Some(region) = &machine.region {
+                let _ = writeln!(dot, "    label={};", quoted(region));
+            }
+            for state in &machine.states {
+                let style = if *state == machine.current {
+                    ", style=filled, fillcolor=lightblue"
+                } else {
+                    ""
+                };
+                let _ = writeln!(dot, "    {} [label={}{style}];", node(state), quoted(state));
+            }
+            for t in &machine.transitions {
+                let style = if on_path(&t.from, &t.to) {
+                    ", penwidth=2"
+                } else {
+                    ""
+                };
+                let _ = writeln!(
+                    dot,
+                    "    {} -> {} [label={}{style}];",
+                    node(&t.from),
+                    node(&t.to),
+                    quoted(&t.trigger)
+                );
+            }
+            for t in &diagram.observed_transitions {
+                let _ = writeln!(
+                    dot,
+                    "    {} -> {} [style=dashed, penwidth=2];",
+                    node(&t.from),
+                    node(&t.to)
+                );
+            }
+            dot.push_str("  }\n");
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::charging_station::ChargingPointFactory;
+    use chrono::Utc;
+    use digitaltwin_core::{ActorFactory, ActorStateType};
+    use digitaltwin_macros::*;
+
+    #[derive(Clone, Debug)]
+    pub struct Open;
+
+    #[derive(Clone, Debug)]
+    pub struct Closed;
+
+    #[actor(default_state = "Open", states("Open", "Closed"), slots("Flow"))]
+    pub struct Valve {
+        #[actor_attr(default = "10.0")]
+        max_flow: f32,
+    }
+
+    #[actor_state(Valve, Open)]
+    #[dispatch_map("Flow" = flow_change)]
+    impl Valve<Open> {
+        fn flow_change(&self, flow: f32) -> Box<ActorStateType> {
+            self.check_flow(flow)
+        }
+
+        fn check_flow(&self, flow: f32) -> Box<ActorStateType> {
+            if flow > self.max_flow {
+                self.transition::<Closed>()
+            } else {
+                self.transition::<Open>()
+            }
+        }
+    }
+
+    #[actor_state(Valve, Closed)]
+    impl Valve<Closed> {}
+
+    #[test]
+    fn test_diagram() {
+        let (actor, _) = ChargingPointFactory::create_default();
+        let actor = actor.execute("VehicleDetected", serde_json::json!({}));
+        let transition = |from: &str, to: &str| TwinTransition {
+            asset_id: "urn:aas:cp-1".into(),
+            from: from.to_string(),
+            to: to.to_string(),
+            timestamp: Utc::now(),
+            correlation_id: "c-1".into(),
+        };
+        let recent = vec![
+            transition("Fault|Offline", "Idle|Offline"),
+            transition("Idle|Offline", "Connected|Offline"),
+        ];
+        let diagram = StateDiagram::new(
+            "urn:aas:cp-1".into(),
+            actor.state(),
+            actor.state_machines(),
+            recent,
+        );
+        let charging = &diagram.machines[0];
+        assert_eq!(charging.machine.region.as_deref(), Some("charging"));
+        assert_eq!(charging.machine.current, "Connected");
+        assert_eq!(charging.path, ["Fault", "Idle", "Connected"]);
+        // Both transitions are made by handlers
+        assert!(charging.observed_transitions.is_empty());
+        let connectivity = &diagram.machines[1];
+        assert_eq!(connectivity.path, ["Offline"]);
+
+        let dot = diagram.to_dot();
+        assert!(dot.contains("\"charging/Connected\" [label=\"Connected\", style=filled"));
+        assert!(dot
+            .contains("\"charging/Idle\" -> \"charging/Connected\" [label=\"VehicleDetected\", penwidth=2]"));
+    }
+
+    #[test]
+    fn test_helper_transitions() {
+        let (actor, _) = ValveFactory::create_default();
+        let machine = &actor.state_machines()[0];
+        // Made by a helper of the same impl block as the handler
+        assert_eq!(machine.transitions.len(), 1);
+        assert_eq!(machine.transitions[0].from, "Open");
+        assert_eq!(machine.transitions[0].to, "Closed");
+        assert_eq!(machine.transitions[0].trigger, "Flow");
+    }
+}
@@ -0,0 +1,201 @@
+use chrono::{DateTime, Utc};
+use log::warn;
+use serde::Serialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::network_receiver::TwinEvent;
+use crate::twin_runner::TwinTransition;
+use digitaltwin_core::AssetID;
+
+/// Events kept for the slow subscribers, which miss the oldest ones beyond
+const BUS_CAPACITY: usize = 1024;
+
+/// A domain event emitted by a twin, to publish on the given topic or on the default one
+#[derive(Debug, Clone, Serialize)]
+pub struct EmittedEvent {
+    pub asset_id: AssetID,
+    #[serde(flatten)]
+    pub event: TwinEvent,
+    #[serde(skip)]
+    pub topic: Option<String>,
+}
+
+/// A failure of a twin that did not stop it (e.g., an event not declared in its AAS)
+#[derive(Debug, Clone, Serialize)]
+pub struct TwinError {
+    pub asset_id: AssetID,
+    pub error: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Stage of the life of a twin runner
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Lifecycle {
+    Started,
+    /// The twin sent its first heartbeat
+    Ready,
+    Stopped,
+    Crashed,
+    /// The twin had no input for a while: its actor was dropped, keeping a snapshot
+    Hibernated,
+    /// The next message rebuilt the actor of a hibernated twin from its snapshot
+    Revived,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LifecycleEvent {
+    pub asset_id: AssetID,
+    pub stage: Lifecycle,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl LifecycleEvent {
+    pub fn now(asset_id: AssetID, stage: Lifecycle) -> Self {
+        LifecycleEvent {
+            asset_id,
+            stage,
+            timestamp: Utc::now(),
+        }
+    }
+}
+
+/// What the twins report on the bus
+#[derive(Debug, Clone)]
+pub enum BusEvent {
+    Transition(TwinTransition),
+    Event(EmittedEvent),
+    Error(TwinError),
+    Lifecycle(LifecycleEvent),
+}
+
+impl BusEvent {
+    /// Name of the kind of event, as in the event stream of the REST API
+    pub fn kind(&self) -> &'static str {
+        match self {
+            BusEvent::Transition(_) => "transition",
+            BusEvent::Event(_) => "event",
+            BusEvent::Error(_) => "error",
+            BusEvent::Lifecycle(_) => "lifecycle",
+        }
+    }
+
+    /// The twin that reported the event
+    pub fn asset_id(&self) -> &AssetID {
+        match self {
+            BusEvent::Transition(transition) => &transition.asset_id,
+            BusEvent::Event(event) => &event.asset_id,
+            BusEvent::Error(error) => &error.asset_id,
+            BusEvent::Lifecycle(lifecycle) => &lifecycle.asset_id,
+        }
+    }
+
+    pub fn timestamp(&self) -> DateTime<Utc> {
+        match self {
+            BusEvent::Transition(transition) => transition.timestamp,
+            BusEvent::Event(event) => event.event.timestamp,
+            BusEvent::Error(error) => error.timestamp,
+            BusEvent::Lifecycle(lifecycle) => lifecycle.timestamp,
+        }
+    }
+
+    /// The event as JSON, without its kind
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            BusEvent::Transition(transition) => serde_json::to_value(transition),
+            BusEvent::Event(event) => serde_json::to_value(event),
+            BusEvent::Error(error) => serde_json::to_value(error),
+            BusEvent::Lifecycle(lifecycle) => serde_json::to_value(lifecycle),
+        }
+        .unwrap_or_default()
+    }
+}
+
+/// Bus carrying the transitions, events, errors and lifecycle of all the twins to the sinks
+/// subscribed to it (MQTT publisher, webhooks, rules, event stream...). Without subscribers
+/// the events are simply dropped.
+#[derive(Clone)]
+pub struct EventBus(broadcast::Sender<BusEvent>);
+
+impl Default for EventBus {
+    fn default() -> Self {
+        EventBus(broadcast::channel(BUS_CAPACITY).0)
+    }
+}
+
+impl EventBus {
+    pub fn publish(&self, event: BusEvent) {
+        let _ = self.0.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<BusEvent> {
+        self.0.subscribe()
+    }
+}
+
+/// The next event of a subscription, skipping the ones missed by a subscriber lagging
+/// behind; None once the bus is closed
+pub async fn next_event(receiver: &mut broadcast::Receiver<BusEvent>, subscriber: &str) -> Option<BusEvent> {
+    loop {
+        match receiver.recv().await {
+            Ok(event) => return Some(event),
+            Err(RecvError::Lagged(skipped)) => warn!("{subscriber} missed {skipped} events of the bus"),
+            Err(RecvError::Closed) => return None,
+        }
+    }
+}
+
+/// The next event of an optional subscription, never completing without one
+pub async fn next_attached_event(
+    receiver: &mut Option<broadcast::Receiver<BusEvent>>,
+    subscriber: &str,
+) -> Option<BusEvent> {
+    match receiver {
+        Some(receiver) => next_event(receiver, subscriber).await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use digitaltwin_core::CorrelationID;
+
+    #[tokio::test]
+    async fn test_event_bus() {
+        let bus = EventBus::default();
+        // Published before subscribing: dropped
+        bus.publish(BusEvent::Lifecycle(LifecycleEvent::now(
+            "urn:aas:example:a".into(),
+            Lifecycle::Started,
+        )));
+        let mut receiver = bus.subscribe();
+        bus.publish(BusEvent::Event(EmittedEvent {
+            asset_id: "urn:aas:example:a".into(),
+            event: TwinEvent {
+                event: "ChargingComplete".to_string(),
+                timestamp: Utc::now(),
+                payload: serde_json::json!({ "power": 1.0 }),
+                correlation_id: CorrelationID::from("c-1"),
+            },
+            topic: Some("custom/topic".to_string()),
+        }));
+        let event = next_event(&mut receiver, "test").await.unwrap();
+        assert_eq!(event.kind(), "event");
+        let json = event.to_json();
+        assert_eq!(json["asset_id"], "urn:aas:example:a");
+        assert_eq!(json["event"], "ChargingComplete");
+        assert_eq!(json["payload"]["power"], 1.0);
+        assert!(json.get("topic").is_none());
+
+        // A lagging subscriber skips the oldest events
+        for _ in 0..BUS_CAPACITY + 1 {
+            bus.publish(BusEvent::Lifecycle(LifecycleEvent::now(
+                "urn:aas:example:a".into(),
+                Lifecycle::Ready,
+            )));
+        }
+        assert!(next_event(&mut receiver, "test").await.is_some());
+        assert_eq!(receiver.len(), BUS_CAPACITY - 1);
+    }
+}
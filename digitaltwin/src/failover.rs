@@ -0,0 +1,163 @@
+use chrono::{DateTime, Utc};
+use clap::{Parser, ValueEnum};
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Interval between two heartbeats of the active instance
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Parser, Clone)]
+pub struct FailoverOptions {
+    /// role of this instance: a standby consumes the same MQTT stream in shadow mode,
+    /// and takes over when the heartbeat of the active instance stops
+    #[clap(long, value_enum, default_value = "primary", env = "FAILOVER_ROLE")]
+    pub role: Role,
+
+    /// topic of the heartbeat published by the active instance
+    #[clap(
+        long,
+        default_value = "twins/runtime/heartbeat",
+        env = "FAILOVER_HEARTBEAT_TOPIC"
+    )]
+    pub heartbeat_topic: String,
+
+    /// seconds without a heartbeat after which the standby takes over
+    #[clap(long, default_value = "15", env = "FAILOVER_TIMEOUT")]
+    pub failover_timeout: u64,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Primary,
+    Standby,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Primary => "primary",
+            Role::Standby => "standby",
+        }
+    }
+}
+
+/// Failover state of the instance, as shown by the REST API
+#[derive(Debug, Clone, Serialize)]
+pub struct FailoverStatus {
+    pub role: Role,
+    /// Whether the instance publishes the state of the twins and actuates the devices
+    pub active: bool,
+    /// Time the instance started, or took over
+    pub since: DateTime<Utc>,
+    /// Latest heartbeat received from the active instance (standby only)
+    pub last_peer_heartbeat: Option<DateTime<Utc>>,
+}
+
+/// Warm-standby state machine. A standby keeps its twins up to date with the sensor
+/// updates and the commands, but does not publish nor actuate anything until it takes over.
+/// Taking over is final: the former primary must be restarted as a standby.
+#[derive(Debug)]
+pub struct Failover {
+    status: FailoverStatus,
+    heartbeat_topic: String,
+    timeout: Duration,
+    /// Latest heartbeat of the active instance, or the start time
+    last_heartbeat: Instant,
+}
+
+/// Failover state shared by the components that execute commands or publish
+pub type SharedFailover = Arc<Mutex<Failover>>;
+
+impl Failover {
+    pub fn new(options: &FailoverOptions) -> Self {
+        Failover {
+            status: FailoverStatus {
+                role: options.role,
+                active: options.role == Role::Primary,
+                since: Utc::now(),
+                last_peer_heartbeat: None,
+            },
+            heartbeat_topic: options.heartbeat_topic.clone(),
+            timeout: Duration::from_secs(options.failover_timeout.max(1)),
+            last_heartbeat: Instant::now(),
+        }
+    }
+
+    pub fn shared(options: &FailoverOptions) -> SharedFailover {
+        Arc::new(Mutex::new(Failover::new(options)))
+    }
+
+    pub fn role(&self) -> Role {
+        self.status.role
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.status.active
+    }
+
+    pub fn heartbeat_topic(&self) -> &str {
+        &self.heartbeat_topic
+    }
+
+    /// Record a heartbeat of the active instance
+    pub fn peer_heartbeat(&mut self, now: Instant) {
+        if !self.status.active {
+            self.last_heartbeat = now;
+            self.status.last_peer_heartbeat = Some(Utc::now());
+        }
+    }
+
+    /// Take over if the active instance is silent; true if the instance just became active
+    pub fn check(&mut self, now: Instant) -> bool {
+        if self.status.active || now.duration_since(self.last_heartbeat) < self.timeout {
+            return false;
+        }
+        self.status.active = true;
+        self.status.since = Utc::now();
+        true
+    }
+
+    pub fn status(&self) -> FailoverStatus {
+        self.status.clone()
+    }
+}
+
+/// Whether the instance is active (commands are executed, state is published)
+pub fn is_active(failover: &SharedFailover) -> bool {
+    failover.lock().unwrap_or_else(|e| e.into_inner()).is_active()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(role: Role) -> FailoverOptions {
+        FailoverOptions {
+            role,
+            heartbeat_topic: "twins/runtime/heartbeat".to_string(),
+            failover_timeout: 15,
+        }
+    }
+
+    #[test]
+    fn test_failover() {
+        let start = Instant::now();
+        let mut primary = Failover::new(&options(Role::Primary));
+        assert!(primary.is_active());
+        assert!(!primary.check(start + Duration::from_secs(60)));
+
+        let mut standby = Failover::new(&options(Role::Standby));
+        assert!(!standby.is_active());
+        standby.peer_heartbeat(start + Duration::from_secs(10));
+        assert!(!standby.check(start + Duration::from_secs(20)));
+        assert!(standby.status().last_peer_heartbeat.is_some());
+        assert!(standby.check(start + Duration::from_secs(25)));
+        assert!(standby.is_active());
+        // Taking over happens once, and the heartbeats it publishes are ignored
+        standby.peer_heartbeat(start + Duration::from_secs(30));
+        assert!(!standby.check(start + Duration::from_secs(60)));
+        assert!(standby.is_active());
+    }
+}
@@ -0,0 +1,275 @@
+use clap::Parser;
+use log::{debug, error, info, trace, warn};
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+use crate::device_trie;
+use crate::network_receiver::{Availability, NetworkMessage};
+use crate::twin_runner::TwinReport;
+use digitaltwin_core::AssetID;
+
+/// Delay before reconnecting to the broker of the mirrored runtime
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Parser, Clone)]
+pub struct FederationOptions {
+    /// MQTT broker of a runtime whose twins are mirrored as read-only proxy twins (e.g., the
+    /// broker of an edge runtime, for a site runtime); the proxies are republished, so that
+    /// the runtimes of the upper level can mirror them in turn
+    #[clap(long, env = "FEDERATION_BROKER")]
+    federation_broker: Option<String>,
+
+    /// state topic of the mirrored runtime
+    #[clap(long, default_value = "twins/state", env = "FEDERATION_STATE_TOPIC")]
+    federation_state_topic: String,
+
+    /// availability topic of the mirrored runtime
+    #[clap(long, default_value = "twins/status", env = "FEDERATION_STATUS_TOPIC")]
+    federation_status_topic: String,
+
+    /// asset IDs of the mirrored twins, with "*" and "?" wildcards (separate them with ',' in
+    /// FEDERATED_TWINS)
+    #[clap(
+        long = "federate",
+        default_value = "*",
+        value_delimiter = ',',
+        env = "FEDERATED_TWINS"
+    )]
+    federated_twins: Vec<String>,
+}
+
+/// A twin of another runtime, mirrored from its state topic
+#[derive(Debug, Clone)]
+pub struct ProxyTwin {
+    /// Latest status report published by the twin, with the runtime it is mirrored from
+    pub report: TwinReport,
+    /// Availability of the twin, from the status topic of its runtime
+    pub online: bool,
+}
+
+pub type SharedProxies = Arc<Mutex<HashMap<AssetID, ProxyTwin>>>;
+
+/// Record the report of a mirrored twin, returning whether it changed. Twins republished by
+/// another level keep the runtime they come from.
+pub fn mirror(proxies: &mut HashMap<AssetID, ProxyTwin>, mut report: TwinReport, source: &str) -> bool {
+    report.mirrored_from.get_or_insert_with(|| source.to_string());
+    let changed = proxies.get(&report.asset_id).is_none_or(|proxy| {
+        // Reports are only compared through their serialization
+        serde_json::to_value(&proxy.report).ok() != serde_json::to_value(&report).ok()
+    });
+    if changed {
+        let online = proxies.get(&report.asset_id).is_none_or(|proxy| proxy.online);
+        proxies.insert(report.asset_id.clone(), ProxyTwin { report, online });
+    }
+    changed
+}
+
+/// Mirrors the twins of another runtime as read-only proxy twins, on its own connection to
+/// the broker of that runtime
+pub struct Federation {
+    options: FederationOptions,
+    mqtt_options: Option<MqttOptions>,
+    /// Whether the proxies are republished: not when mirroring another runtime on the same
+    /// broker, where they would overwrite the state it publishes
+    republish: bool,
+    proxies: SharedProxies,
+    network_ch: mpsc::Sender<NetworkMessage>,
+}
+
+impl Federation {
+    /// The credentials of the runtime connection are also used with the broker of the
+    /// mirrored runtime
+    pub fn new(
+        options: FederationOptions,
+        runtime_options: MqttOptions,
+        network_ch: mpsc::Sender<NetworkMessage>,
+    ) -> Self {
+        let (runtime_broker, _) = runtime_options.broker_address();
+        let republish = options.federation_broker.as_ref() != Some(&runtime_broker);
+        let mqtt_options = options.federation_broker.as_ref().map(|broker| {
+            let mut mqtt_options = MqttOptions::new("dt-federation", broker, 1883);
+            mqtt_options.set_keep_alive(Duration::from_secs(5));
+            if let Some((username, password)) = runtime_options.credentials() {
+                mqtt_options.set_credentials(username, password);
+            }
+            mqtt_options
+        });
+        Federation {
+            options,
+            mqtt_options,
+            republish,
+            proxies: SharedProxies::default(),
+            network_ch,
+        }
+    }
+
+    /// The proxy twins, shared with the manager
+    pub fn proxies(&self) -> SharedProxies {
+        self.proxies.clone()
+    }
+
+    fn is_federated(&self, id: &str) -> bool {
+        self.options
+            .federated_twins
+            .iter()
+            .any(|pattern| device_trie::pattern_matches(pattern, id))
+    }
+
+    /// Mirror a report received on the state topic, republishing it if it changed
+    async fn handle_state(&mut self, id: &str, payload: &[u8], source: &str) {
+        if !self.is_federated(id) {
+            return;
+        }
+        // An empty retained message clears the twin
+        if payload.is_empty() {
+            if self
+                .proxies
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .remove(id)
+                .is_some()
+            {
+                info!("Proxy twin {id} removed");
+            }
+            return;
+        }
+        let report: TwinReport = match serde_json::from_slice(payload) {
+            Ok(report) => report,
+            Err(e) => {
+                warn!("Invalid state of mirrored twin {id}, ignored: {e}");
+                return;
+            }
+        };
+        if report.asset_id != id {
+            warn!("State of twin {} published for {id}, ignored", report.asset_id);
+            return;
+        }
+        let republished = {
+            let mut proxies = self.proxies.lock().unwrap_or_else(|e| e.into_inner());
+            if !proxies.contains_key(id) {
+                info!("Mirroring twin {id} from {source}");
+            }
+            mirror(&mut proxies, report, source).then(|| proxies[id].report.clone())
+        };
+        if let Some(report) = republished {
+            debug!("Proxy twin {id} is now {}", report.state);
+            if self.republish {
+                let _ = self
+                    .network_ch
+                    .send(NetworkMessage::State(Box::new(report)))
+                    .await;
+            }
+        }
+    }
+
+    /// Record the availability of a mirrored twin, republishing it if it changed
+    async fn handle_status(&mut self, id: &str, payload: &[u8]) {
+        let online = payload == Availability::Online.as_str().as_bytes();
+        let changed = {
+            let mut proxies = self.proxies.lock().unwrap_or_else(|e| e.into_inner());
+            match proxies.get_mut(id) {
+                Some(proxy) if proxy.online != online => {
+                    proxy.online = online;
+                    true
+                }
+                _ => false,
+            }
+        };
+        if changed && self.republish {
+            let availability = if online {
+                Availability::Online
+            } else {
+                Availability::Offline
+            };
+            let _ = self
+                .network_ch
+                .send(NetworkMessage::Availability(id.into(), availability))
+                .await;
+        }
+    }
+
+    pub async fn body(&mut self) {
+        let Some(mqtt_options) = self.mqtt_options.clone() else {
+            return;
+        };
+        let (source, _) = mqtt_options.broker_address();
+        info!("Federation body starting, mirroring twins from {source}");
+        let state_prefix = format!("{}/", self.options.federation_state_topic);
+        let status_prefix = format!("{}/", self.options.federation_status_topic);
+        let (client, mut connection) = AsyncClient::new(mqtt_options, 10);
+        loop {
+            match connection.poll().await {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    for filter in [format!("{state_prefix}#"), format!("{status_prefix}+")] {
+                        if let Err(e) = client.subscribe(&filter, QoS::AtLeastOnce).await {
+                            error!("Failed to subscribe to {filter}: {e:?}");
+                        }
+                    }
+                }
+                Ok(Event::Incoming(Packet::Publish(publish))) => {
+                    if let Some(id) = publish.topic.strip_prefix(&state_prefix) {
+                        self.handle_state(id, &publish.payload, &source).await;
+                    } else if let Some(id) = publish.topic.strip_prefix(&status_prefix) {
+                        self.handle_status(id, &publish.payload).await;
+                    }
+                }
+                Ok(event) => trace!("Federation MQTT event: {event:?}"),
+                Err(e) => {
+                    error!("Federation MQTT connection error: {e:?}");
+                    tokio::time::sleep(RECONNECT_DELAY).await;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::labels::Labels;
+
+    fn report(state: &str, mirrored_from: Option<&str>) -> TwinReport {
+        TwinReport {
+            asset_id: "urn:edge:charger".into(),
+            actor_type: "ChargingPoint".to_string(),
+            state: state.to_string(),
+            labels: Labels::from([("site".to_string(), "edge-1".to_string())]),
+            mirrored_from: mirrored_from.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_mirror() {
+        let mut proxies = HashMap::new();
+        assert!(mirror(&mut proxies, report("Idle", None), "edge-1.local"));
+        let proxy = &proxies[&AssetID::from("urn:edge:charger")];
+        assert_eq!(proxy.report.mirrored_from.as_deref(), Some("edge-1.local"));
+        assert!(proxy.online);
+
+        // The proxy republished on the same broker comes back unchanged
+        let republished = proxy.report.clone();
+        assert!(!mirror(&mut proxies, republished, "edge-1.local"));
+        assert!(!mirror(&mut proxies, report("Idle", None), "edge-1.local"));
+
+        proxies
+            .get_mut(&AssetID::from("urn:edge:charger"))
+            .unwrap()
+            .online = false;
+        assert!(mirror(&mut proxies, report("Charging", None), "edge-1.local"));
+        let proxy = &proxies[&AssetID::from("urn:edge:charger")];
+        assert_eq!((proxy.report.state.as_str(), proxy.online), ("Charging", false));
+
+        // A twin mirrored by an intermediate level keeps its origin
+        assert!(mirror(
+            &mut proxies,
+            report("Idle", Some("edge-1.local")),
+            "site.local"
+        ));
+        let proxy = &proxies[&AssetID::from("urn:edge:charger")];
+        assert_eq!(proxy.report.mirrored_from.as_deref(), Some("edge-1.local"));
+    }
+}
@@ -0,0 +1,174 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use digitaltwin_core::TransitionLimits;
+
+/// Event emitted by a twin exceeding the limits on its transitions
+pub const FLAPPING_EVENT: &str = "Flapping";
+
+/// Window of the limit on the number of transitions
+const TRANSITION_WINDOW: Duration = Duration::from_secs(60);
+
+/// A limit on the transitions exceeded by a twin
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum Violation {
+    /// More transitions within a minute than the limit
+    TooManyTransitions { transitions: usize, limit: u32 },
+    /// The previous state was left before the minimum dwell time
+    DwellTooShort { dwell_ms: u64, min_dwell_ms: u64 },
+}
+
+/// Transitions of a twin checked against its limits. A flapping twin with a safe state is
+/// latched into it: its inputs are ignored until a command or a restore releases it.
+#[derive(Debug)]
+pub struct FlappingGuard {
+    limits: TransitionLimits,
+    /// Times of the transitions within the window
+    recent: VecDeque<Instant>,
+    /// Time the current state was entered
+    entered: Instant,
+    latched: bool,
+}
+
+impl FlappingGuard {
+    pub fn new(limits: TransitionLimits, now: Instant) -> Self {
+        FlappingGuard {
+            limits,
+            recent: VecDeque::new(),
+            entered: now,
+            latched: false,
+        }
+    }
+
+    /// Record a transition, returning the limit it exceeds if any. The transitions of a
+    /// latched twin are not checked.
+    pub fn check(&mut self, now: Instant) -> Option<Violation> {
+        let dwell = now.saturating_duration_since(std::mem::replace(&mut self.entered, now));
+        if self.limits.is_empty() {
+            return None;
+        }
+        self.recent.push_back(now);
+        while self
+            .recent
+            .front()
+            .is_some_and(|at| now.saturating_duration_since(*at) >= TRANSITION_WINDOW)
+        {
+            self.recent.pop_front();
+        }
+        if self.latched {
+            return None;
+        }
+        match (self.limits.max_per_minute, self.limits.min_dwell_ms) {
+            (Some(limit), _) if self.recent.len() > limit as usize => Some(Violation::TooManyTransitions {
+                transitions: self.recent.len(),
+                limit,
+            }),
+            (_, Some(min_dwell_ms)) if dwell < Duration::from_millis(min_dwell_ms) => {
+                Some(Violation::DwellTooShort {
+                    dwell_ms: dwell.as_millis() as u64,
+                    min_dwell_ms,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// State to latch the twin into when flapping, unless already latched
+    pub fn latch(&mut self) -> Option<&str> {
+        if self.latched {
+            return None;
+        }
+        let safe_state = self.limits.safe_state.as_deref()?;
+        self.latched = true;
+        Some(safe_state)
+    }
+
+    /// State the twin is latched into, if any
+    pub fn latched_into(&self) -> Option<&str> {
+        self.limits.safe_state.as_deref().filter(|_| self.latched)
+    }
+
+    /// Release the twin latched into its safe state, forgetting its transitions. False if
+    /// it was not latched.
+    pub fn release(&mut self) -> bool {
+        self.recent.clear();
+        std::mem::replace(&mut self.latched, false)
+    }
+}
+
+/// The snapshot of an actor moved into its safe state. The safe state of an actor with
+/// regions is a composite one, as "Fault|Offline", with the state of each region in order.
+pub fn safe_snapshot(
+    mut snapshot: serde_json::Value,
+    regions: &[String],
+    safe_state: &str,
+) -> Result<serde_json::Value, String> {
+    snapshot["state"] = safe_state.into();
+    if regions.is_empty() {
+        return Ok(snapshot);
+    }
+    let states: Vec<&str> = safe_state.split('|').collect();
+    if states.len() != regions.len() {
+        return Err(format!(
+            "safe state {safe_state} does not name a state for each region ({})",
+            regions.join("|")
+        ));
+    }
+    for (region, state) in regions.iter().zip(states) {
+        let region_snapshot = snapshot
+            .get_mut("regions")
+            .and_then(|r| r.get_mut(region))
+            .ok_or_else(|| format!("missing region {region} in snapshot"))?;
+        region_snapshot["state"] = state.into();
+    }
+    Ok(snapshot)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flapping_guard() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let limits = TransitionLimits {
+            max_per_minute: Some(3),
+            min_dwell_ms: Some(1000),
+            safe_state: Some("Off".to_string()),
+        };
+        let mut guard = FlappingGuard::new(limits, start);
+        assert_eq!(guard.check(at(5_000)), None);
+        assert_eq!(
+            guard.check(at(5_500)),
+            Some(Violation::DwellTooShort {
+                dwell_ms: 500,
+                min_dwell_ms: 1000
+            })
+        );
+        assert_eq!(guard.check(at(10_000)), None);
+        assert_eq!(
+            guard.check(at(20_000)),
+            Some(Violation::TooManyTransitions {
+                transitions: 4,
+                limit: 3
+            })
+        );
+        assert_eq!(guard.latch(), Some("Off"));
+        assert_eq!(guard.latch(), None);
+        // The transition into the safe state is not checked
+        assert_eq!(guard.check(at(20_001)), None);
+        assert_eq!(guard.latched_into(), Some("Off"));
+        assert!(guard.release());
+        assert!(!guard.release());
+        // The transitions before the release are forgotten
+        assert_eq!(guard.check(at(30_000)), None);
+
+        // Without limits, nothing is checked
+        let mut guard = FlappingGuard::new(TransitionLimits::default(), start);
+        assert_eq!(guard.check(at(1)), None);
+        assert_eq!(guard.latch(), None);
+    }
+}
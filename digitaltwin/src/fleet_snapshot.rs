@@ -0,0 +1,140 @@
+use chrono::{DateTime, Utc};
+use log::warn;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
+
+use crate::twin_runner::{ActorMessage, TwinReport};
+use digitaltwin_core::AssetID;
+
+/// Longest time a twin stays frozen if it is not released (e.g., the manager went away)
+pub const FREEZE_LIMIT: Duration = Duration::from_secs(5);
+
+/// State of a twin when it froze
+#[derive(Debug, Clone, Serialize)]
+pub struct FrozenTwin {
+    pub report: TwinReport,
+    pub snapshot: serde_json::Value,
+}
+
+/// States of all the running twins at the same instant
+#[derive(Debug, Clone, Serialize)]
+pub struct FleetSnapshot {
+    /// When all the twins were frozen: no twin changed between its capture and this instant
+    pub taken_at: DateTime<Utc>,
+    /// How long the first twin was frozen (milliseconds)
+    pub paused_ms: u64,
+    pub twins: BTreeMap<AssetID, FrozenTwin>,
+    /// Twins that did not freeze in time, left out of the snapshot
+    pub missing: Vec<AssetID>,
+}
+
+/// Freeze all the twins, so that none of them processes any input or command, then take
+/// their states and release them. The twins are released at once when all of them froze,
+/// or when the ones left did not freeze within the timeout.
+pub async fn capture(
+    channels: Vec<(AssetID, mpsc::Sender<ActorMessage>)>,
+    timeout: Duration,
+) -> FleetSnapshot {
+    let started = Instant::now();
+    let deadline = started + timeout;
+    let mut pending = Vec::new();
+    let mut releases = Vec::new();
+    let mut missing = Vec::new();
+    for (id, ch) in channels {
+        let (reply, frozen) = oneshot::channel();
+        let (release, released) = oneshot::channel();
+        match tokio::time::timeout_at(deadline, ch.send(ActorMessage::Freeze(reply, released))).await {
+            Ok(Ok(())) => {
+                pending.push((id, frozen));
+                releases.push(release);
+            }
+            _ => missing.push(id),
+        }
+    }
+    let mut twins = BTreeMap::new();
+    for (id, frozen) in pending {
+        match tokio::time::timeout_at(deadline, frozen).await {
+            Ok(Ok(frozen)) => {
+                twins.insert(id, frozen);
+            }
+            _ => missing.push(id),
+        }
+    }
+    let taken_at = Utc::now();
+    let paused = started.elapsed();
+    for release in releases {
+        let _ = release.send(());
+    }
+    if !missing.is_empty() {
+        warn!("Twins {missing:?} did not freeze in time, left out of the fleet snapshot");
+    }
+    missing.sort();
+    FleetSnapshot {
+        taken_at,
+        paused_ms: paused.as_millis() as u64,
+        twins,
+        missing,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    fn report(id: &str, state: String) -> TwinReport {
+        TwinReport {
+            asset_id: id.into(),
+            actor_type: "Counter".to_string(),
+            state,
+            ..Default::default()
+        }
+    }
+
+    /// A twin counting the shared ticks while it is not frozen
+    fn counter(id: &'static str, ticks: Arc<AtomicU32>) -> mpsc::Sender<ActorMessage> {
+        let (ch, mut messages) = mpsc::channel(5);
+        tokio::spawn(async move {
+            while let Some(message) = messages.recv().await {
+                if let ActorMessage::Freeze(reply, release) = message {
+                    let count = ticks.load(Ordering::SeqCst);
+                    let _ = reply.send(FrozenTwin {
+                        report: report(id, count.to_string()),
+                        snapshot: serde_json::json!({ "count": count }),
+                    });
+                    let _ = release.await;
+                    ticks.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+        });
+        ch
+    }
+
+    #[tokio::test]
+    async fn test_capture() {
+        let ticks = Arc::new(AtomicU32::new(0));
+        let (stuck, _messages) = mpsc::channel(1);
+        let channels = vec![
+            (
+                AssetID::from("urn:aas:example:b"),
+                counter("urn:aas:example:b", ticks.clone()),
+            ),
+            (
+                AssetID::from("urn:aas:example:a"),
+                counter("urn:aas:example:a", ticks.clone()),
+            ),
+            (AssetID::from("urn:aas:example:stuck"), stuck),
+        ];
+        let snapshot = capture(channels, Duration::from_millis(100)).await;
+        // No twin resumed before all of them were captured
+        let states: Vec<&str> = snapshot.twins.values().map(|t| t.report.state.as_str()).collect();
+        assert_eq!(states, ["0", "0"]);
+        assert_eq!(snapshot.missing, ["urn:aas:example:stuck"]);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(ticks.load(Ordering::SeqCst), 2);
+    }
+}
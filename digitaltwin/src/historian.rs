@@ -0,0 +1,119 @@
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use thiserror::Error as ThisError;
+
+use crate::http_client::{self, HttpError};
+use digitaltwin_core::{DeviceID, SlotValue};
+
+#[derive(Parser, Clone)]
+pub struct HistorianOptions {
+    /// time-series store of the sensor values (http:// or https://), answering
+    /// "GET <url>?sensor=<sensor id>&limit=<n>" with a JSON list of {"timestamp": <RFC 3339 time>,
+    /// "value": <value>}; enables the backfill of the twins when they start
+    #[clap(long, env = "HISTORIAN_URL")]
+    historian_url: Option<String>,
+
+    /// number of past values replayed for each bound sensor when a twin starts
+    #[clap(long, default_value_t = 10, env = "BACKFILL_VALUES")]
+    backfill_values: usize,
+}
+
+#[derive(ThisError, Debug)]
+pub enum HistorianError {
+    #[error(transparent)]
+    Http(#[from] HttpError),
+    #[error("invalid values: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// A value of a sensor recorded by the historian
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoricValue {
+    pub timestamp: DateTime<Utc>,
+    pub value: SlotValue,
+}
+
+/// Client of the time-series store, used to backfill the twins
+#[derive(Debug, Clone)]
+pub struct Historian {
+    url: String,
+    limit: usize,
+}
+
+impl Historian {
+    /// The historian configured in the options, if any
+    pub fn from_options(options: &HistorianOptions) -> Option<Self> {
+        let url = options.historian_url.clone()?;
+        (options.backfill_values > 0).then_some(Historian {
+            url,
+            limit: options.backfill_values,
+        })
+    }
+
+    fn query_url(&self, sensor: &DeviceID) -> String {
+        let separator = if self.url.contains('?') { '&' } else { '?' };
+        format!(
+            "{}{separator}sensor={}&limit={}",
+            self.url,
+            encode_component(sensor),
+            self.limit
+        )
+    }
+
+    /// The latest values of a sensor, oldest first
+    pub async fn recent_values(&self, sensor: &DeviceID) -> Result<Vec<HistoricValue>, HistorianError> {
+        let body = http_client::get(&self.query_url(sensor), &[("Accept", "application/json")]).await?;
+        Ok(parse_values(&body, self.limit)?)
+    }
+}
+
+/// Percent-encode a query parameter or a path segment (sensor IDs are URNs, with ':')
+pub fn encode_component(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+/// Parse the values returned by the historian, keeping the latest `limit` ones, oldest
+/// first (stores may return them in any order, and ignore the limit)
+pub fn parse_values(body: &str, limit: usize) -> Result<Vec<HistoricValue>, serde_json::Error> {
+    let mut values: Vec<HistoricValue> = serde_json::from_str(body)?;
+    values.sort_by_key(|value| value.timestamp);
+    let skip = values.len().saturating_sub(limit);
+    values.drain(..skip);
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_values() {
+        let body = r#"[
+            {"timestamp": "2025-03-01T10:02:00Z", "value": 16.0},
+            {"timestamp": "2025-03-01T10:00:00Z", "value": 8.0},
+            {"timestamp": "2025-03-01T10:01:00Z", "value": 12.5}
+        ]"#;
+        let values = parse_values(body, 2).unwrap();
+        assert_eq!(
+            values.iter().map(|v| v.value.clone()).collect::<Vec<_>>(),
+            [SlotValue::Number(12.5), SlotValue::Number(16.0)]
+        );
+        assert!(parse_values(r#"[{"value": 1.0}]"#, 2).is_err());
+
+        let historian = Historian {
+            url: "http://historian.local/values".to_string(),
+            limit: 10,
+        };
+        assert_eq!(
+            historian.query_url(&"urn:iot-sensor:current 1".into()),
+            "http://historian.local/values?sensor=urn%3Aiot-sensor%3Acurrent%201&limit=10"
+        );
+    }
+}
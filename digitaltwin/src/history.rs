@@ -0,0 +1,826 @@
+use chrono::{DateTime, DurationRound, TimeDelta, Utc};
+use clap::{Args, Parser, ValueEnum};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use crate::historian::encode_component;
+use crate::http_client::{self, HttpError};
+use digitaltwin_core::{AssetID, SlotValue};
+
+#[derive(Parser, Clone)]
+pub struct HistoryOptions {
+    /// keep a history of the slot values of the twins, downsampled to 1 minute and 1 hour
+    /// aggregates, for the trend queries of the REST API
+    #[clap(long, env = "HISTORY")]
+    history: bool,
+
+    /// seconds the raw values are kept (0 = not kept)
+    #[clap(long, default_value_t = 3600, env = "HISTORY_RAW_RETENTION")]
+    history_raw_retention: u64,
+
+    /// seconds the 1 minute aggregates are kept (0 = not kept)
+    #[clap(long, default_value_t = 7 * 86400, env = "HISTORY_MINUTE_RETENTION")]
+    history_minute_retention: u64,
+
+    /// seconds the 1 hour aggregates are kept (0 = not kept)
+    #[clap(long, default_value_t = 365 * 86400, env = "HISTORY_HOUR_RETENTION")]
+    history_hour_retention: u64,
+
+    /// retention of the values of a slot, overriding the default ones, as
+    /// "<slot>=<raw>,<minute>,<hour>" in seconds (e.g., "SignalStrength=600,86400,0")
+    #[clap(
+        long = "history-slot-retention",
+        value_parser = parse_slot_retention,
+        value_delimiter = ';',
+        env = "HISTORY_SLOT_RETENTION"
+    )]
+    history_slot_retention: Vec<(String, Retention)>,
+}
+
+fn parse_slot_retention(s: &str) -> Result<(String, Retention), String> {
+    let (slot, windows) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected <slot>=<raw>,<minute>,<hour>: {s}"))?;
+    let windows = windows
+        .split(',')
+        .map(|w| {
+            w.trim()
+                .parse()
+                .map_err(|_| format!("invalid retention in seconds: {w}"))
+        })
+        .collect::<Result<Vec<u64>, _>>()?;
+    match windows[..] {
+        [raw, minute, hour] => Ok((slot.trim().to_string(), Retention::new(raw, minute, hour))),
+        _ => Err(format!("expected 3 retention windows: {s}")),
+    }
+}
+
+/// Arguments of the history subcommand, querying the history of a running runtime
+#[derive(Args)]
+pub struct HistoryArgs {
+    /// asset ID of the twin
+    pub asset_id: String,
+    /// slot of the twin
+    pub slot: String,
+    /// start of the range: an RFC 3339 time, "now" or a time ago (e.g., "-7d", "-90m")
+    #[clap(long, default_value = "-1h", allow_hyphen_values = true)]
+    pub from: String,
+    /// end of the range, as the start
+    #[clap(long, default_value = "now", allow_hyphen_values = true)]
+    pub to: String,
+    /// resolution of the values; by default the finest one still kept at the start
+    #[clap(long, value_enum)]
+    pub resolution: Option<Resolution>,
+    /// aggregate the values instead of listing them
+    #[clap(long, value_enum)]
+    pub aggregate: Option<AggregateFunction>,
+    /// aggregate the values separately for each state of the twin
+    #[clap(long, value_enum, requires = "aggregate")]
+    pub group_by: Option<GroupBy>,
+    /// REST API of the runtime (http:// or https://)
+    #[clap(long, default_value = "http://127.0.0.1:8080", env = "DT_URL")]
+    pub url: String,
+    /// API key of the REST API, if required
+    #[clap(long, env = "DT_API_KEY")]
+    pub api_key: Option<String>,
+}
+
+impl HistoryArgs {
+    /// Query the REST API, returning the JSON response
+    pub async fn run(&self) -> Result<String, HttpError> {
+        let mut query = vec![("from", self.from.clone()), ("to", self.to.clone())];
+        if let Some(resolution) = self.resolution {
+            query.push(("resolution", resolution.as_str().to_string()));
+        }
+        if let Some(function) = self.aggregate {
+            query.push(("function", function.as_str().to_string()));
+        }
+        if let Some(GroupBy::State) = self.group_by {
+            query.push(("group_by", "state".to_string()));
+        }
+        let query: Vec<String> = query
+            .into_iter()
+            .map(|(name, value)| format!("{name}={}", encode_component(&value)))
+            .collect();
+        let url = format!(
+            "{}/twins/{}/slots/{}/history{}?{}",
+            self.url.trim_end_matches('/'),
+            encode_component(&self.asset_id),
+            encode_component(&self.slot),
+            if self.aggregate.is_some() {
+                "/aggregate"
+            } else {
+                ""
+            },
+            query.join("&")
+        );
+        let authorization = self.api_key.as_ref().map(|key| format!("Bearer {key}"));
+        let mut headers = vec![("Accept", "application/json")];
+        if let Some(authorization) = &authorization {
+            headers.push(("Authorization", authorization));
+        }
+        http_client::get(&url, &headers).await
+    }
+}
+
+/// Parse a time of a history query: an RFC 3339 time, "now", or a time ago as a negative
+/// number of seconds, minutes, hours, days or weeks (e.g., "-30s", "-7d")
+pub fn parse_time(s: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>, String> {
+    let s = s.trim();
+    if s == "now" {
+        return Ok(now);
+    }
+    let Some(ago) = s.strip_prefix('-') else {
+        return DateTime::parse_from_rfc3339(s)
+            .map(|time| time.with_timezone(&Utc))
+            .map_err(|e| format!("invalid time {s}: {e}"));
+    };
+    let unit_at = ago.len().saturating_sub(1);
+    let amount: i64 = ago[..unit_at]
+        .parse()
+        .map_err(|_| format!("invalid time ago: {s}"))?;
+    let ago = match &ago[unit_at..] {
+        "s" => TimeDelta::try_seconds(amount),
+        "m" => TimeDelta::try_minutes(amount),
+        "h" => TimeDelta::try_hours(amount),
+        "d" => TimeDelta::try_days(amount),
+        "w" => TimeDelta::try_weeks(amount),
+        _ => return Err(format!("invalid unit in {s}, expected s, m, h, d or w")),
+    };
+    ago.and_then(|ago| now.checked_sub_signed(ago))
+        .ok_or_else(|| format!("time out of range: {s}"))
+}
+
+/// Resolution of the values of a history query
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Resolution {
+    Raw,
+    Minute,
+    Hour,
+}
+
+impl Resolution {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Resolution::Raw => "raw",
+            Resolution::Minute => "minute",
+            Resolution::Hour => "hour",
+        }
+    }
+}
+
+/// Function aggregating the numeric values of a history query
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AggregateFunction {
+    Min,
+    Max,
+    Avg,
+    Last,
+}
+
+impl AggregateFunction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AggregateFunction::Min => "min",
+            AggregateFunction::Max => "max",
+            AggregateFunction::Avg => "avg",
+            AggregateFunction::Last => "last",
+        }
+    }
+
+    fn apply(&self, aggregate: &Aggregate) -> f64 {
+        match self {
+            AggregateFunction::Min => aggregate.min,
+            AggregateFunction::Max => aggregate.max,
+            AggregateFunction::Avg => aggregate.mean,
+            AggregateFunction::Last => aggregate.last,
+        }
+    }
+}
+
+/// Grouping of the values of an aggregation query
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupBy {
+    /// The state of the twin when the value was received
+    State,
+}
+
+/// How long the values of a slot are kept at each resolution (None = not kept)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Retention {
+    raw: Option<TimeDelta>,
+    minute: Option<TimeDelta>,
+    hour: Option<TimeDelta>,
+}
+
+impl Retention {
+    fn new(raw: u64, minute: u64, hour: u64) -> Self {
+        let window = |secs: u64| (secs > 0).then(|| TimeDelta::seconds(secs.min(i64::MAX as u64) as i64));
+        Retention {
+            raw: window(raw),
+            minute: window(minute),
+            hour: window(hour),
+        }
+    }
+
+    fn window(&self, resolution: Resolution) -> Option<TimeDelta> {
+        match resolution {
+            Resolution::Raw => self.raw,
+            Resolution::Minute => self.minute,
+            Resolution::Hour => self.hour,
+        }
+    }
+}
+
+/// A value of a slot, with the state of the twin after receiving it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RecordedValue {
+    pub timestamp: DateTime<Utc>,
+    pub value: SlotValue,
+    pub state: String,
+}
+
+/// Numeric values of a slot aggregated over a minute or an hour, while the twin was in a
+/// state (an interval has an aggregate for each state of the twin during the interval)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Aggregate {
+    /// Start of the interval
+    pub start: DateTime<Utc>,
+    pub state: String,
+    pub count: u64,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    /// Latest value of the interval
+    pub last: f64,
+}
+
+impl Aggregate {
+    fn new(start: DateTime<Utc>, state: &str, value: f64) -> Self {
+        Aggregate {
+            start,
+            state: state.to_string(),
+            count: 1,
+            min: value,
+            max: value,
+            mean: value,
+            last: value,
+        }
+    }
+
+    fn add(&mut self, value: f64) {
+        self.count += 1;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.mean += (value - self.mean) / self.count as f64;
+        self.last = value;
+    }
+
+    /// Fold a later aggregate into this one
+    fn merge(&mut self, other: &Aggregate) {
+        let count = self.count + other.count;
+        self.mean += (other.mean - self.mean) * other.count as f64 / count as f64;
+        self.count = count;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.last = other.last;
+    }
+}
+
+/// Result of an aggregation query
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HistoryAggregation {
+    pub function: AggregateFunction,
+    /// Resolution of the values aggregated: the intervals of the aggregates overlapping
+    /// the start or the end of the range are counted in full
+    pub resolution: Resolution,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    /// A single group without grouping, otherwise a group per state, sorted
+    pub groups: Vec<AggregateGroup>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AggregateGroup {
+    /// The state of the group, when grouping by state
+    pub state: Option<String>,
+    /// Number of numeric values aggregated
+    pub count: u64,
+    /// The aggregated value, None without values
+    pub value: Option<f64>,
+}
+
+/// Values of a slot over a time range, at a resolution
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "resolution", content = "values", rename_all = "snake_case")]
+pub enum HistorySeries {
+    Raw(Vec<RecordedValue>),
+    Minute(Vec<Aggregate>),
+    Hour(Vec<Aggregate>),
+}
+
+/// The values of a slot: raw, and downsampled as they are recorded. Only numeric values
+/// are aggregated; booleans and texts are kept raw.
+#[derive(Debug)]
+struct SlotHistory {
+    retention: Retention,
+    raw: VecDeque<RecordedValue>,
+    minutes: VecDeque<Aggregate>,
+    hours: VecDeque<Aggregate>,
+}
+
+impl SlotHistory {
+    fn new(retention: Retention) -> Self {
+        SlotHistory {
+            retention,
+            raw: VecDeque::new(),
+            minutes: VecDeque::new(),
+            hours: VecDeque::new(),
+        }
+    }
+
+    fn record(&mut self, timestamp: DateTime<Utc>, value: SlotValue, state: &str) {
+        // Values older than the latest one (e.g., late deliveries) would break the
+        // order of the intervals
+        if self.raw.back().is_some_and(|latest| timestamp < latest.timestamp) {
+            return;
+        }
+        if let SlotValue::Number(n) = value {
+            if self.retention.minute.is_some() {
+                downsample(&mut self.minutes, timestamp, TimeDelta::minutes(1), n, state);
+            }
+            if self.retention.hour.is_some() {
+                downsample(&mut self.hours, timestamp, TimeDelta::hours(1), n, state);
+            }
+        }
+        self.raw.push_back(RecordedValue {
+            timestamp,
+            value,
+            state: state.to_string(),
+        });
+        if self.retention.raw.is_none() {
+            // The latest value is kept anyway, to order the next ones
+            self.raw.drain(..self.raw.len() - 1);
+        }
+        self.prune(timestamp);
+    }
+
+    /// Drop the values older than the retention windows
+    fn prune(&mut self, now: DateTime<Utc>) {
+        if let Some(window) = self.retention.raw {
+            while self.raw.len() > 1 && self.raw.front().is_some_and(|v| v.timestamp < now - window) {
+                self.raw.pop_front();
+            }
+        }
+        for (aggregates, window) in [
+            (&mut self.minutes, self.retention.minute),
+            (&mut self.hours, self.retention.hour),
+        ] {
+            let Some(window) = window else { continue };
+            while aggregates.front().is_some_and(|a| a.start < now - window) {
+                aggregates.pop_front();
+            }
+        }
+    }
+
+    /// The finest resolution still covering `from`, or the coarsest one kept
+    fn resolution_for(&self, from: DateTime<Utc>, now: DateTime<Utc>) -> Resolution {
+        let kept: Vec<Resolution> = [Resolution::Raw, Resolution::Minute, Resolution::Hour]
+            .into_iter()
+            .filter(|r| self.retention.window(*r).is_some())
+            .collect();
+        kept.iter()
+            .copied()
+            .find(|r| {
+                self.retention
+                    .window(*r)
+                    .is_some_and(|window| from >= now - window)
+            })
+            .or(kept.last().copied())
+            .unwrap_or(Resolution::Raw)
+    }
+
+    fn series(&self, from: DateTime<Utc>, to: DateTime<Utc>, resolution: Resolution) -> HistorySeries {
+        let aggregates = |aggregates: &VecDeque<Aggregate>, interval: TimeDelta| {
+            aggregates
+                .iter()
+                .filter(|a| a.start + interval > from && a.start <= to)
+                .cloned()
+                .collect()
+        };
+        match resolution {
+            Resolution::Raw => HistorySeries::Raw(
+                self.raw
+                    .iter()
+                    .filter(|v| self.retention.raw.is_some() && v.timestamp >= from && v.timestamp <= to)
+                    .cloned()
+                    .collect(),
+            ),
+            Resolution::Minute => HistorySeries::Minute(aggregates(&self.minutes, TimeDelta::minutes(1))),
+            Resolution::Hour => HistorySeries::Hour(aggregates(&self.hours, TimeDelta::hours(1))),
+        }
+    }
+
+    /// The numeric values in a range as aggregates, a raw value being an aggregate of one
+    fn aggregates(&self, from: DateTime<Utc>, to: DateTime<Utc>, resolution: Resolution) -> Vec<Aggregate> {
+        match self.series(from, to, resolution) {
+            HistorySeries::Raw(values) => values
+                .into_iter()
+                .filter_map(|v| match v.value {
+                    SlotValue::Number(n) => Some(Aggregate::new(v.timestamp, &v.state, n)),
+                    _ => None,
+                })
+                .collect(),
+            HistorySeries::Minute(aggregates) | HistorySeries::Hour(aggregates) => aggregates,
+        }
+    }
+}
+
+/// Add a value to the aggregate of its interval
+fn downsample(
+    aggregates: &mut VecDeque<Aggregate>,
+    timestamp: DateTime<Utc>,
+    interval: TimeDelta,
+    value: f64,
+    state: &str,
+) {
+    let Ok(start) = timestamp.duration_trunc(interval) else {
+        return;
+    };
+    match aggregates.back_mut() {
+        Some(aggregate) if aggregate.start == start && aggregate.state == state => aggregate.add(value),
+        _ => aggregates.push_back(Aggregate::new(start, state, value)),
+    }
+}
+
+/// Extent of the history of a slot, saved in the backups
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryMetadata {
+    pub asset_id: AssetID,
+    pub slot: String,
+    pub raw_values: usize,
+    pub minute_aggregates: usize,
+    pub hour_aggregates: usize,
+    /// Time of the oldest value or aggregate kept
+    pub oldest: Option<DateTime<Utc>>,
+    /// Time of the latest value or aggregate kept
+    pub newest: Option<DateTime<Utc>>,
+}
+
+/// History of the slot values of the twins, kept in memory within the retention windows
+#[derive(Debug)]
+pub struct HistoryStore {
+    default_retention: Retention,
+    slot_retention: HashMap<String, Retention>,
+    slots: HashMap<(AssetID, String), SlotHistory>,
+}
+
+pub type SharedHistory = Arc<Mutex<HistoryStore>>;
+
+impl HistoryStore {
+    /// The history store, if enabled in the options
+    pub fn shared(options: &HistoryOptions) -> Option<SharedHistory> {
+        options
+            .history
+            .then(|| Arc::new(Mutex::new(HistoryStore::new(options))))
+    }
+
+    fn new(options: &HistoryOptions) -> Self {
+        HistoryStore {
+            default_retention: Retention::new(
+                options.history_raw_retention,
+                options.history_minute_retention,
+                options.history_hour_retention,
+            ),
+            slot_retention: options.history_slot_retention.iter().cloned().collect(),
+            slots: HashMap::new(),
+        }
+    }
+
+    /// Record a value of a slot of a twin, with the state of the twin after receiving it
+    pub fn record(
+        &mut self,
+        asset_id: &AssetID,
+        slot: &str,
+        timestamp: DateTime<Utc>,
+        value: SlotValue,
+        state: &str,
+    ) {
+        let retention = self
+            .slot_retention
+            .get(slot)
+            .copied()
+            .unwrap_or(self.default_retention);
+        self.slots
+            .entry((asset_id.clone(), slot.to_string()))
+            .or_insert_with(|| SlotHistory::new(retention))
+            .record(timestamp, value, state);
+    }
+
+    /// Extent of the history of each slot, sorted by asset ID and slot
+    pub fn metadata(&self) -> Vec<HistoryMetadata> {
+        let mut metadata: Vec<HistoryMetadata> = self
+            .slots
+            .iter()
+            .map(|((asset_id, slot), history)| HistoryMetadata {
+                asset_id: asset_id.clone(),
+                slot: slot.clone(),
+                raw_values: history.raw.len(),
+                minute_aggregates: history.minutes.len(),
+                hour_aggregates: history.hours.len(),
+                oldest: [
+                    history.raw.front().map(|v| v.timestamp),
+                    history.minutes.front().map(|a| a.start),
+                    history.hours.front().map(|a| a.start),
+                ]
+                .into_iter()
+                .flatten()
+                .min(),
+                newest: [
+                    history.raw.back().map(|v| v.timestamp),
+                    history.minutes.back().map(|a| a.start),
+                    history.hours.back().map(|a| a.start),
+                ]
+                .into_iter()
+                .flatten()
+                .max(),
+            })
+            .collect();
+        metadata.sort_by(|a, b| (&a.asset_id, &a.slot).cmp(&(&b.asset_id, &b.slot)));
+        metadata
+    }
+
+    /// The values of a slot of a twin between two times, at the given resolution or at the
+    /// finest one covering the range (None if the slot has no history)
+    pub fn query(
+        &mut self,
+        asset_id: &AssetID,
+        slot: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        resolution: Option<Resolution>,
+        now: DateTime<Utc>,
+    ) -> Option<HistorySeries> {
+        let history = self.slots.get_mut(&(asset_id.clone(), slot.to_string()))?;
+        history.prune(now);
+        let resolution = resolution.unwrap_or_else(|| history.resolution_for(from, now));
+        Some(history.series(from, to, resolution))
+    }
+
+    /// Aggregate the numeric values of a slot of a twin between two times, optionally for
+    /// each state of the twin (None if the slot has no history)
+    #[allow(clippy::too_many_arguments)]
+    pub fn aggregate(
+        &mut self,
+        asset_id: &AssetID,
+        slot: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        resolution: Option<Resolution>,
+        function: AggregateFunction,
+        group_by: Option<GroupBy>,
+        now: DateTime<Utc>,
+    ) -> Option<HistoryAggregation> {
+        let history = self.slots.get_mut(&(asset_id.clone(), slot.to_string()))?;
+        history.prune(now);
+        let resolution = resolution.unwrap_or_else(|| history.resolution_for(from, now));
+        let mut groups: BTreeMap<Option<String>, Aggregate> = BTreeMap::new();
+        for aggregate in history.aggregates(from, to, resolution) {
+            let key = group_by.map(|GroupBy::State| aggregate.state.clone());
+            match groups.get_mut(&key) {
+                Some(group) => group.merge(&aggregate),
+                None => {
+                    groups.insert(key, aggregate);
+                }
+            }
+        }
+        let groups = if groups.is_empty() && group_by.is_none() {
+            vec![AggregateGroup {
+                state: None,
+                count: 0,
+                value: None,
+            }]
+        } else {
+            groups
+                .into_iter()
+                .map(|(state, aggregate)| AggregateGroup {
+                    state,
+                    count: aggregate.count,
+                    value: Some(function.apply(&aggregate)),
+                })
+                .collect()
+        };
+        Some(HistoryAggregation {
+            function,
+            resolution,
+            from,
+            to,
+            groups,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(slot_retention: Vec<(String, Retention)>) -> HistoryOptions {
+        HistoryOptions {
+            history: true,
+            history_raw_retention: 3600,
+            history_minute_retention: 86400,
+            history_hour_retention: 0,
+            history_slot_retention: slot_retention,
+        }
+    }
+
+    #[test]
+    fn test_parse_slot_retention() {
+        assert_eq!(
+            parse_slot_retention("SignalStrength=600, 86400, 0").unwrap(),
+            ("SignalStrength".to_string(), Retention::new(600, 86400, 0))
+        );
+        assert!(parse_slot_retention("SignalStrength=600").is_err());
+        assert!(parse_slot_retention("SignalStrength").is_err());
+        assert!(parse_slot_retention("SignalStrength=1h,1d,0").is_err());
+    }
+
+    #[test]
+    fn test_downsampling() {
+        let mut store = HistoryStore::new(&options(Vec::new()));
+        let id = AssetID::from("charger");
+        let start: DateTime<Utc> = "2025-03-01T10:00:00Z".parse().unwrap();
+        // One value every 20 seconds for 2 hours, 0 to 359
+        for i in 0..360 {
+            let time = start + TimeDelta::seconds(20 * i);
+            store.record(&id, "InputCurrent", time, SlotValue::Number(i as f64), "Charging");
+        }
+        let now = start + TimeDelta::hours(2);
+
+        // The raw values of the last hour only
+        let Some(HistorySeries::Raw(raw)) =
+            store.query(&id, "InputCurrent", now - TimeDelta::minutes(30), now, None, now)
+        else {
+            panic!("expected raw values");
+        };
+        assert_eq!(raw.len(), 90);
+        assert_eq!(raw[0].value, SlotValue::Number(270.0));
+
+        // Older values are aggregated by minute
+        let Some(HistorySeries::Minute(minutes)) = store.query(&id, "InputCurrent", start, now, None, now)
+        else {
+            panic!("expected minute aggregates");
+        };
+        assert_eq!(minutes.len(), 120);
+        assert_eq!(
+            minutes[1],
+            Aggregate {
+                start: start + TimeDelta::minutes(1),
+                state: "Charging".to_string(),
+                count: 3,
+                min: 3.0,
+                max: 5.0,
+                mean: 4.0,
+                last: 5.0,
+            }
+        );
+
+        // The hour aggregates are not kept, a late value is ignored
+        assert_eq!(
+            store.query(&id, "InputCurrent", start, now, Some(Resolution::Hour), now),
+            Some(HistorySeries::Hour(Vec::new()))
+        );
+        store.record(&id, "InputCurrent", start, SlotValue::Number(1000.0), "Charging");
+        assert_eq!(
+            store.query(&id, "InputCurrent", start, start, Some(Resolution::Minute), now),
+            Some(HistorySeries::Minute(vec![minutes[0].clone()]))
+        );
+        assert_eq!(store.query(&id, "SignalStrength", start, now, None, now), None);
+
+        let metadata = store.metadata();
+        assert_eq!(metadata.len(), 1);
+        assert_eq!(metadata[0].slot, "InputCurrent");
+        assert_eq!(
+            (
+                metadata[0].raw_values,
+                metadata[0].minute_aggregates,
+                metadata[0].hour_aggregates
+            ),
+            (180, 120, 0)
+        );
+        assert_eq!(metadata[0].oldest, Some(start));
+        assert_eq!(metadata[0].newest, Some(start + TimeDelta::seconds(20 * 359)));
+    }
+
+    #[test]
+    fn test_parse_time() {
+        let now: DateTime<Utc> = "2025-03-08T10:00:00Z".parse().unwrap();
+        assert_eq!(parse_time("now", now).unwrap(), now);
+        assert_eq!(parse_time("-7d", now).unwrap(), now - TimeDelta::days(7));
+        assert_eq!(parse_time("-90m", now).unwrap(), now - TimeDelta::minutes(90));
+        assert_eq!(
+            parse_time("2025-03-01T11:00:00+01:00", now).unwrap(),
+            now - TimeDelta::days(7)
+        );
+        assert!(parse_time("-7y", now).is_err());
+        assert!(parse_time("-d", now).is_err());
+        assert!(parse_time("yesterday", now).is_err());
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let mut store = HistoryStore::new(&options(Vec::new()));
+        let id = AssetID::from("charger");
+        let start: DateTime<Utc> = "2025-03-01T10:00:00Z".parse().unwrap();
+        // Charging at 10 kW for 30 seconds, then idle
+        for (secs, power, state) in [
+            (0, 10.0, "Charging"),
+            (10, 12.0, "Charging"),
+            (20, 8.0, "Charging"),
+            (30, 0.0, "Idle"),
+            (90, 0.5, "Idle"),
+        ] {
+            let time = start + TimeDelta::seconds(secs);
+            store.record(&id, "Power", time, SlotValue::Number(power), state);
+        }
+        let now = start + TimeDelta::minutes(2);
+        let aggregate = |store: &mut HistoryStore, from, resolution, function, group_by| {
+            store
+                .aggregate(&id, "Power", from, now, resolution, function, group_by, now)
+                .unwrap()
+        };
+
+        let avg = aggregate(
+            &mut store,
+            start,
+            None,
+            AggregateFunction::Avg,
+            Some(GroupBy::State),
+        );
+        assert_eq!(avg.resolution, Resolution::Raw);
+        let group = |state: &str, count, value| AggregateGroup {
+            state: Some(state.to_string()),
+            count,
+            value: Some(value),
+        };
+        assert_eq!(avg.groups, [group("Charging", 3, 10.0), group("Idle", 2, 0.25)]);
+
+        // The minute aggregates give the same result
+        let minute = Some(Resolution::Minute);
+        let max = aggregate(
+            &mut store,
+            start,
+            minute,
+            AggregateFunction::Max,
+            Some(GroupBy::State),
+        );
+        assert_eq!(max.groups, [group("Charging", 3, 12.0), group("Idle", 2, 0.5)]);
+        let avg = aggregate(&mut store, start, minute, AggregateFunction::Avg, None);
+        assert_eq!(avg.groups.len(), 1);
+        assert_eq!(avg.groups[0].count, 5);
+        assert!((avg.groups[0].value.unwrap() - 6.1).abs() < 1e-9);
+        let last = aggregate(&mut store, start, minute, AggregateFunction::Last, None);
+        assert_eq!(last.groups[0].value, Some(0.5));
+
+        // No values in the range
+        let empty = aggregate(&mut store, now, None, AggregateFunction::Min, None);
+        assert_eq!(
+            empty.groups,
+            [AggregateGroup {
+                state: None,
+                count: 0,
+                value: None
+            }]
+        );
+    }
+
+    #[test]
+    fn test_slot_retention() {
+        let retention = vec![("Status".to_string(), Retention::new(0, 60, 0))];
+        let mut store = HistoryStore::new(&options(retention));
+        let id = AssetID::from("charger");
+        let start: DateTime<Utc> = "2025-03-01T10:00:00Z".parse().unwrap();
+        store.record(&id, "Status", start, SlotValue::Text("ok".into()), "Idle");
+        store.record(
+            &id,
+            "Status",
+            start + TimeDelta::seconds(1),
+            SlotValue::Number(1.0),
+            "Idle",
+        );
+        // Raw values are not kept, nor the aggregates past their window
+        let now = start + TimeDelta::minutes(5);
+        assert_eq!(
+            store.query(&id, "Status", start, now, Some(Resolution::Raw), now),
+            Some(HistorySeries::Raw(Vec::new()))
+        );
+        assert_eq!(
+            store.query(&id, "Status", start, now, None, now),
+            Some(HistorySeries::Minute(Vec::new()))
+        );
+    }
+}
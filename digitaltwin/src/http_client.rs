@@ -0,0 +1,191 @@
+use std::time::Duration;
+use thiserror::Error as ThisError;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::tls;
+
+/// Maximum time to wait for a response
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(ThisError, Debug)]
+pub enum HttpError {
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+    #[error("only http:// and https:// URLs are supported: {0}")]
+    UnsupportedUrl(String),
+    #[error("request timed out")]
+    Timeout,
+    #[error("malformed response")]
+    Malformed,
+    #[error("unexpected response: {0}")]
+    Status(String),
+}
+
+/// An http:// or https:// URL, split into the host (with the port, if any) and the path
+#[derive(Debug, PartialEq)]
+struct Url<'a> {
+    tls: bool,
+    host: &'a str,
+    path: &'a str,
+}
+
+impl<'a> Url<'a> {
+    fn parse(url: &'a str) -> Result<Self, HttpError> {
+        let (tls, rest) = match (url.strip_prefix("http://"), url.strip_prefix("https://")) {
+            (Some(rest), _) => (false, rest),
+            (_, Some(rest)) => (true, rest),
+            _ => return Err(HttpError::UnsupportedUrl(url.to_string())),
+        };
+        let (host, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        Ok(Url { tls, host, path })
+    }
+
+    /// The host and the port to connect to, the default one of the scheme if not given
+    fn address(&self) -> (&str, u16) {
+        let default = if self.tls { 443 } else { 80 };
+        match self.host.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().unwrap_or(default)),
+            None => (self.host, default),
+        }
+    }
+}
+
+/// GET a resource over HTTP or HTTPS (e.g., from a Vault Agent or a price feed), returning
+/// the body of a 200 response
+pub async fn get(url: &str, headers: &[(&str, &str)]) -> Result<String, HttpError> {
+    let (status, body) = request("GET", url, headers, None).await?;
+    if status.split_whitespace().nth(1) != Some("200") {
+        return Err(HttpError::Status(status));
+    }
+    Ok(body)
+}
+
+/// POST a JSON document over HTTP or HTTPS (e.g., to a webhook), returning the body of a
+/// successful (2xx) response
+pub async fn post_json(url: &str, headers: &[(&str, &str)], json: &str) -> Result<String, HttpError> {
+    send_json("POST", url, headers, json).await
+}
+
+/// Send a JSON document over HTTP or HTTPS with the given method (e.g., PUT to a device API),
+/// returning the body of a successful (2xx) response
+pub async fn send_json(
+    method: &str,
+    url: &str,
+    headers: &[(&str, &str)],
+    json: &str,
+) -> Result<String, HttpError> {
+    send(method, url, headers, "application/json", json).await
+}
+
+/// POST a document of the given content type over HTTP or HTTPS (e.g., InfluxDB line protocol),
+/// returning the body of a successful (2xx) response
+pub async fn post(
+    url: &str,
+    headers: &[(&str, &str)],
+    content_type: &str,
+    content: &str,
+) -> Result<String, HttpError> {
+    send("POST", url, headers, content_type, content).await
+}
+
+async fn send(
+    method: &str,
+    url: &str,
+    headers: &[(&str, &str)],
+    content_type: &str,
+    content: &str,
+) -> Result<String, HttpError> {
+    let (status, body) = request(method, url, headers, Some((content_type, content))).await?;
+    if !status
+        .split_whitespace()
+        .nth(1)
+        .is_some_and(|code| code.starts_with('2'))
+    {
+        return Err(HttpError::Status(status));
+    }
+    Ok(body)
+}
+
+/// Send a request, returning the status line and the body of the response. HTTP/1.0
+/// keeps the response simple: no chunked encoding, closed at the end.
+async fn request(
+    method: &str,
+    url: &str,
+    headers: &[(&str, &str)],
+    content: Option<(&str, &str)>,
+) -> Result<(String, String), HttpError> {
+    let url = Url::parse(url)?;
+    let mut request = format!("{method} {} HTTP/1.0\r\nHost: {}\r\n", url.path, url.host);
+    for (name, value) in headers {
+        request.push_str(&format!("{name}: {value}\r\n"));
+    }
+    if let Some((content_type, body)) = content {
+        request.push_str(&format!(
+            "Content-Type: {content_type}\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        ));
+    } else {
+        request.push_str("\r\n");
+    }
+    let exchange = async {
+        let (host, port) = url.address();
+        let stream = tokio::net::TcpStream::connect((host, port)).await?;
+        if url.tls {
+            exchange(tls::connect(host, stream).await?, &request).await
+        } else {
+            exchange(stream, &request).await
+        }
+    };
+    let response = tokio::time::timeout(REQUEST_TIMEOUT, exchange)
+        .await
+        .map_err(|_| HttpError::Timeout)??;
+    let response = String::from_utf8_lossy(&response);
+    let (head, body) = response.split_once("\r\n\r\n").ok_or(HttpError::Malformed)?;
+    let status = head.lines().next().unwrap_or_default();
+    Ok((status.to_string(), body.to_string()))
+}
+
+/// Write the request and read the response until the server closes the connection
+async fn exchange<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    request: &str,
+) -> Result<Vec<u8>, HttpError> {
+    stream.write_all(request.as_bytes()).await?;
+    let mut response = Vec::new();
+    match stream.read_to_end(&mut response).await {
+        Ok(_) => Ok(response),
+        // Servers often close a TLS connection without notifying it, at the end of the response
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof && !response.is_empty() => Ok(response),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_url() {
+        let url = Url::parse("http://127.0.0.1:8200/v1/secret").unwrap();
+        assert_eq!(
+            (url.tls, url.host, url.path),
+            (false, "127.0.0.1:8200", "/v1/secret")
+        );
+        assert_eq!(url.address(), ("127.0.0.1", 8200));
+        let url = Url::parse("http://prices.local").unwrap();
+        assert_eq!(
+            (url.host, url.path, url.address()),
+            ("prices.local", "/", ("prices.local", 80))
+        );
+        let url = Url::parse("https://hooks.slack.com/services/T0/B0").unwrap();
+        assert_eq!((url.tls, url.path), (true, "/services/T0/B0"));
+        assert_eq!(url.address(), ("hooks.slack.com", 443));
+        assert!(matches!(
+            Url::parse("ftp://prices.local/today"),
+            Err(HttpError::UnsupportedUrl(_))
+        ));
+    }
+}
@@ -0,0 +1,316 @@
+use clap::{Args, Parser};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use thiserror::Error as ThisError;
+
+use crate::manager::{self, TWINS_DIR};
+use crate::templates::{self, Parameters};
+use digitaltwin_core::AssetID;
+
+/// Extensions of the supported template formats, in lookup order
+const TEMPLATE_EXTENSIONS: [&str; 3] = ["yaml", "json", "xml"];
+
+#[derive(Parser, Clone)]
+pub struct ImportOptions {
+    /// directory of the templates used to import a fleet inventory, looked up by name
+    /// ("<name>.template.yaml", ".json" or ".xml")
+    #[clap(long, default_value = "./templates", env = "TEMPLATES_DIR")]
+    templates_dir: PathBuf,
+}
+
+/// Arguments of the import subcommand
+#[derive(Args)]
+pub struct ImportArgs {
+    /// fleet inventory: a CSV file with a header row, or a JSON array of objects (.json)
+    pub inventory: PathBuf,
+    /// template instantiated for each row of the inventory, by name or path; the columns of
+    /// the row are its parameters
+    #[clap(long)]
+    pub template: String,
+}
+
+#[derive(ThisError, Debug)]
+pub enum ImportError {
+    #[error("template {0} not found")]
+    TemplateNotFound(String),
+    #[error("cannot read {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("invalid inventory: {0}")]
+    InvalidInventory(String),
+}
+
+/// A fleet inventory, as received by the REST API
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum Inventory {
+    /// CSV text with a header row
+    Csv(String),
+    /// One object per asset
+    Rows(Vec<serde_json::Map<String, serde_json::Value>>),
+}
+
+impl Inventory {
+    /// Read an inventory file, in JSON if its extension is .json and in CSV otherwise
+    pub fn from_file(path: &Path) -> Result<Vec<Parameters>, ImportError> {
+        let text = std::fs::read_to_string(path).map_err(|e| ImportError::Io(path.to_path_buf(), e))?;
+        let inventory = if path.extension().is_some_and(|ext| ext == "json") {
+            Inventory::Rows(
+                serde_json::from_str(&text).map_err(|e| ImportError::InvalidInventory(e.to_string()))?,
+            )
+        } else {
+            Inventory::Csv(text)
+        };
+        inventory.rows()
+    }
+
+    /// The parameters of each asset
+    pub fn rows(self) -> Result<Vec<Parameters>, ImportError> {
+        match self {
+            Inventory::Csv(text) => parse_csv(&text),
+            Inventory::Rows(rows) => rows
+                .into_iter()
+                .enumerate()
+                .map(|(i, row)| {
+                    row.into_iter()
+                        .map(|(name, value)| match value {
+                            serde_json::Value::String(s) => Ok((name, s)),
+                            serde_json::Value::Number(_) | serde_json::Value::Bool(_) => {
+                                Ok((name, value.to_string()))
+                            }
+                            _ => Err(ImportError::InvalidInventory(format!(
+                                "row {}: {name} is not a scalar",
+                                i + 1
+                            ))),
+                        })
+                        .collect()
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Outcome of an import
+#[derive(Debug, Default, Serialize)]
+pub struct ImportReport {
+    /// Twins whose definition was written, in inventory order
+    pub created: Vec<AssetID>,
+    /// Rows that could not be imported
+    pub failed: Vec<RowFailure>,
+}
+
+/// A row of the inventory that could not be imported
+#[derive(Debug, Serialize)]
+pub struct RowFailure {
+    /// Position of the row in the inventory, from 1 (the CSV header is not counted)
+    pub row: usize,
+    pub error: String,
+}
+
+/// Generates twin definitions from a fleet inventory, writing one definition file per
+/// asset in the twins directory: the twins start at the next reload.
+pub struct Importer {
+    templates_dir: PathBuf,
+    twins_dir: PathBuf,
+}
+
+pub type SharedImporter = Arc<Importer>;
+
+impl Importer {
+    pub fn new(options: &ImportOptions) -> Self {
+        Importer {
+            templates_dir: options.templates_dir.clone(),
+            twins_dir: PathBuf::from(TWINS_DIR),
+        }
+    }
+
+    /// The template with this name in the templates directory, or at this path
+    fn find_template(&self, template: &str) -> Option<PathBuf> {
+        TEMPLATE_EXTENSIONS
+            .iter()
+            .map(|ext| self.templates_dir.join(format!("{template}.template.{ext}")))
+            .chain(std::iter::once(PathBuf::from(template)))
+            .find(|path| path.is_file() && manager::parser_for(path).is_some())
+    }
+
+    /// Instantiate a template for each row of an inventory. Rows whose definition is invalid,
+    /// or whose twin already has a definition file, are skipped and recorded in the report.
+    pub fn import(&self, template: &str, rows: &[Parameters]) -> Result<ImportReport, ImportError> {
+        let path = self
+            .find_template(template)
+            .ok_or_else(|| ImportError::TemplateNotFound(template.to_string()))?;
+        let parse =
+            manager::parser_for(&path).ok_or_else(|| ImportError::TemplateNotFound(template.to_string()))?;
+        let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+        let text = std::fs::read_to_string(&path).map_err(|e| ImportError::Io(path.clone(), e))?;
+
+        let mut report = ImportReport::default();
+        let mut files = HashSet::new();
+        for (i, params) in rows.iter().enumerate() {
+            let result = templates::substitute(&text, params).and_then(|definition| {
+                let aas = parse(definition.as_bytes())?;
+                let file = self.twins_dir.join(definition_file_name(&aas.id, extension));
+                if file.exists() || !files.insert(file.clone()) {
+                    return Err(format!("a definition of {} already exists", aas.id));
+                }
+                std::fs::write(&file, definition)
+                    .map_err(|e| format!("cannot write {}: {e}", file.display()))?;
+                Ok(aas.id)
+            });
+            match result {
+                Ok(id) => report.created.push(id),
+                Err(error) => report.failed.push(RowFailure { row: i + 1, error }),
+            }
+        }
+        info!(
+            "Imported {} twins from template {template}, {} rows failed",
+            report.created.len(),
+            report.failed.len()
+        );
+        Ok(report)
+    }
+}
+
+/// The name of the definition file of a twin, from its asset ID
+fn definition_file_name(id: &str, extension: &str) -> String {
+    let name: String = id
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{name}.{extension}")
+}
+
+/// Parse CSV text with a header row naming the columns. Fields may be quoted, with
+/// doubled quotes inside; blank lines are ignored.
+fn parse_csv(text: &str) -> Result<Vec<Parameters>, ImportError> {
+    let mut records = csv_records(text)?.into_iter();
+    let header: Vec<String> = records
+        .next()
+        .ok_or_else(|| ImportError::InvalidInventory("missing header row".into()))?
+        .into_iter()
+        .map(|name| name.trim().to_string())
+        .collect();
+    records
+        .enumerate()
+        .map(|(i, record)| {
+            if record.len() != header.len() {
+                return Err(ImportError::InvalidInventory(format!(
+                    "row {}: {} fields, expected {}",
+                    i + 1,
+                    record.len(),
+                    header.len()
+                )));
+            }
+            Ok(header.iter().cloned().zip(record).collect())
+        })
+        .collect()
+}
+
+fn csv_records(text: &str) -> Result<Vec<Vec<String>>, ImportError> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => quoted = true,
+            ',' if !quoted => record.push(std::mem::take(&mut field)),
+            '\n' if !quoted => {
+                record.push(std::mem::take(&mut field));
+                let record = std::mem::take(&mut record);
+                if record.len() > 1 || !record[0].trim().is_empty() {
+                    records.push(record);
+                }
+            }
+            '\r' if !quoted && chars.peek() == Some(&'\n') => {}
+            c => field.push(c),
+        }
+    }
+    if quoted {
+        return Err(ImportError::InvalidInventory("unterminated quoted field".into()));
+    }
+    if !record.is_empty() || !field.trim().is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_inventory() {
+        let rows = Inventory::Csv(
+            "station_id,location\r\n000101,\"Garage A, level 1\"\r\n\r\n000102,\"The \"\"B\"\" lot\"\r\n"
+                .into(),
+        )
+        .rows()
+        .unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["station_id"], "000101");
+        assert_eq!(rows[0]["location"], "Garage A, level 1");
+        assert_eq!(rows[1]["location"], "The \"B\" lot");
+
+        assert!(Inventory::Csv("a,b\n1\n".into()).rows().is_err());
+        assert!(Inventory::Csv("a,b\n1,\"2\n".into()).rows().is_err());
+
+        let rows: Inventory =
+            serde_json::from_str(r#"[{"station_id": "000101", "max_current": 32}]"#).unwrap();
+        let rows = rows.rows().unwrap();
+        assert_eq!(rows[0]["max_current"], "32");
+        let rows: Inventory = serde_json::from_str(r#"[{"station_id": null}]"#).unwrap();
+        assert!(rows.rows().is_err());
+    }
+
+    #[test]
+    fn test_import() {
+        let dir = std::env::temp_dir().join(format!("dt-import-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("station.template.yaml"),
+            include_str!("../../twins/charger.yaml").replace("id-000001", "id-{{station_id}}"),
+        )
+        .unwrap();
+        let importer = Importer {
+            templates_dir: dir.clone(),
+            twins_dir: dir.clone(),
+        };
+        let rows = Inventory::Csv("station_id\n000101\n000102\n000101\n".into())
+            .rows()
+            .unwrap();
+        let report = importer.import("station", &rows).unwrap();
+        assert_eq!(
+            report.created,
+            [
+                "urn:aas:smart-home:charging-station:ac-level2:id-000101",
+                "urn:aas:smart-home:charging-station:ac-level2:id-000102"
+            ]
+        );
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].row, 3);
+        assert!(dir
+            .join("urn_aas_smart-home_charging-station_ac-level2_id-000102.yaml")
+            .is_file());
+        assert!(matches!(
+            importer.import("missing", &rows),
+            Err(ImportError::TemplateNotFound(_))
+        ));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,83 @@
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Upper bounds of the histogram buckets, in milliseconds; the last bucket is unbounded
+const BUCKET_BOUNDS_MS: [f64; 12] = [
+    0.1, 0.5, 1.0, 2.0, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0,
+];
+
+/// Histogram of durations with explicit bucket bounds, shaped as an OpenTelemetry
+/// histogram data point: `bucket_counts[i]` counts the values up to `explicit_bounds[i]`
+/// (and above the previous bound), the last one the values above all the bounds
+#[derive(Debug, Clone, Serialize)]
+pub struct LatencyHistogram {
+    pub unit: &'static str,
+    pub count: u64,
+    pub sum: f64,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub explicit_bounds: &'static [f64],
+    pub bucket_counts: Vec<u64>,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        LatencyHistogram {
+            unit: "ms",
+            count: 0,
+            sum: 0.0,
+            min: None,
+            max: None,
+            explicit_bounds: &BUCKET_BOUNDS_MS,
+            bucket_counts: vec![0; BUCKET_BOUNDS_MS.len() + 1],
+        }
+    }
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, duration: Duration) {
+        let ms = duration.as_secs_f64() * 1000.0;
+        let bucket = BUCKET_BOUNDS_MS.partition_point(|&bound| bound < ms);
+        self.bucket_counts[bucket] += 1;
+        self.count += 1;
+        self.sum += ms;
+        self.min = Some(self.min.map_or(ms, |min| min.min(ms)));
+        self.max = Some(self.max.map_or(ms, |max| max.max(ms)));
+    }
+}
+
+/// Latency of the MQTT ingest path of the network receiver
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct IngestMetrics {
+    /// From a Publish returned by the event loop to the end of its fan-out to the twins
+    pub message_latency: LatencyHistogram,
+    /// Time spent waiting for a twin channel to accept an update; high values mean
+    /// slow twins or full channels
+    pub send_wait: LatencyHistogram,
+    /// Duplicate deliveries of messages and updates, dropped by the replay guard
+    pub duplicates_dropped: u64,
+}
+
+/// Ingest metrics shared by the network receiver and the REST server
+pub type SharedIngestMetrics = Arc<Mutex<IngestMetrics>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram() {
+        let mut histogram = LatencyHistogram::default();
+        for us in [50, 100, 700, 3_000, 2_000_000] {
+            histogram.record(Duration::from_micros(us));
+        }
+        assert_eq!(histogram.count, 5);
+        assert_eq!(histogram.bucket_counts[..4], [2, 0, 1, 0]);
+        assert_eq!(histogram.bucket_counts[4], 1);
+        assert_eq!(histogram.bucket_counts[12], 1);
+        assert_eq!(histogram.min, Some(0.05));
+        assert_eq!(histogram.max, Some(2000.0));
+        assert!((histogram.sum - 2003.85).abs() < 1e-9);
+    }
+}
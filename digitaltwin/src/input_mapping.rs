@@ -0,0 +1,262 @@
+use std::str::FromStr;
+
+use digitaltwin_core::{MqttUpdate, SlotValue};
+
+/// A step of a selector into a JSON payload
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    Field(String),
+    Index(usize),
+}
+
+/// Where a mapping reads a field of an update: a path into the JSON payload, as "$.meta.id",
+/// "$.readings[0].w" or "$['power.w']", or a level of the topic, as "topic[2]"
+#[derive(Debug, Clone, PartialEq)]
+pub enum Selector {
+    Payload(Vec<Step>),
+    Topic(usize),
+}
+
+impl FromStr for Selector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        let invalid = |reason: &str| format!("invalid selector {s}: {reason}");
+        if let Some(level) = s.strip_prefix("topic[").and_then(|rest| rest.strip_suffix(']')) {
+            return level
+                .trim()
+                .parse()
+                .map(Selector::Topic)
+                .map_err(|_| invalid("expected a topic level"));
+        }
+        let mut rest = s
+            .strip_prefix('$')
+            .ok_or_else(|| invalid("expected $ or topic[<level>]"))?;
+        let mut steps = Vec::new();
+        while !rest.is_empty() {
+            if let Some(field) = rest.strip_prefix('.') {
+                let end = field.find(['.', '[']).unwrap_or(field.len());
+                if end == 0 {
+                    return Err(invalid("empty field name"));
+                }
+                steps.push(Step::Field(field[..end].to_string()));
+                rest = &field[end..];
+            } else if let Some(bracket) = rest.strip_prefix('[') {
+                let end = bracket.find(']').ok_or_else(|| invalid("missing ]"))?;
+                let inner = bracket[..end].trim();
+                let quoted = inner
+                    .strip_prefix('\'')
+                    .and_then(|name| name.strip_suffix('\''))
+                    .or_else(|| inner.strip_prefix('"').and_then(|name| name.strip_suffix('"')));
+                steps.push(match quoted {
+                    Some(name) => Step::Field(name.to_string()),
+                    None => Step::Index(
+                        inner
+                            .parse()
+                            .map_err(|_| invalid("expected an index or a quoted field name"))?,
+                    ),
+                });
+                rest = &bracket[end + 1..];
+            } else {
+                return Err(invalid("expected . or ["));
+            }
+        }
+        Ok(Selector::Payload(steps))
+    }
+}
+
+impl std::fmt::Display for Selector {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Selector::Topic(level) => write!(f, "topic[{level}]"),
+            Selector::Payload(steps) => {
+                write!(f, "$")?;
+                for step in steps {
+                    match step {
+                        Step::Field(name) => write!(f, "[{name:?}]")?,
+                        Step::Index(index) => write!(f, "[{index}]")?,
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Selector {
+    /// The selected value, if present
+    fn select<'a>(&self, topic: &'a str, payload: &'a serde_json::Value) -> Option<Selected<'a>> {
+        match self {
+            Selector::Topic(level) => topic.split('/').nth(*level).map(Selected::Level),
+            Selector::Payload(steps) => steps
+                .iter()
+                .try_fold(payload, |value, step| match step {
+                    Step::Field(name) => value.get(name),
+                    Step::Index(index) => value.get(index),
+                })
+                .filter(|value| !value.is_null())
+                .map(Selected::Json),
+        }
+    }
+}
+
+enum Selected<'a> {
+    Level(&'a str),
+    Json(&'a serde_json::Value),
+}
+
+impl Selected<'_> {
+    fn text(&self) -> Option<String> {
+        match self {
+            Selected::Level(level) => Some(level.to_string()),
+            Selected::Json(serde_json::Value::String(s)) => Some(s.clone()),
+            Selected::Json(serde_json::Value::Number(n)) => Some(n.to_string()),
+            Selected::Json(_) => None,
+        }
+    }
+
+    /// The value of a slot: numeric strings are read as numbers
+    fn slot_value(&self) -> Option<SlotValue> {
+        let value = match self {
+            Selected::Level(level) => return Some(text_value(level)),
+            Selected::Json(value) => value,
+        };
+        match value {
+            serde_json::Value::Bool(b) => Some(SlotValue::Bool(*b)),
+            serde_json::Value::Number(n) => n.as_f64().map(SlotValue::Number),
+            serde_json::Value::String(s) => Some(text_value(s)),
+            _ => None,
+        }
+    }
+
+    fn number(&self) -> Option<u64> {
+        match self {
+            Selected::Level(level) => level.parse().ok(),
+            Selected::Json(value) => value
+                .as_u64()
+                .or_else(|| value.as_str().and_then(|s| s.parse().ok())),
+        }
+    }
+}
+
+fn text_value(s: &str) -> SlotValue {
+    s.trim()
+        .parse()
+        .map(SlotValue::Number)
+        .unwrap_or_else(|_| SlotValue::Text(s.to_string()))
+}
+
+/// Mapping of the JSON payloads received on a topic filter to updates, without a codec
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputMapping {
+    pub filter: String,
+    device: Selector,
+    value: Selector,
+    /// Sequence number of the reading, for the replay guard
+    seq: Option<Selector>,
+}
+
+/// Parse a mapping, as "<topic filter>:device=<selector>,value=<selector>[,seq=<selector>]"
+pub fn parse_input_mapping(s: &str) -> Result<InputMapping, String> {
+    let invalid = || format!("expected <topic filter>:device=<selector>,value=<selector>: {s}");
+    let (filter, fields) = s.split_once(':').ok_or_else(invalid)?;
+    let filter = filter.trim();
+    if filter.is_empty() {
+        return Err(invalid());
+    }
+    let (mut device, mut value, mut seq) = (None, None, None);
+    for field in fields.split(',') {
+        let (name, selector) = field.split_once('=').ok_or_else(invalid)?;
+        let selector = Some(selector.parse()?);
+        match name.trim() {
+            "device" => device = selector,
+            "value" => value = selector,
+            "seq" => seq = selector,
+            name => return Err(format!("unknown field {name} in the mapping of {filter}")),
+        }
+    }
+    Ok(InputMapping {
+        filter: filter.to_string(),
+        device: device.ok_or_else(|| format!("missing device selector in the mapping of {filter}"))?,
+        value: value.ok_or_else(|| format!("missing value selector in the mapping of {filter}"))?,
+        seq,
+    })
+}
+
+impl InputMapping {
+    /// Extract an update from a payload received on the topic
+    pub fn extract(&self, topic: &str, payload: &[u8]) -> Result<MqttUpdate, String> {
+        let payload: serde_json::Value =
+            serde_json::from_slice(payload).map_err(|e| format!("invalid JSON: {e}"))?;
+        let device = self
+            .device
+            .select(topic, &payload)
+            .and_then(|device| device.text())
+            .filter(|device| !device.is_empty())
+            .ok_or_else(|| format!("no device ID at {}", self.device))?;
+        let value = self
+            .value
+            .select(topic, &payload)
+            .and_then(|value| value.slot_value())
+            .ok_or_else(|| format!("no value at {}", self.value))?;
+        Ok(MqttUpdate {
+            object: device.into(),
+            value,
+            seq: self
+                .seq
+                .as_ref()
+                .and_then(|seq| seq.select(topic, &payload))
+                .and_then(|seq| seq.number()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_input_mapping() {
+        let mapping =
+            parse_input_mapping("vendor/+/telemetry:device=$.meta.id,value=$.readings[0]['w'],seq=$.n")
+                .unwrap();
+        assert_eq!(mapping.filter, "vendor/+/telemetry");
+        assert_eq!(
+            mapping.value,
+            Selector::Payload(vec![
+                Step::Field("readings".to_string()),
+                Step::Index(0),
+                Step::Field("w".to_string())
+            ])
+        );
+        let update = mapping
+            .extract(
+                "vendor/acme/telemetry",
+                br#"{"meta": {"id": "powerAbs123"}, "readings": [{"w": 7.5}, {"w": 1}], "n": 42}"#,
+            )
+            .unwrap();
+        assert_eq!(update.object, "powerAbs123");
+        assert_eq!(update.value, SlotValue::Number(7.5));
+        assert_eq!(update.seq, Some(42));
+
+        // Device from the topic, value as a numeric string
+        let mapping = parse_input_mapping("meters/#:device=topic[1],value=$[\"power.w\"]").unwrap();
+        let update = mapping
+            .extract("meters/m-7/data", br#"{"power.w": "12"}"#)
+            .unwrap();
+        assert_eq!(update.object, "m-7");
+        assert_eq!(update.value, SlotValue::Number(12.0));
+        assert_eq!(
+            mapping
+                .extract("meters/m-7/data", br#"{"power": 12}"#)
+                .unwrap_err(),
+            r#"no value at $["power.w"]"#
+        );
+        assert!(mapping.extract("meters/m-7/data", b"12 W").is_err());
+
+        assert!(parse_input_mapping("vendor/#:device=$.id").is_err());
+        assert!(parse_input_mapping("vendor/#:device=meta.id,value=$.w").is_err());
+        assert!(parse_input_mapping("vendor/#:device=$.id,value=$.w[x]").is_err());
+    }
+}
@@ -0,0 +1,1133 @@
+use clap::Parser;
+use log::{debug, error, info, warn};
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use thiserror::Error as ThisError;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::actuation::BreakerTrip;
+use crate::command::CommandEnvelope;
+use crate::diagram::StateDiagram;
+use crate::fleet_snapshot::{FrozenTwin, FREEZE_LIMIT};
+use crate::network_receiver::NetworkMessage;
+use crate::secrets::{self, SecretsProvider};
+use crate::staging::StagingSource;
+use crate::twin_runner::{ActorMessage, AvailableActions, CommandEvaluation, CommandOutcome, TwinReport};
+use digitaltwin_core::{AssetID, CorrelationID, DeviceID, SensorAnnouncement, SlotValue};
+
+/// Largest frame accepted from a peer
+const MAX_FRAME_SIZE: u32 = 16 * 1024 * 1024;
+
+/// Delay between the attempts to reach the hub, or to register a refused twin again
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Time given to a runner to answer the challenge of the hub
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Parser, Clone)]
+pub struct IpcOptions {
+    /// endpoint where remote twin runners connect, as "tcp:<host>:<port>" or "unix:<path>";
+    /// the inputs and commands of their twins are forwarded to them. The runners prove
+    /// that they hold the ipc_key secret, required over TCP. Frames are encoded as JSON.
+    #[clap(long, value_parser = parse_endpoint, env = "IPC_LISTEN")]
+    ipc_listen: Option<Endpoint>,
+
+    /// endpoint of the runtime receiving the MQTT messages, as in --ipc-listen: the twins of
+    /// this process get their inputs and commands from it, instead of the updates topic
+    #[clap(long, value_parser = parse_endpoint, conflicts_with = "ipc_listen", env = "IPC_CONNECT")]
+    ipc_connect: Option<Endpoint>,
+}
+
+/// Address of an IPC endpoint
+#[derive(Debug, Clone, PartialEq)]
+pub enum Endpoint {
+    Tcp(String),
+    #[cfg(unix)]
+    Unix(std::path::PathBuf),
+}
+
+impl std::fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Endpoint::Tcp(address) => write!(f, "tcp:{address}"),
+            #[cfg(unix)]
+            Endpoint::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+fn parse_endpoint(s: &str) -> Result<Endpoint, String> {
+    match s.split_once(':') {
+        Some(("tcp", address)) if !address.is_empty() => Ok(Endpoint::Tcp(address.to_string())),
+        #[cfg(unix)]
+        Some(("unix", path)) if !path.is_empty() => Ok(Endpoint::Unix(path.into())),
+        _ => Err(format!("expected tcp:<host>:<port> or unix:<path>: {s}")),
+    }
+}
+
+#[derive(ThisError, Debug)]
+pub enum IpcError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("invalid frame: {0}")]
+    Codec(String),
+    #[error("frame of {0} bytes, larger than {MAX_FRAME_SIZE}")]
+    TooLarge(u32),
+}
+
+/// Serializable form of the actor messages: the reply channels are replaced by the IDs
+/// of the reply frames
+#[derive(Debug, Serialize, Deserialize)]
+pub enum WireMessage {
+    InputChange(DeviceID, SlotValue, CorrelationID),
+    Command(CommandEnvelope),
+    Invoke(CommandEnvelope),
+    Evaluate(CommandEnvelope),
+    Report,
+    Actions,
+    Diagram,
+    Snapshot,
+    Restore(serde_json::Value),
+    StagingSource,
+    SensorDiscovered(SensorAnnouncement),
+    RebindSlot(String, DeviceID),
+    ActuationUnavailable(BreakerTrip),
+    Stop,
+}
+
+/// Reply of a remote twin to an Invoke, Evaluate, Report, Actions, Diagram, Snapshot,
+/// Restore, StagingSource or RebindSlot message
+#[derive(Debug, Serialize, Deserialize)]
+pub enum WireReply {
+    Outcome(CommandOutcome),
+    Evaluation(Box<CommandEvaluation>),
+    Report(Box<TwinReport>),
+    Actions(AvailableActions),
+    Diagram(Box<StateDiagram>),
+    Snapshot(serde_json::Value),
+    Restored(Result<String, String>),
+    StagingSource(Box<StagingSource>),
+    Rebound(Result<DeviceID, String>),
+}
+
+/// Frames exchanged by the hub (the runtime with the receiver and the manager) and the
+/// remote twin runners
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Frame {
+    /// Hub to runner: random bytes to sign with the shared key, before any other frame
+    Challenge(Vec<u8>),
+    /// Runner to hub: the HMAC-SHA256 of the challenge
+    Authenticate(Vec<u8>),
+    /// Runner to hub: a twin registers to receive messages
+    Register(AssetID),
+    /// Hub to runner: a twin was not registered, its ID being taken
+    Refused(AssetID),
+    /// Runner to hub: a twin subscribes to sensor/actuator IDs
+    Subscribe(AssetID, Vec<DeviceID>),
+    /// Runner to hub: a twin unsubscribes from sensor/actuator IDs
+    Unsubscribe(AssetID, Vec<DeviceID>),
+    /// Runner to hub: a twin is gone
+    Unregister(AssetID),
+    /// Hub to runner: a message for a twin, with the ID of the expected reply, if any
+    Deliver(AssetID, Option<u64>, WireMessage),
+    /// Runner to hub: the reply to a delivered message
+    Reply(u64, WireReply),
+}
+
+/// Encoding of the frames
+pub trait Codec: Send + Sync {
+    fn encode(&self, frame: &Frame) -> Result<Vec<u8>, IpcError>;
+    fn decode(&self, bytes: &[u8]) -> Result<Frame, IpcError>;
+}
+
+/// Frames encoded as JSON
+pub struct JsonCodec;
+
+impl Codec for JsonCodec {
+    fn encode(&self, frame: &Frame) -> Result<Vec<u8>, IpcError> {
+        serde_json::to_vec(frame).map_err(|e| IpcError::Codec(e.to_string()))
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<Frame, IpcError> {
+        serde_json::from_slice(bytes).map_err(|e| IpcError::Codec(e.to_string()))
+    }
+}
+
+/// Reply channel of an actor message sent to a remote twin
+enum ReplyTo {
+    Outcome(oneshot::Sender<CommandOutcome>),
+    Evaluation(oneshot::Sender<CommandEvaluation>),
+    Report(oneshot::Sender<TwinReport>),
+    Actions(oneshot::Sender<AvailableActions>),
+    Diagram(oneshot::Sender<StateDiagram>),
+    Snapshot(oneshot::Sender<serde_json::Value>),
+    Restored(oneshot::Sender<Result<String, String>>),
+    StagingSource(oneshot::Sender<StagingSource>),
+    Rebound(oneshot::Sender<Result<DeviceID, String>>),
+}
+
+impl ReplyTo {
+    /// Whether the requester stopped waiting for the reply (e.g., after a timeout)
+    fn is_closed(&self) -> bool {
+        match self {
+            ReplyTo::Outcome(ch) => ch.is_closed(),
+            ReplyTo::Evaluation(ch) => ch.is_closed(),
+            ReplyTo::Report(ch) => ch.is_closed(),
+            ReplyTo::Actions(ch) => ch.is_closed(),
+            ReplyTo::Diagram(ch) => ch.is_closed(),
+            ReplyTo::Snapshot(ch) => ch.is_closed(),
+            ReplyTo::Restored(ch) => ch.is_closed(),
+            ReplyTo::StagingSource(ch) => ch.is_closed(),
+            ReplyTo::Rebound(ch) => ch.is_closed(),
+        }
+    }
+
+    fn complete(self, reply: WireReply) {
+        match (self, reply) {
+            (ReplyTo::Outcome(ch), WireReply::Outcome(outcome)) => {
+                let _ = ch.send(outcome);
+            }
+            (ReplyTo::Evaluation(ch), WireReply::Evaluation(evaluation)) => {
+                let _ = ch.send(*evaluation);
+            }
+            (ReplyTo::Report(ch), WireReply::Report(report)) => {
+                let _ = ch.send(*report);
+            }
+            (ReplyTo::Actions(ch), WireReply::Actions(actions)) => {
+                let _ = ch.send(actions);
+            }
+            (ReplyTo::Diagram(ch), WireReply::Diagram(diagram)) => {
+                let _ = ch.send(*diagram);
+            }
+            (ReplyTo::Snapshot(ch), WireReply::Snapshot(snapshot)) => {
+                let _ = ch.send(snapshot);
+            }
+            (ReplyTo::Restored(ch), WireReply::Restored(result)) => {
+                let _ = ch.send(result);
+            }
+            (ReplyTo::StagingSource(ch), WireReply::StagingSource(source)) => {
+                let _ = ch.send(*source);
+            }
+            (ReplyTo::Rebound(ch), WireReply::Rebound(result)) => {
+                let _ = ch.send(result);
+            }
+            (_, reply) => warn!("Unexpected reply from a remote twin: {reply:?}"),
+        }
+    }
+}
+
+/// Reply awaited from a local twin, for a message delivered by the hub
+enum PendingReply {
+    Outcome(oneshot::Receiver<CommandOutcome>),
+    Evaluation(oneshot::Receiver<CommandEvaluation>),
+    Report(oneshot::Receiver<TwinReport>),
+    Actions(oneshot::Receiver<AvailableActions>),
+    Diagram(oneshot::Receiver<StateDiagram>),
+    Snapshot(oneshot::Receiver<serde_json::Value>),
+    Restored(oneshot::Receiver<Result<String, String>>),
+    StagingSource(oneshot::Receiver<StagingSource>),
+    Rebound(oneshot::Receiver<Result<DeviceID, String>>),
+}
+
+impl PendingReply {
+    async fn wait(self) -> Option<WireReply> {
+        match self {
+            PendingReply::Outcome(ch) => ch.await.ok().map(WireReply::Outcome),
+            PendingReply::Evaluation(ch) => ch
+                .await
+                .ok()
+                .map(|evaluation| WireReply::Evaluation(Box::new(evaluation))),
+            PendingReply::Report(ch) => ch.await.ok().map(|report| WireReply::Report(Box::new(report))),
+            PendingReply::Actions(ch) => ch.await.ok().map(WireReply::Actions),
+            PendingReply::Diagram(ch) => ch.await.ok().map(|diagram| WireReply::Diagram(Box::new(diagram))),
+            PendingReply::Snapshot(ch) => ch.await.ok().map(WireReply::Snapshot),
+            PendingReply::Restored(ch) => ch.await.ok().map(WireReply::Restored),
+            PendingReply::StagingSource(ch) => ch
+                .await
+                .ok()
+                .map(|source| WireReply::StagingSource(Box::new(source))),
+            PendingReply::Rebound(ch) => ch.await.ok().map(WireReply::Rebound),
+        }
+    }
+}
+
+impl WireMessage {
+    fn from_actor(message: ActorMessage) -> (Self, Option<ReplyTo>) {
+        match message {
+            ActorMessage::InputChange(device, value, correlation_id) => {
+                (WireMessage::InputChange(device, value, correlation_id), None)
+            }
+            ActorMessage::Command(envelope) => (WireMessage::Command(envelope), None),
+            ActorMessage::Invoke(envelope, ch) => (WireMessage::Invoke(envelope), Some(ReplyTo::Outcome(ch))),
+            ActorMessage::Evaluate(envelope, ch) => {
+                (WireMessage::Evaluate(envelope), Some(ReplyTo::Evaluation(ch)))
+            }
+            ActorMessage::Report(ch) => (WireMessage::Report, Some(ReplyTo::Report(ch))),
+            ActorMessage::Actions(ch) => (WireMessage::Actions, Some(ReplyTo::Actions(ch))),
+            ActorMessage::Diagram(ch) => (WireMessage::Diagram, Some(ReplyTo::Diagram(ch))),
+            ActorMessage::Snapshot(ch) => (WireMessage::Snapshot, Some(ReplyTo::Snapshot(ch))),
+            ActorMessage::Restore(snapshot, ch) => {
+                (WireMessage::Restore(snapshot), Some(ReplyTo::Restored(ch)))
+            }
+            ActorMessage::StagingSource(ch) => (WireMessage::StagingSource, Some(ReplyTo::StagingSource(ch))),
+            ActorMessage::SensorDiscovered(announcement) => {
+                (WireMessage::SensorDiscovered(announcement), None)
+            }
+            ActorMessage::RebindSlot(slot, device, ch) => {
+                (WireMessage::RebindSlot(slot, device), Some(ReplyTo::Rebound(ch)))
+            }
+            ActorMessage::ActuationUnavailable(trip) => (WireMessage::ActuationUnavailable(trip), None),
+            ActorMessage::Stop => (WireMessage::Stop, None),
+            ActorMessage::Freeze(..) => unreachable!("frozen by the forwarder"),
+        }
+    }
+
+    fn into_actor(self) -> (ActorMessage, Option<PendingReply>) {
+        match self {
+            WireMessage::InputChange(device, value, correlation_id) => {
+                (ActorMessage::InputChange(device, value, correlation_id), None)
+            }
+            WireMessage::Command(envelope) => (ActorMessage::Command(envelope), None),
+            WireMessage::Invoke(envelope) => {
+                let (reply, response) = oneshot::channel();
+                (
+                    ActorMessage::Invoke(envelope, reply),
+                    Some(PendingReply::Outcome(response)),
+                )
+            }
+            WireMessage::Evaluate(envelope) => {
+                let (reply, response) = oneshot::channel();
+                (
+                    ActorMessage::Evaluate(envelope, reply),
+                    Some(PendingReply::Evaluation(response)),
+                )
+            }
+            WireMessage::Report => {
+                let (reply, response) = oneshot::channel();
+                (ActorMessage::Report(reply), Some(PendingReply::Report(response)))
+            }
+            WireMessage::Actions => {
+                let (reply, response) = oneshot::channel();
+                (
+                    ActorMessage::Actions(reply),
+                    Some(PendingReply::Actions(response)),
+                )
+            }
+            WireMessage::Diagram => {
+                let (reply, response) = oneshot::channel();
+                (
+                    ActorMessage::Diagram(reply),
+                    Some(PendingReply::Diagram(response)),
+                )
+            }
+            WireMessage::Snapshot => {
+                let (reply, response) = oneshot::channel();
+                (
+                    ActorMessage::Snapshot(reply),
+                    Some(PendingReply::Snapshot(response)),
+                )
+            }
+            WireMessage::Restore(snapshot) => {
+                let (reply, response) = oneshot::channel();
+                (
+                    ActorMessage::Restore(snapshot, reply),
+                    Some(PendingReply::Restored(response)),
+                )
+            }
+            WireMessage::StagingSource => {
+                let (reply, response) = oneshot::channel();
+                (
+                    ActorMessage::StagingSource(reply),
+                    Some(PendingReply::StagingSource(response)),
+                )
+            }
+            WireMessage::SensorDiscovered(announcement) => {
+                (ActorMessage::SensorDiscovered(announcement), None)
+            }
+            WireMessage::RebindSlot(slot, device) => {
+                let (reply, response) = oneshot::channel();
+                (
+                    ActorMessage::RebindSlot(slot, device, reply),
+                    Some(PendingReply::Rebound(response)),
+                )
+            }
+            WireMessage::ActuationUnavailable(trip) => (ActorMessage::ActuationUnavailable(trip), None),
+            WireMessage::Stop => (ActorMessage::Stop, None),
+        }
+    }
+}
+
+type Reader = Box<dyn AsyncRead + Unpin + Send>;
+type Writer = Box<dyn AsyncWrite + Unpin + Send>;
+
+async fn connect(endpoint: &Endpoint) -> std::io::Result<(Reader, Writer)> {
+    match endpoint {
+        Endpoint::Tcp(address) => {
+            let (reader, writer) = tokio::net::TcpStream::connect(address).await?.into_split();
+            Ok((Box::new(reader), Box::new(writer)))
+        }
+        #[cfg(unix)]
+        Endpoint::Unix(path) => {
+            let (reader, writer) = tokio::net::UnixStream::connect(path).await?.into_split();
+            Ok((Box::new(reader), Box::new(writer)))
+        }
+    }
+}
+
+enum Listener {
+    Tcp(tokio::net::TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener),
+}
+
+impl Listener {
+    async fn bind(endpoint: &Endpoint) -> std::io::Result<Self> {
+        match endpoint {
+            Endpoint::Tcp(address) => Ok(Listener::Tcp(tokio::net::TcpListener::bind(address).await?)),
+            #[cfg(unix)]
+            Endpoint::Unix(path) => {
+                // A socket left by a previous run
+                let _ = std::fs::remove_file(path);
+                Ok(Listener::Unix(tokio::net::UnixListener::bind(path)?))
+            }
+        }
+    }
+
+    async fn accept(&self) -> std::io::Result<(Reader, Writer)> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (reader, writer) = listener.accept().await?.0.into_split();
+                Ok((Box::new(reader), Box::new(writer)))
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener) => {
+                let (reader, writer) = listener.accept().await?.0.into_split();
+                Ok((Box::new(reader), Box::new(writer)))
+            }
+        }
+    }
+}
+
+/// Write a frame, prefixed by its length (32 bits, big endian)
+async fn write_frame(writer: &mut Writer, codec: &dyn Codec, frame: &Frame) -> Result<(), IpcError> {
+    let bytes = codec.encode(frame)?;
+    let len = u32::try_from(bytes.len())
+        .ok()
+        .filter(|&len| len <= MAX_FRAME_SIZE)
+        .ok_or(IpcError::TooLarge(u32::MAX))?;
+    writer.write_u32(len).await?;
+    writer.write_all(&bytes).await?;
+    Ok(writer.flush().await?)
+}
+
+/// Read a frame; None when the peer closed the connection
+async fn read_frame(reader: &mut Reader, codec: &dyn Codec) -> Result<Option<Frame>, IpcError> {
+    let len = match reader.read_u32().await {
+        Ok(len) => len,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    if len > MAX_FRAME_SIZE {
+        return Err(IpcError::TooLarge(len));
+    }
+    let mut bytes = vec![0; len as usize];
+    reader.read_exact(&mut bytes).await?;
+    codec.decode(&bytes).map(Some)
+}
+
+/// The frames to send on a connection, and the frames received
+type Connection = (mpsc::Sender<Frame>, mpsc::Receiver<Frame>);
+
+/// Split a connection in two tasks, exchanging the frames over channels: reading a
+/// frame is not cancellation-safe, and a slow twin must not block the connection
+fn spawn_connection(mut reader: Reader, mut writer: Writer, codec: Arc<dyn Codec>) -> Connection {
+    let (outgoing, mut outgoing_rx) = mpsc::channel::<Frame>(64);
+    let (incoming_tx, incoming) = mpsc::channel(64);
+    let write_codec = codec.clone();
+    tokio::spawn(async move {
+        while let Some(frame) = outgoing_rx.recv().await {
+            if let Err(e) = write_frame(&mut writer, write_codec.as_ref(), &frame).await {
+                warn!("IPC connection lost: {e}");
+                return;
+            }
+        }
+    });
+    tokio::spawn(async move {
+        loop {
+            match read_frame(&mut reader, codec.as_ref()).await {
+                Ok(Some(frame)) => {
+                    if incoming_tx.send(frame).await.is_err() {
+                        return;
+                    }
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    warn!("IPC connection lost: {e}");
+                    return;
+                }
+            }
+        }
+    });
+    (outgoing, incoming)
+}
+
+/// Hub side: accepts the remote twin runners and registers their twins with the network
+/// receiver, through channels forwarding the actor messages over the connection
+pub struct IpcHub {
+    endpoint: Option<Endpoint>,
+    key: Option<hmac::Key>,
+    network_ch: mpsc::Sender<NetworkMessage>,
+    codec: Arc<dyn Codec>,
+}
+
+impl IpcHub {
+    pub fn new(
+        options: &IpcOptions,
+        secrets: &SecretsProvider,
+        network_ch: mpsc::Sender<NetworkMessage>,
+    ) -> Self {
+        IpcHub {
+            endpoint: options.ipc_listen.clone(),
+            key: ipc_key(secrets),
+            network_ch,
+            codec: Arc::new(JsonCodec),
+        }
+    }
+
+    pub async fn body(&self) {
+        let Some(endpoint) = &self.endpoint else {
+            return;
+        };
+        if matches!(endpoint, Endpoint::Tcp(_)) && self.key.is_none() {
+            error!(
+                "Not listening for remote twin runners on {endpoint}: the {} secret is required over TCP",
+                secrets::IPC_KEY
+            );
+            return;
+        }
+        let listener = match Listener::bind(endpoint).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!("Cannot listen for remote twin runners on {endpoint}: {e}");
+                return;
+            }
+        };
+        info!("Listening for remote twin runners on {endpoint}");
+        loop {
+            match listener.accept().await {
+                Ok((reader, writer)) => {
+                    debug!("Remote twin runner connected");
+                    let (outgoing, mut incoming) = spawn_connection(reader, writer, self.codec.clone());
+                    let key = self.key.clone();
+                    let network_ch = self.network_ch.clone();
+                    tokio::spawn(async move {
+                        if let Some(key) = key {
+                            if let Err(e) = challenge_runner(&key, &outgoing, &mut incoming).await {
+                                warn!("Remote twin runner refused: {e}");
+                                return;
+                            }
+                        }
+                        serve_runner(outgoing, incoming, network_ch).await;
+                    });
+                }
+                Err(e) => warn!("Failed to accept a remote twin runner: {e}"),
+            }
+        }
+    }
+}
+
+/// The key shared by the hub and the runners, if configured
+fn ipc_key(secrets: &SecretsProvider) -> Option<hmac::Key> {
+    secrets
+        .get(secrets::IPC_KEY)
+        .map(|key| hmac::Key::new(hmac::HMAC_SHA256, key.as_bytes()))
+}
+
+/// Check that a runner holds the shared key, by having it sign random bytes
+async fn challenge_runner(
+    key: &hmac::Key,
+    outgoing: &mpsc::Sender<Frame>,
+    incoming: &mut mpsc::Receiver<Frame>,
+) -> Result<(), String> {
+    let mut challenge = [0; 32];
+    SystemRandom::new()
+        .fill(&mut challenge)
+        .map_err(|_| "cannot generate a challenge".to_string())?;
+    outgoing
+        .send(Frame::Challenge(challenge.to_vec()))
+        .await
+        .map_err(|_| "connection closed".to_string())?;
+    match tokio::time::timeout(HANDSHAKE_TIMEOUT, incoming.recv()).await {
+        Ok(Some(Frame::Authenticate(tag))) => {
+            hmac::verify(key, &challenge, &tag).map_err(|_| "wrong key".to_string())
+        }
+        Ok(Some(_)) => Err("not authenticated".to_string()),
+        Ok(None) => Err("connection closed".to_string()),
+        Err(_) => Err("no answer to the challenge".to_string()),
+    }
+}
+
+/// Answer the challenge of the hub with the shared key
+async fn answer_challenge(
+    key: &hmac::Key,
+    outgoing: &mpsc::Sender<Frame>,
+    incoming: &mut mpsc::Receiver<Frame>,
+) -> Result<(), String> {
+    match tokio::time::timeout(HANDSHAKE_TIMEOUT, incoming.recv()).await {
+        Ok(Some(Frame::Challenge(challenge))) => {
+            let tag = hmac::sign(key, &challenge);
+            outgoing
+                .send(Frame::Authenticate(tag.as_ref().to_vec()))
+                .await
+                .map_err(|_| "connection closed".to_string())
+        }
+        Ok(Some(frame)) => Err(format!("unexpected frame {frame:?}")),
+        Ok(None) => Err("connection closed".to_string()),
+        Err(_) => Err("no challenge from the hub".to_string()),
+    }
+}
+
+/// Replies awaited from the twins of a runner, by frame ID
+type PendingReplies = Arc<Mutex<HashMap<u64, ReplyTo>>>;
+
+/// Handle the frames of a remote twin runner, until it disconnects. The runner only
+/// registers the IDs that are free, and only manages the twins it registered.
+async fn serve_runner(
+    outgoing: mpsc::Sender<Frame>,
+    mut incoming: mpsc::Receiver<Frame>,
+    network_ch: mpsc::Sender<NetworkMessage>,
+) {
+    let pending: PendingReplies = Arc::default();
+    let next_id = Arc::new(AtomicU64::new(0));
+    let mut assets: HashMap<AssetID, mpsc::Sender<ActorMessage>> = HashMap::new();
+    while let Some(frame) = incoming.recv().await {
+        let message = match frame {
+            Frame::Register(asset_id) => {
+                // Registered again when restarted by the runner
+                if let Some(proxy) = assets.get(&asset_id) {
+                    NetworkMessage::Register(asset_id, proxy.clone())
+                } else {
+                    let (proxy, messages) = mpsc::channel(5);
+                    tokio::spawn(forward_to_runner(
+                        asset_id.clone(),
+                        messages,
+                        outgoing.clone(),
+                        pending.clone(),
+                        next_id.clone(),
+                    ));
+                    let (reply, registered) = oneshot::channel();
+                    let register = NetworkMessage::RegisterRemote(asset_id.clone(), proxy.clone(), reply);
+                    if network_ch.send(register).await.is_err() {
+                        return;
+                    }
+                    if registered.await.unwrap_or(false) {
+                        assets.insert(asset_id, proxy);
+                    } else if outgoing.send(Frame::Refused(asset_id)).await.is_err() {
+                        return;
+                    }
+                    continue;
+                }
+            }
+            Frame::Subscribe(asset_id, devices) if assets.contains_key(&asset_id) => {
+                NetworkMessage::Subscribe(asset_id, devices)
+            }
+            Frame::Unsubscribe(asset_id, devices) if assets.contains_key(&asset_id) => {
+                NetworkMessage::Unsubscribe(asset_id, devices)
+            }
+            Frame::Unregister(asset_id) => match assets.remove(&asset_id) {
+                Some(proxy) => NetworkMessage::UnregisterRemote(asset_id, proxy),
+                None => {
+                    warn!("Remote twin runner unregistering {asset_id}, not registered by it, ignored");
+                    continue;
+                }
+            },
+            Frame::Subscribe(asset_id, _) | Frame::Unsubscribe(asset_id, _) => {
+                warn!("Remote twin runner subscribing {asset_id}, not registered by it, ignored");
+                continue;
+            }
+            Frame::Reply(id, reply) => {
+                let reply_to = pending.lock().unwrap_or_else(|e| e.into_inner()).remove(&id);
+                match reply_to {
+                    Some(reply_to) => reply_to.complete(reply),
+                    None => debug!("Reply {id} from a remote twin runner matches no waiting message"),
+                }
+                continue;
+            }
+            frame => {
+                warn!("Unexpected frame from a remote twin runner, ignored: {frame:?}");
+                continue;
+            }
+        };
+        if network_ch.send(message).await.is_err() {
+            return;
+        }
+    }
+    debug!(
+        "Remote twin runner disconnected, unregistering {} twins",
+        assets.len()
+    );
+    for (asset_id, proxy) in assets {
+        let _ = network_ch
+            .send(NetworkMessage::UnregisterRemote(asset_id, proxy))
+            .await;
+    }
+}
+
+/// Keep the reply channel of a message delivered to a remote twin under a new frame ID,
+/// dropping the ones nobody waits for anymore, as the runner may never reply
+fn await_reply(pending: &PendingReplies, next_id: &AtomicU64, reply_to: ReplyTo) -> u64 {
+    let id = next_id.fetch_add(1, Ordering::Relaxed);
+    let mut pending = pending.lock().unwrap_or_else(|e| e.into_inner());
+    pending.retain(|_, reply_to| !reply_to.is_closed());
+    pending.insert(id, reply_to);
+    id
+}
+
+/// Forward the actor messages for a remote twin to its runner
+async fn forward_to_runner(
+    asset_id: AssetID,
+    mut messages: mpsc::Receiver<ActorMessage>,
+    outgoing: mpsc::Sender<Frame>,
+    pending: PendingReplies,
+    next_id: Arc<AtomicU64>,
+) {
+    let deliver = |message: ActorMessage| {
+        let (message, reply_to) = WireMessage::from_actor(message);
+        let id = reply_to.map(|reply_to| await_reply(&pending, &next_id, reply_to));
+        outgoing.send(Frame::Deliver(asset_id.clone(), id, message))
+    };
+    while let Some(message) = messages.recv().await {
+        let ActorMessage::Freeze(reply, release) = message else {
+            if deliver(message).await.is_err() {
+                return;
+            }
+            continue;
+        };
+        // The remote twin is frozen by forwarding nothing more until released
+        let (report, report_response) = oneshot::channel();
+        let (snapshot, snapshot_response) = oneshot::channel();
+        if deliver(ActorMessage::Report(report)).await.is_err()
+            || deliver(ActorMessage::Snapshot(snapshot)).await.is_err()
+        {
+            return;
+        }
+        let responses = async { (report_response.await, snapshot_response.await) };
+        if let Ok((Ok(report), Ok(snapshot))) = tokio::time::timeout(FREEZE_LIMIT, responses).await {
+            if reply.send(FrozenTwin { report, snapshot }).is_ok() {
+                let _ = tokio::time::timeout(FREEZE_LIMIT, release).await;
+            }
+        }
+    }
+}
+
+enum ClientCommand {
+    Register(AssetID, mpsc::Sender<ActorMessage>),
+    Subscribe(AssetID, Vec<DeviceID>),
+    Unsubscribe(AssetID, Vec<DeviceID>),
+    Unregister(AssetID),
+}
+
+/// Runner side: registers the local twins with the hub and delivers them its messages
+pub struct IpcClient {
+    commands: mpsc::Sender<ClientCommand>,
+}
+
+impl IpcClient {
+    /// Start the connection to the hub configured in the options, if any
+    pub fn from_options(options: &IpcOptions, secrets: &SecretsProvider) -> Option<Self> {
+        let endpoint = options.ipc_connect.clone()?;
+        let (commands, commands_rx) = mpsc::channel(16);
+        tokio::spawn(run_client(
+            endpoint,
+            ipc_key(secrets),
+            commands_rx,
+            Arc::new(JsonCodec),
+        ));
+        Some(IpcClient { commands })
+    }
+
+    pub async fn register(&self, asset_id: AssetID, ch: mpsc::Sender<ActorMessage>) {
+        let _ = self.commands.send(ClientCommand::Register(asset_id, ch)).await;
+    }
+
+    pub async fn subscribe(&self, asset_id: AssetID, devices: Vec<DeviceID>) {
+        let _ = self
+            .commands
+            .send(ClientCommand::Subscribe(asset_id, devices))
+            .await;
+    }
+
+    pub async fn unsubscribe(&self, asset_id: AssetID, devices: Vec<DeviceID>) {
+        let _ = self
+            .commands
+            .send(ClientCommand::Unsubscribe(asset_id, devices))
+            .await;
+    }
+
+    pub async fn unregister(&self, asset_id: AssetID) {
+        let _ = self.commands.send(ClientCommand::Unregister(asset_id)).await;
+    }
+}
+
+/// A twin registered with the hub
+struct RemoteRoute {
+    channel: mpsc::Sender<ActorMessage>,
+    devices: Vec<DeviceID>,
+}
+
+/// Connect to the hub, answering its challenge with the shared key, if configured
+async fn connect_to_hub(
+    endpoint: &Endpoint,
+    key: Option<&hmac::Key>,
+    codec: Arc<dyn Codec>,
+) -> Result<Connection, String> {
+    let (reader, writer) = connect(endpoint).await.map_err(|e| e.to_string())?;
+    let (outgoing, mut incoming) = spawn_connection(reader, writer, codec);
+    if let Some(key) = key {
+        answer_challenge(key, &outgoing, &mut incoming)
+            .await
+            .map_err(|e| format!("not authenticated: {e}"))?;
+    }
+    Ok((outgoing, incoming))
+}
+
+/// Keep the connection to the hub, registering the local twins again after a reconnection
+async fn run_client(
+    endpoint: Endpoint,
+    key: Option<hmac::Key>,
+    mut commands: mpsc::Receiver<ClientCommand>,
+    codec: Arc<dyn Codec>,
+) {
+    let mut routes: HashMap<AssetID, RemoteRoute> = HashMap::new();
+    loop {
+        let (outgoing, mut incoming) = match connect_to_hub(&endpoint, key.as_ref(), codec.clone()).await {
+            Ok(connection) => {
+                info!("Connected to the hub {endpoint}");
+                connection
+            }
+            Err(e) => {
+                warn!("Cannot reach the hub {endpoint}: {e}");
+                if !wait_to_reconnect(&mut commands, &mut routes).await {
+                    return;
+                }
+                continue;
+            }
+        };
+        for (asset_id, route) in &routes {
+            let _ = outgoing.send(Frame::Register(asset_id.clone())).await;
+            let _ = outgoing
+                .send(Frame::Subscribe(asset_id.clone(), route.devices.clone()))
+                .await;
+        }
+        // Twins refused by the hub, e.g. still registered by the connection just lost
+        let mut refused: HashSet<AssetID> = HashSet::new();
+        let mut retry = tokio::time::interval(RECONNECT_DELAY);
+        loop {
+            tokio::select! {
+                _ = retry.tick(), if !refused.is_empty() => {
+                    for asset_id in refused.drain() {
+                        if let Some(route) = routes.get(&asset_id) {
+                            let _ = outgoing.send(Frame::Register(asset_id.clone())).await;
+                            let _ = outgoing.send(Frame::Subscribe(asset_id, route.devices.clone())).await;
+                        }
+                    }
+                }
+                command = commands.recv() => {
+                    let Some(command) = command else {
+                        return;
+                    };
+                    let frame = match &command {
+                        ClientCommand::Register(asset_id, _) => Frame::Register(asset_id.clone()),
+                        ClientCommand::Subscribe(asset_id, devices) => {
+                            Frame::Subscribe(asset_id.clone(), devices.clone())
+                        }
+                        ClientCommand::Unsubscribe(asset_id, devices) => {
+                            Frame::Unsubscribe(asset_id.clone(), devices.clone())
+                        }
+                        ClientCommand::Unregister(asset_id) => Frame::Unregister(asset_id.clone()),
+                    };
+                    update_routes(&mut routes, command);
+                    let _ = outgoing.send(frame).await;
+                }
+                frame = incoming.recv() => {
+                    match frame {
+                        Some(Frame::Deliver(asset_id, id, message)) => {
+                            deliver(&routes, asset_id, id, message, &outgoing).await;
+                        }
+                        Some(Frame::Refused(asset_id)) => {
+                            warn!("Twin {asset_id} refused by the hub, its ID being taken, registering it again later");
+                            refused.insert(asset_id);
+                        }
+                        Some(frame) => warn!("Unexpected frame from the hub, ignored: {frame:?}"),
+                        None => {
+                            warn!("Connection to the hub {endpoint} lost");
+                            // Closed right away by a hub refusing the runner, too
+                            if !wait_to_reconnect(&mut commands, &mut routes).await {
+                                return;
+                            }
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Wait before reconnecting to the hub: the twins keep registering meanwhile. False when
+/// the runner is shutting down.
+async fn wait_to_reconnect(
+    commands: &mut mpsc::Receiver<ClientCommand>,
+    routes: &mut HashMap<AssetID, RemoteRoute>,
+) -> bool {
+    let retry = tokio::time::sleep(RECONNECT_DELAY);
+    tokio::pin!(retry);
+    loop {
+        tokio::select! {
+            _ = &mut retry => return true,
+            command = commands.recv() => match command {
+                Some(command) => update_routes(routes, command),
+                None => return false,
+            },
+        }
+    }
+}
+
+fn update_routes(routes: &mut HashMap<AssetID, RemoteRoute>, command: ClientCommand) {
+    match command {
+        ClientCommand::Register(asset_id, channel) => {
+            routes.insert(
+                asset_id,
+                RemoteRoute {
+                    channel,
+                    devices: Vec::new(),
+                },
+            );
+        }
+        ClientCommand::Subscribe(asset_id, devices) => {
+            if let Some(route) = routes.get_mut(&asset_id) {
+                route.devices.extend(devices);
+            }
+        }
+        ClientCommand::Unsubscribe(asset_id, devices) => {
+            if let Some(route) = routes.get_mut(&asset_id) {
+                route.devices.retain(|device| !devices.contains(device));
+            }
+        }
+        ClientCommand::Unregister(asset_id) => {
+            routes.remove(&asset_id);
+        }
+    }
+}
+
+/// Deliver a message from the hub to a local twin, replying in the background
+async fn deliver(
+    routes: &HashMap<AssetID, RemoteRoute>,
+    asset_id: AssetID,
+    id: Option<u64>,
+    message: WireMessage,
+    outgoing: &mpsc::Sender<Frame>,
+) {
+    let Some(route) = routes.get(&asset_id) else {
+        debug!("Message from the hub for unknown twin {asset_id}, ignored");
+        return;
+    };
+    let (message, pending) = message.into_actor();
+    if route.channel.send(message).await.is_err() {
+        debug!("Twin {asset_id} is gone, message from the hub dropped");
+        return;
+    }
+    if let (Some(id), Some(pending)) = (id, pending) {
+        let outgoing = outgoing.clone();
+        tokio::spawn(async move {
+            if let Some(reply) = pending.wait().await {
+                let _ = outgoing.send(Frame::Reply(id, reply)).await;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::CommandSource;
+
+    #[test]
+    fn test_endpoints() {
+        assert_eq!(
+            parse_endpoint("tcp:10.0.0.5:7400"),
+            Ok(Endpoint::Tcp("10.0.0.5:7400".to_string()))
+        );
+        assert_eq!(
+            parse_endpoint("unix:/run/dt.sock"),
+            Ok(Endpoint::Unix("/run/dt.sock".into()))
+        );
+        assert_eq!(
+            parse_endpoint("unix:/run/dt.sock").unwrap().to_string(),
+            "unix:/run/dt.sock"
+        );
+        assert!(parse_endpoint("tcp:").is_err());
+        assert!(parse_endpoint("10.0.0.5:7400").is_err());
+    }
+
+    #[test]
+    fn test_wire_messages() {
+        let codec = JsonCodec;
+        let envelope = CommandEnvelope::new(CommandSource::Rest, "SetMaxCurrent", serde_json::json!(16.0));
+        let (reply, _response) = oneshot::channel();
+        let (message, reply_to) = WireMessage::from_actor(ActorMessage::Invoke(envelope, reply));
+        assert!(matches!(reply_to, Some(ReplyTo::Outcome(_))));
+
+        let frame = Frame::Deliver("urn:twin:1".into(), Some(7), message);
+        let decoded = codec.decode(&codec.encode(&frame).unwrap()).unwrap();
+        let Frame::Deliver(asset_id, Some(7), message) = decoded else {
+            panic!("unexpected frame {decoded:?}");
+        };
+        assert_eq!(asset_id, "urn:twin:1");
+        let (message, pending) = message.into_actor();
+        let ActorMessage::Invoke(envelope, _) = message else {
+            panic!("unexpected message");
+        };
+        assert_eq!(envelope.command, "SetMaxCurrent");
+        assert_eq!(envelope.source, CommandSource::Rest);
+        assert!(matches!(pending, Some(PendingReply::Outcome(_))));
+
+        let frame = Frame::Reply(
+            7,
+            WireReply::Outcome(CommandOutcome::CoolingDown(Duration::from_secs(3))),
+        );
+        let decoded = codec.decode(&codec.encode(&frame).unwrap()).unwrap();
+        assert!(matches!(
+            decoded,
+            Frame::Reply(7, WireReply::Outcome(CommandOutcome::CoolingDown(d))) if d == Duration::from_secs(3)
+        ));
+        assert!(codec.decode(b"{\"Deliver\": 1}").is_err());
+    }
+
+    /// The two ends of a connection, as spawned by the hub and by a runner
+    fn connection() -> (Connection, Connection) {
+        let (hub, runner) = tokio::io::duplex(4096);
+        let (hub_reader, hub_writer) = tokio::io::split(hub);
+        let (runner_reader, runner_writer) = tokio::io::split(runner);
+        (
+            spawn_connection(Box::new(hub_reader), Box::new(hub_writer), Arc::new(JsonCodec)),
+            spawn_connection(
+                Box::new(runner_reader),
+                Box::new(runner_writer),
+                Arc::new(JsonCodec),
+            ),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_handshake() {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, b"shared");
+        let ((hub_out, mut hub_in), (runner_out, mut runner_in)) = connection();
+        let runner_key = key.clone();
+        let runner =
+            tokio::spawn(async move { answer_challenge(&runner_key, &runner_out, &mut runner_in).await });
+        assert_eq!(challenge_runner(&key, &hub_out, &mut hub_in).await, Ok(()));
+        assert_eq!(runner.await.unwrap(), Ok(()));
+
+        let ((hub_out, mut hub_in), (runner_out, mut runner_in)) = connection();
+        let runner = tokio::spawn(async move {
+            let wrong = hmac::Key::new(hmac::HMAC_SHA256, b"guessed");
+            answer_challenge(&wrong, &runner_out, &mut runner_in).await
+        });
+        assert_eq!(
+            challenge_runner(&key, &hub_out, &mut hub_in).await,
+            Err("wrong key".to_string())
+        );
+        assert_eq!(runner.await.unwrap(), Ok(()));
+
+        // A runner registering right away, without answering
+        let ((hub_out, mut hub_in), (runner_out, _runner_in)) = connection();
+        runner_out
+            .send(Frame::Register("urn:twin:1".into()))
+            .await
+            .unwrap();
+        assert!(challenge_runner(&key, &hub_out, &mut hub_in).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_register_refused() {
+        let (network_ch, mut network_rx) = mpsc::channel(5);
+        let (runner_out, incoming) = mpsc::channel(5);
+        let (outgoing, mut runner_in) = mpsc::channel(5);
+        tokio::spawn(serve_runner(outgoing, incoming, network_ch));
+
+        // A twin of this process is registered as urn:twin:local
+        runner_out
+            .send(Frame::Register("urn:twin:local".into()))
+            .await
+            .unwrap();
+        let Some(NetworkMessage::RegisterRemote(asset_id, _, reply)) = network_rx.recv().await else {
+            panic!("registration expected");
+        };
+        assert_eq!(asset_id, "urn:twin:local");
+        reply.send(false).unwrap();
+        assert!(matches!(runner_in.recv().await, Some(Frame::Refused(id)) if id == "urn:twin:local"));
+
+        runner_out
+            .send(Frame::Register("urn:twin:remote".into()))
+            .await
+            .unwrap();
+        let Some(NetworkMessage::RegisterRemote(_, proxy, reply)) = network_rx.recv().await else {
+            panic!("registration expected");
+        };
+        reply.send(true).unwrap();
+
+        // The refused twin is not managed by the runner
+        let devices = vec![DeviceID::from("urn:sensor:1")];
+        runner_out
+            .send(Frame::Subscribe("urn:twin:local".into(), devices.clone()))
+            .await
+            .unwrap();
+        runner_out
+            .send(Frame::Unregister("urn:twin:local".into()))
+            .await
+            .unwrap();
+        runner_out
+            .send(Frame::Subscribe("urn:twin:remote".into(), devices))
+            .await
+            .unwrap();
+        assert!(
+            matches!(network_rx.recv().await, Some(NetworkMessage::Subscribe(id, _)) if id == "urn:twin:remote")
+        );
+
+        // Only the twin registered by the runner is removed when it disconnects
+        drop(runner_out);
+        let Some(NetworkMessage::UnregisterRemote(asset_id, channel)) = network_rx.recv().await else {
+            panic!("removal expected");
+        };
+        assert_eq!(asset_id, "urn:twin:remote");
+        assert!(channel.same_channel(&proxy));
+        assert!(network_rx.recv().await.is_none());
+    }
+
+    #[test]
+    fn test_expired_replies() {
+        let pending = PendingReplies::default();
+        let next_id = AtomicU64::new(0);
+        let (reply, response) = oneshot::channel::<TwinReport>();
+        await_reply(&pending, &next_id, ReplyTo::Report(reply));
+        let (reply, _response) = oneshot::channel::<TwinReport>();
+        await_reply(&pending, &next_id, ReplyTo::Report(reply));
+        assert_eq!(pending.lock().unwrap().len(), 2);
+
+        // The requester of the first report timed out
+        drop(response);
+        let (reply, _response) = oneshot::channel::<TwinReport>();
+        let id = await_reply(&pending, &next_id, ReplyTo::Report(reply));
+        assert_eq!(id, 2);
+        let mut ids: Vec<_> = pending.lock().unwrap().keys().copied().collect();
+        ids.sort();
+        assert_eq!(ids, [1, 2]);
+    }
+}
@@ -0,0 +1,381 @@
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use log::info;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::manager::{ManagerMessage, Query};
+use crate::network_receiver::NetworkMessage;
+use crate::twin_runner::TwinReport;
+use digitaltwin_core::SlotValue;
+
+#[derive(Parser, Clone)]
+pub struct KpiOptions {
+    /// fleet KPI, as "<name>=<function>(<argument>)[ where <condition>[ and <condition>]]":
+    /// count() counts the twins, sum, avg, min and max(<slot>) aggregate the latest values of a
+    /// slot, ratio(<conditions>) is the fraction of the twins meeting the conditions; a
+    /// condition is "state=<state>", "state!=<state>", "type=<twin type>" or "type!=<twin type>"
+    /// (e.g. "charging_power=sum(CurrentPowerDraw) where state=Charging"); separate KPIs with ';'
+    /// in KPIS
+    #[clap(
+        long = "kpi",
+        value_delimiter = ';',
+        default_value = "total_power=sum(CurrentPowerDraw);fault_rate=ratio(state=Fault)",
+        env = "KPIS"
+    )]
+    kpis: Vec<KpiDefinition>,
+
+    /// seconds between two computations of the fleet KPIs (0 = not computed)
+    #[clap(long, default_value_t = 60, env = "KPI_INTERVAL")]
+    kpi_interval: u64,
+}
+
+/// Condition on the report of a twin
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    /// The twin, or one of its regions, is (or is not) in the state
+    State(String, bool),
+    /// The twin is (or is not) of the type
+    Type(String, bool),
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (field, value, expected) = match s.split_once("!=") {
+            Some((field, value)) => (field, value, false),
+            None => match s.split_once('=') {
+                Some((field, value)) => (field, value, true),
+                None => return Err(format!("expected <field>=<value> or <field>!=<value>: {s}")),
+            },
+        };
+        let value = value.trim().to_string();
+        match field.trim() {
+            _ if value.is_empty() => Err(format!("missing value in {s}")),
+            "state" => Ok(Condition::State(value, expected)),
+            "type" => Ok(Condition::Type(value, expected)),
+            field => Err(format!("unknown field {field}, expected state or type")),
+        }
+    }
+}
+
+impl Condition {
+    fn matches(&self, report: &TwinReport) -> bool {
+        match self {
+            // Twins with regions have a composite state, as "Fault|Online"
+            Condition::State(state, expected) => report.state.split('|').any(|s| s == state) == *expected,
+            Condition::Type(actor_type, expected) => (&report.actor_type == actor_type) == *expected,
+        }
+    }
+}
+
+/// Aggregation function of a KPI
+#[derive(Debug, Clone, PartialEq)]
+pub enum KpiFunction {
+    Count,
+    Sum(String),
+    Avg(String),
+    Min(String),
+    Max(String),
+    Ratio(Vec<Condition>),
+}
+
+/// A fleet KPI: an aggregation over the reports of the twins meeting the filter
+#[derive(Debug, Clone, PartialEq)]
+pub struct KpiDefinition {
+    pub name: String,
+    pub function: KpiFunction,
+    pub filter: Vec<Condition>,
+}
+
+fn parse_conditions(s: &str) -> Result<Vec<Condition>, String> {
+    s.split(" and ").map(str::parse).collect()
+}
+
+impl FromStr for KpiDefinition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("expected <name>=<function>(<argument>)[ where <conditions>]: {s}");
+        let (name, expression) = s.split_once('=').ok_or_else(invalid)?;
+        let (aggregation, filter) = match expression.split_once(" where ") {
+            Some((aggregation, filter)) => (aggregation, parse_conditions(filter)?),
+            None => (expression, Vec::new()),
+        };
+        let (function, argument) = aggregation
+            .trim()
+            .strip_suffix(')')
+            .and_then(|a| a.split_once('('))
+            .ok_or_else(invalid)?;
+        let argument = argument.trim();
+        let slot = || {
+            if argument.is_empty() {
+                Err(format!("missing slot in {s}"))
+            } else {
+                Ok(argument.to_string())
+            }
+        };
+        let function = match function.trim() {
+            "count" if argument.is_empty() || argument == "*" => KpiFunction::Count,
+            "sum" => KpiFunction::Sum(slot()?),
+            "avg" => KpiFunction::Avg(slot()?),
+            "min" => KpiFunction::Min(slot()?),
+            "max" => KpiFunction::Max(slot()?),
+            "ratio" => KpiFunction::Ratio(parse_conditions(argument)?),
+            function => return Err(format!("unknown function {function} in {s}")),
+        };
+        Ok(KpiDefinition {
+            name: name.trim().to_string(),
+            function,
+            filter,
+        })
+    }
+}
+
+impl KpiDefinition {
+    /// The value of the KPI over the reports of the twins (None without values)
+    fn evaluate(&self, reports: &[TwinReport]) -> Option<f64> {
+        let twins: Vec<&TwinReport> = reports
+            .iter()
+            .filter(|report| self.filter.iter().all(|c| c.matches(report)))
+            .collect();
+        let values = |slot: &str| -> Vec<f64> {
+            twins
+                .iter()
+                .filter_map(|report| match report.slot_values.get(slot) {
+                    Some(SlotValue::Number(n)) => Some(*n),
+                    _ => None,
+                })
+                .collect()
+        };
+        match &self.function {
+            KpiFunction::Count => Some(twins.len() as f64),
+            KpiFunction::Sum(slot) => Some(values(slot).iter().fold(0.0, |sum, v| sum + v)),
+            KpiFunction::Avg(slot) => {
+                let values = values(slot);
+                (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64)
+            }
+            KpiFunction::Min(slot) => values(slot).into_iter().reduce(f64::min),
+            KpiFunction::Max(slot) => values(slot).into_iter().reduce(f64::max),
+            KpiFunction::Ratio(conditions) => {
+                let matching = twins
+                    .iter()
+                    .filter(|report| conditions.iter().all(|c| c.matches(report)))
+                    .count();
+                (!twins.is_empty()).then(|| matching as f64 / twins.len() as f64)
+            }
+        }
+    }
+}
+
+/// KPIs of the fleet, computed periodically from the reports of the twins
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FleetKpis {
+    /// Time of the computation, None before the first one
+    pub timestamp: Option<DateTime<Utc>>,
+    /// Number of twins running
+    pub twins: usize,
+    /// Number of twins in each state (a twin with regions counts in the state of each region)
+    pub states: BTreeMap<String, usize>,
+    /// Longest time spent by a twin in each state, in milliseconds
+    pub longest_dwell_ms: BTreeMap<String, u64>,
+    /// Value of each KPI, null without values
+    pub kpis: BTreeMap<String, Option<f64>>,
+}
+
+impl FleetKpis {
+    pub fn compute(definitions: &[KpiDefinition], reports: &[TwinReport], now: DateTime<Utc>) -> Self {
+        let mut states = BTreeMap::new();
+        let mut longest_dwell_ms = BTreeMap::new();
+        for report in reports {
+            for state in report.state.split('|') {
+                *states.entry(state.to_string()).or_insert(0) += 1;
+            }
+            for dwell in &report.state_dwell {
+                let longest = longest_dwell_ms.entry(dwell.state.clone()).or_insert(0);
+                *longest = dwell.dwell_ms.max(*longest);
+            }
+        }
+        FleetKpis {
+            timestamp: Some(now),
+            twins: reports.len(),
+            states,
+            longest_dwell_ms,
+            kpis: definitions
+                .iter()
+                .map(|kpi| (kpi.name.clone(), kpi.evaluate(reports)))
+                .collect(),
+        }
+    }
+}
+
+/// Latest fleet KPIs, shared by the KPI engine and the REST server
+pub type SharedKpis = Arc<Mutex<FleetKpis>>;
+
+/// Periodically computes the fleet KPIs and publishes them
+pub struct KpiEngine {
+    definitions: Vec<KpiDefinition>,
+    interval: Option<Duration>,
+    kpis: SharedKpis,
+    manager_ch: mpsc::Sender<ManagerMessage>,
+    network_ch: mpsc::Sender<NetworkMessage>,
+}
+
+impl KpiEngine {
+    pub fn new(
+        options: KpiOptions,
+        manager_ch: mpsc::Sender<ManagerMessage>,
+        network_ch: mpsc::Sender<NetworkMessage>,
+    ) -> Self {
+        KpiEngine {
+            definitions: options.kpis,
+            interval: (options.kpi_interval > 0).then(|| Duration::from_secs(options.kpi_interval)),
+            kpis: SharedKpis::default(),
+            manager_ch,
+            network_ch,
+        }
+    }
+
+    pub fn kpis(&self) -> SharedKpis {
+        self.kpis.clone()
+    }
+
+    pub async fn body(&mut self) {
+        let Some(interval) = self.interval else {
+            return;
+        };
+        info!(
+            "KPI engine starting with {} KPIs, every {}s",
+            self.definitions.len(),
+            interval.as_secs()
+        );
+        let mut tick = tokio::time::interval(interval);
+        loop {
+            tick.tick().await;
+            let (reply, response) = oneshot::channel();
+            if self
+                .manager_ch
+                .send(ManagerMessage::Query(Query::ListTwins(reply)))
+                .await
+                .is_err()
+            {
+                return;
+            }
+            let Ok(reports) = response.await else {
+                continue;
+            };
+            let kpis = FleetKpis::compute(&self.definitions, &reports, Utc::now());
+            *self.kpis.lock().unwrap_or_else(|e| e.into_inner()) = kpis.clone();
+            let _ = self.network_ch.send(NetworkMessage::Kpis(kpis)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state_clock::StateDwell;
+
+    fn report(id: &str, actor_type: &str, state: &str, power: Option<f64>) -> TwinReport {
+        TwinReport {
+            asset_id: id.into(),
+            actor_type: actor_type.to_string(),
+            state: state.to_string(),
+            slot_values: power
+                .map(|power| ("CurrentPowerDraw".to_string(), SlotValue::Number(power)))
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_kpi() {
+        assert_eq!(
+            "charging_power = sum(CurrentPowerDraw) where state=Charging and type!=Heater"
+                .parse::<KpiDefinition>()
+                .unwrap(),
+            KpiDefinition {
+                name: "charging_power".to_string(),
+                function: KpiFunction::Sum("CurrentPowerDraw".to_string()),
+                filter: vec![
+                    Condition::State("Charging".to_string(), true),
+                    Condition::Type("Heater".to_string(), false)
+                ],
+            }
+        );
+        assert_eq!(
+            "faults=ratio(state=Fault)"
+                .parse::<KpiDefinition>()
+                .unwrap()
+                .function,
+            KpiFunction::Ratio(vec![Condition::State("Fault".to_string(), true)])
+        );
+        assert_eq!(
+            "twins=count()".parse::<KpiDefinition>().unwrap().function,
+            KpiFunction::Count
+        );
+        for invalid in [
+            "power",
+            "power=sum()",
+            "power=median(CurrentPowerDraw)",
+            "power=sum(CurrentPowerDraw",
+            "power=sum(CurrentPowerDraw) where colour=red",
+            "faults=ratio(state)",
+        ] {
+            assert!(invalid.parse::<KpiDefinition>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_fleet_kpis() {
+        let mut reports = [
+            report("a", "ChargingPoint", "Charging|Online", Some(7.0)),
+            report("b", "ChargingPoint", "Charging|Offline", Some(4.0)),
+            report("c", "ChargingPoint", "Fault|Online", Some(0.0)),
+            report("d", "Heater", "Idle", None),
+        ];
+        for (report, dwell_ms) in reports.iter_mut().zip([60_000, 5_000, 1_000, 0]) {
+            report.state_dwell = report
+                .state
+                .split('|')
+                .map(|state| StateDwell {
+                    state: state.to_string(),
+                    entered_at: Utc::now(),
+                    dwell_ms,
+                })
+                .collect();
+        }
+        let definitions: Vec<KpiDefinition> = [
+            "total_power=sum(CurrentPowerDraw)",
+            "fault_rate=ratio(state=Fault) where type=ChargingPoint",
+            "avg_charging=avg(CurrentPowerDraw) where state=Charging",
+            "max_heater=max(CurrentPowerDraw) where type=Heater",
+            "online=count() where state!=Offline",
+        ]
+        .into_iter()
+        .map(|kpi| kpi.parse().unwrap())
+        .collect();
+        let kpis = FleetKpis::compute(&definitions, &reports, Utc::now());
+        assert_eq!(kpis.twins, 4);
+        assert_eq!(kpis.states["Charging"], 2);
+        assert_eq!(kpis.states["Online"], 2);
+        assert_eq!(kpis.states["Idle"], 1);
+        assert_eq!(kpis.longest_dwell_ms["Charging"], 60_000);
+        assert_eq!(kpis.longest_dwell_ms["Fault"], 1_000);
+        assert_eq!(kpis.kpis["total_power"], Some(11.0));
+        assert_eq!(kpis.kpis["fault_rate"], Some(1.0 / 3.0));
+        assert_eq!(kpis.kpis["avg_charging"], Some(5.5));
+        assert_eq!(kpis.kpis["max_heater"], None);
+        assert_eq!(kpis.kpis["online"], Some(3.0));
+
+        let kpis = FleetKpis::compute(&definitions, &[], Utc::now());
+        assert_eq!(kpis.kpis["total_power"], Some(0.0));
+        assert_eq!(kpis.kpis["fault_rate"], None);
+    }
+}
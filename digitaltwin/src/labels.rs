@@ -0,0 +1,126 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Labels of a twin (key to value)
+pub type Labels = BTreeMap<String, String>;
+
+/// Requirement of a selector on the labels of a twin
+#[derive(Debug, Clone, PartialEq)]
+enum Requirement {
+    /// "<key>=<value>"
+    Equals(String, String),
+    /// "<key>!=<value>", also met by the twins without the label
+    NotEquals(String, String),
+    /// "<key>": the twin has the label
+    Exists(String),
+    /// "!<key>": the twin does not have the label
+    Missing(String),
+}
+
+impl Requirement {
+    fn matches(&self, labels: &Labels) -> bool {
+        match self {
+            Requirement::Equals(key, value) => labels.get(key) == Some(value),
+            Requirement::NotEquals(key, value) => labels.get(key) != Some(value),
+            Requirement::Exists(key) => labels.contains_key(key),
+            Requirement::Missing(key) => !labels.contains_key(key),
+        }
+    }
+}
+
+/// Selection of the twins by their labels, as comma-separated requirements all met by the
+/// selected twins, e.g. "site=hq, tier!=test, !retired"
+#[derive(Debug, Clone, PartialEq)]
+pub struct LabelSelector {
+    requirements: Vec<Requirement>,
+}
+
+fn label_key(key: &str, selector: &str) -> Result<String, String> {
+    let key = key.trim();
+    if key.is_empty() || key.contains(|c: char| c.is_whitespace() || "=!,".contains(c)) {
+        return Err(format!("invalid label key {key:?} in {selector}"));
+    }
+    Ok(key.to_string())
+}
+
+impl FromStr for LabelSelector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let requirements = s
+            .split(',')
+            .map(str::trim)
+            .filter(|r| !r.is_empty())
+            .map(|requirement| {
+                if let Some((key, value)) = requirement.split_once("!=") {
+                    Ok(Requirement::NotEquals(
+                        label_key(key, s)?,
+                        value.trim().to_string(),
+                    ))
+                } else if let Some((key, value)) = requirement.split_once('=') {
+                    Ok(Requirement::Equals(label_key(key, s)?, value.trim().to_string()))
+                } else if let Some(key) = requirement.strip_prefix('!') {
+                    Ok(Requirement::Missing(label_key(key, s)?))
+                } else {
+                    Ok(Requirement::Exists(label_key(requirement, s)?))
+                }
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        if requirements.is_empty() {
+            return Err("empty label selector".to_string());
+        }
+        Ok(LabelSelector { requirements })
+    }
+}
+
+impl fmt::Display for LabelSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, requirement) in self.requirements.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            match requirement {
+                Requirement::Equals(key, value) => write!(f, "{key}={value}")?,
+                Requirement::NotEquals(key, value) => write!(f, "{key}!={value}")?,
+                Requirement::Exists(key) => write!(f, "{key}")?,
+                Requirement::Missing(key) => write!(f, "!{key}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl LabelSelector {
+    /// Whether a twin with the labels is selected
+    pub fn matches(&self, labels: &Labels) -> bool {
+        self.requirements.iter().all(|r| r.matches(labels))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_label_selector() {
+        let labels: Labels = [("site", "hq"), ("type", "charging-station"), ("tier", "prod")]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let selects = |selector: &str| selector.parse::<LabelSelector>().unwrap().matches(&labels);
+        assert!(selects("site=hq, type=charging-station"));
+        assert!(!selects("site=hq,type=heater"));
+        assert!(selects("tier!=test"));
+        assert!(selects("region!=eu"));
+        assert!(selects("site, !retired"));
+        assert!(!selects("!site"));
+        assert!(!selects("region"));
+
+        let selector: LabelSelector = " site = hq ,tier!=test,, !retired".parse().unwrap();
+        assert_eq!(selector.to_string(), "site=hq,tier!=test,!retired");
+        for invalid in ["", " , ", "=hq", "site hq=1", "!", "a=b=c,!=x"] {
+            assert!(invalid.parse::<LabelSelector>().is_err(), "{invalid}");
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::ingest_metrics::LatencyHistogram;
+use digitaltwin_core::AssetID;
+
+/// Consecutive overruns after which the inputs of a twin are paused, when isolation is enabled
+const OVERRUNS_BEFORE_ISOLATION: u32 = 3;
+
+#[derive(Parser, Clone)]
+pub struct LatencyBudgetOptions {
+    /// maximum execution time of the input_change and execute handlers of a twin, in
+    /// milliseconds (0 = no budget); the "LatencyBudgetMs" property of the TwinConfiguration
+    /// of a twin overrides it
+    #[clap(long, default_value_t = 0, env = "LATENCY_BUDGET_MS")]
+    latency_budget_ms: u64,
+
+    /// pause the inputs of a twin whose handlers exceed the budget several times in a row,
+    /// dropping its input changes for the isolation period (commands are still executed)
+    #[clap(long, env = "ISOLATE_SLOW_TWINS")]
+    isolate_slow_twins: bool,
+
+    /// seconds the inputs of a slow twin stay paused
+    #[clap(long, default_value_t = 60, env = "ISOLATION_PERIOD")]
+    isolation_period: u64,
+}
+
+/// Handler execution time of a twin, shown by the REST API
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct HandlerMetrics {
+    /// Execution time of the input_change and execute handlers
+    pub handler_time: LatencyHistogram,
+    /// Handler executions exceeding the budget
+    pub overruns: u64,
+    /// Times the inputs of the twin were paused
+    pub isolations: u64,
+    /// Input changes dropped while the twin was isolated
+    pub dropped_inputs: u64,
+    /// End of the latest isolation, if any
+    pub isolated_until: Option<DateTime<Utc>>,
+}
+
+/// Handler metrics of the running twins, shared by the twin runners and the REST server
+pub type SharedHandlerMetrics = Arc<Mutex<BTreeMap<AssetID, HandlerMetrics>>>;
+
+/// Outcome of a handler execution checked against the budget
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BudgetCheck {
+    Within,
+    /// The handler took longer than the budget
+    Overrun(Duration),
+    /// The handler took longer than the budget once too often: the inputs are paused
+    Isolate(Duration),
+}
+
+/// Latency budget of the handlers of a twin
+#[derive(Debug, Clone)]
+pub struct LatencyBudget {
+    budget: Option<Duration>,
+    isolation: Option<Duration>,
+    overruns_in_row: u32,
+    isolated_until: Option<Instant>,
+}
+
+impl LatencyBudget {
+    /// The budget of a twin: its own (in milliseconds, from the AAS) or the default one
+    pub fn new(options: &LatencyBudgetOptions, twin_budget_ms: Option<u64>) -> Self {
+        let budget_ms = twin_budget_ms.unwrap_or(options.latency_budget_ms);
+        LatencyBudget {
+            budget: (budget_ms > 0).then(|| Duration::from_millis(budget_ms)),
+            isolation: options
+                .isolate_slow_twins
+                .then(|| Duration::from_secs(options.isolation_period.max(1))),
+            overruns_in_row: 0,
+            isolated_until: None,
+        }
+    }
+
+    /// Check the execution time of a handler against the budget
+    pub fn check(&mut self, elapsed: Duration, now: Instant) -> BudgetCheck {
+        let Some(budget) = self.budget.filter(|budget| elapsed > *budget) else {
+            self.overruns_in_row = 0;
+            return BudgetCheck::Within;
+        };
+        self.overruns_in_row += 1;
+        match self.isolation {
+            Some(isolation) if self.overruns_in_row >= OVERRUNS_BEFORE_ISOLATION => {
+                self.overruns_in_row = 0;
+                self.isolated_until = Some(now + isolation);
+                BudgetCheck::Isolate(isolation)
+            }
+            _ => BudgetCheck::Overrun(budget),
+        }
+    }
+
+    /// Whether the inputs of the twin are paused
+    pub fn is_isolated(&mut self, now: Instant) -> bool {
+        match self.isolated_until {
+            Some(until) if now < until => true,
+            Some(_) => {
+                self.isolated_until = None;
+                false
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency_budget() {
+        let options = LatencyBudgetOptions {
+            latency_budget_ms: 0,
+            isolate_slow_twins: true,
+            isolation_period: 60,
+        };
+        let mut unbounded = LatencyBudget::new(&options, None);
+        assert_eq!(
+            unbounded.check(Duration::from_secs(10), Instant::now()),
+            BudgetCheck::Within
+        );
+
+        let mut budget = LatencyBudget::new(&options, Some(10));
+        let now = Instant::now();
+        let slow = Duration::from_millis(20);
+        assert_eq!(
+            budget.check(slow, now),
+            BudgetCheck::Overrun(Duration::from_millis(10))
+        );
+        // A fast execution resets the count of the overruns in a row
+        assert_eq!(budget.check(Duration::from_millis(5), now), BudgetCheck::Within);
+        assert!(matches!(budget.check(slow, now), BudgetCheck::Overrun(_)));
+        assert!(matches!(budget.check(slow, now), BudgetCheck::Overrun(_)));
+        assert!(!budget.is_isolated(now));
+        assert_eq!(
+            budget.check(slow, now),
+            BudgetCheck::Isolate(Duration::from_secs(60))
+        );
+        assert!(budget.is_isolated(now + Duration::from_secs(59)));
+        assert!(!budget.is_isolated(now + Duration::from_secs(60)));
+
+        let options = LatencyBudgetOptions {
+            isolate_slow_twins: false,
+            ..options
+        };
+        let mut budget = LatencyBudget::new(&options, Some(10));
+        for _ in 0..5 {
+            assert!(matches!(budget.check(slow, now), BudgetCheck::Overrun(_)));
+        }
+        assert!(!budget.is_isolated(now));
+    }
+}
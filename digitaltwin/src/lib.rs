@@ -0,0 +1,65 @@
+//! Digital twin runtime. Embedding applications build a runtime from its configuration,
+//! add their own event sinks and run it; the digitaltwin binary does the same from the
+//! command line.
+
+pub mod actuation;
+pub mod alerting;
+pub mod audit;
+pub mod backup;
+pub mod command;
+pub mod command_auth;
+pub mod command_guard;
+pub mod commissioning;
+pub mod conditions;
+pub mod config;
+pub mod device_trie;
+pub mod diagram;
+pub mod event_bus;
+pub mod failover;
+pub mod federation;
+pub mod flapping;
+pub mod fleet_snapshot;
+pub mod historian;
+pub mod history;
+pub mod http_client;
+pub mod importer;
+pub mod ingest_metrics;
+pub mod input_mapping;
+pub mod ipc;
+pub mod kpi;
+pub mod labels;
+pub mod latency_budget;
+pub mod manager;
+pub mod models;
+pub mod mqtt_link;
+pub mod network_receiver;
+pub mod outbox;
+pub mod predictor;
+pub mod rate_limit;
+pub mod replay_guard;
+pub mod resources;
+pub mod rest_server;
+pub mod runtime;
+pub mod scheduler;
+pub mod secrets;
+pub mod sessions;
+pub mod sinks;
+pub mod smart_charging;
+pub mod sparkplug;
+pub mod staging;
+pub mod startup;
+pub mod state_clock;
+pub mod templates;
+pub mod tls;
+pub mod twin_log;
+pub mod twin_runner;
+pub mod ui_schema;
+pub mod unmapped;
+pub mod virtual_sensors;
+pub mod webhooks;
+
+pub use digitaltwin_core::*;
+pub use digitaltwin_macros::*;
+
+pub use runtime::{Runtime, RuntimeBuilder, RuntimeError};
+pub use sinks::{EventSink, SinkFuture, Sinks};
@@ -1,39 +1,95 @@
-use clap::Parser;
-use log::info;
-use tokio::join;
-
-mod manager;
-mod models;
-mod network_receiver;
-mod twin_runner;
-
-pub use digitaltwin_core::*;
-pub use digitaltwin_macros::*;
-
-#[derive(Parser)]
-struct Cli {
-    #[clap(flatten)]
-    network: network_receiver::NetworkOptions,
-}
+use digitaltwin::{config, importer, secrets, twin_log, RuntimeBuilder};
+use log::{error, info};
 
 #[tokio::main]
 async fn main() {
-    env_logger::init();
+    twin_log::init();
 
-    let cli = Cli::parse();
+    let config = match config::RuntimeConfig::load() {
+        Ok(config) => config,
+        // Usage errors, help and version
+        Err(config::ConfigError::Cli(e)) => e.exit(),
+        Err(e) => {
+            error!("{e}");
+            std::process::exit(1);
+        }
+    };
 
-    info!("Creating components");
-    let mut network_receiver = network_receiver::NetworkReceiver::new(cli.network);
-    let network_channel = network_receiver.get_channel();
-    let mut manager = manager::Manager::new(network_channel);
+    // One-off tasks
+    if let Some(command) = &config.command {
+        match command {
+            config::Command::Import(args) => {
+                let importer = importer::Importer::new(&config.import);
+                match importer::Inventory::from_file(&args.inventory)
+                    .and_then(|rows| importer.import(&args.template, &rows))
+                {
+                    Ok(report) => {
+                        for failure in &report.failed {
+                            error!("Row {}: {}", failure.row, failure.error);
+                        }
+                        if !report.failed.is_empty() {
+                            std::process::exit(1);
+                        }
+                    }
+                    Err(e) => {
+                        error!("Import failed: {e}");
+                        std::process::exit(1);
+                    }
+                }
+            }
+            config::Command::History(args) => match args.run().await {
+                Ok(body) => println!("{body}"),
+                Err(e) => {
+                    error!("History query failed: {e}");
+                    std::process::exit(1);
+                }
+            },
+            config::Command::Backup(args) => match args.run().await {
+                Ok(twins) => info!("Backup of {twins} twins saved"),
+                Err(e) => {
+                    error!("Backup failed: {e}");
+                    std::process::exit(1);
+                }
+            },
+            config::Command::Restore(args) => match args.run().await {
+                Ok(body) => println!("{body}"),
+                Err(e) => {
+                    error!("Restore failed: {e}");
+                    std::process::exit(1);
+                }
+            },
+            config::Command::Twins(args) => match args.run().await {
+                Ok(body) => println!("{body}"),
+                Err(e) => {
+                    error!("Twins query failed: {e}");
+                    std::process::exit(1);
+                }
+            },
+        }
+        return;
+    }
 
-    let manager_channel = manager.get_channel();
-    let _ = manager_channel.send(manager::ManagerMessage::Initialize).await;
+    if let Some(name) = &config.secrets.seal_secret {
+        let mut value = String::new();
+        if let Err(e) = std::io::stdin().read_line(&mut value) {
+            error!("Failed to read the secret value: {e:?}");
+            std::process::exit(1);
+        }
+        match secrets::seal_secret(&config.secrets, name, value.trim_end_matches(['\r', '\n'])) {
+            Ok(()) => info!("Secret {name} sealed"),
+            Err(e) => {
+                error!("Failed to seal secret {name}: {e}");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
 
-    info!("Starting services");
-    let _ = join!(
-        manager.body(),
-        network_receiver.body(),
-        // TODO add rest_server.body(),
-    );
+    match RuntimeBuilder::new(config).build().await {
+        Ok(runtime) => runtime.run().await,
+        Err(e) => {
+            error!("{e}");
+            std::process::exit(1);
+        }
+    }
 }
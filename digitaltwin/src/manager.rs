@@ -1,14 +1,176 @@
-use log::{debug, error, info, trace};
-use std::collections::{HashMap, HashSet};
-use std::fs::File;
-use std::io::BufReader;
+use chrono::{DateTime, Utc};
+use clap::{Args, Parser};
+use log::{debug, error, info, trace, warn, LevelFilter};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use thiserror::Error as ThisError;
-use tokio::sync::mpsc;
-use tokio::task;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::{self, AbortHandle};
 
+use crate::actuation::Actuators;
+use crate::audit::{AuditLog, RebindRecord};
+use crate::backup::{Backup, BackupError, RestoreReport};
+use crate::command::CommandEnvelope;
+use crate::diagram::StateDiagram;
+use crate::event_bus::{BusEvent, EventBus, Lifecycle, LifecycleEvent};
+use crate::federation::SharedProxies;
+use crate::fleet_snapshot::{self, FleetSnapshot};
+use crate::historian::{encode_component, Historian};
+use crate::history::SharedHistory;
+use crate::http_client::{self, HttpError};
+use crate::labels::{LabelSelector, Labels};
+use crate::latency_budget::{LatencyBudgetOptions, SharedHandlerMetrics};
 use crate::network_receiver;
-use crate::twin_runner;
-use digitaltwin_core::{AssetAdministrationShell, AssetID};
+use crate::predictor::PredictorOptions;
+use crate::resources::{self, ResourceReport, TaskStatus, TwinResources};
+use crate::sessions::SharedSessions;
+use crate::staging::{self, StagedTwin, StagedUpdate, StagingSource};
+use crate::startup;
+use crate::templates;
+use crate::twin_log::{self, TwinLogReport};
+use crate::twin_runner::{
+    self, ActorMessage, AvailableActions, CommandEvaluation, CommandOutcome, Heartbeat, TwinReport,
+    TwinServices, HEARTBEAT_INTERVAL,
+};
+use digitaltwin_core::{AssetAdministrationShell, AssetID, AssetKind, DeviceID};
+
+/// Directory of the twin definitions
+pub const TWINS_DIR: &str = "./twins";
+/// Maximum time to wait for a twin to answer a report or invocation request
+const REPORT_TIMEOUT: Duration = Duration::from_secs(2);
+/// Number of missed heartbeats after which a twin is considered unhealthy
+const MISSED_HEARTBEATS: u32 = 3;
+/// Staged updates kept for the REST API
+const STAGED_UPDATES_KEPT: usize = 100;
+
+#[derive(Parser, Clone)]
+pub struct ManagerOptions {
+    /// Restart twins that crash or stop sending heartbeats
+    #[clap(long, env = "RESTART_UNHEALTHY")]
+    restart_unhealthy: bool,
+    /// Reload the twin definitions every given number of seconds, applying any change (0 = never)
+    #[clap(long, default_value = "0", env = "RELOAD_INTERVAL")]
+    reload_interval: u64,
+    /// Append the audit records of the commands to this file, instead of logging them
+    #[clap(long, env = "AUDIT_LOG")]
+    audit_log: Option<std::path::PathBuf>,
+    /// Write the final snapshot of the twins stopped or restarted on request to this directory
+    /// (one JSON file per twin), instead of logging it
+    #[clap(long, env = "SNAPSHOT_DIR")]
+    snapshot_dir: Option<std::path::PathBuf>,
+    /// Command group, as "<group>=<asset id>,<asset id>,..." (separate groups with ';' in TWIN_GROUPS);
+    /// twins can also join groups with the "Groups" property of their TwinConfiguration
+    #[clap(long = "twin-group", value_parser = parse_group, value_delimiter = ';', env = "TWIN_GROUPS")]
+    twin_groups: Vec<(String, Vec<AssetID>)>,
+    /// Label of twins, as "<key>=<value>@<asset id>,<asset id>,..." (separate labels with ';' in
+    /// TWIN_LABELS); the "Labels" of the TwinConfiguration of each twin override them
+    #[clap(long = "twin-label", value_parser = parse_label, value_delimiter = ';', env = "TWIN_LABELS")]
+    twin_labels: Vec<(String, String, Vec<AssetID>)>,
+    /// Default parameter of a twin type, as "<type>.<parameter>=<value>" (separate defaults with ';'
+    /// in TWIN_DEFAULTS), e.g. "ChargingStation.max_current=32.0"; the Parameters of the
+    /// TwinConfiguration of each twin override them. Values are JSON, or plain strings.
+    #[clap(long = "twin-default", value_parser = parse_default, value_delimiter = ';', env = "TWIN_DEFAULTS")]
+    twin_defaults: Vec<(String, String, serde_json::Value)>,
+    /// Twins that must be running before a twin starts, as "<asset id>=<asset id>,<asset id>,..."
+    /// (separate twins with ';' in TWIN_DEPENDENCIES), in addition to the "DependsOn" property of
+    /// the TwinConfiguration of the twin
+    #[clap(
+        long = "twin-dependency",
+        value_parser = startup::parse_dependency,
+        value_delimiter = ';',
+        env = "TWIN_DEPENDENCIES"
+    )]
+    twin_dependencies: Vec<(AssetID, Vec<AssetID>)>,
+    /// Stage the changed definitions alongside the running twins instead of restarting them: the
+    /// new twin replays the recent inputs of the running one, compares its state with it and
+    /// takes over, keeping the state of the running twin if the parameters did not change
+    #[clap(long, env = "STAGED_UPDATES")]
+    staged_updates: bool,
+    /// Recent inputs kept by each twin and replayed into its staged updates
+    #[clap(long, default_value = "100", env = "STAGED_REPLAY_INPUTS")]
+    staged_replay_inputs: usize,
+    /// Hibernate the twins with no input or command for the given number of seconds, keeping
+    /// only their snapshot until the next message revives them (0 = never)
+    #[clap(long, default_value = "0", env = "HIBERNATE_AFTER")]
+    hibernate_after: u64,
+}
+
+fn parse_group(s: &str) -> Result<(String, Vec<AssetID>), String> {
+    let (group, members) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected <group>=<asset id>,...: {s}"))?;
+    let members = members
+        .split(',')
+        .map(str::trim)
+        .filter(|m| !m.is_empty())
+        .map(AssetID::from)
+        .collect();
+    Ok((group.trim().to_string(), members))
+}
+
+fn parse_label(s: &str) -> Result<(String, String, Vec<AssetID>), String> {
+    let invalid = || format!("expected <key>=<value>@<asset id>,...: {s}");
+    let (label, members) = s.split_once('@').ok_or_else(invalid)?;
+    let (key, value) = label.split_once('=').ok_or_else(invalid)?;
+    let key = key.trim();
+    if key.is_empty() {
+        return Err(invalid());
+    }
+    let members = members
+        .split(',')
+        .map(str::trim)
+        .filter(|m| !m.is_empty())
+        .map(AssetID::from)
+        .collect();
+    Ok((key.to_string(), value.trim().to_string(), members))
+}
+
+fn parse_default(s: &str) -> Result<(String, String, serde_json::Value), String> {
+    let (name, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected <type>.<parameter>=<value>: {s}"))?;
+    let (twin_type, parameter) = name
+        .split_once('.')
+        .ok_or_else(|| format!("expected <type>.<parameter>=<value>: {s}"))?;
+    let value = value.trim();
+    let value = serde_json::from_str(value).unwrap_or_else(|_| serde_json::Value::String(value.to_string()));
+    Ok((twin_type.trim().to_string(), parameter.trim().to_string(), value))
+}
+
+/// Default parameters of the twin types, merged with the parameters declared in the AAS
+#[derive(Debug, Default)]
+pub struct TwinDefaults(HashMap<String, serde_json::Map<String, serde_json::Value>>);
+
+impl TwinDefaults {
+    fn new(defaults: &[(String, String, serde_json::Value)]) -> Self {
+        let mut types: HashMap<_, serde_json::Map<_, _>> = HashMap::new();
+        for (twin_type, parameter, value) in defaults {
+            types
+                .entry(twin_type.clone())
+                .or_default()
+                .insert(parameter.clone(), value.clone());
+        }
+        TwinDefaults(types)
+    }
+
+    /// The parameters of a twin of the given type: its own, completed with the defaults of the type
+    pub fn params(&self, twin_type: &str, params: serde_json::Value) -> serde_json::Value {
+        let Some(defaults) = self.0.get(twin_type) else {
+            return params;
+        };
+        let mut merged = defaults.clone();
+        match params {
+            serde_json::Value::Object(params) => merged.extend(params),
+            serde_json::Value::Null => {}
+            // Not an object: the actor decides what to do with it
+            params => return params,
+        }
+        serde_json::Value::Object(merged)
+    }
+}
 
 #[derive(ThisError, Debug)]
 pub enum Error {
@@ -19,29 +181,398 @@ pub enum Error {
     GenericError(String),
 }
 
+#[derive(ThisError, Debug)]
+pub enum RebindError {
+    #[error("twin {0} not found")]
+    NotFound(AssetID),
+    #[error("twin {0} is not running")]
+    NotRunning(AssetID),
+    #[error("cannot rebind: {0}")]
+    Invalid(String),
+}
+
+/// A slot of a twin bound to another sensor at runtime, e.g. after a device replacement.
+/// The binding overrides the data source of the slot in the twin definition until the
+/// runtime restarts.
+#[derive(Debug, Clone)]
+pub struct SlotRebinding {
+    pub twin: AssetID,
+    pub slot: String,
+    pub device_id: DeviceID,
+    /// Who requested the change, for the audit log
+    pub principal: Option<String>,
+}
+
 /// Manager message types
 pub enum ManagerMessage {
     /// Initialize the manager (sent by the main function)
     Initialize,
+    /// Reload the twin definitions, restarting the twins that changed
+    Reload,
     /// Register a new actor (sent by an actor)
     Register(AssetID, mpsc::Sender<twin_runner::ActorMessage>),
+    /// Query the state of the twins
+    Query(Query),
+    /// Periodic liveness signal (sent by an actor)
+    Heartbeat(AssetID, Heartbeat),
+    /// A twin hibernated, or was revived (sent by the twin runner)
+    Hibernating(AssetID, bool),
+    /// A twin runner task terminated, with a flag telling whether it crashed (sent by the twin watcher)
+    TwinExited(AssetID, bool),
+    /// Stop a twin gracefully; it runs again at the next reload of the definitions
+    StopTwin(AssetID),
+    /// Stop a twin gracefully and start it again from its definition and its final snapshot
+    RestartTwin(AssetID),
+    /// A twin stopped on request, with the final snapshot of its actor (sent by an actor)
+    TwinStopped(AssetID, serde_json::Value),
+    /// Bind a slot of a twin to another sensor, replying with the sensor previously bound
+    RebindSlot(SlotRebinding, oneshot::Sender<Result<DeviceID, RebindError>>),
+    /// A twin applied a rebinding, with the sensor previously bound (sent by the rebinding task)
+    SlotRebound(SlotRebinding, DeviceID),
+    /// A staged update of a twin is ready to replace it, or None if the running twin did not
+    /// respond (sent by the staging task)
+    Staged(AssetID, Option<Box<StagedTwin>>),
+    /// Restore a backup: write its definitions, reload them and restore the twins from their snapshots
+    Restore(Box<Backup>, oneshot::Sender<Result<RestoreReport, BackupError>>),
+}
+
+/// Queries answered by the Manager
+pub enum Query {
+    /// Report on all the twins, sorted by asset ID
+    ListTwins(oneshot::Sender<Vec<TwinReport>>),
+    /// Report on a single twin (None if unknown or not responding)
+    Twin(AssetID, oneshot::Sender<Option<TwinReport>>),
+    /// Commands and slots handled by a twin in its current state (None if unknown or not responding)
+    Actions(AssetID, oneshot::Sender<Option<AvailableActions>>),
+    /// Diagram of the state machines of a twin (None if unknown or not responding)
+    Diagram(AssetID, oneshot::Sender<Option<StateDiagram>>),
+    /// Liveness of all the twins, based on heartbeats
+    Health(oneshot::Sender<HealthReport>),
+    /// The AAS of a running twin (None if unknown)
+    Shell(AssetID, oneshot::Sender<Option<AssetAdministrationShell>>),
+    /// Execute a command on a twin and wait for its outcome (None if unknown or not responding)
+    Invoke(AssetID, CommandEnvelope, oneshot::Sender<Option<CommandOutcome>>),
+    /// Evaluate a command on a twin without executing it (None if unknown or not responding)
+    Evaluate(
+        AssetID,
+        CommandEnvelope,
+        oneshot::Sender<Option<CommandEvaluation>>,
+    ),
+    /// Outcome of the latest (re)load of the twin definitions
+    LoadReport(oneshot::Sender<LoadReport>),
+    /// All the command groups and their members, sorted
+    Groups(oneshot::Sender<BTreeMap<String, Vec<AssetID>>>),
+    /// Members of a command group, sorted (empty if the group is unknown)
+    GroupMembers(String, oneshot::Sender<Vec<AssetID>>),
+    /// Running twins whose labels match a selector, sorted
+    Select(LabelSelector, oneshot::Sender<Vec<AssetID>>),
+    /// The AAS of all the running twins
+    Shells(oneshot::Sender<Vec<AssetAdministrationShell>>),
+    /// The schedules declared in the AAS of each running twin (see `twin_schedules`)
+    Schedules(oneshot::Sender<Vec<(AssetID, serde_json::Value)>>),
+    /// Execute a command on the given members of a group, and wait for all the outcomes
+    Broadcast(String, Vec<AssetID>, CommandEnvelope, oneshot::Sender<GroupAck>),
+    /// Set the log verbosity of a twin, or reset it to the global one (false if unknown)
+    SetLogLevel(AssetID, Option<LevelFilter>, oneshot::Sender<bool>),
+    /// Log verbosity and recent log lines of a twin (None if unknown)
+    Logs(AssetID, oneshot::Sender<Option<TwinLogReport>>),
+    /// Channels and bound sensors of the running twins, to rebuild the network routes
+    Routes(oneshot::Sender<Vec<network_receiver::TwinRoute>>),
+    /// Latest staged updates of the twin definitions, oldest first
+    StagedUpdates(oneshot::Sender<Vec<StagedUpdate>>),
+    /// Definitions, snapshots, subscriptions and history metadata of the running twins
+    Backup(oneshot::Sender<Result<Backup, BackupError>>),
+    /// States of all the running twins at the same instant
+    FleetSnapshot(oneshot::Sender<FleetSnapshot>),
+    /// Queue, task status and estimated memory of each twin
+    Resources(oneshot::Sender<ResourceReport>),
+}
+
+/// Arguments of the twins subcommand
+#[derive(Args)]
+pub struct TwinsArgs {
+    /// asset ID of a single twin to report on; all the twins by default
+    pub asset_id: Option<String>,
+    /// only the twins whose labels match the selector (e.g., "site=hq,tier!=test")
+    #[clap(long, conflicts_with = "asset_id")]
+    pub selector: Option<String>,
+    /// REST API of the runtime (http:// or https://)
+    #[clap(long, default_value = "http://127.0.0.1:8080", env = "DT_URL")]
+    pub url: String,
+    /// API key of the REST API, if required
+    #[clap(long, env = "DT_API_KEY")]
+    pub api_key: Option<String>,
+}
+
+impl TwinsArgs {
+    /// Query the REST API, returning the JSON reports of the twins
+    pub async fn run(&self) -> Result<String, HttpError> {
+        let url = self.url.trim_end_matches('/');
+        let url = match (&self.asset_id, &self.selector) {
+            (Some(id), _) => format!("{url}/twins/{}", encode_component(id)),
+            (None, Some(selector)) => format!("{url}/twins?selector={}", encode_component(selector)),
+            (None, None) => format!("{url}/twins"),
+        };
+        let authorization = self.api_key.as_ref().map(|key| format!("Bearer {key}"));
+        let mut headers = vec![("Accept", "application/json")];
+        if let Some(authorization) = &authorization {
+            headers.push(("Authorization", authorization));
+        }
+        http_client::get(&url, &headers).await
+    }
+}
+
+/// Aggregate ack of a command sent to a group of twins
+#[derive(Debug, Clone, Serialize)]
+pub struct GroupAck {
+    pub group: String,
+    pub command: String,
+    pub correlation_id: String,
+    /// Members that executed the command, now or with an earlier delivery
+    pub executed: usize,
+    /// Members that rejected the command, are not running or did not respond in time
+    pub failed: usize,
+    /// Outcome of each member, sorted by asset ID
+    pub members: Vec<MemberAck>,
+}
+
+/// Outcome of a command for a member of a group
+#[derive(Debug, Clone, Serialize)]
+pub struct MemberAck {
+    pub asset_id: AssetID,
+    /// An outcome of the audit log, or "rate_limited", "not_running" or "no_response"
+    pub outcome: &'static str,
+}
+
+impl GroupAck {
+    pub fn new(group: String, envelope: &CommandEnvelope) -> Self {
+        GroupAck {
+            group,
+            command: envelope.command.clone(),
+            correlation_id: envelope.correlation_id.clone(),
+            executed: 0,
+            failed: 0,
+            members: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, asset_id: AssetID, outcome: &'static str) {
+        if outcome == "executed" || outcome == "duplicate" {
+            self.executed += 1;
+        } else {
+            self.failed += 1;
+        }
+        let index = self.members.partition_point(|m| m.asset_id < asset_id);
+        self.members.insert(index, MemberAck { asset_id, outcome });
+    }
+}
+
+/// Outcome of a (re)load of the twin definitions
+#[derive(Debug, Clone, Serialize)]
+pub struct LoadReport {
+    pub timestamp: DateTime<Utc>,
+    /// Twins running after the load, sorted by asset ID
+    pub loaded: Vec<AssetID>,
+    /// Definitions that could not be loaded, and twins that could not be started
+    pub failed: Vec<LoadFailure>,
+    /// Time taken to parse the definitions and start the twins
+    pub duration_ms: u128,
+}
+
+/// A twin definition that could not be loaded or started
+#[derive(Debug, Clone, Serialize)]
+pub struct LoadFailure {
+    /// The file or the asset ID of the definition
+    pub source: String,
+    pub error: String,
+}
+
+impl LoadReport {
+    fn new() -> Self {
+        LoadReport {
+            timestamp: Utc::now(),
+            loaded: Vec::new(),
+            failed: Vec::new(),
+            duration_ms: 0,
+        }
+    }
+
+    fn fail(&mut self, source: impl Into<String>, error: impl ToString) {
+        let failure = LoadFailure {
+            source: source.into(),
+            error: error.to_string(),
+        };
+        error!("Cannot load {}: {}", failure.source, failure.error);
+        self.failed.push(failure);
+    }
+
+    fn log_summary(&self) {
+        if self.failed.is_empty() {
+            info!(
+                "Loaded {} digital twins in {} ms",
+                self.loaded.len(),
+                self.duration_ms
+            );
+        } else {
+            warn!(
+                "Loaded {} digital twins in {} ms, {} definitions failed: {}",
+                self.loaded.len(),
+                self.duration_ms,
+                self.failed.len(),
+                self.failed
+                    .iter()
+                    .map(|f| f.source.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+    }
+}
+
+/// Liveness of the twins
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthReport {
+    /// Number of running twins
+    pub twins: usize,
+    /// Twins that stopped sending heartbeats
+    pub unhealthy: Vec<AssetID>,
+    /// Twins not started yet, with the dependencies they are waiting for
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub waiting: BTreeMap<AssetID, Vec<AssetID>>,
+    /// Twins hibernated for lack of input, sending no heartbeat until revived
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub hibernating: Vec<AssetID>,
+}
+
+/// Liveness information of a twin, updated by its heartbeats
+struct TwinHealth {
+    last_heartbeat: Instant,
+    state: String,
+    queue_depth: usize,
+    healthy: bool,
+    /// Whether the twin sent its first heartbeat, letting the twins depending on it start
+    ready: bool,
+    /// Whether the twin is hibernated, sending no heartbeat
+    hibernating: bool,
+}
+
+/// A twin built and waiting for its dependencies to be ready before starting
+struct WaitingTwin {
+    aas: AssetAdministrationShell,
+    twin: twin_runner::TwinRunner,
+    dependencies: Vec<AssetID>,
+}
+
+/// A twin runner task started by the manager
+struct SupervisedTwin {
+    /// The AAS the twin was created from, used to restart it
+    aas: AssetAdministrationShell,
+    /// Handle to abort the twin runner task
+    abort_handle: AbortHandle,
 }
 
 pub struct Manager {
     actors: HashMap<AssetID, mpsc::Sender<twin_runner::ActorMessage>>,
+    /// Running twin tasks
+    supervised: HashMap<AssetID, SupervisedTwin>,
+    /// Latest heartbeat information of each twin
+    health: HashMap<AssetID, TwinHealth>,
+    /// Twins aborted by the manager that must be restarted once terminated
+    restarting: HashSet<AssetID>,
+    /// Audit log, historian, history, sessions, actuators and event bus shared by the twins
+    services: TwinServices,
+    /// Default parameters of the twin types
+    twin_defaults: Arc<TwinDefaults>,
+    /// Outcome of the latest (re)load of the twin definitions
+    load_report: LoadReport,
+    /// Slots bound to another sensor at runtime, applied to the definitions at each reload
+    rebindings: HashMap<AssetID, BTreeMap<String, DeviceID>>,
+    /// Snapshots of a restored backup, applied to the twins when they start
+    pending_snapshots: HashMap<AssetID, serde_json::Value>,
+    /// Outcome of the snapshots applied to the twins when they started, for the restore report
+    restore_outcomes: HashMap<AssetID, Result<String, String>>,
+    /// Final snapshots of the twins restarted on request, applied when they start again
+    final_snapshots: HashMap<AssetID, serde_json::Value>,
+    /// Twins whose changed definition is being staged
+    staging: HashSet<AssetID>,
+    /// Staged twins replacing the running ones once terminated
+    staged: HashMap<AssetID, StagedTwin>,
+    /// Latest staged updates, oldest first
+    staged_updates: VecDeque<StagedUpdate>,
+    /// Read-only twins mirrored from another runtime, listed along with the local ones
+    proxies: SharedProxies,
+    /// Twins waiting for their dependencies, in startup order
+    waiting: Vec<WaitingTwin>,
+    /// Directory of the twin definitions
+    twins_dir: PathBuf,
     send_ch: mpsc::Sender<ManagerMessage>,
     recv_ch: mpsc::Receiver<ManagerMessage>,
     network_ch: mpsc::Sender<network_receiver::NetworkMessage>,
+    /// Options
+    options: ManagerOptions,
 }
 
 impl Manager {
-    pub fn new(network_ch: mpsc::Sender<network_receiver::NetworkMessage>) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        options: ManagerOptions,
+        historian: Option<Historian>,
+        history: Option<SharedHistory>,
+        sessions: Option<SharedSessions>,
+        predictors: PredictorOptions,
+        bus: EventBus,
+        actuators: Option<Arc<Actuators>>,
+        latency_budget: LatencyBudgetOptions,
+        proxies: SharedProxies,
+        network_ch: mpsc::Sender<network_receiver::NetworkMessage>,
+    ) -> Self {
         let (send_ch, recv_ch) = mpsc::channel(5);
+        let audit_log = match &options.audit_log {
+            Some(path) => AuditLog::open(path).unwrap_or_else(|e| {
+                error!("Cannot open audit log {}, logging instead: {e}", path.display());
+                AuditLog::default()
+            }),
+            None => AuditLog::default(),
+        };
         Manager {
             actors: HashMap::new(),
+            supervised: HashMap::new(),
+            health: HashMap::new(),
+            restarting: HashSet::new(),
+            services: TwinServices {
+                audit_log,
+                historian,
+                history,
+                sessions,
+                predictors,
+                recent_inputs: if options.staged_updates {
+                    options.staged_replay_inputs
+                } else {
+                    0
+                },
+                actuators,
+                bus,
+                latency_budget,
+                handler_metrics: SharedHandlerMetrics::default(),
+                hibernate_after: (options.hibernate_after > 0)
+                    .then(|| Duration::from_secs(options.hibernate_after)),
+            },
+            twin_defaults: Arc::new(TwinDefaults::new(&options.twin_defaults)),
+            load_report: LoadReport::new(),
+            rebindings: HashMap::new(),
+            pending_snapshots: HashMap::new(),
+            restore_outcomes: HashMap::new(),
+            final_snapshots: HashMap::new(),
+            staging: HashSet::new(),
+            staged: HashMap::new(),
+            staged_updates: VecDeque::new(),
+            proxies,
+            waiting: Vec::new(),
+            twins_dir: PathBuf::from(TWINS_DIR),
             send_ch,
             recv_ch,
             network_ch,
+            options,
         }
     }
 
@@ -49,53 +580,1573 @@ impl Manager {
         self.send_ch.clone()
     }
 
-    pub fn initialize_dtwins(&self) -> Result<(), Error> {
-        let mut twins = HashSet::new();
-        for entry in std::fs::read_dir("./twins")? {
+    /// The execution time of the handlers of the twins
+    pub fn handler_metrics(&self) -> SharedHandlerMetrics {
+        self.services.handler_metrics.clone()
+    }
+
+    /// Load all the twin definitions, instantiating the ones derived from a Type shell.
+    /// Invalid definitions are skipped and recorded in the report.
+    async fn load_shells(&self, report: &mut LoadReport) -> Result<Vec<AssetAdministrationShell>, Error> {
+        let mut paths = Vec::new();
+        for entry in std::fs::read_dir(&self.twins_dir)? {
             let path = entry?.path();
-            if path.extension().unwrap_or_default() != "yaml" {
+            // Instances files are read along with their template
+            if parser_for(&path).is_some() && !templates::is_instances(&path) {
+                paths.push(path);
+            }
+        }
+        // The first definition of a duplicate AAS id wins, whatever the parsing order
+        paths.sort();
+
+        // Files are parsed in parallel on the blocking thread pool
+        let parsed = map_blocking(paths, |path| {
+            let result = if templates::is_template(&path) {
+                parse_template(&path)
+            } else {
+                parse_file(&path).map(|aas| vec![aas])
+            };
+            (path, result)
+        })
+        .await;
+        let mut shells = Vec::new();
+        for (path, result) in parsed {
+            match result {
+                Ok(parsed) => {
+                    for aas in parsed {
+                        trace!("{:#?}", aas);
+                        shells.push(aas);
+                    }
+                }
+                Err(e) => report.fail(path.display().to_string(), e),
+            }
+        }
+
+        // Type shells are not run, they are only used to instantiate the shells derived from them
+        let (types, instances): (Vec<_>, Vec<_>) = shells
+            .into_iter()
+            .partition(|aas| aas.asset_kind == AssetKind::Type);
+        let types: HashMap<_, _> = types.into_iter().map(|aas| (aas.id.clone(), aas)).collect();
+
+        let mut twins = HashSet::new();
+        let mut loaded = Vec::new();
+        for aas in instances {
+            let mut aas = match &aas.derived_from {
+                Some(type_id) => match types.get(type_id).map(|type_aas| type_aas.instantiate(&aas)) {
+                    Some(Ok(instance)) => instance,
+                    Some(Err(e)) => {
+                        report.fail(&aas.id, format!("cannot instantiate: {e}"));
+                        continue;
+                    }
+                    None => {
+                        report.fail(&aas.id, format!("type shell {type_id} not found"));
+                        continue;
+                    }
+                },
+                None => aas,
+            };
+            for (slot, device_id) in self.rebindings.get(&aas.id).into_iter().flatten() {
+                if let Err(e) = aas.rebind_slot(slot, device_id) {
+                    warn!("{}: slot {slot} not rebound to {device_id}: {e}", aas.id);
+                }
+            }
+            for problem in aas.validate_semantics() {
+                warn!("{}: {problem}", aas.id);
+            }
+            if !twins.insert(aas.id.clone()) {
+                report.fail(&aas.id, "duplicate AAS id, ignored");
+                continue;
+            }
+            loaded.push(aas);
+        }
+        Ok(loaded)
+    }
+
+    pub async fn initialize_dtwins(&mut self) -> Result<(), Error> {
+        let started = Instant::now();
+        let mut report = LoadReport::new();
+        let shells = self.load_shells(&mut report).await?;
+        let count = shells.len();
+        let mut built = Vec::new();
+        for (i, (aas, twin)) in self.build_twins(shells).await.into_iter().enumerate() {
+            info!(
+                "[{}/{count}] Creating new digital twin for {} ({})",
+                i + 1,
+                aas.id,
+                aas.description.as_ref().unwrap_or(&"-".to_string())
+            );
+            match twin {
+                Ok(twin) => built.push((aas, twin)),
+                Err(e) => report.fail(aas.id, format!("cannot create digital twin: {e}")),
+            }
+        }
+        self.schedule_twins(built, &mut report);
+        self.finish_load(report, started);
+        Ok(())
+    }
+
+    /// The twins that must be running before this one starts, from its AAS and the options
+    fn dependencies(&self, aas: &AssetAdministrationShell) -> Vec<AssetID> {
+        let mut dependencies = aas.twin_dependencies();
+        for (_, configured) in self
+            .options
+            .twin_dependencies
+            .iter()
+            .filter(|(id, _)| *id == aas.id)
+        {
+            dependencies.extend(configured.iter().cloned());
+        }
+        let mut seen = HashSet::new();
+        dependencies.retain(|dependency| *dependency != aas.id && seen.insert(dependency.clone()));
+        dependencies
+    }
+
+    /// Start the built twins after their dependencies: a twin waits until the twins it depends
+    /// on report ready. The twins in a dependency cycle are not started.
+    fn schedule_twins(
+        &mut self,
+        built: Vec<(AssetAdministrationShell, twin_runner::TwinRunner)>,
+        report: &mut LoadReport,
+    ) {
+        let dependencies: Vec<_> = built
+            .iter()
+            .map(|(aas, _)| (aas.id.clone(), self.dependencies(aas)))
+            .collect();
+        let plan = startup::plan(&dependencies);
+        for id in &plan.cyclic {
+            report.fail(id.clone(), "dependency cycle, not started");
+        }
+        let mut built: HashMap<_, _> = built
+            .into_iter()
+            .map(|(aas, twin)| (aas.id.clone(), (aas, twin)))
+            .collect();
+        let mut dependencies: HashMap<_, _> = dependencies.into_iter().collect();
+        for id in plan.order {
+            let (Some((aas, twin)), Some(mut twin_dependencies)) =
+                (built.remove(&id), dependencies.remove(&id))
+            else {
                 continue;
+            };
+            twin_dependencies.retain(|dependency| {
+                let known = built.contains_key(dependency)
+                    || self.supervised.contains_key(dependency)
+                    || self.waiting.iter().any(|waiting| waiting.aas.id == *dependency);
+                if !known {
+                    warn!("Twin {id} depends on unknown twin {dependency}, ignored");
+                }
+                known
+            });
+            if !twin_dependencies.is_empty() {
+                let names: Vec<_> = twin_dependencies.iter().map(AssetID::as_str).collect();
+                info!("Twin {id} starts after {}", names.join(", "));
+            }
+            report.loaded.push(id);
+            self.waiting.push(WaitingTwin {
+                aas,
+                twin,
+                dependencies: twin_dependencies,
+            });
+        }
+        self.start_ready_twins();
+    }
+
+    /// Start the waiting twins whose dependencies are all ready
+    fn start_ready_twins(&mut self) {
+        let mut i = 0;
+        while i < self.waiting.len() {
+            let ready = self.waiting[i]
+                .dependencies
+                .iter()
+                .all(|dependency| self.health.get(dependency).is_some_and(|health| health.ready));
+            if ready {
+                let waiting = self.waiting.remove(i);
+                self.start_twin(waiting.aas, waiting.twin);
+            } else {
+                i += 1;
             }
-            debug!("Processing file: {:?}", path.display());
-            if let Ok(reader) = File::open(&path).map(BufReader::new) {
-                let aas = AssetAdministrationShell::from_reader(reader)
-                    .map_err(|e| Error::GenericError(e.to_string()))?;
-                trace!("{:#?}", aas);
-                if !twins.insert(aas.id.clone()) {
-                    error!("Duplicate AAS id: {}, ignored", aas.id);
+        }
+    }
+
+    /// Log and keep the report of a (re)load
+    fn finish_load(&mut self, mut report: LoadReport, started: Instant) {
+        report.loaded.sort();
+        report.duration_ms = started.elapsed().as_millis();
+        report.log_summary();
+        self.load_report = report;
+    }
+
+    /// Construct the twin runners in parallel on the blocking thread pool
+    async fn build_twins(
+        &self,
+        shells: Vec<AssetAdministrationShell>,
+    ) -> Vec<(AssetAdministrationShell, Result<twin_runner::TwinRunner, Error>)> {
+        let (twin_defaults, manager_ch, network_ch, services) = (
+            self.twin_defaults.clone(),
+            self.send_ch.clone(),
+            self.network_ch.clone(),
+            self.services.clone(),
+        );
+        map_blocking(shells, move |aas| {
+            let twin = twin_runner::TwinRunner::new(
+                aas.clone(),
+                &twin_defaults,
+                manager_ch.clone(),
+                network_ch.clone(),
+                services.clone(),
+            )
+            .map_err(|e| Error::GenericError(e.to_string()));
+            (aas, twin)
+        })
+        .await
+    }
+
+    /// Reload the twin definitions: start the new twins, stop the removed ones, and
+    /// restart the changed ones, publishing what changed
+    async fn reload_dtwins(&mut self) -> Result<(), Error> {
+        let started = Instant::now();
+        let mut report = LoadReport::new();
+        let shells = self.load_shells(&mut report).await?;
+        // The waiting twins are scheduled again from their current definitions
+        self.waiting.clear();
+
+        let removed: Vec<_> = self
+            .supervised
+            .keys()
+            .filter(|id| !shells.iter().any(|aas| &aas.id == *id))
+            .cloned()
+            .collect();
+        for id in removed {
+            info!("Twin {id} removed, stopping it");
+            self.restarting.remove(&id);
+            twin_log::forget(&id);
+            if let Some(twin) = self.supervised.get(&id) {
+                twin.abort_handle.abort();
+            }
+            let _ = self
+                .network_ch
+                .send(network_receiver::NetworkMessage::Unregister(id))
+                .await;
+        }
+
+        let mut new_shells = Vec::new();
+        let mut staged_shells = Vec::new();
+        for aas in shells {
+            let Some(twin) = self.supervised.get_mut(&aas.id) else {
+                new_shells.push(aas);
+                continue;
+            };
+            report.loaded.push(aas.id.clone());
+            let changes = twin.aas.diff(&aas);
+            if changes.is_empty() {
+                continue;
+            }
+            for change in &changes {
+                info!("Twin {} changed: {change:?}", aas.id);
+            }
+            let id = aas.id.clone();
+            let previous = std::mem::replace(&mut twin.aas, aas.clone());
+            match self.actors.get(&id).filter(|_| self.options.staged_updates) {
+                // The running twin is replaced once the new one is staged
+                Some(ch) => {
+                    self.staging.insert(id.clone());
+                    staged_shells.push((previous, aas, ch.clone()));
+                }
+                // The twin is restarted with the new definition once terminated
+                None => {
+                    twin.abort_handle.abort();
+                    self.restarting.insert(id.clone());
+                }
+            }
+            let _ = self
+                .network_ch
+                .send(network_receiver::NetworkMessage::Changes(id, changes))
+                .await;
+        }
+        self.stage_twins(staged_shells).await;
+        let mut built = Vec::new();
+        for (aas, twin) in self.build_twins(new_shells).await {
+            info!("Creating new digital twin for {}", aas.id);
+            match twin {
+                Ok(twin) => built.push((aas, twin)),
+                Err(e) => report.fail(aas.id, format!("cannot create digital twin: {e}")),
+            }
+        }
+        self.schedule_twins(built, &mut report);
+        self.finish_load(report, started);
+        Ok(())
+    }
+
+    /// Build the twins of the changed definitions without starting them, and stage them in a
+    /// task each: replay the recent inputs of the running twin and compare the states
+    async fn stage_twins(
+        &mut self,
+        staged_shells: Vec<(
+            AssetAdministrationShell,
+            AssetAdministrationShell,
+            mpsc::Sender<ActorMessage>,
+        )>,
+    ) {
+        let (previous, staged_shells): (Vec<_>, Vec<_>) = staged_shells
+            .into_iter()
+            .map(|(previous, aas, ch)| ((previous, ch), aas))
+            .unzip();
+        let built = self.build_twins(staged_shells).await;
+        for ((previous, ch), (aas, twin)) in previous.into_iter().zip(built) {
+            let id = aas.id.clone();
+            let mut twin = match twin {
+                Ok(twin) => twin,
+                Err(e) => {
+                    // Restarted anyway, reporting the error as a restart would
+                    error!("Cannot stage twin {id}: {e}");
+                    self.staging.remove(&id);
+                    if let Some(running) = self.supervised.get(&id) {
+                        running.abort_handle.abort();
+                        self.restarting.insert(id);
+                    }
                     continue;
                 }
-                info!(
-                    "Creating new digital twin for {} ({})",
-                    aas.id,
-                    aas.description.as_ref().unwrap_or(&"-".to_string())
-                );
-                let twin = twin_runner::TwinRunner::new(aas, self.send_ch.clone(), self.network_ch.clone());
-                task::spawn(twin_runner::body(Box::new(twin)));
+            };
+            info!("Staging the new definition of twin {id}");
+            let same_parameters = previous.twin_type() == aas.twin_type()
+                && previous.twin_parameters() == aas.twin_parameters();
+            let manager_ch = self.send_ch.clone();
+            task::spawn(async move {
+                let staged = request_staging_source(&ch).await.map(|source| {
+                    let update = staging::stage(&mut twin, source, same_parameters);
+                    Box::new(StagedTwin { aas, twin, update })
+                });
+                let _ = manager_ch.send(ManagerMessage::Staged(id, staged)).await;
+            });
+        }
+    }
+
+    /// Switch over to a staged twin: the running twin is aborted, and the staged one started
+    /// once it terminated. A staged twin of an outdated definition is dropped.
+    fn switch_over(&mut self, id: AssetID, staged: Option<Box<StagedTwin>>) {
+        self.staging.remove(&id);
+        let Some(running) = self.supervised.get(&id) else {
+            return;
+        };
+        let Some(staged) = staged else {
+            warn!("Twin {id} did not respond, restarting it with the new definition");
+            running.abort_handle.abort();
+            self.restarting.insert(id);
+            return;
+        };
+        if !running.aas.diff(&staged.aas).is_empty() {
+            debug!("Staged twin {id} outdated by a newer definition, dropped");
+            return;
+        }
+        let update = &staged.update;
+        let message = format!(
+            "Twin {id} switched over to its new definition: {} inputs replayed, state {} (running {}), {:?}",
+            update.replayed, update.staged_state, update.running_state, update.outcome
+        );
+        if update.outcome == staging::StagingOutcome::Replayed {
+            warn!("{message}");
+        } else {
+            info!("{message}");
+        }
+        if self.staged_updates.len() == STAGED_UPDATES_KEPT {
+            self.staged_updates.pop_front();
+        }
+        self.staged_updates.push_back(staged.update.clone());
+        running.abort_handle.abort();
+        self.staged.insert(id, *staged);
+    }
+
+    /// Create and start a twin, from the final snapshot of its previous runner if any
+    fn spawn_twin(
+        &mut self,
+        aas: AssetAdministrationShell,
+        snapshot: Option<serde_json::Value>,
+    ) -> Result<(), Error> {
+        let mut twin = twin_runner::TwinRunner::new(
+            aas.clone(),
+            &self.twin_defaults,
+            self.send_ch.clone(),
+            self.network_ch.clone(),
+            self.services.clone(),
+        )
+        .map_err(|e| Error::GenericError(e.to_string()))?;
+        if let Some(snapshot) = snapshot {
+            if let Err(e) = twin.restore(snapshot) {
+                error!("Cannot restore twin {} from its final snapshot: {e}", aas.id);
             }
         }
+        self.start_twin(aas, twin);
         Ok(())
     }
 
+    /// Spawn the twin runner task, and a watcher marking the twin offline when it terminates.
+    /// A twin with a pending snapshot from a restored backup starts from it.
+    fn start_twin(&mut self, aas: AssetAdministrationShell, mut twin: twin_runner::TwinRunner) {
+        let id = twin.id();
+        if let Some(snapshot) = self.pending_snapshots.remove(&id) {
+            let outcome = twin.restore(snapshot);
+            if let Err(e) = &outcome {
+                error!("Cannot restore twin {id} from its snapshot: {e}");
+            }
+            self.restore_outcomes.insert(id.clone(), outcome);
+        }
+        let network_ch = self.network_ch.clone();
+        let manager_ch = self.send_ch.clone();
+        self.services.bus.publish(BusEvent::Lifecycle(LifecycleEvent::now(
+            id.clone(),
+            Lifecycle::Started,
+        )));
+        let handle = task::spawn(twin_log::scope(
+            id.clone(),
+            twin_runner::run(Box::new(twin), self.twin_defaults.clone(), self.services.clone()),
+        ));
+        let abort_handle = handle.abort_handle();
+        let watched_id = id.clone();
+        task::spawn(async move {
+            let crashed = match handle.await {
+                Ok(()) => {
+                    info!("Twin {watched_id} stopped");
+                    false
+                }
+                Err(e) if e.is_cancelled() => {
+                    info!("Twin {watched_id} aborted");
+                    false
+                }
+                Err(e) => {
+                    error!("Twin {watched_id} crashed: {e:?}");
+                    true
+                }
+            };
+            let _ = network_ch
+                .send(network_receiver::NetworkMessage::Availability(
+                    watched_id.clone(),
+                    network_receiver::Availability::Offline,
+                ))
+                .await;
+            let _ = manager_ch
+                .send(ManagerMessage::TwinExited(watched_id, crashed))
+                .await;
+        });
+        self.health.insert(
+            id.clone(),
+            TwinHealth {
+                last_heartbeat: Instant::now(),
+                state: String::new(),
+                queue_depth: 0,
+                healthy: true,
+                ready: false,
+                hibernating: false,
+            },
+        );
+        self.supervised.insert(id, SupervisedTwin { aas, abort_handle });
+    }
+
+    /// Mark twins that missed too many heartbeats as unhealthy, restarting them if configured
+    fn check_health(&mut self) {
+        let deadline = HEARTBEAT_INTERVAL * MISSED_HEARTBEATS;
+        for (id, health) in self.health.iter_mut() {
+            if health.healthy && !health.hibernating && health.last_heartbeat.elapsed() > deadline {
+                warn!(
+                    "Twin {id} is unhealthy: no heartbeat for {:?} (last state {}, queue depth {})",
+                    health.last_heartbeat.elapsed(),
+                    health.state,
+                    health.queue_depth
+                );
+                health.healthy = false;
+                if self.options.restart_unhealthy {
+                    if let Some(twin) = self.supervised.get(id) {
+                        info!("Restarting unhealthy twin {id}");
+                        self.restarting.insert(id.clone());
+                        twin.abort_handle.abort();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Task status and history size of each twin, with its channel to measure its queue and
+    /// its snapshot
+    fn twin_resources(&self) -> Vec<(TwinResources, Option<mpsc::Sender<ActorMessage>>)> {
+        let mut twins: HashMap<AssetID, TwinResources> = HashMap::new();
+        for (id, twin) in &self.supervised {
+            let health = self.health.get(id);
+            let task = if twin.abort_handle.is_finished() {
+                TaskStatus::Exited
+            } else {
+                match health {
+                    Some(health) if health.hibernating => TaskStatus::Hibernated,
+                    Some(health) if !health.healthy => TaskStatus::Unhealthy,
+                    Some(health) if health.ready => TaskStatus::Running,
+                    _ => TaskStatus::Starting,
+                }
+            };
+            twins.insert(id.clone(), TwinResources::new(id.clone(), task));
+        }
+        for waiting in &self.waiting {
+            let id = waiting.aas.id.clone();
+            twins.insert(id.clone(), TwinResources::new(id, TaskStatus::Waiting));
+        }
+        if let Some(history) = &self.services.history {
+            let metadata = history.lock().unwrap_or_else(|e| e.into_inner()).metadata();
+            for slot in &metadata {
+                if let Some(twin) = twins.get_mut(&slot.asset_id) {
+                    let (values, bytes) = resources::history_footprint(slot);
+                    twin.history_values += values;
+                    twin.history_bytes += bytes;
+                }
+            }
+        }
+        twins
+            .into_values()
+            .map(|twin| {
+                let ch = self.actors.get(&twin.asset_id).cloned();
+                (twin, ch)
+            })
+            .collect()
+    }
+
+    /// Ask a twin to stop, restarting it once terminated if requested
+    fn stop_twin(&mut self, id: AssetID, restart: bool) {
+        let Some(twin) = self.supervised.get(&id) else {
+            warn!("Cannot stop unknown twin {id}");
+            return;
+        };
+        info!("{} twin {id}", if restart { "Restarting" } else { "Stopping" });
+        if restart {
+            self.restarting.insert(id.clone());
+        }
+        match self.actors.get(&id) {
+            // Sent from a task, as the twin may be waiting for the manager loop
+            Some(ch) => {
+                let ch = ch.clone();
+                task::spawn(async move {
+                    let _ = ch.send(ActorMessage::Stop).await;
+                });
+            }
+            // Not registered yet, nothing to unsubscribe nor to save
+            None => twin.abort_handle.abort(),
+        }
+    }
+
+    /// Ask a twin to bind a slot to another sensor. The rebinding is checked against the
+    /// definition first; the twin replies once its subscriptions are moved.
+    fn rebind_slot(
+        &mut self,
+        rebinding: SlotRebinding,
+        reply: oneshot::Sender<Result<DeviceID, RebindError>>,
+    ) {
+        let id = rebinding.twin.clone();
+        let Some(twin) = self.supervised.get(&id) else {
+            let _ = reply.send(Err(RebindError::NotFound(id)));
+            return;
+        };
+        if let Err(e) = twin
+            .aas
+            .clone()
+            .rebind_slot(&rebinding.slot, &rebinding.device_id)
+        {
+            let _ = reply.send(Err(RebindError::Invalid(e)));
+            return;
+        }
+        let Some(ch) = self.actors.get(&id).cloned() else {
+            let _ = reply.send(Err(RebindError::NotRunning(id)));
+            return;
+        };
+        let manager_ch = self.send_ch.clone();
+        // Sent from a task, as the twin may be waiting for the manager loop
+        task::spawn(async move {
+            let (actor_reply, response) = oneshot::channel();
+            let message =
+                ActorMessage::RebindSlot(rebinding.slot.clone(), rebinding.device_id.clone(), actor_reply);
+            let result = match ch.send(message).await {
+                Ok(()) => tokio::time::timeout(REPORT_TIMEOUT, response)
+                    .await
+                    .ok()
+                    .and_then(Result::ok),
+                Err(_) => None,
+            };
+            let result = match result {
+                Some(Ok(previous)) => {
+                    let _ = manager_ch
+                        .send(ManagerMessage::SlotRebound(rebinding, previous.clone()))
+                        .await;
+                    Ok(previous)
+                }
+                Some(Err(e)) => Err(RebindError::Invalid(e)),
+                None => Err(RebindError::NotRunning(id)),
+            };
+            let _ = reply.send(result);
+        });
+    }
+
+    /// Record a rebinding applied by a twin: keep it for the next reloads, update the
+    /// definition of the twin and publish the change
+    async fn slot_rebound(&mut self, rebinding: SlotRebinding, previous: DeviceID) {
+        let Some(twin) = self.supervised.get_mut(&rebinding.twin) else {
+            return;
+        };
+        let mut aas = twin.aas.clone();
+        if let Err(e) = aas.rebind_slot(&rebinding.slot, &rebinding.device_id) {
+            warn!(
+                "Twin {}: slot {} not rebound: {e}",
+                rebinding.twin, rebinding.slot
+            );
+            return;
+        }
+        let changes = twin.aas.diff(&aas);
+        twin.aas = aas;
+        self.rebindings
+            .entry(rebinding.twin.clone())
+            .or_default()
+            .insert(rebinding.slot.clone(), rebinding.device_id.clone());
+        self.services.audit_log.record(&RebindRecord {
+            asset_id: &rebinding.twin,
+            change: "rebind_slot",
+            slot: &rebinding.slot,
+            previous: &previous,
+            device_id: &rebinding.device_id,
+            principal: rebinding.principal.as_deref(),
+            received: Utc::now(),
+        });
+        let _ = self
+            .network_ch
+            .send(network_receiver::NetworkMessage::Changes(rebinding.twin, changes))
+            .await;
+    }
+
+    /// Save the final snapshot of a twin stopped on request
+    fn save_snapshot(&self, id: &AssetID, snapshot: &serde_json::Value) {
+        let Some(dir) = &self.options.snapshot_dir else {
+            info!("Final snapshot of twin {id}: {snapshot}");
+            return;
+        };
+        let name: String = id
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let path = dir.join(format!("{name}.json"));
+        let result = std::fs::create_dir_all(dir)
+            .and_then(|()| std::fs::write(&path, serde_json::to_vec_pretty(snapshot).unwrap_or_default()));
+        match result {
+            Ok(()) => info!("Final snapshot of twin {id} saved to {}", path.display()),
+            Err(e) => error!("Cannot save the snapshot of twin {id} to {}: {e}", path.display()),
+        }
+    }
+
+    /// Clean up after a twin task terminated, restarting it if needed
+    fn twin_exited(&mut self, id: AssetID, crashed: bool) {
+        let stage = if crashed {
+            Lifecycle::Crashed
+        } else {
+            Lifecycle::Stopped
+        };
+        self.services
+            .bus
+            .publish(BusEvent::Lifecycle(LifecycleEvent::now(id.clone(), stage)));
+        self.actors.remove(&id);
+        self.health.remove(&id);
+        let snapshot = self.final_snapshots.remove(&id);
+        if let Some(staged) = self.staged.remove(&id) {
+            self.supervised.remove(&id);
+            info!("Starting staged twin runner for {id}");
+            self.start_twin(staged.aas, staged.twin);
+            return;
+        }
+        let restart = self.restarting.remove(&id) || (crashed && self.options.restart_unhealthy);
+        if let Some(twin) = self.supervised.remove(&id) {
+            if restart {
+                info!("Starting new twin runner for {id}");
+                if let Err(e) = self.spawn_twin(twin.aas, snapshot) {
+                    error!("Cannot restart twin {id}: {e}");
+                }
+            }
+        }
+    }
+
+    /// Restore a backup: write its definitions and reload them, then restore the running twins
+    /// from their snapshots. The twins restarted by the reload are restored when they start again.
+    async fn restore_backup(
+        &mut self,
+        backup: Backup,
+        reply: oneshot::Sender<Result<RestoreReport, BackupError>>,
+    ) {
+        let twins_dir = self.twins_dir.clone();
+        let written = task::spawn_blocking(move || {
+            backup
+                .write_definitions(&twins_dir)
+                .map(|written| (written, backup))
+        })
+        .await
+        .map_err(|e| BackupError::Invalid(e.to_string()))
+        .and_then(|written| written);
+        let (definitions, backup) = match written {
+            Ok(written) => written,
+            Err(e) => {
+                let _ = reply.send(Err(e));
+                return;
+            }
+        };
+        info!(
+            "Restoring a backup of {} definitions and {} twins",
+            definitions.len(),
+            backup.snapshots.len()
+        );
+        for (id, slots) in backup.subscriptions {
+            self.rebindings.insert(id, slots);
+        }
+        self.restore_outcomes.clear();
+        self.pending_snapshots = backup.snapshots.into_iter().collect();
+        if let Err(e) = self.reload_dtwins().await {
+            error!("Error reloading digital twins: {:?}", e);
+        }
+
+        let mut report = RestoreReport {
+            definitions,
+            ..Default::default()
+        };
+        // Twins started by the reload
+        for (id, outcome) in self.restore_outcomes.drain() {
+            match outcome {
+                Ok(_) => report.restored.push(id),
+                Err(e) => {
+                    report.failed.insert(id, e);
+                }
+            }
+        }
+        // Twins left running by the reload are restored in place, the restarting ones when they start
+        let mut running = Vec::new();
+        for (id, snapshot) in std::mem::take(&mut self.pending_snapshots) {
+            if self.restarting.contains(&id) || self.staging.contains(&id) || self.staged.contains_key(&id) {
+                report.pending.push(id.clone());
+                self.pending_snapshots.insert(id, snapshot);
+            } else if let Some(ch) = self.actors.get(&id) {
+                running.push((id, ch.clone(), snapshot));
+            } else {
+                report.failed.insert(id, "not running".to_string());
+            }
+        }
+        // Sent from a task, as the twins may be waiting for the manager loop
+        task::spawn(async move {
+            for (id, ch, snapshot) in running {
+                match request_restore(&ch, snapshot).await {
+                    Some(Ok(_)) => report.restored.push(id),
+                    Some(Err(e)) => {
+                        report.failed.insert(id, e);
+                    }
+                    None => {
+                        report.failed.insert(id, "no response".to_string());
+                    }
+                }
+            }
+            report.restored.sort();
+            report.pending.sort();
+            let _ = reply.send(Ok(report));
+        });
+    }
+
+    /// Command groups, declared in the configuration or by the running twins
+    fn groups(&self) -> BTreeMap<String, BTreeSet<AssetID>> {
+        let mut groups: BTreeMap<String, BTreeSet<AssetID>> = BTreeMap::new();
+        for (group, members) in &self.options.twin_groups {
+            groups
+                .entry(group.clone())
+                .or_default()
+                .extend(members.iter().cloned());
+        }
+        for (id, twin) in &self.supervised {
+            for group in twin.aas.twin_groups() {
+                groups.entry(group).or_default().insert(id.clone());
+            }
+        }
+        groups
+    }
+
+    /// Labels of a twin, declared in the configuration or in its AAS
+    fn labels(&self, id: &AssetID, aas: &AssetAdministrationShell) -> Labels {
+        let mut labels: Labels = self
+            .options
+            .twin_labels
+            .iter()
+            .filter(|(_, _, members)| members.contains(id))
+            .map(|(key, value, _)| (key.clone(), value.clone()))
+            .collect();
+        labels.extend(aas.twin_labels());
+        labels
+    }
+
+    /// Labels of all the running twins
+    fn all_labels(&self) -> HashMap<AssetID, Labels> {
+        self.supervised
+            .iter()
+            .map(|(id, twin)| (id.clone(), self.labels(id, &twin.aas)))
+            .collect()
+    }
+
+    /// Answer a query without blocking the manager loop, as twins may be slow to respond
+    fn handle_query(&self, query: Query) {
+        match query {
+            Query::ListTwins(reply) => {
+                let channels: Vec<_> = self.actors.values().cloned().collect();
+                let mut labels = self.all_labels();
+                // A local twin takes precedence over a proxy with the same ID
+                let proxies: Vec<_> = {
+                    let proxies = self.proxies.lock().unwrap_or_else(|e| e.into_inner());
+                    proxies
+                        .values()
+                        .filter(|proxy| !self.supervised.contains_key(&proxy.report.asset_id))
+                        .map(|proxy| proxy.report.clone())
+                        .collect()
+                };
+                task::spawn(async move {
+                    let mut reports = proxies;
+                    for ch in channels {
+                        if let Some(mut report) = request_report(&ch).await {
+                            report.labels = labels.remove(&report.asset_id).unwrap_or_default();
+                            reports.push(report);
+                        }
+                    }
+                    reports.sort_by(|a, b| a.asset_id.cmp(&b.asset_id));
+                    let _ = reply.send(reports);
+                });
+            }
+            Query::SetLogLevel(id, level, reply) => {
+                let known = self.supervised.contains_key(&id);
+                if known {
+                    info!(
+                        "Log level of twin {id} set to {}",
+                        level.map_or("default", |l| l.as_str())
+                    );
+                    twin_log::set_level(&id, level);
+                }
+                let _ = reply.send(known);
+            }
+            Query::Logs(id, reply) => {
+                let known = self.supervised.contains_key(&id);
+                let _ = reply.send(known.then(|| twin_log::report(&id)));
+            }
+            Query::Routes(reply) => {
+                let channels: Vec<_> = self
+                    .actors
+                    .iter()
+                    .map(|(id, ch)| (id.clone(), ch.clone()))
+                    .collect();
+                task::spawn(async move {
+                    let mut routes = Vec::new();
+                    for (asset_id, channel) in channels {
+                        // A twin that does not respond keeps its channel, and subscribes
+                        // again when it is restarted
+                        let devices = match request_report(&channel).await {
+                            Some(report) => report.bound_sensors.into_keys().collect(),
+                            None => Vec::new(),
+                        };
+                        routes.push(network_receiver::TwinRoute {
+                            asset_id,
+                            channel,
+                            devices,
+                        });
+                    }
+                    let _ = reply.send(routes);
+                });
+            }
+            Query::Twin(id, reply) => {
+                let channel = self.actors.get(&id).cloned();
+                let labels = self.all_labels().remove(&id).unwrap_or_default();
+                let proxy = (!self.supervised.contains_key(&id))
+                    .then(|| {
+                        let proxies = self.proxies.lock().unwrap_or_else(|e| e.into_inner());
+                        proxies.get(&id).map(|proxy| proxy.report.clone())
+                    })
+                    .flatten();
+                task::spawn(async move {
+                    let report = match channel {
+                        Some(ch) => request_report(&ch)
+                            .await
+                            .map(|report| TwinReport { labels, ..report }),
+                        None => proxy,
+                    };
+                    let _ = reply.send(report);
+                });
+            }
+            Query::Actions(id, reply) => {
+                let channel = self.actors.get(&id).cloned();
+                task::spawn(async move {
+                    let actions = match channel {
+                        Some(ch) => request_actions(&ch).await,
+                        None => None,
+                    };
+                    let _ = reply.send(actions);
+                });
+            }
+            Query::Diagram(id, reply) => {
+                let channel = self.actors.get(&id).cloned();
+                task::spawn(async move {
+                    let diagram = match channel {
+                        Some(ch) => request_diagram(&ch).await,
+                        None => None,
+                    };
+                    let _ = reply.send(diagram);
+                });
+            }
+            Query::Shell(id, reply) => {
+                let _ = reply.send(self.supervised.get(&id).map(|twin| twin.aas.clone()));
+            }
+            Query::Invoke(id, envelope, reply) => {
+                let channel = self.actors.get(&id).cloned();
+                task::spawn(async move {
+                    let outcome = match channel {
+                        Some(ch) => request_invocation(&ch, envelope).await,
+                        None => None,
+                    };
+                    let _ = reply.send(outcome);
+                });
+            }
+            Query::Evaluate(id, envelope, reply) => {
+                let channel = self.actors.get(&id).cloned();
+                task::spawn(async move {
+                    let evaluation = match channel {
+                        Some(ch) => request_evaluation(&ch, envelope).await,
+                        None => None,
+                    };
+                    let _ = reply.send(evaluation);
+                });
+            }
+            Query::LoadReport(reply) => {
+                let _ = reply.send(self.load_report.clone());
+            }
+            Query::Groups(reply) => {
+                let groups = self
+                    .groups()
+                    .into_iter()
+                    .map(|(group, members)| (group, members.into_iter().collect()));
+                let _ = reply.send(groups.collect());
+            }
+            Query::Shells(reply) => {
+                let _ = reply.send(self.supervised.values().map(|twin| twin.aas.clone()).collect());
+            }
+            Query::Schedules(reply) => {
+                let schedules = self
+                    .supervised
+                    .iter()
+                    .map(|(id, twin)| (id.clone(), twin.aas.twin_schedules()))
+                    .filter(|(_, schedules)| !schedules.is_null());
+                let _ = reply.send(schedules.collect());
+            }
+            Query::GroupMembers(group, reply) => {
+                let members = self.groups().remove(&group).unwrap_or_default();
+                let _ = reply.send(members.into_iter().collect());
+            }
+            Query::Select(selector, reply) => {
+                let mut members: Vec<AssetID> = self
+                    .all_labels()
+                    .into_iter()
+                    .filter(|(_, labels)| selector.matches(labels))
+                    .map(|(id, _)| id)
+                    .collect();
+                members.sort();
+                let _ = reply.send(members);
+            }
+            Query::Broadcast(group, members, envelope, reply) => {
+                let mut ack = GroupAck::new(group, &envelope);
+                let mut requests = task::JoinSet::new();
+                for id in members {
+                    match self.actors.get(&id).cloned() {
+                        Some(ch) => {
+                            let envelope = envelope.clone();
+                            requests.spawn(async move { (id, request_invocation(&ch, envelope).await) });
+                        }
+                        None => ack.record(id, "not_running"),
+                    }
+                }
+                task::spawn(async move {
+                    while let Some(Ok((id, outcome))) = requests.join_next().await {
+                        ack.record(id, outcome.as_ref().map_or("no_response", CommandOutcome::as_str));
+                    }
+                    let _ = reply.send(ack);
+                });
+            }
+            Query::StagedUpdates(reply) => {
+                let _ = reply.send(self.staged_updates.iter().cloned().collect());
+            }
+            Query::Backup(reply) => {
+                let channels: Vec<_> = self
+                    .actors
+                    .iter()
+                    .map(|(id, ch)| (id.clone(), ch.clone()))
+                    .collect();
+                let history = self.services.history.clone();
+                let twins_dir = self.twins_dir.clone();
+                task::spawn(async move {
+                    let backup = task::spawn_blocking(move || Backup::new(&twins_dir))
+                        .await
+                        .map_err(|e| BackupError::Invalid(e.to_string()))
+                        .and_then(|backup| backup);
+                    let mut backup = match backup {
+                        Ok(backup) => backup,
+                        Err(e) => {
+                            let _ = reply.send(Err(e));
+                            return;
+                        }
+                    };
+                    for (id, ch) in channels {
+                        // A twin that does not respond is left out of the backup
+                        let (Some(snapshot), Some(report)) =
+                            (request_snapshot(&ch).await, request_report(&ch).await)
+                        else {
+                            warn!("Twin {id} did not respond, not saved in the backup");
+                            continue;
+                        };
+                        let subscriptions = report
+                            .bound_sensors
+                            .into_iter()
+                            .map(|(device_id, slot)| (slot, device_id))
+                            .collect();
+                        backup.snapshots.insert(id.clone(), snapshot);
+                        backup.subscriptions.insert(id, subscriptions);
+                    }
+                    if let Some(history) = history {
+                        backup.history = history.lock().unwrap_or_else(|e| e.into_inner()).metadata();
+                    }
+                    let _ = reply.send(Ok(backup));
+                });
+            }
+            Query::FleetSnapshot(reply) => {
+                let channels: Vec<_> = self
+                    .actors
+                    .iter()
+                    .map(|(id, ch)| (id.clone(), ch.clone()))
+                    .collect();
+                task::spawn(async move {
+                    let _ = reply.send(fleet_snapshot::capture(channels, REPORT_TIMEOUT).await);
+                });
+            }
+            Query::Resources(reply) => {
+                let twins = self.twin_resources();
+                task::spawn(async move {
+                    let _ = reply.send(resources::measure(twins, REPORT_TIMEOUT).await);
+                });
+            }
+            Query::Health(reply) => {
+                let mut unhealthy: Vec<_> = self
+                    .health
+                    .iter()
+                    .filter(|(_, health)| !health.healthy)
+                    .map(|(id, _)| id.clone())
+                    .collect();
+                unhealthy.sort();
+                let mut hibernating: Vec<_> = self
+                    .health
+                    .iter()
+                    .filter(|(_, health)| health.hibernating)
+                    .map(|(id, _)| id.clone())
+                    .collect();
+                hibernating.sort();
+                let waiting = self
+                    .waiting
+                    .iter()
+                    .map(|waiting| {
+                        let dependencies = waiting
+                            .dependencies
+                            .iter()
+                            .filter(|dependency| {
+                                !self.health.get(*dependency).is_some_and(|health| health.ready)
+                            })
+                            .cloned()
+                            .collect();
+                        (waiting.aas.id.clone(), dependencies)
+                    })
+                    .collect();
+                let _ = reply.send(HealthReport {
+                    twins: self.health.len(),
+                    unhealthy,
+                    waiting,
+                    hibernating,
+                });
+            }
+        }
+    }
+
     pub async fn body(&mut self) {
         info!("Manager body starting");
+        let mut health_check = tokio::time::interval(HEARTBEAT_INTERVAL);
+        // The first tick completes immediately, before the twins are initialized
+        let reload_period = Duration::from_secs(self.options.reload_interval.max(1));
+        let mut reload = tokio::time::interval_at(tokio::time::Instant::now() + reload_period, reload_period);
         loop {
             tokio::select! {
+                _ = health_check.tick() => {
+                    self.check_health();
+                }
+                _ = reload.tick(), if self.options.reload_interval > 0 => {
+                    if let Err(e) = self.reload_dtwins().await {
+                        error!("Error reloading digital twins: {:?}", e);
+                    }
+                }
                 Some(msg) = self.recv_ch.recv() => {
                     match msg {
                         ManagerMessage::Register(id, ch) => {
                             debug!("Registering actor with id: {}", id);
                             self.actors.insert(id, ch);
                         }
+                        ManagerMessage::Query(query) => {
+                            self.handle_query(query);
+                        }
+                        ManagerMessage::Heartbeat(id, heartbeat) => {
+                            trace!("Heartbeat from {id}: {heartbeat:?}");
+                            if let Some(health) = self.health.get_mut(&id) {
+                                if !health.healthy {
+                                    info!("Twin {id} is healthy again");
+                                }
+                                health.last_heartbeat = Instant::now();
+                                health.state = heartbeat.state;
+                                health.queue_depth = heartbeat.queue_depth;
+                                health.healthy = true;
+                                health.hibernating = false;
+                                if !std::mem::replace(&mut health.ready, true) {
+                                    self.services
+                                        .bus
+                                        .publish(BusEvent::Lifecycle(LifecycleEvent::now(id, Lifecycle::Ready)));
+                                    self.start_ready_twins();
+                                }
+                            }
+                        }
+                        ManagerMessage::Hibernating(id, hibernating) => {
+                            if let Some(health) = self.health.get_mut(&id) {
+                                health.hibernating = hibernating;
+                                // The heartbeats are due again from the revival
+                                health.last_heartbeat = Instant::now();
+                            }
+                        }
+                        ManagerMessage::TwinExited(id, crashed) => {
+                            self.twin_exited(id, crashed);
+                        }
+                        ManagerMessage::StopTwin(id) => {
+                            self.stop_twin(id, false);
+                        }
+                        ManagerMessage::RestartTwin(id) => {
+                            self.stop_twin(id, true);
+                        }
+                        ManagerMessage::TwinStopped(id, snapshot) => {
+                            self.save_snapshot(&id, &snapshot);
+                            if self.restarting.contains(&id) {
+                                self.final_snapshots.insert(id, snapshot);
+                            }
+                        }
+                        ManagerMessage::RebindSlot(rebinding, reply) => {
+                            self.rebind_slot(rebinding, reply);
+                        }
+                        ManagerMessage::SlotRebound(rebinding, previous) => {
+                            self.slot_rebound(rebinding, previous).await;
+                        }
+                        ManagerMessage::Staged(id, staged) => {
+                            self.switch_over(id, staged);
+                        }
+                        ManagerMessage::Restore(backup, reply) => {
+                            self.restore_backup(*backup, reply).await;
+                        }
                         ManagerMessage::Initialize => {
                             debug!("Initializing digital twins...");
-                            if let Err(e) = self.initialize_dtwins() {
+                            if let Err(e) = self.initialize_dtwins().await {
                                 error!("Error initializing digital twins: {:?}", e);
                             }
                         }
+                        ManagerMessage::Reload => {
+                            debug!("Reloading digital twins...");
+                            if let Err(e) = self.reload_dtwins().await {
+                                error!("Error reloading digital twins: {:?}", e);
+                            }
+                        }
                     }
                 }
             }
         }
     }
 }
+
+/// Apply a function to all the items on the blocking thread pool, splitting them in
+/// one batch per available CPU. The results are in the same order as the items.
+async fn map_blocking<T, R, F>(items: Vec<T>, f: F) -> Vec<R>
+where
+    T: Send + 'static,
+    R: Send + 'static,
+    F: Fn(T) -> R + Clone + Send + 'static,
+{
+    let workers = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let batch_size = items.len().div_ceil(workers).max(1);
+    let mut items = items.into_iter();
+    let mut batches = Vec::new();
+    loop {
+        let batch: Vec<T> = items.by_ref().take(batch_size).collect();
+        if batch.is_empty() {
+            break;
+        }
+        let f = f.clone();
+        batches.push(task::spawn_blocking(move || {
+            batch.into_iter().map(f).collect::<Vec<R>>()
+        }));
+    }
+    let mut results = Vec::new();
+    for batch in batches {
+        match batch.await {
+            Ok(batch) => results.extend(batch),
+            // The functions don't panic, unless there is a bug
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        }
+    }
+    results
+}
+
+/// Parser of a twin definition file
+pub type ShellParser = fn(&[u8]) -> Result<AssetAdministrationShell, String>;
+
+/// The parser of a definition file, selected by its extension
+pub fn parser_for(path: &Path) -> Option<ShellParser> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("yaml") => Some(|content: &[u8]| AssetAdministrationShell::from_reader(content)),
+        Some("json") => Some(|content: &[u8]| AssetAdministrationShell::from_json_reader(content)),
+        Some("xml") => Some(|content: &[u8]| AssetAdministrationShell::from_xml_reader(content)),
+        _ => None,
+    }
+}
+
+fn parse_file(path: &Path) -> Result<AssetAdministrationShell, String> {
+    debug!("Processing file: {:?}", path.display());
+    let parse = parser_for(path).ok_or("unsupported format")?;
+    let content = std::fs::read(path).map_err(|e| e.to_string())?;
+    parse(&content)
+}
+
+/// Expand a template into one shell per parameter set of its instances file
+fn parse_template(path: &Path) -> Result<Vec<AssetAdministrationShell>, String> {
+    debug!("Processing template: {:?}", path.display());
+    let parse = parser_for(path).ok_or("unsupported format")?;
+    let template = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let instances_path = templates::instances_path(path);
+    let instances = std::fs::read_to_string(&instances_path)
+        .map_err(|e| format!("cannot read {}: {e}", instances_path.display()))?;
+    let instances = templates::parse_instances(&instances)?;
+    templates::expand(&template, &instances)?
+        .iter()
+        .enumerate()
+        .map(|(i, text)| parse(text.as_bytes()).map_err(|e| format!("instance {}: {e}", i + 1)))
+        .collect()
+}
+
+/// Ask a twin to execute a command and wait for the outcome
+async fn request_invocation(
+    ch: &mpsc::Sender<ActorMessage>,
+    envelope: CommandEnvelope,
+) -> Option<CommandOutcome> {
+    let (reply, response) = oneshot::channel();
+    ch.send(ActorMessage::Invoke(envelope, reply)).await.ok()?;
+    tokio::time::timeout(REPORT_TIMEOUT, response).await.ok()?.ok()
+}
+
+/// Ask a twin what a command would do, without executing it
+pub async fn request_evaluation(
+    ch: &mpsc::Sender<ActorMessage>,
+    envelope: CommandEnvelope,
+) -> Option<CommandEvaluation> {
+    let (reply, response) = oneshot::channel();
+    ch.send(ActorMessage::Evaluate(envelope, reply)).await.ok()?;
+    tokio::time::timeout(REPORT_TIMEOUT, response).await.ok()?.ok()
+}
+
+/// Ask a twin for the commands and slots it handles in its current state
+async fn request_actions(ch: &mpsc::Sender<ActorMessage>) -> Option<AvailableActions> {
+    let (reply, response) = oneshot::channel();
+    ch.send(ActorMessage::Actions(reply)).await.ok()?;
+    tokio::time::timeout(REPORT_TIMEOUT, response).await.ok()?.ok()
+}
+
+/// Ask a twin for the diagram of its state machines
+async fn request_diagram(ch: &mpsc::Sender<ActorMessage>) -> Option<StateDiagram> {
+    let (reply, response) = oneshot::channel();
+    ch.send(ActorMessage::Diagram(reply)).await.ok()?;
+    tokio::time::timeout(REPORT_TIMEOUT, response).await.ok()?.ok()
+}
+
+/// Ask a twin for its status report
+async fn request_report(ch: &mpsc::Sender<ActorMessage>) -> Option<TwinReport> {
+    let (reply, response) = oneshot::channel();
+    ch.send(ActorMessage::Report(reply)).await.ok()?;
+    tokio::time::timeout(REPORT_TIMEOUT, response).await.ok()?.ok()
+}
+
+/// Ask a twin for a snapshot of its actor
+async fn request_snapshot(ch: &mpsc::Sender<ActorMessage>) -> Option<serde_json::Value> {
+    let (reply, response) = oneshot::channel();
+    ch.send(ActorMessage::Snapshot(reply)).await.ok()?;
+    tokio::time::timeout(REPORT_TIMEOUT, response).await.ok()?.ok()
+}
+
+/// Ask a twin to replace its actor with one restored from a snapshot
+async fn request_restore(
+    ch: &mpsc::Sender<ActorMessage>,
+    snapshot: serde_json::Value,
+) -> Option<Result<String, String>> {
+    let (reply, response) = oneshot::channel();
+    ch.send(ActorMessage::Restore(snapshot, reply)).await.ok()?;
+    tokio::time::timeout(REPORT_TIMEOUT, response).await.ok()?.ok()
+}
+
+/// Ask a twin for its state, a snapshot and its recent inputs, to stage an update of its definition
+async fn request_staging_source(ch: &mpsc::Sender<ActorMessage>) -> Option<StagingSource> {
+    let (reply, response) = oneshot::channel();
+    ch.send(ActorMessage::StagingSource(reply)).await.ok()?;
+    tokio::time::timeout(REPORT_TIMEOUT, response).await.ok()?.ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::CommandSource;
+    use crate::network_receiver::NetworkMessage;
+    use crate::predictor::PredictorOptions;
+    use tokio::sync::broadcast;
+
+    fn test_manager() -> (Manager, mpsc::Receiver<NetworkMessage>) {
+        let (network_ch, network_rx) = mpsc::channel(100);
+        let manager = Manager::new(
+            ManagerOptions::parse_from(["test"]),
+            None,
+            None,
+            None,
+            PredictorOptions::parse_from(["test"]),
+            EventBus::default(),
+            None,
+            LatencyBudgetOptions::parse_from(["test"]),
+            SharedProxies::default(),
+            network_ch,
+        );
+        (manager, network_rx)
+    }
+
+    /// A temporary twins directory, with these definitions
+    fn twins_dir(name: &str, definitions: &[(String, String)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dt-manager-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for (file, content) in definitions {
+            std::fs::write(dir.join(file), content).unwrap();
+        }
+        dir
+    }
+
+    /// A light bulb definition, with this instance number
+    fn light_bulb(instance: usize) -> (String, String) {
+        (
+            format!("light_bulb_{instance:06}.yaml"),
+            include_str!("../../twins/light_bulb.yaml").replace("id-000001", &format!("id-{instance:06}")),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_load_report() {
+        let dir = twins_dir(
+            "load",
+            &[
+                light_bulb(1),
+                ("broken.yaml".to_string(), "id: [not an AAS".to_string()),
+                ("notes.txt".to_string(), "not a definition".to_string()),
+            ],
+        );
+        let (mut manager, _network_rx) = test_manager();
+        manager.twins_dir = dir.clone();
+        manager.initialize_dtwins().await.unwrap();
+
+        // The valid definition runs, the broken one is reported, the other files are ignored
+        let report = &manager.load_report;
+        assert_eq!(report.loaded, ["urn:aas:smart-home:light:light-bulb:id-000001"]);
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(
+            report.failed[0].source,
+            dir.join("broken.yaml").display().to_string()
+        );
+        assert!(manager
+            .supervised
+            .contains_key("urn:aas:smart-home:light:light-bulb:id-000001"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_ack() {
+        let (mut manager, _network_rx) = test_manager();
+        let (running, mut running_rx) = mpsc::channel(5);
+        manager.actors.insert("urn:twin:a".into(), running);
+        let (unresponsive, mut unresponsive_rx) = mpsc::channel(5);
+        manager.actors.insert("urn:twin:c".into(), unresponsive);
+        tokio::spawn(async move {
+            while let Some(ActorMessage::Invoke(envelope, reply)) = running_rx.recv().await {
+                let _ = reply.send(CommandOutcome::Executed(envelope.args));
+            }
+        });
+        // Keeps the requests without answering them
+        tokio::spawn(async move {
+            let mut requests = Vec::new();
+            while let Some(request) = unresponsive_rx.recv().await {
+                requests.push(request);
+            }
+        });
+
+        let envelope = CommandEnvelope::new(CommandSource::Rest, "SwitchOff", serde_json::Value::Null);
+        let (reply, response) = oneshot::channel();
+        let members = vec!["urn:twin:c".into(), "urn:twin:b".into(), "urn:twin:a".into()];
+        manager.handle_query(Query::Broadcast("lights".to_string(), members, envelope, reply));
+        let ack = response.await.unwrap();
+        assert_eq!(
+            (ack.group.as_str(), ack.command.as_str()),
+            ("lights", "SwitchOff")
+        );
+        assert_eq!((ack.executed, ack.failed), (1, 2));
+        let outcomes: Vec<_> = ack
+            .members
+            .iter()
+            .map(|m| (m.asset_id.as_str(), m.outcome))
+            .collect();
+        assert_eq!(
+            outcomes,
+            [
+                ("urn:twin:a", "executed"),
+                ("urn:twin:b", "not_running"),
+                ("urn:twin:c", "no_response")
+            ]
+        );
+    }
+
+    /// The light bulb of the twins directory, running in a manager with these options,
+    /// switched on. Returns the channel of the manager and its lifecycle events.
+    async fn running_light_bulb(
+        name: &str,
+        options: &[&str],
+    ) -> (
+        mpsc::Sender<ManagerMessage>,
+        broadcast::Receiver<BusEvent>,
+        PathBuf,
+    ) {
+        let dir = twins_dir(name, &[light_bulb(1)]);
+        let (mut manager, mut network_rx) = test_manager();
+        manager.options = ManagerOptions::parse_from(["test"].iter().chain(options));
+        manager.twins_dir = dir.clone();
+        let events = manager.services.bus.subscribe();
+        let manager_ch = manager.get_channel();
+        manager_ch.send(ManagerMessage::Initialize).await.unwrap();
+        tokio::spawn(async move { manager.body().await });
+        tokio::spawn(async move { while network_rx.recv().await.is_some() {} });
+
+        let id = AssetID::from("urn:aas:smart-home:light:light-bulb:id-000001");
+        let envelope = CommandEnvelope::new(CommandSource::Rest, "SwitchOn", serde_json::Value::Null);
+        for _ in 0..100 {
+            let (reply, response) = oneshot::channel();
+            let query = Query::Invoke(id.clone(), envelope.clone(), reply);
+            manager_ch.send(ManagerMessage::Query(query)).await.unwrap();
+            if let Some(outcome) = response.await.unwrap() {
+                assert_eq!(outcome.as_str(), "executed");
+                return (manager_ch, events, dir);
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("the twin did not start");
+    }
+
+    /// Wait for a lifecycle event of the twins
+    async fn next_stage(events: &mut broadcast::Receiver<BusEvent>) -> Lifecycle {
+        loop {
+            let event = tokio::time::timeout(Duration::from_secs(2), events.recv()).await;
+            if let BusEvent::Lifecycle(event) = event.unwrap().unwrap() {
+                return event.stage;
+            }
+        }
+    }
+
+    async fn twin_state(manager_ch: &mpsc::Sender<ManagerMessage>, id: &str) -> Option<String> {
+        let (reply, response) = oneshot::channel();
+        let query = Query::Twin(id.into(), reply);
+        manager_ch.send(ManagerMessage::Query(query)).await.unwrap();
+        response.await.unwrap().map(|report| report.state)
+    }
+
+    #[tokio::test]
+    async fn test_stop_twin() {
+        let snapshots = std::env::temp_dir().join(format!("dt-manager-{}-snapshots", std::process::id()));
+        let snapshot_dir = snapshots.to_str().unwrap();
+        let (manager_ch, mut events, dir) =
+            running_light_bulb("stop", &["--snapshot-dir", snapshot_dir]).await;
+        let id = "urn:aas:smart-home:light:light-bulb:id-000001";
+        assert!(matches!(next_stage(&mut events).await, Lifecycle::Started));
+        assert!(matches!(next_stage(&mut events).await, Lifecycle::Ready));
+
+        // The final snapshot is written, and the twin is not restarted
+        manager_ch
+            .send(ManagerMessage::StopTwin(id.into()))
+            .await
+            .unwrap();
+        assert!(matches!(next_stage(&mut events).await, Lifecycle::Stopped));
+        let snapshot = std::fs::read(snapshots.join("urn_aas_smart-home_light_light-bulb_id-000001.json"));
+        let snapshot: serde_json::Value = serde_json::from_slice(&snapshot.unwrap()).unwrap();
+        assert!(snapshot.get("slots").is_some());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(events.try_recv().is_err());
+        assert_eq!(twin_state(&manager_ch, id).await, None);
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::remove_dir_all(&snapshots).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_restart_twin() {
+        let (manager_ch, mut events, dir) = running_light_bulb("restart", &[]).await;
+        let id = "urn:aas:smart-home:light:light-bulb:id-000001";
+        assert_eq!(twin_state(&manager_ch, id).await.as_deref(), Some("On"));
+
+        // The new runner starts from the final snapshot, not from the initial state
+        manager_ch
+            .send(ManagerMessage::RestartTwin(id.into()))
+            .await
+            .unwrap();
+        while !matches!(next_stage(&mut events).await, Lifecycle::Stopped) {}
+        assert!(matches!(next_stage(&mut events).await, Lifecycle::Started));
+        assert!(matches!(next_stage(&mut events).await, Lifecycle::Ready));
+        assert_eq!(twin_state(&manager_ch, id).await.as_deref(), Some("On"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_map_blocking_order() {
+        let items: Vec<usize> = (0..1000).collect();
+        let results = map_blocking(items, |i| {
+            // Later batches finish first
+            std::thread::sleep(Duration::from_micros((1000 - i as u64) / 100));
+            i * 2
+        })
+        .await;
+        assert_eq!(results, (0..1000).map(|i| i * 2).collect::<Vec<_>>());
+        assert!(map_blocking(Vec::<usize>::new(), |i| i).await.is_empty());
+    }
+
+    /// Time to parse the definitions of a large fleet, run with
+    /// cargo test --release -p digitaltwin measure_load_shells -- --ignored --nocapture
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore]
+    async fn measure_load_shells() {
+        const SHELLS: usize = 5000;
+        let definitions: Vec<_> = (0..SHELLS).map(light_bulb).collect();
+        let dir = twins_dir("measure", &definitions);
+        let (mut manager, _network_rx) = test_manager();
+        manager.twins_dir = dir.clone();
+        let started = Instant::now();
+        let shells = manager.load_shells(&mut LoadReport::new()).await.unwrap();
+        let elapsed = started.elapsed();
+        println!(
+            "Loaded {} shells in {} ms ({} us/shell)",
+            shells.len(),
+            elapsed.as_millis(),
+            elapsed.as_micros() / SHELLS as u128
+        );
+        assert_eq!(shells.len(), SHELLS);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,6 +1,8 @@
 use digitaltwin_core::ActorStateType;
 use digitaltwin_macros::*;
 
+use super::connectivity::ConnectivityFactory;
+
 // Charging Station states
 
 /// No vehicle connected
@@ -19,7 +21,17 @@ pub struct Charging;
 #[derive(Clone, Debug)]
 pub struct Fault;
 
-#[actor(default_state = "Idle", slots("CurrentPowerDraw", "InputCurrent"))]
+#[actor(
+    default_state = "Idle",
+    states("Idle", "Connected", "Charging", "Fault"),
+    slots("CurrentPowerDraw", "InputCurrent"),
+    events(
+        "IdlePowerFault",
+        "OvercurrentFault",
+        "ChargingComplete",
+        "ChargingCurrentRequested"
+    )
+)]
 pub struct ChargingStation {
     /// minimum current draw when in charging mode [A]
     #[actor_attr(default = "1.0")]
@@ -40,8 +52,9 @@ impl ChargingStation<Idle> {
     // Otherwise, we assume a fault is present
     fn power_change(&self, pwr: f32) -> Box<ActorStateType> {
         if pwr > self.max_sleep_power {
-            // TODO: raise invalid power absorbtion event
-            self.transition::<Fault>()
+            let mut next = self.clone();
+            next.emit("IdlePowerFault", serde_json::json!({ "power": pwr }));
+            next.transition::<Fault>()
         } else {
             self.transition::<Idle>()
         }
@@ -77,32 +90,56 @@ impl ChargingStation<Connected> {
 #[dispatch_map("CurrentPowerDraw" = power_change)]
 #[dispatch_map("InputCurrent" = current_change)]
 #[command_map("SetChargingCurrent" = set_charging_current)]
+#[command_map("ReadingsMismatch" = readings_mismatch)]
 impl ChargingStation<Charging> {
     // If power goes below the minimum threshold
     // we assume charging is complete (or the user has stopped charging)
     fn power_change(&self, pwr: f32) -> Box<ActorStateType> {
         if pwr < self.max_sleep_power {
-            // TODO: raise "charging complete" event
-            self.transition::<Connected>()
+            let mut next = self.clone();
+            next.emit("ChargingComplete", serde_json::json!({ "power": pwr }));
+            next.transition::<Connected>()
         } else {
             self.transition::<Charging>()
         }
     }
 
-    // If an overcurrent is detected, we assume a fault is present
+    // If an overcurrent is detected, we assume a fault is present. The event carries the
+    // latest power draw, if known, to tell a faulty vehicle from a faulty current sensor
     fn current_change(&self, current: f32) -> Box<ActorStateType> {
         if current > self.max_current {
-            self.transition::<Fault>()
+            let mut payload = serde_json::json!({ "current": current, "max_current": self.max_current });
+            if let Some(power) = self.context().last::<f32>("CurrentPowerDraw") {
+                payload["power"] = power.into();
+            }
+            let mut next = self.clone();
+            next.emit("OvercurrentFault", payload);
+            next.transition::<Fault>()
         } else {
             self.transition::<Charging>()
         }
     }
 
-    // Set the charging current to a new value
+    // Set the charging current to a new value, requested to the device through the
+    // actuation declared for the event in the AAS
     fn set_charging_current(&self, arg: serde_json::Value) -> Box<ActorStateType> {
         log::info!("Set charging current to {}", arg);
-        // TODO: send a "set current" command to the device
-        self.transition::<Charging>()
+        let mut next = self.clone();
+        match arg["desired_current"].as_f64() {
+            Some(current) => next.emit(
+                "ChargingCurrentRequested",
+                serde_json::json!({ "current": current }),
+            ),
+            None => log::warn!("No desired_current in {arg}"),
+        }
+        next.transition::<Charging>()
+    }
+
+    // The readings of several slots contradict each other (e.g., a current with no power,
+    // as found by a condition of the AAS): the meter or the station is faulty
+    fn readings_mismatch(&self, arg: serde_json::Value) -> Box<ActorStateType> {
+        log::warn!("Readings mismatch: {arg}");
+        self.transition::<Fault>()
     }
 }
 
@@ -115,15 +152,22 @@ impl ChargingStation<Fault> {
     }
 }
 
+/// The charging station twin, combining the charging lifecycle
+/// with the independent connectivity status of the station
+#[actor_regions(charging = "ChargingStationFactory", connectivity = "ConnectivityFactory")]
+pub struct ChargingPoint;
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use digitaltwin_core::ActorFactory;
+    use crate::models::aas::home_charging_station::{events, operations, slots};
+    use crate::models::connectivity::{Connectivity, Online};
+    use digitaltwin_core::{ActorFactory, RegionSet};
 
     #[test]
     fn test_idle_state_power_change_high() {
         let (actor, _) = ChargingStationFactory::create_default();
-        let actor = actor.input_change("CurrentPowerDraw", 10.0);
+        let actor = actor.input_change(slots::CURRENT_POWER_DRAW, 10.0);
         // Expect transition to Fault
         assert!(actor.as_any().downcast_ref::<ChargingStation<Fault>>().is_some());
     }
@@ -131,7 +175,7 @@ mod tests {
     #[test]
     fn test_idle_state_vehicle_detected() {
         let (actor, _) = ChargingStationFactory::create_default();
-        let actor = actor.execute("VehicleDetected", serde_json::json!({}));
+        let actor = actor.execute(operations::VEHICLE_DETECTED, serde_json::json!({}));
         // Expect transition to Connected
         assert!(actor
             .as_any()
@@ -144,11 +188,11 @@ mod tests {
         let (actor, _) = ChargingStationFactory::create_default();
         let actor = actor
             // Connect vehicle
-            .execute("VehicleDetected", serde_json::json!({}))
+            .execute(operations::VEHICLE_DETECTED, serde_json::json!({}))
             // Go to charging state
-            .input_change("InputCurrent", 10.0)
+            .input_change(slots::INPUT_CURRENT, 10.0)
             // Emulate power draw going to 1 W
-            .input_change("CurrentPowerDraw", 1.0);
+            .input_change(slots::CURRENT_POWER_DRAW, 1.0);
         // Expect final state to be Connected
         assert!(actor
             .as_any()
@@ -161,9 +205,9 @@ mod tests {
         let (actor, _) = ChargingStationFactory::create_default();
         let actor = actor
             // Connect vehicle
-            .execute("VehicleDetected", serde_json::json!({}))
+            .execute(operations::VEHICLE_DETECTED, serde_json::json!({}))
             // Go to charging state
-            .input_change("InputCurrent", 10.0);
+            .input_change(slots::INPUT_CURRENT, 10.0);
         // Expect transition to Charging
         assert!(actor
             .as_any()
@@ -176,28 +220,139 @@ mod tests {
         let (actor, _) = ChargingStationFactory::create_default();
         let actor = actor
             // Connect vehicle
-            .execute("VehicleDetected", serde_json::json!({}))
+            .execute(operations::VEHICLE_DETECTED, serde_json::json!({}))
             // Go to charging state
-            .input_change("InputCurrent", 10.0)
+            .input_change(slots::INPUT_CURRENT, 10.0)
             // Emulate overcurrent
-            .input_change("InputCurrent", 20.0);
+            .input_change(slots::INPUT_CURRENT, 20.0);
         // Expect transition to Fault
         assert!(actor.as_any().downcast_ref::<ChargingStation<Fault>>().is_some());
     }
 
+    #[test]
+    fn test_charging_state_readings_mismatch() {
+        let (actor, _) = ChargingStationFactory::create_default();
+        let actor = actor
+            .execute(operations::VEHICLE_DETECTED, serde_json::json!({}))
+            .input_change(slots::INPUT_CURRENT, 12.0)
+            .input_change(slots::CURRENT_POWER_DRAW, 40.0)
+            // Met by the CurrentWithoutPower condition of the AAS
+            .execute(
+                "ReadingsMismatch",
+                serde_json::json!({"condition": "CurrentWithoutPower"}),
+            );
+        assert!(actor.as_any().downcast_ref::<ChargingStation<Fault>>().is_some());
+    }
+
+    #[test]
+    fn test_charging_events() {
+        let (actor, _) = ChargingPointFactory::create_default();
+        assert_eq!(
+            actor.events(),
+            vec![
+                "IdlePowerFault",
+                "OvercurrentFault",
+                "ChargingComplete",
+                "ChargingCurrentRequested"
+            ]
+        );
+        let mut actor = actor
+            .execute(operations::VEHICLE_DETECTED, serde_json::json!({}))
+            .input_change(slots::INPUT_CURRENT, 10.0)
+            .input_change(slots::CURRENT_POWER_DRAW, 1.0);
+        let events = actor.take_events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].name, events::CHARGING_COMPLETE);
+        assert_eq!(events[0].payload, serde_json::json!({"power": 1.0}));
+        // Events are delivered once
+        assert!(actor.take_events().is_empty());
+
+        let mut actor = actor
+            .input_change(slots::INPUT_CURRENT, 10.0)
+            .input_change(slots::INPUT_CURRENT, 20.0);
+        let events = actor.take_events();
+        assert_eq!(events[0].name, events::OVERCURRENT_FAULT);
+        assert_eq!(events[0].payload["current"], 20.0);
+
+        let mut actor = actor
+            .execute("Reset", serde_json::json!({}))
+            .execute(operations::VEHICLE_DETECTED, serde_json::json!({}))
+            .input_change(slots::INPUT_CURRENT, 10.0)
+            .execute(
+                operations::SET_CHARGING_CURRENT,
+                serde_json::json!({"desired_current": 8.0}),
+            );
+        let events = actor.take_events();
+        assert_eq!(events[0].name, events::CHARGING_CURRENT_REQUESTED);
+        assert_eq!(events[0].payload, serde_json::json!({"current": 8.0}));
+    }
+
     #[test]
     fn test_fault_state_reset() {
         let (actor, _) = ChargingStationFactory::create_default();
         let actor = actor
             // Connect vehicle
-            .execute("VehicleDetected", serde_json::json!({}))
+            .execute(operations::VEHICLE_DETECTED, serde_json::json!({}))
             // Go to charging state
-            .input_change("InputCurrent", 10.0)
+            .input_change(slots::INPUT_CURRENT, 10.0)
             // Emulate overcurrent
-            .input_change("InputCurrent", 20.0)
+            .input_change(slots::INPUT_CURRENT, 20.0)
             // Reset fault
-            .execute("Reset", serde_json::json!({}));
+            .execute(operations::RESET, serde_json::json!({}));
         // Expect transition back to Idle
         assert!(actor.as_any().downcast_ref::<ChargingStation<Idle>>().is_some());
     }
+
+    #[test]
+    fn test_snapshot_roundtrip() {
+        let (actor, _) = ChargingStationFactory::create_with_params(serde_json::json!({"max_current": 32.0}));
+        let actor = actor.execute(operations::VEHICLE_DETECTED, serde_json::json!({}));
+        let snapshot = actor.to_snapshot().unwrap();
+        assert_eq!(snapshot["actor"], "ChargingStation");
+        assert_eq!(snapshot["state"], "Connected");
+        assert_eq!(snapshot["fields"]["max_current"], 32.0);
+
+        // Restored actor keeps state, properties and dispatch maps
+        let (restored, slots) = ChargingStationFactory::from_snapshot(snapshot).unwrap();
+        assert_eq!(slots, vec!["CurrentPowerDraw", "InputCurrent"]);
+        let restored = restored.input_change(slots::INPUT_CURRENT, 20.0);
+        assert!(restored
+            .as_any()
+            .downcast_ref::<ChargingStation<Charging>>()
+            .is_some());
+    }
+
+    #[test]
+    fn test_charging_point_regions() {
+        let (actor, slots) = ChargingPointFactory::create_default();
+        assert_eq!(slots, vec!["CurrentPowerDraw", "InputCurrent", "SignalStrength"]);
+        assert_eq!(actor.state(), "Idle|Offline");
+
+        let actor = actor
+            .execute(operations::VEHICLE_DETECTED, serde_json::json!({}))
+            .input_change(slots::SIGNAL_STRENGTH, -60.0);
+        assert_eq!(actor.state(), "Connected|Online");
+        // The commands and slots of the current states of all the regions
+        assert_eq!(actor.commands(), ["ConnectionLost", "VehicleDisconnected"]);
+        assert_eq!(actor.inputs(), ["InputCurrent", "SignalStrength"]);
+
+        let regions = actor.as_any().downcast_ref::<RegionSet>().unwrap();
+        assert!(regions
+            .region("connectivity")
+            .and_then(|r| r.as_any().downcast_ref::<Connectivity<Online>>())
+            .is_some());
+
+        // Regions are restored independently from the snapshot
+        let (restored, _) = ChargingPointFactory::from_snapshot(actor.to_snapshot().unwrap()).unwrap();
+        let restored = restored.input_change(slots::INPUT_CURRENT, 10.0);
+        assert_eq!(restored.state(), "Charging|Online");
+    }
+
+    #[test]
+    fn test_snapshot_invalid() {
+        let (actor, _) = ChargingStationFactory::create_default();
+        let mut snapshot = actor.to_snapshot().unwrap();
+        snapshot["state"] = serde_json::json!("Exploded");
+        assert!(ChargingStationFactory::from_snapshot(snapshot).is_err());
+    }
 }
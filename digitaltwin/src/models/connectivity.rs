@@ -0,0 +1,79 @@
+use digitaltwin_core::ActorStateType;
+use digitaltwin_macros::*;
+
+// Connectivity states
+
+/// Device reachable with a usable link
+#[derive(Clone, Debug)]
+pub struct Online;
+
+/// Device unreachable or with a too weak link
+#[derive(Clone, Debug)]
+pub struct Offline;
+
+/// The Connectivity actor, tracking the network link of a device
+#[actor(
+    default_state = "Offline",
+    states("Offline", "Online"),
+    slots("SignalStrength")
+)]
+pub struct Connectivity {
+    /// minimum signal strength to consider the device online [dBm]
+    #[actor_attr(default = "-90.0")]
+    min_signal: f32,
+}
+
+#[actor_state(Connectivity, Online)]
+#[dispatch_map("SignalStrength" = signal_change)]
+#[command_map("ConnectionLost" = connection_lost)]
+impl Connectivity<Online> {
+    // A too weak signal means the device is no longer reachable
+    fn signal_change(&self, signal: f32) -> Box<ActorStateType> {
+        if signal < self.min_signal {
+            self.transition::<Offline>()
+        } else {
+            self.transition::<Online>()
+        }
+    }
+
+    fn connection_lost(&self, _arg: serde_json::Value) -> Box<ActorStateType> {
+        self.transition::<Offline>()
+    }
+}
+
+#[actor_state(Connectivity, Offline)]
+#[dispatch_map("SignalStrength" = signal_change)]
+#[command_map("ConnectionRestored" = connection_restored)]
+impl Connectivity<Offline> {
+    // Any report with a good enough signal brings the device back online
+    fn signal_change(&self, signal: f32) -> Box<ActorStateType> {
+        if signal >= self.min_signal {
+            self.transition::<Online>()
+        } else {
+            self.transition::<Offline>()
+        }
+    }
+
+    fn connection_restored(&self, _arg: serde_json::Value) -> Box<ActorStateType> {
+        self.transition::<Online>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signal_change() {
+        let actor = Connectivity::<Offline>::create(-90.0);
+
+        let actor = actor.input_change("SignalStrength", -70.0);
+        assert!(actor.as_any().downcast_ref::<Connectivity<Online>>().is_some());
+
+        let actor = actor.input_change("SignalStrength", -95.0);
+        assert!(actor.as_any().downcast_ref::<Connectivity<Offline>>().is_some());
+
+        let actor = actor.execute("ConnectionRestored", serde_json::json!({}));
+        assert!(actor.as_any().downcast_ref::<Connectivity<Online>>().is_some());
+    }
+}
@@ -0,0 +1,269 @@
+use digitaltwin_core::{ActorState, ActorStateType};
+use digitaltwin_macros::*;
+
+// Door lock states
+
+/// Bolt engaged, door closed
+#[derive(Clone, Debug)]
+pub struct Locked;
+
+/// Bolt retracted
+#[derive(Clone, Debug)]
+pub struct Unlocked;
+
+/// The bolt is blocked halfway
+#[derive(Clone, Debug)]
+pub struct Jammed;
+
+/// Forced door, tamper alarm or too many wrong PINs
+#[derive(Clone, Debug)]
+pub struct Tampered;
+
+/// The DoorLock actor. Slots carry booleans ("DoorClosed", "TamperAlarm")
+/// or enumerated strings ("BoltPosition": "engaged", "retracted" or "blocked").
+#[actor(
+    default_state = "Unlocked",
+    states("Unlocked", "Locked", "Jammed", "Tampered"),
+    slots("DoorClosed", "BoltPosition", "TamperAlarm")
+)]
+pub struct DoorLock {
+    /// PIN required by the Lock, Unlock and Reset commands
+    #[actor_attr(default = "String::from(\"0000\")")]
+    pin: String,
+    /// wrong PINs accepted before going to tampered state
+    #[actor_attr(default = "3")]
+    max_attempts: u8,
+    /// wrong PINs received since the last successful command
+    #[actor_attr(default = "0")]
+    failed_attempts: u8,
+    /// latest door contact reading
+    #[actor_attr(default = "true")]
+    door_closed: bool,
+}
+
+// Helpers shared by all the states
+impl<S> DoorLock<S>
+where
+    S: Clone + Send + Sync + 'static,
+    DoorLock<S>: ActorState,
+{
+    /// Authorization hook: check the PIN in the command argument (e.g. {"pin": "1234"}).
+    /// On success, return the lock with the failed attempts counter cleared.
+    /// A wrong PIN counts as a failed attempt, and too many of them mean tampering.
+    fn authorize(&self, arg: &serde_json::Value) -> Result<Self, Box<ActorStateType>> {
+        let mut lock = self.clone();
+        let pin = match arg.get("pin") {
+            Some(serde_json::Value::String(pin)) => Some(pin.clone()),
+            Some(serde_json::Value::Number(pin)) => Some(pin.to_string()),
+            _ => None,
+        };
+        if pin.as_deref() == Some(self.pin.as_str()) {
+            lock.failed_attempts = 0;
+            return Ok(lock);
+        }
+        lock.failed_attempts = lock.failed_attempts.saturating_add(1);
+        log::warn!(
+            "Wrong PIN for door lock ({} failed attempts)",
+            lock.failed_attempts
+        );
+        if lock.failed_attempts >= self.max_attempts {
+            Err(lock.transition::<Tampered>())
+        } else {
+            Err(Box::new(lock))
+        }
+    }
+
+    fn with_door_closed(&self, closed: bool) -> Self {
+        let mut lock = self.clone();
+        lock.door_closed = closed;
+        lock
+    }
+
+    /// Follow the bolt position reported by the device
+    fn bolt_change(&self, position: String) -> Box<ActorStateType> {
+        match position.as_str() {
+            "engaged" => self.transition::<Locked>(),
+            "retracted" => self.transition::<Unlocked>(),
+            "blocked" => self.transition::<Jammed>(),
+            _ => {
+                log::warn!("Unknown bolt position: {position}");
+                Box::new(self.clone())
+            }
+        }
+    }
+
+    fn tamper_change(&self, alarm: bool) -> Box<ActorStateType> {
+        if alarm {
+            self.transition::<Tampered>()
+        } else {
+            Box::new(self.clone())
+        }
+    }
+}
+
+#[actor_state(DoorLock, Unlocked)]
+#[dispatch_map("DoorClosed" = door_change)]
+#[dispatch_map("BoltPosition" = bolt_position_change)]
+#[dispatch_map("TamperAlarm" = tamper_alarm_change)]
+#[command_map("Lock" = lock)]
+impl DoorLock<Unlocked> {
+    fn door_change(&self, closed: bool) -> Box<ActorStateType> {
+        self.with_door_closed(closed).transition::<Unlocked>()
+    }
+
+    fn bolt_position_change(&self, position: String) -> Box<ActorStateType> {
+        self.bolt_change(position)
+    }
+
+    fn tamper_alarm_change(&self, alarm: bool) -> Box<ActorStateType> {
+        self.tamper_change(alarm)
+    }
+
+    // An open door can't be locked
+    fn lock(&self, arg: serde_json::Value) -> Box<ActorStateType> {
+        match self.authorize(&arg) {
+            Ok(lock) if lock.door_closed => lock.transition::<Locked>(),
+            Ok(lock) => {
+                log::warn!("Cannot lock an open door");
+                lock.transition::<Unlocked>()
+            }
+            Err(next) => next,
+        }
+    }
+}
+
+#[actor_state(DoorLock, Locked)]
+#[dispatch_map("DoorClosed" = door_change)]
+#[dispatch_map("BoltPosition" = bolt_position_change)]
+#[dispatch_map("TamperAlarm" = tamper_alarm_change)]
+#[command_map("Unlock" = unlock)]
+impl DoorLock<Locked> {
+    // The door opening while locked means it was forced
+    fn door_change(&self, closed: bool) -> Box<ActorStateType> {
+        let lock = self.with_door_closed(closed);
+        if closed {
+            lock.transition::<Locked>()
+        } else {
+            lock.transition::<Tampered>()
+        }
+    }
+
+    fn bolt_position_change(&self, position: String) -> Box<ActorStateType> {
+        self.bolt_change(position)
+    }
+
+    fn tamper_alarm_change(&self, alarm: bool) -> Box<ActorStateType> {
+        self.tamper_change(alarm)
+    }
+
+    fn unlock(&self, arg: serde_json::Value) -> Box<ActorStateType> {
+        match self.authorize(&arg) {
+            Ok(lock) => lock.transition::<Unlocked>(),
+            Err(next) => next,
+        }
+    }
+}
+
+#[actor_state(DoorLock, Jammed)]
+#[dispatch_map("DoorClosed" = door_change)]
+#[dispatch_map("BoltPosition" = bolt_position_change)]
+#[dispatch_map("TamperAlarm" = tamper_alarm_change)]
+impl DoorLock<Jammed> {
+    fn door_change(&self, closed: bool) -> Box<ActorStateType> {
+        self.with_door_closed(closed).transition::<Jammed>()
+    }
+
+    // Wait for the bolt to be freed
+    fn bolt_position_change(&self, position: String) -> Box<ActorStateType> {
+        self.bolt_change(position)
+    }
+
+    fn tamper_alarm_change(&self, alarm: bool) -> Box<ActorStateType> {
+        self.tamper_change(alarm)
+    }
+}
+
+#[actor_state(DoorLock, Tampered)]
+#[dispatch_map("DoorClosed" = door_change)]
+#[command_map("Reset" = reset)]
+impl DoorLock<Tampered> {
+    fn door_change(&self, closed: bool) -> Box<ActorStateType> {
+        self.with_door_closed(closed).transition::<Tampered>()
+    }
+
+    // Only a valid PIN clears the alarm; wrong PINs keep the lock tampered
+    fn reset(&self, arg: serde_json::Value) -> Box<ActorStateType> {
+        match self.authorize(&arg) {
+            Ok(lock) => lock.transition::<Unlocked>(),
+            Err(_) => self.transition::<Tampered>(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use digitaltwin_core::ActorFactory;
+    use serde_json::json;
+
+    #[test]
+    fn test_lock_unlock_with_pin() {
+        let (actor, _) = DoorLockFactory::create_with_params(json!({"pin": "4321"}));
+        let actor = actor.execute("Lock", json!({"pin": "4321"}));
+        assert!(actor.as_any().downcast_ref::<DoorLock<Locked>>().is_some());
+
+        let actor = actor.execute("Unlock", json!({"pin": "0000"}));
+        assert!(actor.as_any().downcast_ref::<DoorLock<Locked>>().is_some());
+        assert_eq!(actor.to_snapshot().unwrap()["fields"]["failed_attempts"], 1);
+
+        let actor = actor.execute("Unlock", json!({"pin": "4321"}));
+        assert!(actor.as_any().downcast_ref::<DoorLock<Unlocked>>().is_some());
+        assert_eq!(actor.to_snapshot().unwrap()["fields"]["failed_attempts"], 0);
+    }
+
+    #[test]
+    fn test_cannot_lock_open_door() {
+        let (actor, _) = DoorLockFactory::create_default();
+        let actor = actor
+            .input_value("DoorClosed", false.into())
+            .execute("Lock", json!({"pin": "0000"}));
+        assert!(actor.as_any().downcast_ref::<DoorLock<Unlocked>>().is_some());
+    }
+
+    #[test]
+    fn test_tampering() {
+        let (actor, _) = DoorLockFactory::create_default();
+        let actor = actor.execute("Lock", json!({"pin": "0000"}));
+
+        // Forced door
+        let forced = actor.input_value("DoorClosed", false.into());
+        assert!(forced.as_any().downcast_ref::<DoorLock<Tampered>>().is_some());
+
+        // Too many wrong PINs
+        let actor = actor
+            .execute("Unlock", json!({"pin": "1111"}))
+            .execute("Unlock", json!({"pin": "2222"}))
+            .execute("Unlock", json!({"pin": "3333"}));
+        assert!(actor.as_any().downcast_ref::<DoorLock<Tampered>>().is_some());
+
+        let actor = actor.execute("Reset", json!({"pin": 0}));
+        assert!(actor.as_any().downcast_ref::<DoorLock<Tampered>>().is_some());
+
+        let actor = actor.execute("Reset", json!({"pin": "0000"}));
+        assert!(actor.as_any().downcast_ref::<DoorLock<Unlocked>>().is_some());
+    }
+
+    #[test]
+    fn test_bolt_position() {
+        let (actor, _) = DoorLockFactory::create_default();
+        let actor = actor.input_value("BoltPosition", "blocked".into());
+        assert!(actor.as_any().downcast_ref::<DoorLock<Jammed>>().is_some());
+
+        // Values of the wrong type are ignored
+        let actor = actor.input_change("BoltPosition", 1.0);
+        assert!(actor.as_any().downcast_ref::<DoorLock<Jammed>>().is_some());
+
+        let actor = actor.input_value("BoltPosition", "engaged".into());
+        assert!(actor.as_any().downcast_ref::<DoorLock<Locked>>().is_some());
+    }
+}
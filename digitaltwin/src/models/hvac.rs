@@ -0,0 +1,414 @@
+use digitaltwin_core::{ActorState, ActorStateType};
+use digitaltwin_macros::*;
+
+// HVAC states
+
+/// Unit switched off
+#[derive(Clone, Debug)]
+pub struct Off;
+
+/// Only ventilating (fan mode, or temperature within the setpoint band)
+#[derive(Clone, Debug)]
+pub struct Fan;
+
+/// Heat pump heating
+#[derive(Clone, Debug)]
+pub struct Heating;
+
+/// Heat pump cooling
+#[derive(Clone, Debug)]
+pub struct Cooling;
+
+/// Outdoor coil defrost cycle (heating suspended)
+#[derive(Clone, Debug)]
+pub struct Defrost;
+
+/// Device is in fault state
+#[derive(Clone, Debug)]
+pub struct Fault;
+
+// Operating modes, as set by the SetMode command
+const MODE_OFF: u8 = 0;
+const MODE_FAN: u8 = 1;
+const MODE_HEAT: u8 = 2;
+const MODE_COOL: u8 = 3;
+const MODE_AUTO: u8 = 4;
+
+// A compressor cycling more than every 6 seconds is flapping
+#[actor(
+    default_state = "Off",
+    states("Off", "Fan", "Heating", "Cooling", "Defrost", "Fault"),
+    slots("SupplyTemp", "ReturnTemp", "Power"),
+    max_transitions_per_minute = 10
+)]
+pub struct Hvac {
+    /// operating mode (0 = off, 1 = fan, 2 = heat, 3 = cool, 4 = auto)
+    #[actor_attr(default = "0")]
+    mode: u8,
+    /// target room temperature [°C]
+    #[actor_attr(default = "21.0")]
+    setpoint: f32,
+    /// half width of the band around the setpoint where the unit keeps its state [°C]
+    #[actor_attr(default = "0.5")]
+    hysteresis: f32,
+    /// latest return (room) air temperature [°C]
+    #[actor_attr(default = "21.0")]
+    return_temp: f32,
+    /// supply air temperature below which heating starts a defrost cycle [°C]
+    #[actor_attr(default = "15.0")]
+    defrost_start_temp: f32,
+    /// supply air temperature ending the defrost cycle [°C]
+    #[actor_attr(default = "25.0")]
+    defrost_end_temp: f32,
+    /// max supply air temperature [°C]
+    #[actor_attr(default = "65.0")]
+    max_supply_temp: f32,
+    /// max power draw [W]
+    #[actor_attr(default = "3500.0")]
+    max_power: f32,
+}
+
+/// Parse the argument of the SetMode command, e.g. {"mode": "heat"}
+fn parse_mode(arg: &serde_json::Value) -> Option<u8> {
+    match arg.get("mode")?.as_str()? {
+        "off" => Some(MODE_OFF),
+        "fan" => Some(MODE_FAN),
+        "heat" => Some(MODE_HEAT),
+        "cool" => Some(MODE_COOL),
+        "auto" => Some(MODE_AUTO),
+        _ => None,
+    }
+}
+
+// Helpers shared by all the states
+impl<S> Hvac<S>
+where
+    S: Clone + Send + Sync + 'static,
+    Hvac<S>: ActorState,
+{
+    /// Go to the state required by the mode and the room temperature.
+    /// Within the hysteresis band the unit goes (or stays) in state `K`.
+    fn regulate<K>(&self) -> Box<ActorStateType>
+    where
+        Hvac<K>: ActorState,
+        K: StateBehavior<Actor = Hvac<K>> + Send + Sync + 'static,
+    {
+        let can_heat = self.mode == MODE_HEAT || self.mode == MODE_AUTO;
+        let can_cool = self.mode == MODE_COOL || self.mode == MODE_AUTO;
+        let too_cold = self.return_temp < self.setpoint - self.hysteresis;
+        let too_hot = self.return_temp > self.setpoint + self.hysteresis;
+        match self.mode {
+            MODE_OFF => self.transition::<Off>(),
+            MODE_FAN => self.transition::<Fan>(),
+            _ if can_heat && too_cold => self.transition::<Heating>(),
+            _ if can_cool && too_hot => self.transition::<Cooling>(),
+            _ if !too_cold && !too_hot => self.transition::<K>(),
+            _ => self.transition::<Fan>(),
+        }
+    }
+
+    fn with_return_temp(&self, temp: f32) -> Self {
+        let mut hvac = self.clone();
+        hvac.return_temp = temp;
+        hvac
+    }
+
+    /// Apply the SetMode command, None if the argument is invalid
+    fn with_mode(&self, arg: &serde_json::Value) -> Option<Self> {
+        let Some(mode) = parse_mode(arg) else {
+            log::warn!("Invalid SetMode argument: {arg}");
+            return None;
+        };
+        let mut hvac = self.clone();
+        hvac.mode = mode;
+        Some(hvac)
+    }
+
+    /// Apply the SetSetpoint command, None if the argument is invalid
+    fn with_setpoint(&self, arg: &serde_json::Value) -> Option<Self> {
+        let Some(setpoint) = arg.get("setpoint").and_then(|s| s.as_f64()) else {
+            log::warn!("Invalid SetSetpoint argument: {arg}");
+            return None;
+        };
+        let mut hvac = self.clone();
+        hvac.setpoint = setpoint as f32;
+        Some(hvac)
+    }
+
+    /// Go to fault state on an overload, otherwise stay in state `K`
+    fn check_power<K>(&self, pwr: f32) -> Box<ActorStateType>
+    where
+        Hvac<K>: ActorState,
+        K: StateBehavior<Actor = Hvac<K>> + Send + Sync + 'static,
+    {
+        if pwr > self.max_power {
+            self.transition::<Fault>()
+        } else {
+            self.transition::<K>()
+        }
+    }
+}
+
+#[actor_state(Hvac, Off)]
+#[dispatch_map("ReturnTemp" = return_temp_change)]
+#[dispatch_map("Power" = power_change)]
+#[command_map("SetMode" = set_mode)]
+#[command_map("SetSetpoint" = set_setpoint)]
+impl Hvac<Off> {
+    fn return_temp_change(&self, temp: f32) -> Box<ActorStateType> {
+        self.with_return_temp(temp).transition::<Off>()
+    }
+
+    // The unit should not draw power when off
+    fn power_change(&self, pwr: f32) -> Box<ActorStateType> {
+        self.check_power::<Off>(pwr)
+    }
+
+    fn set_mode(&self, arg: serde_json::Value) -> Box<ActorStateType> {
+        match self.with_mode(&arg) {
+            Some(hvac) => hvac.regulate::<Fan>(),
+            None => self.transition::<Off>(),
+        }
+    }
+
+    fn set_setpoint(&self, arg: serde_json::Value) -> Box<ActorStateType> {
+        self.with_setpoint(&arg)
+            .unwrap_or_else(|| self.clone())
+            .transition::<Off>()
+    }
+}
+
+#[actor_state(Hvac, Fan)]
+#[dispatch_map("SupplyTemp" = supply_temp_change)]
+#[dispatch_map("ReturnTemp" = return_temp_change)]
+#[dispatch_map("Power" = power_change)]
+#[command_map("SetMode" = set_mode)]
+#[command_map("SetSetpoint" = set_setpoint)]
+impl Hvac<Fan> {
+    fn supply_temp_change(&self, temp: f32) -> Box<ActorStateType> {
+        if temp > self.max_supply_temp {
+            self.transition::<Fault>()
+        } else {
+            self.transition::<Fan>()
+        }
+    }
+
+    fn return_temp_change(&self, temp: f32) -> Box<ActorStateType> {
+        self.with_return_temp(temp).regulate::<Fan>()
+    }
+
+    fn power_change(&self, pwr: f32) -> Box<ActorStateType> {
+        self.check_power::<Fan>(pwr)
+    }
+
+    fn set_mode(&self, arg: serde_json::Value) -> Box<ActorStateType> {
+        self.with_mode(&arg)
+            .unwrap_or_else(|| self.clone())
+            .regulate::<Fan>()
+    }
+
+    fn set_setpoint(&self, arg: serde_json::Value) -> Box<ActorStateType> {
+        self.with_setpoint(&arg)
+            .unwrap_or_else(|| self.clone())
+            .regulate::<Fan>()
+    }
+}
+
+#[actor_state(Hvac, Heating)]
+#[dispatch_map("SupplyTemp" = supply_temp_change)]
+#[dispatch_map("ReturnTemp" = return_temp_change)]
+#[dispatch_map("Power" = power_change)]
+#[command_map("SetMode" = set_mode)]
+#[command_map("SetSetpoint" = set_setpoint)]
+impl Hvac<Heating> {
+    // A cold supply air while heating means the outdoor coil is frozen.
+    // TODO: also start defrost cycles periodically, once timers are available
+    fn supply_temp_change(&self, temp: f32) -> Box<ActorStateType> {
+        if temp > self.max_supply_temp {
+            self.transition::<Fault>()
+        } else if temp < self.defrost_start_temp {
+            self.transition::<Defrost>()
+        } else {
+            self.transition::<Heating>()
+        }
+    }
+
+    fn return_temp_change(&self, temp: f32) -> Box<ActorStateType> {
+        self.with_return_temp(temp).regulate::<Heating>()
+    }
+
+    fn power_change(&self, pwr: f32) -> Box<ActorStateType> {
+        self.check_power::<Heating>(pwr)
+    }
+
+    fn set_mode(&self, arg: serde_json::Value) -> Box<ActorStateType> {
+        self.with_mode(&arg)
+            .unwrap_or_else(|| self.clone())
+            .regulate::<Heating>()
+    }
+
+    fn set_setpoint(&self, arg: serde_json::Value) -> Box<ActorStateType> {
+        self.with_setpoint(&arg)
+            .unwrap_or_else(|| self.clone())
+            .regulate::<Heating>()
+    }
+}
+
+#[actor_state(Hvac, Cooling)]
+#[dispatch_map("SupplyTemp" = supply_temp_change)]
+#[dispatch_map("ReturnTemp" = return_temp_change)]
+#[dispatch_map("Power" = power_change)]
+#[command_map("SetMode" = set_mode)]
+#[command_map("SetSetpoint" = set_setpoint)]
+impl Hvac<Cooling> {
+    fn supply_temp_change(&self, temp: f32) -> Box<ActorStateType> {
+        if temp > self.max_supply_temp {
+            self.transition::<Fault>()
+        } else {
+            self.transition::<Cooling>()
+        }
+    }
+
+    fn return_temp_change(&self, temp: f32) -> Box<ActorStateType> {
+        self.with_return_temp(temp).regulate::<Cooling>()
+    }
+
+    fn power_change(&self, pwr: f32) -> Box<ActorStateType> {
+        self.check_power::<Cooling>(pwr)
+    }
+
+    fn set_mode(&self, arg: serde_json::Value) -> Box<ActorStateType> {
+        self.with_mode(&arg)
+            .unwrap_or_else(|| self.clone())
+            .regulate::<Cooling>()
+    }
+
+    fn set_setpoint(&self, arg: serde_json::Value) -> Box<ActorStateType> {
+        self.with_setpoint(&arg)
+            .unwrap_or_else(|| self.clone())
+            .regulate::<Cooling>()
+    }
+}
+
+#[actor_state(Hvac, Defrost)]
+#[dispatch_map("SupplyTemp" = supply_temp_change)]
+#[dispatch_map("ReturnTemp" = return_temp_change)]
+#[dispatch_map("Power" = power_change)]
+#[command_map("SetMode" = set_mode)]
+#[command_map("SetSetpoint" = set_setpoint)]
+impl Hvac<Defrost> {
+    // The cycle ends once the supply air is warm again
+    fn supply_temp_change(&self, temp: f32) -> Box<ActorStateType> {
+        if temp > self.max_supply_temp {
+            self.transition::<Fault>()
+        } else if temp >= self.defrost_end_temp {
+            self.regulate::<Heating>()
+        } else {
+            self.transition::<Defrost>()
+        }
+    }
+
+    // Regulation is suspended during the defrost cycle
+    fn return_temp_change(&self, temp: f32) -> Box<ActorStateType> {
+        self.with_return_temp(temp).transition::<Defrost>()
+    }
+
+    fn power_change(&self, pwr: f32) -> Box<ActorStateType> {
+        self.check_power::<Defrost>(pwr)
+    }
+
+    // Only switching off interrupts the defrost cycle
+    fn set_mode(&self, arg: serde_json::Value) -> Box<ActorStateType> {
+        match self.with_mode(&arg) {
+            Some(hvac) if hvac.mode == MODE_OFF => hvac.transition::<Off>(),
+            Some(hvac) => hvac.transition::<Defrost>(),
+            None => self.transition::<Defrost>(),
+        }
+    }
+
+    fn set_setpoint(&self, arg: serde_json::Value) -> Box<ActorStateType> {
+        self.with_setpoint(&arg)
+            .unwrap_or_else(|| self.clone())
+            .transition::<Defrost>()
+    }
+}
+
+#[actor_state(Hvac, Fault)]
+#[command_map("Reset" = reset)]
+impl Hvac<Fault> {
+    // Reset the fault state and switch the unit off
+    fn reset(&self, _arg: serde_json::Value) -> Box<ActorStateType> {
+        let mut hvac = self.clone();
+        hvac.mode = MODE_OFF;
+        hvac.transition::<Off>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use digitaltwin_core::ActorFactory;
+    use serde_json::json;
+
+    #[test]
+    fn test_heating_cycle() {
+        let (actor, _) = HvacFactory::create_default();
+        let actor = actor
+            .execute("SetMode", json!({"mode": "heat"}))
+            .execute("SetSetpoint", json!({"setpoint": 22.0}))
+            .input_change("ReturnTemp", 19.0);
+        assert!(actor.as_any().downcast_ref::<Hvac<Heating>>().is_some());
+
+        // Within the hysteresis band, keep heating
+        let actor = actor.input_change("ReturnTemp", 22.3);
+        assert!(actor.as_any().downcast_ref::<Hvac<Heating>>().is_some());
+
+        // Above the band, only ventilate
+        let actor = actor.input_change("ReturnTemp", 22.8);
+        assert!(actor.as_any().downcast_ref::<Hvac<Fan>>().is_some());
+        assert_eq!(actor.transition_limits().max_per_minute, Some(10));
+    }
+
+    #[test]
+    fn test_defrost() {
+        let (actor, _) = HvacFactory::create_default();
+        let actor = actor
+            .execute("SetMode", json!({"mode": "heat"}))
+            .input_change("ReturnTemp", 18.0)
+            .input_change("SupplyTemp", 10.0);
+        assert!(actor.as_any().downcast_ref::<Hvac<Defrost>>().is_some());
+
+        // Room temperature changes don't interrupt the cycle
+        let actor = actor.input_change("ReturnTemp", 17.0);
+        assert!(actor.as_any().downcast_ref::<Hvac<Defrost>>().is_some());
+
+        let actor = actor.input_change("SupplyTemp", 30.0);
+        assert!(actor.as_any().downcast_ref::<Hvac<Heating>>().is_some());
+    }
+
+    #[test]
+    fn test_auto_mode_cooling() {
+        let (actor, _) = HvacFactory::create_default();
+        let actor = actor
+            .execute("SetMode", json!({"mode": "auto"}))
+            .input_change("ReturnTemp", 26.0);
+        assert!(actor.as_any().downcast_ref::<Hvac<Cooling>>().is_some());
+
+        // Invalid arguments are ignored
+        let actor = actor.execute("SetMode", json!({"mode": "turbo"}));
+        assert!(actor.as_any().downcast_ref::<Hvac<Cooling>>().is_some());
+    }
+
+    #[test]
+    fn test_overload_fault_and_reset() {
+        let (actor, _) = HvacFactory::create_default();
+        let actor = actor
+            .execute("SetMode", json!({"mode": "fan"}))
+            .input_change("Power", 5000.0);
+        assert!(actor.as_any().downcast_ref::<Hvac<Fault>>().is_some());
+
+        let actor = actor.execute("Reset", json!({}));
+        assert!(actor.as_any().downcast_ref::<Hvac<Off>>().is_some());
+        assert_eq!(actor.to_snapshot().unwrap()["fields"]["mode"], MODE_OFF);
+    }
+}
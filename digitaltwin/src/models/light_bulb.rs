@@ -8,7 +8,7 @@ pub struct On;
 pub struct Off;
 
 /// The LightBulb actor
-#[actor(default_state = "Off", slots("CurrentPowerDraw"))]
+#[actor(default_state = "Off", states("Off", "On"), slots("CurrentPowerDraw"))]
 pub struct LightBulb {
     #[actor_attr(default = "0.5")]
     threshold: f32,
@@ -66,5 +66,9 @@ mod tests {
 
         let actor = actor.input_change("CurrentPowerDraw", 0.3);
         assert!(actor.as_any().downcast_ref::<LightBulb<Off>>().is_some());
+
+        // A value of the wrong type is ignored, with a warning
+        let actor = actor.input_value("CurrentPowerDraw", true.into());
+        assert!(actor.as_any().downcast_ref::<LightBulb<Off>>().is_some());
     }
 }
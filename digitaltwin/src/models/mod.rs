@@ -1,5 +1,117 @@
+use digitaltwin_core::{ActorFactory, ActorStateType};
+
 pub mod charging_station;
+pub mod connectivity;
+pub mod door_lock;
+pub mod hvac;
 pub mod light_bulb;
+pub mod proxy_twin;
+pub mod smart_meter;
+pub mod threshold_device;
+pub mod water_pump;
 
-pub use charging_station::ChargingStationFactory;
+pub use charging_station::{ChargingPointFactory, ChargingStationFactory};
+pub use connectivity::ConnectivityFactory;
+pub use door_lock::DoorLockFactory;
+pub use hvac::HvacFactory;
 pub use light_bulb::LightBulbFactory;
+pub use proxy_twin::ProxyTwinFactory;
+pub use smart_meter::SmartMeterFactory;
+pub use threshold_device::ThresholdDeviceFactory;
+pub use water_pump::WaterPumpFactory;
+
+/// Create an actor given the name of its registered type and its parameters
+/// (missing parameters take their default value)
+pub fn create_actor(
+    twin_type: &str,
+    params: serde_json::Value,
+) -> Option<(Box<ActorStateType>, Vec<String>)> {
+    match twin_type {
+        "LightBulb" => Some(LightBulbFactory::create_with_params(params)),
+        "ChargingStation" => Some(ChargingStationFactory::create_with_params(params)),
+        "ChargingPoint" => Some(ChargingPointFactory::create_with_params(params)),
+        "Connectivity" => Some(ConnectivityFactory::create_with_params(params)),
+        "DoorLock" => Some(DoorLockFactory::create_with_params(params)),
+        "Hvac" => Some(HvacFactory::create_with_params(params)),
+        "ProxyTwin" => Some(ProxyTwinFactory::create_with_params(params)),
+        "SmartMeter" => Some(SmartMeterFactory::create_with_params(params)),
+        "ThresholdDevice" => Some(ThresholdDeviceFactory::create_with_params(params)),
+        "WaterPump" => Some(WaterPumpFactory::create_with_params(params)),
+        _ => None,
+    }
+}
+
+/// Restore an actor of a registered type from a snapshot produced by `to_snapshot()`
+pub fn restore_actor(
+    twin_type: &str,
+    snapshot: serde_json::Value,
+) -> Result<(Box<ActorStateType>, Vec<String>), String> {
+    match twin_type {
+        "LightBulb" => LightBulbFactory::from_snapshot(snapshot),
+        "ChargingStation" => ChargingStationFactory::from_snapshot(snapshot),
+        "ChargingPoint" => ChargingPointFactory::from_snapshot(snapshot),
+        "Connectivity" => ConnectivityFactory::from_snapshot(snapshot),
+        "DoorLock" => DoorLockFactory::from_snapshot(snapshot),
+        "Hvac" => HvacFactory::from_snapshot(snapshot),
+        "ProxyTwin" => ProxyTwinFactory::from_snapshot(snapshot),
+        "SmartMeter" => SmartMeterFactory::from_snapshot(snapshot),
+        "ThresholdDevice" => ThresholdDeviceFactory::from_snapshot(snapshot),
+        "WaterPump" => WaterPumpFactory::from_snapshot(snapshot),
+        _ => Err(format!("unknown twin type {twin_type}")),
+    }
+}
+
+/// Guess the registered type from the object type found in the asset ID
+/// (e.g. "light" in "urn:aas:smart-home:light:light-bulb:id-000001")
+pub fn type_from_urn_category(category: &str) -> Option<&'static str> {
+    match category {
+        "light" => Some("LightBulb"),
+        "ev" => Some("LightBulb"), // TODO: implement EV
+        "charging-station" => Some("ChargingPoint"),
+        "door-lock" => Some("DoorLock"),
+        "hvac" => Some("Hvac"),
+        "smart-meter" => Some("SmartMeter"),
+        "water-pump" => Some("WaterPump"),
+        _ => None,
+    }
+}
+
+/// Names of the slots, sensors, operations and events of the sample twins, generated
+/// from their AAS: the tests using them break when the definitions change
+#[cfg(test)]
+pub(crate) mod aas {
+    digitaltwin_macros::aas_constants!(
+        "../twins/charger.yaml",
+        "../twins/door_lock.yaml",
+        "../twins/light_bulb.yaml",
+        "../twins/smart_meter.yaml",
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The slot of a ThresholdDevice is one of its parameters: the water heater is not checked
+    #[test]
+    fn test_sample_twin_slots() {
+        let twins = [
+            (
+                aas::home_charging_station::TWIN_TYPE,
+                aas::home_charging_station::slots::ALL,
+            ),
+            (aas::front_door_lock::TWIN_TYPE, aas::front_door_lock::slots::ALL),
+            (aas::light_bulb1::TWIN_TYPE, aas::light_bulb1::slots::ALL),
+            (aas::main_meter::TWIN_TYPE, aas::main_meter::slots::ALL),
+        ];
+        for (twin_type, declared) in twins {
+            let (_, slots) = create_actor(twin_type, serde_json::json!({})).unwrap();
+            for slot in slots {
+                assert!(
+                    declared.contains(&slot.as_str()),
+                    "slot {slot} of {twin_type} has no data source in the sample AAS"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use digitaltwin_core::{ActorFactory, ActorState, ActorStateType, SlotValue};
+use serde::{Deserialize, Serialize};
+
+/// Configuration of a proxy twin, taken from the factory parameters
+/// (the "Parameters" collection of the AAS TwinConfiguration submodel), e.g.
+/// {"state_slot": "Status", "slots": "Power, Temperature", "initial_state": "Unknown"}
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProxyConfig {
+    /// name of the input slot carrying the state reported by the external source
+    pub state_slot: String,
+    /// names of the other input slots, separated by ','
+    pub slots: String,
+    /// state until the external source reports one
+    pub initial_state: String,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        ProxyConfig {
+            state_slot: "State".to_string(),
+            slots: String::new(),
+            initial_state: "Unknown".to_string(),
+        }
+    }
+}
+
+impl ProxyConfig {
+    /// The state slot followed by the value slots
+    fn inputs(&self) -> Vec<String> {
+        std::iter::once(self.state_slot.clone())
+            .chain(
+                self.slots
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|slot| !slot.is_empty() && *slot != self.state_slot)
+                    .map(str::to_string),
+            )
+            .collect()
+    }
+}
+
+/// An asset whose state is decided elsewhere (another runtime, a Ditto thing, a cloud twin
+/// service): the proxy only tracks the latest state and values it reports, without
+/// transitions of its own, so that the asset can be queried, grouped and used in rules like
+/// the local twins. It accepts no command.
+#[derive(Clone, Debug)]
+pub struct ProxyTwin {
+    config: Arc<ProxyConfig>,
+    state: String,
+    /// latest value of each slot
+    values: BTreeMap<String, SlotValue>,
+}
+
+impl ActorState for ProxyTwin {
+    fn input_value(&self, slot: &str, value: SlotValue) -> Box<ActorStateType> {
+        let mut proxy = self.clone();
+        if slot == self.config.state_slot {
+            proxy.state = match &value {
+                SlotValue::Text(state) => state.clone(),
+                value => value.to_string(),
+            };
+        }
+        if self.inputs().iter().any(|input| input == slot) {
+            proxy.values.insert(slot.to_string(), value);
+        }
+        Box::new(proxy)
+    }
+
+    fn execute(&self, command: &str, _input: serde_json::Value) -> Box<ActorStateType> {
+        log::warn!("Command {command} not handled by a proxy twin, which is read-only");
+        Box::new(self.clone())
+    }
+
+    fn inputs(&self) -> Vec<String> {
+        self.config.inputs()
+    }
+
+    fn to_snapshot(&self) -> Result<serde_json::Value, String> {
+        Ok(serde_json::json!({
+            "actor": self.type_name(),
+            "state": self.state(),
+            "config": self.config.as_ref(),
+            "fields": { "values": self.values },
+        }))
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn type_name(&self) -> String {
+        "ProxyTwin".to_string()
+    }
+
+    fn state(&self) -> String {
+        self.state.clone()
+    }
+}
+
+pub struct ProxyTwinFactory;
+
+impl ProxyTwinFactory {
+    fn create(config: ProxyConfig) -> (Box<ActorStateType>, Vec<String>) {
+        let slots = config.inputs();
+        let proxy = ProxyTwin {
+            state: config.initial_state.clone(),
+            config: Arc::new(config),
+            values: BTreeMap::new(),
+        };
+        (Box::new(proxy), slots)
+    }
+}
+
+impl ActorFactory for ProxyTwinFactory {
+    fn create_default() -> (Box<ActorStateType>, Vec<String>) {
+        Self::create(ProxyConfig::default())
+    }
+
+    fn create_with_params(params: serde_json::Value) -> (Box<ActorStateType>, Vec<String>) {
+        let config = if params.is_null() {
+            ProxyConfig::default()
+        } else {
+            serde_json::from_value(params).unwrap_or_else(|e| {
+                log::warn!("Invalid proxy twin parameters, using defaults: {e}");
+                ProxyConfig::default()
+            })
+        };
+        Self::create(config)
+    }
+
+    fn from_snapshot(snapshot: serde_json::Value) -> Result<(Box<ActorStateType>, Vec<String>), String> {
+        if snapshot.get("actor").and_then(|a| a.as_str()) != Some("ProxyTwin") {
+            return Err("snapshot is not a ProxyTwin".to_string());
+        }
+        let config: ProxyConfig = serde_json::from_value(snapshot.get("config").cloned().unwrap_or_default())
+            .map_err(|e| e.to_string())?;
+        let state = snapshot["state"]
+            .as_str()
+            .ok_or("snapshot has no state")?
+            .to_string();
+        let values =
+            serde_json::from_value(snapshot["fields"]["values"].clone()).map_err(|e| e.to_string())?;
+        let slots = config.inputs();
+        let proxy = ProxyTwin {
+            config: Arc::new(config),
+            state,
+            values,
+        };
+        Ok((Box::new(proxy), slots))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn remote_pump() -> (Box<ActorStateType>, Vec<String>) {
+        ProxyTwinFactory::create_with_params(json!({
+            "state_slot": "Status",
+            "slots": "Power, Pressure",
+        }))
+    }
+
+    #[test]
+    fn test_reported_state() {
+        let (actor, slots) = remote_pump();
+        assert_eq!(slots, ["Status", "Power", "Pressure"]);
+        assert_eq!(actor.state(), "Unknown");
+        assert!(actor.commands().is_empty());
+
+        let actor = actor.input_value("Status", "Running".into());
+        assert_eq!(actor.state(), "Running");
+        // The values do not change the state, and commands are ignored
+        let actor = actor.input_change("Power", 1.5);
+        let actor = actor.execute("Stop", json!({}));
+        assert_eq!(actor.state(), "Running");
+        let actor = actor.input_value("Status", SlotValue::Number(3.0));
+        assert_eq!(actor.state(), "3");
+    }
+
+    #[test]
+    fn test_snapshot_roundtrip() {
+        let (actor, _) = remote_pump();
+        let actor = actor.input_value("Status", "Running".into());
+        let actor = actor.input_change("Pressure", 2.5);
+        let (restored, slots) = ProxyTwinFactory::from_snapshot(actor.to_snapshot().unwrap()).unwrap();
+        assert_eq!(slots, ["Status", "Power", "Pressure"]);
+        assert_eq!(restored.state(), "Running");
+        assert_eq!(restored.to_snapshot().unwrap(), actor.to_snapshot().unwrap());
+    }
+}
@@ -0,0 +1,269 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use digitaltwin_core::{ActorState, ActorStateType};
+use digitaltwin_macros::*;
+
+// Smart meter states
+
+/// Grid parameters within limits
+#[derive(Clone, Debug)]
+pub struct Normal;
+
+/// Supply voltage above the allowed maximum
+#[derive(Clone, Debug)]
+pub struct OverVoltage;
+
+/// Grid frequency below the allowed minimum
+#[derive(Clone, Debug)]
+pub struct UnderFrequency;
+
+/// No supply voltage (or no reading yet)
+#[derive(Clone, Debug)]
+pub struct Offline;
+
+#[actor(
+    default_state = "Offline",
+    states("Offline", "Normal", "OverVoltage", "UnderFrequency"),
+    slots("ActivePower", "Voltage", "Frequency")
+)]
+pub struct SmartMeter {
+    /// max supply voltage [V]
+    #[actor_attr(default = "253.0")]
+    max_voltage: f32,
+    /// min supply voltage, below which the meter is considered offline [V]
+    #[actor_attr(default = "100.0")]
+    min_voltage: f32,
+    /// min grid frequency [Hz]
+    #[actor_attr(default = "49.5")]
+    min_frequency: f32,
+    /// latest voltage reading [V]
+    #[actor_attr(default = "0.0")]
+    voltage: f32,
+    /// latest frequency reading [Hz]
+    #[actor_attr(default = "50.0")]
+    frequency: f32,
+    /// latest active power reading [W]
+    #[actor_attr(default = "0.0")]
+    power: f64,
+    /// time of the latest active power reading, 0 if none [s since the Unix epoch]
+    #[actor_attr(default = "0.0")]
+    power_timestamp: f64,
+    /// energy consumed since the last counter reset [kWh]
+    #[actor_attr(default = "0.0")]
+    energy: f64,
+}
+
+/// Current time in seconds since the Unix epoch
+fn now_seconds() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or_default()
+}
+
+// Helpers shared by all the states
+impl<S> SmartMeter<S>
+where
+    S: Clone + Send + Sync + 'static,
+    SmartMeter<S>: ActorState,
+{
+    /// Integrate the previous power reading up to `now`, then record the new one
+    fn accumulate_at(&self, power: f64, now: f64) -> Self {
+        let mut meter = self.clone();
+        if self.power_timestamp > 0.0 && now > self.power_timestamp {
+            let hours = (now - self.power_timestamp) / 3600.0;
+            meter.energy += self.power * hours / 1000.0;
+        }
+        meter.power = power;
+        meter.power_timestamp = now;
+        meter
+    }
+
+    fn with_voltage(&self, voltage: f32) -> Self {
+        let mut meter = self.clone();
+        meter.voltage = voltage;
+        meter
+    }
+
+    fn with_frequency(&self, frequency: f32) -> Self {
+        let mut meter = self.clone();
+        meter.frequency = frequency;
+        meter
+    }
+
+    fn with_energy_reset(&self) -> Self {
+        let mut meter = self.clone();
+        meter.energy = 0.0;
+        meter
+    }
+
+    /// Go to the state matching the latest voltage and frequency readings
+    fn evaluate(&self) -> Box<ActorStateType> {
+        if self.voltage < self.min_voltage {
+            // Stop integrating: the outage must not be accounted for
+            let mut meter = self.clone();
+            meter.power = 0.0;
+            meter.power_timestamp = 0.0;
+            meter.transition::<Offline>()
+        } else if self.voltage > self.max_voltage {
+            self.transition::<OverVoltage>()
+        } else if self.frequency < self.min_frequency {
+            self.transition::<UnderFrequency>()
+        } else {
+            self.transition::<Normal>()
+        }
+    }
+}
+
+#[actor_state(SmartMeter, Normal)]
+#[dispatch_map("ActivePower" = power_change)]
+#[dispatch_map("Voltage" = voltage_change)]
+#[dispatch_map("Frequency" = frequency_change)]
+#[command_map("ResetCounters" = reset_counters)]
+impl SmartMeter<Normal> {
+    fn power_change(&self, pwr: f32) -> Box<ActorStateType> {
+        self.accumulate_at(pwr as f64, now_seconds())
+            .transition::<Normal>()
+    }
+
+    fn voltage_change(&self, voltage: f32) -> Box<ActorStateType> {
+        self.with_voltage(voltage).evaluate()
+    }
+
+    fn frequency_change(&self, frequency: f32) -> Box<ActorStateType> {
+        self.with_frequency(frequency).evaluate()
+    }
+
+    fn reset_counters(&self, _arg: serde_json::Value) -> Box<ActorStateType> {
+        self.with_energy_reset().transition::<Normal>()
+    }
+}
+
+#[actor_state(SmartMeter, OverVoltage)]
+#[dispatch_map("ActivePower" = power_change)]
+#[dispatch_map("Voltage" = voltage_change)]
+#[dispatch_map("Frequency" = frequency_change)]
+#[command_map("ResetCounters" = reset_counters)]
+impl SmartMeter<OverVoltage> {
+    fn power_change(&self, pwr: f32) -> Box<ActorStateType> {
+        self.accumulate_at(pwr as f64, now_seconds())
+            .transition::<OverVoltage>()
+    }
+
+    fn voltage_change(&self, voltage: f32) -> Box<ActorStateType> {
+        self.with_voltage(voltage).evaluate()
+    }
+
+    fn frequency_change(&self, frequency: f32) -> Box<ActorStateType> {
+        self.with_frequency(frequency).evaluate()
+    }
+
+    fn reset_counters(&self, _arg: serde_json::Value) -> Box<ActorStateType> {
+        self.with_energy_reset().transition::<OverVoltage>()
+    }
+}
+
+#[actor_state(SmartMeter, UnderFrequency)]
+#[dispatch_map("ActivePower" = power_change)]
+#[dispatch_map("Voltage" = voltage_change)]
+#[dispatch_map("Frequency" = frequency_change)]
+#[command_map("ResetCounters" = reset_counters)]
+impl SmartMeter<UnderFrequency> {
+    fn power_change(&self, pwr: f32) -> Box<ActorStateType> {
+        self.accumulate_at(pwr as f64, now_seconds())
+            .transition::<UnderFrequency>()
+    }
+
+    fn voltage_change(&self, voltage: f32) -> Box<ActorStateType> {
+        self.with_voltage(voltage).evaluate()
+    }
+
+    fn frequency_change(&self, frequency: f32) -> Box<ActorStateType> {
+        self.with_frequency(frequency).evaluate()
+    }
+
+    fn reset_counters(&self, _arg: serde_json::Value) -> Box<ActorStateType> {
+        self.with_energy_reset().transition::<UnderFrequency>()
+    }
+}
+
+// Power readings are ignored while offline
+#[actor_state(SmartMeter, Offline)]
+#[dispatch_map("Voltage" = voltage_change)]
+#[dispatch_map("Frequency" = frequency_change)]
+#[command_map("ResetCounters" = reset_counters)]
+impl SmartMeter<Offline> {
+    fn voltage_change(&self, voltage: f32) -> Box<ActorStateType> {
+        self.with_voltage(voltage).evaluate()
+    }
+
+    fn frequency_change(&self, frequency: f32) -> Box<ActorStateType> {
+        self.with_frequency(frequency).transition::<Offline>()
+    }
+
+    fn reset_counters(&self, _arg: serde_json::Value) -> Box<ActorStateType> {
+        self.with_energy_reset().transition::<Offline>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use digitaltwin_core::ActorFactory;
+
+    fn energy(actor: &ActorStateType) -> f64 {
+        actor.to_snapshot().unwrap()["fields"]["energy"].as_f64().unwrap()
+    }
+
+    #[test]
+    fn test_grid_states() {
+        let (actor, _) = SmartMeterFactory::create_default();
+        assert_eq!(actor.state(), "Offline");
+
+        let actor = actor.input_change("Voltage", 230.0);
+        assert!(actor.as_any().downcast_ref::<SmartMeter<Normal>>().is_some());
+
+        let actor = actor.input_change("Voltage", 260.0);
+        assert!(actor.as_any().downcast_ref::<SmartMeter<OverVoltage>>().is_some());
+
+        // Over voltage takes precedence over under frequency
+        let actor = actor.input_change("Frequency", 49.0);
+        assert!(actor.as_any().downcast_ref::<SmartMeter<OverVoltage>>().is_some());
+
+        let actor = actor.input_change("Voltage", 231.0);
+        assert!(actor
+            .as_any()
+            .downcast_ref::<SmartMeter<UnderFrequency>>()
+            .is_some());
+
+        let actor = actor.input_change("Frequency", 50.0).input_change("Voltage", 0.0);
+        assert!(actor.as_any().downcast_ref::<SmartMeter<Offline>>().is_some());
+    }
+
+    #[test]
+    fn test_energy_accumulation() {
+        let meter = SmartMeter::<Offline>::create(253.0, 100.0, 49.5, 230.0, 50.0, 0.0, 0.0, 0.0);
+        let meter = meter.as_any().downcast_ref::<SmartMeter<Offline>>().unwrap();
+
+        // 2 kW for one hour, then 1 kW for half an hour
+        let meter = meter
+            .accumulate_at(2000.0, 1000.0)
+            .accumulate_at(1000.0, 4600.0)
+            .accumulate_at(0.0, 6400.0);
+        assert!((meter.energy - 2.5).abs() < 1e-9);
+
+        let actor = meter.execute("ResetCounters", serde_json::json!({}));
+        assert_eq!(energy(actor.as_ref()), 0.0);
+        assert!(actor.as_any().downcast_ref::<SmartMeter<Offline>>().is_some());
+    }
+
+    #[test]
+    fn test_power_ignored_offline() {
+        let (actor, _) = SmartMeterFactory::create_default();
+        let actor = actor
+            .input_change("ActivePower", 3000.0)
+            .input_change("ActivePower", 3000.0);
+        assert_eq!(energy(actor.as_ref()), 0.0);
+        assert_eq!(actor.to_snapshot().unwrap()["fields"]["power_timestamp"], 0.0);
+    }
+}
@@ -0,0 +1,295 @@
+use std::sync::Arc;
+
+use digitaltwin_core::{
+    ActorFactory, ActorState, ActorStateType, DeclaredTransition, SlotValue, StateMachine,
+};
+use serde::{Deserialize, Serialize};
+
+/// Configuration of a threshold device, taken from the factory parameters
+/// (the "Parameters" collection of the AAS TwinConfiguration submodel), e.g.
+/// {"slot": "Temperature", "on_above": 60.0, "off_below": 55.0, "fault_above": 90.0,
+///  "states": {"off": "Idle", "on": "Heating", "fault": "Overheat"}}
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ThresholdConfig {
+    /// name of the input slot
+    pub slot: String,
+    /// value above which the device turns on
+    pub on_above: f64,
+    /// value below which the device turns off
+    pub off_below: f64,
+    /// value above which the device goes in fault state, if any
+    pub fault_above: Option<f64>,
+    /// names of the states
+    pub states: StateNames,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StateNames {
+    pub off: String,
+    pub on: String,
+    pub fault: String,
+}
+
+impl Default for ThresholdConfig {
+    fn default() -> Self {
+        ThresholdConfig {
+            slot: "Value".to_string(),
+            on_above: 1.0,
+            off_below: 0.0,
+            fault_above: None,
+            states: StateNames::default(),
+        }
+    }
+}
+
+impl Default for StateNames {
+    fn default() -> Self {
+        StateNames {
+            off: "Off".to_string(),
+            on: "On".to_string(),
+            fault: "Fault".to_string(),
+        }
+    }
+}
+
+/// The states of a threshold device. Their names are only known at runtime,
+/// so the device is a plain struct instead of a typestate actor.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+enum Level {
+    Off,
+    On,
+    Fault,
+}
+
+/// Generic device that is "on above X, off below Y, fault above Z" on a single
+/// slot; the fault state is latched until the Reset command.
+#[derive(Clone, Debug)]
+pub struct ThresholdDevice {
+    config: Arc<ThresholdConfig>,
+    level: Level,
+    /// latest reading of the slot
+    value: f64,
+}
+
+impl ThresholdDevice {
+    fn with(&self, level: Level, value: f64) -> Box<ActorStateType> {
+        Box::new(ThresholdDevice {
+            config: self.config.clone(),
+            level,
+            value,
+        })
+    }
+
+    /// Compute the next level given a new reading (hysteresis between off_below and on_above)
+    fn next_level(&self, value: f64) -> Level {
+        match (self.level, self.config.fault_above) {
+            (Level::Fault, _) => Level::Fault,
+            (_, Some(fault_above)) if value > fault_above => Level::Fault,
+            (Level::Off, _) if value > self.config.on_above => Level::On,
+            (Level::On, _) if value < self.config.off_below => Level::Off,
+            (level, _) => level,
+        }
+    }
+}
+
+impl ActorState for ThresholdDevice {
+    fn input_value(&self, slot: &str, value: SlotValue) -> Box<ActorStateType> {
+        match value {
+            SlotValue::Number(value) if slot == self.config.slot => self.with(self.next_level(value), value),
+            _ => Box::new(self.clone()),
+        }
+    }
+
+    fn execute(&self, command: &str, _input: serde_json::Value) -> Box<ActorStateType> {
+        match (command, self.level) {
+            // Restart from the off state, the next reading brings the device where it belongs
+            ("Reset", Level::Fault) => self.with(Level::Off, self.value),
+            _ => {
+                log::warn!("Command {command} not handled in state {}", self.state());
+                Box::new(self.clone())
+            }
+        }
+    }
+
+    fn commands(&self) -> Vec<String> {
+        match self.level {
+            Level::Fault => vec!["Reset".to_string()],
+            _ => Vec::new(),
+        }
+    }
+
+    fn inputs(&self) -> Vec<String> {
+        vec![self.config.slot.clone()]
+    }
+
+    fn state_machines(&self) -> Vec<StateMachine> {
+        let names = &self.config.states;
+        let slot = &self.config.slot;
+        let transition = |from: &String, to: &String, trigger: &String| DeclaredTransition {
+            from: from.clone(),
+            to: to.clone(),
+            trigger: trigger.clone(),
+        };
+        let mut states = vec![names.off.clone(), names.on.clone()];
+        let mut transitions = vec![
+            transition(&names.off, &names.on, slot),
+            transition(&names.on, &names.off, slot),
+        ];
+        if self.config.fault_above.is_some() {
+            states.push(names.fault.clone());
+            transitions.push(transition(&names.off, &names.fault, slot));
+            transitions.push(transition(&names.on, &names.fault, slot));
+            transitions.push(transition(&names.fault, &names.off, &"Reset".to_string()));
+        }
+        vec![StateMachine {
+            region: None,
+            states,
+            transitions,
+            current: self.state(),
+        }]
+    }
+
+    fn to_snapshot(&self) -> Result<serde_json::Value, String> {
+        Ok(serde_json::json!({
+            "actor": self.type_name(),
+            "state": self.state(),
+            "level": self.level,
+            "config": self.config.as_ref(),
+            "fields": { "value": self.value },
+        }))
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn type_name(&self) -> String {
+        "ThresholdDevice".to_string()
+    }
+
+    fn state(&self) -> String {
+        match self.level {
+            Level::Off => self.config.states.off.clone(),
+            Level::On => self.config.states.on.clone(),
+            Level::Fault => self.config.states.fault.clone(),
+        }
+    }
+}
+
+pub struct ThresholdDeviceFactory;
+
+impl ThresholdDeviceFactory {
+    fn create(config: ThresholdConfig) -> (Box<ActorStateType>, Vec<String>) {
+        if config.off_below > config.on_above {
+            log::warn!(
+                "Threshold device on {}: off_below ({}) is above on_above ({})",
+                config.slot,
+                config.off_below,
+                config.on_above
+            );
+        }
+        let slots = vec![config.slot.clone()];
+        let device = ThresholdDevice {
+            config: Arc::new(config),
+            level: Level::Off,
+            value: 0.0,
+        };
+        (Box::new(device), slots)
+    }
+}
+
+impl ActorFactory for ThresholdDeviceFactory {
+    fn create_default() -> (Box<ActorStateType>, Vec<String>) {
+        Self::create(ThresholdConfig::default())
+    }
+
+    fn create_with_params(params: serde_json::Value) -> (Box<ActorStateType>, Vec<String>) {
+        let config = if params.is_null() {
+            ThresholdConfig::default()
+        } else {
+            serde_json::from_value(params).unwrap_or_else(|e| {
+                log::warn!("Invalid threshold device parameters, using defaults: {e}");
+                ThresholdConfig::default()
+            })
+        };
+        Self::create(config)
+    }
+
+    fn from_snapshot(snapshot: serde_json::Value) -> Result<(Box<ActorStateType>, Vec<String>), String> {
+        if snapshot.get("actor").and_then(|a| a.as_str()) != Some("ThresholdDevice") {
+            return Err("snapshot is not a ThresholdDevice".to_string());
+        }
+        let config: ThresholdConfig =
+            serde_json::from_value(snapshot.get("config").cloned().unwrap_or_default())
+                .map_err(|e| e.to_string())?;
+        let level: Level = serde_json::from_value(snapshot.get("level").cloned().unwrap_or_default())
+            .map_err(|e| e.to_string())?;
+        let value = snapshot["fields"]["value"].as_f64().unwrap_or_default();
+        let slots = vec![config.slot.clone()];
+        let device = ThresholdDevice {
+            config: Arc::new(config),
+            level,
+            value,
+        };
+        Ok((Box::new(device), slots))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn heater() -> (Box<ActorStateType>, Vec<String>) {
+        ThresholdDeviceFactory::create_with_params(json!({
+            "slot": "Temperature",
+            "on_above": 60.0,
+            "off_below": 55.0,
+            "fault_above": 90.0,
+            "states": {"off": "Idle", "on": "Heating", "fault": "Overheat"}
+        }))
+    }
+
+    #[test]
+    fn test_hysteresis() {
+        let (actor, slots) = heater();
+        assert_eq!(slots, vec!["Temperature".to_string()]);
+        assert_eq!(actor.state(), "Idle");
+
+        let actor = actor.input_change("Temperature", 61.0);
+        assert_eq!(actor.state(), "Heating");
+        let actor = actor.input_change("Temperature", 57.0);
+        assert_eq!(actor.state(), "Heating");
+        let actor = actor.input_change("Temperature", 54.0);
+        assert_eq!(actor.state(), "Idle");
+
+        // Other slots are ignored
+        let actor = actor.input_change("Pressure", 100.0);
+        assert_eq!(actor.state(), "Idle");
+    }
+
+    #[test]
+    fn test_fault_latched() {
+        let (actor, _) = heater();
+        assert!(actor.commands().is_empty());
+        let actor = actor.input_change("Temperature", 95.0);
+        assert_eq!(actor.state(), "Overheat");
+        assert_eq!(actor.commands(), ["Reset"]);
+        let actor = actor.input_change("Temperature", 20.0);
+        assert_eq!(actor.state(), "Overheat");
+        let actor = actor.execute("Reset", json!({}));
+        assert_eq!(actor.state(), "Idle");
+    }
+
+    #[test]
+    fn test_snapshot_roundtrip() {
+        let (actor, _) = heater();
+        let actor = actor.input_change("Temperature", 70.0);
+        let (restored, slots) = ThresholdDeviceFactory::from_snapshot(actor.to_snapshot().unwrap()).unwrap();
+        assert_eq!(slots, vec!["Temperature".to_string()]);
+        assert_eq!(restored.state(), "Heating");
+        assert_eq!(restored.to_snapshot().unwrap(), actor.to_snapshot().unwrap());
+    }
+}
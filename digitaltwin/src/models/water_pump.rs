@@ -0,0 +1,213 @@
+use digitaltwin_core::{ActorState, ActorStateType};
+use digitaltwin_macros::*;
+
+// Water pump states
+
+/// Motor stopped
+#[derive(Clone, Debug)]
+pub struct Idle;
+
+/// Motor running, pumping water
+#[derive(Clone, Debug)]
+pub struct Running;
+
+/// Motor running without water (dry-run protection tripped)
+#[derive(Clone, Debug)]
+pub struct DryRun;
+
+/// Motor current above the allowed maximum
+#[derive(Clone, Debug)]
+pub struct Overload;
+
+#[actor(
+    default_state = "Idle",
+    states("Idle", "Running", "DryRun", "Overload"),
+    slots("FlowRate", "Current", "Pressure")
+)]
+pub struct WaterPump {
+    /// motor current above which the pump is considered running [A]
+    #[actor_attr(default = "0.5")]
+    min_running_current: f32,
+    /// max motor current [A]
+    #[actor_attr(default = "10.0")]
+    max_current: f32,
+    /// flow rate below which there may be no water [l/min]
+    #[actor_attr(default = "1.0")]
+    min_flow_rate: f32,
+    /// outlet pressure below which there may be no water [bar]
+    #[actor_attr(default = "0.3")]
+    min_pressure: f32,
+}
+
+// Helpers shared by all the states
+impl<S> WaterPump<S>
+where
+    S: Clone + Send + Sync + 'static,
+    WaterPump<S>: ActorState,
+{
+    /// Latest reading of a slot, from the context of the twin
+    fn reading(&self, slot: &str) -> f32 {
+        self.context().last(slot).unwrap_or(0.0)
+    }
+
+    fn motor_running(&self) -> bool {
+        self.reading("Current") >= self.min_running_current
+    }
+
+    /// Derived from two slots: a single low reading is not enough to tell that the
+    /// pump is dry, since the flow is low against a closed valve and the pressure
+    /// is low when pumping into an open outlet
+    fn water_present(&self) -> bool {
+        self.reading("FlowRate") >= self.min_flow_rate || self.reading("Pressure") >= self.min_pressure
+    }
+
+    /// Go to the state matching the latest readings
+    fn evaluate(&self) -> Box<ActorStateType> {
+        if self.reading("Current") > self.max_current {
+            self.transition::<Overload>()
+        } else if !self.motor_running() {
+            self.transition::<Idle>()
+        } else if !self.water_present() {
+            self.transition::<DryRun>()
+        } else {
+            self.transition::<Running>()
+        }
+    }
+}
+
+#[actor_state(WaterPump, Idle)]
+#[dispatch_map("FlowRate" = reading_change)]
+#[dispatch_map("Current" = reading_change)]
+#[dispatch_map("Pressure" = reading_change)]
+impl WaterPump<Idle> {
+    fn reading_change(&self, _reading: f32) -> Box<ActorStateType> {
+        self.evaluate()
+    }
+}
+
+#[actor_state(WaterPump, Running)]
+#[dispatch_map("FlowRate" = reading_change)]
+#[dispatch_map("Current" = reading_change)]
+#[dispatch_map("Pressure" = reading_change)]
+impl WaterPump<Running> {
+    fn reading_change(&self, _reading: f32) -> Box<ActorStateType> {
+        self.evaluate()
+    }
+}
+
+// Dry-run protection is latched: water coming back does not restart the pump,
+// it must be stopped or reset
+#[actor_state(WaterPump, DryRun)]
+#[dispatch_map("FlowRate" = water_change)]
+#[dispatch_map("Current" = current_change)]
+#[dispatch_map("Pressure" = water_change)]
+#[command_map("Reset" = reset)]
+impl WaterPump<DryRun> {
+    fn water_change(&self, _reading: f32) -> Box<ActorStateType> {
+        self.transition::<DryRun>()
+    }
+
+    fn current_change(&self, current: f32) -> Box<ActorStateType> {
+        if self.motor_running() && current <= self.max_current {
+            self.transition::<DryRun>()
+        } else {
+            self.evaluate()
+        }
+    }
+
+    fn reset(&self, _arg: serde_json::Value) -> Box<ActorStateType> {
+        self.evaluate()
+    }
+}
+
+// Overload is latched until reset
+#[actor_state(WaterPump, Overload)]
+#[dispatch_map("FlowRate" = reading_change)]
+#[dispatch_map("Current" = reading_change)]
+#[dispatch_map("Pressure" = reading_change)]
+#[command_map("Reset" = reset)]
+impl WaterPump<Overload> {
+    fn reading_change(&self, _reading: f32) -> Box<ActorStateType> {
+        self.transition::<Overload>()
+    }
+
+    fn reset(&self, _arg: serde_json::Value) -> Box<ActorStateType> {
+        self.evaluate()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use digitaltwin_core::{ActorFactory, SlotCache};
+    use serde_json::json;
+
+    /// A pump reading its slots from the cache of a twin
+    fn pump() -> (Box<ActorStateType>, SlotCache) {
+        let (mut actor, _) = WaterPumpFactory::create_default();
+        let cache = SlotCache::new();
+        actor.set_context(cache.context());
+        (actor, cache)
+    }
+
+    fn feed(cache: &SlotCache, actor: Box<ActorStateType>, readings: &[(&str, f32)]) -> Box<ActorStateType> {
+        readings.iter().fold(actor, |actor, (slot, reading)| {
+            cache.feed(actor.as_ref(), slot, (*reading).into(), Utc::now())
+        })
+    }
+
+    #[test]
+    fn test_running() {
+        let (actor, cache) = pump();
+        let actor = feed(
+            &cache,
+            actor,
+            &[("FlowRate", 20.0), ("Pressure", 2.0), ("Current", 4.0)],
+        );
+        assert!(actor.as_any().downcast_ref::<WaterPump<Running>>().is_some());
+
+        let actor = feed(&cache, actor, &[("Current", 0.0)]);
+        assert!(actor.as_any().downcast_ref::<WaterPump<Idle>>().is_some());
+
+        // Without the cache of a twin, the pump has no readings of the other slots
+        let (actor, _) = WaterPumpFactory::create_default();
+        let actor = actor.input_change("Current", 4.0);
+        assert!(actor.as_any().downcast_ref::<WaterPump<Idle>>().is_some());
+    }
+
+    #[test]
+    fn test_dry_run_needs_both_readings() {
+        let (actor, cache) = pump();
+        let actor = feed(
+            &cache,
+            actor,
+            &[("FlowRate", 20.0), ("Pressure", 2.0), ("Current", 4.0)],
+        );
+
+        // Closed valve: no flow, but the pressure is there
+        let actor = feed(&cache, actor, &[("FlowRate", 0.0)]);
+        assert!(actor.as_any().downcast_ref::<WaterPump<Running>>().is_some());
+
+        let actor = feed(&cache, actor, &[("Pressure", 0.0)]);
+        assert!(actor.as_any().downcast_ref::<WaterPump<DryRun>>().is_some());
+
+        // Latched until the motor stops or the pump is reset
+        let actor = feed(&cache, actor, &[("Pressure", 2.0)]);
+        assert!(actor.as_any().downcast_ref::<WaterPump<DryRun>>().is_some());
+        let actor = actor.execute("Reset", json!({}));
+        assert!(actor.as_any().downcast_ref::<WaterPump<Running>>().is_some());
+    }
+
+    #[test]
+    fn test_overload() {
+        let (actor, cache) = pump();
+        let actor = feed(&cache, actor, &[("Current", 12.0)]);
+        assert!(actor.as_any().downcast_ref::<WaterPump<Overload>>().is_some());
+
+        let actor = feed(&cache, actor, &[("Current", 0.0)]);
+        assert!(actor.as_any().downcast_ref::<WaterPump<Overload>>().is_some());
+        let actor = actor.execute("Reset", json!({}));
+        assert!(actor.as_any().downcast_ref::<WaterPump<Idle>>().is_some());
+    }
+}
@@ -0,0 +1,250 @@
+use bytes::Bytes;
+use clap::ValueEnum;
+use rumqttc::{v5, AsyncClient, Event, EventLoop, MqttOptions, Packet, QoS, SubscribeFilter};
+use thiserror::Error as ThisError;
+
+/// MQTT protocol version of the connection of the receiver
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MqttVersion {
+    #[default]
+    #[value(name = "3.1.1")]
+    V311,
+    #[value(name = "5")]
+    V5,
+}
+
+/// Errors of either protocol version, boxed as they carry the requests that failed
+#[derive(ThisError, Debug)]
+pub enum LinkError {
+    #[error(transparent)]
+    Client(#[from] Box<rumqttc::ClientError>),
+    #[error(transparent)]
+    Connection(#[from] Box<rumqttc::ConnectionError>),
+    #[error(transparent)]
+    ClientV5(#[from] Box<v5::ClientError>),
+    #[error(transparent)]
+    ConnectionV5(#[from] Box<v5::ConnectionError>),
+}
+
+/// Metadata of a received message, only carried by MQTT 5. The dedicated properties take
+/// precedence over the user properties with the same name.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MessageProperties {
+    /// Content type ("content-type" user property)
+    pub content_type: Option<String>,
+    /// Correlation data, as UTF-8 ("correlation-id" user property)
+    pub correlation_id: Option<String>,
+}
+
+impl MessageProperties {
+    fn from_v5(properties: Option<v5::mqttbytes::v5::PublishProperties>) -> Self {
+        let Some(properties) = properties else {
+            return MessageProperties::default();
+        };
+        let user_property = |name: &str| {
+            properties
+                .user_properties
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.clone())
+        };
+        MessageProperties {
+            content_type: properties
+                .content_type
+                .clone()
+                .or_else(|| user_property("content-type")),
+            correlation_id: properties
+                .correlation_data
+                .as_ref()
+                .map(|data| String::from_utf8_lossy(data).into_owned())
+                .or_else(|| user_property("correlation-id")),
+        }
+    }
+}
+
+/// A message received from the broker
+#[derive(Debug, Clone)]
+pub struct IncomingMessage {
+    pub topic: String,
+    pub payload: Bytes,
+    pub properties: MessageProperties,
+}
+
+/// What the connection reports, whatever the protocol version
+#[derive(Debug)]
+pub enum LinkEvent {
+    ConnAck,
+    PubAck,
+    Publish(IncomingMessage),
+    /// Any other packet, described for the trace log
+    Other(String),
+}
+
+/// Client of a connection to the broker, in either protocol version
+#[derive(Clone)]
+pub enum MqttClient {
+    V311(AsyncClient),
+    V5(v5::AsyncClient),
+}
+
+/// Event loop of a connection to the broker, in either protocol version
+pub enum MqttConnection {
+    V311(Box<EventLoop>),
+    V5(Box<v5::EventLoop>),
+}
+
+fn qos_v5(qos: QoS) -> v5::mqttbytes::QoS {
+    match qos {
+        QoS::AtMostOnce => v5::mqttbytes::QoS::AtMostOnce,
+        QoS::AtLeastOnce => v5::mqttbytes::QoS::AtLeastOnce,
+        QoS::ExactlyOnce => v5::mqttbytes::QoS::ExactlyOnce,
+    }
+}
+
+/// Open a connection with the given options, converted to MQTT 5 if required
+pub fn connect(options: MqttOptions, version: MqttVersion, cap: usize) -> (MqttClient, MqttConnection) {
+    match version {
+        MqttVersion::V311 => {
+            let (client, connection) = AsyncClient::new(options, cap);
+            (
+                MqttClient::V311(client),
+                MqttConnection::V311(Box::new(connection)),
+            )
+        }
+        MqttVersion::V5 => {
+            let (host, port) = options.broker_address();
+            let mut v5_options = v5::MqttOptions::new(options.client_id(), host, port);
+            v5_options
+                .set_keep_alive(options.keep_alive())
+                .set_clean_start(options.clean_session())
+                .set_outgoing_inflight_upper_limit(options.inflight());
+            if let Some((username, password)) = options.credentials() {
+                v5_options.set_credentials(username, password);
+            }
+            if let Some(will) = options.last_will() {
+                v5_options.set_last_will(v5::mqttbytes::v5::LastWill::new(
+                    will.topic,
+                    will.message.to_vec(),
+                    qos_v5(will.qos),
+                    will.retain,
+                    None,
+                ));
+            }
+            let (client, connection) = v5::AsyncClient::new(v5_options, cap);
+            (MqttClient::V5(client), MqttConnection::V5(Box::new(connection)))
+        }
+    }
+}
+
+impl MqttClient {
+    /// Subscribe to several filters with a single request, so that the request queue does
+    /// not fill up before the event loop is polled
+    pub async fn subscribe_many(&self, filters: &[(String, QoS)]) -> Result<(), LinkError> {
+        match self {
+            MqttClient::V311(client) => client
+                .subscribe_many(
+                    filters
+                        .iter()
+                        .map(|(filter, qos)| SubscribeFilter::new(filter.clone(), *qos)),
+                )
+                .await
+                .map_err(Box::new)?,
+            MqttClient::V5(client) => client
+                .subscribe_many(
+                    filters
+                        .iter()
+                        .map(|(filter, qos)| v5::mqttbytes::v5::Filter::new(filter.clone(), qos_v5(*qos))),
+                )
+                .await
+                .map_err(Box::new)?,
+        }
+        Ok(())
+    }
+
+    /// Publish without waiting, failing if the request queue is full
+    pub fn try_publish(
+        &self,
+        topic: &str,
+        qos: QoS,
+        retain: bool,
+        payload: impl Into<Vec<u8>>,
+    ) -> Result<(), LinkError> {
+        match self {
+            MqttClient::V311(client) => client
+                .try_publish(topic, qos, retain, payload)
+                .map_err(Box::new)?,
+            MqttClient::V5(client) => client
+                .try_publish(topic, qos_v5(qos), retain, payload.into())
+                .map_err(Box::new)?,
+        }
+        Ok(())
+    }
+}
+
+impl MqttConnection {
+    pub async fn poll(&mut self) -> Result<LinkEvent, LinkError> {
+        let event = match self {
+            MqttConnection::V311(connection) => match connection.poll().await.map_err(Box::new)? {
+                Event::Incoming(Packet::ConnAck(_)) => LinkEvent::ConnAck,
+                Event::Incoming(Packet::PubAck(_)) => LinkEvent::PubAck,
+                Event::Incoming(Packet::Publish(publish)) => LinkEvent::Publish(IncomingMessage {
+                    topic: publish.topic,
+                    payload: publish.payload,
+                    properties: MessageProperties::default(),
+                }),
+                event => LinkEvent::Other(format!("{event:?}")),
+            },
+            MqttConnection::V5(connection) => match connection.poll().await.map_err(Box::new)? {
+                v5::Event::Incoming(v5::Incoming::ConnAck(_)) => LinkEvent::ConnAck,
+                v5::Event::Incoming(v5::Incoming::PubAck(_)) => LinkEvent::PubAck,
+                v5::Event::Incoming(v5::Incoming::Publish(publish)) => LinkEvent::Publish(IncomingMessage {
+                    topic: String::from_utf8_lossy(&publish.topic).into_owned(),
+                    payload: publish.payload,
+                    properties: MessageProperties::from_v5(publish.properties),
+                }),
+                event => LinkEvent::Other(format!("{event:?}")),
+            },
+        };
+        Ok(event)
+    }
+}
+
+/// Topic filter of a shared subscription, whose messages go to one of the subscribers of
+/// the group only
+pub fn shared_filter(group: Option<&str>, filter: &str) -> String {
+    match group {
+        Some(group) => format!("$share/{group}/{filter}"),
+        None => filter.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use v5::mqttbytes::v5::PublishProperties;
+
+    #[test]
+    fn test_message_properties() {
+        assert_eq!(MessageProperties::from_v5(None), MessageProperties::default());
+        let properties = PublishProperties {
+            correlation_data: Some(Bytes::from_static(b"req-42")),
+            user_properties: vec![
+                ("Content-Type".to_string(), "application/yaml".to_string()),
+                ("correlation-id".to_string(), "ignored".to_string()),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            MessageProperties::from_v5(Some(properties)),
+            MessageProperties {
+                content_type: Some("application/yaml".to_string()),
+                correlation_id: Some("req-42".to_string()),
+            }
+        );
+        assert_eq!(
+            shared_filter(Some("receivers"), "twins/updates"),
+            "$share/receivers/twins/updates"
+        );
+        assert_eq!(shared_filter(None, "twins/updates"), "twins/updates");
+    }
+}
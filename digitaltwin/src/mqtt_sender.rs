@@ -1,4 +1,7 @@
 use clap::Parser;
+use digitaltwin_core::{ContentType, CURRENT_VERSION};
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
 use rumqttc::{Client, MqttOptions, QoS};
 use serde_json::{json, Value};
 use std::sync::mpsc;
@@ -7,6 +10,8 @@ use std::time::Duration;
 /// Test MQTT message / command sender. Run with
 /// cargo run --bin mqtt_sender -- --broker 192.168.10.112 update \
 ///                --object urn:iot-sensor:powerAbs123 --value 10.0
+/// cargo run --bin mqtt_sender -- --broker 192.168.10.112 update \
+///                --object urn:iot-sensor:powerAbs123 --value 10.0 --object urn:iot-sensor:relay1 --value true
 /// cargo run --bin mqtt_sender -- --broker 192.168.10.112 command \
 ///                --cmd EngineOn --target urn:aas:smart-home:ev:vw-eup:vin-WVWZZZAAZJD000001
 
@@ -26,20 +31,28 @@ struct Args {
     #[arg(short, long, default_value = "twins/updates", env = "MQTT_TOPIC")]
     topic: String,
 
+    /// message envelope version (1 for the runtimes that predate the versioned envelope)
+    #[arg(long, default_value_t = CURRENT_VERSION, value_parser = clap::value_parser!(u64).range(1..=CURRENT_VERSION))]
+    message_version: u64,
+
+    /// content type of the message, as expected by the runtime on the topic
+    #[arg(long, default_value = "application/json")]
+    content_type: ContentType,
+
     #[command(subcommand)]
     action: Action,
 }
 
 #[derive(clap::Subcommand, Debug)]
 enum Action {
-    /// Send an update message.
+    /// Send an update message; repeat --object and --value to send a batch of updates.
     Update {
         /// Object for update (e.g., "urn:iot-sensor:powerAbs123")
-        #[arg(long)]
-        object: String,
-        /// Value for update (e.g., 0.5)
-        #[arg(long)]
-        value: f64,
+        #[arg(long, required = true)]
+        object: Vec<String>,
+        /// Value for update (e.g., 0.5, true or "engaged")
+        #[arg(long, required = true)]
+        value: Vec<String>,
     },
     /// Send a command message.
     Command {
@@ -52,6 +65,16 @@ enum Action {
         /// Arguments for command, as a JSON object (e.g., {"brightness": 0.5})
         #[arg(long)]
         args: Option<String>,
+        /// Idempotency key, to let the twin discard repeated deliveries
+        #[arg(long)]
+        key: Option<String>,
+        /// Identity of the issuer, recorded in the audit log
+        #[arg(long)]
+        principal: Option<String>,
+        /// Shared secret used to sign the command with HMAC-SHA256, stamped with the
+        /// current time and a random nonce
+        #[arg(long, env = "COMMAND_HMAC_KEY", hide_env_values = true)]
+        hmac_key: Option<String>,
     },
 }
 
@@ -59,27 +82,68 @@ fn main() {
     let args = Args::parse();
 
     let mut message_obj = serde_json::Map::new();
+    let version = args.message_version;
     match args.action {
         Action::Update { object, value } => {
-            let update_obj = json!({
-                "object": object,
-                "value": value
-            });
-            message_obj.insert("update".to_string(), update_obj);
+            assert_eq!(object.len(), value.len(), "Each --object needs a --value");
+            let mut updates: Vec<_> = object
+                .into_iter()
+                .zip(value)
+                .map(|(object, value)| {
+                    // Numbers and booleans are sent as such, anything else as a string
+                    let value = serde_json::from_str::<Value>(&value).unwrap_or(Value::String(value));
+                    json!({
+                        "object": object,
+                        "value": value
+                    })
+                })
+                .collect();
+            if version == 1 && updates.len() == 1 {
+                message_obj.insert("update".to_string(), updates.remove(0));
+            } else {
+                message_obj.insert("updates".to_string(), Value::Array(updates));
+            }
         }
         Action::Command {
             cmd: command,
             target,
             args,
+            key,
+            principal,
+            hmac_key,
         } => {
-            let command_obj = json!({
+            let mut command_obj = json!({
                 "command": command,
                 "target": target,
                 "args": json!(args.unwrap_or_else(|| "{}".to_string())),
+                "idempotency_key": key,
+                "principal": principal,
             });
-            message_obj.insert("command".to_string(), command_obj);
+            if let Some(secret) = hmac_key {
+                let mut nonce = [0u8; 16];
+                SystemRandom::new().fill(&mut nonce).expect("no random source");
+                command_obj["ts"] = json!(chrono::Utc::now().timestamp());
+                command_obj["nonce"] = json!(nonce.iter().map(|b| format!("{b:02x}")).collect::<String>());
+                // serde_json sorts the object keys, as expected by the verifier
+                let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+                let tag = hmac::sign(&key, command_obj.to_string().as_bytes());
+                let signature: String = tag.as_ref().iter().map(|b| format!("{b:02x}")).collect();
+                command_obj["signature"] = json!(signature);
+            }
+            if version == 1 {
+                message_obj.insert("command".to_string(), command_obj);
+            } else {
+                message_obj.insert("commands".to_string(), json!([command_obj]));
+            }
         }
     }
+    if version > 1 {
+        message_obj.insert("v".to_string(), json!(version));
+        message_obj.insert(
+            "timestamp".to_string(),
+            json!(chrono::Utc::now().timestamp_millis()),
+        );
+    }
     println!(
         "Sending message to {}:{}: {}",
         args.broker,
@@ -87,7 +151,10 @@ fn main() {
         Value::Object(message_obj.clone())
     );
 
-    let payload = Value::Object(message_obj).to_string();
+    let payload = match args.content_type {
+        ContentType::Json => Value::Object(message_obj).to_string(),
+        ContentType::Yaml => serde_yaml::to_string(&message_obj).expect("Failed to encode message"),
+    };
     let mut mqttoptions = MqttOptions::new("dt-send", args.broker, 1883);
     mqttoptions.set_keep_alive(Duration::from_secs(5));
     let (client, mut connection) = Client::new(mqttoptions, 10);
@@ -1,56 +1,269 @@
-use clap::Parser;
-use log::{debug, error, info, trace};
-use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Packet, QoS};
-use serde::Deserialize;
+use chrono::{DateTime, Utc};
+use clap::{Parser, ValueEnum};
+use log::{debug, error, info, trace, warn};
+use rumqttc::{LastWill, MqttOptions, QoS};
+use serde::Serialize;
 use std::collections::HashMap;
-use tokio::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, mpsc, oneshot};
 
-use crate::twin_runner::ActorMessage;
-use digitaltwin_core::{AssetID, DeviceID};
+use crate::command::{self, CommandEnvelope, CommandSource};
+use crate::command_auth::{CommandAuth, CommandVerifier, VerifyError};
+use crate::commissioning::SharedCommissioning;
+use crate::device_trie::{self, DeviceTrie};
+use crate::event_bus::{self, BusEvent};
+use crate::failover::{self, Role, SharedFailover};
+use crate::ingest_metrics::SharedIngestMetrics;
+use crate::input_mapping::{self, InputMapping};
+use crate::ipc::IpcClient;
+use crate::kpi::FleetKpis;
+use crate::manager::{self, ManagerMessage, Query};
+use crate::mqtt_link::{self, LinkEvent, MessageProperties, MqttClient, MqttConnection, MqttVersion};
+use crate::outbox::{MessageClass, Outbox, OutboxMessage};
+use crate::rate_limit::{RateLimited, SharedRateLimiter};
+use crate::replay_guard::ReplayGuard;
+use crate::secrets::{self, SecretsProvider};
+use crate::twin_runner::{ActorMessage, CommandEvaluation, TwinReport};
+use crate::unmapped::{SharedUnmappedInputs, UnknownInputs, UnmappedInputs, UnmappedKind};
+use crate::virtual_sensors::{self, VirtualSensor, VirtualSensors};
+use digitaltwin_core::{
+    AasChange, AssetID, ContentType, CorrelationID, DeviceID, MqttCommand, MqttMessage, MqttUpdate,
+    SensorAnnouncement,
+};
+
+/// Interval of the consistency check of the subscriptions
+const CONSISTENCY_CHECK_INTERVAL: Duration = Duration::from_secs(60);
 
 #[derive(Parser, Clone)]
 pub struct NetworkOptions {
-    /// MQTT broker address (e.g., "localhost")
-    #[clap(short, long, env = "MQTT_BROKER")]
-    broker: String,
+    /// MQTT broker address (e.g., "localhost"); the subcommands don't need it
+    #[clap(short, long, required = true, env = "MQTT_BROKER")]
+    broker: Option<String>,
+
+    /// MQTT user name; the password is the "mqtt_password" secret
+    #[clap(long, env = "MQTT_USERNAME")]
+    mqtt_username: Option<String>,
 
     /// topic (default is "twins/updates")
     #[clap(short, long, default_value = "twins/updates", env = "MQTT_TOPIC")]
     topic: String,
+
+    /// MQTT protocol version; with 5, the content type and the correlation data of the
+    /// messages are read from their properties
+    #[clap(long, value_enum, default_value = "3.1.1", env = "MQTT_VERSION")]
+    mqtt_version: MqttVersion,
+
+    /// group of a shared subscription to the input topic: each message goes to one of the
+    /// receivers of the group, to scale them horizontally (MQTT 5, or brokers supporting
+    /// shared subscriptions with 3.1.1)
+    #[clap(long, env = "MQTT_SHARED_GROUP")]
+    shared_group: Option<String>,
+
+    /// availability topic for the runtime; each twin uses "<status_topic>/<asset id>"
+    #[clap(long, default_value = "twins/status", env = "MQTT_STATUS_TOPIC")]
+    status_topic: String,
+
+    /// definition change events topic; each twin uses "<changes_topic>/<asset id>"
+    #[clap(long, default_value = "twins/changes", env = "MQTT_CHANGES_TOPIC")]
+    changes_topic: String,
+
+    /// twin events topic, unless the AAS declares one; each event uses "<events_topic>/<asset id>/<event>"
+    #[clap(long, default_value = "twins/events", env = "MQTT_EVENTS_TOPIC")]
+    events_topic: String,
+
+    /// authentication of the commands received over MQTT
+    #[clap(long, value_enum, default_value = "none", env = "COMMAND_AUTH")]
+    command_auth: CommandAuth,
+
+    /// fleet KPIs topic, published as a retained message
+    #[clap(long, default_value = "twins/kpis", env = "MQTT_KPI_TOPIC")]
+    kpi_topic: String,
+
+    /// state topic; the status report of each twin is published as a retained message on
+    /// "<state_topic>/<asset id>", for the runtimes mirroring it
+    #[clap(long, default_value = "twins/state", env = "MQTT_STATE_TOPIC")]
+    state_topic: String,
+
+    /// command rejection acks topic; each twin uses "<acks_topic>/<asset id>"
+    #[clap(long, default_value = "twins/acks", env = "MQTT_ACKS_TOPIC")]
+    acks_topic: String,
+
+    /// topic where undecodable messages and commands failing verification are republished, with the reason
+    #[clap(long, default_value = "twins/deadletter", env = "MQTT_DEAD_LETTER_TOPIC")]
+    dead_letter_topic: String,
+
+    /// topic where sensors announce themselves, as retained messages on "<discovery_topic>/<sensor id>"
+    #[clap(long, default_value = "twins/discovery", env = "MQTT_DISCOVERY_TOPIC")]
+    discovery_topic: String,
+
+    /// content type of the messages received on a topic filter, as "<filter>=<type>"
+    /// (e.g., "twins/yaml/#=application/yaml"); messages on other topics are JSON
+    #[clap(long = "content-type", value_parser = parse_content_type, value_delimiter = ',', env = "MQTT_CONTENT_TYPES")]
+    content_types: Vec<(String, ContentType)>,
+
+    /// virtual sensor computed from the values of other devices, as "<device id>=<expression>"
+    /// (separate sensors with ';' in VIRTUAL_SENSORS); expressions use + - * /, parentheses,
+    /// abs, min and max, and device IDs with '-' go between braces
+    /// (e.g., "urn:virtual:net-power=powerAbs123 - {urn:iot-sensor:solar-1}")
+    #[clap(long = "virtual-sensor", value_parser = virtual_sensors::parse_virtual_sensor, value_delimiter = ';', env = "VIRTUAL_SENSORS")]
+    virtual_sensors: Vec<VirtualSensor>,
+
+    /// mapping of the JSON payloads of a vendor topic filter to updates, as
+    /// "<filter>:device=<selector>,value=<selector>[,seq=<selector>]" (separate mappings with
+    /// ';' in INPUT_MAPPINGS); selectors are paths into the payload such as "$.readings[0].w",
+    /// or a level of the topic such as "topic[1]" (e.g., "acme/+/telemetry:device=$.meta.id,value=$.w")
+    #[clap(long = "input-mapping", value_parser = input_mapping::parse_input_mapping, value_delimiter = ';', env = "INPUT_MAPPINGS")]
+    input_mappings: Vec<InputMapping>,
+
+    /// number of recent message IDs remembered to drop the duplicate deliveries; 0 disables
+    /// the replay guard, for the message IDs and the update sequence numbers
+    #[clap(long, default_value_t = 1024, env = "REPLAY_CACHE_SIZE")]
+    replay_cache_size: usize,
+
+    /// what to do with the updates of devices no twin reads and the commands of unknown
+    /// assets: log them, track them (listed by the REST API) or also dead-letter them
+    #[clap(long, value_enum, default_value = "log", env = "UNKNOWN_INPUTS")]
+    unknown_inputs: UnknownInputs,
+
+    /// how long an unknown device or asset stays listed after its last message (seconds)
+    #[clap(long, default_value_t = 3600, env = "UNKNOWN_INPUTS_WINDOW")]
+    unknown_inputs_window: u64,
+
+    /// keep the MQTT session of the runtime when it disconnects: the broker keeps its
+    /// subscriptions and queues the QoS 1 and 2 inputs until it reconnects
+    #[clap(long, env = "MQTT_PERSISTENT_SESSION")]
+    mqtt_persistent_session: bool,
+
+    /// QoS of the subscriptions to the input topic (updates and commands) and to the sensor
+    /// announcements (0, 1 or 2)
+    #[clap(long, value_parser = parse_qos, default_value = "1", env = "MQTT_INPUT_QOS")]
+    input_qos: QoS,
+
+    /// QoS of a class of published messages, as "<class>=<0|1|2>" (e.g., "events=2,state=0");
+    /// the classes are availability, changes, events, acks, dead-letter, kpis and state, and
+    /// the classes not listed use QoS 1
+    #[clap(long = "publish-qos", value_parser = parse_class_qos, value_delimiter = ',', env = "MQTT_PUBLISH_QOS")]
+    publish_qos: Vec<(MessageClass, QoS)>,
+
+    /// maximum number of QoS 1 and 2 messages published and not acknowledged yet by the broker
+    #[clap(long, value_parser = clap::value_parser!(u16).range(1..), default_value_t = 100, env = "MQTT_MAX_INFLIGHT")]
+    mqtt_max_inflight: u16,
+}
+
+impl NetworkOptions {
+    /// QoS of the published messages of a class, 1 unless configured
+    fn class_qos(&self, class: MessageClass) -> QoS {
+        self.publish_qos
+            .iter()
+            .rev()
+            .find(|(c, _)| *c == class)
+            .map_or(QoS::AtLeastOnce, |(_, qos)| *qos)
+    }
+}
+
+fn parse_qos(s: &str) -> Result<QoS, String> {
+    match s.trim() {
+        "0" => Ok(QoS::AtMostOnce),
+        "1" => Ok(QoS::AtLeastOnce),
+        "2" => Ok(QoS::ExactlyOnce),
+        _ => Err(format!("invalid QoS {s}, expected 0, 1 or 2")),
+    }
+}
+
+fn parse_class_qos(s: &str) -> Result<(MessageClass, QoS), String> {
+    let (class, qos) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected <class>=<qos>: {s}"))?;
+    Ok((MessageClass::from_str(class.trim(), true)?, parse_qos(qos)?))
+}
+
+fn parse_content_type(s: &str) -> Result<(String, ContentType), String> {
+    let (filter, content_type) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected <topic filter>=<content type>: {s}"))?;
+    Ok((filter.to_string(), content_type.parse()?))
+}
+
+/// Availability of the runtime or of a single twin, published as a retained message
+#[derive(Debug, Clone, Copy)]
+pub enum Availability {
+    Online,
+    Offline,
+}
+
+impl Availability {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Availability::Online => "online",
+            Availability::Offline => "offline",
+        }
+    }
 }
 
 /// Network receiver message types
 pub enum NetworkMessage {
     /// Register an entity to receive messages
     Register(AssetID, mpsc::Sender<ActorMessage>),
+    /// Register the twin of a remote runner, unless the ID is taken by another entity:
+    /// the reply tells whether it was registered
+    RegisterRemote(AssetID, mpsc::Sender<ActorMessage>, oneshot::Sender<bool>),
     /// Subscribe an entity to a list of sensor/actuator IDs
     Subscribe(AssetID, Vec<DeviceID>),
+    /// Unsubscribe an entity from a list of sensor/actuator IDs
+    Unsubscribe(AssetID, Vec<DeviceID>),
+    /// Publish the availability of an entity
+    Availability(AssetID, Availability),
+    /// Remove an entity and its subscriptions
+    Unregister(AssetID),
+    /// Remove the twin of a remote runner, if the ID is still registered with its channel
+    UnregisterRemote(AssetID, mpsc::Sender<ActorMessage>),
+    /// Publish the changes to the definition (AAS) of an entity
+    Changes(AssetID, Vec<AasChange>),
+    /// Restore the routes of the running twins, as known by the manager
+    Restore(Vec<TwinRoute>),
+    /// Publish what a command received with the dry run flag would do
+    Evaluation(AssetID, CommandEvaluation),
+    /// Publish the KPIs of the fleet
+    Kpis(FleetKpis),
+    /// Publish the status report of a twin
+    State(Box<TwinReport>),
+}
+
+/// Routing entry of a running twin: its channel and the devices it listens to
+#[derive(Debug, Clone)]
+pub struct TwinRoute {
+    pub asset_id: AssetID,
+    pub channel: mpsc::Sender<ActorMessage>,
+    pub devices: Vec<DeviceID>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
-struct Message {
-    /// data value update
-    update: Option<Update>,
-    /// command to be executed
-    command: Option<Command>,
+/// An event emitted by a twin, shaped as declared in its AAS
+#[derive(Debug, Clone, Serialize)]
+pub struct TwinEvent {
+    pub event: String,
+    pub timestamp: DateTime<Utc>,
+    pub payload: serde_json::Value,
+    /// ID of the input that caused the event
+    pub correlation_id: CorrelationID,
 }
 
-#[derive(Debug, Clone, Deserialize)]
-struct Update {
-    /// ID of the sensor/actuator
-    object: DeviceID,
-    /// update value
-    value: f32,
+/// Ack published with the evaluation of a dry run command
+#[derive(Debug, Serialize)]
+struct EvaluationAck<'a> {
+    status: &'static str,
+    #[serde(flatten)]
+    evaluation: &'a CommandEvaluation,
 }
 
-#[derive(Debug, Clone, Deserialize)]
-struct Command {
-    /// Asset ID of the target
-    target: AssetID,
-    /// command to be executed
-    command: String,
-    /// input value (any JSON object)
-    args: serde_json::Value,
+/// Error ack published when a command is rejected before reaching the twin
+#[derive(Debug, Clone, Serialize)]
+struct CommandAck<'a> {
+    command: &'a str,
+    correlation_id: &'a str,
+    status: &'static str,
+    reason: String,
+    retry_after_ms: u128,
 }
 
 pub struct NetworkReceiver {
@@ -58,108 +271,1070 @@ pub struct NetworkReceiver {
     asset_channels: HashMap<AssetID, mpsc::Sender<ActorMessage>>,
     /// Map of subscriptions (sensor/actuator ID to asset IDs)
     subscriptions: HashMap<DeviceID, Vec<AssetID>>,
+    /// Subscriptions to device ID patterns (e.g., "urn:iot-sensor:carpark-7:*")
+    pattern_subscriptions: DeviceTrie,
     send_ch: mpsc::Sender<NetworkMessage>,
     recv_ch: mpsc::Receiver<NetworkMessage>,
+    /// MQTT client, available after init
+    client: Option<MqttClient>,
+    /// Verifier of the command signatures
+    verifier: CommandVerifier,
+    /// Password of the MQTT user, if any
+    mqtt_password: Option<String>,
+    /// Command rate limits, shared with the REST server
+    rate_limiter: SharedRateLimiter,
+    /// Sensors announced on the discovery topic, replayed to the twins registering later
+    discovered: HashMap<DeviceID, SensorAnnouncement>,
+    /// Warm-standby state: a standby publishes nothing until it takes over
+    failover: SharedFailover,
+    /// Latency of the ingest path, shared with the REST server
+    metrics: SharedIngestMetrics,
+    /// Unknown devices and assets seen recently, shared with the REST server
+    unmapped: SharedUnmappedInputs,
+    /// Virtual sensors, with the latest values of the devices they read
+    virtual_sensors: VirtualSensors,
+    /// Duplicate deliveries of messages and updates
+    replay_guard: ReplayGuard,
+    /// Channel to the manager, to restore the routes of the running twins
+    manager_ch: Option<mpsc::Sender<ManagerMessage>>,
+    /// Devices seen on the broker, in commissioning mode
+    commissioning: Option<SharedCommissioning>,
+    /// Connection to the hub, when the twins of this process are remote twins: the hub
+    /// sends their inputs and commands, this receiver only publishes
+    ipc: Option<IpcClient>,
+    /// Outbound messages kept while the broker is unreachable
+    outbox: Option<Outbox>,
+    /// Subscription to the event bus, publishing the events emitted by the twins
+    bus: Option<broadcast::Receiver<BusEvent>>,
+    /// Whether the connection to the broker is up, since the last ConnAck
+    connected: bool,
     /// Options
     options: NetworkOptions,
 }
 
 impl NetworkReceiver {
-    pub fn new(options: NetworkOptions) -> Self {
+    pub fn new(
+        options: NetworkOptions,
+        secrets: &SecretsProvider,
+        rate_limiter: SharedRateLimiter,
+        failover: SharedFailover,
+    ) -> Self {
         let (send_ch, recv_ch) = mpsc::channel(5);
+        let command_keys = secrets.get_list(secrets::COMMAND_KEYS);
+        let verifier = CommandVerifier::new(options.command_auth, &command_keys);
+        if verifier.is_enabled() && command_keys.is_empty() {
+            error!(
+                "Command authentication is enabled but no keys are configured: all commands will be rejected"
+            );
+        }
         NetworkReceiver {
             asset_channels: HashMap::new(),
             subscriptions: HashMap::new(),
+            pattern_subscriptions: DeviceTrie::default(),
             send_ch,
             recv_ch,
+            client: None,
+            verifier,
+            mqtt_password: secrets.get(secrets::MQTT_PASSWORD),
+            rate_limiter,
+            discovered: HashMap::new(),
+            failover,
+            metrics: SharedIngestMetrics::default(),
+            unmapped: Arc::new(Mutex::new(UnmappedInputs::new(
+                options.unknown_inputs,
+                options.unknown_inputs_window,
+            ))),
+            virtual_sensors: VirtualSensors::new(options.virtual_sensors.clone()),
+            replay_guard: ReplayGuard::new(options.replay_cache_size),
+            manager_ch: None,
+            commissioning: None,
+            ipc: None,
+            outbox: None,
+            bus: None,
+            connected: false,
             options,
         }
     }
 
+    pub fn metrics(&self) -> SharedIngestMetrics {
+        self.metrics.clone()
+    }
+
+    pub fn unmapped(&self) -> SharedUnmappedInputs {
+        self.unmapped.clone()
+    }
+
+    /// Connect the receiver to the manager, which knows the routes of the running twins.
+    /// The manager is created after the receiver, as it needs the receiver channel.
+    pub fn attach_manager(&mut self, manager_ch: mpsc::Sender<ManagerMessage>) {
+        self.manager_ch = Some(manager_ch);
+    }
+
+    /// Record the devices seen on the broker, with the values they publish
+    pub fn attach_commissioning(&mut self, commissioning: SharedCommissioning) {
+        self.commissioning = Some(commissioning);
+    }
+
+    /// Make the twins of this process remote twins of the hub the client is connected to
+    pub fn attach_ipc(&mut self, ipc: IpcClient) {
+        self.ipc = Some(ipc);
+    }
+
+    /// Keep the outbound messages in the outbox while the broker is unreachable
+    pub fn attach_outbox(&mut self, outbox: Outbox) {
+        self.outbox = Some(outbox);
+    }
+
+    /// Publish the events emitted by the twins, received from the event bus
+    pub fn attach_bus(&mut self, bus: broadcast::Receiver<BusEvent>) {
+        self.bus = Some(bus);
+    }
+
+    /// Ask the manager for the routes of the running twins, delivered as a Restore
+    /// message. A restarted receiver gets its tables back without restarting the twins.
+    /// The reply is awaited in a separate task: the twins may be blocked sending to
+    /// the receiver while the manager collects their reports.
+    fn request_routes(&self) {
+        let Some(manager_ch) = self.manager_ch.clone() else {
+            return;
+        };
+        let send_ch = self.send_ch.clone();
+        tokio::spawn(async move {
+            let (reply, response) = oneshot::channel();
+            if manager_ch
+                .send(ManagerMessage::Query(Query::Routes(reply)))
+                .await
+                .is_err()
+            {
+                return;
+            }
+            if let Ok(routes) = response.await {
+                let _ = send_ch.send(NetworkMessage::Restore(routes)).await;
+            }
+        });
+    }
+
+    /// Merge the routes known by the manager into the routing tables
+    fn restore_routes(&mut self, routes: Vec<TwinRoute>) {
+        let mut restored = 0;
+        for route in routes {
+            // Terminated since the manager answered
+            if route.channel.is_closed() {
+                continue;
+            }
+            if !self.asset_channels.contains_key(&route.asset_id) {
+                restored += 1;
+            }
+            self.asset_channels.insert(route.asset_id.clone(), route.channel);
+            for device in &route.devices {
+                self.add_subscription(device, &route.asset_id);
+            }
+        }
+        info!("Restored the routes of {restored} twins from the manager");
+    }
+
     pub fn get_channel(&self) -> mpsc::Sender<NetworkMessage> {
         self.send_ch.clone()
     }
 
-    async fn init(&self, topic: &str) -> EventLoop {
-        debug!("Initializing MQTT connection to {}", self.options.broker);
-        let mut mqttoptions = MqttOptions::new("dt-recv", &self.options.broker, 1883);
+    /// Options of a connection to the broker, with the credentials of the runtime
+    pub fn mqtt_options(&self, client_id: impl Into<String>) -> MqttOptions {
+        // Always set when the runtime runs
+        let broker = self.options.broker.clone().unwrap_or_default();
+        let mut mqttoptions = MqttOptions::new(client_id, broker, 1883);
         mqttoptions.set_keep_alive(std::time::Duration::from_secs(5));
-        let (client, connection) = AsyncClient::new(mqttoptions, 10);
-        client.subscribe(topic, QoS::AtLeastOnce).await.unwrap();
+        if let Some(username) = &self.options.mqtt_username {
+            let password = self.mqtt_password.clone().unwrap_or_else(|| {
+                error!(
+                    "No {} secret found for MQTT user {username}",
+                    secrets::MQTT_PASSWORD
+                );
+                String::new()
+            });
+            mqttoptions.set_credentials(username, password);
+        }
+        mqttoptions
+    }
+
+    async fn init(&mut self, topic: &str) -> MqttConnection {
+        let (role, heartbeat_topic) = {
+            let failover = self.lock_failover();
+            (failover.role(), failover.heartbeat_topic().to_string())
+        };
+        // Both instances of a failover pair are connected to the broker at the same time,
+        // as the remote twin runners
+        let client_id = match role {
+            _ if self.ipc.is_some() => format!("dt-runner-{}", std::process::id()),
+            Role::Primary => "dt-recv".to_string(),
+            Role::Standby => "dt-recv-standby".to_string(),
+        };
+        let mut mqttoptions = self.mqtt_options(client_id);
+        mqttoptions
+            .set_clean_session(!self.options.mqtt_persistent_session)
+            .set_inflight(self.options.mqtt_max_inflight);
+        debug!(
+            "Initializing MQTT connection to {}",
+            mqttoptions.broker_address().0
+        );
+        // The broker marks the runtime as offline if we disconnect abruptly; a standby
+        // going away leaves the active instance online, as a remote twin runner
+        if role == Role::Primary && self.ipc.is_none() {
+            mqttoptions.set_last_will(LastWill::new(
+                &self.options.status_topic,
+                Availability::Offline.as_str(),
+                QoS::AtLeastOnce,
+                true,
+            ));
+        }
+        let (client, connection) = mqtt_link::connect(mqttoptions, self.options.mqtt_version, 10);
+        // All the filters go in one request: the event loop is not polled before init returns
+        let mut filters = Vec::new();
+        if self.ipc.is_none() {
+            let group = self.options.shared_group.as_deref();
+            filters.push((mqtt_link::shared_filter(group, topic), self.options.input_qos));
+            for mapping in &self.options.input_mappings {
+                filters.push((
+                    mqtt_link::shared_filter(group, &mapping.filter),
+                    self.options.input_qos,
+                ));
+            }
+        } else {
+            info!("Remote twin runner: inputs and commands come from the hub, {topic} not subscribed");
+        }
+        if let Some(commissioning) = &self.commissioning {
+            let filter = commissioning
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .filter()
+                .to_string();
+            info!("Commissioning mode: recording the devices seen on {filter}");
+            filters.push((filter, QoS::AtMostOnce));
+        }
+        filters.push((self.discovery_filter(), self.options.input_qos));
+        if role == Role::Standby {
+            filters.push((heartbeat_topic, QoS::AtMostOnce));
+        }
+        if let Err(e) = client.subscribe_many(&filters).await {
+            error!("Failed to subscribe to {filters:?}: {e}");
+        }
+        self.client = Some(client);
         connection
     }
 
+    fn lock_failover(&self) -> std::sync::MutexGuard<'_, failover::Failover> {
+        self.failover.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The MQTT client, if the instance is active: a standby publishes nothing
+    fn publisher(&self) -> Option<&MqttClient> {
+        self.client.as_ref().filter(|_| self.lock_failover().is_active())
+    }
+
+    /// Publish the heartbeat of the active instance, or take over if a standby no
+    /// longer receives the heartbeat of the active one
+    fn check_failover(&mut self) {
+        let promoted = self.lock_failover().check(Instant::now());
+        if promoted {
+            warn!("No heartbeat from the active instance, taking over");
+            // The standby did not publish the availability of the runtime and of the twins
+            self.publish_availability(None, Availability::Online);
+            let assets: Vec<_> = self.asset_channels.keys().cloned().collect();
+            for asset in &assets {
+                self.publish_availability(Some(asset), Availability::Online);
+            }
+        }
+        if let Some(client) = self.publisher() {
+            let (role, topic) = {
+                let failover = self.lock_failover();
+                (failover.role(), failover.heartbeat_topic().to_string())
+            };
+            let payload = serde_json::json!({ "role": role.as_str(), "timestamp": Utc::now() });
+            if let Err(e) = client.try_publish(&topic, QoS::AtMostOnce, false, payload.to_string()) {
+                error!("Failed to publish heartbeat to {topic}: {e:?}");
+            }
+        }
+    }
+
+    /// Publish a retained availability message for the runtime (no asset ID) or a twin
+    fn publish_availability(&mut self, asset: Option<&AssetID>, availability: Availability) {
+        let topic = match asset {
+            Some(id) => format!("{}/{}", self.options.status_topic, id),
+            None => self.options.status_topic.clone(),
+        };
+        self.publish(
+            MessageClass::Availability,
+            topic,
+            true,
+            availability.as_str().to_string(),
+        );
+    }
+
+    /// Publish the changes to the definition of a twin
+    fn publish_changes(&mut self, asset: &AssetID, changes: &[AasChange]) {
+        let topic = format!("{}/{}", self.options.changes_topic, asset);
+        let payload = match serde_json::to_string(changes) {
+            Ok(payload) => payload,
+            Err(e) => {
+                error!("Failed to serialize changes of {asset}: {e:?}");
+                return;
+            }
+        };
+        self.publish(MessageClass::Changes, topic, false, payload);
+    }
+
+    /// Publish an event emitted by a twin
+    fn publish_event(&mut self, asset: &AssetID, event: &TwinEvent, topic: Option<String>) {
+        let topic =
+            topic.unwrap_or_else(|| format!("{}/{}/{}", self.options.events_topic, asset, event.event));
+        let payload = match serde_json::to_string(event) {
+            Ok(payload) => payload,
+            Err(e) => {
+                error!("Failed to serialize event {} of {asset}: {e:?}", event.event);
+                return;
+            }
+        };
+        self.publish(MessageClass::Events, topic, false, payload);
+    }
+
+    /// Publish the KPIs of the fleet, retained
+    fn publish_kpis(&mut self, kpis: &FleetKpis) {
+        let payload = match serde_json::to_string(kpis) {
+            Ok(payload) => payload,
+            Err(e) => {
+                error!("Failed to serialize the fleet KPIs: {e:?}");
+                return;
+            }
+        };
+        let topic = self.options.kpi_topic.clone();
+        self.publish(MessageClass::Kpis, topic, true, payload);
+    }
+
+    /// Publish the status report of a twin, retained
+    fn publish_state(&mut self, report: &TwinReport) {
+        let payload = match serde_json::to_string(report) {
+            Ok(payload) => payload,
+            Err(e) => {
+                error!("Failed to serialize the state of {}: {e:?}", report.asset_id);
+                return;
+            }
+        };
+        let topic = format!("{}/{}", self.options.state_topic, report.asset_id);
+        self.publish(MessageClass::State, topic, true, payload);
+    }
+
+    /// Verify the signature of a command, returning the principal that issued it
+    fn verify_command(&mut self, command: &serde_json::Value) -> Result<Option<String>, VerifyError> {
+        self.verifier.verify(command, Utc::now().timestamp())
+    }
+
+    /// Republish a rejected message on the dead letter topic, with the reason and the
+    /// correlation ID, unless the message could not be decoded
+    fn publish_dead_letter(
+        &mut self,
+        message: serde_json::Value,
+        reason: &dyn std::fmt::Display,
+        correlation_id: Option<&str>,
+    ) {
+        let payload = serde_json::json!({
+            "reason": reason.to_string(),
+            "timestamp": Utc::now(),
+            "correlation_id": correlation_id,
+            "message": message,
+        });
+        let topic = self.options.dead_letter_topic.clone();
+        self.publish(MessageClass::DeadLetter, topic, false, payload.to_string());
+    }
+
+    /// Publish an error ack for a command rejected by the rate limiter
+    fn publish_rate_limited(
+        &mut self,
+        target: &AssetID,
+        envelope: &CommandEnvelope,
+        rejection: &RateLimited,
+    ) {
+        let topic = format!("{}/{}", self.options.acks_topic, target);
+        let ack = CommandAck {
+            command: &envelope.command,
+            correlation_id: &envelope.correlation_id,
+            status: "rejected",
+            reason: rejection.to_string(),
+            retry_after_ms: rejection.retry_after.as_millis(),
+        };
+        let payload = match serde_json::to_string(&ack) {
+            Ok(payload) => payload,
+            Err(e) => {
+                error!("Failed to serialize ack for {target}: {e:?}");
+                return;
+            }
+        };
+        self.publish(MessageClass::Acks, topic, false, payload);
+    }
+
+    /// Publish the evaluation of a dry run command on the acks topic of the twin
+    fn publish_evaluation(&mut self, target: &AssetID, evaluation: &CommandEvaluation) {
+        let topic = format!("{}/{}", self.options.acks_topic, target);
+        let ack = EvaluationAck {
+            status: "dry_run",
+            evaluation,
+        };
+        let payload = match serde_json::to_string(&ack) {
+            Ok(payload) => payload,
+            Err(e) => {
+                error!("Failed to serialize the evaluation for {target}: {e:?}");
+                return;
+            }
+        };
+        self.publish(MessageClass::Acks, topic, false, payload);
+    }
+
+    /// Publish a message with the QoS of its class. The messages of the classes kept in the outbox go there
+    /// when the broker is unreachable or the client queue is full, and behind the messages
+    /// already waiting, to keep their order.
+    fn publish(&mut self, class: MessageClass, topic: String, retain: bool, payload: String) {
+        if self.publisher().is_none() {
+            return;
+        }
+        let qos = self.options.class_qos(class);
+        let Some(client) = &self.client else {
+            return;
+        };
+        // Never block here: the event loop is polled by this same task
+        let outbox = match &mut self.outbox {
+            Some(outbox) if outbox.keeps(class) => outbox,
+            _ => {
+                if let Err(e) = client.try_publish(&topic, qos, retain, payload) {
+                    error!("Failed to publish {} to {topic}: {e:?}", class.as_str());
+                }
+                return;
+            }
+        };
+        if self.connected && outbox.is_empty() {
+            match client.try_publish(&topic, qos, retain, payload.clone()) {
+                Ok(()) => return,
+                Err(e) => debug!(
+                    "Failed to publish {} to {topic}, kept in the outbox: {e:?}",
+                    class.as_str()
+                ),
+            }
+        }
+        if let Err(e) = outbox.push(OutboxMessage::new(class, topic.clone(), retain, payload)) {
+            error!("Failed to keep {} for {topic} in the outbox: {e}", class.as_str());
+        }
+    }
+
+    /// Publish the messages waiting in the outbox, as long as the client queue takes them
+    fn flush_outbox(&mut self) {
+        if !self.connected || self.publisher().is_none() {
+            return;
+        }
+        let (Some(client), Some(outbox)) = (&self.client, &mut self.outbox) else {
+            return;
+        };
+        if outbox.is_empty() {
+            return;
+        }
+        let options = &self.options;
+        let result = outbox.flush(Utc::now(), |message| {
+            client
+                .try_publish(
+                    &message.topic,
+                    options.class_qos(message.class),
+                    message.retain,
+                    message.payload.clone(),
+                )
+                .is_ok()
+        });
+        match result {
+            Ok(published) if published > 0 => {
+                debug!("Published {published} messages from the outbox");
+                if outbox.is_empty() {
+                    info!("Outbox drained");
+                }
+            }
+            Ok(_) => {}
+            Err(e) => error!("Failed to update the outbox: {e}"),
+        }
+    }
+
+    /// Content type of a message: the one of its properties, or the one of its topic
+    fn content_type(&self, topic: &str, properties: &MessageProperties) -> ContentType {
+        if let Some(content_type) = &properties.content_type {
+            // Parameters such as the charset do not change the codec
+            let essence = content_type.split(';').next().unwrap_or_default().trim();
+            match essence.parse() {
+                Ok(content_type) => return content_type,
+                Err(e) => warn!("{e} in the properties of a message from {topic}, using the topic one"),
+            }
+        }
+        self.options
+            .content_types
+            .iter()
+            .find(|(filter, _)| rumqttc::matches(topic, filter))
+            .map(|(_, content_type)| *content_type)
+            .unwrap_or_default()
+    }
+
+    /// Filter matching the discovery topic and the announcements of each sensor
+    fn discovery_filter(&self) -> String {
+        format!("{}/#", self.options.discovery_topic)
+    }
+
+    /// Record a sensor announcement and forward it to its twin, or to all of them
+    async fn handle_announcement(&mut self, topic: &str, payload: &[u8]) {
+        // An empty retained message clears the announcement
+        if payload.is_empty() {
+            return;
+        }
+        let announcement = match SensorAnnouncement::decode(payload) {
+            Ok(announcement) => announcement,
+            Err(e) => {
+                error!("Failed to decode sensor announcement from {topic}: {e}");
+                return;
+            }
+        };
+        debug!("Sensor announced: {announcement:?}");
+        self.discovered
+            .insert(announcement.sensor_id.clone(), announcement.clone());
+        let mut orphans = Vec::new();
+        for (asset, ch) in &self.asset_channels {
+            if announcement.twin.as_ref().is_some_and(|twin| twin != asset) {
+                continue;
+            }
+            if ch
+                .send(ActorMessage::SensorDiscovered(announcement.clone()))
+                .await
+                .is_err()
+            {
+                orphans.push(asset.clone());
+            }
+        }
+        for asset in orphans {
+            self.remove_asset(&asset);
+        }
+    }
+
+    /// Send the sensors announced so far to a twin that just registered. A separate task
+    /// does the sending: the twin can't receive until its initialization is over.
+    fn replay_announcements(&self, asset: &AssetID, ch: &mpsc::Sender<ActorMessage>) {
+        let announcements: Vec<_> = self
+            .discovered
+            .values()
+            .filter(|a| a.twin.as_ref().is_none_or(|twin| twin == asset))
+            .cloned()
+            .collect();
+        if announcements.is_empty() {
+            return;
+        }
+        let ch = ch.clone();
+        tokio::spawn(async move {
+            for announcement in announcements {
+                if ch
+                    .send(ActorMessage::SensorDiscovered(announcement))
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+    }
+
+    /// Decode a message received from the broker and dispatch its updates and commands
+    async fn handle_publish(&mut self, topic: &str, payload: &[u8], properties: MessageProperties) {
+        if let Some(commissioning) = &self.commissioning {
+            // The other topics of the broker only feed the commissioning
+            if !rumqttc::matches(topic, &self.options.topic)
+                && !self
+                    .options
+                    .input_mappings
+                    .iter()
+                    .any(|mapping| rumqttc::matches(topic, &mapping.filter))
+            {
+                let mut commissioning = commissioning.lock().unwrap_or_else(|e| e.into_inner());
+                if !commissioning.observe_raw(topic, payload, Utc::now()) {
+                    trace!("Commissioning: no scalar value in the message from {topic}");
+                }
+                return;
+            }
+        }
+        if let Some(mapping) = self
+            .options
+            .input_mappings
+            .iter()
+            .find(|mapping| rumqttc::matches(topic, &mapping.filter))
+        {
+            match mapping.extract(topic, payload) {
+                Ok(update) => {
+                    let correlation_id = CorrelationID::from(
+                        properties
+                            .correlation_id
+                            .unwrap_or_else(command::new_correlation_id),
+                    );
+                    self.handle_update(topic, update, &correlation_id).await;
+                }
+                Err(e) => {
+                    error!("Failed to map message from {topic}: {e}");
+                    let message = serde_json::from_slice(payload).unwrap_or_else(|_| {
+                        serde_json::Value::String(String::from_utf8_lossy(payload).into_owned())
+                    });
+                    self.publish_dead_letter(message, &e, None);
+                }
+            }
+            return;
+        }
+        let message = match MqttMessage::decode_as(payload, self.content_type(topic, &properties)) {
+            Ok(message) => message,
+            Err(e) => {
+                error!("Failed to decode message from {topic}: {e}");
+                let message = serde_json::from_slice(payload).unwrap_or_else(|_| {
+                    serde_json::Value::String(String::from_utf8_lossy(payload).into_owned())
+                });
+                self.publish_dead_letter(message, &e, None);
+                return;
+            }
+        };
+        debug!("Decoded v{} message: {message:?}", message.version);
+        // The updates and the commands of the message share its correlation ID, or the one
+        // of its properties, unless a command has its own
+        let correlation_id = CorrelationID::from(
+            message
+                .correlation_id
+                .or(properties.correlation_id)
+                .unwrap_or_else(command::new_correlation_id),
+        );
+        if self.replay_guard.is_duplicate_message(message.id.as_deref()) {
+            debug!("Dropped duplicate message {:?} from {topic}", message.id);
+            self.count_duplicate();
+            return;
+        }
+        for update in message.updates {
+            self.handle_update(topic, update, &correlation_id).await;
+        }
+        let mut accepted = true;
+        for command in message.commands {
+            accepted &= self.dispatch_command(topic, command, &correlation_id).await;
+        }
+        if accepted {
+            self.replay_guard.accept_message(message.id.as_deref());
+        }
+    }
+
+    /// Dispatch an update, unless duplicate, and the updates of the virtual sensors reading it
+    async fn handle_update(&mut self, topic: &str, update: MqttUpdate, correlation_id: &CorrelationID) {
+        if self.replay_guard.is_duplicate_update(&update) {
+            debug!("Dropped duplicate update {:?} of {}", update.seq, update.object);
+            self.count_duplicate();
+            return;
+        }
+        if let Some(commissioning) = &self.commissioning {
+            commissioning.lock().unwrap_or_else(|e| e.into_inner()).observe(
+                &update.object,
+                topic,
+                &update.value,
+                Utc::now(),
+            );
+        }
+        // The virtual sensors reading the device are fanned out like real ones
+        let derived = self.virtual_sensors.update(&update);
+        let read = self.virtual_sensors.reads(&update.object);
+        let message =
+            serde_json::json!({ "object": update.object, "value": update.value, "seq": update.seq });
+        let object = update.object.clone();
+        if !self.dispatch_update(update, correlation_id).await && !read {
+            self.unknown_input(UnmappedKind::Device, &object, topic, correlation_id, message);
+        }
+        for update in derived {
+            self.dispatch_update(update, correlation_id).await;
+        }
+    }
+
+    fn count_duplicate(&self) {
+        self.metrics
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .duplicates_dropped += 1;
+    }
+
+    /// Send an update to the twins subscribed to its sensor/actuator. The subscribers are
+    /// borrowed from the tables and the device ID is shared by their messages: the cost of
+    /// an update does not grow with allocations per subscriber. False without subscribers.
+    async fn dispatch_update(&mut self, update: MqttUpdate, correlation_id: &CorrelationID) -> bool {
+        let mut orphans = Vec::new();
+        let exact = self
+            .subscriptions
+            .get(&update.object)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        // Empty, hence not allocated, without pattern subscriptions
+        let mut patterns = self.pattern_subscriptions.matches(&update.object);
+        patterns.retain(|asset| !exact.contains(asset));
+        for target in exact.iter().chain(&patterns) {
+            let Some(ch) = self.asset_channels.get(target) else {
+                error!("No channel found for asset ID: {target:?}");
+                orphans.push(target.clone());
+                continue;
+            };
+            debug!("sending update to asset {target}: {update:?} (correlation ID {correlation_id})");
+            let started = Instant::now();
+            let sent = ch
+                .send(ActorMessage::InputChange(
+                    update.object.clone(),
+                    update.value.clone(),
+                    correlation_id.clone(),
+                ))
+                .await;
+            self.metrics
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .send_wait
+                .record(started.elapsed());
+            if let Err(e) = sent {
+                error!("failed to send update to asset {target}: {e:?}");
+                orphans.push(target.clone());
+            }
+        }
+        let delivered = !exact.is_empty() || !patterns.is_empty();
+        // The twins are gone: their channels are closed
+        for asset in orphans {
+            self.remove_asset(&asset);
+        }
+        delivered
+    }
+
+    /// Track, and dead-letter if configured, a message of an unknown device or asset
+    fn unknown_input(
+        &mut self,
+        kind: UnmappedKind,
+        id: &str,
+        topic: &str,
+        correlation_id: &str,
+        message: serde_json::Value,
+    ) {
+        let mode = {
+            let mut unmapped = self.unmapped.lock().unwrap_or_else(|e| e.into_inner());
+            if unmapped.mode() == UnknownInputs::Log {
+                return;
+            }
+            unmapped.record(kind, id, topic, correlation_id, Utc::now());
+            unmapped.mode()
+        };
+        if mode == UnknownInputs::DeadLetter {
+            let reason = match kind {
+                UnmappedKind::Device => format!("no twin reads device {id}"),
+                UnmappedKind::Asset => format!("unknown asset {id}"),
+            };
+            self.publish_dead_letter(message, &reason, Some(correlation_id));
+        }
+    }
+
+    /// Verify a command and send it to its target, unless rate limited. The twins of a
+    /// standby execute the commands too, to take over with the same state: what they
+    /// publish and actuate is left to the active instance. False if the verification failed.
+    async fn dispatch_command(
+        &mut self,
+        topic: &str,
+        cmd: MqttCommand,
+        correlation_id: &CorrelationID,
+    ) -> bool {
+        debug!("Decoded command: {cmd:?}");
+        let principal = if self.verifier.is_enabled() {
+            match self.verify_command(&cmd.raw) {
+                Ok(principal) => principal,
+                Err(e) => {
+                    let correlation_id = cmd.correlation_id.as_deref().unwrap_or(correlation_id);
+                    warn!(
+                        "Rejected command {} for asset {} (correlation ID {correlation_id}): {e}",
+                        cmd.command, cmd.target
+                    );
+                    self.publish_dead_letter(cmd.raw, &e, Some(correlation_id));
+                    return false;
+                }
+            }
+        } else {
+            cmd.principal.clone()
+        };
+        let Some(ch) = self.asset_channels.get(&cmd.target) else {
+            error!("No channel found for asset ID: {}", cmd.target);
+            let correlation_id = cmd.correlation_id.as_deref().unwrap_or(correlation_id);
+            self.unknown_input(
+                UnmappedKind::Asset,
+                &cmd.target,
+                topic,
+                correlation_id,
+                cmd.raw.clone(),
+            );
+            return true;
+        };
+        debug!("sending command to asset {}: {cmd:?}", cmd.target);
+        let envelope = CommandEnvelope::new(CommandSource::Mqtt, cmd.command, cmd.args)
+            .with_idempotency_key(cmd.idempotency_key)
+            .with_principal(principal)
+            .with_correlation_id(cmd.correlation_id.or_else(|| Some(correlation_id.to_string())));
+        // A dry run changes nothing, the rate limiter included, and is answered by the
+        // active instance
+        if cmd.dry_run {
+            if self.lock_failover().is_active() {
+                self.request_evaluation(cmd.target, ch.clone(), envelope);
+            }
+            return true;
+        }
+        let limited = self.rate_limiter.lock().unwrap_or_else(|e| e.into_inner()).check(
+            envelope.principal.as_deref(),
+            &cmd.target,
+            Instant::now(),
+        );
+        if let Err(rejection) = limited {
+            warn!(
+                "Rejected command {} for asset {}: {rejection}",
+                envelope.command, cmd.target
+            );
+            self.publish_rate_limited(&cmd.target, &envelope, &rejection);
+            return true;
+        }
+        if let Err(e) = ch.send(ActorMessage::Command(envelope)).await {
+            error!("failed to send command to asset {}: {e:?}", cmd.target);
+            self.remove_asset(&cmd.target);
+        }
+        true
+    }
+
+    /// Ask a twin what a command would do. The reply is awaited in a separate task, as
+    /// the twin may be blocked sending to the receiver, and comes back as an Evaluation.
+    fn request_evaluation(&self, target: AssetID, ch: mpsc::Sender<ActorMessage>, envelope: CommandEnvelope) {
+        let send_ch = self.send_ch.clone();
+        tokio::spawn(async move {
+            let evaluation = match manager::request_evaluation(&ch, envelope.clone()).await {
+                Some(evaluation) => evaluation,
+                None => CommandEvaluation::rejected(&envelope, "the twin did not answer"),
+            };
+            let _ = send_ch.send(NetworkMessage::Evaluation(target, evaluation)).await;
+        });
+    }
+
+    /// Subscribe an entity to a device ID or pattern, unless already subscribed
+    fn add_subscription(&mut self, device: &DeviceID, asset: &AssetID) {
+        if device_trie::is_pattern(device) {
+            self.pattern_subscriptions.insert(device, asset);
+            return;
+        }
+        let subscribers = self.subscriptions.entry(device.clone()).or_default();
+        if !subscribers.contains(asset) {
+            subscribers.push(asset.clone());
+        }
+    }
+
+    /// Unsubscribe an entity from a device ID or pattern
+    fn remove_subscription(&mut self, device: &DeviceID, asset: &AssetID) {
+        if device_trie::is_pattern(device) {
+            self.pattern_subscriptions.remove(device, asset);
+            return;
+        }
+        if let Some(subscribers) = self.subscriptions.get_mut(device) {
+            subscribers.retain(|a| a != asset);
+            if subscribers.is_empty() {
+                self.subscriptions.remove(device);
+            }
+        }
+    }
+
+    async fn register(&mut self, src: AssetID, ch: mpsc::Sender<ActorMessage>) {
+        debug!("Registering new asset {src}");
+        // A restarted twin subscribes again
+        self.remove_subscriptions(&src);
+        self.replay_announcements(&src, &ch);
+        if let Some(ipc) = &self.ipc {
+            ipc.register(src.clone(), ch.clone()).await;
+        }
+        self.asset_channels.insert(src, ch);
+    }
+
+    async fn unregister(&mut self, src: AssetID) {
+        debug!("Unregistering asset {src}");
+        self.remove_asset(&src);
+        if let Some(ipc) = &self.ipc {
+            ipc.unregister(src).await;
+        }
+    }
+
+    /// Drop all the subscriptions of an entity
+    fn remove_subscriptions(&mut self, asset: &AssetID) {
+        self.pattern_subscriptions.retain(|a| a != asset);
+        self.subscriptions
+            .values_mut()
+            .for_each(|subscribers| subscribers.retain(|a| a != asset));
+        self.subscriptions
+            .retain(|_, subscribers| !subscribers.is_empty());
+    }
+
+    /// Drop an entity and its subscriptions
+    fn remove_asset(&mut self, asset: &AssetID) {
+        self.remove_subscriptions(asset);
+        self.asset_channels.remove(asset);
+    }
+
+    /// Drop the entities whose channel is closed and the subscriptions of unknown
+    /// entities, which would be missed if the twins terminate without unregistering
+    fn check_subscriptions(&mut self) {
+        let closed: Vec<_> = self
+            .asset_channels
+            .iter()
+            .filter(|(_, ch)| ch.is_closed())
+            .map(|(asset, _)| asset.clone())
+            .collect();
+        for asset in &closed {
+            self.remove_asset(asset);
+        }
+        let mut orphans = 0;
+        for subscribers in self.subscriptions.values_mut() {
+            let before = subscribers.len();
+            subscribers.retain(|asset| self.asset_channels.contains_key(asset));
+            orphans += before - subscribers.len();
+        }
+        self.subscriptions
+            .retain(|_, subscribers| !subscribers.is_empty());
+        let patterns = self.pattern_subscriptions.len();
+        self.pattern_subscriptions
+            .retain(|asset| self.asset_channels.contains_key(asset));
+        orphans += patterns - self.pattern_subscriptions.len();
+        if !closed.is_empty() || orphans > 0 {
+            warn!(
+                "Removed {} closed channels ({closed:?}) and {orphans} orphan subscriptions",
+                closed.len()
+            );
+        }
+    }
+
     pub async fn body(&mut self) {
         info!("Network receiver body starting");
 
         debug!("subscribing to MQTT topic {}", self.options.topic);
-        let mut connection = self.init(&self.options.topic).await;
+        let topic = self.options.topic.clone();
+        let mut connection = self.init(&topic).await;
+        let mut consistency_check = tokio::time::interval(CONSISTENCY_CHECK_INTERVAL);
+        let mut heartbeat = tokio::time::interval(failover::HEARTBEAT_INTERVAL);
+        let heartbeat_topic = self.lock_failover().heartbeat_topic().to_string();
+        self.request_routes();
 
         loop {
             tokio::select! {
+                _ = consistency_check.tick() => {
+                    self.check_subscriptions();
+                }
+                _ = heartbeat.tick() => {
+                    self.check_failover();
+                    self.flush_outbox();
+                }
                 event = connection.poll() => {
                     match event {
-                        Ok(Event::Incoming(pkt)) => {
-                            trace!("Received packet from MQTT: {pkt:?}");
-                            if let Packet::Publish(publish) = pkt {
-                                if let Ok(message) = serde_json::from_slice::<Message>(&publish.payload) {
-                                    debug!("Decoded update: {message:?}");
-                                    if let Some (update) = message.update {
-                                        if let Some(subscribers) = self.subscriptions.get(&update.object) {
-                                            let channels = subscribers.iter().filter_map(|aid| {
-                                                self.asset_channels.get(aid).or_else(|| {
-                                                    error!("No channel found for asset ID: {aid:?}");
-                                                    None
-                                                })
-                                                .map(|ch| (aid, ch))
-                                            });
-                                            for (target, ch) in channels {
-                                                debug!("sending update to asset {target}: {update:?}");
-                                                if let Err(e) = ch.send(ActorMessage::InputChange(update.object.clone(), update.value)).await {
-                                                    error!("failed to send update to asset {update:?}: {e:?}");
-                                                }
-                                            }
-                                        }
-                                    }
-                                    if let Some (cmd) = message.command {
-                                        debug!("Decoded command: {cmd:?}");
-                                        if let Some(ch) = self.asset_channels.get(&cmd.target) {
-                                            debug!("sending command to asset {}: {cmd:?}", cmd.target);
-                                            if let Err(e) = ch.send(ActorMessage::Command(
-                                                cmd.command,
-                                                cmd.args,
-                                            )).await {
-                                                error!("failed to send command to asset {}: {e:?}", cmd.target);
-                                            }
-                                        } else {
-                                            error!("No channel found for asset ID: {}", cmd.target);
-                                        }
-                                    }
-                                } else {
-                                    error!("Failed to decode update from payload");
-                                }
+                        Ok(LinkEvent::ConnAck) => {
+                            trace!("Received ConnAck from MQTT");
+                            self.connected = true;
+                            // The messages kept while disconnected go first
+                            self.flush_outbox();
+                            if self.ipc.is_none() {
+                                // (re)connected: replace any last will published by the broker
+                                self.publish_availability(None, Availability::Online);
+                            }
+                        }
+                        Ok(LinkEvent::PubAck) => {
+                            trace!("Received PubAck from MQTT");
+                            self.flush_outbox();
+                        }
+                        Ok(LinkEvent::Publish(publish)) => {
+                            trace!("Received message from MQTT: {publish:?}");
+                            if publish.topic == heartbeat_topic {
+                                self.lock_failover().peer_heartbeat(Instant::now());
+                            } else if rumqttc::matches(&publish.topic, &self.discovery_filter()) {
+                                self.handle_announcement(&publish.topic, &publish.payload).await;
+                            } else {
+                                let received = Instant::now();
+                                self.handle_publish(&publish.topic, &publish.payload, publish.properties)
+                                    .await;
+                                self.metrics
+                                    .lock()
+                                    .unwrap_or_else(|e| e.into_inner())
+                                    .message_latency
+                                    .record(received.elapsed());
                             }
                         }
-                        Ok(event) => {
-                            trace!("Received event from MQTT: {event:?}");
+                        Ok(LinkEvent::Other(event)) => {
+                            trace!("Received event from MQTT: {event}");
                         }
                         Err(e) => {
+                            self.connected = false;
                             error!("Error receiving message from MQTT: {e:?}");
                         }
                     }
                 }
+                Some(event) = event_bus::next_attached_event(&mut self.bus, "MQTT publisher") => {
+                    if let BusEvent::Event(emitted) = event {
+                        debug!("Asset {} emitted event {}", emitted.asset_id, emitted.event.event);
+                        self.publish_event(&emitted.asset_id, &emitted.event, emitted.topic);
+                    }
+                }
                 Some(msg) = self.recv_ch.recv() => {
                     match msg {
                         NetworkMessage::Subscribe(src, oids) => {
+                            if !self.asset_channels.contains_key(&src) {
+                                warn!("Asset {src} subscribed to {oids:?} without registering, ignored");
+                                continue;
+                            }
                             debug!("Adding new subscriber {src} to messages from {oids:?}");
-                            oids.iter().for_each(|oid| {
-                                self.subscriptions.entry(oid.clone()).or_default().push(src.clone());
-                            });
-                            // TODO: warn if channel for this subscriber is missing
+                            oids.iter().for_each(|oid| self.add_subscription(oid, &src));
+                            if let Some(ipc) = &self.ipc {
+                                ipc.subscribe(src, oids).await;
+                            }
+                        }
+                        NetworkMessage::Unsubscribe(src, oids) => {
+                            debug!("Removing subscriber {src} from messages from {oids:?}");
+                            oids.iter().for_each(|oid| self.remove_subscription(oid, &src));
+                            if let Some(ipc) = &self.ipc {
+                                ipc.unsubscribe(src, oids).await;
+                            }
                         }
                         NetworkMessage::Register(src, ch) => {
-                            debug!("Registering new asset {src}");
-                            self.asset_channels.insert(src.clone(), ch);
+                            self.register(src, ch).await;
+                        }
+                        NetworkMessage::RegisterRemote(src, ch, reply) => {
+                            // A remote runner cannot take over the channel of another twin
+                            let taken = self.asset_channels.get(&src).is_some_and(|current| !current.is_closed());
+                            let _ = reply.send(!taken);
+                            if taken {
+                                warn!("Remote twin runner registering {src}, already registered, refused");
+                                continue;
+                            }
+                            self.register(src, ch).await;
+                        }
+                        NetworkMessage::Unregister(src) => {
+                            self.unregister(src).await;
+                        }
+                        NetworkMessage::UnregisterRemote(src, ch) => {
+                            if self.asset_channels.get(&src).is_some_and(|current| current.same_channel(&ch)) {
+                                self.unregister(src).await;
+                            }
+                        }
+                        NetworkMessage::Changes(src, changes) => {
+                            debug!("Asset {src} definition changed: {changes:?}");
+                            self.publish_changes(&src, &changes);
+                        }
+                        NetworkMessage::Availability(src, availability) => {
+                            debug!("Asset {src} is now {}", availability.as_str());
+                            self.publish_availability(Some(&src), availability);
+                        }
+                        NetworkMessage::Restore(routes) => {
+                            self.restore_routes(routes);
+                        }
+                        NetworkMessage::Evaluation(target, evaluation) => {
+                            debug!("Asset {target} evaluated command {}", evaluation.command);
+                            self.publish_evaluation(&target, &evaluation);
+                        }
+                        NetworkMessage::Kpis(kpis) => {
+                            debug!("Fleet KPIs computed for {} twins", kpis.twins);
+                            self.publish_kpis(&kpis);
+                        }
+                        NetworkMessage::State(report) => {
+                            trace!("Asset {} state published: {}", report.asset_id, report.state);
+                            self.publish_state(&report);
                         }
                     }
                 }
@@ -167,3 +1342,212 @@ impl NetworkReceiver {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::failover::FailoverOptions;
+    use crate::rate_limit::{CommandRateLimiter, RateLimitOptions};
+    use crate::secrets::SecretsOptions;
+    use digitaltwin_core::SlotValue;
+
+    async fn receiver(failover: SharedFailover) -> NetworkReceiver {
+        let secrets = SecretsProvider::load(&SecretsOptions::parse_from(["test"]))
+            .await
+            .unwrap();
+        NetworkReceiver::new(
+            NetworkOptions::parse_from(["test", "--broker", "localhost"]),
+            &secrets,
+            CommandRateLimiter::shared(RateLimitOptions::parse_from(["test"])),
+            failover,
+        )
+    }
+
+    #[test]
+    fn test_publish_qos() {
+        let options = NetworkOptions::parse_from([
+            "test",
+            "--broker",
+            "localhost",
+            "--publish-qos",
+            "events=2, state=0",
+        ]);
+        assert_eq!(options.class_qos(MessageClass::Events), QoS::ExactlyOnce);
+        assert_eq!(options.class_qos(MessageClass::State), QoS::AtMostOnce);
+        assert_eq!(options.class_qos(MessageClass::Acks), QoS::AtLeastOnce);
+        assert_eq!(options.input_qos, QoS::AtLeastOnce);
+        assert!(parse_class_qos("events=3").is_err());
+        assert!(parse_class_qos("heartbeat=1").is_err());
+        assert!(parse_class_qos("events").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_standby_commands() {
+        let failover =
+            failover::Failover::shared(&FailoverOptions::parse_from(["test", "--role", "standby"]));
+        let mut receiver = receiver(failover.clone()).await;
+        let (ch, mut messages) = mpsc::channel(5);
+        receiver.asset_channels.insert("urn:twin:1".into(), ch);
+        let command = |dry_run: bool| MqttCommand {
+            target: "urn:twin:1".into(),
+            command: "SetChargingCurrent".to_string(),
+            args: serde_json::json!(16.0),
+            idempotency_key: None,
+            principal: None,
+            correlation_id: None,
+            dry_run,
+            raw: serde_json::Value::Null,
+        };
+        let correlation_id = CorrelationID::from("c-1");
+
+        // The twins of the standby execute the commands, without answering the dry runs
+        receiver
+            .dispatch_command("twins/commands", command(false), &correlation_id)
+            .await;
+        assert!(matches!(messages.try_recv(), Ok(ActorMessage::Command(envelope)) if envelope.args == 16.0));
+        receiver
+            .dispatch_command("twins/commands", command(true), &correlation_id)
+            .await;
+        tokio::task::yield_now().await;
+        assert!(messages.try_recv().is_err());
+
+        // Taking over, with the same commands executed by the twins
+        assert!(failover
+            .lock()
+            .unwrap()
+            .check(Instant::now() + Duration::from_secs(60)));
+        receiver
+            .dispatch_command("twins/commands", command(false), &correlation_id)
+            .await;
+        assert!(matches!(messages.try_recv(), Ok(ActorMessage::Command(_))));
+        receiver
+            .dispatch_command("twins/commands", command(true), &correlation_id)
+            .await;
+        assert!(matches!(messages.recv().await, Some(ActorMessage::Evaluate(..))));
+    }
+
+    #[tokio::test]
+    async fn test_forged_message_id() {
+        let failover = failover::Failover::shared(&FailoverOptions::parse_from(["test"]));
+        let mut receiver = receiver(failover).await;
+        receiver.verifier = CommandVerifier::new(CommandAuth::Hmac, &["secret".to_string()]);
+        let (ch, mut messages) = mpsc::channel(5);
+        receiver.asset_channels.insert("urn:twin:1".into(), ch);
+        let mut command = serde_json::json!({
+            "target": "urn:twin:1", "command": "TurnOn", "args": null,
+            "ts": Utc::now().timestamp(), "nonce": "n-1",
+        });
+        let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, b"secret");
+        let tag = ring::hmac::sign(&key, crate::command_auth::canonical_json(&command).as_bytes());
+        let signature: String = tag.as_ref().iter().map(|b| format!("{b:02x}")).collect();
+        let mut message = |signature: &str| {
+            command["signature"] = serde_json::json!(signature);
+            serde_json::json!({"v": 2, "id": "m-1", "commands": [command]}).to_string()
+        };
+        let forged = message("00");
+        let genuine = message(&signature);
+
+        // The forged message is rejected without shadowing the genuine one, with its ID
+        receiver
+            .handle_publish("twins/updates", forged.as_bytes(), MessageProperties::default())
+            .await;
+        assert!(messages.try_recv().is_err());
+        receiver
+            .handle_publish("twins/updates", genuine.as_bytes(), MessageProperties::default())
+            .await;
+        assert!(matches!(messages.try_recv(), Ok(ActorMessage::Command(_))));
+
+        // Its redelivery is dropped
+        receiver
+            .handle_publish("twins/updates", genuine.as_bytes(), MessageProperties::default())
+            .await;
+        assert!(messages.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_subscriptions_pruned() {
+        let failover = failover::Failover::shared(&FailoverOptions::parse_from(["test"]));
+        let mut receiver = receiver(failover).await;
+        let (device, other) = (
+            DeviceID::from("urn:iot-sensor:power"),
+            DeviceID::from("urn:iot-sensor:relay"),
+        );
+        let (twin, idle) = (AssetID::from("urn:twin:1"), AssetID::from("urn:twin:2"));
+        let (ch, messages) = mpsc::channel(5);
+        receiver.asset_channels.insert(twin.clone(), ch);
+        let (ch, idle_messages) = mpsc::channel(5);
+        receiver.asset_channels.insert(idle.clone(), ch);
+
+        // Subscribing twice to the same device is a single subscription
+        receiver.add_subscription(&device, &twin);
+        receiver.add_subscription(&device, &twin);
+        receiver.add_subscription(&other, &idle);
+        assert_eq!(receiver.subscriptions[&device], ["urn:twin:1"]);
+
+        // The twin is gone: the next update prunes its channel and its subscriptions
+        drop(messages);
+        let update = MqttUpdate {
+            object: device.clone(),
+            value: SlotValue::Number(1.0),
+            seq: None,
+        };
+        receiver
+            .dispatch_update(update, &CorrelationID::from("c-1"))
+            .await;
+        assert!(!receiver.subscriptions.contains_key(&device));
+        assert!(!receiver.asset_channels.contains_key(&twin));
+
+        // Without updates, the consistency check prunes them
+        drop(idle_messages);
+        assert!(receiver.subscriptions.contains_key(&other));
+        receiver.check_subscriptions();
+        assert!(receiver.subscriptions.is_empty());
+        assert!(receiver.asset_channels.is_empty());
+    }
+
+    /// Cost of the dispatch of an update to the subscribers of its device, run with
+    /// cargo test --release -p digitaltwin measure_dispatch_update -- --ignored --nocapture
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore]
+    async fn measure_dispatch_update() {
+        const UPDATES: u32 = 200_000;
+        const SUBSCRIBERS: usize = 4;
+        let failover = failover::Failover::shared(&FailoverOptions::parse_from(["test"]));
+        let mut receiver = receiver(failover).await;
+        let device = DeviceID::from("urn:iot-sensor:power");
+        let mut drains = Vec::new();
+        for i in 0..SUBSCRIBERS {
+            let asset = AssetID::from(format!("urn:twin:{i}"));
+            let (ch, mut messages) = mpsc::channel(1000);
+            receiver.asset_channels.insert(asset.clone(), ch);
+            receiver.add_subscription(&device, &asset);
+            drains.push(tokio::spawn(async move {
+                let mut received = 0;
+                while messages.recv().await.is_some() {
+                    received += 1;
+                }
+                received
+            }));
+        }
+        let correlation_id = CorrelationID::from("c-1");
+        let started = Instant::now();
+        for i in 0..UPDATES {
+            let update = MqttUpdate {
+                object: device.clone(),
+                value: SlotValue::Number(f64::from(i)),
+                seq: None,
+            };
+            assert!(receiver.dispatch_update(update, &correlation_id).await);
+        }
+        let elapsed = started.elapsed();
+        println!(
+            "Dispatched {UPDATES} updates to {SUBSCRIBERS} subscribers in {} ms ({} ns/update)",
+            elapsed.as_millis(),
+            elapsed.as_nanos() / u128::from(UPDATES)
+        );
+        receiver.asset_channels.clear();
+        for drain in drains {
+            assert_eq!(drain.await.unwrap(), UPDATES);
+        }
+    }
+}
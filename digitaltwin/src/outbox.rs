@@ -0,0 +1,303 @@
+use chrono::{DateTime, Utc};
+use clap::{Parser, ValueEnum};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+use thiserror::Error as ThisError;
+
+/// Name of the outbox file in the outbox directory
+const OUTBOX_FILE: &str = "outbox.jsonl";
+
+#[derive(Parser, Clone)]
+pub struct OutboxOptions {
+    /// directory of the outbox, where the outbound messages are kept while the broker is
+    /// unreachable and published again on reconnect, also across restarts; no outbox by default
+    #[clap(long, env = "OUTBOX_DIR")]
+    outbox_dir: Option<PathBuf>,
+
+    /// class of messages kept in the outbox, as "<class>" or "<class>=<max age in seconds>"
+    /// (older messages are dropped instead of being published); the classes are availability,
+    /// changes, events, acks, dead-letter, kpis and state
+    #[clap(
+        long = "outbox-class",
+        value_parser = parse_class_policy,
+        value_delimiter = ',',
+        default_value = "availability,changes,events,acks",
+        env = "OUTBOX_CLASSES"
+    )]
+    outbox_classes: Vec<(MessageClass, Option<u64>)>,
+
+    /// maximum number of messages in the outbox: when it is full, new messages are dropped
+    #[clap(long, default_value_t = 100_000, env = "OUTBOX_MAX_MESSAGES")]
+    outbox_max_messages: usize,
+}
+
+fn parse_class_policy(s: &str) -> Result<(MessageClass, Option<u64>), String> {
+    let (class, max_age) = match s.split_once('=') {
+        Some((class, max_age)) => (
+            class,
+            Some(
+                max_age
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("invalid max age in seconds: {max_age}"))?,
+            ),
+        ),
+        None => (s, None),
+    };
+    Ok((MessageClass::from_str(class.trim(), true)?, max_age))
+}
+
+/// Class of an outbound message, selecting whether it survives a broker outage
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MessageClass {
+    /// Retained availability of the runtime and of the twins
+    Availability,
+    /// Changes to the definition of the twins
+    Changes,
+    /// Events emitted by the twins, including the commands they send to devices
+    Events,
+    /// Acks of the rejected commands
+    Acks,
+    /// Rejected messages republished with the reason
+    DeadLetter,
+    /// Retained fleet KPIs
+    Kpis,
+    /// Retained status reports of the twins
+    State,
+}
+
+impl MessageClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MessageClass::Availability => "availability",
+            MessageClass::Changes => "changes",
+            MessageClass::Events => "events",
+            MessageClass::Acks => "acks",
+            MessageClass::DeadLetter => "dead letter",
+            MessageClass::Kpis => "kpis",
+            MessageClass::State => "state",
+        }
+    }
+}
+
+#[derive(ThisError, Debug)]
+pub enum OutboxError {
+    #[error("outbox full ({0} messages)")]
+    Full(usize),
+    #[error("cannot write the outbox: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// A message waiting in the outbox, published with the QoS of its class
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OutboxMessage {
+    pub class: MessageClass,
+    pub topic: String,
+    pub retain: bool,
+    pub payload: String,
+    pub queued: DateTime<Utc>,
+}
+
+impl OutboxMessage {
+    pub fn new(class: MessageClass, topic: String, retain: bool, payload: String) -> Self {
+        OutboxMessage {
+            class,
+            topic,
+            retain,
+            payload,
+            queued: Utc::now(),
+        }
+    }
+}
+
+/// Durable queue of the outbound messages that could not be published. The messages are
+/// appended as JSON lines to a file, rewritten when they are published, and published in
+/// order: while the outbox is not empty, new messages of the same classes queue behind.
+#[derive(Debug)]
+pub struct Outbox {
+    path: PathBuf,
+    /// Classes kept in the outbox, with the maximum age of their messages
+    policies: HashMap<MessageClass, Option<Duration>>,
+    max_messages: usize,
+    pending: VecDeque<OutboxMessage>,
+}
+
+impl Outbox {
+    /// The outbox configured in the options, if any, with the messages left by a previous run
+    pub fn from_options(options: &OutboxOptions) -> std::io::Result<Option<Self>> {
+        let Some(dir) = &options.outbox_dir else {
+            return Ok(None);
+        };
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(OUTBOX_FILE);
+        let pending = match std::fs::read_to_string(&path) {
+            Ok(text) => parse_messages(&text),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => VecDeque::new(),
+            Err(e) => return Err(e),
+        };
+        if !pending.is_empty() {
+            info!("{} messages waiting in the outbox", pending.len());
+        }
+        let policies = options
+            .outbox_classes
+            .iter()
+            .map(|(class, max_age)| (*class, max_age.map(Duration::from_secs)))
+            .collect();
+        Ok(Some(Outbox {
+            path,
+            policies,
+            max_messages: options.outbox_max_messages,
+            pending,
+        }))
+    }
+
+    /// Whether the messages of a class are kept in the outbox
+    pub fn keeps(&self, class: MessageClass) -> bool {
+        self.policies.contains_key(&class)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Append a message to the outbox
+    pub fn push(&mut self, message: OutboxMessage) -> Result<(), OutboxError> {
+        if self.pending.len() >= self.max_messages {
+            return Err(OutboxError::Full(self.max_messages));
+        }
+        let line = serde_json::to_string(&message).map_err(std::io::Error::from)?;
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        writeln!(file, "{line}")?;
+        self.pending.push_back(message);
+        Ok(())
+    }
+
+    /// Publish the messages of the outbox in order with `publish`, until it refuses one,
+    /// dropping the messages older than the maximum age of their class. Returns the number
+    /// of messages published.
+    pub fn flush(
+        &mut self,
+        now: DateTime<Utc>,
+        mut publish: impl FnMut(&OutboxMessage) -> bool,
+    ) -> Result<usize, OutboxError> {
+        let (mut published, mut expired) = (0, 0);
+        while let Some(message) = self.pending.front() {
+            let max_age = self.policies.get(&message.class).copied().flatten();
+            let age = (now - message.queued).to_std().unwrap_or_default();
+            if max_age.is_some_and(|max_age| age > max_age) {
+                expired += 1;
+            } else if publish(message) {
+                published += 1;
+            } else {
+                break;
+            }
+            self.pending.pop_front();
+        }
+        if expired > 0 {
+            warn!("Dropped {expired} expired messages from the outbox");
+        }
+        if published + expired > 0 {
+            self.rewrite()?;
+        }
+        Ok(published)
+    }
+
+    /// Replace the outbox file with the pending messages
+    fn rewrite(&self) -> std::io::Result<()> {
+        let mut text = String::new();
+        for message in &self.pending {
+            text.push_str(&serde_json::to_string(message)?);
+            text.push('\n');
+        }
+        let temp = self.path.with_extension("tmp");
+        std::fs::write(&temp, text)?;
+        std::fs::rename(&temp, &self.path)
+    }
+}
+
+/// Parse the lines of an outbox file; a line truncated by a crash is skipped
+fn parse_messages(text: &str) -> VecDeque<OutboxMessage> {
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(message) => Some(message),
+            Err(e) => {
+                warn!("Skipping invalid outbox entry: {e}");
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_class_policy() {
+        assert_eq!(
+            parse_class_policy("events").unwrap(),
+            (MessageClass::Events, None)
+        );
+        assert_eq!(
+            parse_class_policy("dead-letter=3600").unwrap(),
+            (MessageClass::DeadLetter, Some(3600))
+        );
+        assert!(parse_class_policy("heartbeat").is_err());
+        assert!(parse_class_policy("events=1h").is_err());
+    }
+
+    #[test]
+    fn test_outbox() {
+        let dir = std::env::temp_dir().join(format!("dt-outbox-{}", std::process::id()));
+        let options = OutboxOptions {
+            outbox_dir: Some(dir.clone()),
+            outbox_classes: vec![(MessageClass::Events, None), (MessageClass::Acks, Some(60))],
+            outbox_max_messages: 3,
+        };
+        let mut outbox = Outbox::from_options(&options).unwrap().unwrap();
+        assert!(outbox.keeps(MessageClass::Events));
+        assert!(!outbox.keeps(MessageClass::Changes));
+
+        let mut stale = OutboxMessage::new(MessageClass::Acks, "twins/acks/a".into(), false, "{}".into());
+        stale.queued -= chrono::Duration::seconds(120);
+        outbox.push(stale).unwrap();
+        for i in 0..2 {
+            let message = OutboxMessage::new(
+                MessageClass::Events,
+                format!("twins/events/{i}"),
+                false,
+                "{}".into(),
+            );
+            outbox.push(message).unwrap();
+        }
+        let message = OutboxMessage::new(MessageClass::Events, "twins/events/3".into(), false, "{}".into());
+        assert!(matches!(outbox.push(message), Err(OutboxError::Full(3))));
+
+        // The stale ack is dropped, then the broker takes a single message
+        let mut topics = Vec::new();
+        let published = outbox
+            .flush(Utc::now(), |message| {
+                topics.push(message.topic.clone());
+                topics.len() < 2
+            })
+            .unwrap();
+        assert_eq!(published, 1);
+        assert_eq!(topics, ["twins/events/0", "twins/events/1"]);
+
+        // The message left survives a restart
+        let mut outbox = Outbox::from_options(&options).unwrap().unwrap();
+        assert_eq!(outbox.pending.len(), 1);
+        assert_eq!(outbox.pending[0].topic, "twins/events/1");
+        assert_eq!(outbox.flush(Utc::now(), |_| true).unwrap(), 1);
+        assert!(outbox.is_empty());
+        assert!(std::fs::read_to_string(dir.join(OUTBOX_FILE)).unwrap().is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,173 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+use crate::twin_runner::TwinTransition;
+use digitaltwin_core::SlotValue;
+
+/// Event emitted by a twin when the risk estimated by a predictor reaches the alert level
+pub const MAINTENANCE_RISK_EVENT: &str = "MaintenanceRisk";
+
+#[derive(Parser, Clone)]
+pub struct PredictorOptions {
+    /// state counted as a failure by the built-in fault frequency predictor (in any region)
+    #[clap(long, default_value = "Fault", env = "PREDICTOR_FAULT_STATE")]
+    predictor_fault_state: String,
+
+    /// seconds over which the failures of a twin are counted (0 = no built-in predictor)
+    #[clap(long, default_value_t = 86400, env = "PREDICTOR_WINDOW")]
+    predictor_window: u64,
+
+    /// failures in the window giving a risk of 1
+    #[clap(long, default_value_t = 3, env = "PREDICTOR_FAULT_THRESHOLD")]
+    predictor_fault_threshold: u32,
+
+    /// risk (between 0 and 1) from which a twin emits a "MaintenanceRisk" event
+    #[clap(long, default_value_t = 1.0, env = "PREDICTOR_ALERT_RISK")]
+    predictor_alert_risk: f64,
+}
+
+impl PredictorOptions {
+    pub fn alert_risk(&self) -> f64 {
+        self.predictor_alert_risk
+    }
+}
+
+/// Estimate of a predictor, shown in the status report of the twin
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Prediction {
+    pub predictor: String,
+    /// Risk of failure, between 0 and 1
+    pub risk: f64,
+    /// What the estimate is based on
+    pub summary: String,
+}
+
+/// Model estimating the risk of failure of a twin. Each twin has its own instances, fed
+/// with the values received on its slots and with its state transitions.
+pub trait Predictor: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// A value received on a slot, after the smoothing filter
+    fn observe_value(&mut self, _slot: &str, _time: DateTime<Utc>, _value: &SlotValue) {}
+
+    /// A state transition of the twin
+    fn observe_transition(&mut self, _transition: &TwinTransition) {}
+
+    /// The estimate at a given time
+    fn predict(&self, now: DateTime<Utc>) -> Prediction;
+}
+
+/// The predictors of a twin, as enabled in the options
+pub fn predictors(options: &PredictorOptions) -> Vec<Box<dyn Predictor>> {
+    let mut predictors: Vec<Box<dyn Predictor>> = Vec::new();
+    if options.predictor_window > 0 {
+        predictors.push(Box::new(FaultFrequency::new(options)));
+    }
+    predictors
+}
+
+/// Built-in predictor: the risk grows with the number of recent failures
+#[derive(Debug)]
+pub struct FaultFrequency {
+    fault_state: String,
+    window: TimeDelta,
+    threshold: u32,
+    /// Times the twin entered the fault state, oldest first
+    faults: VecDeque<DateTime<Utc>>,
+}
+
+impl FaultFrequency {
+    pub fn new(options: &PredictorOptions) -> Self {
+        FaultFrequency {
+            fault_state: options.predictor_fault_state.clone(),
+            window: TimeDelta::seconds(options.predictor_window as i64),
+            threshold: options.predictor_fault_threshold.max(1),
+            faults: VecDeque::new(),
+        }
+    }
+
+    fn is_fault(&self, state: &str) -> bool {
+        state.split('|').any(|s| s == self.fault_state)
+    }
+}
+
+impl Predictor for FaultFrequency {
+    fn name(&self) -> &str {
+        "fault_frequency"
+    }
+
+    fn observe_transition(&mut self, transition: &TwinTransition) {
+        if self.is_fault(&transition.to) && !self.is_fault(&transition.from) {
+            self.faults.push_back(transition.timestamp);
+        }
+        while self
+            .faults
+            .front()
+            .is_some_and(|fault| *fault < transition.timestamp - self.window)
+        {
+            self.faults.pop_front();
+        }
+    }
+
+    fn predict(&self, now: DateTime<Utc>) -> Prediction {
+        let faults = self
+            .faults
+            .iter()
+            .filter(|fault| **fault >= now - self.window)
+            .count();
+        Prediction {
+            predictor: self.name().to_string(),
+            risk: (faults as f64 / self.threshold as f64).min(1.0),
+            summary: format!(
+                "{faults} {} failures in the last {}s",
+                self.fault_state,
+                self.window.num_seconds()
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transition(from: &str, to: &str, timestamp: DateTime<Utc>) -> TwinTransition {
+        TwinTransition {
+            asset_id: "charger".into(),
+            from: from.to_string(),
+            to: to.to_string(),
+            timestamp,
+            correlation_id: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_fault_frequency() {
+        let options = PredictorOptions {
+            predictor_fault_state: "Fault".to_string(),
+            predictor_window: 3600,
+            predictor_fault_threshold: 2,
+            predictor_alert_risk: 1.0,
+        };
+        let mut predictor = FaultFrequency::new(&options);
+        let start: DateTime<Utc> = "2025-03-01T10:00:00Z".parse().unwrap();
+        let at = |minutes| start + TimeDelta::minutes(minutes);
+        assert_eq!(predictor.predict(start).risk, 0.0);
+
+        predictor.observe_transition(&transition("Idle|Online", "Fault|Online", at(0)));
+        // A change of region while in fault is the same failure
+        predictor.observe_transition(&transition("Fault|Online", "Fault|Offline", at(5)));
+        assert_eq!(predictor.predict(at(10)).risk, 0.5);
+        predictor.observe_transition(&transition("Fault|Offline", "Idle|Offline", at(20)));
+        predictor.observe_transition(&transition("Idle|Offline", "Fault|Offline", at(30)));
+        let prediction = predictor.predict(at(30));
+        assert_eq!(prediction.risk, 1.0);
+        assert_eq!(prediction.summary, "2 Fault failures in the last 3600s");
+
+        // The first failure leaves the window
+        assert_eq!(predictor.predict(at(61)).risk, 0.5);
+        assert_eq!(predictor.predict(at(91)).risk, 0.0);
+    }
+}
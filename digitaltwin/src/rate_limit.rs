@@ -0,0 +1,248 @@
+use clap::Parser;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use digitaltwin_core::AssetID;
+
+/// Key of the bucket of the commands issued without a principal
+const ANONYMOUS: &str = "";
+
+#[derive(Parser, Clone, Debug)]
+pub struct RateLimitOptions {
+    /// commands per second each principal can issue, across all twins (0 disables the limit)
+    #[clap(long, default_value_t = 0.0, env = "PRINCIPAL_COMMAND_RATE")]
+    principal_command_rate: f64,
+
+    /// commands each principal can issue in a burst
+    #[clap(long, default_value_t = 10, env = "PRINCIPAL_COMMAND_BURST")]
+    principal_command_burst: u32,
+
+    /// commands per second each twin can receive, from any principal (0 disables the limit)
+    #[clap(long, default_value_t = 0.0, env = "TWIN_COMMAND_RATE")]
+    twin_command_rate: f64,
+
+    /// commands each twin can receive in a burst
+    #[clap(long, default_value_t = 10, env = "TWIN_COMMAND_BURST")]
+    twin_command_burst: u32,
+}
+
+/// A token bucket, refilled continuously up to its capacity
+#[derive(Debug, Clone)]
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(capacity: f64, now: Instant) -> Self {
+        TokenBucket {
+            tokens: capacity,
+            last_refill: now,
+        }
+    }
+
+    /// Refill the bucket, returning the time to wait for the next token (zero if available)
+    fn refill(&mut self, rate: f64, capacity: f64, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(capacity);
+        self.last_refill = now;
+        if self.tokens >= 1.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((1.0 - self.tokens) / rate)
+        }
+    }
+}
+
+/// The limit a command exceeded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    Principal,
+    Twin,
+}
+
+impl Limit {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Limit::Principal => "principal",
+            Limit::Twin => "twin",
+        }
+    }
+}
+
+/// A command rejected by the rate limiter
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimited {
+    pub limit: Limit,
+    pub retry_after: Duration,
+}
+
+impl std::fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} command rate exceeded, retry in {} ms",
+            self.limit.as_str(),
+            self.retry_after.as_millis()
+        )
+    }
+}
+
+/// Number of commands accepted and rejected by the rate limiter
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RateLimitStats {
+    pub accepted: u64,
+    pub rejected_by_principal: u64,
+    pub rejected_by_twin: u64,
+}
+
+/// Rate limits for the commands, per principal and per twin, checked before the
+/// commands are dispatched to the twins. A command consumes a token from both
+/// buckets only if both have one available.
+#[derive(Debug)]
+pub struct CommandRateLimiter {
+    options: RateLimitOptions,
+    principals: HashMap<String, TokenBucket>,
+    twins: HashMap<AssetID, TokenBucket>,
+    stats: RateLimitStats,
+}
+
+/// Rate limiter shared by the network receiver and the REST server
+pub type SharedRateLimiter = Arc<Mutex<CommandRateLimiter>>;
+
+impl CommandRateLimiter {
+    pub fn new(options: RateLimitOptions) -> Self {
+        CommandRateLimiter {
+            options,
+            principals: HashMap::new(),
+            twins: HashMap::new(),
+            stats: RateLimitStats::default(),
+        }
+    }
+
+    pub fn shared(options: RateLimitOptions) -> SharedRateLimiter {
+        Arc::new(Mutex::new(CommandRateLimiter::new(options)))
+    }
+
+    /// Check whether a command from a principal to a twin can be dispatched now
+    pub fn check(
+        &mut self,
+        principal: Option<&str>,
+        twin: &AssetID,
+        now: Instant,
+    ) -> Result<(), RateLimited> {
+        let principal_limit = (
+            self.options.principal_command_rate,
+            self.options.principal_command_burst.max(1) as f64,
+        );
+        let twin_limit = (
+            self.options.twin_command_rate,
+            self.options.twin_command_burst.max(1) as f64,
+        );
+        let mut principal_bucket = None;
+        if principal_limit.0 > 0.0 {
+            let (rate, capacity) = principal_limit;
+            let bucket = self
+                .principals
+                .entry(principal.unwrap_or(ANONYMOUS).to_string())
+                .or_insert_with(|| TokenBucket::new(capacity, now));
+            let wait = bucket.refill(rate, capacity, now);
+            if !wait.is_zero() {
+                self.stats.rejected_by_principal += 1;
+                return Err(RateLimited {
+                    limit: Limit::Principal,
+                    retry_after: wait,
+                });
+            }
+            principal_bucket = Some(bucket);
+        }
+        if twin_limit.0 > 0.0 {
+            let (rate, capacity) = twin_limit;
+            let bucket = self
+                .twins
+                .entry(twin.clone())
+                .or_insert_with(|| TokenBucket::new(capacity, now));
+            let wait = bucket.refill(rate, capacity, now);
+            if !wait.is_zero() {
+                self.stats.rejected_by_twin += 1;
+                return Err(RateLimited {
+                    limit: Limit::Twin,
+                    retry_after: wait,
+                });
+            }
+            bucket.tokens -= 1.0;
+        }
+        if let Some(bucket) = principal_bucket {
+            bucket.tokens -= 1.0;
+        }
+        self.stats.accepted += 1;
+        Ok(())
+    }
+
+    pub fn stats(&self) -> RateLimitStats {
+        self.stats.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(principal: (f64, u32), twin: (f64, u32)) -> CommandRateLimiter {
+        CommandRateLimiter::new(RateLimitOptions {
+            principal_command_rate: principal.0,
+            principal_command_burst: principal.1,
+            twin_command_rate: twin.0,
+            twin_command_burst: twin.1,
+        })
+    }
+
+    #[test]
+    fn test_principal_limit() {
+        let mut limiter = limiter((1.0, 2), (0.0, 0));
+        let twin = AssetID::from("urn:twin:1");
+        let now = Instant::now();
+        assert!(limiter.check(Some("ops"), &twin, now).is_ok());
+        assert!(limiter.check(Some("ops"), &twin, now).is_ok());
+        assert_eq!(
+            limiter.check(Some("ops"), &twin, now),
+            Err(RateLimited {
+                limit: Limit::Principal,
+                retry_after: Duration::from_secs(1)
+            })
+        );
+        // Other principals have their own bucket, anonymous commands share one
+        assert!(limiter.check(Some("scheduler"), &twin, now).is_ok());
+        assert!(limiter.check(None, &twin, now).is_ok());
+        // Tokens are refilled over time
+        assert!(limiter
+            .check(Some("ops"), &twin, now + Duration::from_secs(1))
+            .is_ok());
+        assert!(limiter
+            .check(Some("ops"), &twin, now + Duration::from_secs(1))
+            .is_err());
+
+        let stats = limiter.stats();
+        assert_eq!((stats.accepted, stats.rejected_by_principal), (5, 2));
+    }
+
+    #[test]
+    fn test_twin_limit() {
+        let mut limiter = limiter((1.0, 2), (0.5, 1));
+        let (a, b) = (AssetID::from("urn:twin:a"), AssetID::from("urn:twin:b"));
+        let now = Instant::now();
+        assert!(limiter.check(Some("ops"), &a, now).is_ok());
+        assert_eq!(
+            limiter.check(Some("ops"), &a, now).map_err(|e| e.limit),
+            Err(Limit::Twin)
+        );
+        // The rejected command did not consume a principal token
+        assert!(limiter.check(Some("ops"), &b, now).is_ok());
+        assert_eq!(limiter.stats().rejected_by_twin, 1);
+        assert!(limiter
+            .check(Some("ops"), &a, now + Duration::from_secs(2))
+            .is_ok());
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use digitaltwin_core::{DeviceID, MqttUpdate};
+
+/// Drops the duplicate deliveries of QoS 1: messages whose ID was accepted recently, and
+/// updates repeating the sequence number of the previous reading of their device.
+/// Messages and updates without an ID or sequence number always pass.
+#[derive(Debug)]
+pub struct ReplayGuard {
+    /// Recent message IDs, oldest first, at most `capacity`
+    recent: VecDeque<String>,
+    seen: HashSet<String>,
+    capacity: usize,
+    /// Sequence number of the latest reading of each device. Only the latest one is
+    /// compared: a sensor restarting its numbering is not mistaken for a replay.
+    last_seq: HashMap<DeviceID, u64>,
+}
+
+impl ReplayGuard {
+    /// A guard remembering the last `capacity` message IDs; 0 disables it
+    pub fn new(capacity: usize) -> Self {
+        ReplayGuard {
+            recent: VecDeque::with_capacity(capacity),
+            seen: HashSet::with_capacity(capacity),
+            capacity,
+            last_seq: HashMap::new(),
+        }
+    }
+
+    /// Whether a message with this ID was already accepted
+    pub fn is_duplicate_message(&self, id: Option<&str>) -> bool {
+        id.is_some_and(|id| self.seen.contains(id))
+    }
+
+    /// Remember the ID of an accepted message, dropping its next deliveries. A rejected
+    /// message (e.g. a forged command) is not remembered, lest it shadow the genuine one.
+    pub fn accept_message(&mut self, id: Option<&str>) {
+        let Some(id) = id else {
+            return;
+        };
+        if self.capacity == 0 || self.seen.contains(id) {
+            return;
+        }
+        if self.recent.len() == self.capacity {
+            if let Some(oldest) = self.recent.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        self.recent.push_back(id.to_string());
+        self.seen.insert(id.to_string());
+    }
+
+    /// Whether an update repeats the previous reading of its device
+    pub fn is_duplicate_update(&mut self, update: &MqttUpdate) -> bool {
+        let Some(seq) = update.seq else {
+            return false;
+        };
+        if self.capacity == 0 {
+            return false;
+        }
+        self.last_seq.insert(update.object.clone(), seq) == Some(seq)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use digitaltwin_core::SlotValue;
+
+    fn update(seq: Option<u64>) -> MqttUpdate {
+        MqttUpdate {
+            object: "urn:iot-sensor:power-1".into(),
+            value: SlotValue::Number(7.2),
+            seq,
+        }
+    }
+
+    #[test]
+    fn test_replay_guard() {
+        let mut guard = ReplayGuard::new(2);
+        // Only the accepted messages are remembered
+        assert!(!guard.is_duplicate_message(Some("a")));
+        assert!(!guard.is_duplicate_message(Some("a")));
+        guard.accept_message(Some("a"));
+        assert!(guard.is_duplicate_message(Some("a")));
+        guard.accept_message(None);
+        assert!(!guard.is_duplicate_message(None));
+        guard.accept_message(Some("b"));
+        // "a" is evicted by "c"
+        guard.accept_message(Some("c"));
+        assert!(!guard.is_duplicate_message(Some("a")));
+        assert!(guard.is_duplicate_message(Some("b")));
+        assert!(guard.is_duplicate_message(Some("c")));
+
+        assert!(!guard.is_duplicate_update(&update(Some(5))));
+        assert!(guard.is_duplicate_update(&update(Some(5))));
+        assert!(!guard.is_duplicate_update(&update(Some(6))));
+        // A restarted sensor numbers its readings from the beginning
+        assert!(!guard.is_duplicate_update(&update(Some(0))));
+        assert!(!guard.is_duplicate_update(&update(None)));
+        assert!(!guard.is_duplicate_update(&update(None)));
+
+        let mut disabled = ReplayGuard::new(0);
+        disabled.accept_message(Some("a"));
+        assert!(!disabled.is_duplicate_message(Some("a")));
+        assert!(!disabled.is_duplicate_update(&update(Some(5))));
+        assert!(!disabled.is_duplicate_update(&update(Some(5))));
+    }
+}
@@ -0,0 +1,182 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task;
+
+use crate::history::{Aggregate, HistoryMetadata, RecordedValue};
+use crate::twin_runner::ActorMessage;
+use digitaltwin_core::AssetID;
+
+/// Status of the task of a twin
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskStatus {
+    /// Waiting for its dependencies, not spawned yet
+    Waiting,
+    /// Spawned, no heartbeat yet
+    Starting,
+    Running,
+    /// Missed too many heartbeats
+    Unhealthy,
+    /// Parked with its snapshot only, until the next message
+    Hibernated,
+    /// Terminated, not cleaned up yet by the manager
+    Exited,
+}
+
+/// Resources used by a twin. The sizes are estimates: the serialized snapshot of the actor,
+/// and the values and aggregates of its history.
+#[derive(Debug, Clone, Serialize)]
+pub struct TwinResources {
+    pub asset_id: AssetID,
+    pub task: TaskStatus,
+    /// Messages waiting in the queue of the twin
+    pub queue_length: usize,
+    pub queue_capacity: usize,
+    /// Size of the snapshot of the actor (None if the twin did not respond)
+    pub snapshot_bytes: Option<usize>,
+    /// Values and aggregates kept in the history of the slots of the twin
+    pub history_values: usize,
+    pub history_bytes: usize,
+}
+
+impl TwinResources {
+    pub fn new(asset_id: AssetID, task: TaskStatus) -> Self {
+        TwinResources {
+            asset_id,
+            task,
+            queue_length: 0,
+            queue_capacity: 0,
+            snapshot_bytes: None,
+            history_values: 0,
+            history_bytes: 0,
+        }
+    }
+
+    /// Estimate of the memory used by the twin
+    pub fn approximate_bytes(&self) -> usize {
+        self.snapshot_bytes.unwrap_or_default() + self.history_bytes
+    }
+}
+
+/// Resources used by all the twins, for capacity planning
+#[derive(Debug, Clone, Serialize)]
+pub struct ResourceReport {
+    /// Number of twins in each task status
+    pub tasks: HashMap<TaskStatus, usize>,
+    /// Messages waiting in the queues of all the twins
+    pub queued: usize,
+    /// Estimate of the memory used by all the twins
+    pub approximate_bytes: usize,
+    /// Estimate of the memory used by a twin, on average
+    pub bytes_per_twin: usize,
+    /// Resources of each twin, sorted by asset ID
+    pub twins: Vec<TwinResources>,
+}
+
+impl ResourceReport {
+    fn new(mut twins: Vec<TwinResources>) -> Self {
+        twins.sort_by(|a, b| a.asset_id.cmp(&b.asset_id));
+        let mut tasks = HashMap::new();
+        for twin in &twins {
+            *tasks.entry(twin.task).or_default() += 1;
+        }
+        let approximate_bytes = twins.iter().map(TwinResources::approximate_bytes).sum();
+        ResourceReport {
+            tasks,
+            queued: twins.iter().map(|twin| twin.queue_length).sum(),
+            approximate_bytes,
+            bytes_per_twin: approximate_bytes.checked_div(twins.len()).unwrap_or_default(),
+            twins,
+        }
+    }
+}
+
+/// Size of the history of a slot, counting the values and aggregates kept
+pub fn history_footprint(metadata: &HistoryMetadata) -> (usize, usize) {
+    let aggregates = metadata.minute_aggregates + metadata.hour_aggregates;
+    (
+        metadata.raw_values + aggregates,
+        metadata.raw_values * size_of::<RecordedValue>() + aggregates * size_of::<Aggregate>(),
+    )
+}
+
+/// Measure the queue of each twin with a channel and ask it for the snapshot of its actor,
+/// all the twins at once
+pub async fn measure(
+    twins: Vec<(TwinResources, Option<mpsc::Sender<ActorMessage>>)>,
+    timeout: Duration,
+) -> ResourceReport {
+    let mut pending = Vec::new();
+    for (mut resources, ch) in twins {
+        let Some(ch) = ch else {
+            pending.push(task::spawn(async move { resources }));
+            continue;
+        };
+        resources.queue_capacity = ch.max_capacity();
+        resources.queue_length = ch.max_capacity() - ch.capacity();
+        pending.push(task::spawn(async move {
+            let (reply, response) = oneshot::channel();
+            if ch.send(ActorMessage::Snapshot(reply)).await.is_ok() {
+                if let Ok(Ok(snapshot)) = tokio::time::timeout(timeout, response).await {
+                    resources.snapshot_bytes = serde_json::to_vec(&snapshot).ok().map(|json| json.len());
+                }
+            }
+            resources
+        }));
+    }
+    let mut twins = Vec::new();
+    for measured in pending {
+        if let Ok(resources) = measured.await {
+            twins.push(resources);
+        }
+    }
+    ResourceReport::new(twins)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_measure() {
+        let (ch, mut messages) = mpsc::channel(5);
+        tokio::spawn(async move {
+            while let Some(message) = messages.recv().await {
+                if let ActorMessage::Snapshot(reply) = message {
+                    let _ = reply.send(serde_json::json!({ "state": "On" }));
+                }
+            }
+        });
+        let (stuck, _messages) = mpsc::channel(5);
+        stuck.send(ActorMessage::Stop).await.unwrap();
+        let mut running = TwinResources::new("urn:aas:example:b".into(), TaskStatus::Running);
+        running.history_bytes = 100;
+        let twins = vec![
+            (running, Some(ch)),
+            (
+                TwinResources::new("urn:aas:example:a".into(), TaskStatus::Waiting),
+                None,
+            ),
+            (
+                TwinResources::new("urn:aas:example:c".into(), TaskStatus::Unhealthy),
+                Some(stuck),
+            ),
+        ];
+        let report = measure(twins, Duration::from_millis(50)).await;
+        let ids: Vec<&str> = report.twins.iter().map(|t| t.asset_id.as_ref()).collect();
+        assert_eq!(
+            ids,
+            ["urn:aas:example:a", "urn:aas:example:b", "urn:aas:example:c"]
+        );
+        assert_eq!(report.twins[1].snapshot_bytes, Some(14));
+        // The stuck twin did not answer, with the stop waiting in its queue
+        assert_eq!(report.twins[2].snapshot_bytes, None);
+        assert_eq!(report.twins[2].queue_length, 1);
+        assert_eq!(report.queued, 1);
+        assert_eq!(report.approximate_bytes, 114);
+        assert_eq!(report.bytes_per_twin, 38);
+        assert_eq!(report.tasks[&TaskStatus::Waiting], 1);
+    }
+}
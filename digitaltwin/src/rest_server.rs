@@ -0,0 +1,1216 @@
+use axum::extract::{DefaultBodyLimit, Path, Query as QueryParams, Request, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::{delete, get, post, put};
+use axum::{Extension, Json, Router};
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use futures_util::stream::{self, Stream};
+use log::{error, info, warn, LevelFilter};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::actuation::{Actuators, BreakerStatus};
+use crate::alerting::{Alert, SharedAlerts};
+use crate::backup::{Backup, BackupError, RestoreReport};
+use crate::command::{CommandEnvelope, CommandSource};
+use crate::commissioning::{BindingProposal, ObservedDevice, SharedCommissioning};
+use crate::diagram::StateDiagram;
+use crate::event_bus::{self, EventBus};
+use crate::failover::{self, FailoverStatus, SharedFailover};
+use crate::fleet_snapshot::FleetSnapshot;
+use crate::history::{
+    self, AggregateFunction, GroupBy, HistoryAggregation, HistorySeries, Resolution, SharedHistory,
+};
+use crate::importer::{ImportError, ImportReport, Inventory, SharedImporter};
+use crate::ingest_metrics::{IngestMetrics, SharedIngestMetrics};
+use crate::kpi::{FleetKpis, SharedKpis};
+use crate::labels::LabelSelector;
+use crate::latency_budget::{HandlerMetrics, SharedHandlerMetrics};
+use crate::manager::{GroupAck, HealthReport, LoadReport, ManagerMessage, Query, RebindError, SlotRebinding};
+use crate::rate_limit::{RateLimitStats, SharedRateLimiter};
+use crate::resources::ResourceReport;
+use crate::scheduler::{ScheduleDefinition, ScheduleError, ScheduledCommand, SharedSchedules};
+use crate::secrets::{self, SecretsProvider};
+use crate::sessions::{SessionReport, SharedSessions};
+use crate::sinks::{SharedSinkStats, SinkStats};
+use crate::smart_charging::{SharedChargingStatus, SmartChargingStatus};
+use crate::staging::StagedUpdate;
+use crate::twin_log::TwinLogReport;
+use crate::twin_runner::{AvailableActions, CommandEvaluation, CommandOutcome, TwinReport};
+use crate::ui_schema::{self, UiSchema};
+use crate::unmapped::{SharedUnmappedInputs, UnmappedReport};
+use digitaltwin_core::{
+    AssetAdministrationShell, AssetID, DeviceID, GeoLocation, OperationRequest, OperationResult, Submodel,
+    SubmodelElement, OPERATIONAL_DATA,
+};
+
+/// Maximum size of a backup sent to the REST API for a restore
+const BACKUP_SIZE_LIMIT: usize = 64 * 1024 * 1024;
+
+#[derive(Parser, Clone)]
+pub struct RestOptions {
+    /// REST API listen address
+    #[clap(long, default_value = "0.0.0.0:8080", env = "HTTP_ADDR")]
+    http_addr: String,
+}
+
+/// State of the other components exposed by the REST API
+#[derive(Clone)]
+pub struct SharedState {
+    /// Command rate limits, shared with the network receiver
+    pub rate_limiter: SharedRateLimiter,
+    /// Scheduled commands, shared with the scheduler
+    pub schedules: SharedSchedules,
+    /// Plans of the smart charging optimizer
+    pub charging_status: SharedChargingStatus,
+    /// Latency of the MQTT ingest path
+    pub ingest_metrics: SharedIngestMetrics,
+    /// Unknown devices and assets seen recently by the network receiver
+    pub unmapped: SharedUnmappedInputs,
+    /// Execution time of the handlers of the twins
+    pub handler_metrics: SharedHandlerMetrics,
+    /// Circuit breakers of the actuation backends
+    pub actuators: Arc<Actuators>,
+    /// Counters of the event sinks
+    pub sinks: SharedSinkStats,
+    /// History of the slot values, if enabled
+    pub history: Option<SharedHistory>,
+    /// Charging sessions of the twins, if enabled
+    pub sessions: Option<SharedSessions>,
+    /// Warm-standby state: a standby rejects the commands
+    pub failover: SharedFailover,
+    /// Alerts firing
+    pub alerts: SharedAlerts,
+    /// Latest KPIs of the fleet
+    pub kpis: SharedKpis,
+    /// Transitions, events, errors and lifecycle of the twins, streamed to the dashboard
+    pub bus: EventBus,
+    /// Generates twin definitions from fleet inventories
+    pub importer: SharedImporter,
+    /// Devices seen on the broker, in commissioning mode
+    pub commissioning: Option<SharedCommissioning>,
+}
+
+pub struct RestServer {
+    manager_ch: mpsc::Sender<ManagerMessage>,
+    /// Keys accepted as bearer tokens; if empty, the API is open
+    api_keys: Arc<Vec<String>>,
+    shared: SharedState,
+    /// Options
+    options: RestOptions,
+}
+
+impl RestServer {
+    pub fn new(
+        options: RestOptions,
+        manager_ch: mpsc::Sender<ManagerMessage>,
+        secrets: &SecretsProvider,
+        shared: SharedState,
+    ) -> Self {
+        let api_keys = secrets.get_list(secrets::REST_API_KEYS);
+        if api_keys.is_empty() {
+            info!("No REST API keys configured: the API is open");
+        }
+        RestServer {
+            manager_ch,
+            api_keys: Arc::new(api_keys),
+            shared,
+            options,
+        }
+    }
+
+    fn router(&self) -> Router {
+        Router::new()
+            .route("/twins", get(list_twins))
+            .route("/twins/import", post(import_twins))
+            .route("/twins/nearby", get(nearby_twins))
+            .route("/twins/{id}", get(get_twin))
+            .route("/twins/{id}/actions", get(twin_actions))
+            .route("/twins/{id}/diagram", get(twin_diagram))
+            .route("/twins/{id}/ui-schema", get(twin_ui_schema))
+            .route("/twins/{id}/sessions", get(twin_sessions))
+            .route("/twins/{id}/logs", get(twin_logs))
+            .route("/twins/{id}/log-level", put(set_log_level))
+            .route("/twins/{id}/stop", post(stop_twin))
+            .route("/twins/{id}/restart", post(restart_twin))
+            .route("/twins/{id}/slots/{slot}/rebind", post(rebind_slot))
+            .route("/twins/{id}/slots/{slot}/history", get(slot_history))
+            .route(
+                "/twins/{id}/slots/{slot}/history/aggregate",
+                get(aggregate_history),
+            )
+            .route("/load-report", get(load_report))
+            .route("/staged-updates", get(staged_updates))
+            .route("/shells/{id}", get(get_shell))
+            .route("/shells/{id}/submodels/{submodel}", get(get_submodel))
+            .route(
+                "/shells/{id}/submodels/{submodel}/operations/{operation}/invoke",
+                post(invoke_operation),
+            )
+            .route("/groups", get(list_groups))
+            .route("/groups/{group}/commands/{command}", post(broadcast_command))
+            .route("/commands/{command}", post(select_command))
+            .route("/schedules", get(list_schedules).post(add_schedule))
+            .route("/schedules/{id}", delete(remove_schedule))
+            .route("/schedules/{id}/pause", post(pause_schedule))
+            .route("/schedules/{id}/resume", post(resume_schedule))
+            .route("/smart-charging", get(smart_charging_status))
+            .route("/metrics/commands", get(command_metrics))
+            .route("/metrics/ingest", get(ingest_metrics))
+            .route("/unmapped", get(unmapped_inputs))
+            .route("/commissioning/devices", get(commissioning_devices))
+            .route("/commissioning/proposal", get(commissioning_proposal))
+            .route("/metrics/handlers", get(handler_metrics))
+            .route("/metrics/actuation", get(actuation_metrics))
+            .route("/metrics/sinks", get(sink_metrics))
+            .route("/metrics/fleet", get(fleet_kpis))
+            .route("/metrics/twins", get(twin_resources))
+            .route("/failover", get(failover_status))
+            .route("/alerts", get(list_alerts))
+            .route("/snapshot", get(fleet_snapshot))
+            .route("/backup", get(get_backup))
+            .route(
+                "/backup/restore",
+                post(restore_backup).layer(DefaultBodyLimit::max(BACKUP_SIZE_LIMIT)),
+            )
+            .route("/stream", get(event_stream))
+            .layer(Extension(self.shared.rate_limiter.clone()))
+            .layer(Extension(self.shared.schedules.clone()))
+            .layer(Extension(self.shared.charging_status.clone()))
+            .layer(Extension(self.shared.ingest_metrics.clone()))
+            .layer(Extension(self.shared.unmapped.clone()))
+            .layer(Extension(self.shared.handler_metrics.clone()))
+            .layer(Extension(self.shared.actuators.clone()))
+            .layer(Extension(self.shared.sinks.clone()))
+            .layer(Extension(self.shared.history.clone()))
+            .layer(Extension(self.shared.sessions.clone()))
+            .layer(Extension(self.shared.failover.clone()))
+            .layer(Extension(self.shared.alerts.clone()))
+            .layer(Extension(self.shared.kpis.clone()))
+            .layer(Extension(self.shared.bus.clone()))
+            .layer(Extension(self.shared.importer.clone()))
+            .layer(Extension(self.shared.commissioning.clone()))
+            // The health check stays open for probes
+            .route_layer(middleware::from_fn_with_state(
+                self.api_keys.clone(),
+                require_api_key,
+            ))
+            .route("/health", get(health))
+            // Static page: the data it shows is fetched with the API key entered by the user
+            .route("/dashboard", get(dashboard))
+            .with_state(self.manager_ch.clone())
+    }
+
+    pub async fn body(&mut self) {
+        info!("REST server body starting on {}", self.options.http_addr);
+        let listener = match tokio::net::TcpListener::bind(&self.options.http_addr).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!("Failed to bind REST server to {}: {e:?}", self.options.http_addr);
+                return;
+            }
+        };
+        if let Err(e) = axum::serve(listener, self.router()).await {
+            error!("REST server error: {e:?}");
+        }
+    }
+}
+
+/// Reject the requests without a valid "Authorization: Bearer <key>" header
+async fn require_api_key(
+    State(api_keys): State<Arc<Vec<String>>>,
+    headers: HeaderMap,
+    request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    if !api_keys.is_empty() {
+        let token = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        let valid = token.is_some_and(|token| api_keys.iter().any(|key| keys_match(key, token)));
+        if !valid {
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+    }
+    Ok(next.run(request).await)
+}
+
+/// Compare two keys in constant time
+fn keys_match(key: &str, token: &str) -> bool {
+    key.len() == token.len()
+        && key
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+/// Send a query to the manager and wait for the response
+async fn query<T>(
+    manager_ch: &mpsc::Sender<ManagerMessage>,
+    query: impl FnOnce(oneshot::Sender<T>) -> Query,
+) -> Result<T, StatusCode> {
+    let (reply, response) = oneshot::channel();
+    manager_ch
+        .send(ManagerMessage::Query(query(reply)))
+        .await
+        .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?;
+    response.await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Overall health: 503 if any twin stopped sending heartbeats
+async fn health(
+    State(manager_ch): State<mpsc::Sender<ManagerMessage>>,
+) -> Result<(StatusCode, Json<HealthReport>), StatusCode> {
+    let report = query(&manager_ch, Query::Health).await?;
+    let status = if report.unhealthy.is_empty() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    Ok((status, Json(report)))
+}
+
+/// Counters of the commands accepted and rejected by the rate limiter
+async fn command_metrics(Extension(rate_limiter): Extension<SharedRateLimiter>) -> Json<RateLimitStats> {
+    Json(rate_limiter.lock().unwrap_or_else(|e| e.into_inner()).stats())
+}
+
+/// Latency histograms of the MQTT ingest path
+async fn ingest_metrics(Extension(metrics): Extension<SharedIngestMetrics>) -> Json<IngestMetrics> {
+    Json(metrics.lock().unwrap_or_else(|e| e.into_inner()).clone())
+}
+
+/// Unknown devices and assets seen recently in the inputs, to find the wiring gaps
+async fn unmapped_inputs(Extension(unmapped): Extension<SharedUnmappedInputs>) -> Json<UnmappedReport> {
+    Json(
+        unmapped
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .report(Utc::now()),
+    )
+}
+
+/// Devices seen on the broker in commissioning mode, with the range of their values
+async fn commissioning_devices(
+    Extension(commissioning): Extension<Option<SharedCommissioning>>,
+) -> Result<Json<Vec<ObservedDevice>>, (StatusCode, String)> {
+    let commissioning =
+        commissioning.ok_or_else(|| (StatusCode::NOT_FOUND, "commissioning mode disabled".to_string()))?;
+    let devices = commissioning.lock().unwrap_or_else(|e| e.into_inner()).devices();
+    Ok(Json(devices))
+}
+
+#[derive(Deserialize)]
+struct ProposalParams {
+    twin_type: String,
+    asset_id: AssetID,
+    /// "yaml" for the definition of the twin only
+    format: Option<String>,
+}
+
+/// Data sources proposed for a new twin from the devices seen in commissioning mode, e.g.
+/// "?twin_type=ChargingPoint&asset_id=urn:aas:site:cp-7&format=yaml"
+async fn commissioning_proposal(
+    Extension(commissioning): Extension<Option<SharedCommissioning>>,
+    QueryParams(params): QueryParams<ProposalParams>,
+) -> Result<Response, (StatusCode, String)> {
+    let commissioning =
+        commissioning.ok_or_else(|| (StatusCode::NOT_FOUND, "commissioning mode disabled".to_string()))?;
+    let proposal: BindingProposal = commissioning
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .propose(&params.twin_type, params.asset_id)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    Ok(match params.format.as_deref() {
+        Some("yaml") => ([(header::CONTENT_TYPE, "application/yaml")], proposal.yaml).into_response(),
+        _ => Json(proposal).into_response(),
+    })
+}
+
+/// Execution time of the handlers of each twin, with the overruns of its latency budget
+async fn handler_metrics(
+    Extension(metrics): Extension<SharedHandlerMetrics>,
+) -> Json<BTreeMap<AssetID, HandlerMetrics>> {
+    Json(metrics.lock().unwrap_or_else(|e| e.into_inner()).clone())
+}
+
+/// Circuit breaker of the actuation backends of each twin that actuated a device
+async fn actuation_metrics(
+    Extension(actuators): Extension<Arc<Actuators>>,
+) -> Json<BTreeMap<AssetID, BreakerStatus>> {
+    Json(actuators.breakers())
+}
+
+/// Queue, task status and estimated memory of each twin
+async fn twin_resources(
+    State(manager_ch): State<mpsc::Sender<ManagerMessage>>,
+) -> Result<Json<ResourceReport>, (StatusCode, String)> {
+    query(&manager_ch, Query::Resources)
+        .await
+        .map(Json)
+        .map_err(|status| (status, "manager not available".into()))
+}
+
+async fn sink_metrics(Extension(sinks): Extension<SharedSinkStats>) -> Json<BTreeMap<String, SinkStats>> {
+    Json(sinks.lock().unwrap_or_else(|e| e.into_inner()).clone())
+}
+
+#[derive(Deserialize)]
+struct HistoryParams {
+    /// Start of the range: an RFC 3339 time, "now" or a time ago (e.g., "-7d"); one hour
+    /// before the end by default
+    from: Option<String>,
+    /// End of the range, now by default
+    to: Option<String>,
+    /// "raw", "minute" or "hour"; by default the finest resolution still kept at the start
+    resolution: Option<Resolution>,
+    /// "min", "max", "avg" or "last" (aggregation only)
+    function: Option<AggregateFunction>,
+    /// "state" to aggregate the values of each state separately (aggregation only)
+    group_by: Option<GroupBy>,
+}
+
+impl HistoryParams {
+    /// The time range of the query
+    fn range(&self, now: DateTime<Utc>) -> Result<(DateTime<Utc>, DateTime<Utc>), (StatusCode, String)> {
+        let parse = |time: &str| history::parse_time(time, now).map_err(|e| (StatusCode::BAD_REQUEST, e));
+        let to = self.to.as_deref().map(parse).transpose()?.unwrap_or(now);
+        let from = match self.from.as_deref() {
+            Some(from) => parse(from)?,
+            None => to - chrono::TimeDelta::hours(1),
+        };
+        Ok((from, to))
+    }
+}
+
+/// Values of a slot of a twin over a time range, e.g. "?from=-1d&resolution=hour"
+async fn slot_history(
+    Extension(history): Extension<Option<SharedHistory>>,
+    Path((id, slot)): Path<(AssetID, String)>,
+    QueryParams(params): QueryParams<HistoryParams>,
+) -> Result<Json<HistorySeries>, (StatusCode, String)> {
+    let not_found = || (StatusCode::NOT_FOUND, format!("no history of {slot}"));
+    let history = history.ok_or_else(not_found)?;
+    let now = Utc::now();
+    let (from, to) = params.range(now)?;
+    let mut history = history.lock().unwrap_or_else(|e| e.into_inner());
+    history
+        .query(&id, &slot, from, to, params.resolution, now)
+        .map(Json)
+        .ok_or_else(not_found)
+}
+
+/// Aggregate of the values of a slot of a twin over a time range, e.g. the average power
+/// while charging in the last week: "?from=-7d&function=avg&group_by=state"
+async fn aggregate_history(
+    Extension(history): Extension<Option<SharedHistory>>,
+    Path((id, slot)): Path<(AssetID, String)>,
+    QueryParams(params): QueryParams<HistoryParams>,
+) -> Result<Json<HistoryAggregation>, (StatusCode, String)> {
+    let not_found = || (StatusCode::NOT_FOUND, format!("no history of {slot}"));
+    let history = history.ok_or_else(not_found)?;
+    let function = params
+        .function
+        .ok_or((StatusCode::BAD_REQUEST, "missing function".to_string()))?;
+    let now = Utc::now();
+    let (from, to) = params.range(now)?;
+    let mut history = history.lock().unwrap_or_else(|e| e.into_inner());
+    history
+        .aggregate(
+            &id,
+            &slot,
+            from,
+            to,
+            params.resolution,
+            function,
+            params.group_by,
+            now,
+        )
+        .map(Json)
+        .ok_or_else(not_found)
+}
+
+#[derive(Deserialize)]
+struct SessionParams {
+    /// Sessions started after this time: an RFC 3339 time or a time ago (e.g., "-7d")
+    from: Option<String>,
+    /// Sessions started before this time
+    to: Option<String>,
+}
+
+/// Charging sessions of a twin, with their energy, e.g. "?from=-7d"
+async fn twin_sessions(
+    Extension(sessions): Extension<Option<SharedSessions>>,
+    Path(id): Path<AssetID>,
+    QueryParams(params): QueryParams<SessionParams>,
+) -> Result<Json<SessionReport>, (StatusCode, String)> {
+    let not_found = || (StatusCode::NOT_FOUND, format!("no sessions of {id}"));
+    let sessions = sessions.ok_or_else(not_found)?;
+    let now = Utc::now();
+    let parse = |time: &str| history::parse_time(time, now).map_err(|e| (StatusCode::BAD_REQUEST, e));
+    let from = params.from.as_deref().map(parse).transpose()?;
+    let to = params.to.as_deref().map(parse).transpose()?;
+    let sessions = sessions.lock().unwrap_or_else(|e| e.into_inner());
+    sessions.report(&id, from, to).map(Json).ok_or_else(not_found)
+}
+
+/// Role of the instance, and whether it is the active one
+async fn failover_status(Extension(failover): Extension<SharedFailover>) -> Json<FailoverStatus> {
+    Json(failover.lock().unwrap_or_else(|e| e.into_inner()).status())
+}
+
+/// Alerts firing, sorted by rule and asset ID
+async fn list_alerts(Extension(alerts): Extension<SharedAlerts>) -> Json<Vec<Alert>> {
+    Json(alerts.lock().unwrap_or_else(|e| e.into_inner()).active())
+}
+
+/// Latest KPIs of the fleet, computed periodically
+async fn fleet_kpis(Extension(kpis): Extension<SharedKpis>) -> Json<FleetKpis> {
+    Json(kpis.lock().unwrap_or_else(|e| e.into_inner()).clone())
+}
+
+/// Definitions, snapshots, subscriptions and history metadata of the running twins
+/// States of all the running twins at the same instant: the twins are paused while they are
+/// captured, so that the states are never from different instants
+async fn fleet_snapshot(
+    State(manager_ch): State<mpsc::Sender<ManagerMessage>>,
+) -> Result<Json<FleetSnapshot>, (StatusCode, String)> {
+    query(&manager_ch, Query::FleetSnapshot)
+        .await
+        .map(Json)
+        .map_err(|status| (status, "manager not available".into()))
+}
+
+async fn get_backup(
+    State(manager_ch): State<mpsc::Sender<ManagerMessage>>,
+) -> Result<Json<Backup>, (StatusCode, String)> {
+    query(&manager_ch, Query::Backup)
+        .await
+        .map_err(|status| (status, "manager not available".into()))?
+        .map(Json)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+/// Restore a backup: write its definitions, reload them and restore the twins from their
+/// snapshots. The definitions of the backup replace the files with the same name.
+async fn restore_backup(
+    State(manager_ch): State<mpsc::Sender<ManagerMessage>>,
+    Json(backup): Json<Backup>,
+) -> Result<Json<RestoreReport>, (StatusCode, String)> {
+    let (reply, response) = oneshot::channel();
+    manager_ch
+        .send(ManagerMessage::Restore(Box::new(backup), reply))
+        .await
+        .map_err(|_| (StatusCode::SERVICE_UNAVAILABLE, "manager not available".into()))?;
+    let report = response
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "no response from the manager".into(),
+            )
+        })?
+        .map_err(|e| {
+            let status = match e {
+                BackupError::Io(..) | BackupError::Http(_) => StatusCode::INTERNAL_SERVER_ERROR,
+                BackupError::Version(_) | BackupError::FileName(_) | BackupError::Invalid(_) => {
+                    StatusCode::BAD_REQUEST
+                }
+            };
+            (status, e.to_string())
+        })?;
+    info!(
+        "Backup restored: {} twins restored, {} pending, {} failed",
+        report.restored.len(),
+        report.pending.len(),
+        report.failed.len()
+    );
+    Ok(Json(report))
+}
+
+/// Server-sent events of the twins from the event bus, named after their kind ("transition",
+/// "event", "error" and "lifecycle"). A slow client misses the oldest ones.
+async fn event_stream(
+    Extension(bus): Extension<EventBus>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let stream = stream::unfold(bus.subscribe(), |mut receiver| async move {
+        let event = event_bus::next_event(&mut receiver, "Event stream").await?;
+        let event = Event::default()
+            .event(event.kind())
+            .json_data(event.to_json())
+            .unwrap_or_default();
+        Some((Ok(event), receiver))
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+/// Built-in dashboard listing the twins, their states and the recent transitions
+async fn dashboard() -> Html<&'static str> {
+    Html(include_str!("dashboard.html"))
+}
+
+#[derive(Deserialize)]
+struct SelectorParams {
+    /// Label selector, e.g. "site=hq, type=charging-station"
+    selector: Option<String>,
+}
+
+impl SelectorParams {
+    fn selector(&self) -> Result<Option<LabelSelector>, (StatusCode, String)> {
+        self.selector
+            .as_deref()
+            .map(str::parse)
+            .transpose()
+            .map_err(|e| (StatusCode::BAD_REQUEST, e))
+    }
+}
+
+/// All the twins, or the ones whose labels match the selector: "?selector=site%3Dhq"
+async fn list_twins(
+    State(manager_ch): State<mpsc::Sender<ManagerMessage>>,
+    QueryParams(params): QueryParams<SelectorParams>,
+) -> Result<Json<Vec<TwinReport>>, (StatusCode, String)> {
+    let selector = params.selector()?;
+    let mut twins = query(&manager_ch, Query::ListTwins)
+        .await
+        .map_err(|status| (status, String::new()))?;
+    if let Some(selector) = selector {
+        twins.retain(|twin| selector.matches(&twin.labels));
+    }
+    Ok(Json(twins))
+}
+
+#[derive(Deserialize)]
+struct NearbyParams {
+    /// Center of the search, in decimal degrees
+    lat: f64,
+    lon: f64,
+    /// Radius of the search, in kilometers
+    radius_km: f64,
+}
+
+/// A twin found by a spatial query, with its distance from the center
+#[derive(Serialize)]
+struct NearbyTwin {
+    distance_km: f64,
+    #[serde(flatten)]
+    twin: TwinReport,
+}
+
+/// Twins located within a radius of a point, nearest first: "?lat=45.46&lon=9.19&radius_km=5"
+async fn nearby_twins(
+    State(manager_ch): State<mpsc::Sender<ManagerMessage>>,
+    QueryParams(params): QueryParams<NearbyParams>,
+) -> Result<Json<Vec<NearbyTwin>>, (StatusCode, String)> {
+    let center = GeoLocation::new(params.lat, params.lon)
+        .ok_or((StatusCode::BAD_REQUEST, "coordinates out of range".to_string()))?;
+    let twins = query(&manager_ch, Query::ListTwins)
+        .await
+        .map_err(|status| (status, String::new()))?;
+    let mut nearby: Vec<NearbyTwin> = twins
+        .into_iter()
+        .filter_map(|twin| {
+            let distance_km = twin.location?.distance_km(&center);
+            (distance_km <= params.radius_km).then_some(NearbyTwin { distance_km, twin })
+        })
+        .collect();
+    nearby.sort_by(|a, b| a.distance_km.total_cmp(&b.distance_km));
+    Ok(Json(nearby))
+}
+
+/// Twins loaded and definitions that failed at the latest (re)load
+async fn load_report(
+    State(manager_ch): State<mpsc::Sender<ManagerMessage>>,
+) -> Result<Json<LoadReport>, StatusCode> {
+    query(&manager_ch, Query::LoadReport).await.map(Json)
+}
+
+/// Latest staged updates of the twin definitions, oldest first
+async fn staged_updates(
+    State(manager_ch): State<mpsc::Sender<ManagerMessage>>,
+) -> Result<Json<Vec<StagedUpdate>>, StatusCode> {
+    query(&manager_ch, Query::StagedUpdates).await.map(Json)
+}
+
+async fn get_twin(
+    State(manager_ch): State<mpsc::Sender<ManagerMessage>>,
+    Path(id): Path<AssetID>,
+) -> Result<Json<TwinReport>, StatusCode> {
+    query(&manager_ch, |reply| Query::Twin(id, reply))
+        .await?
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Commands and slots handled by a twin in its current state
+async fn twin_actions(
+    State(manager_ch): State<mpsc::Sender<ManagerMessage>>,
+    Path(id): Path<AssetID>,
+) -> Result<Json<AvailableActions>, StatusCode> {
+    query(&manager_ch, |reply| Query::Actions(id, reply))
+        .await?
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+#[derive(Deserialize)]
+struct DiagramParams {
+    /// "dot" for the diagram in the DOT language of Graphviz
+    format: Option<String>,
+}
+
+/// Live diagram of the state machines of a twin: its states and declared transitions,
+/// with the current state and the recent path, e.g. "?format=dot | dot -Tsvg".
+/// The declared transitions are the ones made by the handlers of a state, directly or
+/// through the methods of the same impl block: the ones made by helpers shared by all the
+/// states (e.g., `evaluate` of WaterPump) only show up as observed once they happen.
+async fn twin_diagram(
+    State(manager_ch): State<mpsc::Sender<ManagerMessage>>,
+    Path(id): Path<AssetID>,
+    QueryParams(params): QueryParams<DiagramParams>,
+) -> Result<Response, StatusCode> {
+    let diagram: StateDiagram = query(&manager_ch, |reply| Query::Diagram(id, reply))
+        .await?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    Ok(match params.format.as_deref() {
+        Some("dot") => ([(header::CONTENT_TYPE, "text/vnd.graphviz")], diagram.to_dot()).into_response(),
+        _ => Json(diagram).into_response(),
+    })
+}
+
+/// Control panel of a twin for generic frontends: forms for the operations of its AAS,
+/// enabled if handled in the current state, and widgets for its slots
+async fn twin_ui_schema(
+    State(manager_ch): State<mpsc::Sender<ManagerMessage>>,
+    Path(id): Path<AssetID>,
+) -> Result<Json<UiSchema>, StatusCode> {
+    let aas = query(&manager_ch, |reply| Query::Shell(id.clone(), reply))
+        .await?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let report = query(&manager_ch, |reply| Query::Twin(id.clone(), reply))
+        .await?
+        .ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+    let actions = query(&manager_ch, |reply| Query::Actions(id, reply))
+        .await?
+        .ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+    Ok(Json(ui_schema::ui_schema(&aas, &report, &actions)))
+}
+
+/// Log verbosity and recent log lines of a twin
+async fn twin_logs(
+    State(manager_ch): State<mpsc::Sender<ManagerMessage>>,
+    Path(id): Path<AssetID>,
+) -> Result<Json<TwinLogReport>, StatusCode> {
+    query(&manager_ch, |reply| Query::Logs(id, reply))
+        .await?
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+#[derive(Deserialize)]
+struct LogLevelRequest {
+    /// "off", "error", "warn", "info", "debug" or "trace"; null resets the twin to the global level
+    level: Option<String>,
+}
+
+/// Set the log verbosity of a twin, e.g. {"level": "trace"}
+async fn set_log_level(
+    State(manager_ch): State<mpsc::Sender<ManagerMessage>>,
+    Path(id): Path<AssetID>,
+    Json(request): Json<LogLevelRequest>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let level = request
+        .level
+        .map(|level| level.parse::<LevelFilter>())
+        .transpose()
+        .map_err(|_| (StatusCode::BAD_REQUEST, "invalid log level".to_string()))?;
+    let known = query(&manager_ch, |reply| Query::SetLogLevel(id, level, reply))
+        .await
+        .map_err(|status| (status, String::new()))?;
+    if known {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err((StatusCode::NOT_FOUND, String::new()))
+    }
+}
+
+/// Send a stop or restart request to the manager, if the twin is running
+async fn request_stop(
+    manager_ch: &mpsc::Sender<ManagerMessage>,
+    id: AssetID,
+    message: fn(AssetID) -> ManagerMessage,
+) -> StatusCode {
+    match query(manager_ch, |reply| Query::Shell(id.clone(), reply)).await {
+        Ok(Some(_)) => match manager_ch.send(message(id)).await {
+            Ok(()) => StatusCode::ACCEPTED,
+            Err(_) => StatusCode::SERVICE_UNAVAILABLE,
+        },
+        Ok(None) => StatusCode::NOT_FOUND,
+        Err(status) => status,
+    }
+}
+
+/// Stop a twin gracefully, saving its final snapshot
+async fn stop_twin(
+    State(manager_ch): State<mpsc::Sender<ManagerMessage>>,
+    Path(id): Path<AssetID>,
+) -> StatusCode {
+    request_stop(&manager_ch, id, ManagerMessage::StopTwin).await
+}
+
+#[derive(Deserialize)]
+struct RebindRequest {
+    device_id: DeviceID,
+}
+
+#[derive(Serialize)]
+struct RebindResponse {
+    slot: String,
+    previous: DeviceID,
+    device_id: DeviceID,
+}
+
+/// Bind a slot of a running twin to another sensor, e.g. {"device_id": "urn:iot-sensor:current456"},
+/// until the runtime restarts. The "X-Principal" header is recorded in the audit log.
+async fn rebind_slot(
+    State(manager_ch): State<mpsc::Sender<ManagerMessage>>,
+    Path((id, slot)): Path<(AssetID, String)>,
+    headers: HeaderMap,
+    Json(request): Json<RebindRequest>,
+) -> Result<Json<RebindResponse>, (StatusCode, String)> {
+    let rebinding = SlotRebinding {
+        twin: id,
+        slot: slot.clone(),
+        device_id: request.device_id.clone(),
+        principal: headers
+            .get("x-principal")
+            .and_then(|h| h.to_str().ok())
+            .map(str::to_string),
+    };
+    let (reply, response) = oneshot::channel();
+    manager_ch
+        .send(ManagerMessage::RebindSlot(rebinding, reply))
+        .await
+        .map_err(|_| (StatusCode::SERVICE_UNAVAILABLE, "manager not available".into()))?;
+    let previous = response
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "no response from the manager".into(),
+            )
+        })?
+        .map_err(|e| {
+            let status = match e {
+                RebindError::NotFound(_) => StatusCode::NOT_FOUND,
+                RebindError::NotRunning(_) => StatusCode::CONFLICT,
+                RebindError::Invalid(_) => StatusCode::BAD_REQUEST,
+            };
+            (status, e.to_string())
+        })?;
+    Ok(Json(RebindResponse {
+        slot,
+        previous,
+        device_id: request.device_id,
+    }))
+}
+
+#[derive(Deserialize)]
+struct ImportRequest {
+    /// Name of a template in the templates directory
+    template: String,
+    /// CSV text with a header row, or an array of objects
+    inventory: Inventory,
+}
+
+/// Create a twin for each asset of a fleet inventory, e.g.
+/// {"template": "station", "inventory": [{"station_id": "000101"}]}, and reload the definitions
+/// to start them. Rows that cannot be imported are reported, not applied.
+async fn import_twins(
+    State(manager_ch): State<mpsc::Sender<ManagerMessage>>,
+    Extension(importer): Extension<SharedImporter>,
+    Json(request): Json<ImportRequest>,
+) -> Result<(StatusCode, Json<ImportReport>), (StatusCode, String)> {
+    // Templates are only looked up by name, never by path
+    if request.template.contains(['/', '\\']) || request.template.starts_with('.') {
+        return Err((StatusCode::BAD_REQUEST, "invalid template name".into()));
+    }
+    let rows = request
+        .inventory
+        .rows()
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    let report = tokio::task::spawn_blocking(move || importer.import(&request.template, &rows))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map_err(|e| {
+            let status = match e {
+                ImportError::TemplateNotFound(_) => StatusCode::NOT_FOUND,
+                ImportError::Io(..) => StatusCode::INTERNAL_SERVER_ERROR,
+                ImportError::InvalidInventory(_) => StatusCode::BAD_REQUEST,
+            };
+            (status, e.to_string())
+        })?;
+    if report.created.is_empty() {
+        return Ok((StatusCode::OK, Json(report)));
+    }
+    manager_ch
+        .send(ManagerMessage::Reload)
+        .await
+        .map_err(|_| (StatusCode::SERVICE_UNAVAILABLE, "manager not available".into()))?;
+    Ok((StatusCode::CREATED, Json(report)))
+}
+
+/// Stop a twin gracefully and start it again from its definition and its final snapshot
+async fn restart_twin(
+    State(manager_ch): State<mpsc::Sender<ManagerMessage>>,
+    Path(id): Path<AssetID>,
+) -> StatusCode {
+    request_stop(&manager_ch, id, ManagerMessage::RestartTwin).await
+}
+
+/// The AAS of a twin, with the live "OperationalData" submodel if the twin is responding
+async fn get_shell(
+    State(manager_ch): State<mpsc::Sender<ManagerMessage>>,
+    Path(id): Path<AssetID>,
+) -> Result<Json<AssetAdministrationShell>, StatusCode> {
+    let mut aas = query(&manager_ch, |reply| Query::Shell(id.clone(), reply))
+        .await?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    if let Some(report) = query(&manager_ch, |reply| Query::Twin(id, reply)).await? {
+        let operational_data = aas.operational_data(&report.state, &report.slot_values);
+        aas.submodels.push(operational_data);
+    }
+    Ok(Json(aas))
+}
+
+/// A single submodel of a twin, by id_short. "OperationalData" holds the live data.
+async fn get_submodel(
+    State(manager_ch): State<mpsc::Sender<ManagerMessage>>,
+    Path((id, submodel)): Path<(AssetID, String)>,
+) -> Result<Json<Submodel>, StatusCode> {
+    let aas = query(&manager_ch, |reply| Query::Shell(id.clone(), reply))
+        .await?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    if submodel == OPERATIONAL_DATA {
+        let report = query(&manager_ch, |reply| Query::Twin(id, reply))
+            .await?
+            .ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+        return Ok(Json(aas.operational_data(&report.state, &report.slot_values)));
+    }
+    aas.submodels
+        .into_iter()
+        .find(|s| s.id_short == submodel)
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// The scheduled commands, with their next run
+async fn list_schedules(Extension(schedules): Extension<SharedSchedules>) -> Json<Vec<ScheduledCommand>> {
+    let now = chrono::Local::now().naive_local();
+    Json(schedules.lock().unwrap_or_else(|e| e.into_inner()).list(&now))
+}
+
+fn schedule_error(e: ScheduleError) -> (StatusCode, String) {
+    let status = match e {
+        ScheduleError::NotFound(_) => StatusCode::NOT_FOUND,
+        ScheduleError::Exists(_) | ScheduleError::ReadOnly(..) => StatusCode::CONFLICT,
+        ScheduleError::InvalidCron(..) | ScheduleError::InvalidTarget(_) => StatusCode::BAD_REQUEST,
+    };
+    (status, e.to_string())
+}
+
+/// Add a schedule, until the runtime restarts
+async fn add_schedule(
+    Extension(schedules): Extension<SharedSchedules>,
+    Json(definition): Json<ScheduleDefinition>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let id = definition.id.clone();
+    schedules
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .add(definition)
+        .map_err(schedule_error)?;
+    info!("Schedule {id} added");
+    Ok(StatusCode::CREATED)
+}
+
+/// Remove a schedule added through the API; the others can only be paused
+async fn remove_schedule(
+    Extension(schedules): Extension<SharedSchedules>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    schedules
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .remove(&id)
+        .map_err(schedule_error)?;
+    info!("Schedule {id} removed");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn pause_schedule(
+    Extension(schedules): Extension<SharedSchedules>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    set_schedule_enabled(&schedules, &id, false)
+}
+
+async fn resume_schedule(
+    Extension(schedules): Extension<SharedSchedules>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    set_schedule_enabled(&schedules, &id, true)
+}
+
+fn set_schedule_enabled(
+    schedules: &SharedSchedules,
+    id: &str,
+    enabled: bool,
+) -> Result<StatusCode, (StatusCode, String)> {
+    schedules
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .set_enabled(id, enabled)
+        .map_err(schedule_error)?;
+    info!("Schedule {id} {}", if enabled { "resumed" } else { "paused" });
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Electricity prices and charging plans of the smart charging optimizer
+async fn smart_charging_status(
+    Extension(status): Extension<SharedChargingStatus>,
+) -> Json<SmartChargingStatus> {
+    Json(status.lock().unwrap_or_else(|e| e.into_inner()).clone())
+}
+
+/// Command groups and their members
+async fn list_groups(
+    State(manager_ch): State<mpsc::Sender<ManagerMessage>>,
+) -> Result<Json<BTreeMap<String, Vec<AssetID>>>, StatusCode> {
+    query(&manager_ch, Query::Groups).await.map(Json)
+}
+
+/// A REST command, identified by the optional "Idempotency-Key", "X-Principal" and
+/// "X-Correlation-ID" headers
+fn command_envelope(headers: &HeaderMap, command: String, args: serde_json::Value) -> CommandEnvelope {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|h| h.to_str().ok())
+            .map(str::to_string)
+    };
+    CommandEnvelope::new(CommandSource::Rest, command, args)
+        .with_idempotency_key(header("idempotency-key"))
+        .with_principal(header("x-principal"))
+        .with_correlation_id(header("x-correlation-id"))
+}
+
+/// Send a command to all the members of a group, with the JSON body as args, and
+/// wait for the outcome of each member. Members over their rate limit are skipped.
+/// Accepts the same headers as the operation invocations.
+async fn broadcast_command(
+    State(manager_ch): State<mpsc::Sender<ManagerMessage>>,
+    Extension(rate_limiter): Extension<SharedRateLimiter>,
+    Extension(failover): Extension<SharedFailover>,
+    Path((group, command)): Path<(String, String)>,
+    headers: HeaderMap,
+    Json(args): Json<serde_json::Value>,
+) -> Result<Json<GroupAck>, StatusCode> {
+    // Commands are executed by the active instance only
+    if !failover::is_active(&failover) {
+        return Err(StatusCode::SERVICE_UNAVAILABLE);
+    }
+    let members = query(&manager_ch, |reply| Query::GroupMembers(group.clone(), reply)).await?;
+    if members.is_empty() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let envelope = command_envelope(&headers, command, args);
+    info!("Sending command {} to group {group}", envelope.command);
+    broadcast(&manager_ch, &rate_limiter, group, members, envelope)
+        .await
+        .map(Json)
+}
+
+/// Send a command to all the twins whose labels match the selector, as to a group named
+/// after the selector: "/commands/Reset?selector=site%3Dhq"
+async fn select_command(
+    State(manager_ch): State<mpsc::Sender<ManagerMessage>>,
+    Extension(rate_limiter): Extension<SharedRateLimiter>,
+    Extension(failover): Extension<SharedFailover>,
+    Path(command): Path<String>,
+    QueryParams(params): QueryParams<SelectorParams>,
+    headers: HeaderMap,
+    Json(args): Json<serde_json::Value>,
+) -> Result<Json<GroupAck>, (StatusCode, String)> {
+    if !failover::is_active(&failover) {
+        return Err((StatusCode::SERVICE_UNAVAILABLE, "standby instance".to_string()));
+    }
+    let selector = params
+        .selector()?
+        .ok_or((StatusCode::BAD_REQUEST, "missing selector".to_string()))?;
+    let members = query(&manager_ch, |reply| Query::Select(selector.clone(), reply))
+        .await
+        .map_err(|status| (status, String::new()))?;
+    if members.is_empty() {
+        return Err((StatusCode::NOT_FOUND, format!("no twin matches {selector}")));
+    }
+    let envelope = command_envelope(&headers, command, args);
+    info!(
+        "Sending command {} to the twins matching {selector}",
+        envelope.command
+    );
+    broadcast(
+        &manager_ch,
+        &rate_limiter,
+        selector.to_string(),
+        members,
+        envelope,
+    )
+    .await
+    .map(Json)
+    .map_err(|status| (status, String::new()))
+}
+
+/// Send a command to the members of a group and wait for their outcomes, skipping the
+/// members over their rate limit
+async fn broadcast(
+    manager_ch: &mpsc::Sender<ManagerMessage>,
+    rate_limiter: &SharedRateLimiter,
+    group: String,
+    members: Vec<AssetID>,
+    envelope: CommandEnvelope,
+) -> Result<GroupAck, StatusCode> {
+    let now = Instant::now();
+    let (admitted, limited): (Vec<_>, Vec<_>) = {
+        let mut rate_limiter = rate_limiter.lock().unwrap_or_else(|e| e.into_inner());
+        members
+            .into_iter()
+            .partition(|id| rate_limiter.check(envelope.principal.as_deref(), id, now).is_ok())
+    };
+    if !limited.is_empty() {
+        info!("{} members of {group} over their rate limit", limited.len());
+    }
+    let mut ack = query(manager_ch, |reply| {
+        Query::Broadcast(group, admitted, envelope, reply)
+    })
+    .await?;
+    for id in limited {
+        ack.record(id, "rate_limited");
+    }
+    Ok(ack)
+}
+
+/// Invoke an AAS operation, executing the twin command with the same name. The
+/// operation path is made of id_shorts separated by dots, as for nested collections.
+/// Optional headers: "Idempotency-Key" protects against repeated invocations,
+/// "X-Principal" identifies the issuer and "X-Correlation-ID" the originating request.
+#[derive(Deserialize)]
+struct InvokeParams {
+    /// Evaluate the operation without executing it
+    #[serde(default, rename = "dryRun")]
+    dry_run: bool,
+}
+
+async fn invoke_operation(
+    State(manager_ch): State<mpsc::Sender<ManagerMessage>>,
+    Extension(rate_limiter): Extension<SharedRateLimiter>,
+    Extension(failover): Extension<SharedFailover>,
+    Path((id, submodel, operation)): Path<(AssetID, String, String)>,
+    QueryParams(params): QueryParams<InvokeParams>,
+    headers: HeaderMap,
+    Json(request): Json<OperationRequest>,
+) -> Result<Response, StatusCode> {
+    if !failover::is_active(&failover) {
+        return Err(StatusCode::SERVICE_UNAVAILABLE);
+    }
+    let aas = query(&manager_ch, |reply| Query::Shell(id.clone(), reply))
+        .await?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let Some(SubmodelElement::Operation(op)) = aas.find_element(&submodel, &operation) else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    let args = op.command_args(&request);
+    if params.dry_run {
+        return evaluate_operation(&manager_ch, id, &headers, op.id_short.clone(), args)
+            .await
+            .map(|evaluation| Json(evaluation).into_response());
+    }
+    let args = match args {
+        Ok(args) => args,
+        Err(e) => return Ok((StatusCode::BAD_REQUEST, Json(OperationResult::failed(e))).into_response()),
+    };
+    let envelope = command_envelope(&headers, op.id_short.clone(), args);
+    let limited = rate_limiter.lock().unwrap_or_else(|e| e.into_inner()).check(
+        envelope.principal.as_deref(),
+        &id,
+        Instant::now(),
+    );
+    if let Err(rejection) = limited {
+        warn!(
+            "Rejected command {} for asset {id}: {rejection}",
+            envelope.command
+        );
+        return Ok((
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(OperationResult::failed(rejection.to_string())),
+        )
+            .into_response());
+    }
+    let outcome = query(&manager_ch, |reply| Query::Invoke(id, envelope, reply))
+        .await?
+        .ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+    let response = match outcome {
+        CommandOutcome::Executed(result) => (
+            StatusCode::OK,
+            Json(OperationResult::completed(op.output_arguments(&result))),
+        ),
+        CommandOutcome::Duplicate(result) => (
+            StatusCode::OK,
+            Json(OperationResult::completed(op.output_arguments(&result)).with_info("already executed")),
+        ),
+        CommandOutcome::Unauthorized => (
+            StatusCode::FORBIDDEN,
+            Json(OperationResult::failed("not allowed to invoke this operation")),
+        ),
+        CommandOutcome::CoolingDown(remaining) => (
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(OperationResult::failed(format!(
+                "cooling down, retry in {} ms",
+                remaining.as_millis()
+            ))),
+        ),
+    };
+    Ok(response.into_response())
+}
+
+/// Evaluate an operation without executing it: the twin reports the transition the
+/// command would make. Invalid arguments are reported without asking the twin.
+async fn evaluate_operation(
+    manager_ch: &mpsc::Sender<ManagerMessage>,
+    id: AssetID,
+    headers: &HeaderMap,
+    command: String,
+    args: Result<serde_json::Value, String>,
+) -> Result<CommandEvaluation, StatusCode> {
+    match args {
+        Ok(args) => {
+            let envelope = command_envelope(headers, command, args);
+            query(manager_ch, |reply| Query::Evaluate(id, envelope, reply))
+                .await?
+                .ok_or(StatusCode::SERVICE_UNAVAILABLE)
+        }
+        Err(e) => {
+            let envelope = command_envelope(headers, command, serde_json::Value::Null);
+            Ok(CommandEvaluation::rejected(&envelope, e))
+        }
+    }
+}
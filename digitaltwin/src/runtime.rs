@@ -0,0 +1,260 @@
+use log::info;
+use std::sync::Arc;
+use thiserror::Error as ThisError;
+use tokio::join;
+use tokio::sync::mpsc;
+
+use crate::config::RuntimeConfig;
+use crate::sinks::{EventSink, Sinks};
+use crate::{
+    actuation, alerting, commissioning, event_bus, failover, federation, historian, history, importer, ipc,
+    kpi, manager, network_receiver, outbox, rate_limit, rest_server, scheduler, secrets, sessions,
+    smart_charging, sparkplug, webhooks,
+};
+
+#[derive(ThisError, Debug)]
+pub enum RuntimeError {
+    #[error("Failed to load secrets: {0}")]
+    Secrets(#[from] secrets::SecretsError),
+    #[error("Failed to open the outbox: {0}")]
+    Outbox(std::io::Error),
+}
+
+/// Builds a runtime from its configuration, with the event sinks of the embedding
+/// application added to the configured ones
+pub struct RuntimeBuilder {
+    config: RuntimeConfig,
+    sinks: Vec<Box<dyn EventSink>>,
+}
+
+impl RuntimeBuilder {
+    pub fn new(config: RuntimeConfig) -> Self {
+        RuntimeBuilder {
+            config,
+            sinks: Vec::new(),
+        }
+    }
+
+    /// Add a sink fed with the events of the bus, next to the configured ones
+    pub fn sink(mut self, sink: Box<dyn EventSink>) -> Self {
+        self.sinks.push(sink);
+        self
+    }
+
+    /// Load the secrets and create the components; nothing runs until [Runtime::run]
+    pub async fn build(self) -> Result<Runtime, RuntimeError> {
+        let config = self.config;
+        let secrets = Arc::new(secrets::SecretsProvider::load(&config.secrets).await?);
+
+        info!("Creating components");
+        let rate_limiter = rate_limit::CommandRateLimiter::shared(config.rate_limit);
+        let failover = failover::Failover::shared(&config.failover);
+        let mut network_receiver = network_receiver::NetworkReceiver::new(
+            config.network,
+            &secrets,
+            rate_limiter.clone(),
+            failover.clone(),
+        );
+        if let Some(ipc) = ipc::IpcClient::from_options(&config.ipc, &secrets) {
+            network_receiver.attach_ipc(ipc);
+        }
+        if let Some(outbox) = outbox::Outbox::from_options(&config.outbox).map_err(RuntimeError::Outbox)? {
+            network_receiver.attach_outbox(outbox);
+        }
+        let bus = event_bus::EventBus::default();
+        network_receiver.attach_bus(bus.subscribe());
+        if let Some(webhooks) = webhooks::Webhooks::new(&config.webhooks, failover.clone()) {
+            webhooks.subscribe(&bus);
+        }
+        let network_channel = network_receiver.get_channel();
+        let ipc_hub = ipc::IpcHub::new(&config.ipc, &secrets, network_channel.clone());
+        let history = history::HistoryStore::shared(&config.history);
+        let sessions = sessions::SessionLog::shared(&config.sessions);
+        let federation = federation::Federation::new(
+            config.federation,
+            network_receiver.mqtt_options("dt-federation"),
+            network_channel.clone(),
+        );
+        let mut sinks = Sinks::new(
+            &config.sinks,
+            bus.clone(),
+            |client_id| network_receiver.mqtt_options(client_id),
+            &secrets,
+            failover.clone(),
+        );
+        for sink in self.sinks {
+            sinks.register(sink);
+        }
+        let actuators = actuation::Actuators::new(&config.actuation, secrets.clone(), failover.clone());
+        let manager = manager::Manager::new(
+            config.manager,
+            historian::Historian::from_options(&config.historian),
+            history.clone(),
+            sessions.clone(),
+            config.predictor,
+            bus.clone(),
+            Some(actuators.clone()),
+            config.latency_budget,
+            federation.proxies(),
+            network_channel.clone(),
+        );
+
+        let manager_channel = manager.get_channel();
+        network_receiver.attach_manager(manager_channel.clone());
+        let commissioning = commissioning::Commissioning::shared(&config.commissioning);
+        if let Some(commissioning) = &commissioning {
+            network_receiver.attach_commissioning(commissioning.clone());
+        }
+        let scheduler =
+            scheduler::Scheduler::new(config.scheduler, manager_channel.clone(), failover.clone());
+        let smart_charging = smart_charging::SmartCharging::new(
+            config.smart_charging,
+            manager_channel.clone(),
+            failover.clone(),
+        );
+        let alerting = alerting::Alerting::new(
+            config.alerting,
+            manager_channel.clone(),
+            bus.clone(),
+            failover.clone(),
+        );
+        let kpi_engine = kpi::KpiEngine::new(config.kpi, manager_channel.clone(), network_channel);
+        let sparkplug = sparkplug::SparkplugPublisher::new(
+            config.sparkplug,
+            network_receiver.mqtt_options("dt-sparkplug"),
+            manager_channel.clone(),
+            bus.clone(),
+            failover.clone(),
+        );
+        let rest_server = rest_server::RestServer::new(
+            config.rest,
+            manager_channel.clone(),
+            &secrets,
+            rest_server::SharedState {
+                rate_limiter,
+                schedules: scheduler.schedules(),
+                charging_status: smart_charging.status(),
+                ingest_metrics: network_receiver.metrics(),
+                unmapped: network_receiver.unmapped(),
+                handler_metrics: manager.handler_metrics(),
+                actuators,
+                sinks: sinks.stats(),
+                history,
+                sessions,
+                alerts: alerting.alerts(),
+                kpis: kpi_engine.kpis(),
+                importer: Arc::new(importer::Importer::new(&config.import)),
+                commissioning,
+                bus,
+                failover,
+            },
+        );
+
+        Ok(Runtime {
+            manager_channel,
+            manager,
+            network_receiver,
+            rest_server,
+            scheduler,
+            smart_charging,
+            alerting,
+            kpi_engine,
+            sparkplug,
+            sinks,
+            federation,
+            ipc_hub,
+        })
+    }
+}
+
+/// The components of a runtime, wired together
+pub struct Runtime {
+    manager_channel: mpsc::Sender<manager::ManagerMessage>,
+    manager: manager::Manager,
+    network_receiver: network_receiver::NetworkReceiver,
+    rest_server: rest_server::RestServer,
+    scheduler: scheduler::Scheduler,
+    smart_charging: smart_charging::SmartCharging,
+    alerting: alerting::Alerting,
+    kpi_engine: kpi::KpiEngine,
+    sparkplug: sparkplug::SparkplugPublisher,
+    sinks: Sinks,
+    federation: federation::Federation,
+    ipc_hub: ipc::IpcHub,
+}
+
+impl Runtime {
+    /// The counters of the event sinks, configured and added
+    pub fn sink_stats(&self) -> crate::sinks::SharedSinkStats {
+        self.sinks.stats()
+    }
+
+    /// Start the twins and run the services until they all stop
+    pub async fn run(mut self) {
+        let _ = self
+            .manager_channel
+            .send(manager::ManagerMessage::Initialize)
+            .await;
+
+        // SIGHUP reloads the twin definitions
+        #[cfg(unix)]
+        {
+            let manager_channel = self.manager_channel.clone();
+            tokio::spawn(async move {
+                use tokio::signal::unix::{signal, SignalKind};
+                let Ok(mut hangup) = signal(SignalKind::hangup()) else {
+                    return;
+                };
+                while hangup.recv().await.is_some() {
+                    info!("SIGHUP received, reloading twins");
+                    let _ = manager_channel.send(manager::ManagerMessage::Reload).await;
+                }
+            });
+        }
+
+        info!("Starting services");
+        let _ = join!(
+            self.manager.body(),
+            self.network_receiver.body(),
+            self.rest_server.body(),
+            self.scheduler.body(),
+            self.smart_charging.body(),
+            self.alerting.body(),
+            self.kpi_engine.body(),
+            self.sparkplug.body(),
+            self.sinks.body(),
+            self.federation.body(),
+            self.ipc_hub.body(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sinks::SinkFuture;
+    use clap::Parser;
+
+    struct NullSink;
+
+    impl EventSink for NullSink {
+        fn name(&self) -> String {
+            "null".to_string()
+        }
+
+        fn consume<'a>(&'a mut self, _events: &'a [event_bus::BusEvent]) -> SinkFuture<'a> {
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_builder_sink() {
+        let config = RuntimeConfig::parse_from(["test", "--broker", "localhost"]);
+        let runtime = RuntimeBuilder::new(config)
+            .sink(Box::new(NullSink))
+            .build()
+            .await
+            .unwrap();
+        assert!(runtime.sink_stats().lock().unwrap().contains_key("null"));
+    }
+}
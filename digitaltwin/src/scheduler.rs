@@ -0,0 +1,643 @@
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, Local, NaiveDateTime, TimeZone, Timelike, Utc};
+use clap::Parser;
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use thiserror::Error as ThisError;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::command::{CommandEnvelope, CommandSource};
+use crate::failover::{self, SharedFailover};
+use crate::manager::{ManagerMessage, Query};
+use digitaltwin_core::AssetID;
+
+/// Principal of the scheduled commands, to be allowed in the operations restricted to some principals
+pub const SCHEDULER_PRINCIPAL: &str = "scheduler";
+/// Interval between the checks for due schedules
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Parser, Clone)]
+pub struct SchedulerOptions {
+    /// file of scheduled commands, a YAML list of {id, cron, command, args, target or group}
+    #[clap(long, env = "SCHEDULE_FILE")]
+    schedule_file: Option<PathBuf>,
+}
+
+#[derive(ThisError, Debug)]
+pub enum ScheduleError {
+    #[error("invalid cron expression \"{0}\": {1}")]
+    InvalidCron(String, String),
+    #[error("schedule {0} must have either a target or a group")]
+    InvalidTarget(String),
+    #[error("schedule {0} already exists")]
+    Exists(String),
+    #[error("schedule {0} not found")]
+    NotFound(String),
+    #[error("schedule {0} is declared in the {1}, it can only be paused")]
+    ReadOnly(String, &'static str),
+}
+
+/// A cron expression: minute, hour, day of month, month and day of week (0 or 7 = Sunday),
+/// each field being "*", a value, a range ("1-5"), a step ("*/15", "0-30/10") or a list
+/// of them ("1,15"). Months and days of the week can be named ("jan", "mon"). The aliases
+/// "@hourly", "@daily", "@weekly", "@monthly" and "@yearly" are supported.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct CronSchedule {
+    expression: String,
+    minutes: u64,
+    hours: u32,
+    days: u32,
+    months: u16,
+    weekdays: u8,
+    /// The day of month or the day of week field is "*": the other one alone selects the days
+    any_day: bool,
+}
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// Parse a cron field into a bit set of the allowed values
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<u64, String> {
+    let value = |s: &str| -> Result<u32, String> {
+        let v = match names.iter().position(|n| n.eq_ignore_ascii_case(s)) {
+            Some(i) => i as u32 + min,
+            None => s.parse().map_err(|_| format!("invalid value {s}"))?,
+        };
+        if v < min || v > max {
+            return Err(format!("{v} is out of range {min}-{max}"));
+        }
+        Ok(v)
+    };
+    let mut bits = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (
+                range,
+                step.parse::<u32>()
+                    .ok()
+                    .filter(|s| *s > 0)
+                    .ok_or_else(|| format!("invalid step {step}"))?,
+            ),
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (value(start)?, value(end)?),
+                // "5/15" means from 5 to the end, every 15
+                None if step > 1 => (value(range)?, max),
+                None => (value(range)?, value(range)?),
+            },
+        };
+        if start > end {
+            return Err(format!("invalid range {range}"));
+        }
+        for v in (start..=end).step_by(step as usize) {
+            bits |= 1 << v;
+        }
+    }
+    Ok(bits)
+}
+
+impl TryFrom<String> for CronSchedule {
+    type Error = ScheduleError;
+
+    fn try_from(expression: String) -> Result<Self, Self::Error> {
+        let invalid = |e: &str| ScheduleError::InvalidCron(expression.clone(), e.to_string());
+        let fields = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            fields => fields,
+        };
+        let fields: Vec<_> = fields.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(invalid("expected 5 fields"));
+        };
+        let weekdays = parse_field(weekday, 0, 7, &WEEKDAYS).map_err(|e| invalid(&e))?;
+        Ok(CronSchedule {
+            minutes: parse_field(minute, 0, 59, &[]).map_err(|e| invalid(&e))?,
+            hours: parse_field(hour, 0, 23, &[]).map_err(|e| invalid(&e))? as u32,
+            days: parse_field(day, 1, 31, &[]).map_err(|e| invalid(&e))? as u32,
+            months: parse_field(month, 1, 12, &MONTHS).map_err(|e| invalid(&e))? as u16,
+            // 7 is Sunday too
+            weekdays: (weekdays | weekdays >> 7) as u8 & 0x7f,
+            any_day: day == "*" || weekday == "*",
+            expression,
+        })
+    }
+}
+
+impl From<CronSchedule> for String {
+    fn from(schedule: CronSchedule) -> Self {
+        schedule.expression
+    }
+}
+
+impl CronSchedule {
+    fn matches_day(&self, time: &NaiveDateTime) -> bool {
+        let day = self.days & (1 << time.day()) != 0;
+        let weekday = self.weekdays & (1 << time.weekday().num_days_from_sunday()) != 0;
+        // As in cron, a day matches either field when both are restricted
+        let day = if self.any_day {
+            day && weekday
+        } else {
+            day || weekday
+        };
+        day && self.months & (1 << time.month()) != 0
+    }
+
+    /// Whether the schedule fires at the minute of the given time
+    pub fn matches(&self, time: &NaiveDateTime) -> bool {
+        self.matches_day(time)
+            && self.hours & (1 << time.hour()) != 0
+            && self.minutes & (1 << time.minute()) != 0
+    }
+
+    /// The first minute after the given time at which the schedule fires, within 5 years
+    pub fn next_after(&self, time: &NaiveDateTime) -> Option<NaiveDateTime> {
+        let mut next = time.with_second(0)?.with_nanosecond(0)? + ChronoDuration::minutes(1);
+        let limit = next + ChronoDuration::days(5 * 366);
+        while next < limit {
+            if !self.matches_day(&next) {
+                next = next.date().succ_opt()?.and_hms_opt(0, 0, 0)?;
+            } else if self.hours & (1 << next.hour()) == 0 {
+                next = next.with_minute(0)? + ChronoDuration::hours(1);
+            } else if self.minutes & (1 << next.minute()) == 0 {
+                next += ChronoDuration::minutes(1);
+            } else {
+                return Some(next);
+            }
+        }
+        None
+    }
+}
+
+/// A command to be executed on a twin, or on all the members of a group, on a schedule
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduleDefinition {
+    pub id: String,
+    pub cron: CronSchedule,
+    pub command: String,
+    #[serde(default = "empty_args")]
+    pub args: serde_json::Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<AssetID>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+}
+
+fn empty_args() -> serde_json::Value {
+    serde_json::json!({})
+}
+
+impl ScheduleDefinition {
+    fn validate(&self) -> Result<(), ScheduleError> {
+        match (&self.target, &self.group) {
+            (Some(_), None) | (None, Some(_)) => Ok(()),
+            _ => Err(ScheduleError::InvalidTarget(self.id.clone())),
+        }
+    }
+
+    /// Read the schedules declared in the "Schedules" collection of the AAS of a twin
+    fn from_aas(asset_id: &AssetID, schedules: &serde_json::Value) -> Vec<Result<Self, ScheduleError>> {
+        let Some(schedules) = schedules.as_object() else {
+            return Vec::new();
+        };
+        schedules
+            .iter()
+            .map(|(name, schedule)| {
+                let id = format!("{asset_id}/{name}");
+                let field = |name: &str| schedule.get(name).and_then(|v| v.as_str()).unwrap_or_default();
+                Ok(ScheduleDefinition {
+                    cron: CronSchedule::try_from(field("Cron").to_string())?,
+                    command: field("Command").to_string(),
+                    args: schedule.get("Args").cloned().unwrap_or_else(empty_args),
+                    target: Some(asset_id.clone()),
+                    group: None,
+                    id,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Where a schedule was declared
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScheduleSource {
+    /// The schedule file
+    File,
+    /// The AAS of the target twin
+    Aas,
+    /// Added through the REST API, lost at restart
+    Api,
+}
+
+impl ScheduleSource {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ScheduleSource::File => "schedule file",
+            ScheduleSource::Aas => "twin AAS",
+            ScheduleSource::Api => "REST API",
+        }
+    }
+}
+
+/// A schedule, with the outcome of its latest run
+#[derive(Debug, Clone, Serialize)]
+pub struct ScheduledCommand {
+    #[serde(flatten)]
+    pub definition: ScheduleDefinition,
+    pub source: ScheduleSource,
+    pub enabled: bool,
+    pub next_run: Option<DateTime<Local>>,
+    pub last_run: Option<DateTime<Utc>>,
+    pub last_outcome: Option<String>,
+}
+
+/// The schedules, from all the sources, by ID
+#[derive(Debug, Default)]
+pub struct ScheduleTable {
+    schedules: BTreeMap<String, ScheduledCommand>,
+}
+
+/// Schedules shared by the scheduler and the REST server
+pub type SharedSchedules = Arc<Mutex<ScheduleTable>>;
+
+impl ScheduleTable {
+    /// Replace all the schedules of a source, keeping the state of the unchanged ones
+    pub fn replace_source(&mut self, source: ScheduleSource, definitions: Vec<ScheduleDefinition>) {
+        let (mut previous, others): (BTreeMap<_, _>, BTreeMap<_, _>) = std::mem::take(&mut self.schedules)
+            .into_iter()
+            .partition(|(_, s)| s.source == source);
+        self.schedules = others;
+        for definition in definitions {
+            if let Err(e) = definition.validate() {
+                warn!("Ignoring schedule from the {}: {e}", source.as_str());
+                continue;
+            }
+            if self.schedules.contains_key(&definition.id) {
+                warn!(
+                    "Ignoring duplicate schedule {} from the {}",
+                    definition.id,
+                    source.as_str()
+                );
+                continue;
+            }
+            let scheduled = match previous.remove(&definition.id) {
+                Some(old) => ScheduledCommand { definition, ..old },
+                None => ScheduledCommand {
+                    definition,
+                    source,
+                    enabled: true,
+                    next_run: None,
+                    last_run: None,
+                    last_outcome: None,
+                },
+            };
+            self.schedules.insert(scheduled.definition.id.clone(), scheduled);
+        }
+    }
+
+    pub fn add(&mut self, definition: ScheduleDefinition) -> Result<(), ScheduleError> {
+        definition.validate()?;
+        if self.schedules.contains_key(&definition.id) {
+            return Err(ScheduleError::Exists(definition.id));
+        }
+        self.replace_source(
+            ScheduleSource::Api,
+            self.schedules
+                .values()
+                .filter(|s| s.source == ScheduleSource::Api)
+                .map(|s| s.definition.clone())
+                .chain(Some(definition))
+                .collect(),
+        );
+        Ok(())
+    }
+
+    /// Remove a schedule added through the REST API
+    pub fn remove(&mut self, id: &str) -> Result<(), ScheduleError> {
+        match self.schedules.get(id).map(|s| s.source) {
+            None => Err(ScheduleError::NotFound(id.to_string())),
+            Some(ScheduleSource::Api) => {
+                self.schedules.remove(id);
+                Ok(())
+            }
+            Some(source) => Err(ScheduleError::ReadOnly(id.to_string(), source.as_str())),
+        }
+    }
+
+    pub fn set_enabled(&mut self, id: &str, enabled: bool) -> Result<(), ScheduleError> {
+        let schedule = self
+            .schedules
+            .get_mut(id)
+            .ok_or_else(|| ScheduleError::NotFound(id.to_string()))?;
+        schedule.enabled = enabled;
+        Ok(())
+    }
+
+    /// The enabled schedules firing at the minute of the given (local) time
+    pub fn due(&self, time: &NaiveDateTime) -> Vec<ScheduleDefinition> {
+        self.schedules
+            .values()
+            .filter(|s| s.enabled && s.definition.cron.matches(time))
+            .map(|s| s.definition.clone())
+            .collect()
+    }
+
+    pub fn record(&mut self, id: &str, time: DateTime<Utc>, outcome: String) {
+        if let Some(schedule) = self.schedules.get_mut(id) {
+            schedule.last_run = Some(time);
+            schedule.last_outcome = Some(outcome);
+        }
+    }
+
+    /// All the schedules, with their next run after the given (local) time
+    pub fn list(&self, now: &NaiveDateTime) -> Vec<ScheduledCommand> {
+        self.schedules
+            .values()
+            .map(|s| ScheduledCommand {
+                next_run: s
+                    .enabled
+                    .then(|| s.definition.cron.next_after(now))
+                    .flatten()
+                    .and_then(|next| Local.from_local_datetime(&next).earliest()),
+                ..s.clone()
+            })
+            .collect()
+    }
+}
+
+/// Executes the scheduled commands through the manager, at the local time of the runtime.
+/// The schedules declared in the AAS of the twins are refreshed every minute, following
+/// the reloads of the twin definitions.
+pub struct Scheduler {
+    schedules: SharedSchedules,
+    manager_ch: mpsc::Sender<ManagerMessage>,
+    /// A standby instance leaves the scheduled commands to the active one
+    failover: SharedFailover,
+}
+
+impl Scheduler {
+    pub fn new(
+        options: SchedulerOptions,
+        manager_ch: mpsc::Sender<ManagerMessage>,
+        failover: SharedFailover,
+    ) -> Self {
+        let mut table = ScheduleTable::default();
+        if let Some(path) = &options.schedule_file {
+            let definitions = std::fs::read_to_string(path)
+                .map_err(|e| e.to_string())
+                .and_then(|file| {
+                    serde_yaml::from_str::<Vec<ScheduleDefinition>>(&file).map_err(|e| e.to_string())
+                });
+            match definitions {
+                Ok(definitions) => {
+                    info!("Loaded {} schedules from {}", definitions.len(), path.display());
+                    table.replace_source(ScheduleSource::File, definitions);
+                }
+                Err(e) => error!("Cannot load schedule file {}: {e}", path.display()),
+            }
+        }
+        Scheduler {
+            schedules: Arc::new(Mutex::new(table)),
+            manager_ch,
+            failover,
+        }
+    }
+
+    pub fn schedules(&self) -> SharedSchedules {
+        self.schedules.clone()
+    }
+
+    fn table(&self) -> std::sync::MutexGuard<'_, ScheduleTable> {
+        self.schedules.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Reload the schedules declared in the AAS of the running twins
+    async fn refresh_twin_schedules(&self) {
+        let (reply, response) = oneshot::channel();
+        if self
+            .manager_ch
+            .send(ManagerMessage::Query(Query::Schedules(reply)))
+            .await
+            .is_err()
+        {
+            return;
+        }
+        let Ok(twins) = response.await else {
+            return;
+        };
+        let definitions = twins
+            .iter()
+            .flat_map(|(asset_id, schedules)| ScheduleDefinition::from_aas(asset_id, schedules))
+            .filter_map(|definition| {
+                definition
+                    .inspect_err(|e| warn!("Ignoring schedule from the twin AAS: {e}"))
+                    .ok()
+            })
+            .collect();
+        self.table().replace_source(ScheduleSource::Aas, definitions);
+    }
+
+    /// Execute a scheduled command and record its outcome, without blocking the scheduler
+    fn run(&self, definition: ScheduleDefinition) {
+        info!("Running scheduled command {}", definition.id);
+        let schedules = self.schedules.clone();
+        let manager_ch = self.manager_ch.clone();
+        tokio::spawn(async move {
+            let started = Utc::now();
+            let envelope =
+                CommandEnvelope::new(CommandSource::Scheduler, &definition.command, definition.args)
+                    .with_principal(Some(SCHEDULER_PRINCIPAL.to_string()));
+            let outcome = match (definition.target, definition.group) {
+                (Some(target), _) => {
+                    let (reply, response) = oneshot::channel();
+                    let query = Query::Invoke(target, envelope, reply);
+                    match manager_ch.send(ManagerMessage::Query(query)).await {
+                        Ok(()) => response
+                            .await
+                            .ok()
+                            .flatten()
+                            .map_or("no_response", |outcome| outcome.as_str())
+                            .to_string(),
+                        Err(_) => "no_response".to_string(),
+                    }
+                }
+                (None, Some(group)) => run_on_group(&manager_ch, group, envelope).await,
+                (None, None) => unreachable!("validated schedule"),
+            };
+            debug!("Scheduled command {} outcome: {outcome}", definition.id);
+            schedules
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .record(&definition.id, started, outcome);
+        });
+    }
+
+    pub async fn body(&mut self) {
+        info!("Scheduler body starting");
+        // Answered once the twins are initialized, as the manager handles its messages in order
+        self.refresh_twin_schedules().await;
+        let mut tick = tokio::time::interval(TICK_INTERVAL);
+        let mut last_minute = Local::now()
+            .naive_local()
+            .with_second(0)
+            .and_then(|t| t.with_nanosecond(0));
+        loop {
+            tick.tick().await;
+            let now = Local::now().naive_local();
+            let minute = now.with_second(0).and_then(|t| t.with_nanosecond(0));
+            if minute == last_minute {
+                continue;
+            }
+            last_minute = minute;
+            self.refresh_twin_schedules().await;
+            if !failover::is_active(&self.failover) {
+                continue;
+            }
+            let due = self.table().due(&now);
+            for definition in due {
+                self.run(definition);
+            }
+        }
+    }
+}
+
+/// Execute a command on all the members of a group, returning a summary of the outcomes
+async fn run_on_group(
+    manager_ch: &mpsc::Sender<ManagerMessage>,
+    group: String,
+    envelope: CommandEnvelope,
+) -> String {
+    let (reply, response) = oneshot::channel();
+    let query = Query::GroupMembers(group.clone(), reply);
+    if manager_ch.send(ManagerMessage::Query(query)).await.is_err() {
+        return "no_response".to_string();
+    }
+    let members = response.await.unwrap_or_default();
+    if members.is_empty() {
+        return format!("group {group} not found");
+    }
+    let (reply, response) = oneshot::channel();
+    let query = Query::Broadcast(group, members, envelope, reply);
+    if manager_ch.send(ManagerMessage::Query(query)).await.is_err() {
+        return "no_response".to_string();
+    }
+    match response.await {
+        Ok(ack) => format!(
+            "executed by {} of {} members",
+            ack.executed,
+            ack.executed + ack.failed
+        ),
+        Err(_) => "no_response".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn time(y: i32, m: u32, d: u32, h: u32, min: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(y, m, d)
+            .unwrap()
+            .and_hms_opt(h, min, 0)
+            .unwrap()
+    }
+
+    fn cron(expression: &str) -> CronSchedule {
+        CronSchedule::try_from(expression.to_string()).unwrap()
+    }
+
+    #[test]
+    fn test_cron_parsing() {
+        assert!(cron("0 23 * * *").matches(&time(2024, 5, 1, 23, 0)));
+        assert!(!cron("0 23 * * *").matches(&time(2024, 5, 1, 23, 1)));
+        assert!(cron("*/15 8-18 * * mon-fri").matches(&time(2024, 5, 3, 8, 45)));
+        // 2024-05-04 is a Saturday
+        assert!(!cron("*/15 8-18 * * mon-fri").matches(&time(2024, 5, 4, 8, 45)));
+        assert!(cron("30 6 * * 7").matches(&time(2024, 5, 5, 6, 30)));
+        assert!(cron("@monthly").matches(&time(2024, 6, 1, 0, 0)));
+        // Day of month or day of week, when both are restricted
+        assert!(cron("0 0 13 * fri").matches(&time(2024, 5, 13, 0, 0)));
+        assert!(cron("0 0 13 * fri").matches(&time(2024, 5, 17, 0, 0)));
+
+        for invalid in [
+            "",
+            "* * * *",
+            "60 * * * *",
+            "* * * * * *",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "0 0 * foo *",
+        ] {
+            assert!(CronSchedule::try_from(invalid.to_string()).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_next_run() {
+        let now = time(2024, 5, 1, 23, 0);
+        assert_eq!(cron("0 23 * * *").next_after(&now), Some(time(2024, 5, 2, 23, 0)));
+        assert_eq!(
+            cron("*/20 * * * *").next_after(&now),
+            Some(time(2024, 5, 1, 23, 20))
+        );
+        assert_eq!(cron("0 0 29 2 *").next_after(&now), Some(time(2028, 2, 29, 0, 0)));
+        assert_eq!(cron("0 0 31 2 *").next_after(&now), None);
+    }
+
+    #[test]
+    fn test_schedule_table() {
+        let definition = |id: &str, cron: &str| ScheduleDefinition {
+            id: id.to_string(),
+            cron: super::tests::cron(cron),
+            command: "SetChargingCurrent".to_string(),
+            args: serde_json::json!({"desired_current": 6}),
+            target: Some("urn:charger".into()),
+            group: None,
+        };
+        let mut table = ScheduleTable::default();
+        table.replace_source(ScheduleSource::File, vec![definition("night", "0 23 * * *")]);
+        table.add(definition("morning", "0 7 * * *")).unwrap();
+        assert!(matches!(
+            table.add(definition("night", "0 1 * * *")),
+            Err(ScheduleError::Exists(_))
+        ));
+        let invalid = ScheduleDefinition {
+            group: Some("chargers".to_string()),
+            ..definition("both", "0 1 * * *")
+        };
+        assert!(matches!(table.add(invalid), Err(ScheduleError::InvalidTarget(_))));
+
+        let at_night = time(2024, 5, 1, 23, 0);
+        assert_eq!(table.due(&at_night).len(), 1);
+        table.record("night", Utc::now(), "executed".to_string());
+        table.set_enabled("night", false).unwrap();
+        assert!(table.due(&at_night).is_empty());
+
+        // Reloading a source keeps the state of the schedules
+        table.replace_source(ScheduleSource::File, vec![definition("night", "0 22 * * *")]);
+        let night = &table.list(&at_night)[1];
+        assert_eq!(
+            (night.enabled, night.last_outcome.as_deref()),
+            (false, Some("executed"))
+        );
+        assert!(night.next_run.is_none());
+
+        assert!(matches!(table.remove("night"), Err(ScheduleError::ReadOnly(..))));
+        table.remove("morning").unwrap();
+        assert!(matches!(table.remove("morning"), Err(ScheduleError::NotFound(_))));
+    }
+}
@@ -0,0 +1,308 @@
+use clap::Parser;
+use log::{debug, info};
+use ring::aead::{self, Aad, LessSafeKey, Nonce, UnboundKey, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use thiserror::Error as ThisError;
+
+use crate::command_auth::decode_hex;
+use crate::http_client;
+
+/// Password of the MQTT broker user
+pub const MQTT_PASSWORD: &str = "mqtt_password";
+/// Shared secrets verifying the command signatures (comma-separated)
+pub const COMMAND_KEYS: &str = "command_keys";
+/// Keys accepted by the REST API (comma-separated)
+pub const REST_API_KEYS: &str = "rest_api_keys";
+/// Token of the InfluxDB event sink
+pub const INFLUX_TOKEN: &str = "influx_token";
+/// Shared secret of the hub and the remote twin runners
+pub const IPC_KEY: &str = "ipc_key";
+
+#[derive(Parser, Clone)]
+pub struct SecretsOptions {
+    /// encrypted secrets file, a JSON object of AES-256-GCM sealed values (see --seal-secret)
+    #[clap(long, env = "SECRETS_FILE")]
+    secrets_file: Option<PathBuf>,
+
+    /// file holding the key of the secrets file (64 hex digits)
+    #[clap(long, env = "SECRETS_KEY_FILE")]
+    secrets_key_file: Option<PathBuf>,
+
+    /// Vault address, such as a local Vault Agent (e.g., "http://127.0.0.1:8200"); the token is read from VAULT_TOKEN
+    #[clap(long, env = "VAULT_ADDR")]
+    vault_addr: Option<String>,
+
+    /// Vault path of the secret holding the runtime secrets (KV v1 or v2)
+    #[clap(long, default_value = "secret/data/digitaltwin", env = "VAULT_SECRET_PATH")]
+    vault_path: String,
+
+    /// add or replace a secret in the secrets file, reading its value from stdin, then exit
+    #[clap(long, value_name = "NAME")]
+    pub seal_secret: Option<String>,
+}
+
+#[derive(ThisError, Debug)]
+pub enum SecretsError {
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+    #[error("invalid secrets file: {0}")]
+    InvalidFile(String),
+    #[error("invalid secrets key: {0}")]
+    InvalidKey(String),
+    #[error("cannot decrypt secret {0}")]
+    Decrypt(String),
+    #[error("Vault error: {0}")]
+    VaultError(String),
+}
+
+/// A source of secrets, looked up by name
+#[derive(Debug)]
+enum Backend {
+    /// Secrets loaded at startup from the encrypted file or from Vault
+    Loaded(&'static str, HashMap<String, String>),
+    /// Environment variables, named after the secret in upper case (e.g., MQTT_PASSWORD)
+    Env,
+}
+
+/// Secrets used by the runtime (broker credentials, command and API keys). Secrets
+/// are looked up in the encrypted file, then in Vault, then in the environment.
+#[derive(Debug)]
+pub struct SecretsProvider {
+    backends: Vec<Backend>,
+}
+
+impl SecretsProvider {
+    /// Load the secrets from the configured backends
+    pub async fn load(options: &SecretsOptions) -> Result<Self, SecretsError> {
+        let mut backends = Vec::new();
+        if let Some(path) = &options.secrets_file {
+            let key = read_key(options)?;
+            let sealed = read_sealed(path)?;
+            let secrets = sealed
+                .iter()
+                .map(|(name, value)| Ok((name.clone(), open_secret(&key, name, value)?)))
+                .collect::<Result<HashMap<_, _>, SecretsError>>()?;
+            info!("Loaded {} secrets from {}", secrets.len(), path.display());
+            backends.push(Backend::Loaded("file", secrets));
+        }
+        if let Some(addr) = &options.vault_addr {
+            let token = std::env::var("VAULT_TOKEN")
+                .map_err(|_| SecretsError::VaultError("VAULT_TOKEN is not set".to_string()))?;
+            let secrets = fetch_vault_secret(addr, &options.vault_path, &token).await?;
+            info!("Loaded {} secrets from Vault at {addr}", secrets.len());
+            backends.push(Backend::Loaded("vault", secrets));
+        }
+        backends.push(Backend::Env);
+        Ok(SecretsProvider { backends })
+    }
+
+    pub fn get(&self, name: &str) -> Option<String> {
+        self.backends.iter().find_map(|backend| match backend {
+            Backend::Loaded(source, secrets) => secrets
+                .get(name)
+                .inspect(|_| {
+                    debug!("Secret {name} found in {source}");
+                })
+                .cloned(),
+            Backend::Env => std::env::var(name.to_uppercase()).ok(),
+        })
+    }
+
+    /// A secret holding a comma-separated list, such as a set of keys
+    pub fn get_list(&self, name: &str) -> Vec<String> {
+        self.get(name)
+            .map(|list| {
+                list.split(',')
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Add or replace a secret in the encrypted file, creating the file if needed
+pub fn seal_secret(options: &SecretsOptions, name: &str, value: &str) -> Result<(), SecretsError> {
+    let path = options
+        .secrets_file
+        .as_ref()
+        .ok_or_else(|| SecretsError::InvalidFile("no secrets file configured".to_string()))?;
+    let key = read_key(options)?;
+    let mut sealed = if path.exists() {
+        read_sealed(path)?
+    } else {
+        HashMap::new()
+    };
+    sealed.insert(name.to_string(), seal(&key, name, value)?);
+    let json = serde_json::to_string_pretty(&sealed).map_err(|e| SecretsError::InvalidFile(e.to_string()))?;
+    let mut file = std::fs::OpenOptions::new();
+    file.write(true).create(true).truncate(true);
+    // Readable by its owner only
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut file, 0o600);
+    file.open(path)?.write_all(json.as_bytes())?;
+    Ok(())
+}
+
+fn read_key(options: &SecretsOptions) -> Result<LessSafeKey, SecretsError> {
+    let path = options
+        .secrets_key_file
+        .as_ref()
+        .ok_or_else(|| SecretsError::InvalidKey("no key file configured".to_string()))?;
+    let hex = std::fs::read_to_string(path)?;
+    let bytes = decode_hex(hex.trim()).ok_or_else(|| SecretsError::InvalidKey("not hex".to_string()))?;
+    let key = UnboundKey::new(&aead::AES_256_GCM, &bytes)
+        .map_err(|_| SecretsError::InvalidKey("expected 32 bytes".to_string()))?;
+    Ok(LessSafeKey::new(key))
+}
+
+fn read_sealed(path: &Path) -> Result<HashMap<String, String>, SecretsError> {
+    let file = std::fs::read_to_string(path)?;
+    serde_json::from_str(&file).map_err(|e| SecretsError::InvalidFile(e.to_string()))
+}
+
+/// Encrypt a value, bound to the name of the secret. The result is the hex
+/// encoding of the nonce followed by the ciphertext and tag.
+fn seal(key: &LessSafeKey, name: &str, value: &str) -> Result<String, SecretsError> {
+    let mut nonce = [0u8; NONCE_LEN];
+    SystemRandom::new()
+        .fill(&mut nonce)
+        .map_err(|_| SecretsError::InvalidKey("no random source".to_string()))?;
+    let mut data = value.as_bytes().to_vec();
+    key.seal_in_place_append_tag(
+        Nonce::assume_unique_for_key(nonce),
+        Aad::from(name.as_bytes()),
+        &mut data,
+    )
+    .map_err(|_| SecretsError::Decrypt(name.to_string()))?;
+    Ok(nonce.iter().chain(&data).map(|b| format!("{b:02x}")).collect())
+}
+
+fn open_secret(key: &LessSafeKey, name: &str, sealed: &str) -> Result<String, SecretsError> {
+    let decrypt_error = || SecretsError::Decrypt(name.to_string());
+    let bytes = decode_hex(sealed).ok_or_else(decrypt_error)?;
+    if bytes.len() < NONCE_LEN {
+        return Err(decrypt_error());
+    }
+    let (nonce, data) = bytes.split_at(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| decrypt_error())?;
+    let mut data = data.to_vec();
+    let plain = key
+        .open_in_place(nonce, Aad::from(name.as_bytes()), &mut data)
+        .map_err(|_| decrypt_error())?;
+    String::from_utf8(plain.to_vec()).map_err(|_| decrypt_error())
+}
+
+/// Read a secret from Vault, or from a local Vault Agent.
+/// The values of KV v2 secrets are nested in "data.data", those of KV v1 in "data".
+async fn fetch_vault_secret(
+    addr: &str,
+    path: &str,
+    token: &str,
+) -> Result<HashMap<String, String>, SecretsError> {
+    let vault_error = |e: String| SecretsError::VaultError(e);
+    let url = format!(
+        "{}/v1/{}",
+        addr.trim_end_matches('/'),
+        path.trim_start_matches('/')
+    );
+    let body = http_client::get(&url, &[("X-Vault-Token", token)])
+        .await
+        .map_err(|e| vault_error(e.to_string()))?;
+    let body: serde_json::Value =
+        serde_json::from_str(&body).map_err(|e| vault_error(format!("invalid JSON: {e}")))?;
+    let data = match body.pointer("/data/data") {
+        Some(data) if data.is_object() => data,
+        _ => body.get("data").unwrap_or(&serde_json::Value::Null),
+    };
+    let secrets = data
+        .as_object()
+        .ok_or_else(|| vault_error("no data in secret".to_string()))?
+        .iter()
+        .filter_map(|(name, value)| Some((name.clone(), value.as_str()?.to_string())))
+        .collect();
+    Ok(secrets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key() -> LessSafeKey {
+        LessSafeKey::new(UnboundKey::new(&aead::AES_256_GCM, &[7u8; 32]).unwrap())
+    }
+
+    #[test]
+    fn test_seal_and_open() {
+        let key = key();
+        let sealed = seal(&key, MQTT_PASSWORD, "hunter2").unwrap();
+        assert_ne!(sealed, seal(&key, MQTT_PASSWORD, "hunter2").unwrap());
+        assert_eq!(open_secret(&key, MQTT_PASSWORD, &sealed).unwrap(), "hunter2");
+
+        // A value can't be moved to another secret, nor altered
+        assert!(open_secret(&key, REST_API_KEYS, &sealed).is_err());
+        let mut tampered = sealed.clone();
+        tampered.replace_range(30..32, if &sealed[30..32] == "00" { "01" } else { "00" });
+        assert!(open_secret(&key, MQTT_PASSWORD, &tampered).is_err());
+        assert!(open_secret(&key, MQTT_PASSWORD, "0011").is_err());
+    }
+
+    #[test]
+    fn test_seal_secret() {
+        let dir = std::env::temp_dir().join(format!("dt-seal-secret-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (file, key_file) = (dir.join("secrets.json"), dir.join("secrets.key"));
+        std::fs::write(&key_file, "07".repeat(32)).unwrap();
+        let _ = std::fs::remove_file(&file);
+        let options = SecretsOptions::parse_from([
+            "test".as_ref(),
+            "--secrets-file".as_ref(),
+            file.as_os_str(),
+            "--secrets-key-file".as_ref(),
+            key_file.as_os_str(),
+        ]);
+
+        seal_secret(&options, MQTT_PASSWORD, "hunter2").unwrap();
+        seal_secret(&options, IPC_KEY, "k").unwrap();
+        let sealed = read_sealed(&file).unwrap();
+        assert_eq!(
+            open_secret(&key(), MQTT_PASSWORD, &sealed[MQTT_PASSWORD]).unwrap(),
+            "hunter2"
+        );
+        assert_eq!(open_secret(&key(), IPC_KEY, &sealed[IPC_KEY]).unwrap(), "k");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&file).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_lookup_order() {
+        let provider = SecretsProvider {
+            backends: vec![
+                Backend::Loaded(
+                    "file",
+                    HashMap::from([(REST_API_KEYS.to_string(), " key1, key2,".to_string())]),
+                ),
+                Backend::Loaded(
+                    "vault",
+                    HashMap::from([
+                        (REST_API_KEYS.to_string(), "other".to_string()),
+                        (COMMAND_KEYS.to_string(), "k".to_string()),
+                    ]),
+                ),
+            ],
+        };
+        assert_eq!(provider.get_list(REST_API_KEYS), vec!["key1", "key2"]);
+        assert_eq!(provider.get(COMMAND_KEYS).as_deref(), Some("k"));
+        assert_eq!(provider.get(MQTT_PASSWORD), None);
+        assert!(provider.get_list(MQTT_PASSWORD).is_empty());
+    }
+}
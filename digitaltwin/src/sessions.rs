@@ -0,0 +1,259 @@
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use crate::twin_runner::TwinTransition;
+use digitaltwin_core::{AssetID, SlotValue};
+
+#[derive(Parser, Clone)]
+pub struct SessionOptions {
+    /// state of a charging session: a session starts when a twin enters it (in any region)
+    /// and ends when the twin leaves it
+    #[clap(long, default_value = "Charging", env = "SESSION_STATE")]
+    session_state: String,
+
+    /// slot with the power samples integrated into the energy of the sessions; the energy
+    /// is in the unit of the power times hours (e.g., kWh for kW)
+    #[clap(long, default_value = "CurrentPowerDraw", env = "SESSION_POWER_SLOT")]
+    session_power_slot: String,
+
+    /// completed sessions kept for each twin (0 = no session tracking)
+    #[clap(long, default_value_t = 100, env = "SESSIONS_PER_TWIN")]
+    sessions_per_twin: usize,
+}
+
+/// A charging session of a twin
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChargingSession {
+    pub started: DateTime<Utc>,
+    /// None while the session is ongoing
+    pub ended: Option<DateTime<Utc>>,
+    /// State the twin entered at the end of the session (e.g., "Connected")
+    pub end_state: Option<String>,
+    /// Duration in seconds, up to the latest power sample while the session is ongoing
+    pub duration_secs: i64,
+    /// Energy delivered, integrated from the power samples
+    pub energy: f64,
+    pub peak_power: f64,
+    /// Number of power samples received during the session
+    pub samples: u64,
+}
+
+impl ChargingSession {
+    fn new(started: DateTime<Utc>) -> Self {
+        ChargingSession {
+            started,
+            ended: None,
+            end_state: None,
+            duration_secs: 0,
+            energy: 0.0,
+            peak_power: 0.0,
+            samples: 0,
+        }
+    }
+
+    /// Add the energy delivered at a constant or linearly changing power between two times
+    fn integrate(&mut self, from: DateTime<Utc>, to: DateTime<Utc>, power_from: f64, power_to: f64) {
+        let from = from.max(self.started);
+        if to > from {
+            let hours = (to - from).as_seconds_f64() / 3600.0;
+            self.energy += (power_from + power_to) / 2.0 * hours;
+        }
+        self.duration_secs = self.duration_secs.max((to - self.started).num_seconds());
+    }
+}
+
+/// Sessions of a twin and its latest power sample
+#[derive(Debug, Default)]
+struct TwinSessions {
+    current: Option<ChargingSession>,
+    completed: VecDeque<ChargingSession>,
+    last_power: Option<(DateTime<Utc>, f64)>,
+}
+
+/// Sessions of a twin in a time range, shown by the REST API
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionReport {
+    pub asset_id: AssetID,
+    /// Sessions started in the range, oldest first, the ongoing one last
+    pub sessions: Vec<ChargingSession>,
+    pub total_energy: f64,
+}
+
+/// Charging sessions of the twins, derived from their state transitions, with the energy
+/// integrated from the power samples
+#[derive(Debug)]
+pub struct SessionLog {
+    state: String,
+    power_slot: String,
+    limit: usize,
+    twins: HashMap<AssetID, TwinSessions>,
+}
+
+pub type SharedSessions = Arc<Mutex<SessionLog>>;
+
+impl SessionLog {
+    /// The session log, if enabled in the options
+    pub fn shared(options: &SessionOptions) -> Option<SharedSessions> {
+        (options.sessions_per_twin > 0).then(|| Arc::new(Mutex::new(SessionLog::new(options))))
+    }
+
+    fn new(options: &SessionOptions) -> Self {
+        SessionLog {
+            state: options.session_state.clone(),
+            power_slot: options.session_power_slot.clone(),
+            limit: options.sessions_per_twin,
+            twins: HashMap::new(),
+        }
+    }
+
+    /// Whether a (composite) state is a charging state
+    fn is_session_state(&self, state: &str) -> bool {
+        state.split('|').any(|s| s == self.state)
+    }
+
+    /// Record a value of a slot of a twin, integrating the power samples
+    pub fn sample(&mut self, asset_id: &AssetID, slot: &str, time: DateTime<Utc>, value: &SlotValue) {
+        let SlotValue::Number(power) = *value else {
+            return;
+        };
+        if slot != self.power_slot {
+            return;
+        }
+        let twin = self.twins.entry(asset_id.clone()).or_default();
+        if let Some(session) = &mut twin.current {
+            if let Some((last_time, last_power)) = twin.last_power {
+                session.integrate(last_time, time, last_power, power);
+            }
+            session.peak_power = session.peak_power.max(power);
+            session.samples += 1;
+        }
+        twin.last_power = Some((time, power));
+    }
+
+    /// Start or end a session on a state transition of a twin
+    pub fn transition(&mut self, transition: &TwinTransition) {
+        let (was_charging, charging) = (
+            self.is_session_state(&transition.from),
+            self.is_session_state(&transition.to),
+        );
+        if was_charging == charging {
+            return;
+        }
+        let time = transition.timestamp;
+        let twin = self.twins.entry(transition.asset_id.clone()).or_default();
+        if charging {
+            twin.current = Some(ChargingSession::new(time));
+            return;
+        }
+        let Some(mut session) = twin.current.take() else {
+            return;
+        };
+        // The power holds from the latest sample to the end of the session
+        if let Some((last_time, last_power)) = twin.last_power {
+            session.integrate(last_time, time, last_power, last_power);
+        }
+        session.duration_secs = (time - session.started).num_seconds();
+        session.ended = Some(time);
+        session.end_state = Some(transition.to.clone());
+        twin.completed.push_back(session);
+        if twin.completed.len() > self.limit {
+            twin.completed.pop_front();
+        }
+    }
+
+    /// The sessions of a twin started between two times (None if the twin has no sessions)
+    pub fn report(
+        &self,
+        asset_id: &AssetID,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Option<SessionReport> {
+        let twin = self.twins.get(asset_id)?;
+        let sessions: Vec<ChargingSession> = twin
+            .completed
+            .iter()
+            .chain(twin.current.iter())
+            .filter(|s| from.is_none_or(|from| s.started >= from) && to.is_none_or(|to| s.started <= to))
+            .cloned()
+            .collect();
+        Some(SessionReport {
+            asset_id: asset_id.clone(),
+            total_energy: sessions.iter().map(|s| s.energy).sum(),
+            sessions,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeDelta;
+
+    fn transition(from: &str, to: &str, timestamp: DateTime<Utc>) -> TwinTransition {
+        TwinTransition {
+            asset_id: "charger".into(),
+            from: from.to_string(),
+            to: to.to_string(),
+            timestamp,
+            correlation_id: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_sessions() {
+        let mut log = SessionLog::new(&SessionOptions {
+            session_state: "Charging".to_string(),
+            session_power_slot: "CurrentPowerDraw".to_string(),
+            sessions_per_twin: 2,
+        });
+        let id = AssetID::from("charger");
+        let start: DateTime<Utc> = "2025-03-01T10:00:00Z".parse().unwrap();
+        let at = |minutes| start + TimeDelta::minutes(minutes);
+        let power = |log: &mut SessionLog, minutes, kw| {
+            log.sample(&id, "CurrentPowerDraw", at(minutes), &SlotValue::Number(kw));
+        };
+
+        power(&mut log, 0, 0.0);
+        log.transition(&transition("Idle|Online", "Connected|Online", at(1)));
+        log.transition(&transition("Connected|Online", "Charging|Online", at(2)));
+        // 6 kW for 30 minutes, ramping up to 8 kW in the next 30 minutes
+        power(&mut log, 2, 6.0);
+        power(&mut log, 32, 6.0);
+        power(&mut log, 62, 8.0);
+        log.sample(&id, "InputCurrent", at(63), &SlotValue::Number(32.0));
+        let ongoing = log.report(&id, None, None).unwrap();
+        assert_eq!(ongoing.sessions.len(), 1);
+        assert_eq!(ongoing.sessions[0].ended, None);
+        assert_eq!(ongoing.sessions[0].duration_secs, 3600);
+
+        // A change of region keeps the session going
+        log.transition(&transition("Charging|Online", "Charging|Offline", at(70)));
+        // 8 kW held until the end of the session
+        log.transition(&transition("Charging|Offline", "Connected|Offline", at(92)));
+        let report = log.report(&id, None, None).unwrap();
+        let session = &report.sessions[0];
+        assert_eq!(session.ended, Some(at(92)));
+        assert_eq!(session.end_state.as_deref(), Some("Connected|Offline"));
+        assert_eq!(session.duration_secs, 90 * 60);
+        assert!((session.energy - (3.0 + 3.5 + 4.0)).abs() < 1e-9);
+        assert_eq!(session.peak_power, 8.0);
+        assert_eq!(session.samples, 3);
+
+        // Only the latest sessions are kept
+        for minutes in [100, 110] {
+            log.transition(&transition("Connected", "Charging", at(minutes)));
+            log.transition(&transition("Charging", "Connected", at(minutes + 5)));
+        }
+        let report = log.report(&id, None, None).unwrap();
+        assert_eq!(report.sessions.len(), 2);
+        assert_eq!(report.sessions[0].started, at(100));
+        // A session without samples holds the latest power
+        assert!((report.sessions[0].energy - 8.0 * 5.0 / 60.0).abs() < 1e-9);
+        let report = log.report(&id, Some(at(105)), None).unwrap();
+        assert_eq!(report.sessions.len(), 1);
+        assert!(log.report(&"other".into(), None, None).is_none());
+    }
+}
@@ -0,0 +1,586 @@
+use clap::Parser;
+use log::{debug, error, info, trace, warn};
+use rumqttc::{AsyncClient, EventLoop, MqttOptions, QoS};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+
+use crate::event_bus::{BusEvent, EventBus};
+use crate::failover::{self, SharedFailover};
+use crate::http_client;
+use crate::secrets::{self, SecretsProvider};
+
+/// Delay before reconnecting the MQTT sink to the broker
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Parser, Clone)]
+pub struct SinkOptions {
+    /// sink fed with the events of the bus, as "log", "mqtt:<topic prefix>" (published as
+    /// <prefix>/<kind>/<asset id>), "file:<path>" (JSON lines) or "influx:<write url>" (http://
+    /// or https://, InfluxDB line protocol; token from the influx_token secret); separate sinks
+    /// with ';' in EVENT_SINKS
+    #[clap(long = "event-sink", value_parser = parse_sink, value_delimiter = ';', env = "EVENT_SINKS")]
+    event_sinks: Vec<SinkSpec>,
+
+    /// events queued for each sink while it consumes the previous ones; when full, the sink
+    /// falls behind on the bus and misses the oldest events
+    #[clap(long, default_value_t = 256, env = "EVENT_SINK_QUEUE")]
+    event_sink_queue: usize,
+
+    /// maximum number of queued events consumed at once by a sink
+    #[clap(long, default_value_t = 100, env = "EVENT_SINK_BATCH")]
+    event_sink_batch: usize,
+}
+
+/// A built-in sink, as configured
+#[derive(Debug, Clone, PartialEq)]
+pub enum SinkSpec {
+    Log,
+    Mqtt(String),
+    File(PathBuf),
+    Influx(String),
+}
+
+fn parse_sink(s: &str) -> Result<SinkSpec, String> {
+    let invalid = || format!("expected log, mqtt:<topic prefix>, file:<path> or influx:<url>: {s}");
+    let s = s.trim();
+    if s == "log" {
+        return Ok(SinkSpec::Log);
+    }
+    let (kind, target) = s.split_once(':').ok_or_else(invalid)?;
+    let target = target.trim();
+    match kind.trim() {
+        "mqtt" if !target.is_empty() => Ok(SinkSpec::Mqtt(target.trim_end_matches('/').to_string())),
+        "file" if !target.is_empty() => Ok(SinkSpec::File(PathBuf::from(target))),
+        "influx" if target.starts_with("http://") || target.starts_with("https://") => {
+            Ok(SinkSpec::Influx(target.to_string()))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+/// Outcome of the consumption of a batch of events
+pub type SinkFuture<'a> = Pin<Box<dyn Future<Output = Result<(), String>> + Send + 'a>>;
+
+/// A consumer of the events of the bus. Each sink runs in its own task and receives the
+/// events in batches, oldest first; the next batch waits until the sink consumed the
+/// previous one, so a slow sink only delays itself.
+pub trait EventSink: Send {
+    /// Name of the sink, in the logs and in the metrics
+    fn name(&self) -> String;
+
+    /// Whether the sink consumes the event (all of them by default)
+    fn accepts(&self, _event: &BusEvent) -> bool {
+        true
+    }
+
+    /// Consume a batch of events
+    fn consume<'a>(&'a mut self, events: &'a [BusEvent]) -> SinkFuture<'a>;
+}
+
+/// Counters of a sink
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SinkStats {
+    pub consumed: u64,
+    /// Events of the batches the sink failed to consume
+    pub failed: u64,
+    /// Events missed while the queue of the sink was full
+    pub missed: u64,
+    pub last_error: Option<String>,
+}
+
+pub type SharedSinkStats = Arc<Mutex<BTreeMap<String, SinkStats>>>;
+
+/// Writes the events to the log
+pub struct LogSink;
+
+impl EventSink for LogSink {
+    fn name(&self) -> String {
+        "log".to_string()
+    }
+
+    fn consume<'a>(&'a mut self, events: &'a [BusEvent]) -> SinkFuture<'a> {
+        for event in events {
+            info!(target: "events", "{} {}", event.kind(), event.to_json());
+        }
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Publishes the events as JSON on its own connection to the broker
+pub struct MqttSink {
+    prefix: String,
+    client: AsyncClient,
+    /// Polled in the background from the first batch
+    connection: Option<EventLoop>,
+}
+
+impl MqttSink {
+    pub fn new(prefix: String, mqtt_options: MqttOptions) -> Self {
+        let (client, connection) = AsyncClient::new(mqtt_options, 10);
+        MqttSink {
+            prefix,
+            client,
+            connection: Some(connection),
+        }
+    }
+
+    fn topic(&self, event: &BusEvent) -> String {
+        format!("{}/{}/{}", self.prefix, event.kind(), event.asset_id())
+    }
+}
+
+impl EventSink for MqttSink {
+    fn name(&self) -> String {
+        format!("mqtt:{}", self.prefix)
+    }
+
+    fn consume<'a>(&'a mut self, events: &'a [BusEvent]) -> SinkFuture<'a> {
+        if let Some(mut connection) = self.connection.take() {
+            tokio::spawn(async move {
+                loop {
+                    match connection.poll().await {
+                        Ok(event) => trace!("Event sink MQTT event: {event:?}"),
+                        Err(e) => {
+                            error!("Event sink MQTT connection error: {e:?}");
+                            tokio::time::sleep(RECONNECT_DELAY).await;
+                        }
+                    }
+                }
+            });
+        }
+        Box::pin(async move {
+            for event in events {
+                self.client
+                    .publish(
+                        self.topic(event),
+                        QoS::AtLeastOnce,
+                        false,
+                        event.to_json().to_string(),
+                    )
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Appends the events to a file, one JSON object per line
+pub struct FileSink {
+    path: PathBuf,
+}
+
+impl FileSink {
+    pub fn new(path: PathBuf) -> Self {
+        FileSink { path }
+    }
+}
+
+/// An event as a JSON line, with its kind
+fn json_line(event: &BusEvent) -> String {
+    let mut json = event.to_json();
+    if let Some(object) = json.as_object_mut() {
+        object.insert("kind".to_string(), event.kind().into());
+    }
+    format!("{json}\n")
+}
+
+impl EventSink for FileSink {
+    fn name(&self) -> String {
+        format!("file:{}", self.path.display())
+    }
+
+    fn consume<'a>(&'a mut self, events: &'a [BusEvent]) -> SinkFuture<'a> {
+        let lines: String = events.iter().map(json_line).collect();
+        Box::pin(async move {
+            let mut file = tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .await
+                .map_err(|e| e.to_string())?;
+            file.write_all(lines.as_bytes())
+                .await
+                .map_err(|e| e.to_string())?;
+            file.flush().await.map_err(|e| e.to_string())
+        })
+    }
+}
+
+/// Writes the events to InfluxDB, a batch per request
+pub struct InfluxSink {
+    url: String,
+    token: Option<String>,
+}
+
+impl InfluxSink {
+    pub fn new(url: String, secrets: &SecretsProvider) -> Self {
+        InfluxSink {
+            url,
+            token: secrets.get(secrets::INFLUX_TOKEN),
+        }
+    }
+}
+
+/// Escape a measurement, tag key or tag value of the line protocol
+fn escape_tag(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+}
+
+fn string_field(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// An event in the InfluxDB line protocol: the measurement is the kind of event, tagged
+/// with the twin; the events keep the scalar fields of their payload
+fn influx_line(event: &BusEvent) -> String {
+    let mut tags = vec![("asset_id", event.asset_id().to_string())];
+    let mut fields: Vec<(String, String)> = Vec::new();
+    match event {
+        BusEvent::Transition(transition) => {
+            fields.push(("from".to_string(), string_field(&transition.from)));
+            fields.push(("to".to_string(), string_field(&transition.to)));
+        }
+        BusEvent::Event(emitted) => {
+            tags.push(("event", emitted.event.event.clone()));
+            if let Some(payload) = emitted.event.payload.as_object() {
+                for (name, value) in payload {
+                    let value = match value {
+                        serde_json::Value::Number(n) if n.is_f64() => n.to_string(),
+                        serde_json::Value::Number(n) => format!("{n}i"),
+                        serde_json::Value::Bool(b) => b.to_string(),
+                        serde_json::Value::String(s) => string_field(s),
+                        _ => continue,
+                    };
+                    fields.push((escape_tag(name), value));
+                }
+            }
+            if fields.is_empty() {
+                fields.push(("count".to_string(), "1i".to_string()));
+            }
+        }
+        BusEvent::Error(error) => fields.push(("error".to_string(), string_field(&error.error))),
+        BusEvent::Lifecycle(lifecycle) => {
+            let stage = serde_json::to_value(lifecycle.stage).unwrap_or_default();
+            fields.push((
+                "stage".to_string(),
+                string_field(stage.as_str().unwrap_or_default()),
+            ));
+        }
+    }
+    let tags: String = tags
+        .iter()
+        .map(|(key, value)| format!(",{key}={}", escape_tag(value)))
+        .collect();
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|(key, value)| format!("{key}={value}"))
+        .collect();
+    format!(
+        "twin_{}{tags} {} {}\n",
+        event.kind(),
+        fields.join(","),
+        event.timestamp().timestamp_nanos_opt().unwrap_or_default()
+    )
+}
+
+impl EventSink for InfluxSink {
+    fn name(&self) -> String {
+        format!("influx:{}", self.url)
+    }
+
+    fn consume<'a>(&'a mut self, events: &'a [BusEvent]) -> SinkFuture<'a> {
+        let lines: String = events.iter().map(influx_line).collect();
+        Box::pin(async move {
+            let authorization = self.token.as_ref().map(|token| format!("Token {token}"));
+            let headers: Vec<(&str, &str)> = authorization
+                .iter()
+                .map(|value| ("Authorization", value.as_str()))
+                .collect();
+            http_client::post(&self.url, &headers, "text/plain; charset=utf-8", &lines)
+                .await
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        })
+    }
+}
+
+/// The sinks fed with the events of the bus: the configured built-in ones and the ones
+/// registered by an embedding application
+pub struct Sinks {
+    sinks: Vec<Box<dyn EventSink>>,
+    bus: EventBus,
+    queue: usize,
+    batch: usize,
+    stats: SharedSinkStats,
+    /// A standby feeds no sink
+    failover: SharedFailover,
+}
+
+impl Sinks {
+    pub fn new(
+        options: &SinkOptions,
+        bus: EventBus,
+        mqtt_options: impl Fn(String) -> MqttOptions,
+        secrets: &SecretsProvider,
+        failover: SharedFailover,
+    ) -> Self {
+        let mut sinks = Sinks {
+            sinks: Vec::new(),
+            bus,
+            queue: options.event_sink_queue.max(1),
+            batch: options.event_sink_batch.max(1),
+            stats: SharedSinkStats::default(),
+            failover,
+        };
+        for (i, spec) in options.event_sinks.iter().enumerate() {
+            let sink: Box<dyn EventSink> = match spec {
+                SinkSpec::Log => Box::new(LogSink),
+                SinkSpec::Mqtt(prefix) => {
+                    let mqtt_options = mqtt_options(format!("dt-sink-{i}"));
+                    Box::new(MqttSink::new(prefix.clone(), mqtt_options))
+                }
+                SinkSpec::File(path) => Box::new(FileSink::new(path.clone())),
+                SinkSpec::Influx(url) => Box::new(InfluxSink::new(url.clone(), secrets)),
+            };
+            sinks.register(sink);
+        }
+        sinks
+    }
+
+    /// Add a sink, fed once the body starts
+    pub fn register(&mut self, sink: Box<dyn EventSink>) {
+        self.stats
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(sink.name(), SinkStats::default());
+        self.sinks.push(sink);
+    }
+
+    /// The counters of the sinks
+    pub fn stats(&self) -> SharedSinkStats {
+        self.stats.clone()
+    }
+
+    pub async fn body(&mut self) {
+        if self.sinks.is_empty() {
+            return;
+        }
+        info!("Event sinks starting: {} sinks", self.sinks.len());
+        let mut tasks = JoinSet::new();
+        for sink in std::mem::take(&mut self.sinks) {
+            let (queue_ch, queue) = mpsc::channel(self.queue);
+            tasks.spawn(feed(
+                sink.name(),
+                self.bus.clone(),
+                queue_ch,
+                self.stats.clone(),
+                self.failover.clone(),
+            ));
+            tasks.spawn(drain(sink, queue, self.batch, self.stats.clone()));
+        }
+        while tasks.join_next().await.is_some() {}
+    }
+}
+
+/// Queue the events of the bus for a sink, counting the ones missed while the queue is full
+async fn feed(
+    name: String,
+    bus: EventBus,
+    queue_ch: mpsc::Sender<BusEvent>,
+    stats: SharedSinkStats,
+    failover: SharedFailover,
+) {
+    let mut receiver = bus.subscribe();
+    loop {
+        let event = match receiver.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(missed)) => {
+                warn!("Event sink {name} missed {missed} events");
+                let mut stats = stats.lock().unwrap_or_else(|e| e.into_inner());
+                stats.entry(name.clone()).or_default().missed += missed;
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        if !failover::is_active(&failover) {
+            continue;
+        }
+        // Waiting here leaves the events on the bus while the sink is busy
+        if queue_ch.send(event).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Consume the queued events in batches
+async fn drain(
+    mut sink: Box<dyn EventSink>,
+    mut queue: mpsc::Receiver<BusEvent>,
+    batch: usize,
+    stats: SharedSinkStats,
+) {
+    let name = sink.name();
+    let mut events = Vec::with_capacity(batch);
+    while queue.recv_many(&mut events, batch).await > 0 {
+        events.retain(|event| sink.accepts(event));
+        if !events.is_empty() {
+            let result = sink.consume(&events).await;
+            let mut stats = stats.lock().unwrap_or_else(|e| e.into_inner());
+            let stats = stats.entry(name.clone()).or_default();
+            match result {
+                Ok(()) => {
+                    debug!("Event sink {name} consumed {} events", events.len());
+                    stats.consumed += events.len() as u64;
+                }
+                Err(e) => {
+                    warn!("Event sink {name} failed to consume {} events: {e}", events.len());
+                    stats.failed += events.len() as u64;
+                    stats.last_error = Some(e);
+                }
+            }
+        }
+        events.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_bus::{EmittedEvent, Lifecycle, LifecycleEvent};
+    use crate::failover::{Failover, FailoverOptions, Role};
+    use crate::network_receiver::TwinEvent;
+    use crate::secrets::SecretsOptions;
+    use crate::twin_runner::TwinTransition;
+    use chrono::{DateTime, Utc};
+    use digitaltwin_core::CorrelationID;
+
+    /// Collects the events of the twin it accepts
+    struct Collector(Arc<Mutex<Vec<BusEvent>>>);
+
+    impl EventSink for Collector {
+        fn name(&self) -> String {
+            "collector".to_string()
+        }
+
+        fn accepts(&self, event: &BusEvent) -> bool {
+            matches!(event, BusEvent::Transition(_))
+        }
+
+        fn consume<'a>(&'a mut self, events: &'a [BusEvent]) -> SinkFuture<'a> {
+            self.0.lock().unwrap().extend_from_slice(events);
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    fn transition(timestamp: DateTime<Utc>) -> BusEvent {
+        BusEvent::Transition(TwinTransition {
+            asset_id: "urn:aas:charger:id-1".into(),
+            from: "Idle".to_string(),
+            to: "Charging".to_string(),
+            timestamp,
+            correlation_id: CorrelationID::from("c-1"),
+        })
+    }
+
+    #[test]
+    fn test_parse_sink() {
+        assert_eq!(parse_sink("log").unwrap(), SinkSpec::Log);
+        assert_eq!(
+            parse_sink("mqtt:twins/bus/").unwrap(),
+            SinkSpec::Mqtt("twins/bus".to_string())
+        );
+        assert_eq!(
+            parse_sink("file:/var/log/events.jsonl").unwrap(),
+            SinkSpec::File(PathBuf::from("/var/log/events.jsonl"))
+        );
+        assert_eq!(
+            parse_sink("influx:https://influx.local/api/v2/write").unwrap(),
+            SinkSpec::Influx("https://influx.local/api/v2/write".to_string())
+        );
+        assert!(parse_sink("influx:influx.local/api/v2/write").is_err());
+        assert!(parse_sink("kafka:events").is_err());
+    }
+
+    #[test]
+    fn test_influx_line() {
+        let timestamp: DateTime<Utc> = "2025-03-01T10:00:00Z".parse().unwrap();
+        assert_eq!(
+            influx_line(&transition(timestamp)),
+            "twin_transition,asset_id=urn:aas:charger:id-1 from=\"Idle\",to=\"Charging\" 1740823200000000000\n"
+        );
+        let event = BusEvent::Event(EmittedEvent {
+            asset_id: "urn:aas:charger:id-1".into(),
+            event: TwinEvent {
+                event: "Charging Complete".to_string(),
+                timestamp,
+                payload: serde_json::json!({ "power": 1.5, "phases": 3, "note": "a \"b\"", "list": [1] }),
+                correlation_id: CorrelationID::from("c-1"),
+            },
+            topic: None,
+        });
+        assert_eq!(
+            influx_line(&event),
+            "twin_event,asset_id=urn:aas:charger:id-1,event=Charging\\ Complete note=\"a \\\"b\\\"\",phases=3i,power=1.5 1740823200000000000\n"
+        );
+        let mut lifecycle = LifecycleEvent::now("urn:aas:charger:id-1".into(), Lifecycle::Crashed);
+        lifecycle.timestamp = timestamp;
+        assert_eq!(
+            influx_line(&BusEvent::Lifecycle(lifecycle)),
+            "twin_lifecycle,asset_id=urn:aas:charger:id-1 stage=\"crashed\" 1740823200000000000\n"
+        );
+        let line = json_line(&transition(timestamp));
+        assert!(line.ends_with('\n'));
+        let json: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(json["kind"], "transition");
+        assert_eq!(json["to"], "Charging");
+    }
+
+    #[tokio::test]
+    async fn test_sinks() {
+        let options = SinkOptions::parse_from(["test", "--event-sink", "log"]);
+        let bus = EventBus::default();
+        let failover = Failover::shared(&FailoverOptions {
+            role: Role::Primary,
+            heartbeat_topic: "twins/runtime/heartbeat".to_string(),
+            failover_timeout: 15,
+        });
+        let mut sinks = Sinks::new(
+            &options,
+            bus.clone(),
+            |client_id| MqttOptions::new(client_id, "localhost", 1883),
+            &SecretsProvider::load(&SecretsOptions::parse_from(["test"]))
+                .await
+                .unwrap(),
+            failover,
+        );
+        let collected = Arc::new(Mutex::new(Vec::new()));
+        sinks.register(Box::new(Collector(collected.clone())));
+        let stats = sinks.stats();
+        tokio::spawn(async move { sinks.body().await });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        bus.publish(BusEvent::Lifecycle(LifecycleEvent::now(
+            "urn:aas:charger:id-1".into(),
+            Lifecycle::Started,
+        )));
+        bus.publish(transition(Utc::now()));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(collected.lock().unwrap().len(), 1);
+        let stats = stats.lock().unwrap();
+        assert_eq!(stats["collector"].consumed, 1);
+        assert_eq!(stats["log"].consumed, 2);
+    }
+}
@@ -0,0 +1,437 @@
+use chrono::{DateTime, Duration as ChronoDuration, Local, NaiveTime, TimeZone, Utc};
+use clap::Parser;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::command::{CommandEnvelope, CommandSource};
+use crate::failover::{self, SharedFailover};
+use crate::http_client;
+use crate::manager::{ManagerMessage, Query};
+use digitaltwin_core::{AssetAdministrationShell, AssetID};
+
+/// Principal of the commands issued by the optimizer
+pub const SMART_CHARGING_PRINCIPAL: &str = "smart-charging";
+/// Command setting the charging current of a charging station
+const SET_CHARGING_CURRENT: &str = "SetChargingCurrent";
+/// Interval between the checks of the plans
+const TICK_INTERVAL: Duration = Duration::from_secs(10);
+/// Length of the price slots, when not given by the next slot
+const DEFAULT_SLOT: ChronoDuration = ChronoDuration::hours(1);
+
+#[derive(Parser, Clone)]
+pub struct SmartChargingOptions {
+    /// day-ahead electricity prices (http:// or https://), a JSON list of {"start": <RFC 3339 time>,
+    /// "price": <price per kWh>}; enables the smart charging of the charging stations
+    #[clap(long, env = "PRICE_SOURCE_URL")]
+    price_source: Option<String>,
+
+    /// minutes between the price fetches, each one updating the charging plans
+    #[clap(long, default_value_t = 60, env = "PRICE_REFRESH_MINUTES")]
+    price_refresh_minutes: u64,
+
+    /// energy to deliver to each vehicle by the deadline, in kWh
+    #[clap(long, default_value_t = 10.0, env = "CHARGING_ENERGY_KWH")]
+    charging_energy_kwh: f64,
+
+    /// local time by which the vehicles must be charged (e.g., "07:00")
+    #[clap(long, default_value = "07:00", env = "CHARGING_DEADLINE")]
+    charging_deadline: NaiveTime,
+
+    /// voltage of the charging stations, converting the power into the charging current
+    #[clap(long, default_value_t = 230.0, env = "CHARGING_VOLTAGE")]
+    charging_voltage: f64,
+
+    /// maximum power of the charging stations not declaring a "MaxPowerOutput" (kW)
+    #[clap(long, default_value_t = 3.7, env = "DEFAULT_CHARGING_POWER")]
+    default_charging_power: f64,
+}
+
+/// Price of the electricity in a time slot, as published by the price source
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PriceSlot {
+    pub start: DateTime<Utc>,
+    #[serde(default)]
+    pub end: Option<DateTime<Utc>>,
+    /// price per kWh
+    pub price: f64,
+}
+
+/// A time slot in which a station charges, at the given current
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PlannedSlot {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub current: f64,
+    pub price: f64,
+}
+
+/// Charging plan of a charging station, up to the deadline
+#[derive(Debug, Clone, Serialize)]
+pub struct ChargingPlan {
+    pub asset_id: AssetID,
+    pub max_power_kw: f64,
+    pub deadline: DateTime<Utc>,
+    /// Energy the plan delivers, less than requested if there are not enough priced slots
+    pub energy_kwh: f64,
+    pub estimated_cost: f64,
+    pub slots: Vec<PlannedSlot>,
+}
+
+impl ChargingPlan {
+    /// The current the station should charge at, at the given time (0 outside the planned slots)
+    pub fn current_at(&self, time: DateTime<Utc>) -> f64 {
+        self.slots
+            .iter()
+            .find(|slot| slot.start <= time && time < slot.end)
+            .map_or(0.0, |slot| slot.current)
+    }
+}
+
+/// Sort the price slots and fill in their end, as the start of the next slot
+pub fn normalize_prices(mut prices: Vec<PriceSlot>) -> Vec<PriceSlot> {
+    prices.sort_by_key(|slot| slot.start);
+    let starts: Vec<_> = prices
+        .iter()
+        .skip(1)
+        .map(|slot| Some(slot.start))
+        .chain([None])
+        .collect();
+    for (slot, next_start) in prices.iter_mut().zip(starts) {
+        if slot.end.is_none() {
+            slot.end = Some(next_start.unwrap_or(slot.start + DEFAULT_SLOT));
+        }
+    }
+    prices
+}
+
+/// Plan the charging of a station in the cheapest slots between now and the deadline,
+/// at the maximum power, delivering the requested energy. The most expensive slot of
+/// the plan is used at a lower current if only a part of it is needed.
+pub fn plan_charging(
+    prices: &[PriceSlot],
+    now: DateTime<Utc>,
+    deadline: DateTime<Utc>,
+    energy_kwh: f64,
+    max_power_kw: f64,
+    voltage: f64,
+) -> (Vec<PlannedSlot>, f64) {
+    let mut candidates: Vec<_> = prices
+        .iter()
+        .filter_map(|slot| {
+            let start = slot.start.max(now);
+            let end = slot.end.unwrap_or(slot.start + DEFAULT_SLOT).min(deadline);
+            (start < end).then_some((start, end, slot.price))
+        })
+        .collect();
+    candidates.sort_by(|a, b| a.2.total_cmp(&b.2).then(a.0.cmp(&b.0)));
+    let max_current = max_power_kw * 1000.0 / voltage;
+    let mut remaining = energy_kwh;
+    let mut slots = Vec::new();
+    for (start, end, price) in candidates {
+        if remaining <= 0.0 {
+            break;
+        }
+        let hours = (end - start).num_seconds() as f64 / 3600.0;
+        let energy = (max_power_kw * hours).min(remaining);
+        remaining -= energy;
+        slots.push(PlannedSlot {
+            start,
+            end,
+            current: max_current * energy / (max_power_kw * hours),
+            price,
+        });
+    }
+    slots.sort_by_key(|slot| slot.start);
+    (slots, energy_kwh - remaining.max(0.0))
+}
+
+/// State of the optimizer, shown by the REST API
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SmartChargingStatus {
+    pub enabled: bool,
+    pub last_fetch: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub prices: Vec<PriceSlot>,
+    pub plans: Vec<ChargingPlan>,
+}
+
+/// Optimizer state shared with the REST server
+pub type SharedChargingStatus = Arc<Mutex<SmartChargingStatus>>;
+
+/// Optional optimizer of the charging stations: fetches the day-ahead electricity prices,
+/// plans the charging of each station in the cheapest hours before the deadline, and sets
+/// the charging current at the start of each slot through the manager.
+pub struct SmartCharging {
+    status: SharedChargingStatus,
+    manager_ch: mpsc::Sender<ManagerMessage>,
+    /// Current last set on each station
+    applied: HashMap<AssetID, f64>,
+    /// A standby instance plans, but leaves the commands to the active one
+    failover: SharedFailover,
+    options: SmartChargingOptions,
+}
+
+impl SmartCharging {
+    pub fn new(
+        options: SmartChargingOptions,
+        manager_ch: mpsc::Sender<ManagerMessage>,
+        failover: SharedFailover,
+    ) -> Self {
+        let status = SmartChargingStatus {
+            enabled: options.price_source.is_some(),
+            ..Default::default()
+        };
+        SmartCharging {
+            status: Arc::new(Mutex::new(status)),
+            manager_ch,
+            applied: HashMap::new(),
+            failover,
+            options,
+        }
+    }
+
+    pub fn status(&self) -> SharedChargingStatus {
+        self.status.clone()
+    }
+
+    fn lock_status(&self) -> std::sync::MutexGuard<'_, SmartChargingStatus> {
+        self.status.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The next occurrence of the charging deadline
+    fn deadline(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let local = now.with_timezone(&Local);
+        let mut date = local.date_naive();
+        if local.time() >= self.options.charging_deadline {
+            date = date.succ_opt().unwrap_or(date);
+        }
+        Local
+            .from_local_datetime(&date.and_time(self.options.charging_deadline))
+            .earliest()
+            .map_or(now + ChronoDuration::days(1), |deadline| {
+                deadline.with_timezone(&Utc)
+            })
+    }
+
+    async fn fetch_prices(&self, url: &str) -> Result<Vec<PriceSlot>, String> {
+        let body = http_client::get(url, &[("Accept", "application/json")])
+            .await
+            .map_err(|e| e.to_string())?;
+        let prices = serde_json::from_str(&body).map_err(|e| format!("invalid prices: {e}"))?;
+        Ok(normalize_prices(prices))
+    }
+
+    /// The charging stations among the running twins, with their maximum power
+    async fn charging_stations(&self) -> Vec<(AssetID, f64)> {
+        let (reply, response) = oneshot::channel();
+        if self
+            .manager_ch
+            .send(ManagerMessage::Query(Query::Shells(reply)))
+            .await
+            .is_err()
+        {
+            return Vec::new();
+        }
+        let shells: Vec<AssetAdministrationShell> = response.await.unwrap_or_default();
+        shells
+            .iter()
+            .filter(|aas| aas.operations().any(|op| op.id_short == SET_CHARGING_CURRENT))
+            .map(|aas| {
+                let power = aas
+                    .get_property_f64("PowerAndElectrical", "MaxPowerOutput")
+                    .unwrap_or(self.options.default_charging_power);
+                (aas.id.clone(), power)
+            })
+            .collect()
+    }
+
+    /// Fetch the prices and plan the charging of all the stations
+    async fn update_plans(&mut self, url: &str) {
+        let now = Utc::now();
+        let prices = match self.fetch_prices(url).await {
+            Ok(prices) => prices,
+            Err(e) => {
+                warn!("Cannot fetch the electricity prices from {url}: {e}");
+                self.lock_status().last_error = Some(e);
+                return;
+            }
+        };
+        let deadline = self.deadline(now);
+        let plans: Vec<_> = self
+            .charging_stations()
+            .await
+            .into_iter()
+            .map(|(asset_id, max_power_kw)| {
+                let (slots, energy_kwh) = plan_charging(
+                    &prices,
+                    now,
+                    deadline,
+                    self.options.charging_energy_kwh,
+                    max_power_kw,
+                    self.options.charging_voltage,
+                );
+                if energy_kwh < self.options.charging_energy_kwh {
+                    warn!(
+                        "Only {energy_kwh:.1} kWh can be delivered to {asset_id} by {deadline} with the known prices"
+                    );
+                }
+                let estimated_cost = slots
+                    .iter()
+                    .map(|s| {
+                        s.current * self.options.charging_voltage / 1000.0 * s.price * (s.end - s.start).num_seconds()
+                            as f64
+                            / 3600.0
+                    })
+                    .sum();
+                ChargingPlan {
+                    asset_id,
+                    max_power_kw,
+                    deadline,
+                    energy_kwh,
+                    estimated_cost,
+                    slots,
+                }
+            })
+            .collect();
+        info!(
+            "Planned the charging of {} stations with {} price slots",
+            plans.len(),
+            prices.len()
+        );
+        let mut status = self.lock_status();
+        status.last_fetch = Some(now);
+        status.last_error = None;
+        status.prices = prices;
+        status.plans = plans;
+    }
+
+    /// Set the charging current of the stations whose planned current changed
+    async fn apply_plans(&mut self) {
+        if !failover::is_active(&self.failover) {
+            return;
+        }
+        let now = Utc::now();
+        let targets: Vec<_> = self
+            .lock_status()
+            .plans
+            .iter()
+            .map(|plan| (plan.asset_id.clone(), plan.current_at(now)))
+            .collect();
+        for (asset_id, current) in targets {
+            if self.applied.get(&asset_id) == Some(&current) {
+                continue;
+            }
+            let args = serde_json::json!({ "desired_current": current });
+            let envelope = CommandEnvelope::new(CommandSource::Optimizer, SET_CHARGING_CURRENT, args)
+                .with_principal(Some(SMART_CHARGING_PRINCIPAL.to_string()));
+            let (reply, response) = oneshot::channel();
+            let query = Query::Invoke(asset_id.clone(), envelope, reply);
+            if self.manager_ch.send(ManagerMessage::Query(query)).await.is_err() {
+                return;
+            }
+            let outcome = response.await.ok().flatten();
+            debug!("Set charging current of {asset_id} to {current:.1} A: {outcome:?}");
+            // The plan is applied again at the next change, whatever the outcome
+            self.applied.insert(asset_id, current);
+        }
+    }
+
+    pub async fn body(&mut self) {
+        let Some(url) = self.options.price_source.clone() else {
+            return;
+        };
+        info!("Smart charging starting, prices from {url}");
+        let mut refresh = tokio::time::interval(Duration::from_secs(
+            self.options.price_refresh_minutes.max(1) * 60,
+        ));
+        let mut tick = tokio::time::interval(TICK_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = refresh.tick() => {
+                    self.update_plans(&url).await;
+                    self.apply_plans().await;
+                }
+                _ = tick.tick() => {
+                    self.apply_plans().await;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 5, 1, 0, 0, 0).unwrap() + ChronoDuration::hours(hour as i64)
+    }
+
+    fn prices(prices: &[f64]) -> Vec<PriceSlot> {
+        normalize_prices(
+            prices
+                .iter()
+                .enumerate()
+                .map(|(hour, &price)| PriceSlot {
+                    start: at(hour as u32),
+                    end: None,
+                    price,
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_cheapest_slots() {
+        let prices = prices(&[0.30, 0.10, 0.25, 0.05, 0.20, 0.01]);
+        assert_eq!(prices[5].end, Some(at(6)));
+        // 11 kW for 2.5 hours before 05:00, in the 3 cheapest hours
+        let (slots, energy) = plan_charging(&prices, at(0), at(5), 27.5, 11.0, 230.0);
+        assert_eq!(energy, 27.5);
+        let starts: Vec<_> = slots.iter().map(|s| s.start).collect();
+        assert_eq!(starts, [at(1), at(3), at(4)]);
+        let full = 11000.0 / 230.0;
+        assert_eq!(slots[0].current, full);
+        assert_eq!(slots[1].current, full);
+        assert!((slots[2].current - full / 2.0).abs() < 1e-9);
+
+        let plan = ChargingPlan {
+            asset_id: "urn:charger".into(),
+            max_power_kw: 11.0,
+            deadline: at(5),
+            energy_kwh: energy,
+            estimated_cost: 0.0,
+            slots,
+        };
+        assert_eq!(plan.current_at(at(0)), 0.0);
+        assert_eq!(plan.current_at(at(3) + ChronoDuration::minutes(30)), full);
+    }
+
+    #[test]
+    fn test_partial_and_short_plans() {
+        let prices = prices(&[0.10, 0.20]);
+        // Planning in the middle of a slot uses what is left of it
+        let now = at(0) + ChronoDuration::minutes(30);
+        let (slots, energy) = plan_charging(&prices, now, at(10), 100.0, 10.0, 250.0);
+        assert_eq!(slots[0].start, now);
+        assert_eq!(energy, 15.0);
+        assert_eq!(slots.len(), 2);
+        // Nothing to plan after the deadline
+        let (slots, energy) = plan_charging(&prices, at(3), at(10), 10.0, 10.0, 250.0);
+        assert!(slots.is_empty());
+        assert_eq!(energy, 0.0);
+    }
+
+    #[test]
+    fn test_deadline_option() {
+        let options = SmartChargingOptions::parse_from(["test", "--charging-deadline", "06:30"]);
+        assert_eq!(
+            options.charging_deadline,
+            NaiveTime::from_hms_opt(6, 30, 0).unwrap()
+        );
+    }
+}
@@ -0,0 +1,549 @@
+use chrono::Utc;
+use clap::Parser;
+use log::{debug, error, info, trace, warn};
+use rumqttc::{AsyncClient, Event, LastWill, MqttOptions, Packet, QoS};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::event_bus::{BusEvent, EventBus};
+use crate::failover::SharedFailover;
+use crate::manager::{ManagerMessage, Query};
+use crate::twin_runner::{TwinReport, TwinTransition};
+use digitaltwin_core::AssetID;
+
+/// Namespace of the Sparkplug B topics
+const NAMESPACE: &str = "spBv1.0";
+/// Birth/death sequence number, matching an NDEATH with the NBIRTH of the same connection
+const BD_SEQ: &str = "bdSeq";
+/// Node control metric written by the SCADA host to request a new NBIRTH
+const REBIRTH: &str = "Node Control/Rebirth";
+/// Interval of the check of the twins started or removed since the last NBIRTH
+const TWINS_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+/// Delay before reconnecting to the broker
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Parser, Clone)]
+pub struct SparkplugOptions {
+    /// Sparkplug B group ID: if set, the runtime is a Sparkplug edge node publishing the state
+    /// of each twin as the "<asset id>/State" metric (NBIRTH, then NDATA on each transition);
+    /// the NBIRTH also has the "<asset id>/Latitude" and "<asset id>/Longitude" metrics of the
+    /// twins with a location
+    #[clap(long, env = "SPARKPLUG_GROUP")]
+    sparkplug_group: Option<String>,
+
+    /// Sparkplug B edge node ID
+    #[clap(long, default_value = "digitaltwin", env = "SPARKPLUG_NODE")]
+    sparkplug_node: String,
+}
+
+/// Value of a metric, with its Sparkplug B data type
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetricValue {
+    UInt64(u64),
+    Double(f64),
+    Boolean(bool),
+    String(String),
+}
+
+impl MetricValue {
+    fn datatype(&self) -> u64 {
+        match self {
+            MetricValue::UInt64(_) => 8,
+            MetricValue::Double(_) => 10,
+            MetricValue::Boolean(_) => 11,
+            MetricValue::String(_) => 12,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metric {
+    pub name: String,
+    /// Milliseconds since the epoch
+    pub timestamp: u64,
+    pub value: MetricValue,
+}
+
+impl Metric {
+    fn new(name: impl Into<String>, timestamp: u64, value: MetricValue) -> Self {
+        Metric {
+            name: name.into(),
+            timestamp,
+            value,
+        }
+    }
+}
+
+/// A Sparkplug B payload, encoded as its protobuf message. Only the fields and the data
+/// types used by the runtime are supported: the other ones are skipped when decoding.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Payload {
+    /// Milliseconds since the epoch
+    pub timestamp: u64,
+    pub metrics: Vec<Metric>,
+    /// Sequence number of the message, absent in NDEATH
+    pub seq: Option<u64>,
+}
+
+impl Payload {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        put_varint_field(&mut buf, 1, self.timestamp);
+        for metric in &self.metrics {
+            let mut encoded = Vec::new();
+            put_bytes_field(&mut encoded, 1, metric.name.as_bytes());
+            put_varint_field(&mut encoded, 3, metric.timestamp);
+            put_varint_field(&mut encoded, 4, metric.value.datatype());
+            match &metric.value {
+                MetricValue::UInt64(v) => put_varint_field(&mut encoded, 11, *v),
+                MetricValue::Double(v) => put_fixed64_field(&mut encoded, 13, v.to_bits()),
+                MetricValue::Boolean(b) => put_varint_field(&mut encoded, 14, u64::from(*b)),
+                MetricValue::String(s) => put_bytes_field(&mut encoded, 15, s.as_bytes()),
+            }
+            put_bytes_field(&mut buf, 2, &encoded);
+        }
+        if let Some(seq) = self.seq {
+            put_varint_field(&mut buf, 3, seq);
+        }
+        buf
+    }
+
+    pub fn decode(mut bytes: &[u8]) -> Result<Self, String> {
+        let mut payload = Payload::default();
+        while !bytes.is_empty() {
+            match read_field(&mut bytes)? {
+                (1, Field::Varint(timestamp)) => payload.timestamp = timestamp,
+                (2, Field::Bytes(metric)) => payload.metrics.extend(decode_metric(metric)?),
+                (3, Field::Varint(seq)) => payload.seq = Some(seq),
+                _ => {}
+            }
+        }
+        Ok(payload)
+    }
+}
+
+/// Decode a metric, or None if its value has an unsupported data type
+fn decode_metric(mut bytes: &[u8]) -> Result<Option<Metric>, String> {
+    let (mut name, mut timestamp, mut value) = (String::new(), 0, None);
+    while !bytes.is_empty() {
+        match read_field(&mut bytes)? {
+            (1, Field::Bytes(s)) => name = String::from_utf8_lossy(s).into_owned(),
+            (3, Field::Varint(t)) => timestamp = t,
+            (10 | 11, Field::Varint(v)) => value = Some(MetricValue::UInt64(v)),
+            (13, Field::Fixed64(v)) => value = Some(MetricValue::Double(f64::from_bits(v))),
+            (14, Field::Varint(b)) => value = Some(MetricValue::Boolean(b != 0)),
+            (15, Field::Bytes(s)) => {
+                value = Some(MetricValue::String(String::from_utf8_lossy(s).into_owned()))
+            }
+            _ => {}
+        }
+    }
+    Ok(value.map(|value| Metric::new(name, timestamp, value)))
+}
+
+enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    /// Doubles
+    Fixed64(u64),
+    /// Floats, not used by the runtime
+    Fixed32,
+}
+
+fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn put_varint_field(buf: &mut Vec<u8>, field: u64, value: u64) {
+    put_varint(buf, field << 3);
+    put_varint(buf, value);
+}
+
+fn put_fixed64_field(buf: &mut Vec<u8>, field: u64, value: u64) {
+    put_varint(buf, field << 3 | 1);
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_bytes_field(buf: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    put_varint(buf, field << 3 | 2);
+    put_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Result<u64, String> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = bytes.split_first().ok_or("truncated varint")?;
+        *bytes = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err("varint too long".to_string())
+}
+
+fn read_field<'a>(bytes: &mut &'a [u8]) -> Result<(u64, Field<'a>), String> {
+    let key = read_varint(bytes)?;
+    let take = |bytes: &mut &'a [u8], len: usize| {
+        if bytes.len() < len {
+            return Err("truncated field".to_string());
+        }
+        let (field, rest) = bytes.split_at(len);
+        *bytes = rest;
+        Ok(field)
+    };
+    let field = match key & 7 {
+        0 => Field::Varint(read_varint(bytes)?),
+        1 => take(bytes, 8).map(|v| Field::Fixed64(u64::from_le_bytes(v.try_into().unwrap_or_default())))?,
+        2 => {
+            let len = read_varint(bytes)? as usize;
+            Field::Bytes(take(bytes, len)?)
+        }
+        5 => take(bytes, 4).map(|_| Field::Fixed32)?,
+        wire_type => return Err(format!("unsupported wire type {wire_type}")),
+    };
+    Ok((key >> 3, field))
+}
+
+fn now_millis() -> u64 {
+    Utc::now().timestamp_millis().max(0) as u64
+}
+
+/// Publishes the state of the twins as a Sparkplug B edge node, on its own connection
+/// to the broker: the NDEATH is the last will of the connection
+pub struct SparkplugPublisher {
+    options: SparkplugOptions,
+    mqtt_options: MqttOptions,
+    manager_ch: mpsc::Sender<ManagerMessage>,
+    bus: EventBus,
+    /// A standby publishes nothing
+    failover: SharedFailover,
+    /// Birth/death sequence number of the current connection
+    bd_seq: u64,
+    /// Sequence number of the last message published, 0 for the NBIRTH
+    seq: u64,
+    /// State of each twin, as last published; None until the NBIRTH
+    states: Option<HashMap<AssetID, String>>,
+}
+
+impl SparkplugPublisher {
+    pub fn new(
+        options: SparkplugOptions,
+        mqtt_options: MqttOptions,
+        manager_ch: mpsc::Sender<ManagerMessage>,
+        bus: EventBus,
+        failover: SharedFailover,
+    ) -> Self {
+        SparkplugPublisher {
+            options,
+            mqtt_options,
+            manager_ch,
+            bus,
+            failover,
+            bd_seq: 0,
+            seq: 0,
+            states: None,
+        }
+    }
+
+    fn topic(&self, group: &str, message_type: &str) -> String {
+        format!(
+            "{NAMESPACE}/{group}/{message_type}/{}",
+            self.options.sparkplug_node
+        )
+    }
+
+    fn death_will(&self, group: &str) -> LastWill {
+        let payload = Payload {
+            timestamp: now_millis(),
+            metrics: vec![Metric::new(
+                BD_SEQ,
+                now_millis(),
+                MetricValue::UInt64(self.bd_seq),
+            )],
+            seq: None,
+        };
+        LastWill::new(
+            self.topic(group, "NDEATH"),
+            payload.encode(),
+            QoS::AtLeastOnce,
+            false,
+        )
+    }
+
+    fn is_active(&self) -> bool {
+        self.failover
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .is_active()
+    }
+
+    /// Publish the NBIRTH, with the current state of all the twins
+    async fn birth(&mut self, client: &AsyncClient, group: &str) {
+        self.states = None;
+        if !self.is_active() {
+            return;
+        }
+        if let Some(reports) = self.list_twins().await {
+            self.publish_birth(client, group, reports).await;
+        }
+    }
+
+    /// Reports on all the twins, sorted by asset ID
+    async fn list_twins(&self) -> Option<Vec<TwinReport>> {
+        let (reply, response) = oneshot::channel();
+        self.manager_ch
+            .send(ManagerMessage::Query(Query::ListTwins(reply)))
+            .await
+            .ok()?;
+        response.await.ok()
+    }
+
+    async fn publish_birth(&mut self, client: &AsyncClient, group: &str, reports: Vec<TwinReport>) {
+        let now = now_millis();
+        let mut metrics = vec![
+            Metric::new(BD_SEQ, now, MetricValue::UInt64(self.bd_seq)),
+            Metric::new(REBIRTH, now, MetricValue::Boolean(false)),
+        ];
+        // The reports are sorted by asset ID
+        for report in &reports {
+            metrics.push(Metric::new(
+                format!("{}/State", report.asset_id),
+                now,
+                MetricValue::String(report.state.clone()),
+            ));
+            if let Some(location) = report.location {
+                metrics.push(Metric::new(
+                    format!("{}/Latitude", report.asset_id),
+                    now,
+                    MetricValue::Double(location.latitude),
+                ));
+                metrics.push(Metric::new(
+                    format!("{}/Longitude", report.asset_id),
+                    now,
+                    MetricValue::Double(location.longitude),
+                ));
+            }
+        }
+        self.seq = 0;
+        let payload = Payload {
+            timestamp: now,
+            metrics,
+            seq: Some(self.seq),
+        };
+        info!("Publishing the Sparkplug NBIRTH of {} twins", reports.len());
+        if let Err(e) = client
+            .publish(
+                self.topic(group, "NBIRTH"),
+                QoS::AtMostOnce,
+                false,
+                payload.encode(),
+            )
+            .await
+        {
+            error!("Failed to publish the Sparkplug NBIRTH: {e:?}");
+            return;
+        }
+        self.states = Some(
+            reports
+                .into_iter()
+                .map(|report| (report.asset_id, report.state))
+                .collect(),
+        );
+    }
+
+    /// Publish a transition as NDATA, or a new NBIRTH for a twin started after the last one
+    async fn publish_transition(&mut self, client: &AsyncClient, group: &str, transition: TwinTransition) {
+        let known = self
+            .states
+            .as_ref()
+            .is_some_and(|states| states.contains_key(&transition.asset_id));
+        if !known {
+            self.birth(client, group).await;
+            return;
+        }
+        if !self.is_active() {
+            self.states = None;
+            return;
+        }
+        self.seq = (self.seq + 1) % 256;
+        let timestamp = transition.timestamp.timestamp_millis().max(0) as u64;
+        let payload = Payload {
+            timestamp: now_millis(),
+            metrics: vec![Metric::new(
+                format!("{}/State", transition.asset_id),
+                timestamp,
+                MetricValue::String(transition.to.clone()),
+            )],
+            seq: Some(self.seq),
+        };
+        if let Err(e) = client
+            .publish(
+                self.topic(group, "NDATA"),
+                QoS::AtMostOnce,
+                false,
+                payload.encode(),
+            )
+            .await
+        {
+            error!("Failed to publish the Sparkplug NDATA: {e:?}");
+        }
+        if let Some(states) = &mut self.states {
+            states.insert(transition.asset_id, transition.to);
+        }
+    }
+
+    /// Publish a new NBIRTH if twins were started or removed since the last one, as the
+    /// metrics of a node can only change with a birth
+    async fn check_twins(&mut self, client: &AsyncClient, group: &str) {
+        let Some(states) = &self.states else {
+            return;
+        };
+        let Some(reports) = self.list_twins().await else {
+            return;
+        };
+        let changed = reports.len() != states.len()
+            || reports
+                .iter()
+                .any(|report| !states.contains_key(&report.asset_id));
+        if changed && self.is_active() {
+            self.publish_birth(client, group, reports).await;
+        }
+    }
+
+    /// Whether a node command requests a rebirth
+    fn is_rebirth(payload: &[u8]) -> bool {
+        match Payload::decode(payload) {
+            Ok(payload) => payload
+                .metrics
+                .iter()
+                .any(|m| m.name == REBIRTH && m.value == MetricValue::Boolean(true)),
+            Err(e) => {
+                warn!("Invalid Sparkplug node command, ignored: {e}");
+                false
+            }
+        }
+    }
+
+    pub async fn body(&mut self) {
+        let Some(group) = self.options.sparkplug_group.clone() else {
+            return;
+        };
+        info!(
+            "Sparkplug publisher body starting as edge node {group}/{}",
+            self.options.sparkplug_node
+        );
+        let mut bus = self.bus.subscribe();
+        let mut mqtt_options = self.mqtt_options.clone();
+        mqtt_options.set_last_will(self.death_will(&group));
+        let (client, mut connection) = AsyncClient::new(mqtt_options, 10);
+        let command_topic = self.topic(&group, "NCMD");
+        let mut twins_check = tokio::time::interval(TWINS_CHECK_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = twins_check.tick() => {
+                    self.check_twins(&client, &group).await;
+                }
+                event = connection.poll() => match event {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        if let Err(e) = client.subscribe(&command_topic, QoS::AtLeastOnce).await {
+                            error!("Failed to subscribe to {command_topic}: {e:?}");
+                        }
+                        self.birth(&client, &group).await;
+                    }
+                    Ok(Event::Incoming(Packet::Publish(publish))) => {
+                        if publish.topic == command_topic && Self::is_rebirth(&publish.payload) {
+                            info!("Sparkplug rebirth requested");
+                            self.birth(&client, &group).await;
+                        }
+                    }
+                    Ok(event) => trace!("Sparkplug MQTT event: {event:?}"),
+                    Err(e) => {
+                        error!("Sparkplug MQTT connection error: {e:?}");
+                        // The next connection has its own birth and death
+                        self.states = None;
+                        self.bd_seq = (self.bd_seq + 1) % 256;
+                        connection.mqtt_options.set_last_will(self.death_will(&group));
+                        tokio::time::sleep(RECONNECT_DELAY).await;
+                    }
+                },
+                event = bus.recv() => match event {
+                    Ok(BusEvent::Transition(transition)) => {
+                        self.publish_transition(&client, &group, transition).await
+                    }
+                    Ok(_) => {}
+                    Err(RecvError::Lagged(skipped)) => {
+                        debug!("Sparkplug publisher missed {skipped} events, rebirth");
+                        self.birth(&client, &group).await;
+                    }
+                    Err(RecvError::Closed) => return,
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payload() {
+        let payload = Payload {
+            timestamp: 1,
+            metrics: vec![Metric::new("bdSeq", 2, MetricValue::UInt64(3))],
+            seq: Some(0),
+        };
+        // Checked against the Sparkplug B protobuf definition
+        assert_eq!(
+            payload.encode(),
+            [
+                0x08, 0x01, 0x12, 0x0d, 0x0a, 0x05, b'b', b'd', b'S', b'e', b'q', 0x18, 0x02, 0x20, 0x08,
+                0x58, 0x03, 0x18, 0x00
+            ]
+        );
+
+        let payload = Payload {
+            timestamp: 1_760_000_000_000,
+            metrics: vec![
+                Metric::new(REBIRTH, 1_760_000_000_000, MetricValue::Boolean(true)),
+                Metric::new(
+                    "urn:twin:1/State",
+                    1_760_000_000_001,
+                    MetricValue::String("Idle".into()),
+                ),
+                Metric::new(BD_SEQ, 1_760_000_000_002, MetricValue::UInt64(300)),
+                Metric::new(
+                    "urn:twin:1/Latitude",
+                    1_760_000_000_003,
+                    MetricValue::Double(45.4642),
+                ),
+            ],
+            seq: Some(255),
+        };
+        assert_eq!(Payload::decode(&payload.encode()).unwrap(), payload);
+        assert!(SparkplugPublisher::is_rebirth(&payload.encode()));
+
+        // A double metric, as encoded by other Sparkplug clients; a float metric is skipped,
+        // a truncated payload is rejected
+        let mut double = vec![0x12, 0x0e];
+        double.extend([0x0a, 0x01, b'x', 0x20, 0x0a, 0x69]);
+        double.extend(1.5f64.to_le_bytes());
+        assert_eq!(
+            Payload::decode(&double).unwrap().metrics,
+            [Metric::new("x", 0, MetricValue::Double(1.5))]
+        );
+        let mut float = vec![0x12, 0x0a];
+        float.extend([0x0a, 0x01, b'x', 0x20, 0x09, 0x65]);
+        float.extend(1.5f32.to_le_bytes());
+        assert_eq!(Payload::decode(&float).unwrap().metrics, []);
+        assert!(Payload::decode(&payload.encode()[..10]).is_err());
+    }
+}
@@ -0,0 +1,182 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::twin_runner::TwinRunner;
+use digitaltwin_core::{AssetAdministrationShell, AssetID, SlotValue};
+
+/// A value received on a slot, after the smoothing filter, kept to be replayed into a
+/// staged update of the twin
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedInput {
+    pub slot: String,
+    pub time: DateTime<Utc>,
+    pub value: SlotValue,
+}
+
+/// What a staged update needs from the running twin, taken at the same time
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StagingSource {
+    pub state: String,
+    pub snapshot: serde_json::Value,
+    /// Recent inputs, oldest first
+    pub inputs: Vec<RecordedInput>,
+}
+
+/// How the state of a staged update was decided
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StagingOutcome {
+    /// The replayed inputs brought the staged twin to the state of the running one
+    Matched,
+    /// The states diverged, and the staged twin took the snapshot of the running one, as its
+    /// type and parameters did not change
+    Carried,
+    /// The states diverged, and the staged twin kept the state of the replayed inputs, as its
+    /// type or its parameters changed
+    Replayed,
+}
+
+/// A staged update of a twin definition, compared with the running twin before switching over
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StagedUpdate {
+    pub asset_id: AssetID,
+    pub timestamp: DateTime<Utc>,
+    /// Number of inputs replayed into the staged twin
+    pub replayed: usize,
+    pub running_state: String,
+    /// State of the staged twin after the replay
+    pub staged_state: String,
+    pub outcome: StagingOutcome,
+}
+
+/// A staged twin ready to replace the running one
+pub struct StagedTwin {
+    pub aas: AssetAdministrationShell,
+    pub twin: TwinRunner,
+    pub update: StagedUpdate,
+}
+
+/// Bring a staged twin, not started yet, up to date with the running one: replay the recent
+/// inputs and compare the states. On divergence, the snapshot of the running twin is only
+/// taken if it still applies, with the same type and parameters.
+pub fn stage(twin: &mut TwinRunner, source: StagingSource, same_parameters: bool) -> StagedUpdate {
+    twin.replay(&source.inputs);
+    let staged_state = twin.state();
+    let outcome = if staged_state == source.state {
+        StagingOutcome::Matched
+    } else if same_parameters && twin.restore(source.snapshot).is_ok() {
+        StagingOutcome::Carried
+    } else {
+        StagingOutcome::Replayed
+    };
+    StagedUpdate {
+        asset_id: twin.id(),
+        timestamp: Utc::now(),
+        replayed: source.inputs.len(),
+        running_state: source.state,
+        staged_state,
+        outcome,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::AuditLog;
+    use crate::event_bus::EventBus;
+    use crate::latency_budget::{LatencyBudgetOptions, SharedHandlerMetrics};
+    use crate::manager::TwinDefaults;
+    use crate::predictor::PredictorOptions;
+    use crate::twin_runner::TwinServices;
+    use clap::Parser;
+    use tokio::sync::mpsc;
+
+    fn light_bulb(threshold: f64) -> TwinRunner {
+        let aas = format!(
+            r#"
+id: "urn:test:light"
+id_short: "Light"
+submodels:
+  - id: "urn:test:light:configuration"
+    id_short: "TwinConfiguration"
+    elements:
+      - element_type: "property"
+        id_short: "TwinType"
+        value_type: "string"
+        value: "LightBulb"
+      - element_type: "collection"
+        id_short: "Parameters"
+        value:
+          - element_type: "property"
+            id_short: "threshold"
+            value_type: "float"
+            value: {threshold}
+"#
+        );
+        let aas = AssetAdministrationShell::from_reader(aas.as_bytes()).unwrap();
+        let services = TwinServices {
+            audit_log: AuditLog::default(),
+            historian: None,
+            history: None,
+            sessions: None,
+            predictors: PredictorOptions::parse_from(["test"]),
+            recent_inputs: 10,
+            actuators: None,
+            bus: EventBus::default(),
+            latency_budget: LatencyBudgetOptions::parse_from(["test"]),
+            handler_metrics: SharedHandlerMetrics::default(),
+            hibernate_after: None,
+        };
+        let (manager_ch, _) = mpsc::channel(1);
+        let (network_ch, _) = mpsc::channel(1);
+        TwinRunner::new(aas, &TwinDefaults::default(), manager_ch, network_ch, services).unwrap()
+    }
+
+    fn source(state: &str, snapshot: serde_json::Value, powers: &[f64]) -> StagingSource {
+        let start: DateTime<Utc> = "2025-03-01T10:00:00Z".parse().unwrap();
+        StagingSource {
+            state: state.to_string(),
+            snapshot,
+            inputs: powers
+                .iter()
+                .enumerate()
+                .map(|(i, power)| RecordedInput {
+                    slot: "CurrentPowerDraw".to_string(),
+                    time: start + chrono::TimeDelta::seconds(i as i64),
+                    value: SlotValue::Number(*power),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_stage() {
+        let mut running = light_bulb(0.5);
+        running.replay(&source("Off", serde_json::Value::Null, &[0.7]).inputs);
+        assert_eq!(running.state(), "On");
+        let snapshot = running.snapshot().unwrap();
+
+        let mut staged = light_bulb(0.5);
+        let update = stage(&mut staged, source("On", snapshot.clone(), &[0.3, 0.7]), true);
+        assert_eq!(update.outcome, StagingOutcome::Matched);
+        assert_eq!(update.replayed, 2);
+
+        // Switched on by a command, which is not replayed: the state is carried over
+        let mut staged = light_bulb(0.5);
+        let update = stage(&mut staged, source("On", snapshot.clone(), &[0.3]), true);
+        assert_eq!(
+            (update.staged_state.as_str(), update.outcome),
+            ("Off", StagingOutcome::Carried)
+        );
+        assert_eq!(staged.state(), "On");
+
+        // A new threshold gives another meaning to the same inputs
+        let mut staged = light_bulb(0.8);
+        let update = stage(&mut staged, source("On", snapshot, &[0.7]), false);
+        assert_eq!(
+            (update.staged_state.as_str(), update.outcome),
+            ("Off", StagingOutcome::Replayed)
+        );
+        assert_eq!(staged.state(), "Off");
+    }
+}
@@ -0,0 +1,104 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use digitaltwin_core::AssetID;
+
+/// Parse a dependency given on the command line, as "<asset id>=<asset id>,<asset id>,..."
+pub fn parse_dependency(s: &str) -> Result<(AssetID, Vec<AssetID>), String> {
+    let (twin, dependencies) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected <asset id>=<asset id>,...: {s}"))?;
+    let dependencies = dependencies
+        .split(',')
+        .map(str::trim)
+        .filter(|d| !d.is_empty())
+        .map(AssetID::from)
+        .collect();
+    Ok((AssetID::from(twin.trim()), dependencies))
+}
+
+/// Order in which the twins start
+#[derive(Debug, Default, PartialEq)]
+pub struct StartupPlan {
+    /// The twins each after its dependencies, in the order of the definitions otherwise
+    pub order: Vec<AssetID>,
+    /// Twins in a dependency cycle, or depending on one, that cannot start
+    pub cyclic: Vec<AssetID>,
+}
+
+/// Sort the twins so that each one comes after its dependencies (Kahn's algorithm). Only the
+/// dependencies between the given twins are considered.
+pub fn plan(twins: &[(AssetID, Vec<AssetID>)]) -> StartupPlan {
+    let known: BTreeSet<&AssetID> = twins.iter().map(|(id, _)| id).collect();
+    let mut pending: BTreeMap<&AssetID, BTreeSet<&AssetID>> = twins
+        .iter()
+        .map(|(id, dependencies)| {
+            let dependencies = dependencies
+                .iter()
+                .filter(|dependency| *dependency != id && known.contains(dependency))
+                .collect();
+            (id, dependencies)
+        })
+        .collect();
+    let mut plan = StartupPlan::default();
+    loop {
+        // The first twins of the definitions whose dependencies are all ordered
+        let ready: Vec<&AssetID> = twins
+            .iter()
+            .map(|(id, _)| id)
+            .filter(|id| pending.get(id).is_some_and(BTreeSet::is_empty))
+            .collect();
+        if ready.is_empty() {
+            break;
+        }
+        for id in ready {
+            pending.remove(id);
+            for dependencies in pending.values_mut() {
+                dependencies.remove(id);
+            }
+            plan.order.push(id.clone());
+        }
+    }
+    plan.cyclic = twins
+        .iter()
+        .map(|(id, _)| id)
+        .filter(|id| pending.contains_key(id))
+        .cloned()
+        .collect();
+    plan
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn twin(id: &str, dependencies: &[&str]) -> (AssetID, Vec<AssetID>) {
+        (
+            AssetID::from(id),
+            dependencies.iter().copied().map(AssetID::from).collect(),
+        )
+    }
+
+    #[test]
+    fn test_plan() {
+        let plan = plan(&[
+            twin("car-park", &["charger-1", "charger-2"]),
+            twin("charger-1", &["meter"]),
+            twin("charger-2", &["unknown"]),
+            twin("meter", &["meter"]),
+            twin("loop-a", &["loop-b"]),
+            twin("loop-b", &["loop-a"]),
+            twin("after-loop", &["loop-a", "meter"]),
+        ]);
+        assert_eq!(plan.order, ["charger-2", "meter", "charger-1", "car-park"]);
+        assert_eq!(plan.cyclic, ["loop-a", "loop-b", "after-loop"]);
+
+        assert_eq!(
+            parse_dependency("urn:aas:example:car-park = urn:aas:example:c1, urn:aas:example:c2,").unwrap(),
+            twin(
+                "urn:aas:example:car-park",
+                &["urn:aas:example:c1", "urn:aas:example:c2"]
+            )
+        );
+        assert!(parse_dependency("urn:aas:example:car-park").is_err());
+    }
+}
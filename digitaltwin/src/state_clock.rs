@@ -0,0 +1,137 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Time spent by a twin, or one of its regions, in its current state
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateDwell {
+    pub state: String,
+    pub entered_at: DateTime<Utc>,
+    pub dwell_ms: u64,
+}
+
+/// Time each region of a twin entered its current state. A twin with regions has a composite
+/// state, as "Fault|Online": the twin entered it when the last region changed state.
+#[derive(Debug, Clone)]
+pub struct StateClock {
+    regions: Vec<(String, DateTime<Utc>)>,
+}
+
+fn elapsed(since: DateTime<Utc>, now: DateTime<Utc>) -> Duration {
+    (now - since).to_std().unwrap_or_default()
+}
+
+impl StateClock {
+    pub fn new(state: &str, now: DateTime<Utc>) -> Self {
+        StateClock {
+            regions: state.split('|').map(|state| (state.to_string(), now)).collect(),
+        }
+    }
+
+    /// Follow a change of the state, restarting the clock of the regions entering another
+    /// state. The time of the transition is kept if the state did not change.
+    pub fn transition(&mut self, state: &str, now: DateTime<Utc>) {
+        let states: Vec<&str> = state.split('|').collect();
+        if states.len() != self.regions.len() {
+            *self = StateClock::new(state, now);
+            return;
+        }
+        for ((region, entered), state) in self.regions.iter_mut().zip(states) {
+            if region != state {
+                *region = state.to_string();
+                *entered = now;
+            }
+        }
+    }
+
+    /// Time the twin entered its current state
+    pub fn entered_at(&self) -> DateTime<Utc> {
+        self.regions
+            .iter()
+            .map(|(_, entered)| *entered)
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// Times the regions entered their current states, kept in the snapshot of the twin
+    pub fn entered(&self) -> Vec<DateTime<Utc>> {
+        self.regions.iter().map(|(_, entered)| *entered).collect()
+    }
+
+    /// Restore the times the regions entered their states, from the snapshot of a twin in
+    /// the same state. Ignored if they don't match the regions.
+    pub fn restore(&mut self, entered: Vec<DateTime<Utc>>) {
+        if entered.len() == self.regions.len() {
+            for ((_, entered), restored) in self.regions.iter_mut().zip(entered) {
+                *entered = restored;
+            }
+        }
+    }
+
+    /// Time spent in a state, the composite state of the twin or the state of one of its
+    /// regions, None if not in that state
+    pub fn dwell_in(&self, state: &str, now: DateTime<Utc>) -> Option<Duration> {
+        if self.state() == state {
+            return Some(elapsed(self.entered_at(), now));
+        }
+        self.regions
+            .iter()
+            .find(|(region, _)| region == state)
+            .map(|(_, entered)| elapsed(*entered, now))
+    }
+
+    /// Time spent by each region in its current state
+    pub fn dwell(&self, now: DateTime<Utc>) -> Vec<StateDwell> {
+        self.regions
+            .iter()
+            .map(|(state, entered)| StateDwell {
+                state: state.clone(),
+                entered_at: *entered,
+                dwell_ms: elapsed(*entered, now).as_millis() as u64,
+            })
+            .collect()
+    }
+
+    fn state(&self) -> String {
+        self.regions
+            .iter()
+            .map(|(state, _)| state.as_str())
+            .collect::<Vec<_>>()
+            .join("|")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeDelta;
+
+    #[test]
+    fn test_state_clock() {
+        let start = Utc::now();
+        let at = |s| start + TimeDelta::seconds(s);
+        let mut clock = StateClock::new("Idle|Offline", start);
+        clock.transition("Idle|Online", at(10));
+        clock.transition("Idle|Online", at(20));
+        assert_eq!(clock.entered_at(), at(10));
+        assert_eq!(clock.dwell_in("Idle", at(60)), Some(Duration::from_secs(60)));
+        assert_eq!(
+            clock.dwell_in("Idle|Online", at(60)),
+            Some(Duration::from_secs(50))
+        );
+        assert_eq!(clock.dwell_in("Fault", at(60)), None);
+
+        clock.transition("Fault|Online", at(100));
+        let dwell = clock.dwell(at(160));
+        assert_eq!(dwell[0].state, "Fault");
+        assert_eq!(dwell[0].dwell_ms, 60_000);
+        assert_eq!(dwell[1].dwell_ms, 150_000);
+
+        // Restored from the snapshot of the twin
+        let mut restored = StateClock::new("Fault|Online", at(200));
+        restored.restore(clock.entered());
+        assert_eq!(restored.dwell_in("Fault", at(160)), Some(Duration::from_secs(60)));
+        restored.restore(vec![at(0)]);
+        assert_eq!(restored.entered_at(), at(100));
+    }
+}
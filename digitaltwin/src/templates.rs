@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Marker of a template definition file, e.g. "station.template.yaml"
+const TEMPLATE_MARKER: &str = ".template";
+/// Suffix of the file listing the instances of a template, e.g. "station.instances.yaml"
+const INSTANCES_SUFFIX: &str = ".instances.yaml";
+
+/// The parameters of one instance of a template
+pub type Parameters = HashMap<String, String>;
+
+/// Whether a definition file is a template, to be expanded once per instance
+pub fn is_template(path: &Path) -> bool {
+    path.file_stem()
+        .and_then(|stem| stem.to_str())
+        .is_some_and(|stem| stem.ends_with(TEMPLATE_MARKER))
+}
+
+/// Whether a file lists the instances of a template
+pub fn is_instances(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.ends_with(INSTANCES_SUFFIX))
+}
+
+/// The instances file of a template: "station.template.json" is paired with "station.instances.yaml"
+pub fn instances_path(template: &Path) -> PathBuf {
+    let stem = template
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or_default();
+    let name = stem.strip_suffix(TEMPLATE_MARKER).unwrap_or(stem);
+    template.with_file_name(format!("{name}{INSTANCES_SUFFIX}"))
+}
+
+/// Parse an instances file: a YAML list of parameter sets, whose values are scalars
+pub fn parse_instances(text: &str) -> Result<Vec<Parameters>, String> {
+    let instances: Vec<HashMap<String, serde_yaml::Value>> =
+        serde_yaml::from_str(text).map_err(|e| format!("Failed to parse instances: {e}"))?;
+    instances
+        .into_iter()
+        .enumerate()
+        .map(|(i, params)| {
+            params
+                .into_iter()
+                .map(|(name, value)| match value {
+                    serde_yaml::Value::String(s) => Ok((name, s)),
+                    serde_yaml::Value::Number(n) => Ok((name, n.to_string())),
+                    serde_yaml::Value::Bool(b) => Ok((name, b.to_string())),
+                    _ => Err(format!("instance {}: parameter {name} is not a scalar", i + 1)),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Replace the `{{name}}` placeholders of a template with the parameters of an instance.
+/// A placeholder without a parameter is an error, while unused parameters are ignored.
+pub fn substitute(template: &str, params: &Parameters) -> Result<String, String> {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after.find("}}").ok_or("unterminated placeholder")?;
+        let name = after[..end].trim();
+        let value = params
+            .get(name)
+            .ok_or_else(|| format!("missing parameter {name}"))?;
+        result.push_str(value);
+        rest = &after[end + 2..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Expand a template into the text of each of its instances
+pub fn expand(template: &str, instances: &[Parameters]) -> Result<Vec<String>, String> {
+    instances
+        .iter()
+        .enumerate()
+        .map(|(i, params)| substitute(template, params).map_err(|e| format!("instance {}: {e}", i + 1)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_template_paths() {
+        let template = Path::new("./twins/station.template.yaml");
+        assert!(is_template(template));
+        assert!(!is_template(Path::new("./twins/charger.yaml")));
+        assert!(is_instances(Path::new("./twins/station.instances.yaml")));
+        assert!(!is_instances(template));
+        assert_eq!(
+            instances_path(template),
+            Path::new("./twins/station.instances.yaml")
+        );
+        assert_eq!(
+            instances_path(Path::new("./twins/station.template.json")),
+            Path::new("./twins/station.instances.yaml")
+        );
+    }
+
+    #[test]
+    fn test_expand() {
+        let instances = parse_instances(
+            "- station_id: 1\n  sensor_prefix: urn:garage:a\n- station_id: 2\n  sensor_prefix: urn:garage:b\n",
+        )
+        .unwrap();
+        let template = "id: station-{{station_id}}\nsensor: {{ sensor_prefix }}:power\n";
+        assert_eq!(
+            expand(template, &instances).unwrap(),
+            [
+                "id: station-1\nsensor: urn:garage:a:power\n",
+                "id: station-2\nsensor: urn:garage:b:power\n"
+            ]
+        );
+
+        assert_eq!(
+            expand("id: {{station}}", &instances).unwrap_err(),
+            "instance 1: missing parameter station"
+        );
+        assert_eq!(
+            substitute("id: {{station_id", &instances[0]).unwrap_err(),
+            "unterminated placeholder"
+        );
+        assert!(parse_instances("- station_id: [1, 2]\n").is_err());
+    }
+}
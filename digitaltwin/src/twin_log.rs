@@ -0,0 +1,231 @@
+use chrono::{DateTime, Utc};
+use log::{LevelFilter, Log, Metadata, Record};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::sync::{Mutex, OnceLock};
+
+use digitaltwin_core::AssetID;
+
+/// Number of recent log lines kept for each twin
+pub const CAPTURED_LINES: usize = 200;
+
+tokio::task_local! {
+    /// The twin whose runner task is logging
+    static CURRENT_TWIN: AssetID;
+}
+
+/// Run a twin runner task, attributing its log records to the twin
+pub fn scope<F: Future>(id: AssetID, task: F) -> impl Future<Output = F::Output> {
+    CURRENT_TWIN.scope(id, task)
+}
+
+/// A log line captured for a twin
+#[derive(Debug, Clone, Serialize)]
+pub struct LogLine {
+    pub timestamp: DateTime<Utc>,
+    pub level: &'static str,
+    pub target: String,
+    pub message: String,
+}
+
+/// Verbosity and recent log lines of a twin
+#[derive(Debug, Clone, Serialize)]
+pub struct TwinLogReport {
+    pub asset_id: AssetID,
+    /// Verbosity set for the twin; if none, the global one applies
+    pub level: Option<String>,
+    /// Recent lines, oldest first
+    pub lines: Vec<LogLine>,
+}
+
+#[derive(Debug, Default)]
+struct TwinLog {
+    level: Option<LevelFilter>,
+    lines: VecDeque<LogLine>,
+}
+
+/// Per-twin verbosity and capture buffers
+#[derive(Debug, Default)]
+struct TwinLogs {
+    twins: HashMap<AssetID, TwinLog>,
+}
+
+impl TwinLogs {
+    fn level(&self, id: &AssetID) -> Option<LevelFilter> {
+        self.twins.get(id).and_then(|log| log.level)
+    }
+
+    /// The highest verbosity set for any twin
+    fn max_level(&self) -> LevelFilter {
+        self.twins
+            .values()
+            .filter_map(|log| log.level)
+            .max()
+            .unwrap_or(LevelFilter::Off)
+    }
+
+    fn set_level(&mut self, id: &AssetID, level: Option<LevelFilter>) {
+        self.twins.entry(id.clone()).or_default().level = level;
+    }
+
+    fn capture(&mut self, id: &AssetID, line: LogLine) {
+        let lines = &mut self.twins.entry(id.clone()).or_default().lines;
+        if lines.len() == CAPTURED_LINES {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+
+    fn report(&self, id: &AssetID) -> TwinLogReport {
+        let log = self.twins.get(id);
+        TwinLogReport {
+            asset_id: id.clone(),
+            level: log
+                .and_then(|log| log.level)
+                .map(|level| level.as_str().to_lowercase()),
+            lines: log
+                .map(|log| log.lines.iter().cloned().collect())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Logger wrapping env_logger: the records of a twin runner task follow the verbosity
+/// set for the twin, if any, and are captured in its buffer
+struct TwinLogger {
+    inner: env_logger::Logger,
+    twins: Mutex<TwinLogs>,
+}
+
+impl TwinLogger {
+    fn twins(&self) -> std::sync::MutexGuard<'_, TwinLogs> {
+        self.twins.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Let through the records of the most verbose twin
+    fn update_max_level(&self) {
+        log::set_max_level(self.inner.filter().max(self.twins().max_level()));
+    }
+}
+
+impl Log for TwinLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        match CURRENT_TWIN.try_with(|id| self.twins().level(id)) {
+            Ok(Some(level)) => metadata.level() <= level,
+            _ => self.inner.enabled(metadata),
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        let Ok(id) = CURRENT_TWIN.try_with(AssetID::clone) else {
+            self.inner.log(record);
+            return;
+        };
+        let mut twins = self.twins();
+        let enabled = match twins.level(&id) {
+            Some(level) => record.level() <= level,
+            None => self.inner.matches(record),
+        };
+        if !enabled {
+            return;
+        }
+        let line = LogLine {
+            timestamp: Utc::now(),
+            level: record.level().as_str(),
+            target: record.target().to_string(),
+            message: record.args().to_string(),
+        };
+        twins.capture(&id, line.clone());
+        drop(twins);
+        if self.inner.matches(record) {
+            self.inner.log(record);
+        } else {
+            // More verbose than the global filter: env_logger would drop it
+            eprintln!(
+                "[{} {} {}] {}",
+                line.timestamp.format("%Y-%m-%dT%H:%M:%SZ"),
+                line.level,
+                line.target,
+                line.message
+            );
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+static LOGGER: OnceLock<TwinLogger> = OnceLock::new();
+
+/// Install the logger, configured from RUST_LOG as env_logger
+pub fn init() {
+    let logger = LOGGER.get_or_init(|| TwinLogger {
+        inner: env_logger::Builder::from_default_env().build(),
+        twins: Mutex::new(TwinLogs::default()),
+    });
+    if log::set_logger(logger).is_ok() {
+        logger.update_max_level();
+    }
+}
+
+/// Set the verbosity of a twin, or go back to the global one
+pub fn set_level(id: &AssetID, level: Option<LevelFilter>) {
+    if let Some(logger) = LOGGER.get() {
+        logger.twins().set_level(id, level);
+        logger.update_max_level();
+    }
+}
+
+/// The verbosity and the recent log lines of a twin
+pub fn report(id: &AssetID) -> TwinLogReport {
+    match LOGGER.get() {
+        Some(logger) => logger.twins().report(id),
+        None => TwinLogs::default().report(id),
+    }
+}
+
+/// Drop the settings and the lines of a twin that was removed
+pub fn forget(id: &AssetID) {
+    if let Some(logger) = LOGGER.get() {
+        logger.twins().twins.remove(id);
+        logger.update_max_level();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(message: &str) -> LogLine {
+        LogLine {
+            timestamp: Utc::now(),
+            level: "INFO",
+            target: "test".to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_twin_logs() {
+        let (a, b) = (AssetID::from("urn:twin:a"), AssetID::from("urn:twin:b"));
+        let mut logs = TwinLogs::default();
+        for i in 0..CAPTURED_LINES + 5 {
+            logs.capture(&a, line(&i.to_string()));
+        }
+        let report = logs.report(&a);
+        assert_eq!(report.lines.len(), CAPTURED_LINES);
+        assert_eq!(report.lines[0].message, "5");
+        assert_eq!(report.level, None);
+        assert!(logs.report(&b).lines.is_empty());
+
+        assert_eq!(logs.max_level(), LevelFilter::Off);
+        logs.set_level(&a, Some(LevelFilter::Debug));
+        logs.set_level(&b, Some(LevelFilter::Trace));
+        assert_eq!(logs.max_level(), LevelFilter::Trace);
+        assert_eq!(logs.report(&a).level.as_deref(), Some("debug"));
+        logs.set_level(&b, None);
+        assert_eq!(logs.max_level(), LevelFilter::Debug);
+    }
+}
@@ -1,93 +1,742 @@
-use log::{debug, info, trace, warn};
-use std::collections::HashMap;
-use tokio::sync::mpsc;
+use chrono::{DateTime, Utc};
+use log::{debug, error, info, trace, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use thiserror::Error as ThisError;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, oneshot};
 
-use crate::manager::ManagerMessage;
-use crate::models::{ChargingStationFactory, LightBulbFactory};
-use crate::network_receiver::NetworkMessage;
-use digitaltwin_core::{ActorFactory, ActorStateType, AssetAdministrationShell, AssetID, DeviceID};
+use crate::actuation::{Actuators, BreakerStatus, BreakerTrip, ACTUATION_UNAVAILABLE};
+use crate::audit::{AuditLog, AuditRecord, TransitionRecord};
+use crate::command::{self, CommandEnvelope, CommandSource};
+use crate::command_guard::{CommandGuard, Verdict};
+use crate::conditions::{Action, Condition, Conditions};
+use crate::device_trie;
+use crate::diagram::{StateDiagram, RECENT_TRANSITIONS};
+use crate::event_bus::{BusEvent, EmittedEvent, EventBus, Lifecycle, LifecycleEvent, TwinError};
+use crate::flapping::{self, FlappingGuard, Violation, FLAPPING_EVENT};
+use crate::fleet_snapshot::{FrozenTwin, FREEZE_LIMIT};
+use crate::historian::Historian;
+use crate::history::SharedHistory;
+use crate::labels::Labels;
+use crate::latency_budget::{BudgetCheck, LatencyBudget, LatencyBudgetOptions, SharedHandlerMetrics};
+use crate::manager::{ManagerMessage, TwinDefaults};
+use crate::models;
+use crate::network_receiver::{Availability, NetworkMessage, TwinEvent};
+use crate::predictor::{self, Prediction, Predictor, PredictorOptions, MAINTENANCE_RISK_EVENT};
+use crate::sessions::SharedSessions;
+use crate::staging::{RecordedInput, StagingSource};
+use crate::state_clock::{StateClock, StateDwell};
+use digitaltwin_core::{
+    ActorEvent, ActorStateType, AssetAdministrationShell, AssetID, CachedValue, CorrelationID, DeviceID,
+    FilterKind, GeoLocation, IndexedShell, SensorAnnouncement, SlotCache, SlotFilter, SlotValue,
+};
+
+#[derive(ThisError, Debug)]
+pub enum Error {
+    /// The AAS declares no twin type, and none can be guessed from the asset ID
+    #[error("no twin type found for {0}")]
+    MissingTwinType(AssetID),
+    /// No actor factory registered with this name
+    #[error("unknown twin type: {0}")]
+    UnknownTwinType(String),
+}
+
+/// Interval between two heartbeats sent to the manager
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Time without updates after which a bound slot is reported as stale
+pub const SLOT_STALE_AFTER: Duration = Duration::from_secs(600);
 
 /// Actor message types
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub enum ActorMessage {
-    /// Change the value of an input slot
-    InputChange(DeviceID, f32),
+    /// Change the value of an input slot, with the correlation ID of the message
+    InputChange(DeviceID, SlotValue, CorrelationID),
     /// Execute a command
-    Command(String, serde_json::Value),
+    Command(CommandEnvelope),
+    /// Execute a command and reply with its outcome (AAS operation invocation)
+    Invoke(CommandEnvelope, oneshot::Sender<CommandOutcome>),
+    /// Reply with what a command would do, without executing it (dry run)
+    Evaluate(CommandEnvelope, oneshot::Sender<CommandEvaluation>),
+    /// Request a status report
+    Report(oneshot::Sender<TwinReport>),
+    /// Request the commands and slots handled in the current state
+    Actions(oneshot::Sender<AvailableActions>),
+    /// Request the diagram of the state machines, with the recent transitions
+    Diagram(oneshot::Sender<StateDiagram>),
+    /// Request a snapshot of the actor
+    Snapshot(oneshot::Sender<serde_json::Value>),
+    /// Reply with the report and a snapshot, then process nothing until released (fleet snapshot)
+    Freeze(oneshot::Sender<FrozenTwin>, oneshot::Receiver<()>),
+    /// Replace the actor with one restored from a snapshot, replying with its state
+    Restore(serde_json::Value, oneshot::Sender<Result<String, String>>),
+    /// Request the state, a snapshot and the recent inputs, to stage an update of the definition
+    StagingSource(oneshot::Sender<StagingSource>),
+    /// A sensor announced itself on the discovery topic
+    SensorDiscovered(SensorAnnouncement),
+    /// Bind a slot to another sensor, replying with the sensor previously bound
+    RebindSlot(String, DeviceID, oneshot::Sender<Result<DeviceID, String>>),
+    /// The actuation breaker of the twin opened
+    ActuationUnavailable(BreakerTrip),
+    /// Unsubscribe, hand the final snapshot to the manager and terminate
+    Stop,
+}
+
+/// Outcome of a command. The result holds the state of the actor and its
+/// properties after the command (e.g., {"state": "Charging", "max_current": 16.0}).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum CommandOutcome {
+    Executed(serde_json::Value),
+    /// Already executed with the same idempotency key
+    Duplicate(serde_json::Value),
+    /// Ignored, the command can be executed again after the given time
+    CoolingDown(Duration),
+    /// Rejected, the principal is not allowed to issue the command
+    Unauthorized,
+}
+
+impl CommandOutcome {
+    /// Name of the outcome in the audit log and in the group acks
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CommandOutcome::Executed(_) => "executed",
+            CommandOutcome::Duplicate(_) => "duplicate",
+            CommandOutcome::CoolingDown(_) => "cooling_down",
+            CommandOutcome::Unauthorized => "unauthorized",
+        }
+    }
+}
+
+/// What a command would do, evaluated without changing the twin (dry run)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandEvaluation {
+    pub command: String,
+    pub correlation_id: String,
+    /// Whether the command would be accepted: the principal is allowed, the arguments are
+    /// valid and the command is not cooling down
+    pub valid: bool,
+    /// Why the command would be rejected, or not executed
+    pub reason: Option<String>,
+    /// State of the twin now
+    pub from: String,
+    /// State of the twin after the command
+    pub to: String,
+    /// Events the command would emit
+    pub events: Vec<ActorEvent>,
+    /// The state and properties of the actor after the command
+    pub result: serde_json::Value,
+}
+
+impl CommandEvaluation {
+    /// A command rejected before reaching the twin
+    pub fn rejected(envelope: &CommandEnvelope, reason: impl Into<String>) -> Self {
+        CommandEvaluation {
+            command: envelope.command.clone(),
+            correlation_id: envelope.correlation_id.clone(),
+            valid: false,
+            reason: Some(reason.into()),
+            from: String::new(),
+            to: String::new(),
+            events: Vec::new(),
+            result: serde_json::Value::Null,
+        }
+    }
+}
+
+/// Commands and input slots handled by a twin in its current state, e.g. for a UI to
+/// enable only the operations that have an effect
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AvailableActions {
+    pub asset_id: AssetID,
+    pub state: String,
+    /// Commands the actor handles in this state, sorted
+    pub commands: Vec<String>,
+    /// Input slots the actor handles in this state, sorted
+    pub slots: Vec<String>,
+}
+
+/// Liveness signal periodically sent to the manager
+#[derive(Debug, Clone)]
+pub struct Heartbeat {
+    /// Current state of the actor
+    pub state: String,
+    /// Number of messages waiting in the twin's queue
+    pub queue_depth: usize,
+}
+
+/// Binding status of an input slot
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SlotBinding {
+    /// Bound to a sensor that sent updates recently
+    Bound,
+    /// No sensor found in the AAS, nor announced on the discovery topic
+    Unbound,
+    /// Bound to a sensor, but no update received for SLOT_STALE_AFTER
+    Stale,
+}
+
+/// Binding status of an input slot, as shown in the twin report
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SlotStatus {
+    pub status: SlotBinding,
+    /// Sensor bound to the slot (or device ID pattern)
+    pub sensor: Option<DeviceID>,
+    /// Time of the latest value received on the slot
+    pub last_update: Option<DateTime<Utc>>,
+    /// Why the slot is unbound (e.g., the AAS has no SensorID for its data source)
+    pub reason: Option<String>,
+}
+
+/// A state transition of a twin, streamed to the live dashboard
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TwinTransition {
+    pub asset_id: AssetID,
+    pub from: String,
+    pub to: String,
+    pub timestamp: DateTime<Utc>,
+    /// ID of the input that caused the transition
+    pub correlation_id: CorrelationID,
+}
+
+/// Services shared by the twin runners
+#[derive(Clone)]
+pub struct TwinServices {
+    /// Record of the commands received
+    pub audit_log: AuditLog,
+    /// Store of the past sensor values, replayed when a twin starts
+    pub historian: Option<Historian>,
+    /// History of the slot values, downsampled for the trend queries
+    pub history: Option<SharedHistory>,
+    /// Charging sessions, from the state transitions and the power samples
+    pub sessions: Option<SharedSessions>,
+    /// Predictors of the risk of failure, instantiated for each twin
+    pub predictors: PredictorOptions,
+    /// Recent inputs kept by each twin, replayed into the staged updates of its definition
+    pub recent_inputs: usize,
+    /// Backends delivering the events declared with an actuation to the devices
+    pub actuators: Option<Arc<Actuators>>,
+    /// Bus of the transitions, events, errors and lifecycle, to the publisher, webhooks and rules
+    pub bus: EventBus,
+    /// Default latency budget of the handlers
+    pub latency_budget: LatencyBudgetOptions,
+    /// Execution time of the handlers of the twins
+    pub handler_metrics: SharedHandlerMetrics,
+    /// Time without input after which a twin hibernates, if any
+    pub hibernate_after: Option<Duration>,
+}
+
+/// Status report of a twin
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TwinReport {
+    pub asset_id: AssetID,
+    pub actor_type: String,
+    pub state: String,
+    /// Bound sensors (sensor ID to slot name)
+    pub bound_sensors: HashMap<DeviceID, String>,
+    /// Slots without a sensor
+    pub unbound_slots: Vec<String>,
+    /// Binding status of every slot
+    pub slots: BTreeMap<String, SlotStatus>,
+    /// Units of measure of the slots, from the AAS concept descriptions
+    pub slot_units: HashMap<String, String>,
+    /// Smoothing filters applied to the slots, from the data sources
+    pub slot_filters: HashMap<String, String>,
+    /// Latest value received on each slot
+    pub slot_values: HashMap<String, SlotValue>,
+    /// Time of the last input change received
+    pub last_input: Option<DateTime<Utc>>,
+    /// Risk of failure estimated by each predictor
+    pub predictions: Vec<Prediction>,
+    /// Location of the asset, from its nameplate
+    pub location: Option<GeoLocation>,
+    /// Labels of the twin, from its AAS and the configuration
+    pub labels: Labels,
+    /// Runtime the twin is mirrored from, for a read-only proxy twin
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mirrored_from: Option<String>,
+    /// Circuit breaker of the actuation backends, once the twin actuated a device
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub actuation_breaker: Option<BreakerStatus>,
+    /// Safe state the twin is latched into after flapping, ignoring its inputs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latched_into: Option<String>,
+    /// Time the twin entered its current state
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state_entered_at: Option<DateTime<Utc>>,
+    /// Time spent by the twin, or by each of its regions, in its current state
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub state_dwell: Vec<StateDwell>,
 }
 
 pub struct TwinRunner {
-    /// The AAS for this Digital Twin
-    aas: AssetAdministrationShell,
+    /// The AAS for this Digital Twin, indexed for slot binding
+    aas: IndexedShell,
     /// The actor's internal state
     inner_state: Box<ActorStateType>,
-    /// All the slots the actor will listen to (used only during initialization)
-    slots: Vec<&'static str>,
+    /// Registered type of the actor, to restore it from a snapshot
+    twin_type: String,
+    /// All the slots the actor will listen to
+    slots: Vec<String>,
     /// Mapping of sensor IDs to slot names
     slot_map: HashMap<DeviceID, String>,
+    /// Slots with no sensor found in the AAS
+    unbound_slots: Vec<String>,
+    /// Why each unbound slot has no sensor
+    unbound_reasons: HashMap<String, String>,
+    /// Time each slot was bound to its sensor
+    bound_at: HashMap<String, DateTime<Utc>>,
+    /// Units of measure of the slots
+    slot_units: HashMap<String, String>,
+    /// Smoothing filters of the slots, applied before the values reach the actor
+    slot_filters: HashMap<String, SlotFilter>,
+    /// Latest value of each slot with its time, read by the handlers from the actor context,
+    /// the conditions and the reports
+    slot_cache: SlotCache,
+    /// Time of the last input change received
+    last_input: Option<DateTime<Utc>>,
+    /// Whether inputs were received since the state was last published
+    state_dirty: bool,
+    /// Recent inputs, oldest first, replayed into the staged updates of the definition
+    recent_inputs: VecDeque<RecordedInput>,
+    /// Recent transitions, oldest first, drawn on the diagram of the twin
+    recent_transitions: VecDeque<TwinTransition>,
+    /// Number of recent inputs kept
+    recent_limit: usize,
+    /// Whether the actor was brought to its current state before starting (restored from a
+    /// snapshot or staged), so that it is not backfilled from the historian
+    caught_up: bool,
+    /// Duplicate and cooldown filter for incoming commands
+    command_guard: CommandGuard,
+    /// Record of the commands received
+    audit_log: AuditLog,
+    /// Store of the past sensor values, replayed when the twin starts
+    historian: Option<Historian>,
+    /// History of the slot values, downsampled for the trend queries
+    history: Option<SharedHistory>,
+    /// Charging sessions, from the state transitions and the power samples
+    sessions: Option<SharedSessions>,
+    /// Predictors of the risk of failure, fed with the slot values and the transitions
+    predictors: Vec<Box<dyn Predictor>>,
+    /// Risk from which the twin emits a maintenance risk event
+    alert_risk: f64,
+    /// Whether the estimated risk is at the alert level, to emit the event once
+    at_risk: bool,
+    /// Backends delivering the events declared with an actuation to the devices
+    actuators: Option<Arc<Actuators>>,
+    /// Bus of the transitions, events, errors and lifecycle, to the publisher, webhooks and rules
+    bus: EventBus,
+    /// State last published on the bus
+    notified_state: String,
+    /// Time the current state of the twin, and of each of its regions, was entered
+    state_clock: StateClock,
+    /// Maximum execution time of the handlers, pausing the inputs of a slow twin
+    latency_budget: LatencyBudget,
+    /// Limits on the transitions, latching a flapping twin into its safe state
+    flapping: FlappingGuard,
+    /// Commands executed when the latest values of several slots meet a guard
+    conditions: Conditions,
+    /// Execution time of the handlers of the twins
+    handler_metrics: SharedHandlerMetrics,
+    /// Time without input after which the twin hibernates, if any
+    hibernate_after: Option<Duration>,
+    /// Time of the last input, command or change of the bindings
+    last_activity: Instant,
+    /// Whether the twin was revived from hibernation, still registered and subscribed
+    resumed: bool,
+    /// The message that revived the twin, handled before any other one
+    wakeup: Option<ActorMessage>,
     send_ch: mpsc::Sender<ActorMessage>,
     recv_ch: mpsc::Receiver<ActorMessage>,
     manager_ch: mpsc::Sender<ManagerMessage>,
     network_ch: mpsc::Sender<NetworkMessage>,
 }
 
+/// A twin with no input for a while, its actor dropped: only its snapshot, its bindings and
+/// the state of its guards are kept, along with its channel, so that the manager and the
+/// network receiver still reach it
+pub struct HibernatedTwin {
+    aas: AssetAdministrationShell,
+    snapshot: serde_json::Value,
+    /// Report, actions and diagram of the twin when it hibernated, answering the queries meanwhile
+    report: TwinReport,
+    actions: AvailableActions,
+    diagram: StateDiagram,
+    slot_map: HashMap<DeviceID, String>,
+    bound_at: HashMap<String, DateTime<Utc>>,
+    last_input: Option<DateTime<Utc>>,
+    recent_inputs: VecDeque<RecordedInput>,
+    recent_transitions: VecDeque<TwinTransition>,
+    command_guard: CommandGuard,
+    predictors: Vec<Box<dyn Predictor>>,
+    at_risk: bool,
+    latency_budget: LatencyBudget,
+    flapping: FlappingGuard,
+    last_activity: Instant,
+    send_ch: mpsc::Sender<ActorMessage>,
+    recv_ch: mpsc::Receiver<ActorMessage>,
+    manager_ch: mpsc::Sender<ManagerMessage>,
+    network_ch: mpsc::Sender<NetworkMessage>,
+}
+
+impl HibernatedTwin {
+    pub fn id(&self) -> AssetID {
+        self.aas.id.clone()
+    }
+}
+
 impl TwinRunner {
     pub fn new(
         aas: AssetAdministrationShell,
+        twin_defaults: &TwinDefaults,
         manager_ch: mpsc::Sender<ManagerMessage>,
         network_ch: mpsc::Sender<NetworkMessage>,
-    ) -> Self {
-        let object_type = aas.id.split(':').nth(3).unwrap(); // FIXME: unwrap
-        let (inner_state, slots) = match object_type {
-            "light" => LightBulbFactory::create_default(),
-            "ev" => LightBulbFactory::create_default(), // TODO: implement EV
-            "charging-station" => ChargingStationFactory::create_default(),
-            _ => panic!("Unknown object type: {}", object_type),
-        };
+        services: TwinServices,
+    ) -> Result<Self, Error> {
+        // The explicit twin type takes precedence over the object type in the asset ID
+        let twin_type = aas
+            .twin_type()
+            .or_else(|| {
+                aas.id
+                    .category()
+                    .and_then(models::type_from_urn_category)
+                    .map(str::to_string)
+            })
+            .ok_or_else(|| Error::MissingTwinType(aas.id.clone()))?;
+        let (mut inner_state, slots) = models::create_actor(
+            &twin_type,
+            twin_defaults.params(&twin_type, aas.twin_parameters()),
+        )
+        .ok_or_else(|| Error::UnknownTwinType(twin_type.clone()))?;
+        let slot_cache = SlotCache::new();
+        inner_state.set_context(slot_cache.context());
+        let conditions = Condition::from_aas(&aas.twin_conditions())
+            .into_iter()
+            .filter_map(|condition| {
+                condition
+                    .inspect_err(|e| warn!("{} Ignored condition: {e}", aas.id))
+                    .ok()
+            })
+            .inspect(|condition| {
+                for slot in condition
+                    .guard
+                    .slots()
+                    .iter()
+                    .filter(|slot| !slots.contains(slot))
+                {
+                    warn!(
+                        "{} Condition {} reads unknown slot {slot}",
+                        aas.id, condition.name
+                    );
+                }
+            })
+            .collect();
 
         let (send_ch, recv_ch) = mpsc::channel(5);
-        TwinRunner {
-            aas,
+        Ok(TwinRunner {
+            command_guard: CommandGuard::from_aas(&aas),
+            audit_log: services.audit_log,
+            historian: services.historian,
+            history: services.history,
+            sessions: services.sessions,
+            predictors: predictor::predictors(&services.predictors),
+            alert_risk: services.predictors.alert_risk(),
+            at_risk: false,
+            actuators: services.actuators,
+            bus: services.bus,
+            notified_state: inner_state.state(),
+            state_clock: StateClock::new(&inner_state.state(), Utc::now()),
+            latency_budget: LatencyBudget::new(&services.latency_budget, aas.twin_latency_budget_ms()),
+            flapping: FlappingGuard::new(
+                aas.twin_transition_limits().or(inner_state.transition_limits()),
+                Instant::now(),
+            ),
+            conditions: Conditions::new(conditions),
+            handler_metrics: services.handler_metrics,
+            aas: IndexedShell::new(aas),
             inner_state,
+            twin_type,
             slots,
             slot_map: HashMap::new(),
+            unbound_slots: Vec::new(),
+            unbound_reasons: HashMap::new(),
+            bound_at: HashMap::new(),
+            slot_units: HashMap::new(),
+            slot_filters: HashMap::new(),
+            slot_cache,
+            last_input: None,
+            state_dirty: false,
+            recent_inputs: VecDeque::new(),
+            recent_transitions: VecDeque::new(),
+            recent_limit: services.recent_inputs,
+            caught_up: false,
+            hibernate_after: services.hibernate_after,
+            last_activity: Instant::now(),
+            resumed: false,
+            wakeup: None,
             send_ch,
             recv_ch,
             manager_ch,
             network_ch,
-        }
+        })
     }
 
     pub fn id(&self) -> AssetID {
         self.aas.id.clone()
     }
 
-    pub async fn init(&mut self) {
-        // Register the actor with the manager
+    /// The current state of the actor (of all its regions)
+    pub fn state(&self) -> String {
+        self.inner_state.state()
+    }
+
+    /// A snapshot of the actor. An actor that cannot be serialized is logged, and left out
+    /// of the backups rather than saved as an empty snapshot.
+    pub fn snapshot(&self) -> Result<serde_json::Value, String> {
+        let mut snapshot = self.inner_state.to_snapshot().inspect_err(|e| {
+            error!("{} Cannot take a snapshot of the actor: {e}", self.id());
+        })?;
+        // The times the states were entered, to keep the dwell time across restores
+        if let Some(object) = snapshot.as_object_mut() {
+            object.insert(
+                "entered_at".to_string(),
+                serde_json::json!(self.state_clock.entered()),
+            );
+            // The latest values of the slots, read by the actor on the next input
+            object.insert(
+                "slots".to_string(),
+                serde_json::json!(self.slot_cache.context().values()),
+            );
+        }
+        Ok(snapshot)
+    }
+
+    /// Find the sensor bound to a slot through its DataSource reference, or why there is none
+    fn bind_slot(&self, slot: &str) -> Result<DeviceID, String> {
+        let reference = self
+            .aas
+            .reference_value(&format!("PowerAndElectrical.{slot}.DataSource"))
+            .ok_or_else(|| format!("no DataSource reference in PowerAndElectrical.{slot}"))?;
+        self.aas
+            .sensor_id(reference)
+            .map(DeviceID::from)
+            .ok_or_else(|| format!("no SensorID in the data source {reference}"))
+    }
+
+    /// Binding status of a slot
+    fn slot_status(&self, slot: &str, now: DateTime<Utc>) -> SlotStatus {
+        let last_update = self.slot_cache.context().last_update(slot);
+        if let Some(reason) = self.unbound_reasons.get(slot) {
+            return SlotStatus {
+                status: SlotBinding::Unbound,
+                sensor: None,
+                last_update,
+                reason: Some(reason.clone()),
+            };
+        }
+        // Lowest sensor ID, to report the same one if several are bound through patterns
+        let sensor = self
+            .slot_map
+            .iter()
+            .filter(|(_, bound)| *bound == slot)
+            .map(|(sensor, _)| sensor)
+            .min()
+            .cloned();
+        // A slot rebound to another sensor is not stale before the new one had time to report
+        let since = last_update.max(self.bound_at.get(slot).copied());
+        let stale = since.is_none_or(|since| {
+            (now - since)
+                .to_std()
+                .is_ok_and(|elapsed| elapsed >= SLOT_STALE_AFTER)
+        });
+        SlotStatus {
+            status: if stale {
+                SlotBinding::Stale
+            } else {
+                SlotBinding::Bound
+            },
+            sensor,
+            last_update,
+            reason: None,
+        }
+    }
+
+    /// The slot bound to a device, directly or through a device ID pattern
+    fn slot_for(&self, device: &str) -> Option<&String> {
+        self.slot_map.get(device).or_else(|| {
+            self.slot_map
+                .iter()
+                .find(|(sensor, _)| {
+                    device_trie::is_pattern(sensor) && device_trie::pattern_matches(sensor, device)
+                })
+                .map(|(_, slot)| slot)
+        })
+    }
+
+    /// Bind a sensor announced at runtime to the first slot without a sensor whose data
+    /// source has the same measurement type, and subscribe to it
+    async fn bind_discovered(&mut self, announcement: SensorAnnouncement) {
+        if announcement.twin.as_ref().is_some_and(|twin| *twin != self.id())
+            || self.slot_map.contains_key(&announcement.sensor_id)
+        {
+            return;
+        }
+        let Some(position) = self.unbound_slots.iter().position(|slot| {
+            self.aas
+                .reference_value(&format!("PowerAndElectrical.{slot}.DataSource"))
+                .and_then(|reference| self.aas.measurement_type(reference))
+                == Some(announcement.measurement_type.as_str())
+        }) else {
+            debug!(
+                "{} No unbound slot measures {}, sensor {} ignored",
+                self.id(),
+                announcement.measurement_type,
+                announcement.sensor_id
+            );
+            return;
+        };
+        let slot = self.unbound_slots.remove(position);
+        self.unbound_reasons.remove(&slot);
+        self.bound_at.insert(slot.clone(), Utc::now());
+        info!(
+            "{} Sensor {} bound to slot {slot}",
+            self.id(),
+            announcement.sensor_id
+        );
+        self.slot_map.insert(announcement.sensor_id.clone(), slot);
         let _ = self
-            .manager_ch
-            .send(ManagerMessage::Register(self.id(), self.send_ch.clone()))
+            .network_ch
+            .send(NetworkMessage::Subscribe(self.id(), vec![announcement.sensor_id]))
             .await;
+    }
 
-        // Register the actor with the network receiver
+    /// Bind a slot to another sensor, updating its data source in the AAS, and move the
+    /// subscription of the twin from the sensors previously bound to the slot
+    async fn rebind_slot(&mut self, slot: &str, sensor: DeviceID) -> Result<DeviceID, String> {
+        if !self.slots.iter().any(|s| s == slot) {
+            return Err(format!("unknown slot {slot}"));
+        }
+        let mut aas = AssetAdministrationShell::clone(&self.aas);
+        let previous = DeviceID::from(aas.rebind_slot(slot, &sensor)?);
+        self.aas = IndexedShell::new(aas);
+
+        let unbound: Vec<DeviceID> = self
+            .slot_map
+            .iter()
+            .filter(|(device, bound)| *bound == slot && **device != sensor)
+            .map(|(device, _)| device.clone())
+            .collect();
+        self.slot_map.retain(|_, bound| bound != slot);
+        self.slot_map.insert(sensor.clone(), slot.to_string());
+        self.unbound_slots.retain(|s| s != slot);
+        self.unbound_reasons.remove(slot);
+        self.bound_at.insert(slot.to_string(), Utc::now());
+        // The readings of the new sensor are not smoothed with the ones of the previous one
+        if let Some(filter) = self.slot_filters.get_mut(slot) {
+            *filter = SlotFilter::new(filter.kind().clone());
+        }
+        info!("{} Slot {slot} rebound from {previous} to {sensor}", self.id());
+
+        // Other data sources may still use the sensors previously bound
+        let sensor_ids = self
+            .aas
+            .find_elements_in_collection("IoTDataSources", "Sensors", "SensorID");
+        let unused: Vec<DeviceID> = unbound
+            .into_iter()
+            .filter(|device| !sensor_ids.iter().any(|id| id == device.as_ref()))
+            .collect();
+        if !unused.is_empty() {
+            let _ = self
+                .network_ch
+                .send(NetworkMessage::Unsubscribe(self.id(), unused))
+                .await;
+        }
         let _ = self
             .network_ch
-            .send(NetworkMessage::Register(self.id(), self.send_ch.clone()))
+            .send(NetworkMessage::Subscribe(self.id(), vec![sensor]))
             .await;
+        Ok(previous)
+    }
+
+    /// Find the smoothing filter declared in the DataSource of a slot
+    fn slot_filter(&self, slot: &str) -> Result<Option<FilterKind>, String> {
+        match self
+            .aas
+            .reference_value(&format!("PowerAndElectrical.{slot}.DataSource"))
+        {
+            Some(reference) => self.aas.sensor_filter(reference),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn init(&mut self) {
+        if !self.resumed {
+            // Register the actor with the manager
+            let _ = self
+                .manager_ch
+                .send(ManagerMessage::Register(self.id(), self.send_ch.clone()))
+                .await;
+
+            // Register the actor with the network receiver
+            let _ = self
+                .network_ch
+                .send(NetworkMessage::Register(self.id(), self.send_ch.clone()))
+                .await;
+        }
 
         for s in self.slots.iter() {
             // Create an input slot for each reference to the DataSource subsystem found in the PowerAndElectrical submodel
-            if let Some(sensor) = self
+            match self.bind_slot(s) {
+                // Kept from before the hibernation, with the sensors discovered at runtime
+                _ if self.slot_map.values().any(|bound| bound == s) => {}
+                Ok(sensor) => {
+                    self.slot_map.insert(sensor, s.to_string());
+                    self.bound_at.insert(s.to_string(), Utc::now());
+                }
+                Err(reason) => {
+                    warn!("{} No sensor ID found for {}: {reason}", self.id(), s);
+                    self.unbound_slots.push(s.to_string());
+                    self.unbound_reasons.insert(s.to_string(), reason);
+                }
+            }
+            match self.slot_filter(s) {
+                Ok(Some(kind)) => {
+                    self.slot_filters.insert(s.to_string(), SlotFilter::new(kind));
+                }
+                Ok(None) => {}
+                Err(e) => warn!("{} Slot {s} left unfiltered: {e}", self.id()),
+            }
+            if let Some(unit) = self
                 .aas
-                .find_reference_value_in_collection("PowerAndElectrical", s, "DataSource")
-                .and_then(|ref_value| self.aas.resolve_sensor_reference(&ref_value))
+                .element_concept("PowerAndElectrical", s)
+                .and_then(|concept| concept.unit.clone())
             {
-                self.slot_map.insert(sensor, s.to_string());
-            } else {
-                warn!("{} No sensor ID found for {}", self.id(), s);
+                self.slot_units.insert(s.to_string(), unit);
             }
         }
         trace!("Slot map for {} is: {:?}", self.id(), self.slot_map);
+        // Still registered and subscribed since before the hibernation
+        if self.resumed {
+            return;
+        }
+        for problem in self.aas.validate_events(&self.inner_state.events()) {
+            warn!("{} {problem}", self.id());
+        }
+
+        // Catch up with the past values before the live ones
+        if let Some(historian) = self.historian.clone().filter(|_| !self.caught_up) {
+            self.backfill(&historian).await;
+        }
+
+        // Announce the twin is up and running
+        let _ = self
+            .network_ch
+            .send(NetworkMessage::Availability(self.id(), Availability::Online))
+            .await;
+        self.publish_state();
 
         // Subscribe to any sensor IDs found in the AAS in the IoTDataSources submodel under Sensors
         let sensor_ids = self
@@ -100,35 +749,1170 @@ impl TwinRunner {
         // Subscribe to the input sensors
         let _ = self
             .network_ch
-            .send(NetworkMessage::Subscribe(self.id(), sensor_ids))
+            .send(NetworkMessage::Subscribe(
+                self.id(),
+                sensor_ids.into_iter().map(DeviceID::from).collect(),
+            ))
+            .await;
+    }
+
+    /// Publish the events emitted by the last transition, as declared in the AAS, with the
+    /// correlation ID of the input that caused them
+    async fn publish_events(&mut self, correlation_id: &CorrelationID) {
+        for event in self.inner_state.take_events() {
+            let Some(declared) = self.aas.events().into_iter().find(|e| e.id_short == event.name) else {
+                warn!(
+                    "{} Event {} is not declared in the AAS, dropped",
+                    self.id(),
+                    event.name
+                );
+                self.report_error(format!(
+                    "event {} is not declared in the AAS, dropped",
+                    event.name
+                ));
+                continue;
+            };
+            let (payload, problems) = declared.shape_payload(&event.payload);
+            for problem in problems {
+                warn!("{} {problem}", self.id());
+            }
+            let topic = declared.message_topic.clone();
+            let event = TwinEvent {
+                event: event.name,
+                timestamp: Utc::now(),
+                payload,
+                correlation_id: correlation_id.clone(),
+            };
+            if let (Some(actuators), Some(actuation)) = (&self.actuators, &declared.actuation) {
+                actuators.actuate(&self.id(), actuation, &event, self.send_ch.clone());
+            }
+            self.emit_event(event, topic);
+        }
+        // A flapping twin latched into its safe state publishes that transition too
+        loop {
+            let state = self.inner_state.state();
+            if state == self.notified_state {
+                break;
+            }
+            let violation = self.flapping.check(Instant::now());
+            let transition = TwinTransition {
+                asset_id: self.id(),
+                from: std::mem::replace(&mut self.notified_state, state.clone()),
+                to: state,
+                timestamp: Utc::now(),
+                correlation_id: correlation_id.clone(),
+            };
+            self.state_clock.transition(&transition.to, transition.timestamp);
+            self.audit_log.record(&TransitionRecord::new(&transition));
+            if let Some(sessions) = &self.sessions {
+                let mut sessions = sessions.lock().unwrap_or_else(|e| e.into_inner());
+                sessions.transition(&transition);
+            }
+            for predictor in &mut self.predictors {
+                predictor.observe_transition(&transition);
+            }
+            let flapped =
+                violation.map(|violation| (violation, transition.from.clone(), transition.to.clone()));
+            let timestamp = transition.timestamp;
+            if self.recent_transitions.len() == RECENT_TRANSITIONS {
+                self.recent_transitions.pop_front();
+            }
+            self.recent_transitions.push_back(transition.clone());
+            self.bus.publish(BusEvent::Transition(transition));
+            self.check_predictions(timestamp, correlation_id).await;
+            self.publish_state();
+            match flapped {
+                Some((violation, from, to)) => self.flapping_detected(violation, &from, &to, correlation_id),
+                None => break,
+            }
+        }
+    }
+
+    /// Emit a flapping event for a transition exceeding the limits, and latch the twin into
+    /// its safe state if any
+    fn flapping_detected(
+        &mut self,
+        violation: Violation,
+        from: &str,
+        to: &str,
+        correlation_id: &CorrelationID,
+    ) {
+        let latched_into = self.flapping.latch().map(str::to_string);
+        warn!("{} Flapping from {from} to {to}: {violation:?}", self.id());
+        let mut payload = serde_json::json!({ "from": from, "to": to, "latched_into": latched_into });
+        if let (Some(payload), Ok(serde_json::Value::Object(violation))) =
+            (payload.as_object_mut(), serde_json::to_value(&violation))
+        {
+            payload.extend(violation);
+        }
+        let event = TwinEvent {
+            event: FLAPPING_EVENT.to_string(),
+            timestamp: Utc::now(),
+            payload,
+            correlation_id: correlation_id.clone(),
+        };
+        self.emit_event(event, None);
+        let Some(safe_state) = latched_into else {
+            return;
+        };
+        let regions: Vec<String> = self
+            .inner_state
+            .state_machines()
+            .into_iter()
+            .filter_map(|machine| machine.region)
+            .collect();
+        // Refused unless the restored actor is really in the safe state
+        let latched = self
+            .snapshot()
+            .and_then(|snapshot| flapping::safe_snapshot(snapshot, &regions, &safe_state))
+            .and_then(|snapshot| models::restore_actor(&self.twin_type, snapshot))
+            .and_then(|(actor, _)| match actor.state() {
+                state if state == safe_state => Ok(actor),
+                state => Err(format!("restored in state {state}")),
+            });
+        match latched {
+            Ok(mut actor) => {
+                actor.set_context(self.slot_cache.context());
+                info!(
+                    "{} Latched into {safe_state}, inputs ignored until released",
+                    self.id()
+                );
+                self.inner_state = actor;
+            }
+            Err(e) => {
+                self.flapping.release();
+                self.report_error(format!("cannot latch into safe state {safe_state}: {e}"));
+            }
+        }
+    }
+
+    /// Publish the status report of the twin, for the runtimes mirroring it. The network
+    /// receiver is not waited for, as it may be waiting itself to deliver an input to this
+    /// twin: if its queue is full, the report is published again at the next heartbeat.
+    fn publish_state(&mut self) {
+        let report = NetworkMessage::State(Box::new(self.report()));
+        self.state_dirty = matches!(self.network_ch.try_send(report), Err(TrySendError::Full(_)));
+    }
+
+    /// Publish an event on the bus, to send on the given topic or on the default one
+    fn emit_event(&self, event: TwinEvent, topic: Option<String>) {
+        self.bus.publish(BusEvent::Event(EmittedEvent {
+            asset_id: self.id(),
+            event,
+            topic,
+        }));
+    }
+
+    /// Publish a failure of the twin on the bus
+    fn report_error(&self, error: String) {
+        self.bus.publish(BusEvent::Error(TwinError {
+            asset_id: self.id(),
+            error,
+            timestamp: Utc::now(),
+        }));
+    }
+
+    /// The estimates of the predictors
+    fn predictions(&self, now: DateTime<Utc>) -> Vec<Prediction> {
+        self.predictors.iter().map(|p| p.predict(now)).collect()
+    }
+
+    /// Emit a maintenance risk event when the risk estimated by a predictor reaches the
+    /// alert level, once until it falls below it
+    async fn check_predictions(&mut self, now: DateTime<Utc>, correlation_id: &CorrelationID) {
+        let predictions = self.predictions(now);
+        let at_risk: Vec<&Prediction> = predictions.iter().filter(|p| p.risk >= self.alert_risk).collect();
+        let was_at_risk = std::mem::replace(&mut self.at_risk, !at_risk.is_empty());
+        if at_risk.is_empty() || was_at_risk {
+            return;
+        }
+        info!(
+            "{} Risk of failure at {:.2} ({})",
+            self.id(),
+            at_risk[0].risk,
+            at_risk[0].summary
+        );
+        let event = TwinEvent {
+            event: MAINTENANCE_RISK_EVENT.to_string(),
+            timestamp: now,
+            payload: serde_json::json!({ "predictions": at_risk }),
+            correlation_id: correlation_id.clone(),
+        };
+        self.emit_event(event, None);
+    }
+
+    /// Publish the opening of the actuation breaker, and inject it into the actor if it
+    /// handles it in its current state (e.g., to stop relying on the device)
+    async fn actuation_unavailable(&mut self, trip: BreakerTrip) {
+        let payload = serde_json::to_value(&trip).unwrap_or_default();
+        let envelope = CommandEnvelope::new(CommandSource::Actuation, ACTUATION_UNAVAILABLE, payload.clone());
+        let event = TwinEvent {
+            event: ACTUATION_UNAVAILABLE.to_string(),
+            timestamp: Utc::now(),
+            payload,
+            correlation_id: CorrelationID::from(&envelope.correlation_id),
+        };
+        self.emit_event(event, None);
+        if self
+            .inner_state
+            .commands()
+            .iter()
+            .any(|command| command == ACTUATION_UNAVAILABLE)
+        {
+            self.run_command(envelope).await;
+        }
+    }
+
+    /// Execute the commands, and emit the events, of the conditions met by the latest values
+    /// of the slots and the time spent in the current states, with the correlation ID of the
+    /// input that met them
+    async fn check_conditions(&mut self, correlation_id: &CorrelationID) {
+        let values = self.slot_values();
+        let (clock, now) = (&self.state_clock, Utc::now());
+        let met = self
+            .conditions
+            .newly_met(&values, &|state| clock.dwell_in(state, now));
+        for condition in met {
+            match &condition.action {
+                Action::Command(command) => {
+                    info!(
+                        "{} Condition {} met, executing {command}",
+                        self.id(),
+                        condition.name
+                    );
+                    let mut envelope = CommandEnvelope::new(
+                        CommandSource::Condition,
+                        command,
+                        condition.command_args(&values),
+                    );
+                    envelope.correlation_id = correlation_id.to_string();
+                    self.run_command(envelope).await;
+                }
+                Action::Event(event) => {
+                    info!("{} Condition {} met, emitting {event}", self.id(), condition.name);
+                    let event = TwinEvent {
+                        event: event.clone(),
+                        timestamp: now,
+                        payload: condition.command_args(&values),
+                        correlation_id: correlation_id.clone(),
+                    };
+                    self.emit_event(event, None);
+                }
+            }
+        }
+    }
+
+    /// Execute a command, unless filtered by the command guard, and record it in the audit log
+    async fn run_command(&mut self, envelope: CommandEnvelope) -> CommandOutcome {
+        let command = envelope.command.as_str();
+        debug!(
+            "{} Received command {command} with args {:?} from {:?} ({:?}, correlation ID {})",
+            self.id(),
+            envelope.args,
+            envelope.principal,
+            envelope.source,
+            envelope.correlation_id
+        );
+        let outcome = if !self
+            .command_guard
+            .authorize(command, envelope.principal.as_deref())
+        {
+            warn!(
+                "{} Command {command} rejected, {:?} is not allowed",
+                self.id(),
+                envelope.principal
+            );
+            CommandOutcome::Unauthorized
+        } else {
+            let key = envelope.idempotency_key.as_deref();
+            match self.command_guard.check(command, key, Instant::now()) {
+                Verdict::Execute => {
+                    let started = Instant::now();
+                    self.inner_state = self.inner_state.execute(command, envelope.args.clone());
+                    self.check_budget("execute", started.elapsed());
+                    debug!("{} New state: {:?}", self.id(), self.inner_state);
+                    self.publish_events(&CorrelationID::from(&envelope.correlation_id))
+                        .await;
+                    CommandOutcome::Executed(self.command_result())
+                }
+                Verdict::Duplicate => {
+                    debug!(
+                        "{} Command {command} already executed (key {key:?}), acknowledged",
+                        self.id()
+                    );
+                    CommandOutcome::Duplicate(self.command_result())
+                }
+                Verdict::CoolingDown(remaining) => {
+                    warn!(
+                        "{} Command {command} ignored, cooling down for {remaining:?}",
+                        self.id()
+                    );
+                    CommandOutcome::CoolingDown(remaining)
+                }
+            }
+        };
+        let id = self.id();
+        self.audit_log.record(&AuditRecord::new(
+            &id,
+            &envelope,
+            outcome.as_str(),
+            self.inner_state.state(),
+        ));
+        outcome
+    }
+
+    /// Evaluate a command as the command guard and the actor would handle it, leaving
+    /// both unchanged: the transition is computed on a copy of the actor
+    fn evaluate_command(&self, envelope: &CommandEnvelope) -> CommandEvaluation {
+        let command = envelope.command.as_str();
+        let state = self.inner_state.state();
+        let mut evaluation = CommandEvaluation {
+            command: command.to_string(),
+            correlation_id: envelope.correlation_id.clone(),
+            valid: true,
+            reason: None,
+            from: state.clone(),
+            to: state,
+            events: Vec::new(),
+            result: self.command_result(),
+        };
+        if !self
+            .command_guard
+            .authorize(command, envelope.principal.as_deref())
+        {
+            evaluation.valid = false;
+            evaluation.reason = Some(format!("{:?} is not allowed", envelope.principal));
+            return evaluation;
+        }
+        let key = envelope.idempotency_key.as_deref();
+        match self.command_guard.peek(command, key, Instant::now()) {
+            Verdict::Execute => {
+                let mut next = self.inner_state.execute(command, envelope.args.clone());
+                evaluation.to = next.state();
+                evaluation.events = next.take_events();
+                evaluation.result = actor_result(next.as_ref());
+            }
+            Verdict::Duplicate => {
+                evaluation.reason = Some("already executed with the same idempotency key".to_string());
+            }
+            Verdict::CoolingDown(remaining) => {
+                evaluation.valid = false;
+                evaluation.reason = Some(format!("cooling down, retry in {} ms", remaining.as_millis()));
+            }
+        }
+        debug!(
+            "{} Evaluated command {command} (correlation ID {}): {} -> {}",
+            self.id(),
+            envelope.correlation_id,
+            evaluation.from,
+            evaluation.to
+        );
+        evaluation
+    }
+
+    /// The current state and the properties of the actor (of all its regions)
+    fn command_result(&self) -> serde_json::Value {
+        actor_result(self.inner_state.as_ref())
+    }
+
+    /// The latest value of each slot
+    fn slot_values(&self) -> HashMap<String, SlotValue> {
+        self.slot_cache
+            .context()
+            .values()
+            .into_iter()
+            .map(|(slot, cached)| (slot, cached.value))
+            .collect()
+    }
+
+    /// Feed a value to an input slot of the actor
+    fn input_value(&mut self, slot: &str, value: SlotValue, time: DateTime<Utc>) {
+        // Smooth noisy readings before the actor compares them with its thresholds
+        let value = match self.slot_filters.get_mut(slot) {
+            Some(filter) => filter.filter(value),
+            None => value,
+        };
+        self.record_input(slot, time, &value);
+        let started = Instant::now();
+        self.inner_state = self
+            .slot_cache
+            .feed(self.inner_state.as_ref(), slot, value.clone(), time);
+        self.state_clock.transition(&self.inner_state.state(), time);
+        self.check_budget("input_change", started.elapsed());
+        if let Some(history) = &self.history {
+            let mut history = history.lock().unwrap_or_else(|e| e.into_inner());
+            history.record(&self.aas.id, slot, time, value.clone(), &self.inner_state.state());
+        }
+        if let Some(sessions) = &self.sessions {
+            let mut sessions = sessions.lock().unwrap_or_else(|e| e.into_inner());
+            sessions.sample(&self.aas.id, slot, time, &value);
+        }
+        for predictor in &mut self.predictors {
+            predictor.observe_value(slot, time, &value);
+        }
+        self.last_input = Some(time);
+        self.state_dirty = true;
+    }
+
+    /// Keep an input to replay it into a staged update of the definition
+    fn record_input(&mut self, slot: &str, time: DateTime<Utc>, value: &SlotValue) {
+        if self.recent_limit == 0 {
+            return;
+        }
+        if self.recent_inputs.len() == self.recent_limit {
+            self.recent_inputs.pop_front();
+        }
+        self.recent_inputs.push_back(RecordedInput {
+            slot: slot.to_string(),
+            time,
+            value: value.clone(),
+        });
+    }
+
+    /// Feed the recent inputs of the running twin to the actor of a staged twin, before it
+    /// starts. The values were already filtered and recorded in the history, the sessions and
+    /// the predictors by the running twin. The events emitted meanwhile are dropped.
+    pub fn replay(&mut self, inputs: &[RecordedInput]) {
+        for input in inputs {
+            self.inner_state = self.slot_cache.feed(
+                self.inner_state.as_ref(),
+                &input.slot,
+                input.value.clone(),
+                input.time,
+            );
+            self.state_clock.transition(&self.inner_state.state(), input.time);
+            self.last_input = Some(input.time);
+            self.record_input(&input.slot, input.time, &input.value);
+        }
+        self.inner_state.take_events();
+        self.notified_state = self.inner_state.state();
+        self.caught_up = true;
+    }
+
+    /// Record the execution time of a handler and check it against the latency budget
+    fn check_budget(&mut self, handler: &str, elapsed: Duration) {
+        let check = self.latency_budget.check(elapsed, Instant::now());
+        let id = self.id();
+        let error = {
+            let mut metrics = self.handler_metrics.lock().unwrap_or_else(|e| e.into_inner());
+            let metrics = metrics.entry(id.clone()).or_default();
+            metrics.handler_time.record(elapsed);
+            match check {
+                BudgetCheck::Within => return,
+                BudgetCheck::Overrun(budget) => {
+                    metrics.overruns += 1;
+                    warn!("{id} Handler {handler} took {elapsed:?}, over the budget of {budget:?}");
+                    format!("handler {handler} took {elapsed:?}, over the budget of {budget:?}")
+                }
+                BudgetCheck::Isolate(period) => {
+                    metrics.overruns += 1;
+                    metrics.isolations += 1;
+                    metrics.isolated_until = chrono::Duration::from_std(period)
+                        .ok()
+                        .map(|period| Utc::now() + period);
+                    warn!("{id} Handler {handler} took {elapsed:?}, over the budget too often: inputs paused for {period:?}");
+                    format!("handler {handler} took {elapsed:?}, over the budget too often: inputs paused for {period:?}")
+                }
+            }
+        };
+        self.report_error(error);
+    }
+
+    /// Release the twin latched into its safe state after flapping, on the command of an
+    /// operator or a restore
+    fn release_latch(&mut self) {
+        if self.flapping.release() {
+            info!("{} Released from the safe state, inputs resumed", self.id());
+        }
+    }
+
+    /// Drop an input change received while the inputs are paused
+    fn drop_input(&self, device: &DeviceID) {
+        trace!(
+            "{} Inputs paused, dropped the input change of {device}",
+            self.id()
+        );
+        let mut metrics = self.handler_metrics.lock().unwrap_or_else(|e| e.into_inner());
+        metrics.entry(self.id()).or_default().dropped_inputs += 1;
+    }
+
+    /// Replay the latest values of the bound sensors recorded by the historian, oldest
+    /// first, so the actor reaches its current state before the live updates. The events
+    /// emitted meanwhile are dropped: they were published when the values were live.
+    async fn backfill(&mut self, historian: &Historian) {
+        let mut values = Vec::new();
+        for (sensor, slot) in &self.slot_map {
+            if device_trie::is_pattern(sensor) {
+                continue;
+            }
+            match historian.recent_values(sensor).await {
+                Ok(recent) => values.extend(recent.into_iter().map(|value| (slot.clone(), value))),
+                Err(e) => warn!(
+                    "{} Cannot backfill slot {slot} from the historian: {e}",
+                    self.id()
+                ),
+            }
+        }
+        values.sort_by_key(|(_, value)| value.timestamp);
+        let count = values.len();
+        for (slot, value) in values {
+            self.input_value(&slot, value.value, value.timestamp);
+        }
+        self.inner_state.take_events();
+        self.notified_state = self.inner_state.state();
+        info!(
+            "{} Backfilled {count} values from the historian, state {}",
+            self.id(),
+            self.inner_state.state()
+        );
+    }
+
+    /// Leave the network receiver and hand the final snapshot of the actor to the manager
+    async fn stop(&self) {
+        let _ = self.network_ch.send(NetworkMessage::Unregister(self.id())).await;
+        self.handler_metrics
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.id());
+        if let Ok(snapshot) = self.snapshot() {
+            let _ = self
+                .manager_ch
+                .send(ManagerMessage::TwinStopped(self.id(), snapshot))
+                .await;
+        }
+    }
+
+    /// Build a status report of the twin
+    pub fn report(&self) -> TwinReport {
+        let now = Utc::now();
+        TwinReport {
+            asset_id: self.id(),
+            actor_type: self.inner_state.type_name(),
+            state: self.inner_state.state(),
+            bound_sensors: self.slot_map.clone(),
+            unbound_slots: self.unbound_slots.clone(),
+            slots: self
+                .slots
+                .iter()
+                .map(|slot| (slot.clone(), self.slot_status(slot, now)))
+                .collect(),
+            slot_units: self.slot_units.clone(),
+            slot_filters: self
+                .slot_filters
+                .iter()
+                .map(|(slot, filter)| (slot.clone(), filter.kind().to_string()))
+                .collect(),
+            slot_values: self.slot_values(),
+            last_input: self.last_input,
+            predictions: self.predictions(now),
+            location: self.aas.twin_location(),
+            labels: self.aas.twin_labels(),
+            mirrored_from: None,
+            actuation_breaker: self
+                .actuators
+                .as_ref()
+                .and_then(|actuators| actuators.breaker(&self.id())),
+            latched_into: self.flapping.latched_into().map(str::to_string),
+            state_entered_at: Some(self.state_clock.entered_at()),
+            state_dwell: self.state_clock.dwell(now),
+        }
+    }
+
+    /// The commands and slots handled in the current state
+    pub fn available_actions(&self) -> AvailableActions {
+        AvailableActions {
+            asset_id: self.id(),
+            state: self.inner_state.state(),
+            commands: self.inner_state.commands(),
+            slots: self.inner_state.inputs(),
+        }
+    }
+
+    /// The state machines of the actor, with the current state and the recent transitions
+    pub fn diagram(&self) -> StateDiagram {
+        StateDiagram::new(
+            self.id(),
+            self.inner_state.state(),
+            self.inner_state.state_machines(),
+            self.recent_transitions.iter().cloned().collect(),
+        )
+    }
+
+    /// Replace the actor with one restored from a snapshot (e.g., from a backup), returning
+    /// its state. The transition is published at the next call to `publish_events`.
+    pub fn restore(&mut self, snapshot: serde_json::Value) -> Result<String, String> {
+        let entered = snapshot
+            .get("entered_at")
+            .and_then(|entered| serde_json::from_value(entered.clone()).ok());
+        let slots: Option<HashMap<String, CachedValue>> = snapshot
+            .get("slots")
+            .and_then(|slots| serde_json::from_value(slots.clone()).ok());
+        let (mut actor, _) = models::restore_actor(&self.twin_type, snapshot)?;
+        if actor.type_name() != self.inner_state.type_name() {
+            return Err(format!(
+                "snapshot of a {}, not of a {}",
+                actor.type_name(),
+                self.inner_state.type_name()
+            ));
+        }
+        for (slot, cached) in slots.unwrap_or_default() {
+            self.slot_cache.record(&slot, cached.value, cached.timestamp);
+        }
+        actor.set_context(self.slot_cache.context());
+        self.inner_state = actor;
+        self.state_clock = StateClock::new(&self.inner_state.state(), Utc::now());
+        if let Some(entered) = entered {
+            self.state_clock.restore(entered);
+        }
+        self.caught_up = true;
+        info!("{} Restored in state {}", self.id(), self.inner_state.state());
+        Ok(self.inner_state.state())
+    }
+
+    /// Whether the twin received no input for the hibernation period, and has nothing left to do
+    fn is_idle(&self) -> bool {
+        self.hibernate_after
+            .is_some_and(|after| self.last_activity.elapsed() >= after)
+            && !self.state_dirty
+            && self.recv_ch.is_empty()
+            && self.flapping.latched_into().is_none()
+            && !self.timer_running()
+    }
+
+    /// Whether a condition may be met by the time spent in the current state
+    fn timer_running(&self) -> bool {
+        let now = Utc::now();
+        self.conditions
+            .timer_running(&|state| self.state_clock.dwell_in(state, now))
+    }
+
+    /// Drop the actor, keeping its snapshot, bindings and channels
+    fn hibernate(self: Box<Self>, snapshot: serde_json::Value) -> Box<HibernatedTwin> {
+        info!("{} Hibernating in state {}", self.id(), self.state());
+        let report = self.report();
+        let actions = self.available_actions();
+        let diagram = self.diagram();
+        let twin = *self;
+        Box::new(HibernatedTwin {
+            aas: AssetAdministrationShell::clone(&twin.aas),
+            snapshot,
+            report,
+            actions,
+            diagram,
+            slot_map: twin.slot_map,
+            bound_at: twin.bound_at,
+            last_input: twin.last_input,
+            recent_inputs: twin.recent_inputs,
+            recent_transitions: twin.recent_transitions,
+            command_guard: twin.command_guard,
+            predictors: twin.predictors,
+            at_risk: twin.at_risk,
+            latency_budget: twin.latency_budget,
+            flapping: twin.flapping,
+            last_activity: twin.last_activity,
+            send_ch: twin.send_ch,
+            recv_ch: twin.recv_ch,
+            manager_ch: twin.manager_ch,
+            network_ch: twin.network_ch,
+        })
+    }
+
+    /// Rebuild a hibernated twin from its snapshot, to handle the message that woke it up.
+    /// The command guard, the predictors, the latency budget and the flapping guard carry on.
+    pub fn revive(
+        hibernated: Box<HibernatedTwin>,
+        wakeup: ActorMessage,
+        twin_defaults: &TwinDefaults,
+        services: TwinServices,
+    ) -> Result<Self, Error> {
+        let hibernated = *hibernated;
+        let mut twin = TwinRunner::new(
+            hibernated.aas,
+            twin_defaults,
+            hibernated.manager_ch,
+            hibernated.network_ch,
+            services,
+        )?;
+        if let Err(e) = twin.restore(hibernated.snapshot) {
+            error!(
+                "{} Cannot revive from the snapshot, starting over: {e}",
+                twin.id()
+            );
+        }
+        twin.notified_state = twin.state();
+        twin.slot_map = hibernated.slot_map;
+        twin.bound_at = hibernated.bound_at;
+        // The conditions met before hibernating don't fire again
+        let values = twin.slot_values();
+        let (clock, now) = (&twin.state_clock, Utc::now());
+        twin.conditions
+            .newly_met(&values, &|state| clock.dwell_in(state, now));
+        twin.last_input = hibernated.last_input;
+        twin.recent_inputs = hibernated.recent_inputs;
+        twin.recent_transitions = hibernated.recent_transitions;
+        // Keyed commands are not executed again, nor the cooldowns reset
+        twin.command_guard = hibernated.command_guard;
+        twin.predictors = hibernated.predictors;
+        twin.at_risk = hibernated.at_risk;
+        twin.latency_budget = hibernated.latency_budget;
+        twin.flapping = hibernated.flapping;
+        // Revived by a query, the twin hibernates again
+        twin.last_activity = hibernated.last_activity;
+        twin.send_ch = hibernated.send_ch;
+        twin.recv_ch = hibernated.recv_ch;
+        twin.resumed = true;
+        twin.wakeup = Some(wakeup);
+        Ok(twin)
+    }
+}
+
+/// The state and the properties of an actor (of all its regions)
+fn actor_result(actor: &ActorStateType) -> serde_json::Value {
+    // Only the state if the actor cannot be serialized
+    let snapshot = actor.to_snapshot().unwrap_or_default();
+    let actors = match snapshot.get("regions").and_then(|r| r.as_object()) {
+        Some(regions) => regions.values().collect(),
+        None => vec![&snapshot],
+    };
+    let mut result = serde_json::Map::new();
+    for fields in actors.iter().filter_map(|a| a.get("fields")?.as_object()) {
+        result.extend(fields.clone());
+    }
+    result.insert("state".to_string(), actor.state().into());
+    serde_json::Value::Object(result)
+}
+
+/// Run a twin until it stops: an idle twin hibernates, and is revived by the next message
+/// other than a query answered while parked
+pub async fn run(mut twin: Box<TwinRunner>, twin_defaults: Arc<TwinDefaults>, services: TwinServices) {
+    loop {
+        let Some(hibernated) = body(twin).await else {
+            return;
+        };
+        let id = hibernated.id();
+        let manager_ch = hibernated.manager_ch.clone();
+        let _ = manager_ch
+            .send(ManagerMessage::Hibernating(id.clone(), true))
             .await;
+        services.bus.publish(BusEvent::Lifecycle(LifecycleEvent::now(
+            id.clone(),
+            Lifecycle::Hibernated,
+        )));
+        let (hibernated, wakeup) = park(hibernated).await;
+        let Some(wakeup) = wakeup else {
+            return;
+        };
+        twin = match TwinRunner::revive(hibernated, wakeup, &twin_defaults, services.clone()) {
+            Ok(twin) => Box::new(twin),
+            Err(e) => {
+                error!("{id} Cannot revive: {e}");
+                return;
+            }
+        };
+        info!("{id} Revived in state {}", twin.state());
+        let _ = manager_ch
+            .send(ManagerMessage::Hibernating(id.clone(), false))
+            .await;
+        services
+            .bus
+            .publish(BusEvent::Lifecycle(LifecycleEvent::now(id, Lifecycle::Revived)));
+    }
+}
+
+/// Answer the queries of a hibernated twin until another message wakes it up, or None once
+/// the twin is unreachable. The dry runs of the commands need the actor: they wake it up,
+/// without keeping it awake.
+async fn park(mut twin: Box<HibernatedTwin>) -> (Box<HibernatedTwin>, Option<ActorMessage>) {
+    while let Some(msg) = twin.recv_ch.recv().await {
+        match msg {
+            ActorMessage::Report(reply) => {
+                let _ = reply.send(twin.report.clone());
+            }
+            ActorMessage::Actions(reply) => {
+                let _ = reply.send(twin.actions.clone());
+            }
+            ActorMessage::Diagram(reply) => {
+                let _ = reply.send(twin.diagram.clone());
+            }
+            ActorMessage::Snapshot(reply) => {
+                let _ = reply.send(twin.snapshot.clone());
+            }
+            ActorMessage::StagingSource(reply) => {
+                let _ = reply.send(StagingSource {
+                    state: twin.report.state.clone(),
+                    snapshot: twin.snapshot.clone(),
+                    inputs: twin.recent_inputs.iter().cloned().collect(),
+                });
+            }
+            ActorMessage::Freeze(reply, release) => {
+                let frozen = FrozenTwin {
+                    report: twin.report.clone(),
+                    snapshot: twin.snapshot.clone(),
+                };
+                if reply.send(frozen).is_ok() {
+                    let _ = tokio::time::timeout(FREEZE_LIMIT, release).await;
+                }
+            }
+            msg => return (twin, Some(msg)),
+        }
     }
+    (twin, None)
 }
 
-pub async fn body(mut twin: Box<TwinRunner>) {
+async fn body(mut twin: Box<TwinRunner>) -> Option<Box<HibernatedTwin>> {
     twin.init().await;
     info!("Twin runner body {} starting", twin.id());
+    // The message that revived a hibernated twin comes first
+    if let Some(msg) = twin.wakeup.take() {
+        if !handle_message(&mut twin, msg).await {
+            return None;
+        }
+    }
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
     loop {
         tokio::select! {
-            Some(msg) = twin.recv_ch.recv() => {
-                match msg {
-                    ActorMessage::InputChange(obj_id, value) => {
-                        if let Some(slot) = twin.slot_map.get(&obj_id) {
-                            debug!("{} Received input change: {} = {}", twin.id(), slot, value);
-                            twin.inner_state = twin.inner_state.input_change(slot, value);
-                            debug!("{} New state: {:?}", twin.id(), twin.inner_state);
-                        } else {
-                            warn!("{} Received input change from unknown object: {}", twin.id(), obj_id);
-                            debug!("{} current slot map: {:?}", twin.id(), twin.slot_map);
-                        }
-                    }
-                    ActorMessage::Command(command, args) => {
-                        debug!("{} Received command {command} with args {args:?}", twin.id());
-                        twin.inner_state = twin.inner_state.execute(&command, args);
-                        debug!("{} New state: {:?}", twin.id(), twin.inner_state);
+            _ = heartbeat.tick() => {
+                let _ = twin
+                    .manager_ch
+                    .send(ManagerMessage::Heartbeat(
+                        twin.id(),
+                        Heartbeat {
+                            state: twin.inner_state.state(),
+                            queue_depth: twin.recv_ch.len(),
+                        },
+                    ))
+                    .await;
+                // The slot values are published at most once per heartbeat
+                if twin.state_dirty {
+                    twin.publish_state();
+                }
+                if twin.timer_running() {
+                    twin.check_conditions(&command::new_correlation_id().into()).await;
+                }
+                if twin.is_idle() {
+                    if let Ok(snapshot) = twin.snapshot() {
+                        return Some(twin.hibernate(snapshot));
                     }
                 }
             }
+            Some(msg) = twin.recv_ch.recv() => {
+                if !handle_message(&mut twin, msg).await {
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+/// Handle a message of the twin, false once it stopped
+async fn handle_message(twin: &mut TwinRunner, msg: ActorMessage) -> bool {
+    // The queries do not keep the twin awake
+    if !matches!(
+        msg,
+        ActorMessage::Report(_)
+            | ActorMessage::Actions(_)
+            | ActorMessage::Diagram(_)
+            | ActorMessage::Evaluate(..)
+            | ActorMessage::Snapshot(_)
+            | ActorMessage::StagingSource(_)
+            | ActorMessage::Freeze(..)
+    ) {
+        twin.last_activity = Instant::now();
+    }
+    match msg {
+        ActorMessage::InputChange(obj_id, value, correlation_id) => {
+            if twin.latency_budget.is_isolated(Instant::now()) || twin.flapping.latched_into().is_some() {
+                twin.drop_input(&obj_id);
+            } else if let Some(slot) = twin.slot_for(&obj_id).cloned() {
+                debug!(
+                    "{} Received input change: {} = {} (correlation ID {correlation_id})",
+                    twin.id(),
+                    slot,
+                    value
+                );
+                twin.input_value(&slot, value, Utc::now());
+                debug!("{} New state: {:?}", twin.id(), twin.inner_state);
+                twin.publish_events(&correlation_id).await;
+                twin.check_conditions(&correlation_id).await;
+            } else {
+                warn!(
+                    "{} Received input change from unknown object: {}",
+                    twin.id(),
+                    obj_id
+                );
+                debug!("{} current slot map: {:?}", twin.id(), twin.slot_map);
+            }
+        }
+        ActorMessage::SensorDiscovered(announcement) => {
+            twin.bind_discovered(announcement).await;
+        }
+        ActorMessage::Command(envelope) => {
+            twin.release_latch();
+            twin.run_command(envelope).await;
+        }
+        ActorMessage::Invoke(envelope, reply) => {
+            twin.release_latch();
+            let outcome = twin.run_command(envelope).await;
+            let _ = reply.send(outcome);
+        }
+        ActorMessage::Evaluate(envelope, reply) => {
+            let _ = reply.send(twin.evaluate_command(&envelope));
+        }
+        ActorMessage::Report(reply) => {
+            let _ = reply.send(twin.report());
+        }
+        ActorMessage::Actions(reply) => {
+            let _ = reply.send(twin.available_actions());
+        }
+        ActorMessage::Diagram(reply) => {
+            let _ = reply.send(twin.diagram());
+        }
+        // Not answered if the actor cannot be serialized
+        ActorMessage::StagingSource(reply) => {
+            if let Ok(snapshot) = twin.snapshot() {
+                let _ = reply.send(StagingSource {
+                    state: twin.inner_state.state(),
+                    snapshot,
+                    inputs: twin.recent_inputs.iter().cloned().collect(),
+                });
+            }
+        }
+        ActorMessage::Snapshot(reply) => {
+            if let Ok(snapshot) = twin.snapshot() {
+                let _ = reply.send(snapshot);
+            }
+        }
+        ActorMessage::Freeze(reply, release) => {
+            if let Ok(snapshot) = twin.snapshot() {
+                let frozen = FrozenTwin {
+                    report: twin.report(),
+                    snapshot,
+                };
+                if reply.send(frozen).is_ok() {
+                    let _ = tokio::time::timeout(FREEZE_LIMIT, release).await;
+                }
+            }
+        }
+        ActorMessage::Restore(snapshot, reply) => {
+            twin.release_latch();
+            let result = twin.restore(snapshot);
+            if result.is_ok() {
+                let correlation_id = CorrelationID::from(command::new_correlation_id());
+                twin.publish_events(&correlation_id).await;
+            }
+            let _ = reply.send(result);
+        }
+        ActorMessage::RebindSlot(slot, sensor, reply) => {
+            let result = twin.rebind_slot(&slot, sensor).await;
+            let _ = reply.send(result);
+        }
+        ActorMessage::ActuationUnavailable(trip) => {
+            twin.actuation_unavailable(trip).await;
+        }
+        ActorMessage::Stop => {
+            info!("{} Stopping", twin.id());
+            twin.stop().await;
+            return false;
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::latency_budget::LatencyBudgetOptions;
+    use clap::Parser;
+
+    /// A twin of the given type, with the given properties of its configuration, publishing
+    /// its state on the network channel
+    fn test_twin(
+        twin_type: &str,
+        properties: &[(&str, &str)],
+        network_ch: mpsc::Sender<NetworkMessage>,
+    ) -> (TwinRunner, TwinServices) {
+        let mut yaml = format!(
+            r#"
+id: "urn:test:twin"
+id_short: "Twin"
+submodels:
+  - id: "urn:test:twin:configuration"
+    id_short: "TwinConfiguration"
+    elements:
+      - element_type: "property"
+        id_short: "TwinType"
+        value_type: "string"
+        value: "{twin_type}"
+"#
+        );
+        for (id_short, value) in properties {
+            let (value_type, value) = match value.parse::<i64>() {
+                Ok(value) => ("int", value.to_string()),
+                Err(_) => ("string", format!("\"{value}\"")),
+            };
+            yaml.push_str(&format!(
+                "      - element_type: \"property\"\n        id_short: \"{id_short}\"\n        value_type: \"{value_type}\"\n        value: {value}\n"
+            ));
+        }
+        let aas = AssetAdministrationShell::from_reader(yaml.as_bytes()).unwrap();
+        let services = TwinServices {
+            audit_log: AuditLog::default(),
+            historian: None,
+            history: None,
+            sessions: None,
+            predictors: PredictorOptions::parse_from(["test"]),
+            recent_inputs: 0,
+            actuators: None,
+            bus: EventBus::default(),
+            latency_budget: LatencyBudgetOptions::parse_from(["test"]),
+            handler_metrics: SharedHandlerMetrics::default(),
+            hibernate_after: Some(Duration::ZERO),
+        };
+        let (manager_ch, _) = mpsc::channel(1);
+        let twin = TwinRunner::new(
+            aas,
+            &TwinDefaults::default(),
+            manager_ch,
+            network_ch,
+            services.clone(),
+        )
+        .unwrap();
+        (twin, services)
+    }
+
+    #[tokio::test]
+    async fn test_hibernate() {
+        let (network_ch, _) = mpsc::channel(1);
+        let (mut twin, services) = test_twin("LightBulb", &[], network_ch);
+        let defaults = TwinDefaults::default();
+        twin.input_value("CurrentPowerDraw", SlotValue::Number(0.7), Utc::now());
+        assert_eq!(twin.state(), "On");
+        // Not idle until the state is published
+        assert!(!twin.is_idle());
+        twin.publish_events(&"c-1".into()).await;
+        assert!(twin.is_idle());
+
+        let snapshot = twin.snapshot().unwrap();
+        let ch = twin.send_ch.clone();
+        let parked = tokio::spawn(park(Box::new(twin).hibernate(snapshot)));
+        // Answered from the report of the hibernated twin
+        let (reply, report) = oneshot::channel();
+        ch.send(ActorMessage::Report(reply)).await.unwrap();
+        assert_eq!(report.await.unwrap().state, "On");
+        ch.send(ActorMessage::Stop).await.unwrap();
+        let (hibernated, wakeup) = parked.await.unwrap();
+        assert!(matches!(wakeup, Some(ActorMessage::Stop)));
+
+        let twin = TwinRunner::revive(hibernated, ActorMessage::Stop, &defaults, services).unwrap();
+        assert_eq!(twin.state(), "On");
+        assert_eq!(twin.slot_values()["CurrentPowerDraw"], SlotValue::Number(0.7));
+        // The path of the diagram goes on from the transitions before hibernating
+        assert_eq!(twin.diagram().machines[0].path, ["Off", "On"]);
+        assert!(twin.resumed && matches!(twin.wakeup, Some(ActorMessage::Stop)));
+    }
+
+    #[tokio::test]
+    async fn test_hibernate_keyed_command() {
+        let (network_ch, _) = mpsc::channel(1);
+        let (mut twin, services) = test_twin("LightBulb", &[], network_ch);
+        let mut envelope = CommandEnvelope::new(CommandSource::Rest, "SwitchOn", serde_json::json!({}));
+        envelope.idempotency_key = Some("k-1".to_string());
+        assert!(matches!(
+            twin.run_command(envelope.clone()).await,
+            CommandOutcome::Executed(_)
+        ));
+
+        let snapshot = twin.snapshot().unwrap();
+        let ch = twin.send_ch.clone();
+        let parked = tokio::spawn(park(Box::new(twin).hibernate(snapshot)));
+        // Polling the actions does not wake the twin up
+        let (reply, actions) = oneshot::channel();
+        ch.send(ActorMessage::Actions(reply)).await.unwrap();
+        assert_eq!(actions.await.unwrap().state, "On");
+        ch.send(ActorMessage::Command(envelope.clone())).await.unwrap();
+        let (hibernated, wakeup) = parked.await.unwrap();
+        assert!(matches!(wakeup, Some(ActorMessage::Command(_))));
+
+        // The redelivered command is not executed again
+        let wakeup = wakeup.unwrap();
+        let mut twin = TwinRunner::revive(hibernated, wakeup, &TwinDefaults::default(), services).unwrap();
+        assert!(matches!(
+            twin.run_command(envelope).await,
+            CommandOutcome::Duplicate(_)
+        ));
+    }
+
+    #[test]
+    fn test_restore_slot_values() {
+        let (network_ch, _) = mpsc::channel(1);
+        let (mut twin, _) = test_twin("WaterPump", &[], network_ch.clone());
+        for (slot, reading) in [("FlowRate", 20.0), ("Pressure", 2.0), ("Current", 4.0)] {
+            twin.input_value(slot, SlotValue::Number(reading), Utc::now());
+        }
+        assert_eq!(twin.state(), "Running");
+
+        // A new twin, with an empty cache, gets the other readings from the snapshot
+        let (mut restored, _) = test_twin("WaterPump", &[], network_ch);
+        assert_eq!(restored.restore(twin.snapshot().unwrap()).unwrap(), "Running");
+        assert_eq!(restored.slot_values(), twin.slot_values());
+        restored.input_value("FlowRate", SlotValue::Number(18.0), Utc::now());
+        assert_eq!(restored.state(), "Running");
+    }
+
+    #[tokio::test]
+    async fn test_publish_state_flood() {
+        // A network receiver busy delivering inputs to the twin, not reading its queue
+        let (network_ch, mut network_rx) = mpsc::channel(1);
+        let (mut twin, _) = test_twin("LightBulb", &[], network_ch);
+        let correlation_id: CorrelationID = "c-1".into();
+        for i in 0..100 {
+            let power = if i % 2 == 0 { 0.7 } else { 0.3 };
+            twin.input_value("CurrentPowerDraw", SlotValue::Number(power), Utc::now());
+            // Each transition publishes the state without waiting for the receiver
+            tokio::time::timeout(Duration::from_secs(1), twin.publish_events(&correlation_id))
+                .await
+                .expect("blocked on the network receiver");
+        }
+        assert_eq!(twin.state(), "Off");
+        // Published again at the next heartbeat
+        assert!(twin.state_dirty);
+        assert!(matches!(network_rx.try_recv(), Ok(NetworkMessage::State(report)) if report.state == "On"));
+        twin.publish_state();
+        assert!(!twin.state_dirty);
+        assert!(matches!(network_rx.try_recv(), Ok(NetworkMessage::State(report)) if report.state == "Off"));
+    }
+
+    #[tokio::test]
+    async fn test_flapping_regions() {
+        let limits = [
+            ("MaxTransitionsPerMinute", "2"),
+            ("FlappingSafeState", "Fault|Offline"),
+        ];
+        let (network_ch, _) = mpsc::channel(1);
+        let (mut twin, _) = test_twin("ChargingPoint", &limits, network_ch);
+        let correlation_id: CorrelationID = "c-1".into();
+        for signal in [-70.0, -95.0, -70.0] {
+            twin.input_value("SignalStrength", SlotValue::Number(signal), Utc::now());
+            twin.publish_events(&correlation_id).await;
+        }
+        // Each region moved into its part of the safe state
+        assert_eq!(twin.state(), "Fault|Offline");
+        assert_eq!(twin.flapping.latched_into(), Some("Fault|Offline"));
+
+        // A safe state the regions cannot be in is refused
+        for safe_state in ["Fault", "Exploded|Offline"] {
+            let limits = [
+                ("MaxTransitionsPerMinute", "2"),
+                ("FlappingSafeState", safe_state),
+            ];
+            let (network_ch, _) = mpsc::channel(1);
+            let (mut twin, _) = test_twin("ChargingPoint", &limits, network_ch);
+            for signal in [-70.0, -95.0, -70.0] {
+                twin.input_value("SignalStrength", SlotValue::Number(signal), Utc::now());
+                twin.publish_events(&correlation_id).await;
+            }
+            assert_eq!(twin.state(), "Idle|Online");
+            assert_eq!(twin.flapping.latched_into(), None);
         }
     }
 }
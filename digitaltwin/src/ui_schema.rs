@@ -0,0 +1,233 @@
+use serde::Serialize;
+
+use crate::twin_runner::{AvailableActions, TwinReport};
+use digitaltwin_core::{AssetAdministrationShell, AssetID, SlotValue, SubmodelElement, ValueType};
+
+/// Submodel holding the slots of a twin
+const SLOTS_SUBMODEL: &str = "PowerAndElectrical";
+/// Properties of a slot collection bounding the values shown by a gauge
+const MIN_VALUE: &str = "MinValue";
+const MAX_VALUE: &str = "MaxValue";
+
+/// Description of the control panel of a twin, for generic frontends: a form for each
+/// operation of the AAS and a widget for each slot
+#[derive(Debug, Clone, Serialize)]
+pub struct UiSchema {
+    pub asset_id: AssetID,
+    /// Name of the panel, the id_short of the shell
+    pub title: String,
+    pub description: Option<String>,
+    pub state: String,
+    pub forms: Vec<CommandForm>,
+    pub widgets: Vec<SlotWidget>,
+}
+
+/// Form invoking an operation of the AAS
+#[derive(Debug, Clone, Serialize)]
+pub struct CommandForm {
+    /// Submodel of the operation, part of its invoke path
+    pub submodel: String,
+    pub operation: String,
+    /// Whether the twin handles the command in its current state
+    pub enabled: bool,
+    /// One field per input variable, in declaration order
+    pub fields: Vec<FormField>,
+    /// Names of the output variables
+    pub outputs: Vec<String>,
+}
+
+/// Field of a form, from an input variable of the operation
+#[derive(Debug, Clone, Serialize)]
+pub struct FormField {
+    pub name: String,
+    pub input: FieldInput,
+    /// Value of the variable in the AAS, if any
+    pub default: Option<serde_json::Value>,
+}
+
+/// Input control of a field, from the value type of the variable
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldInput {
+    Number,
+    Integer,
+    Text,
+    Checkbox,
+    Json,
+}
+
+/// Widget showing the latest value of a slot
+#[derive(Debug, Clone, Serialize)]
+pub struct SlotWidget {
+    pub slot: String,
+    /// Preferred name of the concept of the slot, or the slot name
+    pub label: String,
+    pub kind: WidgetKind,
+    pub unit: Option<String>,
+    /// Range of a gauge, from the "MinValue" and "MaxValue" properties of the slot
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub value: Option<SlotValue>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WidgetKind {
+    /// A numeric value
+    Gauge,
+    /// An on/off value
+    Indicator,
+    /// A text value
+    Label,
+}
+
+/// Build the UI schema of a twin from its AAS, its status report and the actions it
+/// handles in its current state
+pub fn ui_schema(
+    aas: &AssetAdministrationShell,
+    report: &TwinReport,
+    actions: &AvailableActions,
+) -> UiSchema {
+    let forms = aas
+        .submodels
+        .iter()
+        .flat_map(|submodel| {
+            submodel.elements.iter().filter_map(move |element| match element {
+                SubmodelElement::Operation(op) => Some((submodel.id_short.clone(), op)),
+                _ => None,
+            })
+        })
+        .map(|(submodel, op)| CommandForm {
+            submodel,
+            operation: op.id_short.clone(),
+            enabled: actions.commands.contains(&op.id_short),
+            fields: op
+                .input_variables
+                .iter()
+                .map(|var| FormField {
+                    name: var.name.clone(),
+                    input: field_input(&var.value_type),
+                    default: serde_json::to_value(&var.value).ok().filter(|v| !v.is_null()),
+                })
+                .collect(),
+            outputs: op.output_variables.iter().map(|var| var.name.clone()).collect(),
+        })
+        .collect();
+
+    let widgets = report
+        .slots
+        .keys()
+        .map(|slot| {
+            let concept = aas.element_concept(SLOTS_SUBMODEL, slot);
+            let value = report.slot_values.get(slot).cloned();
+            let kind = match (concept.and_then(|c| c.value_type.as_ref()), &value) {
+                (Some(ValueType::Bool), _) | (None, Some(SlotValue::Bool(_))) => WidgetKind::Indicator,
+                (Some(ValueType::String), _) | (None, Some(SlotValue::Text(_))) => WidgetKind::Label,
+                _ => WidgetKind::Gauge,
+            };
+            let bound = |name: &str| {
+                aas.get_property_f64(SLOTS_SUBMODEL, &format!("{slot}.{name}"))
+                    .ok()
+            };
+            SlotWidget {
+                slot: slot.clone(),
+                label: concept
+                    .and_then(|c| c.preferred_name.clone())
+                    .unwrap_or_else(|| slot.clone()),
+                kind,
+                unit: report.slot_units.get(slot).cloned(),
+                min: bound(MIN_VALUE),
+                max: bound(MAX_VALUE),
+                value,
+            }
+        })
+        .collect();
+
+    UiSchema {
+        asset_id: aas.id.clone(),
+        title: aas.id_short.clone(),
+        description: aas.description.clone(),
+        state: actions.state.clone(),
+        forms,
+        widgets,
+    }
+}
+
+fn field_input(value_type: &ValueType) -> FieldInput {
+    match value_type {
+        ValueType::Float => FieldInput::Number,
+        ValueType::Int => FieldInput::Integer,
+        ValueType::String => FieldInput::Text,
+        ValueType::Bool => FieldInput::Checkbox,
+        ValueType::Json => FieldInput::Json,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::twin_runner::{SlotBinding, SlotStatus};
+    use std::collections::{BTreeMap, HashMap};
+
+    #[test]
+    fn test_ui_schema() {
+        // A range for the gauge of the input current
+        let yaml = include_str!("../../twins/charger.yaml").replace(
+            "            id_short: \"InputCurrentValue\"\n",
+            "            id_short: \"MaxValue\"\n            value_type: \"float\"\n            value: 32\n\n          - element_type: \"property\"\n            id_short: \"InputCurrentValue\"\n",
+        );
+        let aas: AssetAdministrationShell = serde_yaml::from_str(&yaml).unwrap();
+        let report = TwinReport {
+            asset_id: aas.id.clone(),
+            actor_type: "ChargingPoint".to_string(),
+            state: "Idle|Online".to_string(),
+            slots: ["InputCurrent", "SignalStrength"]
+                .into_iter()
+                .map(|slot| {
+                    let status = SlotStatus {
+                        status: SlotBinding::Unbound,
+                        sensor: None,
+                        last_update: None,
+                        reason: None,
+                    };
+                    (slot.to_string(), status)
+                })
+                .collect::<BTreeMap<_, _>>(),
+            slot_units: HashMap::from([("InputCurrent".to_string(), "A".to_string())]),
+            slot_values: HashMap::from([("InputCurrent".to_string(), SlotValue::Number(6.0))]),
+            ..Default::default()
+        };
+        let actions = AvailableActions {
+            asset_id: aas.id.clone(),
+            state: report.state.clone(),
+            commands: vec!["VehicleDetected".to_string()],
+            slots: vec!["CurrentPowerDraw".to_string()],
+        };
+
+        let schema = ui_schema(&aas, &report, &actions);
+        assert_eq!(schema.title, "HomeChargingStation");
+        let set_current = schema
+            .forms
+            .iter()
+            .find(|f| f.operation == "SetChargingCurrent")
+            .unwrap();
+        assert_eq!(set_current.submodel, SLOTS_SUBMODEL);
+        assert!(!set_current.enabled);
+        assert_eq!(set_current.fields[0].name, "desired_current");
+        assert_eq!(set_current.fields[0].input, FieldInput::Number);
+        assert_eq!(set_current.fields[0].default, Some(serde_json::json!(0.0)));
+        assert_eq!(set_current.outputs, ["state"]);
+        assert!(schema
+            .forms
+            .iter()
+            .any(|f| f.operation == "VehicleDetected" && f.enabled));
+
+        let current = &schema.widgets[0];
+        assert_eq!(current.slot, "InputCurrent");
+        assert_eq!(current.kind, WidgetKind::Gauge);
+        assert_eq!(current.unit.as_deref(), Some("A"));
+        assert_eq!((current.min, current.max), (None, Some(32.0)));
+        assert!(matches!(current.value, Some(SlotValue::Number(n)) if n == 6.0));
+        assert!(schema.widgets[1].value.is_none());
+    }
+}
@@ -0,0 +1,188 @@
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Unknown devices and assets kept, the least recently seen ones dropped beyond
+const UNMAPPED_KEPT: usize = 1000;
+
+/// What the network receiver does with the updates of devices no twin reads and with the
+/// commands of unknown assets
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum UnknownInputs {
+    /// Log the commands of unknown assets, drop the updates silently
+    Log,
+    /// Count them and list the ones seen recently
+    Track,
+    /// Track them and publish them on the dead letter topic
+    DeadLetter,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnmappedKind {
+    /// An update of a device no twin is subscribed to
+    Device,
+    /// A command to an asset with no running twin
+    Asset,
+}
+
+/// A device or an asset seen in the inputs with no twin to deliver them to
+#[derive(Debug, Clone, Serialize)]
+pub struct UnmappedInput {
+    pub id: String,
+    pub kind: UnmappedKind,
+    /// Messages received within the window
+    pub count: u64,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    /// Topic of the latest message
+    pub last_topic: String,
+    /// Correlation ID of the latest message
+    pub last_correlation_id: String,
+}
+
+/// Unknown devices and assets seen recently, to find the wiring gaps while commissioning
+#[derive(Debug, Clone, Serialize)]
+pub struct UnmappedReport {
+    pub mode: UnknownInputs,
+    /// Messages of unknown devices or assets since the start
+    pub device_messages: u64,
+    pub asset_messages: u64,
+    /// Devices and assets seen within the window, the most recent first
+    pub recent: Vec<UnmappedInput>,
+}
+
+/// Tracker of the unknown devices and assets seen within a window
+#[derive(Debug)]
+pub struct UnmappedInputs {
+    mode: UnknownInputs,
+    window: chrono::Duration,
+    device_messages: u64,
+    asset_messages: u64,
+    inputs: HashMap<(UnmappedKind, String), UnmappedInput>,
+}
+
+pub type SharedUnmappedInputs = Arc<Mutex<UnmappedInputs>>;
+
+impl UnmappedInputs {
+    pub fn new(mode: UnknownInputs, window_secs: u64) -> Self {
+        UnmappedInputs {
+            mode,
+            window: chrono::Duration::seconds(window_secs.try_into().unwrap_or(i64::MAX)),
+            device_messages: 0,
+            asset_messages: 0,
+            inputs: HashMap::new(),
+        }
+    }
+
+    pub fn mode(&self) -> UnknownInputs {
+        self.mode
+    }
+
+    /// Record a message of an unknown device or asset
+    pub fn record(
+        &mut self,
+        kind: UnmappedKind,
+        id: &str,
+        topic: &str,
+        correlation_id: &str,
+        now: DateTime<Utc>,
+    ) {
+        match kind {
+            UnmappedKind::Device => self.device_messages += 1,
+            UnmappedKind::Asset => self.asset_messages += 1,
+        }
+        self.prune(now);
+        let key = (kind, id.to_string());
+        if !self.inputs.contains_key(&key) && self.inputs.len() >= UNMAPPED_KEPT {
+            let oldest = self
+                .inputs
+                .iter()
+                .min_by_key(|(_, input)| input.last_seen)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.inputs.remove(&oldest);
+            }
+        }
+        let input = self.inputs.entry(key).or_insert_with(|| UnmappedInput {
+            id: id.to_string(),
+            kind,
+            count: 0,
+            first_seen: now,
+            last_seen: now,
+            last_topic: String::new(),
+            last_correlation_id: String::new(),
+        });
+        input.count += 1;
+        input.last_seen = now;
+        input.last_topic = topic.to_string();
+        input.last_correlation_id = correlation_id.to_string();
+    }
+
+    /// Drop the devices and assets not seen within the window
+    fn prune(&mut self, now: DateTime<Utc>) {
+        let since = now - self.window;
+        self.inputs.retain(|_, input| input.last_seen >= since);
+    }
+
+    pub fn report(&mut self, now: DateTime<Utc>) -> UnmappedReport {
+        self.prune(now);
+        let mut recent: Vec<UnmappedInput> = self.inputs.values().cloned().collect();
+        recent.sort_by(|a, b| b.last_seen.cmp(&a.last_seen).then_with(|| a.id.cmp(&b.id)));
+        UnmappedReport {
+            mode: self.mode,
+            device_messages: self.device_messages,
+            asset_messages: self.asset_messages,
+            recent,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unmapped_inputs() {
+        let mut unmapped = UnmappedInputs::new(UnknownInputs::Track, 60);
+        let start: DateTime<Utc> = "2025-03-01T10:00:00Z".parse().unwrap();
+        let at = |secs| start + chrono::Duration::seconds(secs);
+        unmapped.record(
+            UnmappedKind::Device,
+            "urn:iot-sensor:x",
+            "twins/updates",
+            "c-1",
+            at(0),
+        );
+        unmapped.record(
+            UnmappedKind::Asset,
+            "urn:aas:example:gone",
+            "twins/updates",
+            "c-2",
+            at(10),
+        );
+        unmapped.record(
+            UnmappedKind::Device,
+            "urn:iot-sensor:x",
+            "acme/1/telemetry",
+            "c-3",
+            at(30),
+        );
+
+        let report = unmapped.report(at(40));
+        assert_eq!((report.device_messages, report.asset_messages), (2, 1));
+        assert_eq!(report.recent[0].id, "urn:iot-sensor:x");
+        assert_eq!(report.recent[0].count, 2);
+        assert_eq!(report.recent[0].first_seen, at(0));
+        assert_eq!(report.recent[0].last_topic, "acme/1/telemetry");
+        assert_eq!(report.recent[1].kind, UnmappedKind::Asset);
+
+        // The asset was not seen within the window, the totals are kept
+        let report = unmapped.report(at(80));
+        assert_eq!(report.recent.len(), 1);
+        assert_eq!(report.asset_messages, 1);
+    }
+}
@@ -0,0 +1,405 @@
+use std::collections::{BTreeSet, HashMap};
+use std::str::FromStr;
+
+use digitaltwin_core::{DeviceID, MqttUpdate, SlotValue};
+
+/// Arithmetic expression over the values of other devices
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+    Number(f64),
+    Device(DeviceID),
+    Negate(Box<Expression>),
+    Binary(Operator, Box<Expression>, Box<Expression>),
+    Call(Function, Vec<Expression>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Function {
+    Abs,
+    Min,
+    Max,
+}
+
+impl Expression {
+    /// The devices the expression reads
+    pub fn devices(&self) -> BTreeSet<DeviceID> {
+        let mut devices = BTreeSet::new();
+        self.collect_devices(&mut devices);
+        devices
+    }
+
+    fn collect_devices(&self, devices: &mut BTreeSet<DeviceID>) {
+        match self {
+            Expression::Number(_) => {}
+            Expression::Device(id) => {
+                devices.insert(id.clone());
+            }
+            Expression::Negate(operand) => operand.collect_devices(devices),
+            Expression::Binary(_, left, right) => {
+                left.collect_devices(devices);
+                right.collect_devices(devices);
+            }
+            Expression::Call(_, args) => args.iter().for_each(|arg| arg.collect_devices(devices)),
+        }
+    }
+
+    /// Value of the expression, if all the devices have a value and the result is finite
+    pub fn evaluate(&self, value_of: &impl Fn(&str) -> Option<f64>) -> Option<f64> {
+        let value = match self {
+            Expression::Number(n) => *n,
+            Expression::Device(id) => value_of(id)?,
+            Expression::Negate(operand) => -operand.evaluate(value_of)?,
+            Expression::Binary(op, left, right) => {
+                let (left, right) = (left.evaluate(value_of)?, right.evaluate(value_of)?);
+                match op {
+                    Operator::Add => left + right,
+                    Operator::Subtract => left - right,
+                    Operator::Multiply => left * right,
+                    Operator::Divide => left / right,
+                }
+            }
+            Expression::Call(function, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| arg.evaluate(value_of))
+                    .collect::<Option<Vec<f64>>>()?;
+                match function {
+                    Function::Abs => args[0].abs(),
+                    Function::Min => args.into_iter().fold(f64::INFINITY, f64::min),
+                    Function::Max => args.into_iter().fold(f64::NEG_INFINITY, f64::max),
+                }
+            }
+        };
+        value.is_finite().then_some(value)
+    }
+}
+
+/// Device IDs are written as they are when made of letters, digits, '_', '.' and ':', and
+/// between braces otherwise (e.g., "{urn:iot-sensor:power-1}"), since '-' is the subtraction
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | ':')
+}
+
+/// Recursive descent parser of the expressions
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&mut self) -> Option<char> {
+        self.skip_spaces();
+        self.input[self.pos..].chars().next()
+    }
+
+    fn error(&self, message: &str) -> String {
+        format!("{message} at \"{}\"", &self.input[self.pos..])
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn skip_spaces(&mut self) {
+        let rest = &self.input[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> &'a str {
+        let rest = &self.input[self.pos..];
+        let len = rest.find(|c| !f(c)).unwrap_or(rest.len());
+        self.pos += len;
+        &rest[..len]
+    }
+
+    /// expression := term (("+" | "-") term)*
+    fn expression(&mut self) -> Result<Expression, String> {
+        let mut left = self.term()?;
+        loop {
+            let op = if self.eat('+') {
+                Operator::Add
+            } else if self.eat('-') {
+                Operator::Subtract
+            } else {
+                return Ok(left);
+            };
+            left = Expression::Binary(op, Box::new(left), Box::new(self.term()?));
+        }
+    }
+
+    /// term := factor (("*" | "/") factor)*
+    fn term(&mut self) -> Result<Expression, String> {
+        let mut left = self.factor()?;
+        loop {
+            let op = if self.eat('*') {
+                Operator::Multiply
+            } else if self.eat('/') {
+                Operator::Divide
+            } else {
+                return Ok(left);
+            };
+            left = Expression::Binary(op, Box::new(left), Box::new(self.factor()?));
+        }
+    }
+
+    /// factor := "-" factor | "(" expression ")" | number | function "(" expression ("," expression)* ")"
+    ///         | device
+    fn factor(&mut self) -> Result<Expression, String> {
+        if self.eat('-') {
+            return Ok(Expression::Negate(Box::new(self.factor()?)));
+        }
+        if self.eat('(') {
+            let inner = self.expression()?;
+            return if self.eat(')') {
+                Ok(inner)
+            } else {
+                Err(self.error("expected ')'"))
+            };
+        }
+        if self.eat('{') {
+            let id = self.take_while(|c| c != '}').trim().to_string();
+            if !self.eat('}') || id.is_empty() {
+                return Err(self.error("expected a device ID and '}'"));
+            }
+            return Ok(Expression::Device(id.into()));
+        }
+        match self.peek() {
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let number = self.take_while(|c| c.is_ascii_digit() || c == '.');
+                number
+                    .parse()
+                    .map(Expression::Number)
+                    .map_err(|_| self.error("invalid number"))
+            }
+            Some(c) if is_name_char(c) => {
+                let name = self.take_while(is_name_char);
+                let function = match name {
+                    "abs" => Function::Abs,
+                    "min" => Function::Min,
+                    "max" => Function::Max,
+                    _ => return Ok(Expression::Device(name.into())),
+                };
+                if !self.eat('(') {
+                    return Ok(Expression::Device(name.into()));
+                }
+                let mut args = vec![self.expression()?];
+                loop {
+                    if self.eat(')') {
+                        break;
+                    }
+                    if !self.eat(',') {
+                        return Err(self.error("expected ',' or ')'"));
+                    }
+                    args.push(self.expression()?);
+                }
+                if function == Function::Abs && args.len() != 1 {
+                    return Err(format!("abs takes one argument, not {}", args.len()));
+                }
+                Ok(Expression::Call(function, args))
+            }
+            _ => Err(self.error("expected a number, a device ID or '('")),
+        }
+    }
+}
+
+impl FromStr for Expression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser { input: s, pos: 0 };
+        let expression = parser.expression()?;
+        parser.skip_spaces();
+        if parser.pos < s.len() {
+            return Err(parser.error("unexpected input"));
+        }
+        Ok(expression)
+    }
+}
+
+/// A device whose value is computed from the values of other devices
+#[derive(Debug, Clone, PartialEq)]
+pub struct VirtualSensor {
+    pub id: DeviceID,
+    pub expression: Expression,
+}
+
+pub fn parse_virtual_sensor(s: &str) -> Result<VirtualSensor, String> {
+    let (id, expression) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected <device id>=<expression>: {s}"))?;
+    let id = id.trim();
+    if id.is_empty() {
+        return Err(format!("missing device ID: {s}"));
+    }
+    let expression: Expression = expression.parse()?;
+    if expression.devices().contains(id) {
+        return Err(format!("virtual sensor {id} depends on itself"));
+    }
+    Ok(VirtualSensor {
+        id: id.into(),
+        expression,
+    })
+}
+
+/// Numeric reading of a value: booleans count as 0 and 1
+fn numeric(value: &SlotValue) -> Option<f64> {
+    match value {
+        SlotValue::Number(n) => Some(*n),
+        SlotValue::Bool(b) => Some(if *b { 1.0 } else { 0.0 }),
+        SlotValue::Text(_) => None,
+    }
+}
+
+/// The virtual sensors, with the latest values of the devices they read
+#[derive(Debug, Default)]
+pub struct VirtualSensors {
+    sensors: Vec<VirtualSensor>,
+    /// Indices of the sensors reading each device
+    readers: HashMap<DeviceID, Vec<usize>>,
+    values: HashMap<DeviceID, f64>,
+}
+
+impl VirtualSensors {
+    pub fn new(sensors: Vec<VirtualSensor>) -> Self {
+        let mut readers: HashMap<DeviceID, Vec<usize>> = HashMap::new();
+        for (index, sensor) in sensors.iter().enumerate() {
+            for device in sensor.expression.devices() {
+                readers.entry(device).or_default().push(index);
+            }
+        }
+        VirtualSensors {
+            sensors,
+            readers,
+            values: HashMap::new(),
+        }
+    }
+
+    /// Whether a virtual sensor reads the device
+    pub fn reads(&self, device: &str) -> bool {
+        self.readers.contains_key(device)
+    }
+
+    /// Record an update and return the updates of the virtual sensors depending on it, directly
+    /// or through other virtual sensors. Each sensor is computed at most once per update, so
+    /// circular definitions do not loop.
+    pub fn update(&mut self, update: &MqttUpdate) -> Vec<MqttUpdate> {
+        let mut derived = Vec::new();
+        if !self.readers.contains_key(&update.object) {
+            return derived;
+        }
+        let mut computed = BTreeSet::new();
+        let mut changed = vec![(update.object.clone(), numeric(&update.value))];
+        while let Some((device, value)) = changed.pop() {
+            match value {
+                Some(value) => self.values.insert(device.clone(), value),
+                None => self.values.remove(&device),
+            };
+            for &index in self.readers.get(&device).into_iter().flatten() {
+                if !computed.insert(index) {
+                    continue;
+                }
+                let sensor = &self.sensors[index];
+                let Some(value) = sensor.expression.evaluate(&|id| self.values.get(id).copied()) else {
+                    continue;
+                };
+                changed.push((sensor.id.clone(), Some(value)));
+                derived.push(MqttUpdate {
+                    object: sensor.id.clone(),
+                    value: SlotValue::Number(value),
+                    seq: None,
+                });
+            }
+        }
+        derived
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(object: &str, value: f64) -> MqttUpdate {
+        MqttUpdate {
+            object: object.into(),
+            value: SlotValue::Number(value),
+            seq: None,
+        }
+    }
+
+    fn values(updates: Vec<MqttUpdate>) -> Vec<(String, SlotValue)> {
+        updates.into_iter().map(|u| (u.object.into(), u.value)).collect()
+    }
+
+    #[test]
+    fn test_expressions() {
+        let values = |id: &str| match id {
+            "powerAbs123" => Some(5.0),
+            "solarProd456" => Some(3.5),
+            "urn:iot-sensor:grid-1" => Some(-2.0),
+            _ => None,
+        };
+        let eval = |s: &str| s.parse::<Expression>().unwrap().evaluate(&values);
+        assert_eq!(eval("powerAbs123 - solarProd456"), Some(1.5));
+        assert_eq!(eval("2 * (powerAbs123 + 1) / 4"), Some(3.0));
+        assert_eq!(eval("-powerAbs123 - -1"), Some(-4.0));
+        assert_eq!(
+            eval("abs({urn:iot-sensor:grid-1}) + max(1, solarProd456, 2)"),
+            Some(5.5)
+        );
+        assert_eq!(eval("min(powerAbs123, unknown)"), None);
+        assert_eq!(eval("powerAbs123 / 0"), None);
+        assert!("powerAbs123 -".parse::<Expression>().is_err());
+        assert!("(1 + 2".parse::<Expression>().is_err());
+        assert!("1 2".parse::<Expression>().is_err());
+        assert!("abs(1, 2)".parse::<Expression>().is_err());
+
+        let sensor = parse_virtual_sensor("urn:virtual:net-power = powerAbs123 - solarProd456").unwrap();
+        assert_eq!(sensor.id, "urn:virtual:net-power");
+        assert_eq!(
+            sensor.expression.devices().into_iter().collect::<Vec<_>>(),
+            ["powerAbs123", "solarProd456"]
+        );
+        assert!(parse_virtual_sensor("a=a+1").is_err());
+        assert!(parse_virtual_sensor("powerAbs123").is_err());
+    }
+
+    #[test]
+    fn test_virtual_sensors() {
+        let mut sensors = VirtualSensors::new(vec![
+            parse_virtual_sensor("net=power - solar").unwrap(),
+            parse_virtual_sensor("netKw=net / 1000").unwrap(),
+            // Circular definitions stop after one round
+            parse_virtual_sensor("a=b + 1").unwrap(),
+            parse_virtual_sensor("b=a + 1").unwrap(),
+        ]);
+        assert!(sensors.update(&update("power", 4000.0)).is_empty());
+        assert!(sensors.update(&update("other", 1.0)).is_empty());
+        assert_eq!(
+            values(sensors.update(&update("solar", 1500.0))),
+            [
+                ("net".to_string(), SlotValue::Number(2500.0)),
+                ("netKw".to_string(), SlotValue::Number(2.5))
+            ]
+        );
+        assert_eq!(
+            values(sensors.update(&update("a", 1.0))),
+            [
+                ("b".to_string(), SlotValue::Number(2.0)),
+                ("a".to_string(), SlotValue::Number(3.0))
+            ]
+        );
+    }
+}
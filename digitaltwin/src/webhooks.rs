@@ -0,0 +1,247 @@
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use log::{debug, warn};
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::event_bus::{self, BusEvent, EventBus};
+use crate::failover::{self, SharedFailover};
+use crate::http_client;
+use digitaltwin_core::{AssetID, CorrelationID};
+
+/// Name of the notifications of the state transitions, as the events of the twins
+pub const STATE_CHANGED: &str = "StateChanged";
+
+#[derive(Parser, Clone)]
+pub struct WebhookOptions {
+    /// webhook (http:// or https://), as "<asset id>:<event>=<url>" (separate webhooks with ';' in WEBHOOKS);
+    /// "*" matches any twin or event, and the "StateChanged" event notifies the state transitions
+    /// (e.g., "*:OvercurrentFault=http://alerts.local/hook")
+    #[clap(long = "webhook", value_parser = parse_webhook, value_delimiter = ';', env = "WEBHOOKS")]
+    webhooks: Vec<Webhook>,
+
+    /// attempts after the first one, when a webhook fails
+    #[clap(long, default_value_t = 3, env = "WEBHOOK_RETRIES")]
+    webhook_retries: u32,
+
+    /// delay before the first retry of a webhook, doubled at each attempt (milliseconds)
+    #[clap(long, default_value_t = 500, env = "WEBHOOK_BACKOFF_MS")]
+    webhook_backoff_ms: u64,
+}
+
+/// A webhook, notified of the events of the matching twins
+#[derive(Debug, Clone, PartialEq)]
+pub struct Webhook {
+    /// Asset ID of the twin, or "*"
+    pub asset: String,
+    /// Name of the event (or STATE_CHANGED), or "*"
+    pub event: String,
+    pub url: String,
+}
+
+impl Webhook {
+    fn matches(&self, asset: &AssetID, event: &str) -> bool {
+        (self.asset == "*" || self.asset == *asset) && (self.event == "*" || self.event == event)
+    }
+}
+
+fn parse_webhook(s: &str) -> Result<Webhook, String> {
+    let invalid = || format!("expected <asset id>:<event>=<url>: {s}");
+    let (selector, url) = s.split_once('=').ok_or_else(invalid)?;
+    // Asset IDs are URNs: the event is after the last colon
+    let (asset, event) = selector.rsplit_once(':').ok_or_else(invalid)?;
+    let (asset, event, url) = (asset.trim(), event.trim(), url.trim());
+    if asset.is_empty() || event.is_empty() || !(url.starts_with("http://") || url.starts_with("https://")) {
+        return Err(invalid());
+    }
+    Ok(Webhook {
+        asset: asset.to_string(),
+        event: event.to_string(),
+        url: url.to_string(),
+    })
+}
+
+/// Payload POSTed to the webhooks
+#[derive(Debug, Clone, Serialize)]
+pub struct Notification {
+    pub asset_id: AssetID,
+    pub event: String,
+    pub timestamp: DateTime<Utc>,
+    pub payload: serde_json::Value,
+    /// ID of the input that caused the event
+    pub correlation_id: CorrelationID,
+}
+
+impl Notification {
+    /// Notification of a state transition or of an event published on the bus, if any
+    pub fn from_bus(event: BusEvent) -> Option<Self> {
+        match event {
+            BusEvent::Transition(transition) => Some(Notification {
+                asset_id: transition.asset_id,
+                event: STATE_CHANGED.to_string(),
+                timestamp: transition.timestamp,
+                payload: serde_json::json!({ "from": transition.from, "to": transition.to }),
+                correlation_id: transition.correlation_id,
+            }),
+            BusEvent::Event(emitted) => Some(Notification {
+                asset_id: emitted.asset_id,
+                event: emitted.event.event,
+                timestamp: emitted.event.timestamp,
+                payload: emitted.event.payload,
+                correlation_id: emitted.event.correlation_id,
+            }),
+            BusEvent::Error(_) | BusEvent::Lifecycle(_) => None,
+        }
+    }
+}
+
+/// The configured webhooks, shared by the twins
+pub struct Webhooks {
+    hooks: Vec<Webhook>,
+    retries: u32,
+    backoff: Duration,
+    /// A standby instance does not notify
+    failover: SharedFailover,
+}
+
+impl Webhooks {
+    /// The configured webhooks, if any
+    pub fn new(options: &WebhookOptions, failover: SharedFailover) -> Option<Arc<Self>> {
+        (!options.webhooks.is_empty()).then(|| {
+            Arc::new(Webhooks {
+                hooks: options.webhooks.clone(),
+                retries: options.webhook_retries,
+                backoff: Duration::from_millis(options.webhook_backoff_ms),
+                failover,
+            })
+        })
+    }
+
+    /// Notify the webhooks of the transitions and events published on the bus, in the background
+    pub fn subscribe(self: Arc<Self>, bus: &EventBus) {
+        let mut receiver = bus.subscribe();
+        tokio::spawn(async move {
+            while let Some(event) = event_bus::next_event(&mut receiver, "Webhooks").await {
+                if let Some(notification) = Notification::from_bus(event) {
+                    self.notify(notification);
+                }
+            }
+        });
+    }
+
+    /// Delay before the given retry (1 for the first one)
+    fn backoff(&self, retry: u32) -> Duration {
+        self.backoff
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+    }
+
+    /// POST a notification to the matching webhooks, in the background
+    pub fn notify(self: &Arc<Self>, notification: Notification) {
+        if !failover::is_active(&self.failover) {
+            return;
+        }
+        let urls: Vec<String> = self
+            .hooks
+            .iter()
+            .filter(|hook| hook.matches(&notification.asset_id, &notification.event))
+            .map(|hook| hook.url.clone())
+            .collect();
+        if urls.is_empty() {
+            return;
+        }
+        let body = match serde_json::to_string(&notification) {
+            Ok(body) => Arc::new(body),
+            Err(e) => {
+                warn!(
+                    "Cannot serialize the notification of {}: {e}",
+                    notification.asset_id
+                );
+                return;
+            }
+        };
+        for url in urls {
+            let (webhooks, body) = (self.clone(), body.clone());
+            tokio::spawn(async move { webhooks.deliver(&url, &body).await });
+        }
+    }
+
+    /// POST a notification to a webhook, retrying with exponential backoff
+    async fn deliver(&self, url: &str, body: &str) {
+        let mut retry = 0;
+        loop {
+            match http_client::post_json(url, &[], body).await {
+                Ok(_) => {
+                    debug!("Webhook {url} notified");
+                    return;
+                }
+                Err(e) if retry < self.retries => {
+                    retry += 1;
+                    debug!("Webhook {url} failed ({e}), retry {retry} of {}", self.retries);
+                    tokio::time::sleep(self.backoff(retry)).await;
+                }
+                Err(e) => {
+                    warn!("Webhook {url} failed after {} attempts: {e}", retry + 1);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_bus::{Lifecycle, LifecycleEvent};
+    use crate::failover::{Failover, FailoverOptions, Role};
+    use crate::twin_runner::TwinTransition;
+
+    #[test]
+    fn test_webhooks() {
+        let hook =
+            parse_webhook("urn:aas:charger:id-1:OvercurrentFault=http://alerts.local/hook?a=b").unwrap();
+        assert_eq!(hook.asset, "urn:aas:charger:id-1");
+        assert_eq!(hook.event, "OvercurrentFault");
+        assert_eq!(hook.url, "http://alerts.local/hook?a=b");
+        assert!(hook.matches(&"urn:aas:charger:id-1".into(), "OvercurrentFault"));
+        assert!(!hook.matches(&"urn:aas:charger:id-2".into(), "OvercurrentFault"));
+        let any = parse_webhook("*:*=http://alerts.local").unwrap();
+        assert!(any.matches(&"urn:aas:charger:id-2".into(), STATE_CHANGED));
+        assert!(parse_webhook("*=http://alerts.local").is_err());
+        assert_eq!(
+            parse_webhook("*:*=https://alerts.local").unwrap().url,
+            "https://alerts.local"
+        );
+        assert!(parse_webhook("*:*=ftp://alerts.local").is_err());
+
+        let options = WebhookOptions {
+            webhooks: vec![any],
+            webhook_retries: 3,
+            webhook_backoff_ms: 500,
+        };
+        let failover = Failover::shared(&FailoverOptions {
+            role: Role::Primary,
+            heartbeat_topic: "twins/runtime/heartbeat".to_string(),
+            failover_timeout: 15,
+        });
+        let webhooks = Webhooks::new(&options, failover).unwrap();
+        assert_eq!(webhooks.backoff(1), Duration::from_millis(500));
+        assert_eq!(webhooks.backoff(3), Duration::from_millis(2000));
+
+        let transition = Notification::from_bus(BusEvent::Transition(TwinTransition {
+            asset_id: "urn:aas:charger:id-1".into(),
+            from: "Idle".to_string(),
+            to: "Charging".to_string(),
+            timestamp: Utc::now(),
+            correlation_id: CorrelationID::from("c-1"),
+        }))
+        .unwrap();
+        assert_eq!(transition.event, STATE_CHANGED);
+        assert_eq!(
+            transition.payload,
+            serde_json::json!({ "from": "Idle", "to": "Charging" })
+        );
+        let lifecycle = LifecycleEvent::now("urn:aas:charger:id-1".into(), Lifecycle::Started);
+        assert!(Notification::from_bus(BusEvent::Lifecycle(lifecycle)).is_none());
+    }
+}
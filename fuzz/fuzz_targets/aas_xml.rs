@@ -0,0 +1,11 @@
+#![no_main]
+
+use digitaltwin_core::{AssetAdministrationShell, IndexedShell};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(aas) = AssetAdministrationShell::from_xml_reader(data) {
+        let _ = aas.to_xml();
+        let _ = IndexedShell::new(aas);
+    }
+});
@@ -0,0 +1,11 @@
+#![no_main]
+
+use digitaltwin_core::{AssetAdministrationShell, IndexedShell};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(aas) = AssetAdministrationShell::from_reader(data) {
+        // The loaded shells are indexed and walked by the runtime
+        let _ = IndexedShell::new(aas);
+    }
+});
@@ -0,0 +1,9 @@
+#![no_main]
+
+use digitaltwin_core::{ContentType, MqttMessage};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = MqttMessage::decode_as(data, ContentType::Json);
+    let _ = MqttMessage::decode_as(data, ContentType::Yaml);
+});